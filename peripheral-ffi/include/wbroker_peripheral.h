@@ -0,0 +1,94 @@
+#ifndef WBROKER_PERIPHERAL_H
+#define WBROKER_PERIPHERAL_H
+
+/* Generated by cbindgen from peripheral-ffi/src/lib.rs, do not edit. */
+
+#include <stdint.h>
+
+// Success.
+#define WBP_OK 0
+
+// A required pointer argument was NULL.
+#define WBP_ERR_NULL_POINTER -1
+
+// An argument is out of range, or the text is not valid UTF-8.
+#define WBP_ERR_INVALID_ARGUMENT -2
+
+// The I2C bus or the device failed.
+#define WBP_ERR_I2C -3
+
+// The driver runtime could not be created.
+#define WBP_ERR_RUNTIME -4
+
+// The driver panicked. The handle should be closed.
+#define WBP_ERR_PANIC -5
+
+// Another device than a BME280 or BMP280 answered.
+#define WBP_ERR_WRONG_CHIP -6
+
+// Opaque BME280 handle.
+typedef struct WbpBme280 WbpBme280;
+
+// Opaque SO1602A handle.
+typedef struct WbpSo1602a WbpSo1602a;
+
+// Measurement of the BME280.
+typedef struct WbpMeasurement {
+  // Temperature in °C.
+  double temperature_c;
+  // Relative humidity in %.
+  double humidity_relative;
+  // Pressure in Pa.
+  double pressure_pa;
+} WbpMeasurement;
+
+// Open a BME280 on /dev/i2c-1.
+// `address` is 0x76 or 0x77. On success `*out` receives a handle to be
+// released with `bme280_close`, otherwise it is set to NULL.
+//
+// # Safety
+// `out` must be NULL or valid for writes.
+int32_t bme280_open(uint16_t address, struct WbpBme280 **out);
+
+// Take a forced-mode measurement into `*out`.
+//
+// # Safety
+// `handle` must come from `bme280_open` and not be closed, `out` must be
+// NULL or valid for writes.
+int32_t bme280_read(struct WbpBme280 *handle, struct WbpMeasurement *out);
+
+// Release a BME280 handle. NULL is ignored.
+//
+// # Safety
+// `handle` must come from `bme280_open` and not be closed yet.
+void bme280_close(struct WbpBme280 *handle);
+
+// Open and set up an SO1602A on /dev/i2c-1.
+// `address` is 0x3c or 0x3d. On success `*out` receives a handle to be
+// released with `so1602a_close`, otherwise it is set to NULL.
+//
+// # Safety
+// `out` must be NULL or valid for writes.
+int32_t so1602a_open(uint16_t address, struct WbpSo1602a **out);
+
+// Print ASCII text at the start of a line (0 or 1).
+//
+// # Safety
+// `handle` must come from `so1602a_open` and not be closed, `text` must be
+// NULL or a NUL-terminated string.
+int32_t so1602a_print(struct WbpSo1602a *handle, uint8_t line, const char *text);
+
+// Clear the display.
+//
+// # Safety
+// `handle` must come from `so1602a_open` and not be closed.
+int32_t so1602a_clear(struct WbpSo1602a *handle);
+
+// Release an SO1602A handle. The display keeps its content. NULL is
+// ignored.
+//
+// # Safety
+// `handle` must come from `so1602a_open` and not be closed yet.
+void so1602a_close(struct WbpSo1602a *handle);
+
+#endif  /* WBROKER_PERIPHERAL_H */
@@ -0,0 +1,338 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! # C ABI of the peripheral drivers
+//!
+//! Lets other languages drive the BME280 and the SO1602A, e.g. a Python
+//! provisioning script through ctypes, without running the daemon.
+//! The declarations are in `include/wbroker_peripheral.h`, generated with
+//! cbindgen (see `cbindgen.toml`).
+//!
+//! Every function returns one of the `WBP_*` codes, or nothing for the
+//! `*_close` functions. Panics are caught and reported as `WBP_ERR_PANIC`,
+//! they never unwind into the caller. A handle must not be used from two
+//! threads at once.
+
+use std::ffi::{CStr, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use peripheral::bme280::{Bme280, Bme280Error};
+use peripheral::bus::I2cBus;
+use peripheral::so1602a::{SO1602A, SO1602A_1ST_LINE, SO1602A_2ND_LINE};
+use rppal::i2c;
+use tokio::runtime::{Builder, Runtime};
+
+/// Success.
+pub const WBP_OK: i32 = 0;
+/// A required pointer argument was NULL.
+pub const WBP_ERR_NULL_POINTER: i32 = -1;
+/// An argument is out of range, or the text is not valid UTF-8.
+pub const WBP_ERR_INVALID_ARGUMENT: i32 = -2;
+/// The I2C bus or the device failed.
+pub const WBP_ERR_I2C: i32 = -3;
+/// The driver runtime could not be created.
+pub const WBP_ERR_RUNTIME: i32 = -4;
+/// The driver panicked. The handle should be closed.
+pub const WBP_ERR_PANIC: i32 = -5;
+/// Another device than a BME280 or BMP280 answered.
+pub const WBP_ERR_WRONG_CHIP: i32 = -6;
+
+/// Measurement of the BME280.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WbpMeasurement {
+    /// Temperature in °C.
+    pub temperature_c: f64,
+    /// Relative humidity in %.
+    pub humidity_relative: f64,
+    /// Pressure in Pa.
+    pub pressure_pa: f64,
+}
+
+/// I2C bus of a handle, the hardware bus or a mock in tests.
+pub struct DynBus(Box<dyn I2cBus + Send>);
+
+impl I2cBus for DynBus {
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error> {
+        self.0.smbus_write_byte(command, value)
+    }
+
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error> {
+        self.0.smbus_read_byte(command)
+    }
+
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.0.block_read(command, buffer)
+    }
+
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error> {
+        self.0.block_write(command, buffer)
+    }
+
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error> {
+        self.0.smbus_send_byte(value)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.0.read(buffer)
+    }
+
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), i2c::Error> {
+        self.0.set_slave_address(addr)
+    }
+}
+
+/// Opaque BME280 handle.
+pub struct WbpBme280 {
+    device: Bme280<DynBus>,
+    runtime: Runtime,
+}
+
+impl WbpBme280 {
+    /// Open a BME280 on the given bus.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the BME280.
+    /// # Returns
+    /// * `Err(code)` if the chip ID is wrong or the calibration cannot be
+    ///   read.
+    pub fn with_bus(bus: Box<dyn I2cBus + Send>) -> Result<Self, i32> {
+        let runtime = runtime()?;
+        let device = Bme280::with_bus(DynBus(bus)).map_err(|e| match e {
+            Bme280Error::WrongChipId { .. } => WBP_ERR_WRONG_CHIP,
+            _ => WBP_ERR_I2C,
+        })?;
+        Ok(Self { device, runtime })
+    }
+}
+
+/// Opaque SO1602A handle.
+pub struct WbpSo1602a {
+    device: SO1602A<DynBus>,
+}
+
+impl WbpSo1602a {
+    /// Set up an SO1602A on the given bus.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the SO1602A.
+    /// # Returns
+    /// * `Err(code)` if the display does not respond.
+    pub fn with_bus(bus: Box<dyn I2cBus + Send>) -> Result<Self, i32> {
+        let device = SO1602A::with_bus(DynBus(bus));
+        runtime()?
+            .block_on(device.setup())
+            .map_err(|_| WBP_ERR_I2C)?;
+        Ok(Self { device })
+    }
+}
+
+/// Runtime driving the drivers' waits.
+fn runtime() -> Result<Runtime, i32> {
+    Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .map_err(|_| WBP_ERR_RUNTIME)
+}
+
+/// Open the hardware I2C bus addressed to a device.
+fn open_bus(address: u16) -> Result<Box<dyn I2cBus + Send>, i32> {
+    let mut bus = i2c::I2c::new().map_err(|_| WBP_ERR_I2C)?;
+    bus.set_slave_address(address).map_err(|_| WBP_ERR_I2C)?;
+    Ok(Box::new(bus))
+}
+
+/// Run `f`, turning a panic into `WBP_ERR_PANIC`.
+fn guard<F: FnOnce() -> i32>(f: F) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(WBP_ERR_PANIC)
+}
+
+/// Store a new handle in `out`.
+/// # Safety
+/// `out` must be NULL or valid for writes.
+unsafe fn open_into<T>(out: *mut *mut T, open: impl FnOnce() -> Result<T, i32>) -> i32 {
+    if out.is_null() {
+        return WBP_ERR_NULL_POINTER;
+    }
+    // SAFETY: checked for NULL, valid for writes per the caller
+    unsafe { *out = ptr::null_mut() };
+    match open() {
+        Ok(handle) => {
+            // SAFETY: as above
+            unsafe { *out = Box::into_raw(Box::new(handle)) };
+            WBP_OK
+        }
+        Err(code) => code,
+    }
+}
+
+/// Open a BME280 on /dev/i2c-1.
+/// `address` is 0x76 or 0x77. On success `*out` receives a handle to be
+/// released with `bme280_close`, otherwise it is set to NULL.
+///
+/// # Safety
+/// `out` must be NULL or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bme280_open(address: u16, out: *mut *mut WbpBme280) -> i32 {
+    // SAFETY: forwarded from the caller
+    guard(|| unsafe { open_into(out, || WbpBme280::with_bus(open_bus(address)?)) })
+}
+
+/// Take a forced-mode measurement into `*out`.
+///
+/// # Safety
+/// `handle` must come from `bme280_open` and not be closed, `out` must be
+/// NULL or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bme280_read(handle: *mut WbpBme280, out: *mut WbpMeasurement) -> i32 {
+    guard(|| {
+        if handle.is_null() || out.is_null() {
+            return WBP_ERR_NULL_POINTER;
+        }
+        // SAFETY: live handle per the caller
+        let handle = unsafe { &*handle };
+        match handle.runtime.block_on(handle.device.make_measurement()) {
+            Ok(measurement) => {
+                // SAFETY: checked for NULL, valid for writes per the caller
+                unsafe {
+                    *out = WbpMeasurement {
+                        temperature_c: measurement.temperature_c,
+                        humidity_relative: measurement.humidity_relative,
+                        pressure_pa: measurement.pressure_pa,
+                    }
+                };
+                WBP_OK
+            }
+            Err(_) => WBP_ERR_I2C,
+        }
+    })
+}
+
+/// Release a BME280 handle. NULL is ignored.
+///
+/// # Safety
+/// `handle` must come from `bme280_open` and not be closed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bme280_close(handle: *mut WbpBme280) {
+    if !handle.is_null() {
+        // SAFETY: owned handle per the caller
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+/// Open and set up an SO1602A on /dev/i2c-1.
+/// `address` is 0x3c or 0x3d. On success `*out` receives a handle to be
+/// released with `so1602a_close`, otherwise it is set to NULL.
+///
+/// # Safety
+/// `out` must be NULL or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn so1602a_open(address: u16, out: *mut *mut WbpSo1602a) -> i32 {
+    // SAFETY: forwarded from the caller
+    guard(|| unsafe { open_into(out, || WbpSo1602a::with_bus(open_bus(address)?)) })
+}
+
+/// Print ASCII text at the start of a line (0 or 1).
+///
+/// # Safety
+/// `handle` must come from `so1602a_open` and not be closed, `text` must be
+/// NULL or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn so1602a_print(
+    handle: *mut WbpSo1602a,
+    line: u8,
+    text: *const c_char,
+) -> i32 {
+    guard(|| {
+        if handle.is_null() || text.is_null() {
+            return WBP_ERR_NULL_POINTER;
+        }
+        let line_addr = match line {
+            0 => SO1602A_1ST_LINE,
+            1 => SO1602A_2ND_LINE,
+            _ => return WBP_ERR_INVALID_ARGUMENT,
+        };
+        // SAFETY: NUL-terminated per the caller
+        let Ok(text) = unsafe { CStr::from_ptr(text) }.to_str() else {
+            return WBP_ERR_INVALID_ARGUMENT;
+        };
+        // SAFETY: live handle per the caller
+        let handle = unsafe { &*handle };
+        match handle.device.put_str(line_addr, text) {
+            Ok(()) => WBP_OK,
+            Err(_) => WBP_ERR_I2C,
+        }
+    })
+}
+
+/// Clear the display.
+///
+/// # Safety
+/// `handle` must come from `so1602a_open` and not be closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn so1602a_clear(handle: *mut WbpSo1602a) -> i32 {
+    guard(|| {
+        if handle.is_null() {
+            return WBP_ERR_NULL_POINTER;
+        }
+        // SAFETY: live handle per the caller
+        let handle = unsafe { &*handle };
+        match handle.device.clear_home() {
+            Ok(()) => WBP_OK,
+            Err(_) => WBP_ERR_I2C,
+        }
+    })
+}
+
+/// Release an SO1602A handle. The display keeps its content. NULL is
+/// ignored.
+///
+/// # Safety
+/// `handle` must come from `so1602a_open` and not be closed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn so1602a_close(handle: *mut WbpSo1602a) {
+    if !handle.is_null() {
+        // SAFETY: owned handle per the caller
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_catches_panic() {
+        assert_eq!(guard(|| panic!("driver bug")), WBP_ERR_PANIC);
+        assert_eq!(guard(|| WBP_OK), WBP_OK);
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        unsafe {
+            assert_eq!(bme280_open(0x76, ptr::null_mut()), WBP_ERR_NULL_POINTER);
+            let mut out = WbpMeasurement::default();
+            assert_eq!(bme280_read(ptr::null_mut(), &mut out), WBP_ERR_NULL_POINTER);
+            assert_eq!(so1602a_clear(ptr::null_mut()), WBP_ERR_NULL_POINTER);
+            bme280_close(ptr::null_mut());
+            so1602a_close(ptr::null_mut());
+        }
+    }
+}
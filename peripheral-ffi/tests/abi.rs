@@ -0,0 +1,133 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! ABI test through the exported C functions, with the devices on a mock bus.
+
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Arc;
+
+use peripheral::bus::{I2cBus, MockI2cBus};
+use rppal::i2c;
+use wbroker_peripheral::*;
+
+/// Mock bus which stays inspectable after it is moved into a handle.
+struct SharedMock(Arc<MockI2cBus>);
+
+impl I2cBus for SharedMock {
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error> {
+        self.0.smbus_write_byte(command, value)
+    }
+
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error> {
+        self.0.smbus_read_byte(command)
+    }
+
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.0.block_read(command, buffer)
+    }
+
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error> {
+        self.0.block_write(command, buffer)
+    }
+
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error> {
+        self.0.smbus_send_byte(value)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.0.read(buffer)
+    }
+
+    fn set_slave_address(&mut self, _addr: u16) -> Result<(), i2c::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_bme280_read_through_abi() {
+    let bus = MockI2cBus::new();
+    // Same chip ID, calibration and raw data as the driver's own mock test
+    bus.seed(0xD0, &[0x60]);
+    bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+    bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+    let handle = Box::into_raw(Box::new(WbpBme280::with_bus(Box::new(bus)).unwrap()));
+
+    let mut measurement = WbpMeasurement::default();
+    unsafe {
+        assert_eq!(bme280_read(handle, &mut measurement), WBP_OK);
+        assert_eq!(bme280_read(handle, ptr::null_mut()), WBP_ERR_NULL_POINTER);
+        bme280_close(handle);
+    }
+    assert!((measurement.temperature_c - 25.08).abs() < 0.01);
+
+    // BME680
+    let bus = MockI2cBus::new();
+    bus.seed(0xD0, &[0x61]);
+    assert_eq!(
+        WbpBme280::with_bus(Box::new(bus)).err(),
+        Some(WBP_ERR_WRONG_CHIP)
+    );
+}
+
+#[test]
+fn test_so1602a_print_through_abi() {
+    let bus = Arc::new(MockI2cBus::new());
+    let handle = Box::into_raw(Box::new(
+        WbpSo1602a::with_bus(Box::new(SharedMock(bus.clone()))).unwrap(),
+    ));
+    bus.clear();
+
+    let text = CString::new("Hi").unwrap();
+    unsafe {
+        assert_eq!(so1602a_print(handle, 1, text.as_ptr()), WBP_OK);
+        assert_eq!(
+            so1602a_print(handle, 2, text.as_ptr()),
+            WBP_ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(so1602a_print(handle, 0, ptr::null()), WBP_ERR_NULL_POINTER);
+        assert_eq!(so1602a_clear(handle), WBP_OK);
+        so1602a_close(handle);
+    }
+    assert_eq!(
+        bus.writes(),
+        vec![
+            (0x00, 0xA0),
+            (0x40, b'H'),
+            (0x40, b'i'),
+            (0x00, 0x01),
+            (0x00, 0x02)
+        ]
+    );
+}
+
+#[test]
+fn test_open_reports_missing_bus() {
+    // No I2C bus on the test machine: the error comes back as a code
+    let mut handle: *mut WbpBme280 = ptr::null_mut();
+    let code = unsafe { bme280_open(0x76, &mut handle) };
+    if code == WBP_OK {
+        unsafe { bme280_close(handle) };
+    } else {
+        assert_eq!(code, WBP_ERR_I2C);
+        assert!(handle.is_null());
+    }
+}
@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+# MIT License
+# Copyright (c) 2025 Yukke.org
+# See LICENSE.txt at the root of the repository.
+"""Smoke test of the C ABI through ctypes.
+
+Build the library first, then run this script from peripheral-ffi/:
+
+    cargo build --release
+    python3 tests/ctypes_smoke.py [path/to/libwbroker_peripheral.so]
+
+Without I2C hardware the open calls must fail with WBP_ERR_I2C. On a Pi with
+the BME280 and the SO1602A connected, the reading is shown on the display.
+"""
+
+import ctypes
+import pathlib
+import sys
+
+WBP_OK = 0
+WBP_ERR_NULL_POINTER = -1
+WBP_ERR_I2C = -3
+
+
+class WbpMeasurement(ctypes.Structure):
+    _fields_ = [
+        ("temperature_c", ctypes.c_double),
+        ("humidity_relative", ctypes.c_double),
+        ("pressure_pa", ctypes.c_double),
+    ]
+
+
+def load(path):
+    lib = ctypes.CDLL(str(path))
+    handle = ctypes.POINTER(ctypes.c_void_p)
+    lib.bme280_open.argtypes = [ctypes.c_uint16, ctypes.POINTER(handle)]
+    lib.bme280_open.restype = ctypes.c_int32
+    lib.bme280_read.argtypes = [handle, ctypes.POINTER(WbpMeasurement)]
+    lib.bme280_read.restype = ctypes.c_int32
+    lib.bme280_close.argtypes = [handle]
+    lib.bme280_close.restype = None
+    lib.so1602a_open.argtypes = [ctypes.c_uint16, ctypes.POINTER(handle)]
+    lib.so1602a_open.restype = ctypes.c_int32
+    lib.so1602a_print.argtypes = [handle, ctypes.c_uint8, ctypes.c_char_p]
+    lib.so1602a_print.restype = ctypes.c_int32
+    lib.so1602a_clear.argtypes = [handle]
+    lib.so1602a_clear.restype = ctypes.c_int32
+    lib.so1602a_close.argtypes = [handle]
+    lib.so1602a_close.restype = None
+    return lib, handle
+
+
+def main():
+    default = pathlib.Path(__file__).resolve().parents[1] / "target/release/libwbroker_peripheral.so"
+    lib, handle = load(sys.argv[1] if len(sys.argv) > 1 else default)
+
+    # NULL arguments are reported, not dereferenced
+    assert lib.bme280_open(0x76, None) == WBP_ERR_NULL_POINTER
+    assert lib.bme280_read(None, ctypes.byref(WbpMeasurement())) == WBP_ERR_NULL_POINTER
+    assert lib.so1602a_print(None, 0, b"x") == WBP_ERR_NULL_POINTER
+    lib.bme280_close(None)
+    lib.so1602a_close(None)
+
+    sensor = handle()
+    code = lib.bme280_open(0x76, ctypes.byref(sensor))
+    if code == WBP_ERR_I2C:
+        assert not sensor
+        print("ok (no I2C hardware)")
+        return
+    assert code == WBP_OK, code
+
+    measurement = WbpMeasurement()
+    assert lib.bme280_read(sensor, ctypes.byref(measurement)) == WBP_OK
+    lib.bme280_close(sensor)
+
+    display = handle()
+    assert lib.so1602a_open(0x3C, ctypes.byref(display)) == WBP_OK
+    assert lib.so1602a_clear(display) == WBP_OK
+    line = "%.1fC %.1f%%" % (measurement.temperature_c, measurement.humidity_relative)
+    assert lib.so1602a_print(display, 0, line.encode("ascii")) == WBP_OK
+    lib.so1602a_close(display)
+    print("ok", line)
+
+
+if __name__ == "__main__":
+    main()
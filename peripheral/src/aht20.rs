@@ -0,0 +1,255 @@
+// MIT License
+// Original by Copyright (c) 2021 Neutroni
+// Modified by Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! AHT20 temperature and humidity sensor driver for Raspberry Pi
+//!
+//! Commands and conversions follow the Aosong AHT20 datasheet. The AHT20
+//! has no pressure sensor, so measurements carry a NaN pressure.
+
+use std::sync::{Mutex, MutexGuard};
+
+use rppal::i2c::{Error, I2c};
+use tokio::time::{Duration, sleep};
+
+use crate::bme280::Measurement;
+use crate::bus::I2cBus;
+
+/// AHT20 I2C address
+pub const AHT20_ADDR: u16 = 0x38;
+
+/// Read the status byte
+const CMD_STATUS: u8 = 0x71;
+/// Load the calibration, with its two parameter bytes
+const CMD_INITIALIZE: (u8, [u8; 2]) = (0xBE, [0x08, 0x00]);
+/// Trigger a measurement, with its two parameter bytes
+const CMD_TRIGGER: (u8, [u8; 2]) = (0xAC, [0x33, 0x00]);
+/// Status bit set while a measurement is running
+const STATUS_BUSY: u8 = 0x80;
+/// Status bit set once the calibration is loaded
+const STATUS_CALIBRATED: u8 = 0x08;
+/// Wait after the initialization command
+const INITIALIZE_TIME_MS: u64 = 10;
+/// A measurement takes 80 ms
+const MEASUREMENT_TIME_MS: u64 = 80;
+/// Wait before the status is read again while the sensor is busy
+const POLL_INTERVAL_MS: u64 = 10;
+/// Reads while busy before a measurement is given up
+const MAX_POLLS: u32 = 10;
+/// Full scale of the 20-bit values
+const FULL_SCALE: f64 = (1 << 20) as f64;
+
+/// AHT20 Driver
+/// The sensor needs 40 ms after power-on before it is created.
+pub struct Aht20<B: I2cBus = I2c> {
+    bus: Mutex<B>,
+    /// Held from the trigger command until its data is read
+    measuring: tokio::sync::Mutex<()>,
+}
+
+impl Aht20<I2c> {
+    /// Create a new AHT20 instance.
+    /// # Arguments
+    /// * `addr` - I2C address of the AHT20.
+    /// # Returns
+    /// * Result<Aht20, Error>
+    pub fn new(addr: u16) -> Result<Aht20, Error> {
+        let mut bus: I2c = I2c::new()?;
+        bus.set_slave_address(addr)?;
+        return Aht20::with_bus(bus);
+    }
+}
+
+impl<B: I2cBus> Aht20<B> {
+    /// Create a new AHT20 instance on the given bus.
+    /// The status is read, and the sensor is initialized if its calibration
+    /// is not loaded yet. The thread sleeps during the initialization.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the AHT20.
+    /// # Returns
+    /// * Result<Aht20, Error>
+    pub fn with_bus(bus: B) -> Result<Aht20<B>, Error> {
+        let status: u8 = bus.smbus_read_byte(CMD_STATUS)?;
+        if status & STATUS_CALIBRATED == 0 {
+            bus.block_write(CMD_INITIALIZE.0, &CMD_INITIALIZE.1)?;
+            std::thread::sleep(Duration::from_millis(INITIALIZE_TIME_MS));
+        }
+        return Result::Ok(Aht20 {
+            bus: Mutex::new(bus),
+            measuring: tokio::sync::Mutex::new(()),
+        });
+    }
+
+    /// Lock the bus of the driver.
+    fn lock(&self) -> MutexGuard<'_, B> {
+        return self.bus.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Make a measurement.
+    /// The data is read after the measurement time, and again every 10 ms
+    /// while the busy bit is still set.
+    /// # Returns
+    /// * Result<Measurement, Error> with a NaN pressure, `Error::Io` with
+    ///   `TimedOut` if the sensor stays busy.
+    pub async fn make_measurement(&self) -> Result<Measurement, Error> {
+        let _measuring = self.measuring.lock().await;
+        self.lock().block_write(CMD_TRIGGER.0, &CMD_TRIGGER.1)?;
+        //Wait for measurement to complete, with the bus released
+        sleep(Duration::from_millis(MEASUREMENT_TIME_MS)).await;
+        let mut data: [u8; 6] = [0; 6];
+        for _ in 0..MAX_POLLS {
+            self.lock().read(&mut data)?;
+            if data[0] & STATUS_BUSY == 0 {
+                return Result::Ok(parse_measurement(&data));
+            }
+            sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        }
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "AHT20 is still busy",
+        )));
+    }
+}
+
+/// Split the six bytes of a measurement into the raw values.
+/// # Arguments
+/// * `data` - Status byte, then 20 bits of humidity and 20 bits of
+///   temperature, most significant bits first.
+/// # Returns
+/// * `(humidity, temperature)`, 20 bits each.
+pub fn unpack(data: &[u8; 6]) -> (u32, u32) {
+    let humidity: u32 =
+        ((data[1] as u32) << 12) | ((data[2] as u32) << 4) | ((data[3] as u32) >> 4);
+    let temperature: u32 =
+        (((data[3] & 0x0F) as u32) << 16) | ((data[4] as u32) << 8) | (data[5] as u32);
+    return (humidity, temperature);
+}
+
+/// Convert the six bytes of a measurement.
+/// # Arguments
+/// * `data` - Status byte and the raw values.
+/// # Returns
+/// * Measurement with a NaN pressure.
+pub fn parse_measurement(data: &[u8; 6]) -> Measurement {
+    let (humidity, temperature) = unpack(data);
+    return Measurement {
+        temperature_c: raw_to_temperature(temperature),
+        pressure_pa: f64::NAN,
+        humidity_relative: raw_to_humidity(humidity),
+    };
+}
+
+/// Convert a raw humidity (datasheet: raw / 2^20 * 100).
+/// # Arguments
+/// * `raw` - 20-bit humidity.
+/// # Returns
+/// * Relative humidity in %, 0.0 to 100.0.
+pub fn raw_to_humidity(raw: u32) -> f64 {
+    return raw as f64 / FULL_SCALE * 100.0;
+}
+
+/// Convert a raw temperature (datasheet: raw / 2^20 * 200 - 50).
+/// # Arguments
+/// * `raw` - 20-bit temperature.
+/// # Returns
+/// * Temperature in °C, -50.0 to 150.0.
+pub fn raw_to_temperature(raw: u32) -> f64 {
+    return raw as f64 / FULL_SCALE * 200.0 - 50.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{MockI2cBus, Transfer};
+
+    #[test]
+    fn test_unpack() {
+        // The third byte holds the lowest humidity and the highest
+        // temperature bits
+        assert_eq!(
+            unpack(&[0x1C, 0x12, 0x34, 0x56, 0x78, 0x9A]),
+            (0x12345, 0x6789A)
+        );
+        assert_eq!(unpack(&[0x1C, 0xFF, 0xFF, 0xF0, 0x00, 0x00]), (0xFFFFF, 0));
+        assert_eq!(unpack(&[0x1C, 0x00, 0x00, 0x0F, 0xFF, 0xFF]), (0, 0xFFFFF));
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(raw_to_humidity(0), 0.0);
+        assert_eq!(raw_to_humidity(0x80000), 50.0);
+        assert!((raw_to_humidity(0xFFFFF) - 100.0).abs() < 0.001);
+        assert_eq!(raw_to_temperature(0), -50.0);
+        assert_eq!(raw_to_temperature(0x80000), 50.0);
+        assert!((raw_to_temperature(0xFFFFF) - 150.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_parse_measurement() {
+        // 50 %, 0x5999A / 2^20 * 200 - 50 = 20 °C
+        let measurement = parse_measurement(&[0x1C, 0x80, 0x00, 0x05, 0x99, 0x9A]);
+        assert_eq!(measurement.humidity_relative, 50.0);
+        assert!((measurement.temperature_c - 20.0).abs() < 0.001);
+        assert!(measurement.pressure_pa.is_nan());
+    }
+
+    #[tokio::test]
+    async fn test_make_measurement_on_mock_bus() {
+        let bus = MockI2cBus::new();
+        // Not calibrated yet, then busy once
+        bus.seed(CMD_STATUS, &[0x10]);
+        bus.queue_read(&[0x9C, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        bus.queue_read(&[0x1C, 0x80, 0x00, 0x05, 0x99, 0x9A]);
+        let sensor = Aht20::with_bus(bus).unwrap();
+
+        let measurement = sensor.make_measurement().await.unwrap();
+        assert_eq!(measurement.humidity_relative, 50.0);
+        assert_eq!(
+            sensor.lock().transfers(),
+            vec![
+                Transfer::Read(0x71),
+                // Block writes are recorded as consecutive registers
+                Transfer::Write(0xBE, 0x08),
+                Transfer::Write(0xBF, 0x00),
+                Transfer::Write(0xAC, 0x33),
+                Transfer::Write(0xAD, 0x00),
+                Transfer::Receive(6),
+                Transfer::Receive(6),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calibrated_sensor_is_not_initialized() {
+        let bus = MockI2cBus::new();
+        bus.seed(CMD_STATUS, &[0x18]);
+        // Busy for good
+        for _ in 0..MAX_POLLS {
+            bus.queue_read(&[0x98, 0, 0, 0, 0, 0]);
+        }
+        let sensor = Aht20::with_bus(bus).unwrap();
+        assert_eq!(sensor.lock().writes(), vec![]);
+
+        match sensor.make_measurement().await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// MIT License
+// Original by Copyright (c) 2021 Neutroni
+// Modified by Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// https://www.mouser.com/datasheet/2/348/bh1750fvi-e-186247.pdf
+
+//! BH1750 ambient light sensor driver for Raspberry Pi
+
+use std::sync::{Mutex, MutexGuard};
+
+use rppal::i2c::{Error, I2c};
+
+use crate::bus::I2cBus;
+
+/// BH1750 I2C address with the ADDR pin low
+pub const BH1750_ADDR: u16 = 0x23;
+/// BH1750 I2C address with the ADDR pin high
+pub const BH1750_ADDR2: u16 = 0x5C;
+
+/// Power on, waiting for a measurement command
+const POWER_ON: u8 = 0x01;
+/// Measure continuously at 1 lx resolution, 120 ms per measurement
+const CONTINUOUS_HIGH_RES: u8 = 0x10;
+
+/// BH1750 Driver
+/// The sensor measures continuously once created, a read returns the last
+/// completed measurement.
+pub struct Bh1750<B: I2cBus = I2c> {
+    bus: Mutex<B>,
+}
+
+impl Bh1750<I2c> {
+    /// Create a new BH1750 instance.
+    /// # Arguments
+    /// * `addr` - I2C address of the BH1750.
+    /// # Returns
+    /// * Result<Bh1750, Error>
+    pub fn new(addr: u16) -> Result<Bh1750, Error> {
+        let mut bus: I2c = I2c::new()?;
+        bus.set_slave_address(addr)?;
+        return Bh1750::with_bus(bus);
+    }
+}
+
+impl<B: I2cBus> Bh1750<B> {
+    /// Create a new BH1750 instance on the given bus and start measuring.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the BH1750.
+    /// # Returns
+    /// * Result<Bh1750, Error>
+    pub fn with_bus(bus: B) -> Result<Bh1750<B>, Error> {
+        bus.session(|bus| {
+            bus.smbus_send_byte(POWER_ON)?;
+            bus.smbus_send_byte(CONTINUOUS_HIGH_RES)
+        })?;
+        return Result::Ok(Bh1750 {
+            bus: Mutex::new(bus),
+        });
+    }
+
+    /// Lock the bus of the driver.
+    fn lock(&self) -> MutexGuard<'_, B> {
+        return self.bus.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Read the illuminance.
+    /// The BH1750 has no registers, so the read repeats the running mode
+    /// command before the two result bytes, which keeps it measuring.
+    /// # Returns
+    /// * Result<f64, Error> - Illuminance in lux.
+    pub fn read_lux(&self) -> Result<f64, Error> {
+        let mut data: [u8; 2] = [0; 2];
+        self.lock()
+            .session(|bus| bus.block_read(CONTINUOUS_HIGH_RES, &mut data))?;
+        return Result::Ok(raw_to_lux(u16::from_be_bytes(data)));
+    }
+}
+
+/// Convert a high resolution mode count to lux (datasheet: count / 1.2 at
+/// the default measurement time).
+/// # Arguments
+/// * `raw` - 16-bit measurement result.
+/// # Returns
+/// * Illuminance in lux, 0.0 to 54612.5.
+pub fn raw_to_lux(raw: u16) -> f64 {
+    return raw as f64 / 1.2;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{MockI2cBus, Transfer};
+
+    #[test]
+    fn test_raw_to_lux() {
+        // Datasheet example: 1000_0011_1001_0000b = 33680 counts
+        assert!((raw_to_lux(0x8390) - 28066.7).abs() < 0.1);
+        assert_eq!(raw_to_lux(0), 0.0);
+        assert_eq!(raw_to_lux(12), 10.0);
+        assert!((raw_to_lux(u16::MAX) - 54612.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_starts_measuring_and_reads_lux() {
+        let bus = MockI2cBus::new();
+        bus.seed(CONTINUOUS_HIGH_RES, &[0x01, 0x2C]);
+
+        let sensor = Bh1750::with_bus(bus).unwrap();
+        let lux = sensor.read_lux().unwrap();
+
+        assert_eq!(lux, 250.0);
+        assert_eq!(
+            sensor.lock().transfers(),
+            vec![
+                Transfer::Send(POWER_ON),
+                Transfer::Send(CONTINUOUS_HIGH_RES),
+                Transfer::Read(CONTINUOUS_HIGH_RES),
+            ]
+        );
+    }
+}
@@ -24,62 +24,888 @@
 
 //! BME280 Driver for Raspberry Pi
 
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+
 use rppal::i2c::{Error, I2c};
 use tokio::time::{sleep, Duration};
 
+use crate::bus::I2cBus;
+use crate::math::{
+    indicated_altitude_m, parse_calibration, refine_humidity, refine_pressure, refine_temperature,
+    sea_level_pressure_hpa, CalibrationData, TemperatureData, CALIB_H_LEN, CALIB_TP_LEN,
+};
+
 /// BME280 I2C Address 1
 pub const BME280_ADDR: u16 = 0x76;
 /// BME280 I2C Address 2
 pub const BME280_ADDR2: u16 = 0x77;
+/// Chip ID of the BME280, read from register 0xD0
+pub const BME280_CHIP_ID: u8 = 0x60;
+/// Chip ID of the BMP280, which has no humidity sensor
+pub const BMP280_CHIP_ID: u8 = 0x58;
+
+//Register locations
+const REG_CONTROL_HUM: u8 = 0xF2;
+const REG_CONTROL: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+/// Reset register, see `RESET_COMMAND`
+pub const REG_RESET: u8 = 0xE0;
+/// Written to the reset register for a power-on reset
+pub const RESET_COMMAND: u8 = 0xB6;
+/// Start-up time after a reset in ms, before the calibration can be read.
+/// The datasheet gives 2 ms at most.
+pub const STARTUP_TIME_MS: u64 = 2;
+//Modes of the ctrl_meas register
+const MODE_SLEEP: u8 = 0;
+const MODE_FORCED: u8 = 1;
+const MODE_NORMAL: u8 = 3;
+
+/// BME280 measurement settings
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bme280Settings {
+    /// Temperature oversampling (0 = skipped, 1, 2, 4, 8 or 16)
+    pub oversampling_temperature: u8,
+    /// Pressure oversampling (0 = skipped, 1, 2, 4, 8 or 16)
+    pub oversampling_pressure: u8,
+    /// Humidity oversampling (0 = skipped, 1, 2, 4, 8 or 16)
+    pub oversampling_humidity: u8,
+    /// IIR filter coefficient (0 = off, 2, 4, 8 or 16)
+    pub filter: u8,
+}
+
+impl Default for Bme280Settings {
+    fn default() -> Self {
+        Bme280Settings {
+            oversampling_temperature: 1,
+            oversampling_pressure: 1,
+            oversampling_humidity: 1,
+            filter: 0,
+        }
+    }
+}
+
+impl Bme280Settings {
+    /// Check the settings.
+    /// Temperature can not be skipped because pressure and humidity
+    /// compensation depend on it.
+    /// # Returns
+    /// * `Ok(())` or the list of problems.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+        for (name, value) in [
+            ("oversampling_temperature", self.oversampling_temperature),
+            ("oversampling_pressure", self.oversampling_pressure),
+            ("oversampling_humidity", self.oversampling_humidity),
+        ] {
+            if oversampling_bits(value).is_none() {
+                errors.push(format!(
+                    "{} must be 0, 1, 2, 4, 8 or 16, got {}",
+                    name, value
+                ));
+            }
+        }
+        if self.oversampling_temperature == 0 {
+            errors.push("oversampling_temperature can not be 0".to_string());
+        }
+        if filter_bits(self.filter).is_none() {
+            errors.push(format!(
+                "filter must be 0, 2, 4, 8 or 16, got {}",
+                self.filter
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Get the oversampling rates of the settings.
+    /// Unsupported rates fall back to x1, validate() reports them.
+    pub fn oversampling(&self) -> OversamplingConfig {
+        let rate = |samples: u8| Oversampling::from_samples(samples).unwrap_or(Oversampling::X1);
+        OversamplingConfig {
+            temperature: rate(self.oversampling_temperature),
+            pressure: rate(self.oversampling_pressure),
+            humidity: rate(self.oversampling_humidity),
+        }
+    }
+
+    /// Maximum measurement time in milliseconds (datasheet 9.1), rounded up.
+    pub fn measurement_time_ms(&self) -> u64 {
+        self.oversampling().measurement_time_ms()
+    }
+
+    /// The settings with other oversampling rates.
+    /// # Arguments
+    /// * `oversampling` - Rates of the three channels.
+    pub fn with_oversampling(self, oversampling: OversamplingConfig) -> Self {
+        Bme280Settings {
+            oversampling_temperature: oversampling.temperature.samples(),
+            oversampling_pressure: oversampling.pressure.samples(),
+            oversampling_humidity: oversampling.humidity.samples(),
+            ..self
+        }
+    }
+}
+
+/// Oversampling rate of one BME280 channel
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Oversampling {
+    /// The channel is not measured, its data reads 0x80000 (0x8000 for humidity)
+    Skip,
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl Oversampling {
+    /// Get the rate for a number of samples.
+    /// # Arguments
+    /// * `samples` - Oversampling rate (0 = skipped)
+    /// # Returns
+    /// * The rate, or None if it is not supported
+    pub fn from_samples(samples: u8) -> Option<Oversampling> {
+        match samples {
+            0 => Some(Oversampling::Skip),
+            1 => Some(Oversampling::X1),
+            2 => Some(Oversampling::X2),
+            4 => Some(Oversampling::X4),
+            8 => Some(Oversampling::X8),
+            16 => Some(Oversampling::X16),
+            _ => None,
+        }
+    }
+
+    /// Get the rate for its register bits.
+    /// # Arguments
+    /// * `bits` - Register bits (0 = skipped, 1-5 = x1-x16)
+    /// # Returns
+    /// * The rate, or None for other values
+    pub fn from_bits(bits: u8) -> Option<Oversampling> {
+        match bits {
+            0 => Some(Oversampling::Skip),
+            1 => Some(Oversampling::X1),
+            2 => Some(Oversampling::X2),
+            3 => Some(Oversampling::X4),
+            4 => Some(Oversampling::X8),
+            5 => Some(Oversampling::X16),
+            _ => None,
+        }
+    }
+
+    /// Number of samples taken, 0 when skipped.
+    pub fn samples(self) -> u8 {
+        match self {
+            Oversampling::Skip => 0,
+            Oversampling::X1 => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+            Oversampling::X16 => 16,
+        }
+    }
+
+    /// Register bits of the rate (osrs_t, osrs_p and osrs_h).
+    pub fn bits(self) -> u8 {
+        match self {
+            Oversampling::Skip => 0,
+            Oversampling::X1 => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 3,
+            Oversampling::X8 => 4,
+            Oversampling::X16 => 5,
+        }
+    }
+}
+
+/// Oversampling rates of the three BME280 channels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OversamplingConfig {
+    pub temperature: Oversampling,
+    pub pressure: Oversampling,
+    pub humidity: Oversampling,
+}
+
+impl OversamplingConfig {
+    /// Value of the ctrl_hum register (0xF2).
+    pub fn control_hum(&self) -> u8 {
+        self.humidity.bits()
+    }
+
+    /// Value of the ctrl_meas register (0xF4).
+    /// # Arguments
+    /// * `mode` - Mode bits (0 = sleep, 1 = forced, 3 = normal)
+    pub fn control_meas(&self, mode: u8) -> u8 {
+        self.temperature.bits() << 5 | self.pressure.bits() << 2 | mode & 0x03
+    }
+
+    /// Maximum measurement time in milliseconds (datasheet 9.1), rounded up.
+    /// 1.25 + 2.3 * T_os + (2.3 * P_os + 0.575) + (2.3 * H_os + 0.575),
+    /// skipped channels take no time.
+    pub fn measurement_time_ms(&self) -> u64 {
+        let channel = |oversampling: Oversampling, setup: f64| match oversampling {
+            Oversampling::Skip => 0.0,
+            rate => 2.3 * rate.samples() as f64 + setup,
+        };
+        let max_time = 1.25
+            + channel(self.temperature, 0.0)
+            + channel(self.pressure, 0.575)
+            + channel(self.humidity, 0.575);
+        max_time.ceil() as u64
+    }
+}
+
+/// Convert an oversampling rate to register bits
+/// # Arguments
+/// * `samples` - Oversampling rate (0 = skipped)
+/// # Returns
+/// * Register bits, or None if the rate is not supported
+pub fn oversampling_bits(samples: u8) -> Option<u8> {
+    Oversampling::from_samples(samples).map(Oversampling::bits)
+}
+
+/// Convert an IIR filter coefficient to register bits
+/// # Arguments
+/// * `coefficient` - Filter coefficient (0 = off)
+/// # Returns
+/// * Register bits, or None if the coefficient is not supported
+pub fn filter_bits(coefficient: u8) -> Option<u8> {
+    Filter::from_coefficient(coefficient).map(Filter::bits)
+}
+
+/// IIR filter coefficient of the BME280
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default]
+    Off,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl Filter {
+    /// Get the filter for a coefficient.
+    /// # Arguments
+    /// * `coefficient` - Filter coefficient (0 = off)
+    /// # Returns
+    /// * The filter, or None if the coefficient is not supported
+    pub fn from_coefficient(coefficient: u8) -> Option<Filter> {
+        match coefficient {
+            0 => Some(Filter::Off),
+            2 => Some(Filter::X2),
+            4 => Some(Filter::X4),
+            8 => Some(Filter::X8),
+            16 => Some(Filter::X16),
+            _ => None,
+        }
+    }
+
+    /// Filter coefficient, 0 when off.
+    pub fn coefficient(self) -> u8 {
+        match self {
+            Filter::Off => 0,
+            Filter::X2 => 2,
+            Filter::X4 => 4,
+            Filter::X8 => 8,
+            Filter::X16 => 16,
+        }
+    }
+
+    /// Register bits of the filter (filter[2:0] of the config register).
+    pub fn bits(self) -> u8 {
+        match self {
+            Filter::Off => 0,
+            Filter::X2 => 1,
+            Filter::X4 => 2,
+            Filter::X8 => 3,
+            Filter::X16 => 4,
+        }
+    }
+}
+
+/// Power mode of the BME280
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// No measurements, the lowest power
+    Sleep,
+    /// One measurement per make_measurement(), then back to sleep
+    #[default]
+    Forced,
+    /// Continuous measurements, a standby time apart
+    Normal,
+}
+
+impl Mode {
+    /// Register bits of the mode (mode[1:0] of the ctrl_meas register).
+    pub fn bits(self) -> u8 {
+        match self {
+            Mode::Sleep => MODE_SLEEP,
+            Mode::Forced => MODE_FORCED,
+            Mode::Normal => MODE_NORMAL,
+        }
+    }
+}
+
+/// How the BME280 takes measurements
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Bme280Mode {
+    /// Each make_measurement() starts a conversion and waits for it
+    #[default]
+    Forced,
+    /// The chip measures continuously, make_measurement() reads the
+    /// latest result without waiting
+    Normal { standby_time: StandbyTime },
+}
+
+/// Time the BME280 waits between measurements in normal mode
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StandbyTime {
+    #[default]
+    Ms0_5,
+    Ms10,
+    Ms20,
+    Ms62_5,
+    Ms125,
+    Ms250,
+    Ms500,
+    Ms1000,
+}
+
+impl StandbyTime {
+    /// Register bits of the standby time (t_sb[2:0] of the config register).
+    pub fn bits(self) -> u8 {
+        match self {
+            StandbyTime::Ms0_5 => 0,
+            StandbyTime::Ms62_5 => 1,
+            StandbyTime::Ms125 => 2,
+            StandbyTime::Ms250 => 3,
+            StandbyTime::Ms500 => 4,
+            StandbyTime::Ms1000 => 5,
+            StandbyTime::Ms10 => 6,
+            StandbyTime::Ms20 => 7,
+        }
+    }
+}
+
+/// Value of the config register (0xF5). The SPI 3-wire bit stays off.
+/// # Arguments
+/// * `standby` - Standby time in normal mode.
+/// * `filter` - IIR filter coefficient.
+pub fn config_byte(standby: StandbyTime, filter: Filter) -> u8 {
+    standby.bits() << 5 | filter.bits() << 2
+}
+
+/// Chip found behind the driver
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Chip {
+    Bme280,
+    /// Same registers without humidity, the humidity of its measurements
+    /// is NaN
+    Bmp280,
+}
+
+impl Chip {
+    /// Get the chip for a chip ID.
+    /// # Arguments
+    /// * `id` - Value of register 0xD0
+    /// # Returns
+    /// * The chip, or None for other devices
+    pub fn from_id(id: u8) -> Option<Chip> {
+        match id {
+            BME280_CHIP_ID => Some(Chip::Bme280),
+            BMP280_CHIP_ID => Some(Chip::Bmp280),
+            _ => None,
+        }
+    }
+
+    /// Whether the chip measures humidity.
+    pub fn has_humidity(self) -> bool {
+        self == Chip::Bme280
+    }
+}
+
+/// Error of the BME280 driver
+#[derive(Debug)]
+pub enum Bme280Error {
+    /// The bus failed
+    I2c(Error),
+    /// Another device answered at the address. A BMP280 is accepted as
+    /// well, its layout is known.
+    WrongChipId {
+        /// `BME280_CHIP_ID`
+        expected: u8,
+        /// Chip ID read from register 0xD0
+        found: u8,
+    },
+    /// Settings the chip does not support
+    InvalidSettings(String),
+    /// A measurement was requested in sleep mode
+    SleepMode,
+}
+
+impl fmt::Display for Bme280Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bme280Error::I2c(e) => write!(f, "{}", e),
+            Bme280Error::WrongChipId { expected, found } => write!(
+                f,
+                "chip ID {:#04x} is neither a BME280 ({:#04x}) nor a BMP280 ({:#04x})",
+                found, expected, BMP280_CHIP_ID
+            ),
+            Bme280Error::InvalidSettings(message) => write!(f, "{}", message),
+            Bme280Error::SleepMode => write!(f, "BME280 is in sleep mode"),
+        }
+    }
+}
+
+impl std::error::Error for Bme280Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Bme280Error::I2c(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for Bme280Error {
+    fn from(e: Error) -> Self {
+        Bme280Error::I2c(e)
+    }
+}
 
 /// BME280 Driver
-pub struct Bme280 {
-    bus: I2c,
-    calibration: CalibrationData,
+/// Measurements are serialized by the driver, so an `Arc<Bme280>` can be
+/// shared by several tasks.
+pub struct Bme280<B: I2cBus = I2c> {
+    bus: Mutex<B>,
+    chip: Chip,
+    /// Read again after a soft reset
+    calibration: Mutex<CalibrationData>,
+    settings: Mutex<Bme280Settings>,
+    /// Power mode and the standby time used in normal mode
+    mode: Mutex<(Mode, StandbyTime)>,
+    /// Held from the start of a measurement until its data is read
+    measuring: tokio::sync::Mutex<()>,
+    /// Waited after a reset
+    startup_time: Duration,
 }
 
-impl Bme280 {
+impl Bme280<I2c> {
     /// Create a new BME280 instance.
     /// # Arguments
     /// * `addr` - I2C address of the BME280.
     /// # Returns
-    /// * Result<Bme280, Error>
-    pub fn new(addr: u16) -> Result<Bme280, Error> {
+    /// * Result<Bme280, Bme280Error>
+    pub fn new(addr: u16) -> Result<Bme280, Bme280Error> {
         let mut bus: I2c = I2c::new()?;
         //Default BME280 address is 0x76, but it can be set to 0x77
         bus.set_slave_address(addr)?;
-        let calibration: CalibrationData = read_calibration(&bus)?;
-        return Result::Ok(Bme280 { bus, calibration });
+        return Bme280::with_bus(bus);
+    }
+
+    /// Create a new BME280 instance and reset the chip first, so it starts
+    /// from its power-on state whatever the last run left behind.
+    /// # Arguments
+    /// * `addr` - I2C address of the BME280.
+    /// * `startup_time` - Wait after the reset, see `STARTUP_TIME_MS`.
+    /// # Returns
+    /// * Result<Bme280, Bme280Error>
+    pub fn new_with_reset(addr: u16, startup_time: Duration) -> Result<Bme280, Bme280Error> {
+        let mut bus: I2c = I2c::new()?;
+        bus.set_slave_address(addr)?;
+        return Bme280::with_bus_and_reset(bus, startup_time);
+    }
+
+    /// Create a new BME280 instance measuring with the given oversampling
+    /// rates instead of x1 on every channel.
+    /// # Arguments
+    /// * `addr` - I2C address of the BME280.
+    /// * `oversampling` - Rates of the three channels.
+    /// # Returns
+    /// * Result<Bme280, Bme280Error>, `InvalidSettings` if temperature is
+    ///   skipped
+    pub fn new_with_oversampling(
+        addr: u16,
+        oversampling: OversamplingConfig,
+    ) -> Result<Bme280, Bme280Error> {
+        let mut bus: I2c = I2c::new()?;
+        bus.set_slave_address(addr)?;
+        return Bme280::with_bus_and_oversampling(bus, oversampling);
+    }
+}
+
+impl<B: I2cBus> Bme280<B> {
+    /// Create a new BME280 instance on the given bus.
+    /// The chip ID is checked first, a BMP280 is accepted without humidity.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the BME280.
+    /// # Returns
+    /// * Result<Bme280, Bme280Error>, `WrongChipId` for other devices
+    pub fn with_bus(bus: B) -> Result<Bme280<B>, Bme280Error> {
+        return Bme280::create(bus, None);
+    }
+
+    /// Create a new BME280 instance on the given bus and reset the chip
+    /// after checking its ID. The thread sleeps during the start-up time.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the BME280.
+    /// * `startup_time` - Wait after the reset, see `STARTUP_TIME_MS`.
+    /// # Returns
+    /// * Result<Bme280, Bme280Error>, `WrongChipId` for other devices
+    pub fn with_bus_and_reset(bus: B, startup_time: Duration) -> Result<Bme280<B>, Bme280Error> {
+        return Bme280::create(bus, Some(startup_time));
+    }
+
+    /// Create a new BME280 instance on the given bus, measuring with the
+    /// given oversampling rates.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the BME280.
+    /// * `oversampling` - Rates of the three channels.
+    /// # Returns
+    /// * Result<Bme280, Bme280Error>, `WrongChipId` for other devices,
+    ///   `InvalidSettings` if temperature is skipped
+    pub fn with_bus_and_oversampling(
+        bus: B,
+        oversampling: OversamplingConfig,
+    ) -> Result<Bme280<B>, Bme280Error> {
+        let bme280: Bme280<B> = Bme280::create(bus, None)?;
+        bme280.configure(bme280.settings().with_oversampling(oversampling))?;
+        return Result::Ok(bme280);
+    }
+
+    /// Check the chip ID, reset the chip if a start-up time is given and
+    /// read the calibration.
+    fn create(bus: B, reset: Option<Duration>) -> Result<Bme280<B>, Bme280Error> {
+        let id: u8 = read_chip_id(&bus)?;
+        let chip: Chip = Chip::from_id(id).ok_or(Bme280Error::WrongChipId {
+            expected: BME280_CHIP_ID,
+            found: id,
+        })?;
+        if let Some(startup_time) = reset {
+            bus.smbus_write_byte(REG_RESET, RESET_COMMAND)?;
+            std::thread::sleep(startup_time);
+        }
+        let calibration: CalibrationData = bus.session(|bus| read_calibration(bus))?;
+        return Result::Ok(Bme280 {
+            bus: Mutex::new(bus),
+            chip,
+            calibration: Mutex::new(calibration),
+            settings: Mutex::new(Bme280Settings::default()),
+            mode: Mutex::new((Mode::Forced, StandbyTime::default())),
+            measuring: tokio::sync::Mutex::new(()),
+            startup_time: reset.unwrap_or(Duration::from_millis(STARTUP_TIME_MS)),
+        });
+    }
+
+    /// Set the wait after a reset, `STARTUP_TIME_MS` by default.
+    /// # Arguments
+    /// * `startup_time` - Wait before the calibration is read again.
+    /// # Returns
+    /// * The driver
+    pub fn with_startup_time(mut self, startup_time: Duration) -> Self {
+        self.startup_time = startup_time;
+        return self;
+    }
+
+    /// Get the wait after a reset.
+    pub fn startup_time(&self) -> Duration {
+        return self.startup_time;
+    }
+
+    /// Lock the bus of the driver.
+    /// A poisoned lock is recovered, the next measurement starts over anyway.
+    fn lock(&self) -> MutexGuard<'_, B> {
+        return self.bus.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Get the chip behind the driver.
+    pub fn chip(&self) -> Chip {
+        return self.chip;
+    }
+
+    /// Get the current measurement settings.
+    pub fn settings(&self) -> Bme280Settings {
+        return *self.settings.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Get the power mode.
+    pub fn mode(&self) -> Mode {
+        return self.mode.lock().unwrap_or_else(|e| e.into_inner()).0;
+    }
+
+    /// Get the standby time of normal mode.
+    pub fn standby(&self) -> StandbyTime {
+        return self.mode.lock().unwrap_or_else(|e| e.into_inner()).1;
+    }
+
+    /// Apply measurement settings.
+    /// The filter is written to the config register right away, the
+    /// oversampling rates are used from the next measurement on. In normal
+    /// mode the chip is restarted with the new settings.
+    /// # Arguments
+    /// * `settings` - Measurement settings.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn configure(&self, settings: Bme280Settings) -> Result<(), Bme280Error> {
+        if let Err(errors) = settings.validate() {
+            return Err(Bme280Error::InvalidSettings(errors.join(", ")));
+        }
+        return self.apply(settings, self.mode(), self.standby());
+    }
+
+    /// Set the oversampling rates from their register codes.
+    /// The filter is kept, the rates are used from the next measurement on.
+    /// # Arguments
+    /// * `temp` - Temperature code (1-5, temperature can not be skipped).
+    /// * `pres` - Pressure code (0 = skipped, 1-5 = x1-x16).
+    /// * `hum` - Humidity code (0 = skipped, 1-5 = x1-x16).
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn set_oversampling(&self, temp: u8, pres: u8, hum: u8) -> Result<(), Bme280Error> {
+        let samples = |name: &str, code: u8| match Oversampling::from_bits(code) {
+            Some(oversampling) => Ok(oversampling.samples()),
+            None => Err(Bme280Error::InvalidSettings(format!(
+                "{} oversampling code must be 0-5, got {}",
+                name, code
+            ))),
+        };
+        return self.configure(Bme280Settings {
+            oversampling_temperature: samples("temp", temp)?,
+            oversampling_pressure: samples("pres", pres)?,
+            oversampling_humidity: samples("hum", hum)?,
+            ..self.settings()
+        });
+    }
+
+    /// Measure continuously in normal mode.
+    /// The chip measures with the current oversampling rates and waits
+    /// `standby` between measurements. make_measurement() and read_latest()
+    /// then return the last result without starting a conversion.
+    /// # Arguments
+    /// * `standby` - Time between measurements.
+    /// * `filter` - IIR filter coefficient.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn start_normal_mode(
+        &self,
+        standby: StandbyTime,
+        filter: Filter,
+    ) -> Result<(), Bme280Error> {
+        let settings = Bme280Settings {
+            filter: filter.coefficient(),
+            ..self.settings()
+        };
+        return self.apply(settings, Mode::Normal, standby);
+    }
+
+    /// Switch between forced and normal mode.
+    /// # Arguments
+    /// * `mode` - How measurements are taken.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn set_operating_mode(&self, mode: Bme280Mode) -> Result<(), Bme280Error> {
+        return match mode {
+            Bme280Mode::Forced => self.set_mode(Mode::Forced),
+            Bme280Mode::Normal { standby_time } => {
+                self.apply(self.settings(), Mode::Normal, standby_time)
+            }
+        };
+    }
+
+    /// Switch to normal mode, e.g. right after `Bme280::new`.
+    /// # Arguments
+    /// * `standby_time` - Time between measurements.
+    /// # Returns
+    /// * The driver measuring continuously.
+    pub fn into_normal_mode(self, standby_time: StandbyTime) -> Result<Self, Bme280Error> {
+        self.set_operating_mode(Bme280Mode::Normal { standby_time })?;
+        return Result::Ok(self);
+    }
+
+    /// Switch back to forced mode.
+    /// # Returns
+    /// * The driver measuring on demand.
+    pub fn into_forced_mode(self) -> Result<Self, Bme280Error> {
+        self.set_operating_mode(Bme280Mode::Forced)?;
+        return Result::Ok(self);
+    }
+
+    /// Switch the power mode.
+    /// The chip only changes mode on a write to the ctrl_meas register, so
+    /// the registers are written here: the chip is put to sleep, the config
+    /// register is written, and normal mode is started again if requested.
+    /// In forced mode make_measurement() starts each conversion, in sleep
+    /// mode it fails.
+    /// # Arguments
+    /// * `mode` - New power mode.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn set_mode(&self, mode: Mode) -> Result<(), Bme280Error> {
+        return self.apply(self.settings(), mode, self.standby());
+    }
+
+    /// Set the standby time of normal mode.
+    /// The time is written to the config register right away, in normal
+    /// mode the chip is restarted with it.
+    /// # Arguments
+    /// * `standby` - Time between measurements.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub fn set_standby(&self, standby: StandbyTime) -> Result<(), Bme280Error> {
+        return self.apply(self.settings(), self.mode(), standby);
+    }
+
+    /// Write the settings and the mode to the chip.
+    /// # Arguments
+    /// * `settings` - Validated measurement settings.
+    /// * `mode` - Power mode.
+    /// * `standby` - Standby time of normal mode.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    fn apply(
+        &self,
+        settings: Bme280Settings,
+        mode: Mode,
+        standby: StandbyTime,
+    ) -> Result<(), Bme280Error> {
+        let mut current = self.mode.lock().unwrap_or_else(|e| e.into_inner());
+        let oversampling: OversamplingConfig = settings.oversampling();
+        let filter: Filter = Filter::from_coefficient(settings.filter).unwrap_or_default();
+        let config: u8 = config_byte(standby, filter);
+        let running: bool = current.0 == Mode::Normal;
+        let humidity: bool = self.chip.has_humidity();
+        self.lock().session(|bus| {
+            //Writes to the config register may be ignored in normal mode
+            if running || mode != Mode::Forced {
+                bus.smbus_write_byte(REG_CONTROL, oversampling.control_meas(MODE_SLEEP))?;
+            }
+            bus.smbus_write_byte(REG_CONFIG, config)?;
+            if mode == Mode::Normal {
+                if humidity {
+                    bus.smbus_write_byte(REG_CONTROL_HUM, oversampling.control_hum())?;
+                }
+                bus.smbus_write_byte(REG_CONTROL, oversampling.control_meas(MODE_NORMAL))?;
+            }
+            Ok(())
+        })?;
+        *current = (mode, standby);
+        *self.settings.lock().unwrap_or_else(|e| e.into_inner()) = settings;
+        return Result::Ok(());
+    }
+
+    /// Reset the chip as on power-on.
+    /// Writes 0xB6 to register 0xE0, waits the start-up time and reads the
+    /// calibration again. The chip is back in sleep mode with its default
+    /// settings afterwards, and so is the driver: forced mode with the
+    /// default settings.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub async fn reset(&self) -> Result<(), Bme280Error> {
+        let _measuring = self.measuring.lock().await;
+        return self.reset_locked().await;
+    }
+
+    /// Reset the chip as on power-on and read the calibration again.
+    /// The settings and the mode of the driver are written back afterwards,
+    /// so measurements continue as before.
+    /// # Returns
+    /// * Result<(), Bme280Error>
+    pub async fn soft_reset(&self) -> Result<(), Bme280Error> {
+        let _measuring = self.measuring.lock().await;
+        let (settings, mode, standby) = (self.settings(), self.mode(), self.standby());
+        self.reset_locked().await?;
+        return self.apply(settings, mode, standby);
+    }
+
+    /// Reset the chip, with the measuring lock held.
+    async fn reset_locked(&self) -> Result<(), Bme280Error> {
+        self.lock().smbus_write_byte(REG_RESET, RESET_COMMAND)?;
+        sleep(self.startup_time).await;
+        let calibration: CalibrationData = self.lock().session(|bus| read_calibration(bus))?;
+        *self.calibration.lock().unwrap_or_else(|e| e.into_inner()) = calibration;
+        *self.mode.lock().unwrap_or_else(|e| e.into_inner()) =
+            (Mode::Forced, StandbyTime::default());
+        *self.settings.lock().unwrap_or_else(|e| e.into_inner()) = Bme280Settings::default();
+        return Result::Ok(());
+    }
+
+    /// Read the last measurement without starting a conversion.
+    /// In forced mode this is the result of the last make_measurement().
+    /// # Returns
+    /// * Result<Measurement, Bme280Error>
+    pub async fn read_latest(&self) -> Result<Measurement, Bme280Error> {
+        let _measuring = self.measuring.lock().await;
+        return self.read_data();
     }
 
     /// Make a measurement.
+    /// Concurrent callers take turns, a second measurement is not started
+    /// before the data of the first one is read. The conversion is waited
+    /// for with the runtime's timer, so no worker thread is blocked.
+    /// # Returns
+    /// * Result<Measurement, Bme280Error>
+    pub async fn make_measurement(&self) -> Result<Measurement, Bme280Error> {
+        let _measuring = self.measuring.lock().await;
+        if let Some(wait_time) = self.start_measurement()? {
+            //Wait for measurement to complete, with the bus released
+            sleep(wait_time).await;
+        }
+        return self.read_data();
+    }
+
+    /// Make a measurement without an async runtime.
+    /// The thread sleeps during the conversion. Must not be called from
+    /// async code, use make_measurement() there.
+    /// # Returns
+    /// * Result<Measurement, Bme280Error>
+    #[cfg(feature = "blocking")]
+    pub fn make_measurement_blocking(&self) -> Result<Measurement, Bme280Error> {
+        let _measuring = self.measuring.blocking_lock();
+        if let Some(wait_time) = self.start_measurement()? {
+            std::thread::sleep(wait_time);
+        }
+        return self.read_data();
+    }
+
+    /// Start a forced measurement.
     /// # Returns
-    /// * Result<Measurement, Error>
-    pub async fn make_measurement(&self) -> Result<Measurement, Error> {
-        //Oversampling settings
-        const OVERSAMPLE_TEMP: u8 = 1;
-        const OVERSAMPLE_PRES: u8 = 1;
-        const OVERSAMPLE_HUM: u8 = 1;
+    /// * Time until the data is ready, None in normal mode where the chip
+    ///   measures on its own.
+    fn start_measurement(&self) -> Result<Option<Duration>, Bme280Error> {
+        match self.mode() {
+            Mode::Normal => return Result::Ok(None),
+            Mode::Sleep => return Err(Bme280Error::SleepMode),
+            Mode::Forced => {}
+        }
+        //Oversampling settings, validated by configure()
+        let oversampling: OversamplingConfig = self.settings().oversampling();
         //Forced mode: perform one measurement, store result and return to sleep mode
-        const MODE: u8 = 1;
-        const CONTROL: u8 = OVERSAMPLE_TEMP << 5 | OVERSAMPLE_PRES << 2 | MODE;
-        //Register locations
-        const REG_DATA: u8 = 0xF7;
-        const REG_CONTROL: u8 = 0xF4;
-        const REG_CONTROL_HUM: u8 = 0xF2;
+        let control: u8 = oversampling.control_meas(MODE_FORCED);
         //Start the measurement
-        self.bus.smbus_write_byte(REG_CONTROL_HUM, OVERSAMPLE_HUM)?;
-        self.bus.smbus_write_byte(REG_CONTROL, CONTROL)?;
-        //Wait for measurement to complete
-        const WAIT_TIME: u64 = ((1.25
-            + (2.3 * (OVERSAMPLE_TEMP as f64))
-            + ((2.3 * (OVERSAMPLE_PRES as f64)) + 0.575)
-            + ((2.3 * OVERSAMPLE_HUM as f64) + 0.575)) as u64)
-            + 1;
-        sleep(Duration::from_millis(WAIT_TIME)).await;
+        self.lock().session(|bus| {
+            if self.chip.has_humidity() {
+                bus.smbus_write_byte(REG_CONTROL_HUM, oversampling.control_hum())?;
+            }
+            bus.smbus_write_byte(REG_CONTROL, control)
+        })?;
+        let wait_time: u64 = oversampling.measurement_time_ms() + 1;
+        return Result::Ok(Some(Duration::from_millis(wait_time)));
+    }
+
+    /// Read and compensate the data registers.
+    /// # Returns
+    /// * Result<Measurement, Bme280Error>
+    fn read_data(&self) -> Result<Measurement, Bme280Error> {
         //Read measured data
         let mut data: [u8; 8] = [0; 8];
-        self.bus.block_read(REG_DATA, &mut data)?;
+        self.lock()
+            .session(|bus| bus.block_read(REG_DATA, &mut data))?;
         //Parse read data to i32 values
         let pres_raw: i32 =
             ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | ((data[2] as i32) >> 4);
@@ -87,11 +913,15 @@ impl Bme280 {
             ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | ((data[5] as i32) >> 4);
         let hum_raw: i32 = ((data[6] as i32) << 8) | (data[7] as i32);
         //Refine read values
-        let temperature_data: TemperatureData = refine_temperature(temp_raw, &self.calibration);
+        let calibration = self.calibration.lock().unwrap_or_else(|e| e.into_inner());
+        let temperature_data: TemperatureData = refine_temperature(temp_raw, &calibration);
         let t_fine: i32 = temperature_data.t_fine;
         let temperature_c: f64 = temperature_data.temperature_c;
-        let humidity_relative: f64 = refine_humidity(hum_raw, &self.calibration, t_fine);
-        let pressure_pa: f64 = refine_pressure(pres_raw, &self.calibration, t_fine);
+        let humidity_relative: f64 = match self.chip {
+            Chip::Bme280 => refine_humidity(hum_raw, &calibration, t_fine),
+            Chip::Bmp280 => f64::NAN,
+        };
+        let pressure_pa: f64 = refine_pressure(pres_raw, &calibration, t_fine);
 
         return Result::Ok(Measurement {
             temperature_c,
@@ -118,195 +948,185 @@ pub struct Measurement {
     pub humidity_relative: f64,
 }
 
-/// Calibration data
-#[derive(Debug)]
-struct CalibrationData {
-    dig_t1: u16,
-    dig_t2: i16,
-    dig_t3: i16,
-    dig_p1: u16,
-    dig_p2: i16,
-    dig_p3: i16,
-    dig_p4: i16,
-    dig_p5: i16,
-    dig_p6: i16,
-    dig_p7: i16,
-    dig_p8: i16,
-    dig_p9: i16,
-    dig_h1: u8,
-    dig_h2: i16,
-    dig_h3: u8,
-    dig_h4: i16,
-    dig_h5: i16,
-    dig_h6: i8,
-}
-
-/// Temperature data
-#[derive(Debug)]
-struct TemperatureData {
-    /// Temperature fine
-    t_fine: i32,
-    /// Temperature in Celsius
-    temperature_c: f64,
+/// Per-field difference between two measurements.
+/// A field is `None` when it is NaN on either side, so a missing reading is
+/// never mistaken for "no change" or for a jump.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeasurementDelta {
+    /// Temperature difference in Celsius (°C)
+    pub temperature_c: Option<f64>,
+    /// Pressure difference in pascal (Pa)
+    pub pressure_pa: Option<f64>,
+    /// Humidity difference in percent (%)
+    pub humidity_relative: Option<f64>,
+}
+
+impl Measurement {
+    /// Difference to an earlier measurement.
+    /// # Arguments
+    /// * `other` - Measurement to compare with.
+    /// # Returns
+    /// * `self - other` for each field.
+    pub fn delta(&self, other: &Measurement) -> MeasurementDelta {
+        MeasurementDelta {
+            temperature_c: field_delta(self.temperature_c, other.temperature_c),
+            pressure_pa: field_delta(self.pressure_pa, other.pressure_pa),
+            humidity_relative: field_delta(self.humidity_relative, other.humidity_relative),
+        }
+    }
+
+    /// Altitude of the measurement, from the barometric formula.
+    /// # Arguments
+    /// * `sea_level_pa` - Reference pressure at sea level in Pa.
+    /// # Returns
+    /// * Altitude in m.
+    pub fn altitude_m(&self, sea_level_pa: f64) -> f64 {
+        altitude_m(self.pressure_pa, sea_level_pa)
+    }
+
+    /// Dew point of the measurement, see `dew_point_c`.
+    /// # Returns
+    /// * Dew point in Celsius, NaN without humidity.
+    pub fn dew_point_c(&self) -> f64 {
+        dew_point_c(self.temperature_c, self.humidity_relative)
+    }
 }
 
-/// Get i16 value from u8 array
+/// Dew point from the Magnus formula.
 /// # Arguments
-/// * `arr` - u8 array
-/// * `index` - index
+/// * `temperature_c` - Temperature in Celsius.
+/// * `humidity_relative` - Relative humidity in %.
 /// # Returns
-/// * i16
-fn get_i16_from_u8_array(arr: &[u8], index: usize) -> i16 {
-    return ((arr[index + 1] as i16) << 8) | (arr[index] as i16);
+/// * Dew point in Celsius, -inf at 0 % RH.
+pub fn dew_point_c(temperature_c: f64, humidity_relative: f64) -> f64 {
+    const B: f64 = 17.62;
+    const C: f64 = 243.12;
+    let gamma = (humidity_relative / 100.0).ln() + B * temperature_c / (C + temperature_c);
+    C * gamma / (B - gamma)
 }
 
-/// Get u16 value from u8 array
+/// Altitude for a pressure, from the barometric formula of the ICAO
+/// standard atmosphere.
 /// # Arguments
-/// * `arr` - u8 array
-/// * `index` - index
+/// * `pressure_pa` - Measured pressure in Pa.
+/// * `sea_level_pa` - Reference pressure at sea level in Pa.
 /// # Returns
-/// * u16
-fn get_u16_from_u8_array(arr: &[u8], index: usize) -> u16 {
-    return ((arr[index + 1] as u16) << 8) | (arr[index] as u16);
+/// * Altitude in m, negative below sea level.
+pub fn altitude_m(pressure_pa: f64, sea_level_pa: f64) -> f64 {
+    indicated_altitude_m(pressure_pa / 100.0, sea_level_pa / 100.0)
 }
 
-/// Read calibration data
+/// Pressure reduced to sea level, the inverse of `altitude_m`.
 /// # Arguments
-/// * `bus` - I2c
+/// * `pressure_pa` - Measured pressure in Pa.
+/// * `altitude_m` - Altitude of the sensor in m.
 /// # Returns
-/// * Result<CalibrationData, Error>
-fn read_calibration(bus: &I2c) -> Result<CalibrationData, Error> {
-    let mut cal1: [u8; 24] = [0; 24];
-    bus.block_read(0x88, &mut cal1)?;
-    let cal2: u8 = bus.smbus_read_byte(0xA1)?;
-    let mut cal3: [u8; 7] = [0; 7];
-    bus.block_read(0xE1, &mut cal3)?;
+/// * Sea level equivalent pressure in Pa.
+pub fn sea_level_pressure(pressure_pa: f64, altitude_m: f64) -> f64 {
+    sea_level_pressure_hpa(pressure_pa / 100.0, altitude_m) * 100.0
+}
+
+/// Difference of one field, `None` if it is not a number.
+fn field_delta(value: f64, other: f64) -> Option<f64> {
+    let delta = value - other;
+    (!delta.is_nan()).then_some(delta)
+}
 
-    //Convert byte data to word values
-    let dig_t1: u16 = get_u16_from_u8_array(&cal1, 0);
-    let dig_t2: i16 = get_i16_from_u8_array(&cal1, 2);
-    let dig_t3: i16 = get_i16_from_u8_array(&cal1, 4);
-
-    let dig_p1: u16 = get_u16_from_u8_array(&cal1, 6);
-    let dig_p2: i16 = get_i16_from_u8_array(&cal1, 8);
-    let dig_p3: i16 = get_i16_from_u8_array(&cal1, 10);
-    let dig_p4: i16 = get_i16_from_u8_array(&cal1, 12);
-    let dig_p5: i16 = get_i16_from_u8_array(&cal1, 14);
-    let dig_p6: i16 = get_i16_from_u8_array(&cal1, 16);
-    let dig_p7: i16 = get_i16_from_u8_array(&cal1, 18);
-    let dig_p8: i16 = get_i16_from_u8_array(&cal1, 20);
-    let dig_p9: i16 = get_i16_from_u8_array(&cal1, 22);
-
-    let dig_h1: u8 = cal2;
-    let dig_h2: i16 = get_i16_from_u8_array(&cal3, 0);
-    let dig_h3: u8 = cal3[2];
-
-    let e4: u8 = cal3[3];
-    let e5: u8 = cal3[4];
-    let e6: u8 = cal3[5];
-
-    let dig_h4: i16 = ((e4 as i16) << 4) | ((e5 & 0x0F) as i16);
-    let dig_h5: i16 = ((e6 as i16) << 4) | ((e5 >> 4) as i16);
-    let dig_h6: i8 = cal3[6] as i8;
-
-    return Result::Ok(CalibrationData {
-        dig_t1,
-        dig_t2,
-        dig_t3,
-        dig_p1,
-        dig_p2,
-        dig_p3,
-        dig_p4,
-        dig_p5,
-        dig_p6,
-        dig_p7,
-        dig_p8,
-        dig_p9,
-        dig_h1,
-        dig_h2,
-        dig_h3,
-        dig_h4,
-        dig_h5,
-        dig_h6,
-    });
-}
-
-/// Refine temperature
+/// Read the chip ID
 /// # Arguments
-/// * `temp_raw` - Raw temperature value
-/// * `calibration` - Calibration data
+/// * `bus` - I2C bus
 /// # Returns
-/// * TemperatureData - Refined temperature data
-fn refine_temperature(temp_raw: i32, calibration: &CalibrationData) -> TemperatureData {
-    let var1: f64 = ((temp_raw as f64) / 16384.0 - (calibration.dig_t1 as f64) / 1024.0)
-        * (calibration.dig_t2 as f64);
-    let var2: f64 = (((temp_raw as f64) / 131072.0 - (calibration.dig_t1 as f64) / 8192.0)
-        * ((temp_raw as f64) / 131072.0 - (calibration.dig_t1 as f64) / 8192.0))
-        * (calibration.dig_t3 as f64);
-    let sum: f64 = var1 + var2;
-    let t_fine: i32 = sum as i32;
-    let temperature_c: f64 = sum / 5120.0;
-    return TemperatureData {
-        t_fine,
-        temperature_c,
-    };
-}
-
-/// Refine pressure
+/// * Result<u8, Bme280Error>
+pub fn read_chip_id(bus: &dyn I2cBus) -> Result<u8, Bme280Error> {
+    const REG_CHIP_ID: u8 = 0xD0;
+    return Result::Ok(bus.smbus_read_byte(REG_CHIP_ID)?);
+}
+
+/// What was found at an address while detecting a BME280
+#[derive(Debug)]
+pub enum Probe {
+    /// Nothing answered
+    NoResponse(Bme280Error),
+    /// Another device answered, with this chip ID
+    WrongChipId(u8),
+}
+
+/// No BME280 was found at any of the tried addresses
+#[derive(Debug)]
+pub struct DetectError {
+    /// What was found at each tried address, in order
+    pub tried: Vec<(u16, Probe)>,
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no BME280 found")?;
+        for (i, (address, probe)) in self.tried.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            match probe {
+                Probe::NoResponse(e) => {
+                    write!(f, "{}{:#04x} did not respond ({})", separator, address, e)?
+                }
+                Probe::WrongChipId(id) => {
+                    write!(f, "{}{:#04x} has chip ID {:#04x}", separator, address, id)?
+                }
+            }
+        }
+        return Result::Ok(());
+    }
+}
+
+impl std::error::Error for DetectError {}
+
+/// Find a BME280 by its chip ID.
+/// The addresses are tried in order, the first one answering with
+/// `BME280_CHIP_ID` is used.
 /// # Arguments
-/// * `pres_raw` - Raw pressure value
-/// * `calibration` - Calibration data
-/// * `t_fine` - Temperature fine
+/// * `addresses` - I2C addresses to try, e.g. `[BME280_ADDR, BME280_ADDR2]`
+/// * `device` - Opens the bus addressed to an address
 /// # Returns
-/// * f64 - Pressure in pascal
-fn refine_pressure(pres_raw: i32, calibration: &CalibrationData, t_fine: i32) -> f64 {
-    let mut var1: f64 = ((t_fine as f64) / 2.0) - 64000.0;
-    let mut var2: f64 = var1 * var1 * (calibration.dig_p6 as f64) / 32768.0;
-    var2 = var2 + var1 * (calibration.dig_p5 as f64) * 2.0;
-    var2 = (var2 / 4.0) + ((calibration.dig_p4 as f64) * 65536.0);
-    var1 = ((calibration.dig_p3 as f64) * var1 * var1 / 524288.0
-        + (calibration.dig_p2 as f64) * var1)
-        / 524288.0;
-    var1 = (1.0 + var1 / 32768.0) * (calibration.dig_p1 as f64);
-    if var1 == 0.0 {
-        return 0.0; // avoid exception caused by division by zero
-    }
-    let mut p: f64 = 1048576.0 - (pres_raw as f64);
-    p = (p - (var2 / 4096.0)) * 6250.0 / var1;
-    var1 = (calibration.dig_p9 as f64) * p * p / 2147483648.0;
-    var2 = p * (calibration.dig_p8 as f64) / 32768.0;
-    p = p + (var1 + var2 + (calibration.dig_p7 as f64)) / 16.0;
-    return p;
-}
-
-/// Refine humidity
+/// * `Ok((address, bus))` of the BME280, or what was found at each address
+pub fn detect<B: I2cBus>(
+    addresses: &[u16],
+    mut device: impl FnMut(u16) -> B,
+) -> Result<(u16, B), DetectError> {
+    let mut tried: Vec<(u16, Probe)> = Vec::new();
+    for &address in addresses {
+        let bus: B = device(address);
+        match read_chip_id(&bus) {
+            Ok(BME280_CHIP_ID) => return Result::Ok((address, bus)),
+            Ok(id) => tried.push((address, Probe::WrongChipId(id))),
+            Err(e) => tried.push((address, Probe::NoResponse(e))),
+        }
+    }
+    return Err(DetectError { tried });
+}
+
+/// Read calibration data
 /// # Arguments
-/// * `hum_raw` - Raw humidity value
-/// * `calibration` - Calibration data
-/// * `t_fine` - Temperature fine
+/// * `bus` - I2C bus in a session
 /// # Returns
-/// * f64 - Humidity in percent
-fn refine_humidity(hum_raw: i32, calibration: &CalibrationData, t_fine: i32) -> f64 {
-    let mut var_h = (t_fine as f64) - 76800.0;
-    var_h = ((hum_raw as f64)
-        - ((calibration.dig_h4 as f64) * 64.0 + (calibration.dig_h5 as f64) / 16384.0 * var_h))
-        * ((calibration.dig_h2 as f64) / 65536.0
-            * (1.0
-                + (calibration.dig_h6 as f64) / 67108864.0
-                    * var_h
-                    * (1.0 + (calibration.dig_h3 as f64) / 67108864.0 * var_h)));
-    var_h = var_h * (1.0 - (calibration.dig_h1 as f64) * var_h / 524288.0);
-    if var_h > 100.0 {
-        var_h = 100.0;
-    } else if var_h < 0.0 {
-        var_h = 0.0;
-    }
-    return var_h;
+/// * Result<CalibrationData, Error>
+fn read_calibration(bus: &dyn I2cBus) -> Result<CalibrationData, Error> {
+    let mut cal1: [u8; CALIB_TP_LEN] = [0; CALIB_TP_LEN];
+    bus.block_read(0x88, &mut cal1)?;
+    let cal2: u8 = bus.smbus_read_byte(0xA1)?;
+    let mut cal3: [u8; CALIB_H_LEN] = [0; CALIB_H_LEN];
+    bus.block_read(0xE1, &mut cal3)?;
+    return Result::Ok(parse_calibration(&cal1, cal2, &cal3));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bus::{MockI2cBus, SharedI2c, Transfer};
+
+    /// Mock bus answering with the BME280 chip ID.
+    fn mock_bus() -> MockI2cBus {
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[BME280_CHIP_ID]);
+        bus
+    }
 
     #[test]
     fn test_measurement_creation() {
@@ -321,6 +1141,98 @@ mod tests {
         assert_eq!(measurement.humidity_relative, 50.0);
     }
 
+    #[test]
+    fn test_measurement_delta() {
+        let earlier = Measurement {
+            temperature_c: 24.5,
+            pressure_pa: 101325.0,
+            humidity_relative: 55.0,
+        };
+        let later = Measurement {
+            temperature_c: 25.0,
+            pressure_pa: 101300.0,
+            humidity_relative: 55.0,
+        };
+
+        let delta = later.delta(&earlier);
+        assert_eq!(delta.temperature_c, Some(0.5));
+        assert_eq!(delta.pressure_pa, Some(-25.0));
+        assert_eq!(delta.humidity_relative, Some(0.0));
+        assert_eq!(earlier.delta(&later).temperature_c, Some(-0.5));
+    }
+
+    #[test]
+    fn test_measurement_delta_with_nan() {
+        let earlier = Measurement {
+            temperature_c: 24.5,
+            pressure_pa: 101325.0,
+            humidity_relative: f64::NAN,
+        };
+        let later = Measurement {
+            temperature_c: f64::NAN,
+            pressure_pa: 101325.0,
+            humidity_relative: 60.0,
+        };
+
+        let delta = later.delta(&earlier);
+        assert_eq!(delta.temperature_c, None);
+        assert_eq!(delta.pressure_pa, Some(0.0));
+        assert_eq!(delta.humidity_relative, None);
+    }
+
+    #[test]
+    fn test_altitude_and_sea_level_pressure_are_inverses() {
+        for (pressure_pa, sea_level_pa) in [
+            (101325.0, 101325.0),
+            (95000.0, 102000.0),
+            (70000.0, 99000.0),
+        ] {
+            let altitude = altitude_m(pressure_pa, sea_level_pa);
+            let reduced = sea_level_pressure(pressure_pa, altitude);
+            assert!(
+                (reduced - sea_level_pa).abs() < 1e-6,
+                "{}: {}",
+                pressure_pa,
+                reduced
+            );
+        }
+        let measurement = Measurement {
+            temperature_c: 15.0,
+            pressure_pa: 89875.0,
+            humidity_relative: 50.0,
+        };
+        let altitude = measurement.altitude_m(101325.0);
+        assert!((altitude - 1000.0).abs() < 1.0, "{}", altitude);
+        assert!(altitude_m(102000.0, 101325.0) < 0.0);
+    }
+
+    #[test]
+    fn test_dew_point() {
+        // Saturated air condenses at its own temperature
+        for temperature_c in [-20.0, 0.0, 15.5, 40.0] {
+            let dew_point = dew_point_c(temperature_c, 100.0);
+            assert!((dew_point - temperature_c).abs() < 1e-9, "{}", dew_point);
+        }
+        // Reference values of the Magnus formula
+        for (temperature_c, humidity_relative, expected) in [
+            (20.0, 50.0, 9.26),
+            (25.0, 60.0, 16.69),
+            (30.0, 80.0, 26.17),
+            (10.0, 90.0, 8.43),
+            (0.0, 50.0, -9.20),
+        ] {
+            let dew_point = dew_point_c(temperature_c, humidity_relative);
+            assert!((dew_point - expected).abs() < 0.01, "{}", dew_point);
+        }
+        let measurement = Measurement {
+            temperature_c: 20.0,
+            pressure_pa: 101325.0,
+            humidity_relative: 50.0,
+        };
+        assert!((measurement.dew_point_c() - 9.26).abs() < 0.01);
+        assert!(dew_point_c(20.0, f64::NAN).is_nan());
+    }
+
     #[test]
     fn test_measurement_within_ranges() {
         let measurement = Measurement {
@@ -334,107 +1246,89 @@ mod tests {
         assert!(measurement.humidity_relative >= 0.0 && measurement.humidity_relative <= 100.0);
     }
 
-    #[test]
-    fn test_get_u16_from_u8_array() {
-        let data = [0x34, 0x12, 0x78, 0x56];
-        let result = get_u16_from_u8_array(&data, 0);
-        assert_eq!(result, 0x1234);
-
-        let result2 = get_u16_from_u8_array(&data, 2);
-        assert_eq!(result2, 0x5678);
-    }
+    /// Calibration registers of the datasheet's example values, as read
+    /// from 0x88, 0xA1 and 0xE1.
+    const CALIB_TP: [u8; CALIB_TP_LEN] = [
+        0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC, // T1-T3
+        0x7D, 0x8E, 0x43, 0xD6, 0xD0, 0x0B, // P1-P3
+        0x27, 0x0B, 0x8C, 0x00, 0xF9, 0xFF, // P4-P6
+        0x8C, 0x3C, 0xF8, 0xC6, 0x70, 0x17, // P7-P9
+    ];
+    const CALIB_H1: u8 = 0x4B;
+    const CALIB_H: [u8; CALIB_H_LEN] = [0x6A, 0x01, 0x00, 0x14, 0x2A, 0x03, 0x1E];
 
     #[test]
-    fn test_get_i16_from_u8_array() {
-        let data = [0xFF, 0xFF, 0x00, 0x01];
-        let result = get_i16_from_u8_array(&data, 0);
-        assert_eq!(result, -1);
-
-        let result2 = get_i16_from_u8_array(&data, 2);
-        assert_eq!(result2, 256);
+    fn test_parse_calibration() {
+        let calibration = parse_calibration(&CALIB_TP, CALIB_H1, &CALIB_H);
+        assert_eq!(
+            calibration,
+            CalibrationData {
+                dig_t1: 27504,
+                dig_t2: 26435,
+                dig_t3: -1000,
+                dig_p1: 36477,
+                dig_p2: -10685,
+                dig_p3: 3024,
+                dig_p4: 2855,
+                dig_p5: 140,
+                dig_p6: -7,
+                dig_p7: 15500,
+                dig_p8: -14600,
+                dig_p9: 6000,
+                dig_h1: 75,
+                dig_h2: 362,
+                dig_h3: 0,
+                dig_h4: 330,
+                dig_h5: 50,
+                dig_h6: 30,
+            }
+        );
     }
 
     #[test]
-    fn test_refine_temperature() {
-        let calibration = CalibrationData {
-            dig_t1: 27504,
-            dig_t2: 26435,
-            dig_t3: -1000,
-            dig_p1: 0,
-            dig_p2: 0,
-            dig_p3: 0,
-            dig_p4: 0,
-            dig_p5: 0,
-            dig_p6: 0,
-            dig_p7: 0,
-            dig_p8: 0,
-            dig_p9: 0,
-            dig_h1: 0,
-            dig_h2: 0,
-            dig_h3: 0,
-            dig_h4: 0,
-            dig_h5: 0,
-            dig_h6: 0,
-        };
-
-        let temp_raw = 519888;
-        let result = refine_temperature(temp_raw, &calibration);
-        assert!(result.temperature_c > 0.0);
-        assert!(result.t_fine != 0);
+    fn test_read_calibration_from_registers() {
+        let bus = MockI2cBus::new();
+        bus.seed(0x88, &CALIB_TP);
+        bus.seed(0xA1, &[CALIB_H1]);
+        bus.seed(0xE1, &CALIB_H);
+        assert_eq!(
+            read_calibration(&bus).unwrap(),
+            parse_calibration(&CALIB_TP, CALIB_H1, &CALIB_H)
+        );
     }
 
     #[test]
-    fn test_refine_pressure_zero_division() {
-        let calibration = CalibrationData {
-            dig_t1: 0,
-            dig_t2: 0,
-            dig_t3: 0,
-            dig_p1: 0,
-            dig_p2: 0,
-            dig_p3: 0,
-            dig_p4: 0,
-            dig_p5: 0,
-            dig_p6: 0,
-            dig_p7: 0,
-            dig_p8: 0,
-            dig_p9: 0,
-            dig_h1: 0,
-            dig_h2: 0,
-            dig_h3: 0,
-            dig_h4: 0,
-            dig_h5: 0,
-            dig_h6: 0,
-        };
+    fn test_detect_picks_the_responding_address() {
+        let bus = MockI2cBus::new();
+        bus.unplug(BME280_ADDR);
+        bus.seed(0xD0, &[BME280_CHIP_ID]);
+        let shared = SharedI2c::new(bus);
 
-        let result = refine_pressure(100000, &calibration, 128000);
-        assert_eq!(result, 0.0);
+        let addresses = [BME280_ADDR, BME280_ADDR2];
+        let (address, device) = detect(&addresses, |address| shared.device(address)).unwrap();
+        assert_eq!(address, BME280_ADDR2);
+        assert_eq!(device.address(), BME280_ADDR2);
+        assert!(Bme280::with_bus(device).is_ok());
     }
 
     #[test]
-    fn test_refine_humidity_boundary_values() {
-        let calibration = CalibrationData {
-            dig_t1: 0,
-            dig_t2: 0,
-            dig_t3: 0,
-            dig_p1: 0,
-            dig_p2: 0,
-            dig_p3: 0,
-            dig_p4: 0,
-            dig_p5: 0,
-            dig_p6: 0,
-            dig_p7: 0,
-            dig_p8: 0,
-            dig_p9: 0,
-            dig_h1: 75,
-            dig_h2: 365,
-            dig_h3: 0,
-            dig_h4: 328,
-            dig_h5: 0,
-            dig_h6: 30,
-        };
+    fn test_detect_reports_every_address() {
+        let bus = MockI2cBus::new();
+        bus.unplug(BME280_ADDR);
+        // BMP280
+        bus.seed(0xD0, &[0x58]);
+        let shared = SharedI2c::new(bus);
 
-        let result = refine_humidity(32768, &calibration, 128000);
-        assert!(result >= 0.0 && result <= 100.0);
+        let addresses = [BME280_ADDR, BME280_ADDR2];
+        let error = detect(&addresses, |address| shared.device(address))
+            .err()
+            .unwrap();
+        assert_eq!(error.tried.len(), 2);
+        assert!(matches!(error.tried[0], (0x76, Probe::NoResponse(_))));
+        assert!(matches!(error.tried[1], (0x77, Probe::WrongChipId(0x58))));
+        let message = error.to_string();
+        assert!(message.starts_with("no BME280 found: 0x76 did not respond ("));
+        assert!(message.ends_with(", 0x77 has chip ID 0x58"));
     }
 
     #[test]
@@ -466,4 +1360,466 @@ mod tests {
         assert_eq!(cloned.pressure_pa, original.pressure_pa);
         assert_eq!(copied.humidity_relative, original.humidity_relative);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_chip_id_is_checked() {
+        // BME680
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[0x61]);
+        let error = Bme280::with_bus(bus).err().unwrap();
+        assert!(matches!(
+            error,
+            Bme280Error::WrongChipId {
+                expected: 0x60,
+                found: 0x61
+            }
+        ));
+        assert!(
+            error
+                .to_string()
+                .starts_with("chip ID 0x61 is neither a BME280")
+        );
+
+        let bus = MockI2cBus::new();
+        bus.unplug(BME280_ADDR);
+        let shared = SharedI2c::new(bus);
+        let error = Bme280::with_bus(shared.device(BME280_ADDR)).err().unwrap();
+        assert!(matches!(error, Bme280Error::I2c(_)));
+
+        // A BMP280 measures without humidity
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[BMP280_CHIP_ID]);
+        let bmp280 = Bme280::with_bus(bus).unwrap();
+        assert_eq!(bmp280.chip(), Chip::Bmp280);
+        assert!(!bmp280.chip().has_humidity());
+        let measurement = bmp280.make_measurement().await.unwrap();
+        assert!(measurement.humidity_relative.is_nan());
+        assert_eq!(bmp280.lock().writes(), vec![(0xF4, 0x25)]);
+    }
+
+    #[tokio::test]
+    async fn test_make_measurement_on_mock_bus() {
+        let bus = mock_bus();
+        // dig_t1 = 27504, dig_t2 = 26435, dig_t3 = -1000
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+        let bme280 = Bme280::with_bus(bus).unwrap();
+        assert_eq!(bme280.calibration.lock().unwrap().dig_t1, 27504);
+        assert_eq!(bme280.calibration.lock().unwrap().dig_t3, -1000);
+
+        let measurement = bme280.make_measurement().await.unwrap();
+        assert_eq!(bme280.lock().writes(), vec![(0xF2, 0x01), (0xF4, 0x25)]);
+        assert!((measurement.temperature_c - 25.08).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_soft_reset_on_mock_bus() {
+        let bus = mock_bus();
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        let bme280 = Bme280::with_bus(bus)
+            .unwrap()
+            .into_normal_mode(StandbyTime::Ms125)
+            .unwrap();
+        // dig_t1 = 27505 after the reset
+        bme280.lock().seed(0x88, &[0x71]);
+        bme280.lock().clear();
+
+        bme280.soft_reset().await.unwrap();
+        assert_eq!(bme280.calibration.lock().unwrap().dig_t1, 27505);
+        // Reset, then normal mode is started again
+        assert_eq!(
+            bme280.lock().writes(),
+            vec![
+                (0xE0, 0xB6),
+                (0xF4, 0x24),
+                (0xF5, 0x40),
+                (0xF2, 0x01),
+                (0xF4, 0x27)
+            ]
+        );
+        // The calibration is read after the reset
+        assert_eq!(bme280.lock().transfers()[1], Transfer::Read(0x88));
+    }
+
+    #[tokio::test]
+    async fn test_reset_on_mock_bus() {
+        assert_eq!((REG_RESET, RESET_COMMAND), (0xE0, 0xB6));
+        let bus = mock_bus();
+        bus.seed(0x88, &[0x70, 0x6B]);
+        let bme280 = Bme280::with_bus_and_reset(bus, Duration::from_millis(1)).unwrap();
+        // The chip ID is checked before the reset, the calibration after it
+        assert_eq!(
+            bme280.lock().transfers()[..3],
+            [
+                Transfer::Read(0xD0),
+                Transfer::Write(0xE0, 0xB6),
+                Transfer::Read(0x88)
+            ]
+        );
+        assert_eq!(bme280.startup_time(), Duration::from_millis(1));
+
+        let bme280 = bme280
+            .with_startup_time(Duration::ZERO)
+            .into_normal_mode(StandbyTime::Ms125)
+            .unwrap();
+        bme280.lock().clear();
+        bme280.reset().await.unwrap();
+        // Nothing is written back after a plain reset
+        assert_eq!(bme280.lock().writes(), vec![(0xE0, 0xB6)]);
+        assert_eq!(bme280.mode(), Mode::Forced);
+        assert_eq!(bme280.standby(), StandbyTime::default());
+        assert_eq!(bme280.settings(), Bme280Settings::default());
+
+        // Another device is not reset
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[0x61]);
+        assert!(Bme280::with_bus_and_reset(bus, Duration::ZERO).is_err());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_make_measurement_blocking_on_mock_bus() {
+        let bus = mock_bus();
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+        let bme280 = Bme280::with_bus(bus).unwrap();
+
+        let started = std::time::Instant::now();
+        let measurement = bme280.make_measurement_blocking().unwrap();
+        // 1.25 + 2.3 + 2.875 + 2.875 = 9.3ms, rounded up, plus 1ms
+        assert!(started.elapsed() >= Duration::from_millis(11));
+        assert_eq!(bme280.lock().writes(), vec![(0xF2, 0x01), (0xF4, 0x25)]);
+        assert!((measurement.temperature_c - 25.08).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_measurements_do_not_interleave() {
+        let bme280 = std::sync::Arc::new(Bme280::with_bus(mock_bus()).unwrap());
+        bme280.lock().clear();
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let bme280 = bme280.clone();
+                tokio::spawn(async move {
+                    for _ in 0..5 {
+                        bme280.make_measurement().await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Every measurement is started, waited for and read before the next
+        let transfers = bme280.lock().transfers();
+        assert_eq!(transfers.len(), 8 * 5 * 3);
+        for measurement in transfers.chunks(3) {
+            assert_eq!(
+                measurement,
+                [
+                    Transfer::Write(0xF2, 0x01),
+                    Transfer::Write(0xF4, 0x25),
+                    Transfer::Read(0xF7)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        assert!(Bme280Settings::default().validate().is_ok());
+
+        let settings = Bme280Settings {
+            oversampling_temperature: 0,
+            oversampling_pressure: 3,
+            oversampling_humidity: 16,
+            filter: 5,
+        };
+        let errors = settings.validate().unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("oversampling_pressure"));
+    }
+
+    #[test]
+    fn test_measurement_time() {
+        // 1.25 + 2.3 + 2.875 + 2.875 = 9.3ms
+        assert_eq!(Bme280Settings::default().measurement_time_ms(), 10);
+        let settings = Bme280Settings {
+            oversampling_temperature: 16,
+            oversampling_pressure: 0,
+            oversampling_humidity: 0,
+            filter: 0,
+        };
+        assert_eq!(settings.measurement_time_ms(), 39);
+    }
+
+    #[test]
+    fn test_oversampling_control_bytes() {
+        let oversampling = OversamplingConfig {
+            temperature: Oversampling::X2,
+            pressure: Oversampling::X16,
+            humidity: Oversampling::X4,
+        };
+        assert_eq!(oversampling.control_hum(), 0x03);
+        // osrs_t 010, osrs_p 101, forced mode 01
+        assert_eq!(oversampling.control_meas(1), 0x55);
+        // 1.25 + 4.6 + 37.375 + 9.775 = 53ms
+        assert_eq!(oversampling.measurement_time_ms(), 53);
+
+        let skipped = OversamplingConfig {
+            pressure: Oversampling::Skip,
+            humidity: Oversampling::Skip,
+            ..oversampling
+        };
+        assert_eq!(skipped.control_hum(), 0);
+        // osrs_p bits are 000
+        assert_eq!(skipped.control_meas(1) & 0x1C, 0);
+        assert_eq!(skipped.control_meas(1), 0x41);
+
+        for samples in [0, 1, 2, 4, 8, 16] {
+            let rate = Oversampling::from_samples(samples).unwrap();
+            assert_eq!(rate.samples(), samples);
+            assert_eq!(oversampling_bits(samples), Some(rate.bits()));
+        }
+        assert_eq!(Oversampling::from_samples(3), None);
+        assert_eq!(
+            Bme280Settings::default().oversampling(),
+            OversamplingConfig {
+                temperature: Oversampling::X1,
+                pressure: Oversampling::X1,
+                humidity: Oversampling::X1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configure_on_mock_bus() {
+        let bme280 = Bme280::with_bus(mock_bus()).unwrap();
+        let settings = Bme280Settings {
+            oversampling_temperature: 2,
+            oversampling_pressure: 16,
+            oversampling_humidity: 4,
+            filter: 4,
+        };
+        bme280.configure(settings).unwrap();
+        assert_eq!(bme280.settings(), settings);
+
+        bme280.make_measurement().await.unwrap();
+        assert_eq!(
+            bme280.lock().writes(),
+            vec![(0xF5, 0x08), (0xF2, 0x03), (0xF4, 0x55)]
+        );
+
+        let invalid = Bme280Settings {
+            filter: 3,
+            ..settings
+        };
+        assert!(matches!(
+            bme280.configure(invalid),
+            Err(Bme280Error::InvalidSettings(_))
+        ));
+        assert_eq!(bme280.settings(), settings);
+    }
+
+    #[tokio::test]
+    async fn test_created_with_oversampling() {
+        let oversampling = OversamplingConfig {
+            temperature: Oversampling::X2,
+            pressure: Oversampling::X16,
+            humidity: Oversampling::X4,
+        };
+        let bme280 = Bme280::with_bus_and_oversampling(mock_bus(), oversampling).unwrap();
+        assert_eq!(bme280.settings().oversampling(), oversampling);
+        bme280.lock().clear();
+        bme280.make_measurement().await.unwrap();
+        assert_eq!(bme280.lock().writes(), vec![(0xF2, 0x03), (0xF4, 0x55)]);
+
+        let skipped = OversamplingConfig {
+            temperature: Oversampling::Skip,
+            ..oversampling
+        };
+        assert!(matches!(
+            Bme280::with_bus_and_oversampling(mock_bus(), skipped),
+            Err(Bme280Error::InvalidSettings(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_set_oversampling_on_mock_bus() {
+        let bme280 = Bme280::with_bus(mock_bus()).unwrap();
+        // (temp, pres, hum) codes, ctrl_meas in forced mode, ctrl_hum
+        let combinations = [
+            ((1, 1, 1), 0x25, 0x01),
+            ((5, 0, 3), 0xA1, 0x03),
+            ((2, 5, 0), 0x55, 0x00),
+            ((3, 4, 5), 0x71, 0x05),
+        ];
+        for ((temp, pres, hum), control, control_hum) in combinations {
+            bme280.set_oversampling(temp, pres, hum).unwrap();
+            bme280.lock().clear();
+            bme280.make_measurement().await.unwrap();
+            assert_eq!(
+                bme280.lock().writes(),
+                vec![(0xF2, control_hum), (0xF4, control)]
+            );
+        }
+        // 1.25 + 9.2 + 18.975 + 37.375 = 66.8ms
+        assert_eq!(bme280.settings().measurement_time_ms(), 67);
+
+        let settings = bme280.settings();
+        assert!(matches!(
+            bme280.set_oversampling(6, 1, 1),
+            Err(Bme280Error::InvalidSettings(_))
+        ));
+        assert!(bme280.set_oversampling(1, 1, 8).is_err());
+        // Temperature compensates the other channels
+        assert!(bme280.set_oversampling(0, 1, 1).is_err());
+        assert_eq!(bme280.settings(), settings);
+    }
+
+    #[test]
+    fn test_config_byte() {
+        let standby = [
+            (StandbyTime::Ms0_5, 0x00),
+            (StandbyTime::Ms62_5, 0x20),
+            (StandbyTime::Ms125, 0x40),
+            (StandbyTime::Ms250, 0x60),
+            (StandbyTime::Ms500, 0x80),
+            (StandbyTime::Ms1000, 0xA0),
+            (StandbyTime::Ms10, 0xC0),
+            (StandbyTime::Ms20, 0xE0),
+        ];
+        for (time, expected) in standby {
+            assert_eq!(config_byte(time, Filter::Off), expected, "{:?}", time);
+        }
+        let filters = [
+            (Filter::Off, 0x00),
+            (Filter::X2, 0x04),
+            (Filter::X4, 0x08),
+            (Filter::X8, 0x0C),
+            (Filter::X16, 0x10),
+        ];
+        for (filter, expected) in filters {
+            assert_eq!(
+                config_byte(StandbyTime::Ms0_5, filter),
+                expected,
+                "{:?}",
+                filter
+            );
+            assert_eq!(Filter::from_coefficient(filter.coefficient()), Some(filter));
+        }
+        assert_eq!(config_byte(StandbyTime::Ms125, Filter::X16), 0x50);
+        assert_eq!(Filter::from_coefficient(3), None);
+    }
+
+    #[tokio::test]
+    async fn test_normal_mode_on_mock_bus() {
+        let bus = mock_bus();
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+        let bme280 = Bme280::with_bus(bus).unwrap();
+
+        bme280
+            .start_normal_mode(StandbyTime::Ms125, Filter::X4)
+            .unwrap();
+        assert_eq!(bme280.mode(), Mode::Normal);
+        assert_eq!(bme280.standby(), StandbyTime::Ms125);
+        assert_eq!(bme280.settings().filter, 4);
+        // Sleep, config, then ctrl_hum and ctrl_meas in normal mode
+        assert_eq!(
+            bme280.lock().writes(),
+            vec![(0xF4, 0x24), (0xF5, 0x48), (0xF2, 0x01), (0xF4, 0x27)]
+        );
+
+        // Measurements only read the data registers
+        bme280.lock().clear();
+        let measurement = bme280.make_measurement().await.unwrap();
+        assert!((measurement.temperature_c - 25.08).abs() < 0.01);
+        bme280.read_latest().await.unwrap();
+        assert!(bme280.lock().writes().is_empty());
+
+        // The standby time stays in the config register
+        bme280.set_mode(Mode::Forced).unwrap();
+        assert_eq!(bme280.mode(), Mode::Forced);
+        assert_eq!(bme280.lock().writes(), vec![(0xF4, 0x24), (0xF5, 0x48)]);
+        bme280.lock().clear();
+        bme280.make_measurement().await.unwrap();
+        assert_eq!(bme280.lock().writes(), vec![(0xF2, 0x01), (0xF4, 0x25)]);
+    }
+
+    #[tokio::test]
+    async fn test_operating_modes_on_mock_bus() {
+        let bme280 = Bme280::with_bus(mock_bus())
+            .unwrap()
+            .into_normal_mode(StandbyTime::Ms62_5)
+            .unwrap();
+        assert_eq!(bme280.mode(), Mode::Normal);
+        assert_eq!(
+            bme280.lock().writes(),
+            vec![(0xF4, 0x24), (0xF5, 0x20), (0xF2, 0x01), (0xF4, 0x27)]
+        );
+        // No control register writes and no wait for a conversion
+        bme280.lock().clear();
+        for _ in 0..3 {
+            bme280.make_measurement().await.unwrap();
+        }
+        assert!(bme280.lock().writes().is_empty());
+
+        let bme280 = bme280.into_forced_mode().unwrap();
+        assert_eq!(bme280.mode(), Mode::Forced);
+        bme280.lock().clear();
+        bme280.make_measurement().await.unwrap();
+        assert_eq!(bme280.lock().writes(), vec![(0xF2, 0x01), (0xF4, 0x25)]);
+
+        bme280
+            .set_operating_mode(Bme280Mode::Normal {
+                standby_time: StandbyTime::Ms1000,
+            })
+            .unwrap();
+        assert_eq!(bme280.standby(), StandbyTime::Ms1000);
+        assert_eq!(Bme280Mode::default(), Bme280Mode::Forced);
+    }
+
+    #[tokio::test]
+    async fn test_set_mode_and_standby_on_mock_bus() {
+        let bme280 = Bme280::with_bus(mock_bus()).unwrap();
+        bme280
+            .configure(Bme280Settings {
+                filter: 16,
+                ..Bme280Settings::default()
+            })
+            .unwrap();
+        bme280.lock().clear();
+
+        // In forced mode only the config register is written
+        bme280.set_standby(StandbyTime::Ms1000).unwrap();
+        assert_eq!(bme280.standby(), StandbyTime::Ms1000);
+        assert_eq!(bme280.lock().writes(), vec![(0xF5, 0xB0)]);
+
+        bme280.lock().clear();
+        bme280.set_mode(Mode::Normal).unwrap();
+        bme280.set_standby(StandbyTime::Ms20).unwrap();
+        assert_eq!(
+            bme280.lock().writes(),
+            vec![
+                (0xF4, 0x24),
+                (0xF5, 0xB0),
+                (0xF2, 0x01),
+                (0xF4, 0x27),
+                (0xF4, 0x24),
+                (0xF5, 0xF0),
+                (0xF2, 0x01),
+                (0xF4, 0x27),
+            ]
+        );
+
+        bme280.lock().clear();
+        bme280.set_mode(Mode::Sleep).unwrap();
+        assert_eq!(bme280.lock().writes(), vec![(0xF4, 0x24), (0xF5, 0xF0)]);
+        assert!(matches!(
+            bme280.make_measurement().await,
+            Err(Bme280Error::SleepMode)
+        ));
+        assert_eq!(Mode::Normal.bits(), 3);
+    }
+}
@@ -0,0 +1,584 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! # I2C bus abstraction
+//!
+//! Drivers talk to the bus through `I2cBus` so they can run against
+//! `rppal::i2c::I2c` on the Pi and against `MockI2cBus` in tests.
+//!
+//! Several devices can share one bus through `SharedI2c`. Each device gets
+//! an `I2cDevice` handle which selects its slave address and holds the bus
+//! for a whole `session`, so a frame write or a measurement read is not
+//! interleaved with transfers to another device.
+//!
+//! ## Concurrency
+//!
+//! The drivers take `&self` and keep their bus behind a `std::sync::Mutex`,
+//! locked for one session at a time and never across an await. Operations
+//! which wait for the device between sessions, such as a BME280
+//! measurement, also hold an async lock of the driver, so a second caller
+//! waits instead of restarting the device halfway. A driver can therefore
+//! be shared between tasks as `Arc<SO1602A>` or `Arc<Bme280>`, whether it
+//! owns its bus or uses an `I2cDevice`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rppal::i2c;
+
+use crate::chaos::FaultInjector;
+
+/// Subset of the I2C operations used by the drivers.
+pub trait I2cBus {
+    /// Write a byte to a register (SMBus Write Byte).
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error>;
+    /// Read a byte from a register (SMBus Read Byte).
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error>;
+    /// Read consecutive registers starting at `command`.
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error>;
+    /// Write `command` followed by `buffer` (I2C Block Write).
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error>;
+    /// Send a single byte without a register (SMBus Send Byte).
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error>;
+    /// Read bytes without a register, for devices which answer a command
+    /// sent earlier.
+    /// # Arguments
+    /// * `buffer` - Filled completely, a short read is an error
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error>;
+    /// Select the slave address used by the following transfers.
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), i2c::Error>;
+
+    /// Wait between transfers, for devices which need pacing.
+    /// The thread is blocked, so only short delays belong here.
+    /// # Arguments
+    /// * `duration` - Time to wait
+    fn delay(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    /// Run `f` as one bus session.
+    /// A shared bus is held for the whole closure, so `f` must not block for
+    /// long and the session must never be kept across an await.
+    /// # Arguments
+    /// * `f` - Transfers to run on the bus
+    /// # Returns
+    /// * Result of `f`
+    fn session<T, F>(&self, f: F) -> Result<T, i2c::Error>
+    where
+        Self: Sized,
+        F: FnOnce(&dyn I2cBus) -> Result<T, i2c::Error>,
+    {
+        f(self)
+    }
+}
+
+impl I2cBus for i2c::I2c {
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error> {
+        i2c::I2c::smbus_write_byte(self, command, value)
+    }
+
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error> {
+        i2c::I2c::smbus_read_byte(self, command)
+    }
+
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        i2c::I2c::block_read(self, command, buffer)
+    }
+
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error> {
+        i2c::I2c::block_write(self, command, buffer)
+    }
+
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error> {
+        i2c::I2c::smbus_send_byte(self, value)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        // I2c::read needs the bus mutably. A combined transfer writing
+        // nothing reads the whole buffer the same way
+        i2c::I2c::write_read(self, &[], buffer)
+    }
+
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), i2c::Error> {
+        i2c::I2c::set_slave_address(self, addr)
+    }
+}
+
+/// Bus state guarded by the arbiter.
+#[derive(Debug)]
+struct Arbiter<B> {
+    bus: B,
+    address: Option<u16>,
+}
+
+/// I2C bus shared by several devices.
+#[derive(Debug)]
+pub struct SharedI2c<B: I2cBus = i2c::I2c> {
+    inner: Arc<Mutex<Arbiter<B>>>,
+}
+
+impl<B: I2cBus> Clone for SharedI2c<B> {
+    fn clone(&self) -> Self {
+        SharedI2c {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl SharedI2c<i2c::I2c> {
+    /// Open the default I2C bus for sharing
+    /// # Returns
+    /// * Result<SharedI2c, i2c::Error>
+    pub fn open() -> Result<SharedI2c<i2c::I2c>, i2c::Error> {
+        Ok(SharedI2c::new(i2c::I2c::new()?))
+    }
+
+    /// Get the clock speed of the bus.
+    /// The speed is set by the kernel (`dtparam=i2c_arm_baudrate` on the
+    /// Pi) and can not be changed from user space.
+    /// # Returns
+    /// * Result<u32, i2c::Error> - Clock speed in Hz
+    pub fn clock_speed(&self) -> Result<u32, i2c::Error> {
+        self.lock().bus.clock_speed()
+    }
+}
+
+impl<B: I2cBus> SharedI2c<B> {
+    /// Share the given bus
+    /// # Arguments
+    /// * `bus` - I2C bus
+    /// # Returns
+    /// * SharedI2c instance
+    pub fn new(bus: B) -> SharedI2c<B> {
+        SharedI2c {
+            inner: Arc::new(Mutex::new(Arbiter { bus, address: None })),
+        }
+    }
+
+    /// Get a handle to the device at the given address
+    /// # Arguments
+    /// * `addr` - I2C address of the device
+    /// # Returns
+    /// * I2cDevice instance
+    pub fn device(&self, addr: u16) -> I2cDevice<B> {
+        I2cDevice {
+            shared: self.clone(),
+            address: addr,
+        }
+    }
+
+    /// Lock the bus.
+    /// A panic in another session does not leave the bus in a broken state,
+    /// so a poisoned lock is recovered.
+    fn lock(&self) -> MutexGuard<'_, Arbiter<B>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Handle to one device on a `SharedI2c` bus.
+/// Each transfer outside of a `session` is a session of its own.
+#[derive(Debug, Clone)]
+pub struct I2cDevice<B: I2cBus = i2c::I2c> {
+    shared: SharedI2c<B>,
+    address: u16,
+}
+
+impl<B: I2cBus> I2cDevice<B> {
+    /// Get the I2C address of the device
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+}
+
+impl<B: I2cBus> I2cBus for I2cDevice<B> {
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error> {
+        self.session(|bus| bus.smbus_write_byte(command, value))
+    }
+
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error> {
+        self.session(|bus| bus.smbus_read_byte(command))
+    }
+
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.session(|bus| bus.block_read(command, buffer))
+    }
+
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error> {
+        self.session(|bus| bus.block_write(command, buffer))
+    }
+
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error> {
+        self.session(|bus| bus.smbus_send_byte(value))
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.session(|bus| bus.read(buffer))
+    }
+
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), i2c::Error> {
+        self.address = addr;
+        Ok(())
+    }
+
+    fn delay(&self, duration: Duration) {
+        // Held like a transfer, so no other device writes in the gap
+        let _ = self.session(|bus| {
+            bus.delay(duration);
+            Ok(())
+        });
+    }
+
+    fn session<T, F>(&self, f: F) -> Result<T, i2c::Error>
+    where
+        F: FnOnce(&dyn I2cBus) -> Result<T, i2c::Error>,
+    {
+        f(&self.select()?.bus)
+    }
+}
+
+impl<B: I2cBus> I2cDevice<B> {
+    /// Lock the bus and address it to this device.
+    fn select(&self) -> Result<MutexGuard<'_, Arbiter<B>>, i2c::Error> {
+        let mut arbiter = self.shared.lock();
+        if arbiter.address != Some(self.address) {
+            // Forget the address first so a failed switch is retried
+            arbiter.address = None;
+            arbiter.bus.set_slave_address(self.address)?;
+            arbiter.address = Some(self.address);
+        }
+        Ok(arbiter)
+    }
+}
+
+/// Transfer recorded by `MockI2cBus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// `(register, value)` written.
+    Write(u8, u8),
+    /// Register read from, the first one of a block read.
+    Read(u8),
+    /// Byte sent without a register.
+    Send(u8),
+    /// Number of bytes read without a register.
+    Receive(usize),
+    /// Wait between transfers.
+    Delay(Duration),
+}
+
+/// Mock bus recording writes and returning seeded register values.
+/// Registers which were not seeded read as 0. A block write is recorded as
+/// writes to consecutive registers. Reads without a register
+/// return the queued replies in order, zeros once none is left. Delays are
+/// recorded as transfers without waiting. Every address answers with the
+/// same registers, except addresses marked with `unplug`, where transfers
+/// fail. Faults of the whole bus are scripted through `faults`.
+#[derive(Debug, Default)]
+pub struct MockI2cBus {
+    transfers: Mutex<Vec<Transfer>>,
+    writes: Mutex<Vec<(u8, u8)>>,
+    sent: Mutex<Vec<u8>>,
+    registers: Mutex<HashMap<u8, u8>>,
+    replies: Mutex<VecDeque<Vec<u8>>>,
+    addresses: Mutex<Vec<u16>>,
+    selected: Mutex<Option<u16>>,
+    unplugged: Mutex<Vec<u16>>,
+    faults: Arc<FaultInjector>,
+}
+
+impl MockI2cBus {
+    /// Create a new mock bus.
+    pub fn new() -> MockI2cBus {
+        MockI2cBus::default()
+    }
+
+    /// Seed register values starting at `register`.
+    /// # Arguments
+    /// * `register` - First register
+    /// * `data` - Values of the consecutive registers
+    pub fn seed(&self, register: u8, data: &[u8]) {
+        let mut registers = self.registers.lock().unwrap();
+        for (i, d) in data.iter().enumerate() {
+            registers.insert(register.wrapping_add(i as u8), *d);
+        }
+    }
+
+    /// Queue the reply of the next read without a register.
+    /// # Arguments
+    /// * `data` - Bytes returned, zero-padded to the length read
+    pub fn queue_read(&self, data: &[u8]) {
+        self.replies.lock().unwrap().push_back(data.to_vec());
+    }
+
+    /// Make transfers to `addr` fail as if nothing was connected there.
+    /// # Arguments
+    /// * `addr` - I2C address without a device
+    pub fn unplug(&self, addr: u16) {
+        self.unplugged.lock().unwrap().push(addr);
+    }
+
+    /// Faults injected into the transfers, shared with the caller.
+    pub fn faults(&self) -> Arc<FaultInjector> {
+        Arc::clone(&self.faults)
+    }
+
+    /// Recorded `(register, value)` writes, oldest first.
+    pub fn writes(&self) -> Vec<(u8, u8)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// Recorded bytes sent without a register, oldest first.
+    pub fn sent(&self) -> Vec<u8> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Recorded transfers of every kind, oldest first.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        self.transfers.lock().unwrap().clone()
+    }
+
+    /// Recorded slave address selections, oldest first.
+    pub fn addresses(&self) -> Vec<u16> {
+        self.addresses.lock().unwrap().clone()
+    }
+
+    /// Record a transfer in order.
+    /// # Returns
+    /// * `Err` without recording if the selected address is unplugged or a
+    ///   fault is injected
+    fn record(&self, transfer: Transfer) -> Result<(), i2c::Error> {
+        let selected = *self.selected.lock().unwrap();
+        if selected.is_some_and(|a| self.unplugged.lock().unwrap().contains(&a)) {
+            // EREMOTEIO, as the kernel reports a missing acknowledge
+            return Err(i2c::Error::Io(std::io::Error::from_raw_os_error(121)));
+        }
+        self.faults.check()?;
+        self.transfers.lock().unwrap().push(transfer);
+        Ok(())
+    }
+
+    /// Forget all recorded writes.
+    pub fn clear(&self) {
+        self.transfers.lock().unwrap().clear();
+        self.writes.lock().unwrap().clear();
+        self.sent.lock().unwrap().clear();
+        self.addresses.lock().unwrap().clear();
+    }
+}
+
+impl I2cBus for MockI2cBus {
+    fn smbus_write_byte(&self, command: u8, value: u8) -> Result<(), i2c::Error> {
+        self.record(Transfer::Write(command, value))?;
+        self.writes.lock().unwrap().push((command, value));
+        Ok(())
+    }
+
+    fn smbus_read_byte(&self, command: u8) -> Result<u8, i2c::Error> {
+        self.record(Transfer::Read(command))?;
+        Ok(*self.registers.lock().unwrap().get(&command).unwrap_or(&0))
+    }
+
+    fn block_read(&self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.record(Transfer::Read(command))?;
+        let registers = self.registers.lock().unwrap();
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = *registers.get(&command.wrapping_add(i as u8)).unwrap_or(&0);
+        }
+        Ok(())
+    }
+
+    fn block_write(&self, command: u8, buffer: &[u8]) -> Result<(), i2c::Error> {
+        for (i, value) in buffer.iter().enumerate() {
+            self.smbus_write_byte(command.wrapping_add(i as u8), *value)?;
+        }
+        Ok(())
+    }
+
+    fn smbus_send_byte(&self, value: u8) -> Result<(), i2c::Error> {
+        self.record(Transfer::Send(value))?;
+        self.sent.lock().unwrap().push(value);
+        Ok(())
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        self.record(Transfer::Receive(buffer.len()))?;
+        let reply = self.replies.lock().unwrap().pop_front();
+        let reply = reply.unwrap_or_default();
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = *reply.get(i).unwrap_or(&0);
+        }
+        Ok(())
+    }
+
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), i2c::Error> {
+        self.addresses.get_mut().unwrap().push(addr);
+        *self.selected.get_mut().unwrap() = Some(addr);
+        Ok(())
+    }
+
+    fn delay(&self, duration: Duration) {
+        // Not a transfer, so neither unplugged addresses nor faults apply
+        self.transfers
+            .lock()
+            .unwrap()
+            .push(Transfer::Delay(duration));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_records_writes() {
+        let bus = MockI2cBus::new();
+        bus.smbus_write_byte(0xF4, 0x25).unwrap();
+        bus.smbus_send_byte(0x08).unwrap();
+        bus.smbus_read_byte(0xD0).unwrap();
+        assert_eq!(bus.writes(), vec![(0xF4, 0x25)]);
+        assert_eq!(bus.sent(), vec![0x08]);
+        assert_eq!(
+            bus.transfers(),
+            vec![
+                Transfer::Write(0xF4, 0x25),
+                Transfer::Send(0x08),
+                Transfer::Read(0xD0)
+            ]
+        );
+
+        bus.clear();
+        assert!(bus.writes().is_empty());
+        assert!(bus.sent().is_empty());
+        assert!(bus.transfers().is_empty());
+    }
+
+    #[test]
+    fn test_mock_seeded_reads() {
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[0x60]);
+        bus.seed(0xF7, &[1, 2, 3]);
+        assert_eq!(bus.smbus_read_byte(0xD0).unwrap(), 0x60);
+        assert_eq!(bus.smbus_read_byte(0xD1).unwrap(), 0);
+
+        let mut buffer = [0u8; 4];
+        bus.block_read(0xF7, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3, 0]);
+
+        bus.block_write(0xAC, &[0x33, 0x00]).unwrap();
+        assert_eq!(bus.writes(), vec![(0xAC, 0x33), (0xAD, 0x00)]);
+    }
+
+    #[test]
+    fn test_device_read_without_register() {
+        let bus = MockI2cBus::new();
+        bus.queue_read(&[0xBE, 0xEF, 0x92]);
+        bus.queue_read(&[0x01]);
+        let shared = SharedI2c::new(bus);
+        let sensor = shared.device(0x44);
+
+        let mut buffer = [0u8; 3];
+        sensor.read(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xBE, 0xEF, 0x92]);
+        // Also within a session
+        let mut status = [0u8; 1];
+        sensor.session(|bus| bus.read(&mut status)).unwrap();
+        assert_eq!(status, [0x01]);
+        sensor.read(&mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0, 0]);
+
+        let arbiter = shared.lock();
+        assert_eq!(arbiter.bus.addresses(), vec![0x44]);
+        assert_eq!(
+            arbiter.bus.transfers(),
+            vec![
+                Transfer::Receive(3),
+                Transfer::Receive(1),
+                Transfer::Receive(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shared_bus_switches_address_only_when_needed() {
+        let shared = SharedI2c::new(MockI2cBus::new());
+        let display = shared.device(0x3C);
+        let sensor = shared.device(0x76);
+
+        display.smbus_write_byte(0x00, 0x01).unwrap();
+        display.smbus_write_byte(0x40, b'A').unwrap();
+        sensor.smbus_write_byte(0xF4, 0x25).unwrap();
+        display.smbus_write_byte(0x40, b'B').unwrap();
+
+        let arbiter = shared.lock();
+        assert_eq!(arbiter.bus.addresses(), vec![0x3C, 0x76, 0x3C]);
+        assert_eq!(arbiter.bus.writes().len(), 4);
+    }
+
+    #[test]
+    fn test_device_delay_reaches_the_bus() {
+        let shared = SharedI2c::new(MockI2cBus::new());
+        let display = shared.device(0x3C);
+        display.delay(Duration::from_micros(30));
+
+        let arbiter = shared.lock();
+        assert_eq!(
+            arbiter.bus.transfers(),
+            vec![Transfer::Delay(Duration::from_micros(30))]
+        );
+    }
+
+    #[test]
+    fn test_session_is_not_interleaved() {
+        let shared = SharedI2c::new(MockI2cBus::new());
+        let handles: Vec<_> = [(0x3C, 0x40), (0x76, 0xF4)]
+            .into_iter()
+            .map(|(addr, register)| {
+                let device = shared.device(addr);
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        device
+                            .session(|bus| {
+                                for i in 0..16 {
+                                    bus.smbus_write_byte(register, i)?;
+                                }
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let arbiter = shared.lock();
+        let writes = arbiter.bus.writes();
+        assert_eq!(writes.len(), 2 * 50 * 16);
+        for frame in writes.chunks(16) {
+            assert!(frame.iter().all(|w| w.0 == frame[0].0));
+            assert_eq!(
+                frame.iter().map(|w| w.1).collect::<Vec<_>>(),
+                (0..16).collect::<Vec<u8>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! # Fault injection for the fake hardware
+//!
+//! `MockI2cBus` and `MockDisplay` share a `FaultInjector` with the test, so
+//! the test can make the next operations fail, make every operation slow or
+//! lock the whole device up after handing the fake to a driver, and check
+//! that the code on top recovers.
+//!
+//! Each operation of the fake first calls `FaultInjector::check`, which
+//! waits for the latency and then fails the operation as scripted. A
+//! locked-up device fails every operation with a timeout, like a bus whose
+//! SDA line is held low, until `release`.
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rppal::i2c;
+
+/// Scripted faults of a fake device.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    state: Mutex<FaultState>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    /// Operations still to fail.
+    fail_next: u32,
+    /// Kind of the errors of `fail_next`.
+    kind: io::ErrorKind,
+    /// Wait before every operation.
+    latency: Duration,
+    /// Every operation fails until released.
+    locked: bool,
+    /// Operations failed so far.
+    injected: u64,
+}
+
+impl Default for FaultState {
+    fn default() -> Self {
+        FaultState {
+            fail_next: 0,
+            kind: io::ErrorKind::Other,
+            latency: Duration::ZERO,
+            locked: false,
+            injected: 0,
+        }
+    }
+}
+
+impl FaultInjector {
+    /// Create an injector without faults.
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    /// Lock the state. A panic in a test holding it does not matter.
+    fn lock(&self) -> std::sync::MutexGuard<'_, FaultState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fail the next operations.
+    /// # Arguments
+    /// * `count` - Number of operations to fail
+    /// * `kind` - Kind of the I/O error returned
+    pub fn fail_next(&self, count: u32, kind: io::ErrorKind) {
+        let mut state = self.lock();
+        state.fail_next = count;
+        state.kind = kind;
+    }
+
+    /// Wait before every operation, `Duration::ZERO` to stop.
+    /// The thread is blocked like by a slow bus.
+    /// # Arguments
+    /// * `latency` - Wait per operation
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().latency = latency;
+    }
+
+    /// Fail every operation with a timeout until `release`.
+    pub fn lock_up(&self) {
+        self.lock().locked = true;
+    }
+
+    /// End a lock-up.
+    pub fn release(&self) {
+        self.lock().locked = false;
+    }
+
+    /// Number of operations failed so far.
+    pub fn injected(&self) -> u64 {
+        self.lock().injected
+    }
+
+    /// Apply the faults to an operation, called by the fake before it.
+    /// # Returns
+    /// * `Err` if the operation is to fail
+    pub fn check(&self) -> Result<(), i2c::Error> {
+        let latency = self.lock().latency;
+        if !latency.is_zero() {
+            std::thread::sleep(latency);
+        }
+        let mut state = self.lock();
+        let kind = if state.locked {
+            io::ErrorKind::TimedOut
+        } else if state.fail_next > 0 {
+            state.fail_next -= 1;
+            state.kind
+        } else {
+            return Ok(());
+        };
+        state.injected += 1;
+        Err(i2c::Error::Io(io::Error::new(kind, "injected fault")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(result: Result<(), i2c::Error>) -> Option<io::ErrorKind> {
+        match result {
+            Ok(()) => None,
+            Err(i2c::Error::Io(e)) => Some(e.kind()),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn test_fail_next_then_recover() {
+        let faults = FaultInjector::new();
+        assert_eq!(kind(faults.check()), None);
+        faults.fail_next(2, io::ErrorKind::BrokenPipe);
+        assert_eq!(kind(faults.check()), Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(kind(faults.check()), Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(kind(faults.check()), None);
+        assert_eq!(faults.injected(), 2);
+    }
+
+    #[test]
+    fn test_lock_up_until_released() {
+        let faults = FaultInjector::new();
+        faults.lock_up();
+        for _ in 0..5 {
+            assert_eq!(kind(faults.check()), Some(io::ErrorKind::TimedOut));
+        }
+        faults.release();
+        assert_eq!(kind(faults.check()), None);
+        assert_eq!(faults.injected(), 5);
+    }
+
+    #[test]
+    fn test_latency() {
+        let faults = FaultInjector::new();
+        faults.set_latency(Duration::from_millis(5));
+        let start = std::time::Instant::now();
+        faults.check().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    }
+}
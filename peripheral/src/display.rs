@@ -0,0 +1,312 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! # Character display abstraction
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use rppal::i2c;
+
+use crate::chaos::FaultInjector;
+use crate::hd44780::HD44780_16X2_GEOMETRY;
+
+/// Largest number of lines of the supported displays
+pub const DISPLAY_MAX_ROWS: usize = 4;
+
+/// Size and DDRAM layout of a character display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayGeometry {
+    /// Characters per line
+    pub cols: u8,
+    /// Number of lines, 1 to `DISPLAY_MAX_ROWS`
+    pub rows: u8,
+    /// "Set DDRAM Address" command of the start of each line. Entries past
+    /// `rows` are not used.
+    pub line_addresses: [u8; DISPLAY_MAX_ROWS],
+}
+
+impl DisplayGeometry {
+    /// Get the "Set DDRAM Address" command of the start of a line
+    /// # Arguments
+    /// * `row` - Line number starting at 0, past the last line for the last
+    /// # Returns
+    /// * Position of the start of the line
+    pub fn line_address(&self, row: u8) -> u8 {
+        let last = self.rows.max(1) - 1;
+        self.line_addresses[usize::from(row.min(last))]
+    }
+
+    /// Find the line and column of a "Set DDRAM Address" position
+    /// # Arguments
+    /// * `position` - Position
+    /// # Returns
+    /// * `(row, column)` of the line starting closest before the position,
+    ///   `None` before the first line
+    pub fn locate(&self, position: u8) -> Option<(usize, usize)> {
+        self.line_addresses[..usize::from(self.rows)]
+            .iter()
+            .enumerate()
+            .filter(|(_, start)| **start <= position)
+            .max_by_key(|(_, start)| **start)
+            .map(|(row, start)| (row, usize::from(position - start)))
+    }
+}
+
+/// Common interface of the character display drivers.
+/// Positions are "Set DDRAM Address" commands, as returned by
+/// `line_address`, so that `put_str(line_address(1), ..)` prints on
+/// the 2nd line whatever the controller's DDRAM layout is.
+pub trait CharDisplay {
+    /// Setup the display
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn setup(&self) -> impl Future<Output = Result<(), i2c::Error>>;
+
+    /// Get the size and DDRAM layout of the display
+    /// # Returns
+    /// * Geometry of the display
+    fn geometry(&self) -> DisplayGeometry;
+
+    /// Get the "Set DDRAM Address" command of the start of a line
+    /// # Arguments
+    /// * `row` - Line number starting at 0
+    /// # Returns
+    /// * Position of the start of the line
+    fn line_address(&self, row: u8) -> u8 {
+        self.geometry().line_address(row)
+    }
+
+    /// Register Custom Character
+    /// # Arguments
+    /// * `index` - Character Index
+    /// * `data` - Character Data
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error>;
+
+    /// Put a character at the specified position
+    /// # Arguments
+    /// * `position` - Position
+    /// * `data` - Character
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error>;
+
+    /// Print a string at the specified position
+    /// # Arguments
+    /// * `line_addr` - Position
+    /// * `s` - String
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error>;
+
+    /// Clear Display and Home Position
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn clear_home(&self) -> Result<(), i2c::Error>;
+
+    /// Turn the display off, keeping its content
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn display_off(&self) -> Result<(), i2c::Error>;
+
+    /// Turn the display back on
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    fn display_on(&self) -> Result<(), i2c::Error>;
+}
+
+/// Display emulating the DDRAM of a geometry, for rendering tests.
+/// Lines start at 0x80 and 0xC0 like on the 16x2 HD44780 unless created
+/// `with_geometry`. Faults of every operation, setup included, are
+/// scripted through `faults`.
+#[derive(Debug)]
+pub struct MockDisplay {
+    geometry: DisplayGeometry,
+    ddram: Mutex<Vec<Vec<u8>>>,
+    on: Mutex<bool>,
+    faults: Arc<FaultInjector>,
+}
+
+impl Default for MockDisplay {
+    fn default() -> Self {
+        MockDisplay::with_geometry(HD44780_16X2_GEOMETRY)
+    }
+}
+
+impl MockDisplay {
+    /// Create a new blank mock display.
+    pub fn new() -> MockDisplay {
+        MockDisplay::default()
+    }
+
+    /// Create a new blank mock display of a geometry.
+    /// # Arguments
+    /// * `geometry` - Size and line addresses to emulate
+    pub fn with_geometry(geometry: DisplayGeometry) -> MockDisplay {
+        MockDisplay {
+            geometry,
+            ddram: Mutex::new(blank(geometry)),
+            on: Mutex::new(true),
+            faults: Arc::new(FaultInjector::new()),
+        }
+    }
+
+    /// Get the displayed characters, one string per line.
+    /// Custom characters 0-7 are shown as the subscript digits '₀'-'₇'.
+    pub fn grid(&self) -> Vec<String> {
+        self.ddram
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|&c| match c {
+                        0..=7 => char::from_u32(0x2080 + c as u32).unwrap_or('?'),
+                        0x20..=0x7E => c as char,
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Faults injected into the operations, shared with the caller.
+    pub fn faults(&self) -> Arc<FaultInjector> {
+        Arc::clone(&self.faults)
+    }
+
+    /// Whether the display is on.
+    pub fn is_on(&self) -> bool {
+        *self.on.lock().unwrap()
+    }
+
+    /// Write bytes from a "Set DDRAM Address" position.
+    /// Characters past the last column are dropped.
+    fn write(&self, position: u8, data: &[u8]) {
+        let Some((row, column)) = self.geometry.locate(position) else {
+            return;
+        };
+        let mut ddram = self.ddram.lock().unwrap();
+        for (i, d) in data.iter().enumerate() {
+            if let Some(cell) = ddram[row].get_mut(column + i) {
+                *cell = *d;
+            }
+        }
+    }
+}
+
+/// Blank DDRAM of a geometry
+fn blank(geometry: DisplayGeometry) -> Vec<Vec<u8>> {
+    vec![vec![b' '; usize::from(geometry.cols)]; usize::from(geometry.rows)]
+}
+
+impl CharDisplay for MockDisplay {
+    async fn setup(&self) -> Result<(), i2c::Error> {
+        self.faults.check()
+    }
+
+    fn geometry(&self) -> DisplayGeometry {
+        self.geometry
+    }
+
+    fn register_char(&self, _index: u8, _data: [u8; 8]) -> Result<(), i2c::Error> {
+        self.faults.check()
+    }
+
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        self.faults.check()?;
+        self.write(position, &[data]);
+        Ok(())
+    }
+
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        self.faults.check()?;
+        self.write(line_addr, s.as_bytes());
+        Ok(())
+    }
+
+    fn clear_home(&self) -> Result<(), i2c::Error> {
+        self.faults.check()?;
+        *self.ddram.lock().unwrap() = blank(self.geometry);
+        Ok(())
+    }
+
+    fn display_off(&self) -> Result<(), i2c::Error> {
+        self.faults.check()?;
+        *self.on.lock().unwrap() = false;
+        Ok(())
+    }
+
+    fn display_on(&self) -> Result<(), i2c::Error> {
+        self.faults.check()?;
+        *self.on.lock().unwrap() = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_display_grid() {
+        let display = MockDisplay::new();
+        display.put_str(display.line_address(0), "Hello").unwrap();
+        display
+            .put_str(display.line_address(1) + 14, "abc")
+            .unwrap();
+        display.put_u8(0x80 + 6, 0x01).unwrap();
+
+        assert_eq!(
+            display.grid(),
+            vec![
+                "Hello ₁         ".to_string(),
+                "              ab".to_string()
+            ]
+        );
+
+        display.clear_home().unwrap();
+        assert_eq!(display.grid()[0], " ".repeat(16));
+    }
+
+    #[test]
+    fn test_mock_display_interleaved_lines() {
+        // The 20x4 HD44780 continues the 1st line on the 3rd
+        let geometry = crate::hd44780::HD44780_20X4_GEOMETRY;
+        let display = MockDisplay::with_geometry(geometry);
+        for row in 0..4 {
+            display
+                .put_str(display.line_address(row), &format!("line {}", row))
+                .unwrap();
+        }
+        display.put_u8(0x94 + 19, b'#').unwrap();
+
+        let grid = display.grid();
+        assert_eq!(grid.len(), 4);
+        assert_eq!(grid[2], "line 2             #");
+        assert_eq!(grid[3], format!("{:<20}", "line 3"));
+        assert_eq!(geometry.locate(0xC5), Some((1, 5)));
+        assert_eq!(geometry.locate(0x7F), None);
+        assert_eq!(display.line_address(7), 0xD4);
+    }
+}
@@ -0,0 +1,459 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! # HD44780 Driver over a PCF8574 I2C backpack for Raspberry Pi
+//!
+//! The PCF8574 drives the HD44780 in 4-bit mode with the usual backpack wiring:
+//! P0=RS, P1=RW, P2=EN, P3=Backlight, P4-P7=D4-D7.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use rppal::i2c;
+use tokio::time::{Duration, sleep};
+
+use crate::bus::I2cBus;
+use crate::display::{CharDisplay, DisplayGeometry};
+
+/// PCF8574 I2C Address
+pub const HD44780_PCF8574_ADDR: u16 = 0x27;
+/// PCF8574A I2C Address
+pub const HD44780_PCF8574A_ADDR: u16 = 0x3F;
+
+/// HD44780 start of 1st Line Address
+pub const HD44780_1ST_LINE: u8 = 0x80;
+/// HD44780 start of 2nd Line Address
+pub const HD44780_2ND_LINE: u8 = 0xC0;
+/// HD44780 start of 3rd Line Address on 20 column modules
+pub const HD44780_3RD_LINE_20: u8 = 0x94;
+/// HD44780 start of 4th Line Address on 20 column modules
+pub const HD44780_4TH_LINE_20: u8 = 0xD4;
+
+/// 16x2 HD44780 module
+pub const HD44780_16X2_GEOMETRY: DisplayGeometry = DisplayGeometry {
+    cols: 16,
+    rows: 2,
+    line_addresses: [HD44780_1ST_LINE, HD44780_2ND_LINE, 0, 0],
+};
+/// 20x4 HD44780 module, whose 3rd and 4th lines continue the 1st and 2nd
+pub const HD44780_20X4_GEOMETRY: DisplayGeometry = DisplayGeometry {
+    cols: 20,
+    rows: 4,
+    line_addresses: [
+        HD44780_1ST_LINE,
+        HD44780_2ND_LINE,
+        HD44780_3RD_LINE_20,
+        HD44780_4TH_LINE_20,
+    ],
+};
+
+/// Register Select bit (0: command, 1: data)
+pub const HD44780_PIN_RS: u8 = 0x01;
+/// Enable bit
+pub const HD44780_PIN_EN: u8 = 0x04;
+/// Backlight bit
+pub const HD44780_PIN_BACKLIGHT: u8 = 0x08;
+
+/// Clear Display Command
+pub const HD44780_CLEARDISPLAY: u8 = 0x01;
+/// Return Home Command
+pub const HD44780_RETURNHOME: u8 = 0x02;
+/// Entry Mode Set Command, increment without shift
+pub const HD44780_ENTRYMODE_INCREMENT: u8 = 0x06;
+/// Display Control Command
+pub const HD44780_DISPLAYCONTROL: u8 = 0x08;
+/// Display ON in Display Control
+pub const HD44780_DISPLAYCONTROL_DISPLAY_ON: u8 = 0x04;
+/// Function Set Command, 4-bit bus, 2 lines, 5x8 dots
+pub const HD44780_FUNCTIONSET_4BIT_2LINE: u8 = 0x28;
+/// Set CGRAM Address Command
+pub const HD44780_SETCGRAMADDR: u8 = 0x40;
+
+/// HD44780 Driver
+pub struct Hd44780<B: I2cBus = i2c::I2c> {
+    bus: B,
+    backlight: AtomicBool,
+    geometry: DisplayGeometry,
+}
+
+impl Hd44780<i2c::I2c> {
+    /// Create a new HD44780 instance
+    /// # Arguments
+    /// * `addr` - I2C Address of the PCF8574
+    /// # Returns
+    /// * Hd44780 instance
+    pub fn new(addr: u16) -> Result<Hd44780<i2c::I2c>, i2c::Error> {
+        let mut i2c = i2c::I2c::new()?;
+        i2c.set_slave_address(addr)?;
+        Ok(Hd44780::with_bus(i2c))
+    }
+}
+
+impl<B: I2cBus> Hd44780<B> {
+    /// Create a new HD44780 instance on the given bus
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the PCF8574
+    /// # Returns
+    /// * Hd44780 instance
+    pub fn with_bus(bus: B) -> Hd44780<B> {
+        Hd44780 {
+            bus,
+            backlight: AtomicBool::new(true),
+            geometry: HD44780_16X2_GEOMETRY,
+        }
+    }
+
+    /// Set the size and line addresses of the module
+    /// # Arguments
+    /// * `geometry` - Geometry, `HD44780_16X2_GEOMETRY` unless set
+    /// # Returns
+    /// * Hd44780 instance
+    pub fn with_geometry(mut self, geometry: DisplayGeometry) -> Hd44780<B> {
+        self.geometry = geometry;
+        self
+    }
+
+    /// Backlight bit to send with every port write
+    fn backlight(&self) -> u8 {
+        if self.backlight.load(Ordering::Relaxed) {
+            HD44780_PIN_BACKLIGHT
+        } else {
+            0
+        }
+    }
+
+    /// Send Command
+    /// # Arguments
+    /// * `data` - Command
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn send_command(&self, data: u8) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        self.bus.session(|bus| write_byte(bus, data, bl))
+    }
+
+    /// Send Data
+    /// # Arguments
+    /// * `data` - Data
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn send_data(&self, data: u8) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        self.bus
+            .session(|bus| write_byte(bus, data, HD44780_PIN_RS | bl))
+    }
+
+    /// Setup HD44780 Device
+    /// Runs the 4-bit "initialization by instruction" sequence of the datasheet.
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub async fn setup(&self) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        // Wait for the power supply to settle
+        sleep(Duration::from_millis(50)).await;
+        // Force 8-bit mode three times, then switch to 4-bit mode.
+        // The bus is released during the waits.
+        self.bus.session(|bus| write_nibble(bus, 0x03, bl))?;
+        sleep(Duration::from_millis(5)).await;
+        self.bus.session(|bus| write_nibble(bus, 0x03, bl))?;
+        sleep(Duration::from_millis(1)).await;
+        self.bus.session(|bus| {
+            write_nibble(bus, 0x03, bl)?;
+            write_nibble(bus, 0x02, bl)?;
+            write_byte(bus, HD44780_FUNCTIONSET_4BIT_2LINE, bl)?;
+            write_byte(bus, HD44780_DISPLAYCONTROL, bl)?;
+            write_byte(bus, HD44780_CLEARDISPLAY, bl)
+        })?;
+        sleep(Duration::from_millis(2)).await;
+        self.bus.session(|bus| {
+            write_byte(bus, HD44780_ENTRYMODE_INCREMENT, bl)?;
+            write_byte(
+                bus,
+                HD44780_DISPLAYCONTROL | HD44780_DISPLAYCONTROL_DISPLAY_ON,
+                bl,
+            )
+        })
+    }
+
+    /// Register Custom Character
+    /// # Arguments
+    /// * `index` - Character Index
+    /// * `data` - Character Data
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        self.bus.session(|bus| {
+            write_byte(bus, HD44780_SETCGRAMADDR | (index << 3), bl)?;
+            for d in data {
+                write_byte(bus, d, HD44780_PIN_RS | bl)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Put a character at the specified position
+    /// # Arguments
+    /// * `position` - Position
+    /// * `data` - Character
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        self.bus.session(|bus| {
+            write_byte(bus, position, bl)?;
+            write_byte(bus, data, HD44780_PIN_RS | bl)
+        })
+    }
+
+    /// Print a string at the specified line
+    /// # Arguments
+    /// * `line_addr` - Line
+    /// * `s` - String
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        let bl = self.backlight();
+        self.bus.session(|bus| {
+            write_byte(bus, line_addr, bl)?;
+            for c in s.as_bytes() {
+                write_byte(bus, *c, HD44780_PIN_RS | bl)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Clear Display and Home Position
+    /// Both commands take up to 1.52ms, which is much longer than the next
+    /// bus transfer, so the driver waits here.
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn clear_home(&self) -> Result<(), i2c::Error> {
+        self.send_command(HD44780_CLEARDISPLAY)?;
+        thread::sleep(Duration::from_millis(2));
+        self.send_command(HD44780_RETURNHOME)?;
+        thread::sleep(Duration::from_millis(2));
+        Ok(())
+    }
+
+    /// Turn the display and the backlight off, keeping DDRAM
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn display_off(&self) -> Result<(), i2c::Error> {
+        self.backlight.store(false, Ordering::Relaxed);
+        self.send_command(HD44780_DISPLAYCONTROL)
+    }
+
+    /// Turn the display and the backlight on
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn display_on(&self) -> Result<(), i2c::Error> {
+        self.backlight.store(true, Ordering::Relaxed);
+        self.send_command(HD44780_DISPLAYCONTROL | HD44780_DISPLAYCONTROL_DISPLAY_ON)
+    }
+}
+
+impl<B: I2cBus> CharDisplay for Hd44780<B> {
+    async fn setup(&self) -> Result<(), i2c::Error> {
+        Hd44780::setup(self).await
+    }
+
+    fn geometry(&self) -> DisplayGeometry {
+        self.geometry
+    }
+
+    fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+        Hd44780::register_char(self, index, data)
+    }
+
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        Hd44780::put_u8(self, position, data)
+    }
+
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        Hd44780::put_str(self, line_addr, s)
+    }
+
+    fn clear_home(&self) -> Result<(), i2c::Error> {
+        Hd44780::clear_home(self)
+    }
+
+    fn display_off(&self) -> Result<(), i2c::Error> {
+        Hd44780::display_off(self)
+    }
+
+    fn display_on(&self) -> Result<(), i2c::Error> {
+        Hd44780::display_on(self)
+    }
+}
+
+/// Write one nibble with an enable pulse
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `nibble` - Value in the lower 4 bits
+/// * `flags` - RS and backlight bits
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_nibble(bus: &dyn I2cBus, nibble: u8, flags: u8) -> Result<(), i2c::Error> {
+    for b in pack_nibble(nibble, flags) {
+        bus.smbus_send_byte(b)?;
+    }
+    Ok(())
+}
+
+/// Write a full byte as two nibbles
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `data` - Byte
+/// * `flags` - RS and backlight bits
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_byte(bus: &dyn I2cBus, data: u8, flags: u8) -> Result<(), i2c::Error> {
+    for b in pack_byte(data, flags) {
+        bus.smbus_send_byte(b)?;
+    }
+    Ok(())
+}
+
+/// Pack a nibble into PCF8574 port values latched by an enable pulse
+/// # Arguments
+/// * `nibble` - Value in the lower 4 bits
+/// * `flags` - RS and backlight bits
+/// # Returns
+/// * Port values with EN high, then EN low
+fn pack_nibble(nibble: u8, flags: u8) -> [u8; 2] {
+    let port = ((nibble & 0x0F) << 4) | (flags & 0x0F & !HD44780_PIN_EN);
+    [port | HD44780_PIN_EN, port]
+}
+
+/// Pack a byte into PCF8574 port values, high nibble first
+/// # Arguments
+/// * `data` - Byte
+/// * `flags` - RS and backlight bits
+/// # Returns
+/// * Port values for both nibbles
+fn pack_byte(data: u8, flags: u8) -> [u8; 4] {
+    let high = pack_nibble(data >> 4, flags);
+    let low = pack_nibble(data & 0x0F, flags);
+    [high[0], high[1], low[0], low[1]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::MockI2cBus;
+
+    #[test]
+    fn test_pack_nibble() {
+        assert_eq!(pack_nibble(0x03, 0), [0x34, 0x30]);
+        assert_eq!(pack_nibble(0x0A, HD44780_PIN_RS), [0xA5, 0xA1]);
+        // Only the lower nibble is used
+        assert_eq!(pack_nibble(0xF2, 0), [0x24, 0x20]);
+    }
+
+    #[test]
+    fn test_pack_byte_high_nibble_first() {
+        assert_eq!(
+            pack_byte(0x28, HD44780_PIN_BACKLIGHT),
+            [0x2C, 0x28, 0x8C, 0x88]
+        );
+    }
+
+    #[test]
+    fn test_send_command_on_mock_bus() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.send_command(HD44780_FUNCTIONSET_4BIT_2LINE).unwrap();
+        assert_eq!(hd.bus.sent(), vec![0x2C, 0x28, 0x8C, 0x88]);
+        assert!(hd.bus.writes().is_empty());
+    }
+
+    #[test]
+    fn test_send_data_sets_rs() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.send_data(b'A').unwrap();
+        assert_eq!(hd.bus.sent(), vec![0x4D, 0x49, 0x1D, 0x19]);
+    }
+
+    #[test]
+    fn test_put_str_sets_address_first() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.put_str(HD44780_2ND_LINE, "Hi").unwrap();
+        let sent = hd.bus.sent();
+        assert_eq!(sent.len(), 12);
+        assert_eq!(
+            &sent[0..4],
+            &pack_byte(HD44780_2ND_LINE, HD44780_PIN_BACKLIGHT)
+        );
+        assert_eq!(
+            &sent[4..8],
+            &pack_byte(b'H', HD44780_PIN_RS | HD44780_PIN_BACKLIGHT)
+        );
+    }
+
+    #[test]
+    fn test_register_char_cgram_address() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.register_char(1, [0x1F; 8]).unwrap();
+        let sent = hd.bus.sent();
+        assert_eq!(sent.len(), 4 * 9);
+        assert_eq!(&sent[0..4], &pack_byte(0x48, HD44780_PIN_BACKLIGHT));
+    }
+
+    #[tokio::test]
+    async fn test_setup_sequence() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.setup().await.unwrap();
+        let sent = hd.bus.sent();
+        // 4 single nibbles followed by 5 full commands
+        assert_eq!(sent.len(), 4 * 2 + 5 * 4);
+        assert_eq!(&sent[0..2], &[0x3C, 0x38]);
+        assert_eq!(&sent[6..8], &[0x2C, 0x28]);
+    }
+
+    #[test]
+    fn test_display_off_turns_backlight_off() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        hd.display_off().unwrap();
+        assert_eq!(hd.bus.sent(), pack_byte(HD44780_DISPLAYCONTROL, 0).to_vec());
+
+        // Writes while off keep the backlight off
+        hd.bus.clear();
+        hd.send_data(b'A').unwrap();
+        assert_eq!(hd.bus.sent(), pack_byte(b'A', HD44780_PIN_RS).to_vec());
+
+        hd.bus.clear();
+        hd.display_on().unwrap();
+        assert_eq!(
+            hd.bus.sent(),
+            pack_byte(0x0C, HD44780_PIN_BACKLIGHT).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_line_addresses() {
+        let hd = Hd44780::with_bus(MockI2cBus::new());
+        assert_eq!(CharDisplay::line_address(&hd, 0), 0x80);
+        assert_eq!(CharDisplay::line_address(&hd, 1), 0xC0);
+        let hd = hd.with_geometry(HD44780_20X4_GEOMETRY);
+        assert_eq!(CharDisplay::line_address(&hd, 2), 0x94);
+        assert_eq!(CharDisplay::line_address(&hd, 3), 0xD4);
+    }
+}
@@ -19,5 +19,26 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "std")]
+pub mod aht20;
+#[cfg(feature = "std")]
+pub mod bh1750;
+#[cfg(feature = "std")]
 pub mod bme280;
+#[cfg(feature = "std")]
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod chaos;
+#[cfg(feature = "std")]
+pub mod display;
+#[cfg(feature = "std")]
+pub mod hd44780;
+pub mod math;
+#[cfg(feature = "std")]
+pub mod sensor;
+#[cfg(feature = "std")]
+pub mod sht31;
+#[cfg(feature = "std")]
 pub mod so1602a;
@@ -0,0 +1,459 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Sensor math without I/O.
+//!
+//! Calibration decoding and compensation of the BME280 and the derived
+//! quantities. Only `core` and `libm` are used, so the module builds
+//! without the `std` feature for microcontrollers.
+
+/// Calibration data
+#[derive(Debug, PartialEq, Eq)]
+pub struct CalibrationData {
+    pub dig_t1: u16,
+    pub dig_t2: i16,
+    pub dig_t3: i16,
+    pub dig_p1: u16,
+    pub dig_p2: i16,
+    pub dig_p3: i16,
+    pub dig_p4: i16,
+    pub dig_p5: i16,
+    pub dig_p6: i16,
+    pub dig_p7: i16,
+    pub dig_p8: i16,
+    pub dig_p9: i16,
+    pub dig_h1: u8,
+    pub dig_h2: i16,
+    pub dig_h3: u8,
+    pub dig_h4: i16,
+    pub dig_h5: i16,
+    pub dig_h6: i8,
+}
+
+/// Temperature data
+#[derive(Debug)]
+pub struct TemperatureData {
+    /// Temperature fine
+    pub t_fine: i32,
+    /// Temperature in Celsius
+    pub temperature_c: f64,
+}
+
+/// Length of the calibration block at 0x88 (dig_T1 to dig_P9)
+pub const CALIB_TP_LEN: usize = 24;
+/// Length of the calibration block at 0xE1 (dig_H2 to dig_H6)
+pub const CALIB_H_LEN: usize = 7;
+
+/// Decode the calibration registers
+/// # Arguments
+/// * `tp` - Registers 0x88 to 0x9F
+/// * `h1` - Register 0xA1
+/// * `h` - Registers 0xE1 to 0xE7
+/// # Returns
+/// * CalibrationData
+pub fn parse_calibration(tp: &[u8; CALIB_TP_LEN], h1: u8, h: &[u8; CALIB_H_LEN]) -> CalibrationData {
+    return CalibrationData {
+        dig_t1: dig_t1(tp),
+        dig_t2: dig_t2(tp),
+        dig_t3: dig_t3(tp),
+        dig_p1: dig_p1(tp),
+        dig_p2: dig_p2(tp),
+        dig_p3: dig_p3(tp),
+        dig_p4: dig_p4(tp),
+        dig_p5: dig_p5(tp),
+        dig_p6: dig_p6(tp),
+        dig_p7: dig_p7(tp),
+        dig_p8: dig_p8(tp),
+        dig_p9: dig_p9(tp),
+        dig_h1: h1,
+        dig_h2: dig_h2(h),
+        dig_h3: dig_h3(h),
+        dig_h4: dig_h4(h),
+        dig_h5: dig_h5(h),
+        dig_h6: dig_h6(h),
+    };
+}
+
+/// dig_T1, unsigned little-endian at 0x88/0x89
+fn dig_t1(tp: &[u8; CALIB_TP_LEN]) -> u16 {
+    return u16::from_le_bytes([tp[0], tp[1]]);
+}
+
+/// dig_T2, signed little-endian at 0x8A/0x8B
+fn dig_t2(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[2], tp[3]]);
+}
+
+/// dig_T3, signed little-endian at 0x8C/0x8D
+fn dig_t3(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[4], tp[5]]);
+}
+
+/// dig_P1, unsigned little-endian at 0x8E/0x8F
+fn dig_p1(tp: &[u8; CALIB_TP_LEN]) -> u16 {
+    return u16::from_le_bytes([tp[6], tp[7]]);
+}
+
+/// dig_P2, signed little-endian at 0x90/0x91
+fn dig_p2(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[8], tp[9]]);
+}
+
+/// dig_P3, signed little-endian at 0x92/0x93
+fn dig_p3(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[10], tp[11]]);
+}
+
+/// dig_P4, signed little-endian at 0x94/0x95
+fn dig_p4(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[12], tp[13]]);
+}
+
+/// dig_P5, signed little-endian at 0x96/0x97
+fn dig_p5(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[14], tp[15]]);
+}
+
+/// dig_P6, signed little-endian at 0x98/0x99
+fn dig_p6(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[16], tp[17]]);
+}
+
+/// dig_P7, signed little-endian at 0x9A/0x9B
+fn dig_p7(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[18], tp[19]]);
+}
+
+/// dig_P8, signed little-endian at 0x9C/0x9D
+fn dig_p8(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[20], tp[21]]);
+}
+
+/// dig_P9, signed little-endian at 0x9E/0x9F
+fn dig_p9(tp: &[u8; CALIB_TP_LEN]) -> i16 {
+    return i16::from_le_bytes([tp[22], tp[23]]);
+}
+
+/// dig_H2, signed little-endian at 0xE1/0xE2
+fn dig_h2(h: &[u8; CALIB_H_LEN]) -> i16 {
+    return i16::from_le_bytes([h[0], h[1]]);
+}
+
+/// dig_H3, unsigned at 0xE3
+fn dig_h3(h: &[u8; CALIB_H_LEN]) -> u8 {
+    return h[2];
+}
+
+/// dig_H4, signed 12 bits: 0xE4 holds bits 11:4, the low nibble of 0xE5
+/// bits 3:0
+fn dig_h4(h: &[u8; CALIB_H_LEN]) -> i16 {
+    // 0xE4 is sign-extended, as in the Bosch reference driver
+    return ((h[3] as i8 as i16) << 4) | ((h[4] & 0x0F) as i16);
+}
+
+/// dig_H5, signed 12 bits: 0xE6 holds bits 11:4, the high nibble of 0xE5
+/// bits 3:0
+fn dig_h5(h: &[u8; CALIB_H_LEN]) -> i16 {
+    return ((h[5] as i8 as i16) << 4) | ((h[4] >> 4) as i16);
+}
+
+/// dig_H6, signed at 0xE7
+fn dig_h6(h: &[u8; CALIB_H_LEN]) -> i8 {
+    return h[6] as i8;
+}
+
+/// Refine temperature
+/// # Arguments
+/// * `temp_raw` - Raw temperature value
+/// * `calibration` - Calibration data
+/// # Returns
+/// * TemperatureData - Refined temperature data
+pub fn refine_temperature(temp_raw: i32, calibration: &CalibrationData) -> TemperatureData {
+    let var1: f64 = ((temp_raw as f64) / 16384.0 - (calibration.dig_t1 as f64) / 1024.0)
+        * (calibration.dig_t2 as f64);
+    let var2: f64 = (((temp_raw as f64) / 131072.0 - (calibration.dig_t1 as f64) / 8192.0)
+        * ((temp_raw as f64) / 131072.0 - (calibration.dig_t1 as f64) / 8192.0))
+        * (calibration.dig_t3 as f64);
+    let sum: f64 = var1 + var2;
+    let t_fine: i32 = sum as i32;
+    let temperature_c: f64 = sum / 5120.0;
+    return TemperatureData {
+        t_fine,
+        temperature_c,
+    };
+}
+
+/// Refine pressure
+/// # Arguments
+/// * `pres_raw` - Raw pressure value
+/// * `calibration` - Calibration data
+/// * `t_fine` - Temperature fine
+/// # Returns
+/// * f64 - Pressure in pascal
+pub fn refine_pressure(pres_raw: i32, calibration: &CalibrationData, t_fine: i32) -> f64 {
+    let mut var1: f64 = ((t_fine as f64) / 2.0) - 64000.0;
+    let mut var2: f64 = var1 * var1 * (calibration.dig_p6 as f64) / 32768.0;
+    var2 = var2 + var1 * (calibration.dig_p5 as f64) * 2.0;
+    var2 = (var2 / 4.0) + ((calibration.dig_p4 as f64) * 65536.0);
+    var1 = ((calibration.dig_p3 as f64) * var1 * var1 / 524288.0
+        + (calibration.dig_p2 as f64) * var1)
+        / 524288.0;
+    var1 = (1.0 + var1 / 32768.0) * (calibration.dig_p1 as f64);
+    if var1 == 0.0 {
+        return 0.0; // avoid exception caused by division by zero
+    }
+    let mut p: f64 = 1048576.0 - (pres_raw as f64);
+    p = (p - (var2 / 4096.0)) * 6250.0 / var1;
+    var1 = (calibration.dig_p9 as f64) * p * p / 2147483648.0;
+    var2 = p * (calibration.dig_p8 as f64) / 32768.0;
+    p = p + (var1 + var2 + (calibration.dig_p7 as f64)) / 16.0;
+    return p;
+}
+
+/// Refine humidity
+/// # Arguments
+/// * `hum_raw` - Raw humidity value
+/// * `calibration` - Calibration data
+/// * `t_fine` - Temperature fine
+/// # Returns
+/// * f64 - Humidity in percent
+pub fn refine_humidity(hum_raw: i32, calibration: &CalibrationData, t_fine: i32) -> f64 {
+    let mut var_h = (t_fine as f64) - 76800.0;
+    var_h = ((hum_raw as f64)
+        - ((calibration.dig_h4 as f64) * 64.0 + (calibration.dig_h5 as f64) / 16384.0 * var_h))
+        * ((calibration.dig_h2 as f64) / 65536.0
+            * (1.0
+                + (calibration.dig_h6 as f64) / 67108864.0
+                    * var_h
+                    * (1.0 + (calibration.dig_h3 as f64) / 67108864.0 * var_h)));
+    var_h = var_h * (1.0 - (calibration.dig_h1 as f64) * var_h / 524288.0);
+    if var_h > 100.0 {
+        var_h = 100.0;
+    } else if var_h < 0.0 {
+        var_h = 0.0;
+    }
+    return var_h;
+}
+
+/// Sea level temperature of the ICAO standard atmosphere, in K.
+const SEA_LEVEL_TEMPERATURE_K: f64 = 288.15;
+
+/// Temperature lapse rate of the troposphere, in K/m.
+const LAPSE_RATE_K_PER_M: f64 = 0.0065;
+
+/// R·L / (g·M) of the ICAO standard atmosphere.
+const PRESSURE_EXPONENT: f64 = 0.190263;
+
+/// Altitude an altimeter set to QNH indicates, from the ICAO standard
+/// atmosphere below the tropopause (11 km).
+/// # Arguments
+/// * `station_hpa` - Measured station pressure in hPa.
+/// * `qnh_hpa` - Altimeter setting in hPa.
+/// # Returns
+/// * Indicated altitude in m, negative below the QNH reference level.
+pub fn indicated_altitude_m(station_hpa: f64, qnh_hpa: f64) -> f64 {
+    SEA_LEVEL_TEMPERATURE_K / LAPSE_RATE_K_PER_M
+        * (1.0 - libm::pow(station_hpa / qnh_hpa, PRESSURE_EXPONENT))
+}
+
+/// Sea level pressure for a station pressure measured at a known altitude,
+/// the inverse of `indicated_altitude_m`.
+/// # Arguments
+/// * `station_hpa` - Measured station pressure in hPa.
+/// * `altitude_m` - Altitude of the station in m.
+/// # Returns
+/// * Pressure reduced to sea level in hPa.
+pub fn sea_level_pressure_hpa(station_hpa: f64, altitude_m: f64) -> f64 {
+    station_hpa
+        / libm::pow(
+            1.0 - altitude_m * LAPSE_RATE_K_PER_M / SEA_LEVEL_TEMPERATURE_K,
+            1.0 / PRESSURE_EXPONENT,
+        )
+}
+
+/// Temperature-humidity index, `a·T + b·H·(c·T - d) + e`.
+/// # Arguments
+/// * `temperature_c` - Temperature in Celsius.
+/// * `humidity_relative` - Relative humidity in %.
+/// * `coefficients` - `[a, b, c, d, e]` of the formula.
+/// # Returns
+/// * Temperature-humidity index.
+pub fn thi(temperature_c: f64, humidity_relative: f64, coefficients: [f64; 5]) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    a * temperature_c + b * humidity_relative * (c * temperature_c - d) + e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sea level pressure of the ICAO standard atmosphere, in hPa.
+    const STANDARD_PRESSURE_HPA: f64 = 1013.25;
+
+    /// Calibration with every coefficient 0.
+    const ZERO: CalibrationData = CalibrationData {
+        dig_t1: 0,
+        dig_t2: 0,
+        dig_t3: 0,
+        dig_p1: 0,
+        dig_p2: 0,
+        dig_p3: 0,
+        dig_p4: 0,
+        dig_p5: 0,
+        dig_p6: 0,
+        dig_p7: 0,
+        dig_p8: 0,
+        dig_p9: 0,
+        dig_h1: 0,
+        dig_h2: 0,
+        dig_h3: 0,
+        dig_h4: 0,
+        dig_h5: 0,
+        dig_h6: 0,
+    };
+
+    #[test]
+    fn test_calibration_words_are_little_endian() {
+        let mut tp = [0; CALIB_TP_LEN];
+        tp[0..4].copy_from_slice(&[0x34, 0x12, 0xFF, 0xFF]);
+        tp[22..24].copy_from_slice(&[0x00, 0x01]);
+        assert_eq!(dig_t1(&tp), 0x1234);
+        assert_eq!(dig_t2(&tp), -1);
+        assert_eq!(dig_p9(&tp), 256);
+
+        // Unsigned words keep their top bit
+        tp[6..8].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(dig_p1(&tp), u16::MAX);
+    }
+
+    #[test]
+    fn test_dig_h4_h5_nibble_packing() {
+        // 0xE5 is shared: low nibble to dig_H4, high nibble to dig_H5
+        let h = [0, 0, 0, 0xAB, 0xCD, 0x12, 0];
+        assert_eq!(dig_h4(&h), -1347); // 0xAB_D
+        assert_eq!(dig_h5(&h), 300); // 0x12_C
+
+        let h = [0, 0, 0, 0x14, 0x04, 0x00, 0];
+        assert_eq!(dig_h4(&h), 324);
+        assert_eq!(dig_h5(&h), 0);
+
+        // The 12-bit values are signed
+        let h = [0, 0, 0, 0xFF, 0x8F, 0xF0, 0];
+        assert_eq!(dig_h4(&h), -1);
+        assert_eq!(dig_h5(&h), -248);
+    }
+
+    #[test]
+    fn test_dig_h6_is_signed() {
+        let h = [0, 0, 0, 0, 0, 0, 0xE2];
+        assert_eq!(dig_h6(&h), -30);
+    }
+
+    #[test]
+    fn test_refine_temperature() {
+        let calibration = CalibrationData {
+            dig_t1: 27504,
+            dig_t2: 26435,
+            dig_t3: -1000,
+            ..ZERO
+        };
+
+        let temp_raw = 519888;
+        let result = refine_temperature(temp_raw, &calibration);
+        assert!(result.temperature_c > 0.0);
+        assert!(result.t_fine != 0);
+    }
+
+    #[test]
+    fn test_refine_pressure_zero_division() {
+        let result = refine_pressure(100000, &ZERO, 128000);
+        assert_eq!(result, 0.0);
+    }
+
+    #[test]
+    fn test_refine_humidity_boundary_values() {
+        let calibration = CalibrationData {
+            dig_h1: 75,
+            dig_h2: 365,
+            dig_h4: 328,
+            dig_h6: 30,
+            ..ZERO
+        };
+
+        let result = refine_humidity(32768, &calibration, 128000);
+        assert!((0.0..=100.0).contains(&result));
+    }
+
+    #[test]
+    fn test_thi() {
+        // The common formula 0.81·T + 0.01·H·(0.99·T - 14.3) + 46.3
+        let coefficients = [0.81, 0.01, 0.99, 14.3, 46.3];
+        let value = thi(25.0, 60.0, coefficients);
+        assert!((value - 72.82).abs() < 1e-9, "{}", value);
+    }
+
+    #[test]
+    fn test_pressure_altitude_matches_icao_table() {
+        // ICAO Doc 7488 standard atmosphere, geopotential altitude
+        let table = [
+            (0.0, 1013.25),
+            (500.0, 954.61),
+            (1000.0, 898.75),
+            (1500.0, 845.56),
+            (2000.0, 794.95),
+            (3000.0, 701.09),
+            (5000.0, 540.20),
+        ];
+        for (altitude, pressure) in table {
+            let indicated = indicated_altitude_m(pressure, STANDARD_PRESSURE_HPA);
+            assert!(
+                (indicated - altitude).abs() < 1.0,
+                "{} hPa: {} m, expected {} m",
+                pressure,
+                indicated,
+                altitude
+            );
+        }
+    }
+
+    #[test]
+    fn test_qnh_shifts_indicated_altitude() {
+        // Station at 1013.25 hPa, QNH 10 hPa higher: about 8.3 m per hPa
+        let indicated = indicated_altitude_m(STANDARD_PRESSURE_HPA, 1023.25);
+        assert!((indicated - 83.0).abs() < 1.0, "{}", indicated);
+        // Below the reference level
+        assert!(indicated_altitude_m(1020.0, STANDARD_PRESSURE_HPA) < 0.0);
+        assert_eq!(indicated_altitude_m(1018.0, 1018.0), 0.0);
+    }
+
+    #[test]
+    fn test_sea_level_pressure_inverts_altitude() {
+        // ICAO standard atmosphere at 1000 m
+        let reduced = sea_level_pressure_hpa(898.75, 1000.0);
+        assert!((reduced - STANDARD_PRESSURE_HPA).abs() < 0.1, "{}", reduced);
+        for (station, qnh) in [(1013.25, 1013.25), (954.61, 1020.0), (701.09, 990.0)] {
+            let altitude = indicated_altitude_m(station, qnh);
+            let reduced = sea_level_pressure_hpa(station, altitude);
+            assert!((reduced - qnh).abs() < 1e-9, "{} hPa: {}", station, reduced);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// MIT License
+// Original by Copyright (c) 2021 Neutroni
+// Modified by Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! # Environment sensors behind one trait
+//!
+//! `Sensor` is what a program measuring temperature, humidity and pressure
+//! needs from a device, so it can hold a `Box<dyn Sensor>` and leave the
+//! choice of the device to its configuration. `Bme280`, `Sht31` and
+//! `Aht20` implement it, and `ConstantSensor` stands in for a device that
+//! is not there.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use rppal::i2c::Error;
+
+use crate::aht20::Aht20;
+use crate::bme280::{Bme280, Bme280Error, Bme280Settings, Measurement};
+use crate::bus::I2cBus;
+use crate::sht31::Sht31;
+
+/// Future returned by `Sensor::measure`.
+pub type MeasureFuture<'a> = Pin<Box<dyn Future<Output = Result<Measurement, Error>> + 'a>>;
+
+/// Future returned by `Sensor::reset`.
+pub type ResetFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
+/// Temperature, humidity and pressure sensor.
+pub trait Sensor {
+    /// Take a measurement.
+    /// Values a device does not measure are NaN.
+    fn measure(&self) -> MeasureFuture<'_>;
+
+    /// Apply oversampling and filter settings. Devices without such
+    /// settings ignore them.
+    /// # Arguments
+    /// * `settings` - Measurement settings.
+    /// # Returns
+    /// * Result<(), Error>
+    fn configure(&self, settings: Bme280Settings) -> Result<(), Error> {
+        let _ = settings;
+        Ok(())
+    }
+
+    /// Reset the device and restore its settings. Devices without a reset
+    /// ignore it.
+    fn reset(&self) -> ResetFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Whether the device measures humidity. Devices which do not return
+    /// NaN for it.
+    fn measures_humidity(&self) -> bool {
+        true
+    }
+
+    /// Whether the device can get stuck on one reading. Devices returning
+    /// the same reading by design do not.
+    fn can_stick(&self) -> bool {
+        true
+    }
+}
+
+impl<B: I2cBus> Sensor for Bme280<B> {
+    fn measure(&self) -> MeasureFuture<'_> {
+        Box::pin(async move { self.make_measurement().await.map_err(bus_error) })
+    }
+
+    fn configure(&self, settings: Bme280Settings) -> Result<(), Error> {
+        Bme280::configure(self, settings).map_err(bus_error)
+    }
+
+    fn reset(&self) -> ResetFuture<'_> {
+        Box::pin(async move { self.soft_reset().await.map_err(bus_error) })
+    }
+
+    fn measures_humidity(&self) -> bool {
+        self.chip().has_humidity()
+    }
+}
+
+/// Error of a `Bme280` as the error of the trait.
+/// Bus errors are passed on, the others become I/O errors carrying them.
+fn bus_error(e: Bme280Error) -> Error {
+    let kind = match e {
+        Bme280Error::I2c(e) => return e,
+        Bme280Error::InvalidSettings(_) => std::io::ErrorKind::InvalidInput,
+        _ => std::io::ErrorKind::Other,
+    };
+    Error::Io(std::io::Error::new(kind, e))
+}
+
+impl<B: I2cBus> Sensor for Sht31<B> {
+    fn measure(&self) -> MeasureFuture<'_> {
+        Box::pin(self.make_measurement())
+    }
+
+    fn reset(&self) -> ResetFuture<'_> {
+        Box::pin(Sht31::reset(self))
+    }
+}
+
+impl<B: I2cBus> Sensor for Aht20<B> {
+    fn measure(&self) -> MeasureFuture<'_> {
+        Box::pin(self.make_measurement())
+    }
+}
+
+/// Sensor returning the same measurement every time.
+/// Useful to run a program without the hardware, e.g. in CI.
+#[derive(Copy, Clone, Debug)]
+pub struct ConstantSensor {
+    measurement: Measurement,
+}
+
+impl ConstantSensor {
+    /// Create a new constant sensor.
+    /// # Arguments
+    /// * `measurement` - Measurement returned.
+    pub fn new(measurement: Measurement) -> ConstantSensor {
+        ConstantSensor { measurement }
+    }
+}
+
+impl Default for ConstantSensor {
+    /// 20 °C, 50 % and the standard atmosphere of 101325 Pa.
+    fn default() -> Self {
+        ConstantSensor::new(Measurement {
+            temperature_c: 20.0,
+            pressure_pa: 101325.0,
+            humidity_relative: 50.0,
+        })
+    }
+}
+
+impl Sensor for ConstantSensor {
+    fn measure(&self) -> MeasureFuture<'_> {
+        let measurement = self.measurement;
+        Box::pin(async move { Ok(measurement) })
+    }
+
+    fn can_stick(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::MockI2cBus;
+
+    #[tokio::test]
+    async fn test_sensors_behind_the_trait() {
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[0x60]);
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+        let sht31 = MockI2cBus::new();
+        sht31.queue_read(&[0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]);
+        let sensors: Vec<Box<dyn Sensor>> = vec![
+            Box::new(Bme280::with_bus(bus).unwrap()),
+            Box::new(ConstantSensor::default()),
+            Box::new(Sht31::with_bus(sht31).unwrap()),
+        ];
+
+        let mut temperatures = Vec::new();
+        for sensor in &sensors {
+            sensor.configure(Bme280Settings::default()).unwrap();
+            sensor.reset().await.unwrap();
+            temperatures.push(sensor.measure().await.unwrap().temperature_c);
+        }
+        assert!((temperatures[0] - 25.08).abs() < 0.01);
+        assert_eq!(temperatures[1], 20.0);
+        assert!((temperatures[2] - 25.0).abs() < 0.01);
+        let can_stick: Vec<_> = sensors.iter().map(|sensor| sensor.can_stick()).collect();
+        assert_eq!(can_stick, vec![true, false, true]);
+    }
+}
@@ -0,0 +1,251 @@
+// MIT License
+// Original by Copyright (c) 2021 Neutroni
+// Modified by Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! SHT31 temperature and humidity sensor driver for Raspberry Pi
+//!
+//! Conversions and the CRC follow the Sensirion SHT3x-DIS datasheet. The
+//! SHT31 has no pressure sensor, so measurements carry a NaN pressure.
+
+use std::sync::{Mutex, MutexGuard};
+
+use rppal::i2c::{Error, I2c};
+use tokio::time::{Duration, sleep};
+
+use crate::bme280::Measurement;
+use crate::bus::I2cBus;
+
+/// SHT31 I2C address with the ADDR pin low
+pub const SHT31_ADDR: u16 = 0x44;
+/// SHT31 I2C address with the ADDR pin high
+pub const SHT31_ADDR2: u16 = 0x45;
+
+/// Single shot measurement, high repeatability, clock stretching enabled
+const MEASURE_HIGH_REPEATABILITY: [u8; 2] = [0x2C, 0x06];
+/// Soft reset
+const SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+/// Longest high repeatability measurement is 15 ms. Read after it, since
+/// the Pi does not support clock stretching.
+const MEASUREMENT_TIME_MS: u64 = 16;
+/// Soft reset takes at most 1.5 ms
+const SOFT_RESET_TIME_MS: u64 = 2;
+
+/// SHT31 Driver
+pub struct Sht31<B: I2cBus = I2c> {
+    bus: Mutex<B>,
+    /// Held from the measurement command until its data is read
+    measuring: tokio::sync::Mutex<()>,
+}
+
+impl Sht31<I2c> {
+    /// Create a new SHT31 instance.
+    /// # Arguments
+    /// * `addr` - I2C address of the SHT31.
+    /// # Returns
+    /// * Result<Sht31, Error>
+    pub fn new(addr: u16) -> Result<Sht31, Error> {
+        let mut bus: I2c = I2c::new()?;
+        bus.set_slave_address(addr)?;
+        return Sht31::with_bus(bus);
+    }
+}
+
+impl<B: I2cBus> Sht31<B> {
+    /// Create a new SHT31 instance on the given bus.
+    /// The sensor is soft reset, which fails if nothing answers at the
+    /// address. The thread sleeps until the reset is done.
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the SHT31.
+    /// # Returns
+    /// * Result<Sht31, Error>
+    pub fn with_bus(bus: B) -> Result<Sht31<B>, Error> {
+        bus.smbus_write_byte(SOFT_RESET[0], SOFT_RESET[1])?;
+        std::thread::sleep(Duration::from_millis(SOFT_RESET_TIME_MS));
+        return Result::Ok(Sht31 {
+            bus: Mutex::new(bus),
+            measuring: tokio::sync::Mutex::new(()),
+        });
+    }
+
+    /// Lock the bus of the driver.
+    fn lock(&self) -> MutexGuard<'_, B> {
+        return self.bus.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Soft reset the sensor.
+    /// # Returns
+    /// * Result<(), Error>
+    pub async fn reset(&self) -> Result<(), Error> {
+        let _measuring = self.measuring.lock().await;
+        self.lock().smbus_write_byte(SOFT_RESET[0], SOFT_RESET[1])?;
+        sleep(Duration::from_millis(SOFT_RESET_TIME_MS)).await;
+        return Ok(());
+    }
+
+    /// Make a single shot measurement.
+    /// # Returns
+    /// * Result<Measurement, Error> with a NaN pressure, `Error::Io` with
+    ///   `InvalidData` if a CRC does not match.
+    pub async fn make_measurement(&self) -> Result<Measurement, Error> {
+        let _measuring = self.measuring.lock().await;
+        self.lock()
+            .smbus_write_byte(MEASURE_HIGH_REPEATABILITY[0], MEASURE_HIGH_REPEATABILITY[1])?;
+        //Wait for measurement to complete, with the bus released
+        sleep(Duration::from_millis(MEASUREMENT_TIME_MS)).await;
+        let mut data: [u8; 6] = [0; 6];
+        self.lock().read(&mut data)?;
+        return parse_measurement(&data);
+    }
+}
+
+/// Convert the six bytes of a measurement.
+/// # Arguments
+/// * `data` - Temperature word, its CRC, humidity word, its CRC.
+/// # Returns
+/// * Result<Measurement, Error> with a NaN pressure.
+pub fn parse_measurement(data: &[u8; 6]) -> Result<Measurement, Error> {
+    let temperature: u16 = checked_word(&data[0..3])?;
+    let humidity: u16 = checked_word(&data[3..6])?;
+    return Result::Ok(Measurement {
+        temperature_c: raw_to_temperature(temperature),
+        pressure_pa: f64::NAN,
+        humidity_relative: raw_to_humidity(humidity),
+    });
+}
+
+/// Check the CRC of a word.
+/// # Arguments
+/// * `data` - Word, most significant byte first, and its CRC.
+fn checked_word(data: &[u8]) -> Result<u16, Error> {
+    let crc: u8 = crc8(&data[0..2]);
+    if crc != data[2] {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "SHT31 CRC mismatch: received {:#04x}, calculated {:#04x}",
+                data[2], crc
+            ),
+        )));
+    }
+    return Result::Ok(u16::from_be_bytes([data[0], data[1]]));
+}
+
+/// CRC-8 of the sensor (polynomial 0x31, initial value 0xFF).
+/// # Arguments
+/// * `data` - Bytes to check.
+/// # Returns
+/// * CRC, 0x92 for 0xBE 0xEF.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    return crc;
+}
+
+/// Convert a raw temperature (datasheet: -45 + 175 * raw / (2^16 - 1)).
+/// # Arguments
+/// * `raw` - 16-bit temperature word.
+/// # Returns
+/// * Temperature in °C, -45.0 to 130.0.
+pub fn raw_to_temperature(raw: u16) -> f64 {
+    return -45.0 + 175.0 * raw as f64 / 65535.0;
+}
+
+/// Convert a raw humidity (datasheet: 100 * raw / (2^16 - 1)).
+/// # Arguments
+/// * `raw` - 16-bit humidity word.
+/// # Returns
+/// * Relative humidity in %, 0.0 to 100.0.
+pub fn raw_to_humidity(raw: u16) -> f64 {
+    return 100.0 * raw as f64 / 65535.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{MockI2cBus, Transfer};
+
+    #[test]
+    fn test_crc8() {
+        // Datasheet example
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+        assert_eq!(crc8(&[0x00, 0x00]), 0x81);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(raw_to_temperature(0), -45.0);
+        assert_eq!(raw_to_temperature(u16::MAX), 130.0);
+        assert!((raw_to_temperature(0x6666) - 25.0).abs() < 0.01);
+        assert_eq!(raw_to_humidity(0), 0.0);
+        assert_eq!(raw_to_humidity(u16::MAX), 100.0);
+        assert!((raw_to_humidity(0x8000) - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_measurement_checks_crc() {
+        let data = [
+            0x66,
+            0x66,
+            crc8(&[0x66, 0x66]),
+            0x80,
+            0x00,
+            crc8(&[0x80, 0x00]),
+        ];
+        let measurement = parse_measurement(&data).unwrap();
+        assert!((measurement.temperature_c - 25.0).abs() < 0.01);
+        assert!((measurement.humidity_relative - 50.0).abs() < 0.01);
+        assert!(measurement.pressure_pa.is_nan());
+
+        let mut corrupted = data;
+        corrupted[4] ^= 0x01;
+        match parse_measurement(&corrupted) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_make_measurement_on_mock_bus() {
+        let bus = MockI2cBus::new();
+        bus.queue_read(&[0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]);
+        let sensor = Sht31::with_bus(bus).unwrap();
+
+        let measurement = sensor.make_measurement().await.unwrap();
+        assert!((measurement.temperature_c - 25.0).abs() < 0.01);
+        assert!((measurement.humidity_relative - 50.0).abs() < 0.01);
+        assert_eq!(
+            sensor.lock().transfers(),
+            vec![
+                Transfer::Write(0x30, 0xA2),
+                Transfer::Write(0x2C, 0x06),
+                Transfer::Receive(6),
+            ]
+        );
+    }
+}
@@ -20,11 +20,26 @@
 // SOFTWARE.
 
 //! # SO1602A Driver for Raspberry Pi
+//!
+//! Every command and data byte is its own SMBus transfer; a line is not
+//! sent as one bulk write. A write delay set with `with_write_delay` is
+//! therefore inserted after every byte, including within `put_str`, so a
+//! 16 character line is sent with 17 delays. The session holding the bus
+//! covers the delays, so frames stay unbroken on a shared bus.
+//!
+//! The 20x4 SO2004A of the same family is driven the same way with
+//! `with_geometry(SO2004A_GEOMETRY)`, which also switches the controller
+//! to 4 lines during `setup`.
+
+use std::sync::{Mutex, MutexGuard};
 
 use tokio::time::{sleep, Duration};
 
 use rppal::i2c;
 
+use crate::bus::I2cBus;
+use crate::display::{CharDisplay, DisplayGeometry};
+
 /// SO1602A I2C Address 1
 pub const SO1602A_ADDR: u16 = 0x3c;
 /// SO1602A I2C Address 2
@@ -34,6 +49,28 @@ pub const SO1602A_ADDR2: u16 = 0x3d;
 pub const SO1602A_1ST_LINE: u8 = 0x80;
 /// SO1602A start of 2nd Line Address
 pub const SO1602A_2ND_LINE: u8 = 0xA0;
+/// SO2004A start of 3rd Line Address
+pub const SO2004A_3RD_LINE: u8 = 0xC0;
+/// SO2004A start of 4th Line Address
+pub const SO2004A_4TH_LINE: u8 = 0xE0;
+
+/// 16x2 SO1602A
+pub const SO1602A_GEOMETRY: DisplayGeometry = DisplayGeometry {
+    cols: 16,
+    rows: 2,
+    line_addresses: [SO1602A_1ST_LINE, SO1602A_2ND_LINE, 0, 0],
+};
+/// 20x4 SO2004A
+pub const SO2004A_GEOMETRY: DisplayGeometry = DisplayGeometry {
+    cols: 20,
+    rows: 4,
+    line_addresses: [
+        SO1602A_1ST_LINE,
+        SO1602A_2ND_LINE,
+        SO2004A_3RD_LINE,
+        SO2004A_4TH_LINE,
+    ],
+};
 
 /// SO1602A Command
 pub const SO1602A_COMMAND: u8 = 0x00;
@@ -69,28 +106,116 @@ pub const SO1602A_FUNCTIONSET_RE_BLINKENABLE: u8 = 0x04;
 /// Function Set Reverse in Function Set when RE=1
 pub const SO1602A_FUNCTIONSET_RE_REVERSE: u8 = 0x01;
 
+/// Extended Function Set Command when RE=1
+pub const SO1602A_EXTENDED_FUNCTIONSET: u8 = 0x08;
+/// 3 or 4 Line in Extended Function Set
+pub const SO1602A_EXTENDED_FUNCTIONSET_4LINE: u8 = 0x01;
+
 /// SD flag ON Command
 pub const SO1602A_OLED_ON: u8 = 0x79;
 /// SD flag OFF Command
 pub const SO1602A_OLED_OFF: u8 = 0x78;
 /// OLED Contrast Command
 pub const SO1602A_OLED_CONSTRAST: u8 = 0x81;
+/// Contrast level set by `setup` unless configured otherwise
+pub const SO1602A_DEFAULT_CONTRAST: u8 = 0x7F;
+/// Number of steps of the startup contrast ramp
+pub const SO1602A_CONTRAST_RAMP_STEPS: u32 = 16;
 
 /// SO1602A Driver
-pub struct SO1602A {
-    i2c: i2c::I2c,
+/// Each method writes in one bus session, so an `Arc<SO1602A>` can be
+/// shared by several tasks without mixing their frames.
+pub struct SO1602A<B: I2cBus = i2c::I2c> {
+    i2c: Mutex<B>,
+    /// Held across `setup`, which waits between sessions
+    setup_lock: tokio::sync::Mutex<()>,
+    contrast: u8,
+    contrast_ramp: Duration,
+    write_delay: Duration,
+    geometry: DisplayGeometry,
 }
 
-impl SO1602A {
+impl SO1602A<i2c::I2c> {
     /// Create a new SO1602A instance
     /// # Arguments
     /// * `addr` - I2C Address
     /// # Returns
     /// * SO1602A instance
-    pub fn new(addr: u16) -> Result<SO1602A, i2c::Error> {
+    pub fn new(addr: u16) -> Result<SO1602A<i2c::I2c>, i2c::Error> {
         let mut i2c = i2c::I2c::new()?;
         i2c.set_slave_address(addr)?;
-        Ok(SO1602A { i2c })
+        Ok(SO1602A::with_bus(i2c))
+    }
+}
+
+impl<B: I2cBus> SO1602A<B> {
+    /// Create a new SO1602A instance on the given bus
+    /// # Arguments
+    /// * `bus` - I2C bus addressed to the SO1602A
+    /// # Returns
+    /// * SO1602A instance
+    pub fn with_bus(bus: B) -> SO1602A<B> {
+        SO1602A {
+            i2c: Mutex::new(bus),
+            setup_lock: tokio::sync::Mutex::new(()),
+            contrast: SO1602A_DEFAULT_CONTRAST,
+            contrast_ramp: Duration::ZERO,
+            write_delay: Duration::ZERO,
+            geometry: SO1602A_GEOMETRY,
+        }
+    }
+
+    /// Set the size and line addresses of the module
+    /// # Arguments
+    /// * `geometry` - `SO1602A_GEOMETRY` or `SO2004A_GEOMETRY`
+    /// # Returns
+    /// * SO1602A instance
+    pub fn with_geometry(mut self, geometry: DisplayGeometry) -> SO1602A<B> {
+        self.geometry = geometry;
+        self
+    }
+
+    /// Set the contrast applied by `setup`
+    /// # Arguments
+    /// * `level` - Contrast level
+    /// * `ramp` - Time to fade in from 0 to `level`, zero to set it at once
+    /// # Returns
+    /// * SO1602A instance
+    pub fn with_contrast(mut self, level: u8, ramp: Duration) -> SO1602A<B> {
+        self.contrast = level;
+        self.contrast_ramp = ramp;
+        self
+    }
+
+    /// Set the wait after each command and data byte
+    /// Some modules lose bytes written back to back and need about 30us
+    /// between them. The bus stays held during the waits.
+    /// # Arguments
+    /// * `delay` - Wait after each write, zero for none
+    /// # Returns
+    /// * SO1602A instance
+    pub fn with_write_delay(mut self, delay: Duration) -> SO1602A<B> {
+        self.write_delay = delay;
+        self
+    }
+
+    /// Lock the bus of the driver.
+    /// A panic in another session does not leave the display in a broken
+    /// state, so a poisoned lock is recovered.
+    fn lock(&self) -> MutexGuard<'_, B> {
+        self.i2c.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` as one bus session, holding the driver's bus for its duration.
+    /// # Arguments
+    /// * `f` - Transfers to run on the bus
+    /// # Returns
+    /// * Result of `f`
+    fn session<T, F>(&self, f: F) -> Result<T, i2c::Error>
+    where
+        F: FnOnce(&dyn I2cBus) -> Result<T, i2c::Error>,
+    {
+        self.lock().session(f)
     }
 
     /// Send Command
@@ -99,8 +224,7 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn send_command(&self, data: u8) -> Result<(), i2c::Error> {
-        self.i2c.smbus_write_byte(SO1602A_COMMAND, data)?;
-        Ok(())
+        self.session(|bus| write_command(bus, self.write_delay, data))
     }
 
     /// Send Data
@@ -109,8 +233,7 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn send_data(&self, data: u8) -> Result<(), i2c::Error> {
-        self.i2c.smbus_write_byte(SO1602A_DATA, data)?;
-        Ok(())
+        self.session(|bus| write_data(bus, self.write_delay, data))
     }
 
     /// Wait
@@ -127,41 +250,83 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn send_oled_command(&self, d1: u8, d2: u8) -> Result<(), i2c::Error> {
-        // Extended register mode (RE=1)
-        self.send_command(
-            SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE | SO1602A_FUNCTIONSET_RE,
-        )?;
-        // OLED Command Set (SD=1)
-        self.send_command(SO1602A_OLED_ON)?;
-
-        // Send OLED Command
-        self.send_command(d1)?;
-        self.send_command(d2)?;
-
-        // Reset to OLED Command Set (SD=0)
-        self.send_command(SO1602A_OLED_OFF)?;
-        // Reset to Extended Command Set (RE=0)
-        self.send_command(SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE)?;
+        self.session(|bus| write_oled_command(bus, self.write_delay, d1, d2))
+    }
+
+    /// Set Contrast
+    /// # Arguments
+    /// * `level` - Contrast level (0x00-0xFF)
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn set_contrast(&self, level: u8) -> Result<(), i2c::Error> {
+        self.send_oled_command(SO1602A_OLED_CONSTRAST, level)
+    }
 
+    /// Fade the contrast in from 0 to the configured level
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    async fn ramp_contrast(&self) -> Result<(), i2c::Error> {
+        let interval = self.contrast_ramp / SO1602A_CONTRAST_RAMP_STEPS;
+        let mut last = 0;
+        for step in 1..=SO1602A_CONTRAST_RAMP_STEPS {
+            let level = (u32::from(self.contrast) * step / SO1602A_CONTRAST_RAMP_STEPS) as u8;
+            // Low targets give repeated levels, which are skipped
+            if level > last {
+                self.set_contrast(level)?;
+                last = level;
+            }
+            sleep(interval).await;
+        }
         Ok(())
     }
 
     /// Setup SO1602A Device
+    /// With a contrast ramp, the display starts dark and fades in after
+    /// being cleared.
     /// # Returns
     /// * Result<(), i2c::Error>
     pub async fn setup(&self) -> Result<(), i2c::Error> {
-        // Contrast Setting
-        self.send_oled_command(SO1602A_OLED_CONSTRAST, 0x7F)?;
-        // Display ON, Cursor OFF, Blink OFF
-        self.send_command(SO1602A_DISPLAYCONTROL | SO1602A_DISPLAYCONTROL_DISPLAY_ON)?;
-        // Clear Display
-        self.send_command(SO1602A_BASIC_CLEARDISPLAY)?;
-        // Position to Home
-        self.send_command(SO1602A_BASIC_HOMEPOSITION)?;
-
-        // wait
+        let _setup = self.setup_lock.lock().await;
+        let ramp = !self.contrast_ramp.is_zero();
+        let lines = if self.geometry.rows > 2 {
+            SO1602A_EXTENDED_FUNCTIONSET_4LINE
+        } else {
+            0
+        };
+        self.session(|bus| {
+            // 1-2 or 3-4 Lines
+            write_extended_command(
+                bus,
+                self.write_delay,
+                SO1602A_EXTENDED_FUNCTIONSET | lines,
+            )?;
+            // Contrast Setting
+            let initial = if ramp { 0 } else { self.contrast };
+            write_oled_command(
+                bus,
+                self.write_delay,
+                SO1602A_OLED_CONSTRAST,
+                initial,
+            )?;
+            // Display ON, Cursor OFF, Blink OFF
+            write_command(
+                bus,
+                self.write_delay,
+                SO1602A_DISPLAYCONTROL | SO1602A_DISPLAYCONTROL_DISPLAY_ON,
+            )?;
+            // Clear Display
+            write_command(bus, self.write_delay, SO1602A_BASIC_CLEARDISPLAY)?;
+            // Position to Home
+            write_command(bus, self.write_delay, SO1602A_BASIC_HOMEPOSITION)
+        })?;
+
+        // wait, with the bus released
         self.wait(20).await;
 
+        if ramp {
+            self.ramp_contrast().await?;
+        }
+
         Ok(())
     }
 
@@ -172,11 +337,13 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
-        self.send_command(0x40 | (index << 3))?;
-        for d in data {
-            self.send_data(d)?;
-        }
-        Ok(())
+        self.session(|bus| {
+            write_command(bus, self.write_delay, 0x40 | (index << 3))?;
+            for d in data {
+                write_data(bus, self.write_delay, d)?;
+            }
+            Ok(())
+        })
     }
 
     /// Put a character at the specified position
@@ -186,9 +353,10 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
-        self.send_command(position)?;
-        self.send_data(data)?;
-        Ok(())
+        self.session(|bus| {
+            write_command(bus, self.write_delay, position)?;
+            write_data(bus, self.write_delay, data)
+        })
     }
 
     /// Print a string at the specified line
@@ -198,26 +366,168 @@ impl SO1602A {
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
-        self.send_command(line_addr)?;
-        for c in s.as_bytes() {
-            self.send_data(*c)?;
-        }
-        Ok(())
+        self.session(|bus| {
+            write_command(bus, self.write_delay, line_addr)?;
+            for c in s.as_bytes() {
+                write_data(bus, self.write_delay, *c)?;
+            }
+            Ok(())
+        })
     }
 
     /// Clear Display and Home Position
     /// # Returns
     /// * Result<(), i2c::Error>
     pub fn clear_home(&self) -> Result<(), i2c::Error> {
-        self.send_command(0x01)?;
-        self.send_command(0x02)?;
-        Ok(())
+        self.session(|bus| {
+            write_command(bus, self.write_delay, 0x01)?;
+            write_command(bus, self.write_delay, 0x02)
+        })
+    }
+
+    /// Turn the display off, keeping DDRAM
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn display_off(&self) -> Result<(), i2c::Error> {
+        self.send_command(SO1602A_DISPLAYCONTROL)
+    }
+
+    /// Turn the display on, Cursor OFF, Blink OFF
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn display_on(&self) -> Result<(), i2c::Error> {
+        self.send_command(SO1602A_DISPLAYCONTROL | SO1602A_DISPLAYCONTROL_DISPLAY_ON)
+    }
+}
+
+/// Write a command byte
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `delay` - Wait after the write
+/// * `data` - Command
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_command(bus: &dyn I2cBus, delay: Duration, data: u8) -> Result<(), i2c::Error> {
+    bus.smbus_write_byte(SO1602A_COMMAND, data)?;
+    pace(bus, delay);
+    Ok(())
+}
+
+/// Write a data byte
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `delay` - Wait after the write
+/// * `data` - Data
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_data(bus: &dyn I2cBus, delay: Duration, data: u8) -> Result<(), i2c::Error> {
+    bus.smbus_write_byte(SO1602A_DATA, data)?;
+    pace(bus, delay);
+    Ok(())
+}
+
+/// Wait between two writes, if pacing is configured
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `delay` - Wait, zero for none
+fn pace(bus: &dyn I2cBus, delay: Duration) {
+    if !delay.is_zero() {
+        bus.delay(delay);
+    }
+}
+
+/// Write a Command of the extended command set
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `delay` - Wait after each write
+/// * `data` - Command
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_extended_command(bus: &dyn I2cBus, delay: Duration, data: u8) -> Result<(), i2c::Error> {
+    // Extended register mode (RE=1)
+    write_command(
+        bus,
+        delay,
+        SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE | SO1602A_FUNCTIONSET_RE,
+    )?;
+    write_command(bus, delay, data)?;
+    // Reset to Extended Command Set (RE=0)
+    write_command(bus, delay, SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE)
+}
+
+/// Write an OLED Command
+/// # Arguments
+/// * `bus` - I2C bus in a session
+/// * `delay` - Wait after each write
+/// * `d1` - Command 1
+/// * `d2` - Command 2
+/// # Returns
+/// * Result<(), i2c::Error>
+fn write_oled_command(
+    bus: &dyn I2cBus,
+    delay: Duration,
+    d1: u8,
+    d2: u8,
+) -> Result<(), i2c::Error> {
+    // Extended register mode (RE=1)
+    write_command(
+        bus,
+        delay,
+        SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE | SO1602A_FUNCTIONSET_RE,
+    )?;
+    // OLED Command Set (SD=1)
+    write_command(bus, delay, SO1602A_OLED_ON)?;
+
+    // Send OLED Command
+    write_command(bus, delay, d1)?;
+    write_command(bus, delay, d2)?;
+
+    // Reset to OLED Command Set (SD=0)
+    write_command(bus, delay, SO1602A_OLED_OFF)?;
+    // Reset to Extended Command Set (RE=0)
+    write_command(bus, delay, SO1602A_FUNCTIONSET | SO1602A_FUNCTIONSET_2OR4LINE)?;
+
+    Ok(())
+}
+
+impl<B: I2cBus> CharDisplay for SO1602A<B> {
+    async fn setup(&self) -> Result<(), i2c::Error> {
+        SO1602A::setup(self).await
+    }
+
+    fn geometry(&self) -> DisplayGeometry {
+        self.geometry
+    }
+
+    fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+        SO1602A::register_char(self, index, data)
+    }
+
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        SO1602A::put_u8(self, position, data)
+    }
+
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        SO1602A::put_str(self, line_addr, s)
+    }
+
+    fn clear_home(&self) -> Result<(), i2c::Error> {
+        SO1602A::clear_home(self)
+    }
+
+    fn display_off(&self) -> Result<(), i2c::Error> {
+        SO1602A::display_off(self)
+    }
+
+    fn display_on(&self) -> Result<(), i2c::Error> {
+        SO1602A::display_on(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bus::{MockI2cBus, Transfer};
 
     #[test]
     fn test_constants() {
@@ -311,6 +621,162 @@ mod tests {
         assert_eq!(instruction_set_config, 0x29);
     }
 
+    #[test]
+    fn test_put_str_on_mock_bus() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.put_str(SO1602A_2ND_LINE, "Hi").unwrap();
+        assert_eq!(
+            display.lock().writes(),
+            vec![
+                (SO1602A_COMMAND, SO1602A_2ND_LINE),
+                (SO1602A_DATA, b'H'),
+                (SO1602A_DATA, b'i'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_writers_do_not_interleave() {
+        let display = std::sync::Arc::new(SO1602A::with_bus(MockI2cBus::new()));
+        let handles: Vec<_> = [b'A', b'B', b'C', b'D']
+            .into_iter()
+            .map(|c| {
+                let display = display.clone();
+                std::thread::spawn(move || {
+                    let line = String::from_utf8(vec![c; 16]).unwrap();
+                    for _ in 0..50 {
+                        display.put_str(SO1602A_1ST_LINE, &line).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let writes = display.lock().writes();
+        assert_eq!(writes.len(), 4 * 50 * 17);
+        for frame in writes.chunks(17) {
+            assert_eq!(frame[0], (SO1602A_COMMAND, SO1602A_1ST_LINE));
+            assert!(frame[1..].iter().all(|w| *w == frame[1]), "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn test_display_off_on() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.display_off().unwrap();
+        display.display_on().unwrap();
+        assert_eq!(
+            display.lock().writes(),
+            vec![(SO1602A_COMMAND, 0x08), (SO1602A_COMMAND, 0x0C)]
+        );
+    }
+
+    #[test]
+    fn test_write_delay_after_each_byte() {
+        let delay = Duration::from_micros(30);
+        let display = SO1602A::with_bus(MockI2cBus::new()).with_write_delay(delay);
+        display.put_str(SO1602A_2ND_LINE, "Hi").unwrap();
+        display.send_command(SO1602A_BASIC_HOMEPOSITION).unwrap();
+        assert_eq!(
+            display.lock().transfers(),
+            vec![
+                Transfer::Write(SO1602A_COMMAND, SO1602A_2ND_LINE),
+                Transfer::Delay(delay),
+                Transfer::Write(SO1602A_DATA, b'H'),
+                Transfer::Delay(delay),
+                Transfer::Write(SO1602A_DATA, b'i'),
+                Transfer::Delay(delay),
+                Transfer::Write(SO1602A_COMMAND, SO1602A_BASIC_HOMEPOSITION),
+                Transfer::Delay(delay),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_write_delay_by_default() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.set_contrast(0x40).unwrap();
+        let transfers = display.lock().transfers();
+        assert_eq!(transfers.len(), 6);
+        assert!(!transfers.iter().any(|t| matches!(t, Transfer::Delay(_))));
+    }
+
+    /// Contrast levels sent with the OLED contrast command
+    fn contrast_levels(writes: &[(u8, u8)]) -> Vec<u8> {
+        writes
+            .windows(2)
+            .filter(|w| w[0] == (SO1602A_COMMAND, SO1602A_OLED_CONSTRAST))
+            .map(|w| w[1].1)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_setup_without_ramp() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.setup().await.unwrap();
+        assert_eq!(
+            contrast_levels(&display.lock().writes()),
+            vec![SO1602A_DEFAULT_CONTRAST]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_setup_contrast_ramp() {
+        let display = SO1602A::with_bus(MockI2cBus::new())
+            .with_contrast(0xC0, Duration::from_millis(32));
+        display.setup().await.unwrap();
+
+        let levels = contrast_levels(&display.lock().writes());
+        // Starts dark, then strictly increasing up to the target
+        assert_eq!(levels[0], 0);
+        assert!(levels.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(*levels.last().unwrap(), 0xC0);
+        assert_eq!(levels.len(), 1 + SO1602A_CONTRAST_RAMP_STEPS as usize);
+    }
+
+    #[tokio::test]
+    async fn test_setup_contrast_ramp_low_target() {
+        let display =
+            SO1602A::with_bus(MockI2cBus::new()).with_contrast(3, Duration::from_millis(16));
+        display.setup().await.unwrap();
+        assert_eq!(contrast_levels(&display.lock().writes()), vec![0, 1, 2, 3]);
+    }
+
+    /// Commands sent between entering and leaving the extended command set
+    fn extended_commands(writes: &[(u8, u8)]) -> Vec<u8> {
+        writes
+            .windows(3)
+            .filter(|w| {
+                w[0] == (SO1602A_COMMAND, 0x2A)
+                    && w[1].1 != SO1602A_OLED_ON
+                    && w[2] == (SO1602A_COMMAND, 0x28)
+            })
+            .map(|w| w[1].1)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_setup_line_count() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.setup().await.unwrap();
+        assert_eq!(extended_commands(&display.lock().writes()), vec![0x08]);
+
+        let display = SO1602A::with_bus(MockI2cBus::new()).with_geometry(SO2004A_GEOMETRY);
+        display.setup().await.unwrap();
+        assert_eq!(extended_commands(&display.lock().writes()), vec![0x09]);
+        assert_eq!(CharDisplay::line_address(&display, 2), 0xC0);
+        assert_eq!(CharDisplay::line_address(&display, 3), 0xE0);
+    }
+
+    #[test]
+    fn test_set_contrast() {
+        let display = SO1602A::with_bus(MockI2cBus::new());
+        display.set_contrast(0x40).unwrap();
+        assert_eq!(contrast_levels(&display.lock().writes()), vec![0x40]);
+    }
+
     #[test]
     fn test_character_index_bounds() {
         let max_custom_chars = 8;
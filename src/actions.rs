@@ -0,0 +1,107 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Control and alert state recorded alongside the measurements.
+//!
+//! Subsystems publish their current output here and every stored row carries
+//! a snapshot, so actions can be plotted together with the environment from a
+//! single table. A subsystem that is disabled leaves its value at `None`.
+
+use std::sync::{Arc, Mutex};
+
+/// State of the control and alert outputs at one instant.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ActionSnapshot {
+    /// Fan relay state, `None` when fan control is disabled.
+    pub fan_state: Option<bool>,
+    /// Bitmask of the active alerts, `None` when alerts are disabled.
+    pub alert_active: Option<u32>,
+}
+
+/// Action state shared between the subsystems and the main loop.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct SharedActions {
+    inner: Arc<Mutex<ActionSnapshot>>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl SharedActions {
+    /// Create a new state with every subsystem disabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish the fan relay state.
+    /// # Arguments
+    /// * `state` - Relay state, `None` if fan control is disabled.
+    pub fn set_fan_state(&self, state: Option<bool>) {
+        self.lock().fan_state = state;
+    }
+
+    /// Publish the active alerts.
+    /// # Arguments
+    /// * `mask` - Bitmask of the active alerts, `None` if alerts are disabled.
+    pub fn set_alert_active(&self, mask: Option<u32>) {
+        self.lock().alert_active = mask;
+    }
+
+    /// Get the current state.
+    pub fn snapshot(&self) -> ActionSnapshot {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ActionSnapshot> {
+        // The snapshot is plain data, so a poisoned lock is still usable
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_by_default() {
+        let actions = SharedActions::new();
+        assert_eq!(actions.snapshot(), ActionSnapshot::default());
+        assert_eq!(actions.snapshot().fan_state, None);
+        assert_eq!(actions.snapshot().alert_active, None);
+    }
+
+    #[test]
+    fn test_published_state_is_shared() {
+        let actions = SharedActions::new();
+        let publisher = actions.clone();
+        publisher.set_fan_state(Some(true));
+        publisher.set_alert_active(Some(0b101));
+        assert_eq!(
+            actions.snapshot(),
+            ActionSnapshot {
+                fan_state: Some(true),
+                alert_active: Some(0b101),
+            }
+        );
+
+        publisher.set_fan_state(None);
+        assert_eq!(actions.snapshot().fan_state, None);
+    }
+}
@@ -0,0 +1,1073 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Threshold alerts with escalating notification.
+//!
+//! An alert is shown on the display as soon as it is raised. While it
+//! persists it escalates to a logged warning and then to a webhook, each
+//! stage firing once. Clearing the alert resets its escalation. The timing
+//! is kept free of I/O so it can be driven by simulated time. A rule with a
+//! message also has it scrolled across the display while it is active.
+//!
+//! With `history` on, each alert is stored from raise to clear with the
+//! most extreme value it reached.
+//!
+//! A band_change rule instead notifies once each time the smoothed value
+//! settles into another band. It is never shown as an active alert.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::Measurement;
+use serde_json::json;
+
+use crate::config::{
+    AlertField, AlertRuleConfig, AlertRuleKind, AlertsConfig, BandChangeConfig, EscalationConfig,
+};
+use crate::helper;
+use crate::hooks;
+
+/// Longest wait for a webhook to respond.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Notification stage of a persisting alert, in escalation order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Display,
+    Log,
+    Webhook,
+}
+
+/// When each stage fires, counted from the moment the alert is raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscalationPlan {
+    pub log_after: Duration,
+    /// `None` if no webhook is configured.
+    pub webhook_after: Option<Duration>,
+}
+
+impl EscalationPlan {
+    /// Build the plan from the config.
+    pub fn from_config(config: &EscalationConfig) -> Self {
+        Self {
+            log_after: Duration::from_secs(config.log_after_mins * 60),
+            webhook_after: config
+                .webhook_url
+                .as_ref()
+                .map(|_| Duration::from_secs(config.webhook_after_mins * 60)),
+        }
+    }
+
+    /// Delay of a stage, `None` if the stage is disabled.
+    fn delay(&self, stage: Stage) -> Option<Duration> {
+        match stage {
+            Stage::Display => Some(Duration::ZERO),
+            Stage::Log => Some(self.log_after),
+            Stage::Webhook => self.webhook_after,
+        }
+    }
+}
+
+/// Escalation of one alert.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Escalation {
+    /// When the alert was raised, `None` while clear.
+    since: Option<Instant>,
+    /// Last stage fired.
+    reached: Option<Stage>,
+}
+
+impl Escalation {
+    /// Advance the escalation.
+    /// # Arguments
+    /// * `plan` - Stage delays.
+    /// * `active` - Whether the alert condition holds.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * Stages fired by this update, in order.
+    pub fn update(&mut self, plan: &EscalationPlan, active: bool, now: Instant) -> Vec<Stage> {
+        if !active {
+            *self = Self::default();
+            return Vec::new();
+        }
+        let since = *self.since.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(since);
+        let mut fired = Vec::new();
+        for stage in [Stage::Display, Stage::Log, Stage::Webhook] {
+            if self.reached.is_some_and(|reached| reached >= stage) {
+                continue;
+            }
+            match plan.delay(stage) {
+                Some(delay) if elapsed >= delay => {
+                    self.reached = Some(stage);
+                    fired.push(stage);
+                }
+                _ => break,
+            }
+        }
+        fired
+    }
+
+    /// Whether the alert is raised.
+    pub fn is_active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// How long the alert has persisted.
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        self.since
+            .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+}
+
+/// Band of a band_change rule the smoothed value settled into. The value
+/// must stay out of its band for the settle time, and beyond a boundary by
+/// the hysteresis margin, before the new band is taken, so noise around a
+/// boundary is not notified. The first band settled into is not notified.
+#[derive(Debug, Clone)]
+pub struct BandTracker {
+    width: f64,
+    origin: f64,
+    settle: Duration,
+    margin: f64,
+    smoothing: Duration,
+    /// Smoothed value and when it was last updated.
+    smoothed: Option<(f64, Instant)>,
+    /// Band settled into.
+    band: Option<i64>,
+    /// Band the value moved to, and since when.
+    candidate: Option<(i64, Instant)>,
+}
+
+/// Band change reported by `BandTracker::update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandChange {
+    pub from: i64,
+    pub to: i64,
+    /// Smoothed value.
+    pub value: f64,
+}
+
+impl BandTracker {
+    /// Create a tracker from the band settings of a rule.
+    pub fn new(config: &BandChangeConfig) -> Self {
+        Self {
+            width: config.width,
+            origin: config.origin,
+            settle: Duration::from_secs(config.settle_secs),
+            margin: config.hysteresis * config.width,
+            smoothing: Duration::from_secs(config.smoothing_secs),
+            smoothed: None,
+            band: None,
+            candidate: None,
+        }
+    }
+
+    /// Feed a reading.
+    /// # Arguments
+    /// * `value` - Raw value, non-finite values are ignored.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The change once the value settled into another band.
+    pub fn update(&mut self, value: f64, now: Instant) -> Option<BandChange> {
+        if !value.is_finite() {
+            return None;
+        }
+        let value = self.smooth(value, now);
+        // Staying in the settled band wins over a pending move
+        if self.band.is_some_and(|band| self.holds(band, value)) {
+            self.candidate = None;
+            return None;
+        }
+        let band = match self.candidate {
+            Some((candidate, _)) if self.holds(candidate, value) => candidate,
+            _ => self.band_of(value),
+        };
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == band => since,
+            _ => {
+                self.candidate = Some((band, now));
+                now
+            }
+        };
+        if now.saturating_duration_since(since) < self.settle {
+            return None;
+        }
+        self.candidate = None;
+        let from = self.band.replace(band)?;
+        Some(BandChange {
+            from,
+            to: band,
+            value,
+        })
+    }
+
+    /// Bounds of a band, lower inclusive and upper exclusive.
+    pub fn bounds(&self, band: i64) -> (f64, f64) {
+        let lower = self.origin + band as f64 * self.width;
+        (lower, lower + self.width)
+    }
+
+    /// Band containing a value, without hysteresis.
+    fn band_of(&self, value: f64) -> i64 {
+        ((value - self.origin) / self.width).floor() as i64
+    }
+
+    /// Whether a value is within a band widened by the hysteresis margin.
+    fn holds(&self, band: i64, value: f64) -> bool {
+        let (lower, upper) = self.bounds(band);
+        lower - self.margin <= value && value < upper + self.margin
+    }
+
+    /// Exponential moving average with the smoothing time constant, so
+    /// irregular intervals (captures, skipped ticks) weigh by elapsed time.
+    fn smooth(&mut self, value: f64, now: Instant) -> f64 {
+        let smoothed = match self.smoothed {
+            Some((previous, at)) if !self.smoothing.is_zero() => {
+                let elapsed = now.saturating_duration_since(at).as_secs_f64();
+                let alpha = 1.0 - (-elapsed / self.smoothing.as_secs_f64()).exp();
+                previous + alpha * (value - previous)
+            }
+            _ => value,
+        };
+        self.smoothed = Some((smoothed, now));
+        smoothed
+    }
+}
+
+/// Threshold crossed by an alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+impl Direction {
+    /// Name stored in the `alert_events` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Above => "above",
+            Self::Below => "below",
+        }
+    }
+}
+
+/// Threshold crossed by an active alert and its most extreme value so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Excursion {
+    direction: Direction,
+    peak: f64,
+}
+
+impl Excursion {
+    /// Take a value into the peak, the highest above and the lowest below.
+    fn extend(&mut self, value: f64) {
+        self.peak = match self.direction {
+            Direction::Above => self.peak.max(value),
+            Direction::Below => self.peak.min(value),
+        };
+    }
+}
+
+/// Change of an alert reported by `AlertEngine::update`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertTransition {
+    /// A stage fired.
+    Escalated {
+        name: String,
+        stage: Stage,
+        value: f64,
+        elapsed: Duration,
+    },
+    /// The condition no longer holds, after `duration` with the most
+    /// extreme value `peak`.
+    Cleared {
+        name: String,
+        field: AlertField,
+        direction: Direction,
+        peak: f64,
+        duration: Duration,
+    },
+    /// A band_change rule settled into another band, bounds `[lower, upper)`.
+    BandChanged {
+        name: String,
+        value: f64,
+        lower: f64,
+        upper: f64,
+        rising: bool,
+    },
+}
+
+/// Alert rules and their escalation.
+#[derive(Debug, Clone)]
+pub struct AlertEngine {
+    rules: Vec<AlertRuleConfig>,
+    plan: EscalationPlan,
+    escalations: Vec<Escalation>,
+    /// Tracker of each band_change rule, `None` for threshold rules.
+    bands: Vec<Option<BandTracker>>,
+    /// Latest value of each rule, filled into its message.
+    values: Vec<f64>,
+    /// Excursion of each active threshold rule.
+    excursions: Vec<Option<Excursion>>,
+}
+
+impl AlertEngine {
+    /// Build the engine from the config.
+    pub fn from_config(config: &AlertsConfig) -> Self {
+        Self {
+            rules: config.rules.clone(),
+            plan: EscalationPlan::from_config(&config.escalation),
+            escalations: vec![Escalation::default(); config.rules.len()],
+            bands: config
+                .rules
+                .iter()
+                .map(|rule| match (rule.kind, &rule.band) {
+                    (AlertRuleKind::BandChange, Some(band)) => Some(BandTracker::new(band)),
+                    _ => None,
+                })
+                .collect(),
+            values: vec![f64::NAN; config.rules.len()],
+            excursions: vec![None; config.rules.len()],
+        }
+    }
+
+    /// Whether any rule is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Check the rules against a measurement.
+    /// # Arguments
+    /// * `measurement` - Measurement of the main sensor.
+    /// * `thi` - Temperature-humidity index of the measurement.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * Fired stages, cleared alerts and band changes.
+    pub fn update(
+        &mut self,
+        measurement: &Measurement,
+        thi: f64,
+        now: Instant,
+    ) -> Vec<AlertTransition> {
+        let mut transitions = Vec::new();
+        let rules = self.rules.iter().zip(&mut self.escalations);
+        let rules = rules.zip(&mut self.bands).zip(&mut self.values);
+        let rules = rules.zip(&mut self.excursions);
+        for ((((rule, escalation), tracker), latest), excursion) in rules {
+            let value = field_value(rule.field, measurement, thi);
+            *latest = value;
+            if let Some(tracker) = tracker {
+                if let Some(change) = tracker.update(value, now) {
+                    let (lower, upper) = tracker.bounds(change.to);
+                    transitions.push(AlertTransition::BandChanged {
+                        name: rule.name.clone(),
+                        value: change.value,
+                        lower,
+                        upper,
+                        rising: change.to > change.from,
+                    });
+                }
+                continue;
+            }
+            let active = is_violated(rule, value);
+            let duration = escalation.elapsed(now);
+            for stage in escalation.update(&self.plan, active, now) {
+                transitions.push(AlertTransition::Escalated {
+                    name: rule.name.clone(),
+                    stage,
+                    value,
+                    elapsed: escalation.elapsed(now),
+                });
+            }
+            if active {
+                let direction = match rule.above {
+                    Some(above) if value > above => Direction::Above,
+                    _ => Direction::Below,
+                };
+                excursion
+                    .get_or_insert(Excursion {
+                        direction,
+                        peak: value,
+                    })
+                    .extend(value);
+            } else if let Some(excursion) = excursion.take() {
+                transitions.push(AlertTransition::Cleared {
+                    name: rule.name.clone(),
+                    field: rule.field,
+                    direction: excursion.direction,
+                    peak: excursion.peak,
+                    duration,
+                });
+            }
+        }
+        transitions
+    }
+
+    /// Bitmask of the active alerts, bit N for rule N.
+    pub fn active_mask(&self) -> u32 {
+        self.escalations
+            .iter()
+            .enumerate()
+            .filter(|(_, escalation)| escalation.is_active())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// Name of the first active alert, shown on the display.
+    pub fn display_text(&self) -> Option<&str> {
+        self.rules
+            .iter()
+            .zip(&self.escalations)
+            .find(|(_, escalation)| escalation.is_active())
+            .map(|(rule, _)| rule.name.as_str())
+    }
+
+    /// Whether an active alert is drawn over the display lease.
+    pub fn overrides_lease(&self) -> bool {
+        self.rules
+            .iter()
+            .zip(&self.escalations)
+            .any(|(rule, escalation)| rule.always_override && escalation.is_active())
+    }
+
+    /// Message of the first active alert, scrolled on the display.
+    /// # Returns
+    /// * The message with the latest value filled in, `None` if the alert
+    ///   has no message or no alert is active.
+    pub fn display_message(&self) -> Option<String> {
+        let ((rule, _), value) = self
+            .rules
+            .iter()
+            .zip(&self.escalations)
+            .zip(&self.values)
+            .find(|((_, escalation), _)| escalation.is_active())?;
+        let template = rule.message.as_deref()?;
+        let message = render_message(template, &rule.name, *value);
+        Some(helper::expand_char_placeholders(&message))
+    }
+}
+
+/// Alert from raise to clear, a row of the `alert_events` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEpisode {
+    pub name: String,
+    pub field: AlertField,
+    pub direction: Direction,
+    pub started_at: DateTime<Local>,
+    pub ended_at: DateTime<Local>,
+    /// Highest value above the threshold, or lowest below it.
+    pub peak: f64,
+}
+
+impl AlertEpisode {
+    /// Episode ended by a transition.
+    /// # Arguments
+    /// * `transition` - Transition from `AlertEngine::update`.
+    /// * `ended_at` - Time of the measurement that cleared the alert.
+    /// # Returns
+    /// * The episode if the transition cleared an alert.
+    pub fn from_transition(
+        transition: &AlertTransition,
+        ended_at: DateTime<Local>,
+    ) -> Option<Self> {
+        let AlertTransition::Cleared {
+            name,
+            field,
+            direction,
+            peak,
+            duration,
+        } = transition
+        else {
+            return None;
+        };
+        let duration = chrono::Duration::from_std(*duration).unwrap_or_default();
+        Some(Self {
+            name: name.clone(),
+            field: *field,
+            direction: *direction,
+            started_at: ended_at - duration,
+            ended_at,
+            peak: *peak,
+        })
+    }
+}
+
+/// Fill the placeholders of an alert message: `{name}` with the name of the
+/// rule, `{value}` with the value to one decimal and `{value:N}` with N
+/// decimals, up to 3. Other placeholders are left as they are.
+/// # Arguments
+/// * `template` - Message of the rule, e.g. "HIGH HUMIDITY {value:0}%".
+/// * `name` - Name of the rule.
+/// * `value` - Current value of the watched field.
+/// # Returns
+/// * Message to show.
+pub fn render_message(template: &str, name: &str, value: f64) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let replacement = after.find('}').and_then(|end| {
+            let text = match &after[..end] {
+                "name" => name.to_string(),
+                "value" => format!("{:.1}", value),
+                key => {
+                    let decimals = key.strip_prefix("value:")?.parse::<usize>().ok();
+                    format!("{:.*}", decimals.filter(|d| *d <= 3)?, value)
+                }
+            };
+            Some((text, end))
+        });
+        match replacement {
+            Some((text, end)) => {
+                result.push_str(&text);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Send the notification of a transition. The display stage is only
+/// logged here, the page shows the alert while it is active. Band changes
+/// are logged and posted to the webhook right away.
+/// # Arguments
+/// * `transition` - Transition from `AlertEngine::update`.
+/// * `webhook_url` - URL posted to at the webhook stage.
+pub fn notify(transition: &AlertTransition, webhook_url: Option<&str>) {
+    match transition {
+        AlertTransition::Escalated {
+            name,
+            stage: Stage::Display,
+            value,
+            ..
+        } => println!("Alert {} raised ({})", name, value),
+        AlertTransition::Escalated {
+            name,
+            stage: Stage::Log,
+            value,
+            elapsed,
+        } => eprintln!(
+            "Warning: alert {} has persisted for {} min ({})",
+            name,
+            elapsed.as_secs() / 60,
+            value
+        ),
+        AlertTransition::Escalated {
+            name,
+            stage: Stage::Webhook,
+            value,
+            elapsed,
+        } => {
+            let Some(url) = webhook_url else {
+                return;
+            };
+            let body = json!({
+                "alert": name,
+                "value": value,
+                "persisted_secs": elapsed.as_secs(),
+            });
+            post_webhook(name, url, body);
+        }
+        AlertTransition::Cleared {
+            name,
+            peak,
+            duration,
+            ..
+        } => println!(
+            "Alert {} cleared after {} min (peak {})",
+            name,
+            duration.as_secs() / 60,
+            peak
+        ),
+        AlertTransition::BandChanged {
+            name,
+            value,
+            lower,
+            upper,
+            rising,
+        } => {
+            println!(
+                "Alert {}: {} to {}..{} ({:.2})",
+                name,
+                if *rising { "up" } else { "down" },
+                lower,
+                upper,
+                value
+            );
+            let Some(url) = webhook_url else {
+                return;
+            };
+            let body = json!({
+                "alert": name,
+                "value": value,
+                "band": [lower, upper],
+                "direction": if *rising { "up" } else { "down" },
+            });
+            post_webhook(name, url, body);
+        }
+    }
+}
+
+/// Post the body of an alert to the webhook in the background.
+fn post_webhook(name: &str, url: &str, body: serde_json::Value) {
+    let url = url.to_string();
+    let name = name.to_string();
+    tokio::spawn(async move {
+        let posted = tokio::time::timeout(WEBHOOK_TIMEOUT, hooks::post_json(&url, &body)).await;
+        match posted {
+            Ok(Ok(status)) if (200..300).contains(&status) => {
+                println!("Alert {} posted to the webhook", name)
+            }
+            Ok(Ok(status)) => eprintln!("Webhook of alert {} returned {}", name, status),
+            Ok(Err(e)) => eprintln!("Webhook of alert {} failed: {}", name, e),
+            Err(_) => eprintln!("Webhook of alert {} timed out", name),
+        }
+    });
+}
+
+/// Value of a field. NaN never violates a threshold.
+fn field_value(field: AlertField, measurement: &Measurement, thi: f64) -> f64 {
+    match field {
+        AlertField::TemperatureC => measurement.temperature_c,
+        AlertField::HumidityRelative => measurement.humidity_relative,
+        AlertField::PressurePa => measurement.pressure_pa,
+        AlertField::Thi => thi,
+    }
+}
+
+/// Whether a value is beyond the thresholds of a rule.
+fn is_violated(rule: &AlertRuleConfig, value: f64) -> bool {
+    rule.above.is_some_and(|above| value > above) || rule.below.is_some_and(|below| value < below)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn plan() -> EscalationPlan {
+        EscalationPlan {
+            log_after: 5 * MINUTE,
+            webhook_after: Some(15 * MINUTE),
+        }
+    }
+
+    fn config() -> AlertsConfig {
+        AlertsConfig {
+            rules: vec![
+                AlertRuleConfig {
+                    name: "HOT".to_string(),
+                    kind: AlertRuleKind::Threshold,
+                    field: AlertField::TemperatureC,
+                    above: Some(30.0),
+                    below: None,
+                    band: None,
+                    message: None,
+                    always_override: false,
+                },
+                AlertRuleConfig {
+                    name: "DRY".to_string(),
+                    kind: AlertRuleKind::Threshold,
+                    field: AlertField::HumidityRelative,
+                    above: None,
+                    below: Some(30.0),
+                    band: None,
+                    message: None,
+                    always_override: false,
+                },
+            ],
+            escalation: EscalationConfig {
+                webhook_url: Some("http://127.0.0.1:9/alert".to_string()),
+                ..EscalationConfig::default()
+            },
+            history: false,
+        }
+    }
+
+    /// Bands every 2 C, settling after 5 minutes, without smoothing.
+    fn bands() -> BandChangeConfig {
+        BandChangeConfig {
+            width: 2.0,
+            settle_secs: 300,
+            smoothing_secs: 0,
+            ..BandChangeConfig::default()
+        }
+    }
+
+    /// Feed one reading per minute.
+    fn feed(tracker: &mut BandTracker, start: Instant, values: &[f64]) -> Vec<(u32, BandChange)> {
+        values
+            .iter()
+            .enumerate()
+            .filter_map(|(minute, &value)| {
+                let minute = minute as u32;
+                tracker
+                    .update(value, start + minute * MINUTE)
+                    .map(|change| (minute, change))
+            })
+            .collect()
+    }
+
+    fn measurement(temperature_c: f64, humidity_relative: f64) -> Measurement {
+        Measurement {
+            temperature_c,
+            pressure_pa: 101325.0,
+            humidity_relative,
+        }
+    }
+
+    #[test]
+    fn test_stages_fire_once_in_order() {
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        let mut fired = Vec::new();
+        // One update per minute for 20 minutes
+        for minute in 0..20 {
+            let stages = escalation.update(&plan(), true, start + minute * MINUTE);
+            fired.extend(stages.into_iter().map(|stage| (minute, stage)));
+        }
+        assert_eq!(
+            fired,
+            vec![(0, Stage::Display), (5, Stage::Log), (15, Stage::Webhook)]
+        );
+    }
+
+    #[test]
+    fn test_late_update_fires_skipped_stages_together() {
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        assert_eq!(
+            escalation.update(&plan(), true, start),
+            vec![Stage::Display]
+        );
+        assert_eq!(
+            escalation.update(&plan(), true, start + 20 * MINUTE),
+            vec![Stage::Log, Stage::Webhook]
+        );
+        assert!(
+            escalation
+                .update(&plan(), true, start + 30 * MINUTE)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_clear_resets_escalation() {
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        escalation.update(&plan(), true, start);
+        escalation.update(&plan(), true, start + 6 * MINUTE);
+        assert!(
+            escalation
+                .update(&plan(), false, start + 7 * MINUTE)
+                .is_empty()
+        );
+        assert!(!escalation.is_active());
+
+        // Raised again, the delays count from the new start
+        let again = start + 8 * MINUTE;
+        assert_eq!(
+            escalation.update(&plan(), true, again),
+            vec![Stage::Display]
+        );
+        assert!(
+            escalation
+                .update(&plan(), true, again + 4 * MINUTE)
+                .is_empty()
+        );
+        assert_eq!(
+            escalation.update(&plan(), true, again + 5 * MINUTE),
+            vec![Stage::Log]
+        );
+    }
+
+    #[test]
+    fn test_webhook_stage_needs_url() {
+        let plan = EscalationPlan::from_config(&EscalationConfig::default());
+        assert_eq!(plan.webhook_after, None);
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        escalation.update(&plan, true, start);
+        assert_eq!(
+            escalation.update(&plan, true, start + 60 * MINUTE),
+            vec![Stage::Log]
+        );
+    }
+
+    #[test]
+    fn test_engine_transitions_and_mask() {
+        let mut engine = AlertEngine::from_config(&config());
+        let start = Instant::now();
+        assert!(
+            engine
+                .update(&measurement(25.0, 50.0), 70.0, start)
+                .is_empty()
+        );
+        assert_eq!(engine.active_mask(), 0);
+        assert_eq!(engine.display_text(), None);
+
+        let transitions = engine.update(&measurement(31.0, 20.0), 80.0, start + MINUTE);
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(engine.active_mask(), 0b11);
+        assert_eq!(engine.display_text(), Some("HOT"));
+
+        let transitions = engine.update(&measurement(31.5, 40.0), 80.0, start + 6 * MINUTE);
+        assert_eq!(
+            transitions,
+            vec![
+                AlertTransition::Escalated {
+                    name: "HOT".to_string(),
+                    stage: Stage::Log,
+                    value: 31.5,
+                    elapsed: 5 * MINUTE,
+                },
+                AlertTransition::Cleared {
+                    name: "DRY".to_string(),
+                    field: AlertField::HumidityRelative,
+                    direction: Direction::Below,
+                    peak: 20.0,
+                    duration: 5 * MINUTE,
+                },
+            ]
+        );
+        assert_eq!(engine.active_mask(), 0b01);
+    }
+
+    #[test]
+    fn test_cleared_alert_is_one_episode() {
+        let mut engine = AlertEngine::from_config(&config());
+        let start = Instant::now();
+        let ended_at = Local::now();
+        let mut episodes = Vec::new();
+        // Raised at minute 1, peaking at minute 3, cleared at minute 5
+        for (minute, temperature_c) in [25.0, 30.5, 31.0, 32.5, 31.5, 29.0].into_iter().enumerate()
+        {
+            let transitions = engine.update(
+                &measurement(temperature_c, 50.0),
+                70.0,
+                start + minute as u32 * MINUTE,
+            );
+            episodes.extend(
+                transitions
+                    .iter()
+                    .filter_map(|transition| AlertEpisode::from_transition(transition, ended_at)),
+            );
+        }
+        assert_eq!(
+            episodes,
+            vec![AlertEpisode {
+                name: "HOT".to_string(),
+                field: AlertField::TemperatureC,
+                direction: Direction::Above,
+                started_at: ended_at - chrono::Duration::minutes(4),
+                ended_at,
+                peak: 32.5,
+            }]
+        );
+        // Raised again, the peak starts over
+        engine.update(&measurement(30.5, 50.0), 70.0, start + 6 * MINUTE);
+        let transitions = engine.update(&measurement(25.0, 50.0), 70.0, start + 7 * MINUTE);
+        assert!(matches!(
+            transitions[..],
+            [AlertTransition::Cleared { peak: 30.5, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_render_message() {
+        let message = render_message("HIGH HUMIDITY {value:0}% - CHECK VENTILATION", "WET", 85.4);
+        assert_eq!(message, "HIGH HUMIDITY 85% - CHECK VENTILATION");
+        assert_eq!(render_message("{name} {value}", "HOT", 31.24), "HOT 31.2");
+        assert_eq!(render_message("{value:2}C", "HOT", 31.0), "31.00C");
+        // Unknown and unclosed placeholders are kept
+        assert_eq!(
+            render_message("{value:9} {char:1} {value", "HOT", 31.0),
+            "{value:9} {char:1} {value"
+        );
+    }
+
+    #[test]
+    fn test_engine_display_message() {
+        let mut config = config();
+        config.rules[1].message = Some("HUMIDITY {value:0}% - USE THE HUMIDIFIER".to_string());
+        let mut engine = AlertEngine::from_config(&config);
+        let start = Instant::now();
+
+        engine.update(&measurement(25.0, 20.0), 70.0, start);
+        assert_eq!(
+            engine.display_message().as_deref(),
+            Some("HUMIDITY 20% - USE THE HUMIDIFIER")
+        );
+        engine.update(&measurement(25.0, 18.0), 70.0, start + MINUTE);
+        assert_eq!(
+            engine.display_message().as_deref(),
+            Some("HUMIDITY 18% - USE THE HUMIDIFIER")
+        );
+        // HOT comes first and has no message
+        engine.update(&measurement(31.0, 18.0), 80.0, start + 2 * MINUTE);
+        assert_eq!(engine.display_text(), Some("HOT"));
+        assert_eq!(engine.display_message(), None);
+        engine.update(&measurement(25.0, 50.0), 70.0, start + 3 * MINUTE);
+        assert_eq!(engine.display_message(), None);
+    }
+
+    #[test]
+    fn test_engine_overrides_lease() {
+        let mut config = config();
+        config.rules[0].always_override = true;
+        let mut engine = AlertEngine::from_config(&config);
+        let start = Instant::now();
+
+        engine.update(&measurement(25.0, 20.0), 70.0, start);
+        assert!(!engine.overrides_lease());
+        engine.update(&measurement(31.0, 20.0), 80.0, start + MINUTE);
+        assert!(engine.overrides_lease());
+        engine.update(&measurement(25.0, 20.0), 70.0, start + 2 * MINUTE);
+        assert!(!engine.overrides_lease());
+    }
+
+    #[test]
+    fn test_missing_value_does_not_alert() {
+        let mut engine = AlertEngine::from_config(&config());
+        engine.update(&measurement(f64::NAN, f64::NAN), f64::NAN, Instant::now());
+        assert_eq!(engine.active_mask(), 0);
+    }
+
+    #[test]
+    fn test_band_change_after_settle_time() {
+        let mut tracker = BandTracker::new(&bands());
+        let start = Instant::now();
+        // The first band is settled into without a notification
+        let mut values = vec![23.0; 6];
+        // Into 24-26 at minute 6, notified 5 minutes later
+        values.extend([25.0; 6]);
+        let changes = feed(&mut tracker, start, &values);
+        assert_eq!(
+            changes,
+            vec![(
+                11,
+                BandChange {
+                    from: 11,
+                    to: 12,
+                    value: 25.0
+                }
+            )]
+        );
+        assert_eq!(tracker.bounds(12), (24.0, 26.0));
+    }
+
+    #[test]
+    fn test_band_change_needs_settling() {
+        let mut tracker = BandTracker::new(&bands());
+        let start = Instant::now();
+        // Brief excursions into the next band restart the settle timer
+        let values = [
+            23.0, 23.0, 23.0, 23.0, 23.0, 23.0, 25.0, 25.0, 25.0, 23.0, 25.0, 25.0, 25.0, 25.0,
+            23.0, 23.0,
+        ];
+        assert!(feed(&mut tracker, start, &values).is_empty());
+    }
+
+    #[test]
+    fn test_band_boundary_hysteresis() {
+        let mut tracker = BandTracker::new(&bands());
+        let start = Instant::now();
+        // Settled in 22-24, then hovering just past 24 within the 0.4 margin
+        let mut values = vec![23.5; 6];
+        values.extend([24.1, 23.9, 24.3, 24.2, 24.1, 24.3, 24.2, 24.3]);
+        assert!(feed(&mut tracker, start, &values).is_empty());
+
+        // Past the margin the new band is taken, and then held the same way
+        let mut tracker = BandTracker::new(&bands());
+        let mut values = vec![23.5; 6];
+        values.extend([24.5; 6]);
+        values.extend([23.7, 23.8, 23.7, 23.9, 23.7, 23.8]);
+        let changes = feed(&mut tracker, start, &values);
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].1.from, changes[0].1.to), (11, 12));
+    }
+
+    #[test]
+    fn test_band_smoothing() {
+        let mut tracker = BandTracker::new(&BandChangeConfig {
+            smoothing_secs: 120,
+            ..bands()
+        });
+        let start = Instant::now();
+        // A one minute spike is damped below the next band
+        let mut values = vec![23.0; 6];
+        values.push(30.0);
+        values.extend([23.0; 10]);
+        assert!(feed(&mut tracker, start, &values).is_empty());
+    }
+
+    #[test]
+    fn test_engine_band_change() {
+        let mut config = config();
+        config.rules.push(AlertRuleConfig {
+            name: "TEMP".to_string(),
+            kind: AlertRuleKind::BandChange,
+            field: AlertField::TemperatureC,
+            above: None,
+            below: None,
+            band: Some(bands()),
+            message: None,
+            always_override: false,
+        });
+        let mut engine = AlertEngine::from_config(&config);
+        let start = Instant::now();
+        for minute in 0..6 {
+            assert!(
+                engine
+                    .update(&measurement(21.0, 50.0), 70.0, start + minute * MINUTE)
+                    .is_empty()
+            );
+        }
+        let mut transitions = Vec::new();
+        for minute in 6..12 {
+            transitions.extend(engine.update(
+                &measurement(19.0, 50.0),
+                70.0,
+                start + minute * MINUTE,
+            ));
+        }
+        assert_eq!(
+            transitions,
+            vec![AlertTransition::BandChanged {
+                name: "TEMP".to_string(),
+                value: 19.0,
+                lower: 18.0,
+                upper: 20.0,
+                rising: false,
+            }]
+        );
+        // Never an active alert
+        assert_eq!(engine.active_mask(), 0);
+        assert_eq!(engine.display_text(), None);
+    }
+}
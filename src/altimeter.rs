@@ -0,0 +1,230 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! QNH setting of the altimeter check page.
+//!
+//! With a QNH from the config or from `POST /api/qnh`, the altimeter page
+//! shows the altitude an altimeter set to that QNH would indicate at the
+//! measured station pressure. A QNH set over HTTP is kept in the state file,
+//! a small JSON file of runtime state which survives restarts, and takes
+//! precedence over the config. The page flags the QNH as stale once it is
+//! older than `stale_after_hours`, as the weather has moved on by then.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+use crate::config::{self, AltimeterConfig};
+use crate::state;
+
+/// A QNH and when it was set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QnhSetting {
+    pub hpa: f64,
+    pub set_at: DateTime<Local>,
+}
+
+/// QNH as shown on the altimeter page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QnhStatus {
+    pub hpa: f64,
+    /// Time since it was set.
+    pub age: Duration,
+    /// Whether it is older than the staleness limit.
+    pub stale: bool,
+}
+
+/// QNH shared by the measurement loop and the HTTP API.
+#[derive(Debug)]
+pub struct QnhStore {
+    setting: RwLock<Option<QnhSetting>>,
+    state_file: PathBuf,
+    stale_after: Duration,
+}
+
+impl QnhStore {
+    /// Load the QNH from the state file, or take the one from the config.
+    /// An unreadable state file is logged and ignored.
+    /// # Arguments
+    /// * `config` - Altimeter configuration.
+    /// * `now` - Current time, when a QNH from the config counts as set.
+    pub fn load(config: &AltimeterConfig, now: DateTime<Local>) -> Self {
+        let state_file = PathBuf::from(&config.state_file);
+        let saved = read_state(&state_file).unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to read the QNH from {}: {}",
+                state_file.display(),
+                e
+            );
+            None
+        });
+        let setting = saved.or(config.qnh_hpa.map(|hpa| QnhSetting { hpa, set_at: now }));
+        Self {
+            setting: RwLock::new(setting),
+            state_file,
+            stale_after: config.stale_after(),
+        }
+    }
+
+    /// Set a new QNH and keep it in the state file.
+    /// # Arguments
+    /// * `hpa` - QNH in hPa.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The new setting.
+    /// * `Err(e)` if the state file could not be written, the QNH is not
+    ///   applied then.
+    pub fn set(&self, hpa: f64, now: DateTime<Local>) -> Result<QnhSetting, Box<dyn Error>> {
+        config::validate_qnh(hpa)?;
+        let setting = QnhSetting { hpa, set_at: now };
+        write_state(&self.state_file, &setting)?;
+        *self.setting.write().unwrap_or_else(|e| e.into_inner()) = Some(setting);
+        Ok(setting)
+    }
+
+    /// Current setting, `None` until a QNH is set.
+    pub fn setting(&self) -> Option<QnhSetting> {
+        *self.setting.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Current QNH with its age.
+    /// # Arguments
+    /// * `now` - Current time.
+    pub fn status(&self, now: DateTime<Local>) -> Option<QnhStatus> {
+        self.setting().map(|setting| {
+            // A clock stepped back makes it look new rather than negative
+            let age = (now - setting.set_at).to_std().unwrap_or_default();
+            QnhStatus {
+                hpa: setting.hpa,
+                age,
+                stale: age > self.stale_after,
+            }
+        })
+    }
+}
+
+/// Read the QNH of the state file.
+/// # Returns
+/// * `Ok(None)` if there is no state file or it holds no QNH.
+fn read_state(path: &Path) -> Result<Option<QnhSetting>, Box<dyn Error>> {
+    let Some(state) = state::read(path)? else {
+        return Ok(None);
+    };
+    let (Some(hpa), Some(set_at)) = (
+        state.get("qnh_hpa").and_then(|v| v.as_f64()),
+        state.get("qnh_set_at").and_then(|v| v.as_str()),
+    ) else {
+        return Ok(None);
+    };
+    config::validate_qnh(hpa)?;
+    let set_at = DateTime::parse_from_rfc3339(set_at)?.with_timezone(&Local);
+    Ok(Some(QnhSetting { hpa, set_at }))
+}
+
+/// Write the QNH to the state file.
+fn write_state(path: &Path, setting: &QnhSetting) -> Result<(), Box<dyn Error>> {
+    state::write(
+        path,
+        [
+            ("qnh_hpa", setting.hpa.into()),
+            ("qnh_set_at", setting.set_at.to_rfc3339().into()),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+
+    fn config(name: &str) -> AltimeterConfig {
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        AltimeterConfig {
+            enabled: true,
+            qnh_hpa: Some(1013.0),
+            state_file: path.display().to_string(),
+            ..AltimeterConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_qnh_persists_in_state_file() {
+        let config = config("qnh-state");
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 8, 0, 0).unwrap();
+        let store = QnhStore::load(&config, start);
+        assert_eq!(store.setting().unwrap().hpa, 1013.0);
+
+        let set_at = start + chrono::Duration::hours(1);
+        store.set(1018.0, set_at).unwrap();
+        assert!(store.set(10.0, set_at).is_err());
+        assert_eq!(store.setting().unwrap().hpa, 1018.0);
+
+        // Taken over the config after a restart
+        let restarted = QnhStore::load(&config, start + chrono::Duration::hours(2));
+        let setting = restarted.setting().unwrap();
+        fs::remove_file(&config.state_file).unwrap();
+        assert_eq!(setting.hpa, 1018.0);
+        assert_eq!(setting.set_at, set_at);
+    }
+
+    #[test]
+    fn test_qnh_goes_stale() {
+        let config = config("qnh-stale");
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 8, 0, 0).unwrap();
+        let store = QnhStore::load(&config, start);
+
+        let status = store.status(start + chrono::Duration::hours(3)).unwrap();
+        assert_eq!(status.age, Duration::from_secs(3 * 3600));
+        assert!(!status.stale);
+        assert!(
+            store
+                .status(start + chrono::Duration::hours(13))
+                .unwrap()
+                .stale
+        );
+        // Clock stepped back
+        let status = store.status(start - chrono::Duration::hours(1)).unwrap();
+        assert_eq!(status.age, Duration::ZERO);
+
+        let without = AltimeterConfig {
+            qnh_hpa: None,
+            ..config
+        };
+        assert_eq!(QnhStore::load(&without, start).status(start), None);
+    }
+
+    #[test]
+    fn test_invalid_state_file_falls_back_to_config() {
+        let config = config("qnh-invalid");
+        fs::write(&config.state_file, "not json").unwrap();
+
+        let store = QnhStore::load(&config, Local::now());
+
+        fs::remove_file(&config.state_file).unwrap();
+        assert_eq!(store.setting().unwrap().hpa, 1013.0);
+    }
+}
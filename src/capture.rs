@@ -0,0 +1,324 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! On-demand burst capture.
+//!
+//! A capture runs the measurement loop at a shorter period for a limited
+//! time and tags the stored rows with its id. Its start and end are stored
+//! in the `events` table, the end with the number of stored and skipped
+//! rows. Only one capture runs at a time. Rows are skipped instead of queued
+//! while the write queue is backed up, so a capture cannot outgrow it.
+
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Period of the measurement loop outside captures.
+pub const NORMAL_RATE_MS: u64 = 200;
+
+/// Shortest capture period. A BME280 measurement with oversampling takes
+/// tens of milliseconds.
+pub const MIN_RATE_MS: u64 = 50;
+
+/// Longest capture.
+pub const MAX_DURATION_SECS: u64 = 3600;
+
+/// Capture rows are skipped while more rows wait in the write queue.
+pub const MAX_QUEUED_ROWS: usize = 1000;
+
+/// Requested capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRequest {
+    /// Measurement period in milliseconds.
+    pub rate_ms: u64,
+    /// Length of the capture in seconds.
+    pub duration_secs: u64,
+}
+
+impl CaptureRequest {
+    /// Check the request.
+    /// # Returns
+    /// * `Err(message)` if the period or duration is out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_RATE_MS..=NORMAL_RATE_MS).contains(&self.rate_ms) {
+            return Err(format!(
+                "rate_ms must be {}-{}, got {}",
+                MIN_RATE_MS, NORMAL_RATE_MS, self.rate_ms
+            ));
+        }
+        if !(1..=MAX_DURATION_SECS).contains(&self.duration_secs) {
+            return Err(format!(
+                "duration_secs must be 1-{}, got {}",
+                MAX_DURATION_SECS, self.duration_secs
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// State of a running capture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaptureStatus {
+    /// Id stored in the `capture_id` column, the start time in Unix
+    /// milliseconds.
+    pub capture_id: i64,
+    pub rate_ms: u64,
+    pub duration_secs: u64,
+    pub started_at: String,
+    /// Rows queued for storage.
+    pub stored: u64,
+    /// Rows skipped because the write queue was full.
+    pub skipped: u64,
+}
+
+/// Capture run by the measurement loop.
+#[derive(Debug)]
+pub struct Capture {
+    pub status: CaptureStatus,
+    until: Instant,
+}
+
+impl Capture {
+    /// Start a capture.
+    /// # Arguments
+    /// * `request` - Validated request.
+    /// * `now` - Current time.
+    /// * `instant` - Current monotonic time.
+    pub fn start(request: CaptureRequest, now: DateTime<Local>, instant: Instant) -> Self {
+        Self {
+            status: CaptureStatus {
+                capture_id: now.timestamp_millis(),
+                rate_ms: request.rate_ms,
+                duration_secs: request.duration_secs,
+                started_at: now.to_rfc3339(),
+                stored: 0,
+                skipped: 0,
+            },
+            until: instant + Duration::from_secs(request.duration_secs),
+        }
+    }
+
+    /// Measurement period during the capture.
+    pub fn rate(&self) -> Duration {
+        Duration::from_millis(self.status.rate_ms)
+    }
+
+    /// Whether the capture has run its duration.
+    pub fn is_over(&self, instant: Instant) -> bool {
+        instant >= self.until
+    }
+
+    /// Decide whether a row is stored, given the write queue length.
+    /// # Arguments
+    /// * `queued` - Rows waiting in the write queue.
+    /// # Returns
+    /// * The capture id to tag the row with, `None` to skip the row.
+    pub fn admit(&mut self, queued: usize) -> Option<i64> {
+        if queued >= MAX_QUEUED_ROWS {
+            self.status.skipped += 1;
+            return None;
+        }
+        self.status.stored += 1;
+        Some(self.status.capture_id)
+    }
+
+    /// Event data of the capture.
+    pub fn event_detail(&self) -> serde_json::Value {
+        json!(self.status)
+    }
+}
+
+/// Capture slot shared by the HTTP API and the measurement loop.
+#[derive(Debug, Default)]
+pub struct CaptureControl {
+    slot: Mutex<Slot>,
+}
+
+#[derive(Debug, Default)]
+enum Slot {
+    #[default]
+    Idle,
+    Requested(CaptureRequest),
+    Active(CaptureStatus),
+}
+
+impl CaptureControl {
+    /// Request a capture.
+    /// # Arguments
+    /// * `request` - Capture to run.
+    /// # Returns
+    /// * `Err(message)` if a capture is already requested or running.
+    pub fn request(&self, request: CaptureRequest) -> Result<(), String> {
+        let mut slot = self.lock();
+        match *slot {
+            Slot::Idle => {
+                *slot = Slot::Requested(request);
+                Ok(())
+            }
+            Slot::Requested(_) | Slot::Active(_) => {
+                Err("A capture is already active, only one may run at a time".to_string())
+            }
+        }
+    }
+
+    /// Start the requested capture, if any.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * The capture to run.
+    pub fn start(&self, now: DateTime<Local>, instant: Instant) -> Option<Capture> {
+        let mut slot = self.lock();
+        let Slot::Requested(request) = *slot else {
+            return None;
+        };
+        let capture = Capture::start(request, now, instant);
+        *slot = Slot::Active(capture.status.clone());
+        Some(capture)
+    }
+
+    /// Publish the counters of the running capture.
+    pub fn publish(&self, capture: &Capture) {
+        *self.lock() = Slot::Active(capture.status.clone());
+    }
+
+    /// Free the slot once the capture has ended.
+    pub fn finish(&self) {
+        *self.lock() = Slot::Idle;
+    }
+
+    /// Running capture, `None` when idle or not started yet.
+    pub fn status(&self) -> Option<CaptureStatus> {
+        match &*self.lock() {
+            Slot::Active(status) => Some(status.clone()),
+            Slot::Idle | Slot::Requested(_) => None,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Slot> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Ask a running daemon to start a capture over its HTTP API.
+/// # Arguments
+/// * `listen` - Listen address of the API, e.g. "127.0.0.1:8080".
+/// * `request` - Capture to run.
+/// # Returns
+/// * The response body on success.
+pub async fn request_over_http(
+    listen: &str,
+    request: CaptureRequest,
+) -> Result<String, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(listen)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", listen, e))?;
+    let http_request = format!(
+        "POST /api/capture?rate_ms={}&duration_secs={} HTTP/1.1\r\n\
+         Host: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        request.rate_ms, request.duration_secs, listen
+    );
+    stream.write_all(http_request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or("Invalid HTTP response")?;
+    if (200..300).contains(&status) {
+        Ok(body.to_string())
+    } else {
+        Err(format!("Capture rejected ({}): {}", status, body).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> CaptureRequest {
+        CaptureRequest {
+            rate_ms: 100,
+            duration_secs: 600,
+        }
+    }
+
+    #[test]
+    fn test_validate_request() {
+        assert!(request().validate().is_ok());
+        for (rate_ms, duration_secs) in [(10, 600), (1000, 600), (100, 0), (100, 7200)] {
+            let request = CaptureRequest {
+                rate_ms,
+                duration_secs,
+            };
+            assert!(request.validate().is_err(), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn test_only_one_capture_at_a_time() {
+        let control = CaptureControl::default();
+        let now = Local::now();
+        let instant = Instant::now();
+        assert!(control.start(now, instant).is_none());
+
+        control.request(request()).unwrap();
+        assert!(control.request(request()).is_err());
+        assert_eq!(control.status(), None);
+
+        let capture = control.start(now, instant).unwrap();
+        assert_eq!(capture.status.capture_id, now.timestamp_millis());
+        assert_eq!(capture.rate(), Duration::from_millis(100));
+        assert!(control.request(request()).is_err());
+        assert_eq!(control.status(), Some(capture.status.clone()));
+
+        control.finish();
+        assert!(control.request(request()).is_ok());
+    }
+
+    #[test]
+    fn test_capture_ends_after_duration() {
+        let instant = Instant::now();
+        let capture = Capture::start(request(), Local::now(), instant);
+        assert!(!capture.is_over(instant + Duration::from_secs(599)));
+        assert!(capture.is_over(instant + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn test_admit_respects_queue_limit() {
+        let mut capture = Capture::start(request(), Local::now(), Instant::now());
+        let id = capture.status.capture_id;
+        assert_eq!(capture.admit(0), Some(id));
+        assert_eq!(capture.admit(MAX_QUEUED_ROWS - 1), Some(id));
+        assert_eq!(capture.admit(MAX_QUEUED_ROWS), None);
+        assert_eq!((capture.status.stored, capture.status.skipped), (2, 1));
+        assert_eq!(capture.event_detail()["skipped"], 1);
+    }
+}
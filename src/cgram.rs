@@ -0,0 +1,153 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Allocation of the CGRAM slots of the character displays.
+//!
+//! The controllers hold 8 custom characters, and the slot of a character is
+//! its code in the display text. The configured custom characters keep the
+//! slot they are defined at, since the text refers to them as `{char:N}`.
+//! Built-in glyphs are placed around them: in a slot already holding the
+//! same bitmap, else in their usual slot if it is free, else in the lowest
+//! free slot.
+
+/// Number of custom characters the display controllers hold.
+pub const SLOTS: usize = 8;
+
+/// Backslash dot of the activity indicator, missing from the character ROM.
+pub const BACKSLASH: [u8; 8] = [
+    0b00000,
+    0b10000,
+    0b01000,
+    0b00100,
+    0b00010,
+    0b00001,
+    0b00000,
+    0b00000,
+];
+
+/// Slot of the activity indicator glyph unless a custom character holds it.
+const INDICATOR_SLOT: u8 = 1;
+
+/// Characters to register in CGRAM and the slots of the built-in glyphs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgramSlots {
+    chars: Vec<(u8, [u8; 8])>,
+    indicator: u8,
+}
+
+impl CgramSlots {
+    /// Place the built-in glyphs next to the custom characters.
+    /// # Arguments
+    /// * `custom_chars` - `(index, bitmap)` pairs with distinct indexes below `SLOTS`.
+    /// # Returns
+    /// * `Err(message)` if there is no slot left for a built-in glyph.
+    pub fn allocate(custom_chars: Vec<(u8, [u8; 8])>) -> Result<Self, String> {
+        let mut chars = custom_chars;
+        let indicator = place(&mut chars, BACKSLASH, INDICATOR_SLOT).ok_or_else(|| {
+            format!(
+                "display.custom_chars fills all {} CGRAM slots, one is needed for the activity indicator",
+                SLOTS
+            )
+        })?;
+        Ok(Self { chars, indicator })
+    }
+
+    /// Characters to register, custom and built-in.
+    pub fn chars(&self) -> &[(u8, [u8; 8])] {
+        &self.chars
+    }
+
+    /// Slot of the backslash glyph of the activity indicator.
+    pub fn indicator(&self) -> u8 {
+        self.indicator
+    }
+}
+
+impl Default for CgramSlots {
+    /// Only the built-in glyphs.
+    fn default() -> Self {
+        Self {
+            chars: vec![(INDICATOR_SLOT, BACKSLASH)],
+            indicator: INDICATOR_SLOT,
+        }
+    }
+}
+
+/// Find or take a slot for a glyph.
+/// # Arguments
+/// * `chars` - Characters placed so far, the glyph is added if it takes a new slot.
+/// * `bitmap` - Glyph.
+/// * `preferred` - Slot to take if it is free.
+/// # Returns
+/// * `None` if every slot holds another character.
+fn place(chars: &mut Vec<(u8, [u8; 8])>, bitmap: [u8; 8], preferred: u8) -> Option<u8> {
+    if let Some((index, _)) = chars.iter().find(|(_, data)| *data == bitmap) {
+        return Some(*index);
+    }
+    let is_free = |slot: &u8| chars.iter().all(|(index, _)| index != slot);
+    let slot = std::iter::once(preferred)
+        .chain(0..SLOTS as u8)
+        .find(is_free)?;
+    chars.push((slot, bitmap));
+    Some(slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: [u8; 8] = [0x1f; 8];
+
+    #[test]
+    fn test_indicator_takes_its_slot() {
+        let slots = CgramSlots::allocate(Vec::new()).unwrap();
+        assert_eq!(slots, CgramSlots::default());
+
+        let slots = CgramSlots::allocate(vec![(0, BLOCK)]).unwrap();
+        assert_eq!(slots.indicator(), 1);
+        assert_eq!(slots.chars(), &[(0, BLOCK), (1, BACKSLASH)]);
+    }
+
+    #[test]
+    fn test_indicator_shares_identical_glyph() {
+        let slots = CgramSlots::allocate(vec![(5, BACKSLASH)]).unwrap();
+        assert_eq!(slots.indicator(), 5);
+        assert_eq!(slots.chars(), &[(5, BACKSLASH)]);
+    }
+
+    #[test]
+    fn test_indicator_moves_around_custom_chars() {
+        let slots = CgramSlots::allocate(vec![(1, BLOCK), (0, BLOCK)]).unwrap();
+        assert_eq!(slots.indicator(), 2);
+        assert_eq!(slots.chars().len(), 3);
+
+        // Seven custom characters leave one slot
+        let chars: Vec<_> = (0..8).filter(|&i| i != 6).map(|i| (i, BLOCK)).collect();
+        assert_eq!(CgramSlots::allocate(chars).unwrap().indicator(), 6);
+    }
+
+    #[test]
+    fn test_full_cgram_is_rejected() {
+        let chars: Vec<_> = (0..8).map(|i| (i, BLOCK)).collect();
+        let message = CgramSlots::allocate(chars).unwrap_err();
+        assert!(message.contains("activity indicator"));
+    }
+}
@@ -19,88 +19,3682 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use chrono_tz::Tz;
+use peripheral::bme280::{BME280_ADDR, BME280_ADDR2, Bme280Settings, Measurement};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
+
+use crate::cgram::{self, CgramSlots};
+use crate::config_file;
+use crate::database::DEFAULT_SENSOR_LABEL;
+use crate::error::ConfigError;
+use crate::helper::units::NumberLocale;
+use crate::helper::{
+    MAX_DECIMALS, MeasurementFormat, MeasurementLayout, RoundingMode, THI_DISPLAY_LIMITS,
+    ThiCoefficients,
+};
+use crate::sensor::MIN_TRIMMED_SAMPLES;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
 pub struct Config {
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub hardware: HardwareConfig,
+    #[serde(default)]
+    pub sensor: SensorConfig,
+    #[serde(default)]
+    pub sensors: SensorsConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub screensaver: ScreensaverConfig,
+    #[serde(default)]
+    pub quality: QualityConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub comfort: ComfortConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub disk: DiskConfig,
+    #[serde(default)]
+    pub power: PowerConfig,
+    #[serde(default)]
+    pub peers: PeersConfig,
+    #[serde(default)]
+    pub subsystems: SubsystemsConfig,
+    #[serde(default)]
+    pub altimeter: AltimeterConfig,
+    #[serde(default)]
+    pub extremes: ExtremesConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub daily_metrics: DailyMetricsConfig,
+    #[serde(default)]
+    pub trace: TraceConfig,
+    #[serde(default)]
+    pub safe_mode: SafeModeConfig,
+    /// Faults of the simulated sensor, for resilience tests. Deliberately
+    /// left out of the documentation and the schema.
+    #[serde(default)]
+    #[cfg_attr(feature = "config-schema", schemars(skip))]
+    pub chaos: ChaosConfig,
+    /// Auto-dimming by ambient light, off without the section.
+    pub light_sensor: Option<LightSensorConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
 pub struct DatabaseConfig {
     pub url: String,
+    /// Which time is stored in the `timestamp` column.
+    #[serde(default)]
+    pub timestamp_source: TimestampSource,
+    /// SQLite-only write tuning.
+    #[serde(default)]
+    pub sqlite: SqliteConfig,
+    /// Handling of rows stamped earlier than the previous row of the same
+    /// sensor, e.g. after the clock was stepped back.
+    #[serde(default)]
+    pub backward_timestamps: BackwardTimestamps,
+    /// Longest time one insert or commit may take before it is abandoned
+    /// (0 = no limit).
+    #[serde(default = "default_insert_timeout_secs")]
+    pub insert_timeout_secs: u64,
+    /// Plausibility check diverting rows to `sensor_data_quarantine`.
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    /// Store one row per sensor with the time-weighted mean of the readings
+    /// over this many seconds (0 = every reading). Capture rows are always
+    /// stored as read.
+    #[serde(default)]
+    pub save_window_secs: u64,
+    /// Close the save windows on the wall-clock multiples of their length
+    /// and stamp the rows with that time, keeping the time of the latest
+    /// reading in `measured_at`.
+    #[serde(default)]
+    pub align_to_interval: bool,
+    /// Decimals of the stored values (0-6). Full precision if not set.
+    pub decimals: Option<u8>,
+    /// Rounding of the stored values, with `decimals`.
+    #[serde(default)]
+    pub rounding: RoundingMode,
 }
 
-impl Default for Config {
+impl Default for DatabaseConfig {
     fn default() -> Self {
         Self {
-            database: DatabaseConfig {
-                url: "Not specified".to_string(),
+            url: String::new(),
+            timestamp_source: TimestampSource::default(),
+            sqlite: SqliteConfig::default(),
+            backward_timestamps: BackwardTimestamps::default(),
+            insert_timeout_secs: default_insert_timeout_secs(),
+            validation: ValidationConfig::default(),
+            save_window_secs: 0,
+            align_to_interval: false,
+            decimals: None,
+            rounding: RoundingMode::default(),
+        }
+    }
+}
+
+/// Most decimals of the stored values, finer than any sensor resolves.
+pub const MAX_STORED_DECIMALS: u8 = 6;
+
+fn default_insert_timeout_secs() -> u64 {
+    10
+}
+
+impl DatabaseConfig {
+    /// Limit of one insert or commit.
+    /// # Returns
+    /// * `None` if inserts may take any time.
+    pub fn insert_timeout(&self) -> Option<Duration> {
+        (self.insert_timeout_secs > 0).then(|| Duration::from_secs(self.insert_timeout_secs))
+    }
+
+    /// Time covered by one stored row.
+    /// # Returns
+    /// * `None` if every reading is stored.
+    pub fn save_window(&self) -> Option<Duration> {
+        (self.save_window_secs > 0).then(|| Duration::from_secs(self.save_window_secs))
+    }
+
+    /// Check that aligned rows have a window and keep their timestamp.
+    /// # Returns
+    /// * `Err(message)` if `align_to_interval` is set without a save window
+    ///   or with insertion timestamps.
+    pub fn validate_alignment(&self) -> Result<(), String> {
+        if !self.align_to_interval {
+            return Ok(());
+        }
+        if self.save_window_secs == 0 {
+            return Err("database.align_to_interval needs a save_window_secs".to_string());
+        }
+        if self.timestamp_source == TimestampSource::Insertion {
+            return Err(
+                "database.align_to_interval needs timestamp_source = \"measurement\"".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Rounding of the stored values.
+    /// # Returns
+    /// * Decimals and mode, `None` to keep full precision.
+    pub fn storage_rounding(&self) -> Option<(u8, RoundingMode)> {
+        self.decimals.map(|decimals| (decimals, self.rounding))
+    }
+
+    /// Check the decimals of the stored values.
+    /// # Returns
+    /// * `Err(message)` if more decimals are requested than are useful.
+    pub fn validate_decimals(&self) -> Result<(), String> {
+        match self.decimals {
+            Some(decimals) if decimals > MAX_STORED_DECIMALS => Err(format!(
+                "database.decimals must be at most {}, got {}",
+                MAX_STORED_DECIMALS, decimals
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Ranges of plausible values, `[min, max]`. Rows with a value outside, or
+/// not a number, are stored in `sensor_data_quarantine` with the reason
+/// instead of `sensor_data`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Check the rows. Every row goes to `sensor_data` when disabled.
+    pub enabled: bool,
+    pub temperature_c: [f64; 2],
+    pub humidity_relative: [f64; 2],
+    pub pressure_pa: [f64; 2],
+}
+
+impl Default for ValidationConfig {
+    /// The BME280 operating range.
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            temperature_c: [-40.0, 85.0],
+            humidity_relative: [0.0, 100.0],
+            pressure_pa: [30000.0, 110000.0],
+        }
+    }
+}
+
+impl ValidationConfig {
+    /// Check the ranges.
+    /// # Returns
+    /// * `Err(message)` naming the first invalid range.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_ranges(
+            "database.validation",
+            [
+                ("temperature_c", self.temperature_c),
+                ("humidity_relative", self.humidity_relative),
+                ("pressure_pa", self.pressure_pa),
+            ],
+        )
+    }
+}
+
+/// Check `[min, max]` ranges.
+/// # Arguments
+/// * `section` - Section of the ranges, for the message.
+/// * `ranges` - Names and ranges.
+/// # Returns
+/// * `Err(message)` naming the first invalid range.
+fn validate_ranges(section: &str, ranges: [(&str, [f64; 2]); 3]) -> Result<(), String> {
+    for (name, [min, max]) in ranges {
+        if !(min.is_finite() && max.is_finite() && min < max) {
+            return Err(format!(
+                "{}.{} must be [min, max] with min below max, got [{}, {}]",
+                section, name, min, max
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// SQLite write tuning, to reduce SD card wear.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SqliteConfig {
+    /// Group rows into one transaction committed at most this many seconds
+    /// apart (0 = commit every row). Rows not yet committed are lost on a
+    /// crash or power cut.
+    pub commit_interval_secs: u64,
+    /// Commit early once this many rows are pending.
+    pub batch_size: usize,
+    /// `PRAGMA synchronous` for the connection. SQLite's default is used if
+    /// not specified.
+    pub synchronous: Option<SqliteSynchronous>,
+}
+
+/// Value of SQLite's `PRAGMA synchronous`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+/// Source of the stored sample timestamp.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampSource {
+    /// Time the sensor reading was taken, captured as soon as the
+    /// measurement returns. Rows keep the real sampling time even when the
+    /// write queue is backed up.
+    #[default]
+    Measurement,
+    /// Time the row is written by the database task. This lags the reading
+    /// by however long the sample waited in the write queue.
+    Insertion,
+}
+
+/// Handling of a timestamp earlier than the previous one of the sensor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum BackwardTimestamps {
+    /// Store the row 1 ms after the previous one.
+    #[default]
+    Clamp,
+    /// Do not store the row.
+    Drop,
+    /// Keep the timestamps strictly increasing: a row stamped at or before
+    /// the previous one is stored 1 ms after the later of the previous row
+    /// and the time of insertion. The stored time is then no longer the
+    /// measurement time, but sorting by time matches the order of the rows.
+    Bump,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ClockConfig {
+    /// Times before this year are treated as "not set" (no RTC, NTP not synced yet).
+    pub min_valid_year: i32,
+    /// Skip database writes while the time is not set.
+    pub skip_db_when_unsynced: bool,
+    /// Handling of measurement ticks missed while a cycle overran.
+    pub missed_ticks: MissedTicks,
+    /// When the first measurement tick is due.
+    pub first_tick: FirstTick,
+    /// IANA time zone of the timestamps, e.g. "Asia/Tokyo", in place of the
+    /// system one. For images without tzdata, see the `timezone` module.
+    pub timezone: Option<String>,
+}
+
+/// When the first measurement tick is due after start-up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum FirstTick {
+    /// One interval after start-up, giving the display time to settle.
+    #[default]
+    AfterInterval,
+    /// Right away.
+    Immediate,
+}
+
+/// Handling of measurement ticks whose time passed while a cycle overran.
+/// Either way the ticks stay on the grid of the start time plus multiples
+/// of the interval, so the lag does not accumulate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MissedTicks {
+    /// Run once right away, then continue at the next tick of the grid.
+    #[default]
+    Skip,
+    /// Run every missed tick right away, one after the other.
+    CatchUp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// Display driver.
+    #[serde(rename = "type")]
+    pub driver: DisplayType,
+    /// I2C address. The driver's default address is used if not specified.
+    pub address: Option<u16>,
+    /// Columns and lines of the module.
+    pub size: DisplaySize,
+    /// Custom characters registered in CGRAM during display setup.
+    pub custom_chars: Vec<CustomCharConfig>,
+    /// Contrast level (SO1602A only).
+    pub contrast: u8,
+    /// Fade the contrast in from 0 over this many milliseconds at setup
+    /// (SO1602A only, 0 = off).
+    pub contrast_ramp_ms: u64,
+    /// How far past a rounding midpoint a value must move before the
+    /// displayed digit changes, as a fraction of the last digit (0 = off).
+    pub rounding_hysteresis: f64,
+    /// Decimals of the displayed temperature (0-2).
+    pub temperature_decimals: u8,
+    /// Decimals of the displayed humidity (0-2).
+    pub humidity_decimals: u8,
+    /// Rounding of the displayed values. The database keeps full precision
+    /// unless `[database] decimals` is set.
+    pub rounding: RoundingMode,
+    /// Retries of a failed display write in the loop before the display is
+    /// re-initialized.
+    pub write_retries: u32,
+    /// Lowest displayed THI. The database keeps the unclamped value.
+    pub thi_min: i16,
+    /// Highest displayed THI. The database keeps the unclamped value.
+    pub thi_max: i16,
+    /// Spacing of the measurement line of 16x2 displays.
+    pub layout: MeasurementLayout,
+    /// Warn at start-up when the decimals are finer than the sensor
+    /// resolves with the [sensor] settings.
+    pub precision_check: bool,
+    /// Wait after each command and data byte in microseconds, for modules
+    /// which lose bytes written back to back (SO1602A only, 0 = off).
+    pub write_delay_us: u64,
+}
+
+/// Longest accepted display write delay, in microseconds.
+/// The bus is held during the delays, so a frame must stay short.
+pub const MAX_WRITE_DELAY_US: u64 = 1000;
+
+/// I2C bus settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HardwareConfig {
+    /// Expected I2C clock speed in Hz. The kernel sets the speed
+    /// (`dtparam=i2c_arm_baudrate`), so a mismatch is reported at start-up
+    /// instead of applied.
+    pub i2c_speed_hz: Option<u32>,
+}
+
+/// Range of accepted I2C clock speeds, in Hz.
+pub const I2C_SPEED_RANGE_HZ: (u32, u32) = (10_000, 1_000_000);
+
+impl HardwareConfig {
+    /// Check the I2C clock speed.
+    /// # Returns
+    /// * `Err(message)` if the speed is out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = I2C_SPEED_RANGE_HZ;
+        match self.i2c_speed_hz {
+            Some(hz) if !(min..=max).contains(&hz) => Err(format!(
+                "hardware.i2c_speed_hz must be {}-{}, got {}",
+                min, max, hz
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compare the configured I2C clock speed with the bus.
+    /// # Arguments
+    /// * `actual_hz` - Clock speed reported by the bus.
+    /// # Returns
+    /// * `Some(message)` explaining how to change the speed if it differs.
+    pub fn speed_mismatch(&self, actual_hz: u32) -> Option<String> {
+        let expected = self.i2c_speed_hz?;
+        (expected != actual_hz).then(|| {
+            format!(
+                "the I2C bus runs at {} Hz, not the configured {} Hz. Set dtparam=i2c_arm_baudrate={} in /boot/firmware/config.txt and reboot.",
+                actual_hz, expected, expected
+            )
+        })
+    }
+}
+
+/// Custom character definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct CustomCharConfig {
+    /// CGRAM slot (0-7), referenced as `{char:N}` in display text.
+    pub index: u8,
+    /// 8 rows of 5 pixels, written as bits ("01000") or art (".#...").
+    pub rows: Vec<String>,
+}
+
+/// BME280 tuning, also adjustable at run time over HTTP.
+/// Comfort index formula.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ComfortFormula {
+    /// Standard temperature-humidity index.
+    #[default]
+    Thi,
+    /// Same form with the coefficients from the config.
+    Custom,
+}
+
+/// Comfort index used for the display and the stored `thi` column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ComfortConfig {
+    pub formula: ComfortFormula,
+    /// Coefficients of `a*T + b*H*(c*T - d) + e`, used by "custom" only.
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+}
+
+impl Default for ComfortConfig {
+    fn default() -> Self {
+        let standard = ThiCoefficients::STANDARD;
+        Self {
+            formula: ComfortFormula::default(),
+            a: standard.a,
+            b: standard.b,
+            c: standard.c,
+            d: standard.d,
+            e: standard.e,
+        }
+    }
+}
+
+impl ComfortConfig {
+    /// Coefficients of the selected formula.
+    pub fn coefficients(&self) -> ThiCoefficients {
+        match self.formula {
+            ComfortFormula::Thi => ThiCoefficients::STANDARD,
+            ComfortFormula::Custom => ThiCoefficients {
+                a: self.a,
+                b: self.b,
+                c: self.c,
+                d: self.d,
+                e: self.e,
             },
         }
     }
+
+    /// Check the custom coefficients.
+    /// # Returns
+    /// * `Err(message)` if a coefficient is not a finite number.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.formula == ComfortFormula::Thi {
+            return Ok(());
+        }
+        for (name, value) in [
+            ("a", self.a),
+            ("b", self.b),
+            ("c", self.c),
+            ("d", self.d),
+            ("e", self.e),
+        ] {
+            if !value.is_finite() {
+                return Err(format!(
+                    "comfort.{} must be a finite number, got {}",
+                    name, value
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Formula and coefficients for the log.
+    pub fn summary(&self) -> String {
+        let c = self.coefficients();
+        format!(
+            "{:?}: {}*T + {}*H*({}*T - {}) + {}",
+            self.formula, c.a, c.b, c.c, c.d, c.e
+        )
+    }
 }
 
-impl Config {
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        Ok(config)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SensorConfig {
+    /// Temperature oversampling (1, 2, 4, 8 or 16).
+    pub oversampling_temperature: u8,
+    /// Pressure oversampling (0 = skipped, 1, 2, 4, 8 or 16).
+    pub oversampling_pressure: u8,
+    /// Humidity oversampling (0 = skipped, 1, 2, 4, 8 or 16).
+    pub oversampling_humidity: u8,
+    /// IIR filter coefficient (0 = off, 2, 4, 8 or 16).
+    pub filter: u8,
+    /// Added to the measured temperature in °C.
+    pub temperature_offset_c: f64,
+    /// Added to the measured relative humidity in %.
+    pub humidity_offset: f64,
+    /// Added to the measured pressure in Pa.
+    pub pressure_offset_pa: f64,
+    /// Period of the measurement loop in milliseconds, outside captures
+    /// and safe mode.
+    pub interval_ms: u64,
+    /// Sea level reference pressure in Pa for the altitude of a reading.
+    pub sea_level_pa: f64,
+}
+
+/// Sensors logged to the database.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SensorsConfig {
+    /// Driver of the main sensor.
+    #[serde(rename = "type")]
+    pub driver: SensorType,
+    /// Label of the main BME280, stored in the `sensor` column.
+    pub label: String,
+    /// Profile of the main BME280. Without one its rows are checked against
+    /// [database.validation] and not compensated.
+    pub profile: Option<SensorProfile>,
+    /// Additional sensors, logged for comparison only.
+    pub extra: Vec<ExtraSensorConfig>,
+    /// Readings per measurement. 1 takes a single reading, 3 or more store
+    /// the mean of the readings without the lowest and highest.
+    pub samples: usize,
+    /// Find the main BME280 at 0x76 or 0x77 by its chip ID instead of
+    /// using 0x76. Extra sensors without an address get the other one.
+    pub auto_detect: bool,
+    /// Identical readings in a row after which a sensor is taken as stuck
+    /// and reset, 0 = never.
+    pub stuck_after: usize,
+    /// Plausible ranges and compensation of each profile.
+    pub profiles: SensorProfilesConfig,
+}
+
+/// Additional sensor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct ExtraSensorConfig {
+    /// Sensor driver.
+    #[serde(rename = "type", default)]
+    pub driver: SensorType,
+    /// I2C address. The driver's secondary address is used if not specified,
+    /// or with `auto_detect` the one the main sensor does not use.
+    pub address: Option<u16>,
+    /// Label stored in the `sensor` column.
+    pub label: String,
+    /// Profile of the sensor, as for the main one.
+    pub profile: Option<SensorProfile>,
+}
+
+/// Where a sensor is mounted. The profile selects its plausible ranges and
+/// self-heating compensation from [sensors.profiles].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SensorProfile {
+    Indoor,
+    Outdoor,
+    /// Anything else, with ranges of your own.
+    Custom,
+}
+
+/// Plausible ranges, `[min, max]`, and compensation of a sensor profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct ProfileConfig {
+    pub temperature_c: [f64; 2],
+    pub humidity_relative: [f64; 2],
+    pub pressure_pa: [f64; 2],
+    /// Degrees the sensor reads above the air, from its own heat and that of
+    /// the board next to it. Subtracted from the temperature, the humidity
+    /// is stored as measured.
+    #[serde(default)]
+    pub self_heating_c: f64,
+}
+
+/// Largest self-heating compensation accepted, in degrees.
+pub const MAX_SELF_HEATING_C: f64 = 10.0;
+
+impl ProfileConfig {
+    /// Plausible ranges of the profile, checked when [database.validation]
+    /// is enabled.
+    pub fn validation(&self) -> ValidationConfig {
+        ValidationConfig {
+            enabled: true,
+            temperature_c: self.temperature_c,
+            humidity_relative: self.humidity_relative,
+            pressure_pa: self.pressure_pa,
+        }
     }
+}
+
+/// Profiles of the sensors. A profile set here replaces the built-in one
+/// and needs every range.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SensorProfilesConfig {
+    pub indoor: ProfileConfig,
+    pub outdoor: ProfileConfig,
+    pub custom: ProfileConfig,
+}
 
-    pub fn load_or_default_with_status<P: AsRef<Path>>(path: P) -> (Self, bool) {
-        match Self::load_from_file(&path) {
-            Ok(config) => (config, true),
-            Err(_) => (Self::default(), false),
+impl Default for SensorProfilesConfig {
+    fn default() -> Self {
+        let pressure_pa = [30000.0, 110000.0];
+        Self {
+            indoor: ProfileConfig {
+                temperature_c: [0.0, 40.0],
+                humidity_relative: [0.0, 100.0],
+                pressure_pa,
+                self_heating_c: 0.0,
+            },
+            outdoor: ProfileConfig {
+                temperature_c: [-40.0, 60.0],
+                humidity_relative: [0.0, 100.0],
+                pressure_pa,
+                self_heating_c: 0.0,
+            },
+            // The BME280 operating range
+            custom: ProfileConfig {
+                temperature_c: [-40.0, 85.0],
+                humidity_relative: [0.0, 100.0],
+                pressure_pa,
+                self_heating_c: 0.0,
+            },
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl SensorProfilesConfig {
+    /// Settings of a profile.
+    pub fn get(&self, profile: SensorProfile) -> &ProfileConfig {
+        match profile {
+            SensorProfile::Indoor => &self.indoor,
+            SensorProfile::Outdoor => &self.outdoor,
+            SensorProfile::Custom => &self.custom,
+        }
+    }
 
-    #[test]
-    fn test_default_config() {
-        let config = Config::default();
-        assert_eq!(config.database.url, "Not specified");
+    /// Check the ranges and the compensation of every profile.
+    /// # Returns
+    /// * `Err(message)` naming the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, profile) in [
+            ("indoor", &self.indoor),
+            ("outdoor", &self.outdoor),
+            ("custom", &self.custom),
+        ] {
+            let section = format!("sensors.profiles.{}", name);
+            validate_ranges(
+                &section,
+                [
+                    ("temperature_c", profile.temperature_c),
+                    ("humidity_relative", profile.humidity_relative),
+                    ("pressure_pa", profile.pressure_pa),
+                ],
+            )?;
+            if !(0.0..=MAX_SELF_HEATING_C).contains(&profile.self_heating_c) {
+                return Err(format!(
+                    "{}.self_heating_c must be 0-{}, got {}",
+                    section, MAX_SELF_HEATING_C, profile.self_heating_c
+                ));
+            }
+        }
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_config_deserialization() {
-        let toml_str = r#"
-[database]
-url = "sqlite:./test.db"
-"#;
-        let config: Config = toml::from_str(toml_str).unwrap();
-        assert_eq!(config.database.url, "sqlite:./test.db");
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SensorType {
+    /// Bosch BME280.
+    #[default]
+    Bme280,
+    /// Fixed reading of 20 °C, 50 % and 101325 Pa, for running without the
+    /// sensor.
+    Constant,
+    /// Sensirion SHT31, temperature and humidity only.
+    Sht31,
+    /// Aosong AHT20, temperature and humidity only.
+    Aht20,
+}
+
+impl SensorType {
+    /// Whether the sensor measures the pressure. Rows of the others have a
+    /// NaN pressure.
+    pub fn measures_pressure(self) -> bool {
+        !matches!(self, SensorType::Sht31 | SensorType::Aht20)
     }
+}
 
-    #[test]
-    fn test_load_from_file_not_found() {
-        let result = Config::load_from_file("nonexistent_config.toml");
-        assert!(result.is_err());
+/// Built-in HTTP API.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Listen address, e.g. "127.0.0.1:8080". The API is disabled if not specified.
+    pub listen: Option<String>,
+    /// Listen address of the kiosk, e.g. "0.0.0.0:8081", serving
+    /// `GET /api/current` and `GET /healthz` only. Disabled if not specified.
+    pub kiosk_bind: Option<String>,
+}
+
+impl HttpConfig {
+    /// Check that the kiosk does not share the address of the full API.
+    /// # Returns
+    /// * `Err(message)` if both listen on the same address.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.kiosk_bind.is_some() && self.kiosk_bind == self.listen {
+            return Err(format!(
+                "http.kiosk_bind must differ from http.listen, both are {}",
+                self.listen.as_deref().unwrap_or_default()
+            ));
+        }
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_config_debug_format() {
-        let config = Config::default();
-        let debug_string = format!("{:?}", config);
-        assert!(debug_string.contains("Config"));
-        assert!(debug_string.contains("DatabaseConfig"));
-        assert!(debug_string.contains("Not specified"));
+/// Whether a failure of each subsystem at start-up stops the program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SubsystemsConfig {
+    /// The sensors and their settings.
+    pub sensor: Criticality,
+    /// Database connection and migrations.
+    pub database: Criticality,
+    /// The after_insert hook command.
+    pub hooks: Criticality,
+    /// Binding the HTTP API.
+    pub http: Criticality,
+    /// The screensaver wake button.
+    pub button: Criticality,
+    /// The UPS signal pins.
+    pub power: Criticality,
+    /// The ambient light sensor.
+    pub light_sensor: Criticality,
+}
+
+/// How a subsystem failure is handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Criticality {
+    /// The program exits.
+    #[default]
+    Critical,
+    /// The program continues without the subsystem.
+    Optional,
+}
+
+/// BH1750 ambient light sensor setting the display contrast.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct LightSensorConfig {
+    /// I2C address, 0x23 or 0x5c.
+    pub address: u16,
+    /// Illuminance at and below which `min_contrast` is used.
+    pub dark_lux: f64,
+    /// Illuminance at and above which `max_contrast` is used.
+    pub bright_lux: f64,
+    pub min_contrast: u8,
+    pub max_contrast: u8,
+}
+
+impl Default for LightSensorConfig {
+    fn default() -> Self {
+        Self {
+            address: 0x23,
+            dark_lux: 1.0,
+            bright_lux: 1000.0,
+            min_contrast: 0x10,
+            max_contrast: 0xFF,
+        }
     }
+}
 
-    #[test]
-    fn test_database_config_debug_format() {
-        let db_config = DatabaseConfig {
-            url: "sqlite:./test.db".to_string(),
-        };
-        let debug_string = format!("{:?}", db_config);
-        assert!(debug_string.contains("DatabaseConfig"));
-        assert!(debug_string.contains("sqlite:./test.db"));
+impl LightSensorConfig {
+    /// Check the lux range and the contrast range.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.dark_lux > 0.0 && self.dark_lux < self.bright_lux) {
+            return Err(format!(
+                "light_sensor.dark_lux must be above 0 and below bright_lux, got {} and {}",
+                self.dark_lux, self.bright_lux
+            ));
+        }
+        if self.min_contrast > self.max_contrast {
+            return Err(format!(
+                "light_sensor.min_contrast {} is above max_contrast {}",
+                self.min_contrast, self.max_contrast
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Altimeter check page: the altitude indicated at a QNH setting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AltimeterConfig {
+    /// Whether the page is shown.
+    pub enabled: bool,
+    /// QNH in hPa used until one is set over HTTP.
+    pub qnh_hpa: Option<f64>,
+    /// File keeping the QNH set over HTTP across restarts.
+    pub state_file: String,
+    /// Hours after which the QNH is shown as stale.
+    pub stale_after_hours: u64,
+}
+
+impl Default for AltimeterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            qnh_hpa: None,
+            state_file: "state.json".to_string(),
+            stale_after_hours: 12,
+        }
+    }
+}
+
+/// Range of QNH settings accepted, in hPa.
+pub const QNH_RANGE_HPA: (f64, f64) = (850.0, 1100.0);
+
+impl AltimeterConfig {
+    /// Check the QNH and the staleness limit.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(qnh) = self.qnh_hpa {
+            validate_qnh(qnh).map_err(|e| format!("altimeter.qnh_hpa: {}", e))?;
+        }
+        if self.stale_after_hours == 0 {
+            return Err("altimeter.stale_after_hours must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Age after which the QNH is shown as stale.
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_secs(self.stale_after_hours * 3600)
+    }
+}
+
+/// Check a QNH setting.
+/// # Arguments
+/// * `qnh_hpa` - QNH in hPa.
+/// # Returns
+/// * `Err(message)` if it is outside `QNH_RANGE_HPA`.
+pub fn validate_qnh(qnh_hpa: f64) -> Result<(), String> {
+    let (min, max) = QNH_RANGE_HPA;
+    if !(min..=max).contains(&qnh_hpa) {
+        return Err(format!(
+            "QNH {} hPa is outside {} to {} hPa",
+            qnh_hpa, min, max
+        ));
+    }
+    Ok(())
+}
+
+/// Page of the highest temperature and lowest humidity since they were
+/// last viewed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ExtremesConfig {
+    /// Whether the page is shown.
+    pub enabled: bool,
+    /// File keeping the extremes across restarts.
+    pub state_file: String,
+}
+
+impl Default for ExtremesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_file: "state.json".to_string(),
+        }
+    }
+}
+
+/// Faults injected into the simulated sensor of `soak --simulate`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Every Nth measurement fails with a bus timeout (0 = never).
+    pub fail_every: u64,
+    /// Every Nth measurement reads far out of range (0 = never).
+    pub out_of_range_every: u64,
+    /// Wait added to every measurement, in milliseconds.
+    pub latency_ms: u64,
+}
+
+/// Heating and cooling degree-hours and the mold-risk index, shown on a
+/// page and at `/api/current`, with daily totals in the `daily_metrics`
+/// table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DailyMetricsConfig {
+    /// Whether the metrics are tracked.
+    pub enabled: bool,
+    /// Base temperature of the degree-hours in Celsius. Time below it adds
+    /// heating degree-hours, time above it cooling degree-hours.
+    pub base_temperature_c: f64,
+    /// Relative humidity above which time counts towards the mold risk, in %.
+    pub mold_humidity_relative: f64,
+}
+
+impl Default for DailyMetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_temperature_c: 18.0,
+            mold_humidity_relative: 70.0,
+        }
+    }
+}
+
+impl DailyMetricsConfig {
+    /// Check the base temperature and the humidity threshold.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.base_temperature_c.is_finite() {
+            return Err(format!(
+                "daily_metrics.base_temperature_c must be a finite number, got {}",
+                self.base_temperature_c
+            ));
+        }
+        if !(0.0..100.0).contains(&self.mold_humidity_relative) {
+            return Err(format!(
+                "daily_metrics.mold_humidity_relative must be 0-100, got {}",
+                self.mold_humidity_relative
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Debug trace of the measurement loop, see `trace`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct TraceConfig {
+    /// Directory of the trace files.
+    pub dir: String,
+    /// Largest size of the files of one trace together, in bytes. The
+    /// oldest entries are dropped beyond it.
+    pub max_bytes: u64,
+    /// Longest trace which may be requested, in seconds.
+    pub max_secs: u64,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            dir: ".".to_string(),
+            max_bytes: 4 * 1024 * 1024,
+            max_secs: 600,
+        }
+    }
+}
+
+/// Smallest `trace.max_bytes`, room for a few ticks per file.
+pub const MIN_TRACE_BYTES: u64 = 64 * 1024;
+
+impl TraceConfig {
+    /// Check the size and duration limits.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_bytes < MIN_TRACE_BYTES {
+            return Err(format!(
+                "trace.max_bytes must be at least {}, got {}",
+                MIN_TRACE_BYTES, self.max_bytes
+            ));
+        }
+        if !(1..=3600).contains(&self.max_secs) {
+            return Err(format!(
+                "trace.max_secs must be 1-3600, got {}",
+                self.max_secs
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Safe mode after repeated crashes, see `safe_mode`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SafeModeConfig {
+    /// Count crashes and start in safe mode after too many.
+    pub enabled: bool,
+    /// File the crashes are counted in.
+    pub crash_file: String,
+    /// Most crashes within the window before safe mode starts.
+    pub max_crashes: u32,
+    /// Window the crashes are counted in, in minutes. Safe mode also ends
+    /// once the last crash is this old.
+    pub window_mins: u64,
+    /// Interval between measurements in safe mode, in milliseconds.
+    pub interval_ms: u64,
+}
+
+impl Default for SafeModeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            crash_file: "crashes.json".to_string(),
+            max_crashes: 3,
+            window_mins: 10,
+            interval_ms: 2000,
+        }
+    }
+}
+
+impl SafeModeConfig {
+    /// Window the crashes are counted in.
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_mins * 60)
+    }
+
+    /// Check the crash count, the window and the interval.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_crashes == 0 {
+            return Err("safe_mode.max_crashes must be at least 1".to_string());
+        }
+        if !(1..=1440).contains(&self.window_mins) {
+            return Err(format!(
+                "safe_mode.window_mins must be 1-1440, got {}",
+                self.window_mins
+            ));
+        }
+        if !(crate::capture::NORMAL_RATE_MS..=60_000).contains(&self.interval_ms) {
+            return Err(format!(
+                "safe_mode.interval_ms must be {}-60000, got {}",
+                crate::capture::NORMAL_RATE_MS,
+                self.interval_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// CSV written by the `export` subcommand. The database and the JSON API
+/// are not affected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Locale of the decimal separator and digit grouping, e.g. `de`.
+    pub locale: String,
+    /// Separate the integer digits in thousands.
+    pub grouping: bool,
+    /// Field delimiter.
+    pub delimiter: char,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            locale: "C".to_string(),
+            grouping: false,
+            delimiter: ',',
+        }
+    }
+}
+
+impl ExportConfig {
+    /// Check the locale and the delimiter.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        NumberLocale::parse(&self.locale).map_err(|e| format!("export.locale: {}", e))?;
+        crate::export::validate_delimiter(self.delimiter)
+            .map_err(|e| format!("export.delimiter: {}", e))
+    }
+}
+
+/// Other units whose readings are shown on a display page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PeersConfig {
+    /// Seconds between polls of every unit.
+    pub poll_interval_secs: u64,
+    /// Seconds a unit has to answer.
+    pub timeout_secs: u64,
+    /// Seconds each page is shown before the next one.
+    pub page_secs: u64,
+    pub units: Vec<PeerConfig>,
+}
+
+impl Default for PeersConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 30,
+            timeout_secs: 5,
+            page_secs: 5,
+            units: Vec::new(),
+        }
+    }
+}
+
+/// Another unit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct PeerConfig {
+    /// Name shown on the display, up to `PEER_NAME_MAX` characters.
+    pub name: String,
+    /// Base URL of its HTTP API, e.g. "http://192.168.1.20:8080".
+    pub url: String,
+}
+
+/// Longest peer name which fits the peers page.
+pub const PEER_NAME_MAX: usize = 4;
+
+impl PeersConfig {
+    /// Check the units and intervals.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("poll_interval_secs", self.poll_interval_secs),
+            ("timeout_secs", self.timeout_secs),
+            ("page_secs", self.page_secs),
+        ] {
+            if value == 0 {
+                return Err(format!("peers.{} must be at least 1", name));
+            }
+        }
+        for unit in &self.units {
+            let length = unit.name.chars().count();
+            if length == 0 || length > PEER_NAME_MAX {
+                return Err(format!(
+                    "peers.units name {:?} must be 1 to {} characters",
+                    unit.name, PEER_NAME_MAX
+                ));
+            }
+            if !unit.url.starts_with("http://") {
+                return Err(format!(
+                    "peers.units url {} of {} must start with http://",
+                    unit.url, unit.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Timeout of one poll.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+/// External commands run on events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Command run after each row is stored, e.g.
+    /// "/usr/local/bin/notify {sensor} {temperature_c}".
+    /// Placeholders: {timestamp}, {sensor}, {temperature_c},
+    /// {humidity_relative}, {pressure_pa}, {thi}, {quality} and {json}.
+    pub after_insert: Option<String>,
+    /// Measurement fields in the {json} of `after_insert`.
+    pub after_insert_fields: Vec<FieldGroup>,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            after_insert: None,
+            after_insert_fields: FieldGroup::ALL.to_vec(),
+        }
+    }
+}
+
+impl HooksConfig {
+    /// Check the field selections.
+    /// # Returns
+    /// * `Err(message)` if a selection is empty.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.after_insert_fields.is_empty() {
+            return Err("hooks.after_insert_fields must select at least one field".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Free space checks of the filesystem holding the SQLite file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DiskConfig {
+    /// Store a warning event below this much free space.
+    pub warn_below_mb: u64,
+    /// Drop rows instead of writing them below this much free space.
+    pub pause_below_mb: u64,
+    /// Seconds between checks.
+    pub check_interval_secs: u64,
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        Self {
+            warn_below_mb: 200,
+            pause_below_mb: 50,
+            check_interval_secs: 30,
+        }
+    }
+}
+
+impl DiskConfig {
+    /// Check the thresholds.
+    /// # Returns
+    /// * `Err(message)` if the floor is above the warning threshold.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pause_below_mb > self.warn_below_mb {
+            return Err(format!(
+                "disk.pause_below_mb ({}) must not be above warn_below_mb ({})",
+                self.pause_below_mb, self.warn_below_mb
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Wind-down on the power loss signals of a UPS, disabled without pins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PowerConfig {
+    /// BCM GPIO number pulled low by the UPS while mains power is lost.
+    pub mains_loss_pin: Option<u8>,
+    /// BCM GPIO number pulled low by the UPS when its battery runs low.
+    pub battery_low_pin: Option<u8>,
+    /// Seconds between stored rows while on battery. 0 keeps every row.
+    pub save_interval_secs: u64,
+    /// What happens to the display while on battery.
+    pub display: WindDownDisplay,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            mains_loss_pin: None,
+            battery_low_pin: None,
+            save_interval_secs: 60,
+            display: WindDownDisplay::default(),
+        }
+    }
+}
+
+impl PowerConfig {
+    /// Check the pins.
+    /// # Returns
+    /// * `Err(message)` if both signals are on the same pin.
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.mains_loss_pin, self.battery_low_pin) {
+            (Some(mains), Some(battery)) if mains == battery => Err(format!(
+                "power.mains_loss_pin and battery_low_pin are both GPIO {}",
+                mains
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Interval between stored rows while on battery.
+    pub fn save_interval(&self) -> Option<Duration> {
+        (self.save_interval_secs > 0).then(|| Duration::from_secs(self.save_interval_secs))
+    }
+}
+
+/// Display state while on battery.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum WindDownDisplay {
+    /// Turned off.
+    #[default]
+    Off,
+    /// Minimum contrast (so1602a), turned off on other displays.
+    Dim,
+}
+
+/// Threshold alerts on the main sensor, disabled without rules.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRuleConfig>,
+    pub escalation: EscalationConfig,
+    /// Store each threshold alert, from raise to clear, in the
+    /// `alert_events` table.
+    pub history: bool,
+}
+
+/// Alert raised while a value is above or below a threshold, or a
+/// notification when a value settles into another band.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct AlertRuleConfig {
+    /// Name shown on the display and in the log.
+    pub name: String,
+    /// Kind of rule, "threshold" if not specified.
+    #[serde(default, rename = "type")]
+    pub kind: AlertRuleKind,
+    /// Watched value.
+    pub field: AlertField,
+    /// Raised while the value is above this (threshold).
+    pub above: Option<f64>,
+    /// Raised while the value is below this (threshold).
+    pub below: Option<f64>,
+    /// Bands of the value (band_change).
+    pub band: Option<BandChangeConfig>,
+    /// Message scrolled on the 2nd line while the alert is active
+    /// (threshold), see `alerts::render_message` for the placeholders.
+    pub message: Option<String>,
+    /// Drawn while active even when an external client holds the display
+    /// lease (threshold).
+    #[serde(default)]
+    pub always_override: bool,
+}
+
+/// Kind of alert rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AlertRuleKind {
+    /// Raised and escalated while `above`/`below` is crossed.
+    #[default]
+    Threshold,
+    /// Notified once each time the value settles into another band.
+    BandChange,
+}
+
+/// Bands of a band_change rule: `[origin + n * width, origin + (n + 1) * width)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct BandChangeConfig {
+    /// Width of a band, in the unit of the field.
+    pub width: f64,
+    /// Lower bound of band 0.
+    pub origin: f64,
+    /// Seconds the smoothed value must stay in a new band before it is notified.
+    pub settle_secs: u64,
+    /// Distance past a boundary, as a fraction of `width`, before the value
+    /// counts as out of its current band.
+    pub hysteresis: f64,
+    /// Time constant of the exponential smoothing in seconds (0 = off).
+    pub smoothing_secs: u64,
+}
+
+impl Default for BandChangeConfig {
+    fn default() -> Self {
+        Self {
+            width: 0.0,
+            origin: 0.0,
+            settle_secs: 300,
+            hysteresis: 0.2,
+            smoothing_secs: 60,
+        }
+    }
+}
+
+impl BandChangeConfig {
+    /// Check the band settings.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    fn validate(&self) -> Result<(), String> {
+        if !(self.width.is_finite() && self.width > 0.0) {
+            return Err(format!("band width must be above 0, got {}", self.width));
+        }
+        if !self.origin.is_finite() {
+            return Err("band origin must be a finite number".to_string());
+        }
+        if !(0.0..0.5).contains(&self.hysteresis) {
+            return Err(format!(
+                "band hysteresis must be from 0 to below 0.5, got {}",
+                self.hysteresis
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Value watched by an alert rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AlertField {
+    TemperatureC,
+    HumidityRelative,
+    PressurePa,
+    Thi,
+}
+
+impl AlertField {
+    /// Name of the field as written in the config.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TemperatureC => "temperature_c",
+            Self::HumidityRelative => "humidity_relative",
+            Self::PressurePa => "pressure_pa",
+            Self::Thi => "thi",
+        }
+    }
+}
+
+/// Notification of an alert as it persists. It is shown on the display
+/// as soon as it is raised.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct EscalationConfig {
+    /// Log a warning once the alert persisted this many minutes.
+    pub log_after_mins: u64,
+    /// Post to `webhook_url` once the alert persisted this many minutes.
+    pub webhook_after_mins: u64,
+    /// URL posted to, "http://" only. No webhook is sent if not specified.
+    pub webhook_url: Option<String>,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        Self {
+            log_after_mins: 5,
+            webhook_after_mins: 15,
+            webhook_url: None,
+        }
+    }
+}
+
+impl AlertsConfig {
+    /// Most rules, one bit each in the stored `alert_active` mask.
+    pub const MAX_RULES: usize = 32;
+
+    /// Check the rules and escalation.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rules.len() > Self::MAX_RULES {
+            return Err(format!(
+                "alerts.rules can hold at most {} rules, got {}",
+                Self::MAX_RULES,
+                self.rules.len()
+            ));
+        }
+        let mut names: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if rule.name.trim().is_empty() {
+                return Err("alerts.rules name must not be empty".to_string());
+            }
+            if names.contains(&rule.name.as_str()) {
+                return Err(format!("alerts.rules name {:?} is used twice", rule.name));
+            }
+            names.push(&rule.name);
+            if rule.kind == AlertRuleKind::BandChange {
+                let Some(band) = &rule.band else {
+                    return Err(format!("alerts.rules {:?} needs `band`", rule.name));
+                };
+                if rule.above.is_some() || rule.below.is_some() {
+                    return Err(format!(
+                        "alerts.rules {:?} of type band_change takes no `above` or `below`",
+                        rule.name
+                    ));
+                }
+                if rule.message.is_some() || rule.always_override {
+                    return Err(format!(
+                        "alerts.rules {:?} of type band_change is never shown, it takes no `message` or `always_override`",
+                        rule.name
+                    ));
+                }
+                band.validate()
+                    .map_err(|e| format!("alerts.rules {:?} {}", rule.name, e))?;
+                continue;
+            }
+            if rule.band.is_some() {
+                return Err(format!(
+                    "alerts.rules {:?} `band` needs type = \"band_change\"",
+                    rule.name
+                ));
+            }
+            if rule.above.is_none() && rule.below.is_none() {
+                return Err(format!(
+                    "alerts.rules {:?} needs `above` and/or `below`",
+                    rule.name
+                ));
+            }
+            if rule
+                .above
+                .into_iter()
+                .chain(rule.below)
+                .any(|v| !v.is_finite())
+            {
+                return Err(format!(
+                    "alerts.rules {:?} thresholds must be finite numbers",
+                    rule.name
+                ));
+            }
+        }
+        let escalation = &self.escalation;
+        if escalation.webhook_after_mins < escalation.log_after_mins {
+            return Err(format!(
+                "alerts.escalation.webhook_after_mins ({}) must not be before log_after_mins ({})",
+                escalation.webhook_after_mins, escalation.log_after_mins
+            ));
+        }
+        let webhook_url = escalation.webhook_url.as_ref();
+        if let Some(url) = webhook_url.filter(|url| !url.starts_with("http://")) {
+            return Err(format!(
+                "alerts.escalation.webhook_url must start with http://, got {}",
+                url
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Group of measurement fields emitted by a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum FieldGroup {
+    /// temperature_c, humidity_relative and pressure_pa.
+    Raw,
+    /// Values computed from the raw ones: thi.
+    Derived,
+}
+
+impl FieldGroup {
+    /// Every group.
+    pub const ALL: &'static [FieldGroup] = &[FieldGroup::Raw, FieldGroup::Derived];
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct QualityConfig {
+    /// Number of recent measurement attempts considered.
+    pub window: usize,
+    /// Rows are flagged suspect while at least this many of the recent
+    /// attempts failed. 0 disables the check.
+    pub suspect_failures: usize,
+    /// Fraction of each sensor range treated as its edge (0.01 = 1%).
+    pub boundary_margin: f64,
+    /// Store the quality score (0-100) of each row in `quality_score`.
+    pub store_score: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ScreensaverConfig {
+    /// Blank the display after this many seconds without activity. 0 disables blanking.
+    pub idle_timeout_secs: u64,
+    /// BCM GPIO number of a push button (to GND) which wakes the display.
+    pub wake_pin: Option<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayType {
+    /// SO1602A character OLED.
+    #[default]
+    So1602a,
+    /// HD44780 character LCD behind a PCF8574 I2C backpack.
+    Hd44780,
+}
+
+/// Columns and lines of the display module.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub enum DisplaySize {
+    /// 16 columns, 2 lines: SO1602A, 1602 LCD.
+    #[default]
+    #[serde(rename = "16x2")]
+    Cols16Rows2,
+    /// 20 columns, 4 lines: SO2004A, 2004 LCD.
+    #[serde(rename = "20x4")]
+    Cols20Rows4,
+}
+
+impl Default for SensorsConfig {
+    fn default() -> Self {
+        Self {
+            driver: SensorType::Bme280,
+            label: DEFAULT_SENSOR_LABEL.to_string(),
+            profile: None,
+            extra: Vec::new(),
+            samples: 1,
+            auto_detect: false,
+            stuck_after: 50,
+            profiles: SensorProfilesConfig::default(),
+        }
+    }
+}
+
+impl SensorsConfig {
+    /// Check that every label is set and unique, that the number of
+    /// samples leaves something after trimming, that a single reading is
+    /// not taken as stuck, and that no extra sensor sits where the main one
+    /// is detected.
+    /// # Returns
+    /// * `Err(message)` describing the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut labels: Vec<&str> = Vec::new();
+        for label in std::iter::once(&self.label).chain(self.extra.iter().map(|s| &s.label)) {
+            if label.trim().is_empty() {
+                return Err("sensor label must not be empty".to_string());
+            }
+            if labels.contains(&label.as_str()) {
+                return Err(format!("sensor label \"{}\" is used more than once", label));
+            }
+            labels.push(label);
+        }
+        if self.samples != 1 && self.samples < MIN_TRIMMED_SAMPLES {
+            return Err(format!(
+                "sensors.samples must be 1 or at least {}, got {}",
+                MIN_TRIMMED_SAMPLES, self.samples
+            ));
+        }
+        if self.stuck_after == 1 {
+            return Err("sensors.stuck_after must be 0 or at least 2, got 1".to_string());
+        }
+        if self.auto_detect {
+            let detected = [BME280_ADDR, BME280_ADDR2];
+            if let Some(extra) = self
+                .extra
+                .iter()
+                .find(|s| s.address.is_some_and(|a| detected.contains(&a)))
+            {
+                return Err(format!(
+                    "sensors.auto_detect can not be used with the extra sensor \"{}\" at {:#04x}",
+                    extra.label,
+                    extra.address.unwrap_or_default()
+                ));
+            }
+        }
+        self.profiles.validate()
+    }
+
+    /// Labels of the sensors with their profile settings, the main sensor
+    /// first. Sensors without a profile are left out.
+    pub fn profiled(&self) -> impl Iterator<Item = (&str, &ProfileConfig)> {
+        std::iter::once((self.label.as_str(), self.profile))
+            .chain(self.extra.iter().map(|s| (s.label.as_str(), s.profile)))
+            .filter_map(|(label, profile)| Some((label, self.profiles.get(profile?))))
+    }
+
+    /// Labels of the sensors which measure no pressure, the main sensor
+    /// first.
+    pub fn without_pressure(&self) -> impl Iterator<Item = &str> {
+        std::iter::once((self.label.as_str(), self.driver))
+            .chain(self.extra.iter().map(|s| (s.label.as_str(), s.driver)))
+            .filter(|(_, driver)| !driver.measures_pressure())
+            .map(|(label, _)| label)
+    }
+
+    /// Self-heating compensation of a sensor profile, 0 without one.
+    pub fn self_heating_c(&self, profile: Option<SensorProfile>) -> f64 {
+        profile.map_or(0.0, |profile| self.profiles.get(profile).self_heating_c)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database: DatabaseConfig {
+                url: "Not specified".to_string(),
+                timestamp_source: TimestampSource::default(),
+                sqlite: SqliteConfig::default(),
+                backward_timestamps: BackwardTimestamps::default(),
+                insert_timeout_secs: default_insert_timeout_secs(),
+                validation: ValidationConfig::default(),
+                save_window_secs: 0,
+                align_to_interval: false,
+                decimals: None,
+                rounding: RoundingMode::default(),
+            },
+            clock: ClockConfig::default(),
+            display: DisplayConfig::default(),
+            hardware: HardwareConfig::default(),
+            sensor: SensorConfig::default(),
+            sensors: SensorsConfig::default(),
+            http: HttpConfig::default(),
+            screensaver: ScreensaverConfig::default(),
+            quality: QualityConfig::default(),
+            hooks: HooksConfig::default(),
+            comfort: ComfortConfig::default(),
+            alerts: AlertsConfig::default(),
+            disk: DiskConfig::default(),
+            power: PowerConfig::default(),
+            peers: PeersConfig::default(),
+            subsystems: SubsystemsConfig::default(),
+            altimeter: AltimeterConfig::default(),
+            extremes: ExtremesConfig::default(),
+            export: ExportConfig::default(),
+            daily_metrics: DailyMetricsConfig::default(),
+            trace: TraceConfig::default(),
+            safe_mode: SafeModeConfig::default(),
+            chaos: ChaosConfig::default(),
+            light_sensor: None,
+        }
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        let settings = Bme280Settings::default();
+        Self {
+            oversampling_temperature: settings.oversampling_temperature,
+            oversampling_pressure: settings.oversampling_pressure,
+            oversampling_humidity: settings.oversampling_humidity,
+            filter: settings.filter,
+            temperature_offset_c: 0.0,
+            humidity_offset: 0.0,
+            pressure_offset_pa: 0.0,
+            interval_ms: crate::capture::NORMAL_RATE_MS,
+            sea_level_pa: 101325.0,
+        }
+    }
+}
+
+impl SensorConfig {
+    /// Get the period of the measurement loop.
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// Get the BME280 register settings.
+    pub fn settings(&self) -> Bme280Settings {
+        Bme280Settings {
+            oversampling_temperature: self.oversampling_temperature,
+            oversampling_pressure: self.oversampling_pressure,
+            oversampling_humidity: self.oversampling_humidity,
+            filter: self.filter,
+        }
+    }
+
+    /// Apply the offsets to a measurement.
+    /// Humidity is kept within 0-100%.
+    /// # Arguments
+    /// * `measurement` - Measurement from the sensor.
+    /// # Returns
+    /// * Corrected measurement.
+    pub fn apply_offsets(&self, measurement: Measurement) -> Measurement {
+        Measurement {
+            temperature_c: measurement.temperature_c + self.temperature_offset_c,
+            pressure_pa: measurement.pressure_pa + self.pressure_offset_pa,
+            humidity_relative: (measurement.humidity_relative + self.humidity_offset)
+                .clamp(0.0, 100.0),
+        }
+    }
+
+    /// Check the settings and the offsets.
+    /// # Returns
+    /// * `Ok(())` or the list of problems.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = self.settings().validate().err().unwrap_or_default();
+        for (name, value, limit) in [
+            ("temperature_offset_c", self.temperature_offset_c, 10.0),
+            ("humidity_offset", self.humidity_offset, 20.0),
+            ("pressure_offset_pa", self.pressure_offset_pa, 5000.0),
+        ] {
+            if !value.is_finite() || value.abs() > limit {
+                errors.push(format!("{} must be within ±{}, got {}", name, limit, value));
+            }
+        }
+        if !(crate::capture::MIN_RATE_MS..=60_000).contains(&self.interval_ms) {
+            errors.push(format!(
+                "interval_ms must be {}-60000, got {}",
+                crate::capture::MIN_RATE_MS,
+                self.interval_ms
+            ));
+        }
+        let (min_hpa, max_hpa) = QNH_RANGE_HPA;
+        if !(min_hpa * 100.0..=max_hpa * 100.0).contains(&self.sea_level_pa) {
+            errors.push(format!(
+                "sea_level_pa must be {}-{}, got {}",
+                min_hpa * 100.0,
+                max_hpa * 100.0,
+                self.sea_level_pa
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            driver: DisplayType::default(),
+            address: None,
+            size: DisplaySize::default(),
+            custom_chars: vec![
+                // Backslash dot, shared with the activity indicator
+                CustomCharConfig {
+                    index: 1,
+                    rows: [
+                        "00000",
+                        "10000",
+                        "01000",
+                        "00100",
+                        "00010",
+                        "00001",
+                        "00000",
+                        "00000",
+                    ]
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect(),
+                },
+            ],
+            contrast: 0x7F,
+            contrast_ramp_ms: 0,
+            rounding_hysteresis: 0.3,
+            temperature_decimals: 1,
+            humidity_decimals: 1,
+            rounding: RoundingMode::default(),
+            write_retries: 1,
+            thi_min: THI_DISPLAY_LIMITS.0,
+            thi_max: THI_DISPLAY_LIMITS.1,
+            layout: MeasurementLayout::default(),
+            precision_check: true,
+            write_delay_us: 0,
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// I2C address of the display, the driver's default if not specified.
+    pub fn i2c_address(&self) -> u16 {
+        self.address.unwrap_or(match self.driver {
+            DisplayType::So1602a => peripheral::so1602a::SO1602A_ADDR,
+            DisplayType::Hd44780 => peripheral::hd44780::HD44780_PCF8574_ADDR,
+        })
+    }
+
+    /// Size and line addresses of the display for its driver.
+    pub fn geometry(&self) -> peripheral::display::DisplayGeometry {
+        use peripheral::{hd44780, so1602a};
+        match (self.driver, self.size) {
+            (DisplayType::So1602a, DisplaySize::Cols16Rows2) => so1602a::SO1602A_GEOMETRY,
+            (DisplayType::So1602a, DisplaySize::Cols20Rows4) => so1602a::SO2004A_GEOMETRY,
+            (DisplayType::Hd44780, DisplaySize::Cols16Rows2) => hd44780::HD44780_16X2_GEOMETRY,
+            (DisplayType::Hd44780, DisplaySize::Cols20Rows4) => hd44780::HD44780_20X4_GEOMETRY,
+        }
+    }
+
+    /// Precision of the displayed measurement.
+    pub fn measurement_format(&self) -> MeasurementFormat {
+        MeasurementFormat {
+            temperature_decimals: self.temperature_decimals.min(MAX_DECIMALS),
+            humidity_decimals: self.humidity_decimals.min(MAX_DECIMALS),
+            rounding: self.rounding,
+            thi_range: (self.thi_min, self.thi_max),
+            layout: self.layout,
+        }
+    }
+
+    /// Wait after each display write.
+    /// # Returns
+    /// * Zero if pacing is disabled.
+    pub fn write_delay(&self) -> Duration {
+        Duration::from_micros(self.write_delay_us)
+    }
+
+    /// Check the display write delay.
+    /// # Returns
+    /// * `Err(message)` if the delay is too long.
+    pub fn validate_write_delay(&self) -> Result<(), String> {
+        if self.write_delay_us > MAX_WRITE_DELAY_US {
+            return Err(format!(
+                "display.write_delay_us must be at most {}, got {}",
+                MAX_WRITE_DELAY_US, self.write_delay_us
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check the display precision.
+    /// # Returns
+    /// * `Err(message)` if more decimals are requested than fit the line.
+    pub fn validate_precision(&self) -> Result<(), String> {
+        for (name, decimals) in [
+            ("temperature_decimals", self.temperature_decimals),
+            ("humidity_decimals", self.humidity_decimals),
+        ] {
+            if decimals > MAX_DECIMALS {
+                return Err(format!(
+                    "display.{} must be 0-{}, got {}",
+                    name, MAX_DECIMALS, decimals
+                ));
+            }
+        }
+        let (min, max) = THI_DISPLAY_LIMITS;
+        if self.thi_min < min || self.thi_max > max || self.thi_min > self.thi_max {
+            return Err(format!(
+                "display.thi_min and thi_max must satisfy {} <= thi_min <= thi_max <= {}, got {} and {}",
+                min, max, self.thi_min, self.thi_max
+            ));
+        }
+        Ok(())
+    }
+
+    /// Duration of the startup contrast ramp.
+    /// # Returns
+    /// * Zero if the ramp is disabled.
+    pub fn contrast_ramp(&self) -> Duration {
+        Duration::from_millis(self.contrast_ramp_ms)
+    }
+
+    /// Convert the custom characters to CGRAM bitmaps.
+    /// # Returns
+    /// * `Ok(Vec<(index, bitmap)>)` if all definitions are valid.
+    /// * `Err(message)` describing the first invalid definition.
+    pub fn custom_char_bitmaps(&self) -> Result<Vec<(u8, [u8; 8])>, String> {
+        if self.custom_chars.len() > cgram::SLOTS {
+            return Err(format!(
+                "display.custom_chars defines {} characters, the display holds {}",
+                self.custom_chars.len(),
+                cgram::SLOTS
+            ));
+        }
+        let mut bitmaps: Vec<(u8, [u8; 8])> = Vec::new();
+        for custom_char in &self.custom_chars {
+            let bitmap = custom_char.bitmap()?;
+            if bitmaps.iter().any(|(index, _)| *index == custom_char.index) {
+                return Err(format!(
+                    "custom character {} is defined twice",
+                    custom_char.index
+                ));
+            }
+            bitmaps.push((custom_char.index, bitmap));
+        }
+        Ok(bitmaps)
+    }
+
+    /// Allocate the CGRAM slots of the custom characters and the built-in
+    /// glyphs.
+    /// # Returns
+    /// * `Err(message)` if a definition is invalid or the slots run out.
+    pub fn cgram_slots(&self) -> Result<CgramSlots, String> {
+        CgramSlots::allocate(self.custom_char_bitmaps()?)
+    }
+}
+
+impl CustomCharConfig {
+    /// Convert the rows to a CGRAM bitmap.
+    /// # Returns
+    /// * `Ok(bitmap)` if the index is 0-7 and there are exactly 8 rows of 5 pixels.
+    /// * `Err(message)` otherwise.
+    pub fn bitmap(&self) -> Result<[u8; 8], String> {
+        if self.index > 7 {
+            return Err(format!(
+                "custom character index {} is out of range (0-7)",
+                self.index
+            ));
+        }
+        if self.rows.len() != 8 {
+            return Err(format!(
+                "custom character {} needs 8 rows, got {}",
+                self.index,
+                self.rows.len()
+            ));
+        }
+        let mut bitmap = [0u8; 8];
+        for (row, line) in bitmap.iter_mut().zip(&self.rows) {
+            if line.chars().count() != 5 {
+                return Err(format!(
+                    "custom character {} row \"{}\" must be 5 pixels wide",
+                    self.index, line
+                ));
+            }
+            for c in line.chars() {
+                let bit = match c {
+                    '1' | '#' => 1,
+                    '0' | '.' => 0,
+                    _ => {
+                        return Err(format!(
+                            "custom character {} row \"{}\" has invalid pixel '{}'",
+                            self.index, line, c
+                        ));
+                    }
+                };
+                *row = (*row << 1) | bit;
+            }
+        }
+        Ok(bitmap)
+    }
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            suspect_failures: 3,
+            boundary_margin: 0.01,
+            store_score: false,
+        }
+    }
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            commit_interval_secs: 0,
+            batch_size: 100,
+            synchronous: None,
+        }
+    }
+}
+
+impl SqliteConfig {
+    /// Interval between group commits.
+    /// # Returns
+    /// * `None` if every row is committed on its own.
+    pub fn commit_interval(&self) -> Option<Duration> {
+        (self.commit_interval_secs > 0).then(|| Duration::from_secs(self.commit_interval_secs))
+    }
+}
+
+impl SqliteSynchronous {
+    /// Statement applying the setting to a connection.
+    pub fn pragma(&self) -> &'static str {
+        match self {
+            SqliteSynchronous::Off => "PRAGMA synchronous = OFF",
+            SqliteSynchronous::Normal => "PRAGMA synchronous = NORMAL",
+            SqliteSynchronous::Full => "PRAGMA synchronous = FULL",
+            SqliteSynchronous::Extra => "PRAGMA synchronous = EXTRA",
+        }
+    }
+}
+
+impl ScreensaverConfig {
+    /// Get the idle timeout, `None` if blanking is disabled.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_secs > 0).then(|| Duration::from_secs(self.idle_timeout_secs))
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            min_valid_year: 2020,
+            skip_db_when_unsynced: true,
+            missed_ticks: MissedTicks::default(),
+            first_tick: FirstTick::default(),
+            timezone: None,
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Forced time zone.
+    /// # Returns
+    /// * The zone, `None` to use the system one.
+    /// * `Err(message)` if the zone is unknown.
+    pub fn forced_timezone(&self) -> Result<Option<Tz>, String> {
+        self.timezone
+            .as_deref()
+            .map(|name| {
+                name.parse::<Tz>()
+                    .map_err(|_| format!("clock.timezone {:?} is not a known time zone", name))
+            })
+            .transpose()
+    }
+}
+
+impl Config {
+    /// Load and validate a config file.
+    /// # Arguments
+    /// * `path` - Config file.
+    /// # Returns
+    /// * `Err(e)` naming the file and what is wrong with it.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|source| ConfigError::parse(path, &content, source))?;
+        config.validate().map_err(|message| ConfigError::Invalid {
+            path: path.display().to_string(),
+            message,
+        })?;
+        Ok(config)
+    }
+
+    /// Replace the [sensor] section of a config file.
+    /// Other values, comments and formatting are kept, see `config_file`.
+    /// # Arguments
+    /// * `path` - Config file.
+    /// * `sensor` - New sensor section.
+    /// # Returns
+    /// * Result<(), ConfigError>
+    pub fn save_sensor_section<P: AsRef<Path>>(
+        path: P,
+        sensor: &SensorConfig,
+    ) -> Result<(), ConfigError> {
+        config_file::update(path, |config| config.sensor = sensor.clone())
+    }
+
+    /// Check values which the TOML types alone cannot express.
+    /// # Returns
+    /// * `Err(message)` describing the first invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        self.display.cgram_slots()?;
+        self.clock.forced_timezone()?;
+        self.display.validate_precision()?;
+        self.display.validate_write_delay()?;
+        self.hardware.validate()?;
+        self.database.validation.validate()?;
+        self.database.validate_decimals()?;
+        self.database.validate_alignment()?;
+        self.sensor.validate().map_err(|errors| errors.join(", "))?;
+        self.sensors.validate()?;
+        self.comfort.validate()?;
+        self.hooks.validate()?;
+        self.http.validate()?;
+        self.alerts.validate()?;
+        self.disk.validate()?;
+        self.power.validate()?;
+        self.peers.validate()?;
+        self.altimeter.validate()?;
+        self.export.validate()?;
+        self.daily_metrics.validate()?;
+        self.trace.validate()?;
+        self.safe_mode.validate()?;
+        if let Some(light_sensor) = &self.light_sensor {
+            light_sensor.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Load a config file, or the defaults when there is none.
+    /// # Arguments
+    /// * `path` - Config file.
+    /// # Returns
+    /// * The config, and whether it was loaded from the file.
+    /// * `Err(e)` if the file exists but cannot be read or is invalid.
+    pub fn load_or_default_with_status<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Self, bool), ConfigError> {
+        if !path.as_ref().exists() {
+            return Ok((Self::default(), false));
+        }
+        Ok((Self::load_from_file(&path)?, true))
+    }
+
+    /// JSON Schema of the config file, for editor completion and validation.
+    /// Checks of `validate` which the types cannot express are not included.
+    /// # Returns
+    /// * The schema document.
+    #[cfg(feature = "config-schema")]
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(Config)).expect("schema is valid JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = Config::default();
+        assert_eq!(config.database.url, "Not specified");
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.database.url, "sqlite:./test.db");
+    }
+
+    #[test]
+    fn test_load_from_file_not_found() {
+        let error = Config::load_from_file("nonexistent_config.toml").unwrap_err();
+        assert!(matches!(error, ConfigError::Read { .. }));
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to read config nonexistent_config.toml: ")
+        );
+    }
+
+    #[test]
+    fn test_load_or_default_with_status() {
+        let (config, loaded) =
+            Config::load_or_default_with_status("nonexistent_config.toml").unwrap();
+        assert!(!loaded);
+        assert_eq!(config.database.url, "Not specified");
+
+        let path =
+            std::env::temp_dir().join(format!("wbroker-test-invalid-{}.toml", std::process::id()));
+        fs::write(&path, "[database]\nurl = 1\n").unwrap();
+        let result = Config::load_or_default_with_status(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "config-schema")]
+    #[test]
+    fn test_json_schema() {
+        let text = serde_json::to_string_pretty(&Config::json_schema()).unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(
+            schema["properties"]["database"]["$ref"],
+            "#/$defs/DatabaseConfig"
+        );
+        let database = &schema["$defs"]["DatabaseConfig"];
+        assert_eq!(database["properties"]["url"]["type"], "string");
+        assert!(
+            database["required"]
+                .as_array()
+                .unwrap()
+                .contains(&"url".into())
+        );
+    }
+
+    #[test]
+    fn test_config_debug_format() {
+        let config = Config::default();
+        let debug_string = format!("{:?}", config);
+        assert!(debug_string.contains("Config"));
+        assert!(debug_string.contains("DatabaseConfig"));
+        assert!(debug_string.contains("Not specified"));
+    }
+
+    #[test]
+    fn test_database_config_debug_format() {
+        let db_config = DatabaseConfig {
+            url: "sqlite:./test.db".to_string(),
+            timestamp_source: TimestampSource::Measurement,
+            sqlite: SqliteConfig::default(),
+            backward_timestamps: BackwardTimestamps::Clamp,
+            insert_timeout_secs: 10,
+            validation: ValidationConfig::default(),
+            save_window_secs: 60,
+            align_to_interval: true,
+            decimals: Some(2),
+            rounding: RoundingMode::HalfEven,
+        };
+        let debug_string = format!("{:?}", db_config);
+        assert!(debug_string.contains("DatabaseConfig"));
+        assert!(debug_string.contains("sqlite:./test.db"));
+    }
+
+    #[test]
+    fn test_timestamp_source() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.timestamp_source,
+            TimestampSource::Measurement
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+timestamp_source = "insertion"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.database.timestamp_source, TimestampSource::Insertion);
+    }
+
+    #[test]
+    fn test_power_config() {
+        let config = Config::default();
+        assert_eq!(config.power.mains_loss_pin, None);
+        assert_eq!(config.power.save_interval(), Some(Duration::from_secs(60)));
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[power]
+mains_loss_pin = 5
+battery_low_pin = 6
+save_interval_secs = 0
+display = "dim"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.power.mains_loss_pin, Some(5));
+        assert_eq!(config.power.save_interval(), None);
+        assert_eq!(config.power.display, WindDownDisplay::Dim);
+
+        let mut power = config.power.clone();
+        power.battery_low_pin = Some(5);
+        assert!(power.validate().unwrap_err().contains("GPIO 5"));
+    }
+
+    #[test]
+    fn test_subsystems_config() {
+        let config = Config::default();
+        assert_eq!(config.subsystems.sensor, Criticality::Critical);
+        assert_eq!(config.subsystems.database, Criticality::Critical);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[subsystems]
+database = "optional"
+http = "optional"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.subsystems.database, Criticality::Optional);
+        assert_eq!(config.subsystems.http, Criticality::Optional);
+        assert_eq!(config.subsystems.sensor, Criticality::Critical);
+        assert!(toml::from_str::<SubsystemsConfig>(r#"sensor = "maybe""#).is_err());
+    }
+
+    #[test]
+    fn test_light_sensor_config() {
+        assert_eq!(Config::default().light_sensor, None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[light_sensor]
+bright_lux = 500.0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        let light_sensor = config.light_sensor.unwrap();
+        assert_eq!(light_sensor.address, 0x23);
+        assert_eq!(light_sensor.bright_lux, 500.0);
+
+        let invalid = |edit: fn(&mut LightSensorConfig)| {
+            let mut light_sensor = LightSensorConfig::default();
+            edit(&mut light_sensor);
+            light_sensor.validate().unwrap_err()
+        };
+        assert!(invalid(|l| l.dark_lux = 0.0).contains("dark_lux"));
+        assert!(invalid(|l| l.bright_lux = 0.5).contains("dark_lux"));
+        assert!(invalid(|l| l.max_contrast = 0x08).contains("min_contrast"));
+    }
+
+    #[test]
+    fn test_altimeter_config() {
+        let config = Config::default();
+        assert!(!config.altimeter.enabled);
+        assert_eq!(
+            config.altimeter.stale_after(),
+            Duration::from_secs(12 * 3600)
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[altimeter]
+enabled = true
+qnh_hpa = 1018.0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.altimeter.qnh_hpa, Some(1018.0));
+        assert_eq!(config.altimeter.state_file, "state.json");
+
+        let mut altimeter = config.altimeter.clone();
+        altimeter.qnh_hpa = Some(101_800.0);
+        assert!(altimeter.validate().unwrap_err().contains("outside"));
+        assert!(validate_qnh(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_extremes_config() {
+        assert!(!Config::default().extremes.enabled);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[extremes]
+enabled = true
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.extremes.enabled);
+        assert_eq!(config.extremes.state_file, "state.json");
+    }
+
+    #[test]
+    fn test_export_config() {
+        assert_eq!(Config::default().export.delimiter, ',');
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[export]
+locale = "de_DE.UTF-8"
+delimiter = ";"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.export.delimiter, ';');
+
+        let mut export = config.export.clone();
+        export.locale = "xx".to_string();
+        assert_eq!(
+            export.validate(),
+            Err("export.locale: unknown locale xx".to_string())
+        );
+        export.locale = "C".to_string();
+        export.delimiter = '"';
+        assert!(
+            export
+                .validate()
+                .unwrap_err()
+                .starts_with("export.delimiter")
+        );
+    }
+
+    #[test]
+    fn test_hardware_config() {
+        let config = Config::default();
+        assert_eq!(config.hardware.i2c_speed_hz, None);
+        assert_eq!(config.display.write_delay(), Duration::ZERO);
+        assert_eq!(config.hardware.speed_mismatch(400_000), None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+write_delay_us = 30
+
+[hardware]
+i2c_speed_hz = 100000
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.display.write_delay(), Duration::from_micros(30));
+        assert_eq!(config.hardware.speed_mismatch(100_000), None);
+        assert!(
+            config
+                .hardware
+                .speed_mismatch(400_000)
+                .unwrap()
+                .contains("dtparam=i2c_arm_baudrate=100000")
+        );
+
+        config.display.write_delay_us = MAX_WRITE_DELAY_US + 1;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("display.write_delay_us")
+        );
+        config.display.write_delay_us = 0;
+        config.hardware.i2c_speed_hz = Some(5_000_000);
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("hardware.i2c_speed_hz")
+        );
+    }
+
+    #[test]
+    fn test_daily_metrics_config() {
+        let config = Config::default();
+        assert!(!config.daily_metrics.enabled);
+        assert_eq!(config.daily_metrics.base_temperature_c, 18.0);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[daily_metrics]
+enabled = true
+base_temperature_c = 15.5
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.daily_metrics.base_temperature_c, 15.5);
+        assert_eq!(config.daily_metrics.mold_humidity_relative, 70.0);
+
+        config.daily_metrics.mold_humidity_relative = 100.0;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("daily_metrics.mold_humidity_relative")
+        );
+    }
+
+    #[test]
+    fn test_safe_mode_config() {
+        let config = Config::default();
+        assert!(config.safe_mode.enabled);
+        assert_eq!(config.safe_mode.window(), Duration::from_secs(600));
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[safe_mode]
+max_crashes = 5
+window_mins = 30
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.safe_mode.max_crashes, 5);
+        assert_eq!(config.safe_mode.interval_ms, 2000);
+
+        config.safe_mode.interval_ms = 100;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("safe_mode.interval_ms")
+        );
+        config.safe_mode.interval_ms = 2000;
+        config.safe_mode.max_crashes = 0;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("safe_mode.max_crashes")
+        );
+    }
+
+    #[test]
+    fn test_peers_config() {
+        let config = Config::default();
+        assert!(config.peers.units.is_empty());
+        assert_eq!(config.peers.poll_interval_secs, 30);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[peers]
+poll_interval_secs = 10
+
+[[peers.units]]
+name = "Liv"
+url = "http://192.168.1.20:8080"
+
+[[peers.units]]
+name = "Bed"
+url = "http://192.168.1.21:8080"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.peers.units[1].name, "Bed");
+        assert_eq!(config.peers.timeout(), Duration::from_secs(5));
+
+        let invalid = |edit: fn(&mut PeersConfig)| {
+            let mut peers = config.peers.clone();
+            edit(&mut peers);
+            peers.validate().unwrap_err()
+        };
+        assert!(invalid(|p| p.units[0].name = "Living".to_string()).contains("1 to 4"));
+        assert!(invalid(|p| p.units[0].url = "https://x".to_string()).contains("http://"));
+        assert!(invalid(|p| p.page_secs = 0).contains("page_secs"));
+    }
+
+    #[test]
+    fn test_insert_timeout() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.insert_timeout(),
+            Some(Duration::from_secs(10))
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+insert_timeout_secs = 0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.database.insert_timeout(), None);
+        assert_eq!(config.database.save_window(), None);
+    }
+
+    #[test]
+    fn test_save_window() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+save_window_secs = 300
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.save_window(),
+            Some(Duration::from_secs(300))
+        );
+        assert!(!config.database.align_to_interval);
+
+        config.database.align_to_interval = true;
+        assert!(config.validate().is_ok());
+        config.database.timestamp_source = TimestampSource::Insertion;
+        assert!(config.validate().is_err());
+        config.database.timestamp_source = TimestampSource::Measurement;
+        config.database.save_window_secs = 0;
+        assert!(config.validate().unwrap_err().contains("save_window_secs"));
+    }
+
+    #[test]
+    fn test_storage_rounding() {
+        assert_eq!(Config::default().database.storage_rounding(), None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+decimals = 2
+rounding = "half_even"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.database.storage_rounding(),
+            Some((2, RoundingMode::HalfEven))
+        );
+
+        let mut database = config.database;
+        database.decimals = Some(7);
+        assert_eq!(
+            database.validate_decimals(),
+            Err("database.decimals must be at most 6, got 7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_comfort_config() {
+        let config = Config::default();
+        assert_eq!(config.comfort.formula, ComfortFormula::Thi);
+        assert_eq!(config.comfort.coefficients(), ThiCoefficients::STANDARD);
+
+        // The coefficients only apply to the custom formula
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[comfort]
+a = 1.0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.comfort.coefficients(), ThiCoefficients::STANDARD);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[comfort]
+formula = "custom"
+a = 0.8
+e = 46.0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.comfort.coefficients(),
+            ThiCoefficients {
+                a: 0.8,
+                e: 46.0,
+                ..ThiCoefficients::STANDARD
+            }
+        );
+        assert!(config.comfort.summary().starts_with("Custom: 0.8*T"));
+    }
+
+    #[test]
+    fn test_comfort_config_rejects_non_finite() {
+        let mut config = Config::default();
+        config.comfort.formula = ComfortFormula::Custom;
+        config.comfort.d = f64::NAN;
+        assert!(config.validate().unwrap_err().contains("comfort.d"));
+        // Ignored while the standard formula is selected
+        config.comfort.formula = ComfortFormula::Thi;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_disk_config() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[disk]
+warn_below_mb = 500
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.disk.warn_below_mb, 500);
+        assert_eq!(config.disk.pause_below_mb, 50);
+
+        let mut config = config;
+        config.disk.pause_below_mb = 600;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("disk.pause_below_mb")
+        );
+    }
+
+    #[test]
+    fn test_alerts_config() {
+        assert!(Config::default().alerts.rules.is_empty());
+        assert!(!Config::default().alerts.history);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[[alerts.rules]]
+name = "HOT"
+field = "temperature_c"
+above = 30.0
+message = "TEMPERATURE {value}C - OPEN A WINDOW"
+
+[alerts.escalation]
+log_after_mins = 10
+webhook_after_mins = 30
+webhook_url = "http://127.0.0.1:8000/alert"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.alerts.rules[0].field, AlertField::TemperatureC);
+        assert_eq!(config.alerts.rules[0].below, None);
+        assert_eq!(
+            config.alerts.rules[0].message.as_deref(),
+            Some("TEMPERATURE {value}C - OPEN A WINDOW")
+        );
+        assert_eq!(config.alerts.escalation.log_after_mins, 10);
+
+        let invalid = |edit: fn(&mut AlertsConfig)| {
+            let mut alerts = config.alerts.clone();
+            edit(&mut alerts);
+            alerts.validate().unwrap_err()
+        };
+        assert!(invalid(|a| a.rules[0].above = None).contains("above"));
+        assert!(invalid(|a| a.rules[0].above = Some(f64::NAN)).contains("finite"));
+        assert!(invalid(|a| a.rules.push(a.rules[0].clone())).contains("twice"));
+        assert!(
+            invalid(|a| {
+                a.rules[0].kind = AlertRuleKind::BandChange;
+                a.rules[0].above = None;
+                a.rules[0].band = Some(BandChangeConfig {
+                    width: 2.0,
+                    ..BandChangeConfig::default()
+                });
+                a.rules[0].message = Some("HOT {value}C".to_string());
+            })
+            .contains("message")
+        );
+        assert!(invalid(|a| a.escalation.webhook_after_mins = 5).contains("log_after_mins"));
+        assert!(
+            invalid(|a| a.escalation.webhook_url = Some("https://x".to_string()))
+                .contains("http://")
+        );
+    }
+
+    #[test]
+    fn test_band_change_rule() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[[alerts.rules]]
+name = "TEMP"
+type = "band_change"
+field = "temperature_c"
+band = { width = 2, settle_secs = 120 }
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        let rule = &config.alerts.rules[0];
+        assert_eq!(rule.kind, AlertRuleKind::BandChange);
+        let band = rule.band.as_ref().unwrap();
+        assert_eq!(band.width, 2.0);
+        assert_eq!(band.settle_secs, 120);
+        assert_eq!(band.origin, 0.0);
+        assert_eq!(band.hysteresis, 0.2);
+
+        let invalid = |edit: fn(&mut AlertRuleConfig)| {
+            let mut alerts = config.alerts.clone();
+            edit(&mut alerts.rules[0]);
+            alerts.validate().unwrap_err()
+        };
+        assert!(invalid(|r| r.band = None).contains("needs `band`"));
+        assert!(invalid(|r| r.above = Some(30.0)).contains("no `above`"));
+        assert!(invalid(|r| r.band.as_mut().unwrap().width = 0.0).contains("width"));
+        assert!(invalid(|r| r.band.as_mut().unwrap().hysteresis = 0.5).contains("hysteresis"));
+        assert!(invalid(|r| r.kind = AlertRuleKind::Threshold).contains("band_change"));
+    }
+
+    #[test]
+    fn test_backward_timestamps() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.backward_timestamps,
+            BackwardTimestamps::Clamp
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+backward_timestamps = "drop"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.backward_timestamps,
+            BackwardTimestamps::Drop
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+backward_timestamps = "bump"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.database.backward_timestamps,
+            BackwardTimestamps::Bump
+        );
+    }
+
+    #[test]
+    fn test_validation_config() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.database.validation.enabled);
+        assert_eq!(config.database.validation.temperature_c, [-40.0, 85.0]);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[database.validation]
+temperature_c = [-10, 50]
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.database.validation.temperature_c, [-10.0, 50.0]);
+        assert_eq!(config.database.validation.pressure_pa, [30000.0, 110000.0]);
+
+        config.database.validation.humidity_relative = [100.0, 0.0];
+        assert!(config.validate().unwrap_err().contains("humidity_relative"));
+    }
+
+    #[test]
+    fn test_sqlite_config() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        // Every row is committed on its own unless opted in
+        assert_eq!(config.database.sqlite, SqliteConfig::default());
+        assert_eq!(config.database.sqlite.commit_interval(), None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[database.sqlite]
+commit_interval_secs = 60
+batch_size = 50
+synchronous = "normal"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let sqlite = &config.database.sqlite;
+        assert_eq!(sqlite.commit_interval(), Some(Duration::from_secs(60)));
+        assert_eq!(sqlite.batch_size, 50);
+        assert_eq!(sqlite.synchronous, Some(SqliteSynchronous::Normal));
+        assert_eq!(
+            SqliteSynchronous::Normal.pragma(),
+            "PRAGMA synchronous = NORMAL"
+        );
+    }
+
+    #[test]
+    fn test_clock_config_default_when_missing() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.clock.min_valid_year, 2020);
+        assert!(config.clock.skip_db_when_unsynced);
+        assert_eq!(config.clock.missed_ticks, MissedTicks::Skip);
+        assert_eq!(config.clock.first_tick, FirstTick::AfterInterval);
+        assert_eq!(config.clock.forced_timezone(), Ok(None));
+    }
+
+    #[test]
+    fn test_clock_config_deserialization() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[clock]
+min_valid_year = 2024
+skip_db_when_unsynced = false
+missed_ticks = "catch_up"
+first_tick = "immediate"
+timezone = "Europe/Berlin"
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.clock.min_valid_year, 2024);
+        assert!(!config.clock.skip_db_when_unsynced);
+        assert_eq!(config.clock.missed_ticks, MissedTicks::CatchUp);
+        assert_eq!(config.clock.first_tick, FirstTick::Immediate);
+        assert_eq!(
+            config.clock.forced_timezone(),
+            Ok(Some(chrono_tz::Europe::Berlin))
+        );
+
+        config.clock.timezone = Some("Mars/Olympus_Mons".to_string());
+        assert!(config.validate().unwrap_err().contains("clock.timezone"));
+    }
+
+    #[test]
+    fn test_display_config_default() {
+        let config = Config::default();
+        assert_eq!(config.display.driver, DisplayType::So1602a);
+        assert_eq!(config.display.address, None);
+        assert_eq!(config.display.contrast, 0x7F);
+        assert_eq!(config.display.rounding_hysteresis, 0.3);
+        assert_eq!(config.display.write_retries, 1);
+        assert_eq!(
+            config.display.measurement_format(),
+            MeasurementFormat::default()
+        );
+        // No fade-in unless configured, for the fastest boot
+        assert!(config.display.contrast_ramp().is_zero());
+    }
+
+    #[test]
+    fn test_display_config_precision() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+temperature_decimals = 2
+humidity_decimals = 0
+rounding = "half_even"
+layout = "compact"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        let format = config.display.measurement_format();
+        assert_eq!(format.layout, MeasurementLayout::Compact);
+        assert_eq!(format.temperature_decimals, 2);
+        assert_eq!(format.humidity_decimals, 0);
+        assert_eq!(format.rounding, RoundingMode::HalfEven);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+humidity_decimals = 3
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().unwrap_err().contains("humidity_decimals"));
+    }
+
+    #[test]
+    fn test_display_config_thi_range() {
+        let config = Config::default();
+        assert_eq!(
+            config.display.measurement_format().thi_range,
+            THI_DISPLAY_LIMITS
+        );
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+thi_min = 0
+thi_max = 99
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.display.measurement_format().thi_range, (0, 99));
+
+        for (min, max) in [(-100, 99), (0, 1000), (50, 40)] {
+            let mut config = Config::default();
+            config.display.thi_min = min;
+            config.display.thi_max = max;
+            assert!(config.validate().unwrap_err().contains("thi_min"));
+        }
+    }
+
+    #[test]
+    fn test_display_config_contrast_ramp() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+contrast = 0xC0
+contrast_ramp_ms = 1500
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.display.contrast, 0xC0);
+        assert_eq!(config.display.contrast_ramp(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_display_config_hd44780() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+type = "hd44780"
+address = 0x3F
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.display.driver, DisplayType::Hd44780);
+        assert_eq!(config.display.address, Some(0x3F));
+    }
+
+    #[test]
+    fn test_display_config_unknown_type() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+type = "vfd"
+"#;
+        let result: Result<Config, _> = toml::from_str(toml_str);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_custom_chars_default() {
+        let config = Config::default();
+        let bitmaps = config.display.custom_char_bitmaps().unwrap();
+        assert_eq!(
+            bitmaps,
+            vec![(
+                1,
+                [
+                    0b00000,
+                    0b10000,
+                    0b01000,
+                    0b00100,
+                    0b00010,
+                    0b00001,
+                    0b00000,
+                    0b00000
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_custom_chars_art_form() {
+        let toml_str = r##"
+[database]
+url = "sqlite:./test.db"
+
+[[display.custom_chars]]
+index = 2
+rows = [".....", ".#.#.", ".....", "#...#", ".###.", ".....", ".....", "....."]
+"##;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        let bitmaps = config.display.custom_char_bitmaps().unwrap();
+        // Defining custom_chars replaces the default set
+        assert_eq!(bitmaps.len(), 1);
+        assert_eq!(bitmaps[0].0, 2);
+        assert_eq!(bitmaps[0].1[1], 0b01010);
+        assert_eq!(bitmaps[0].1[4], 0b01110);
+    }
+
+    #[test]
+    fn test_custom_chars_removed() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[display]
+custom_chars = []
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.display.custom_char_bitmaps().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_custom_chars_validation() {
+        let custom_char = |index: u8, rows: &[&str]| CustomCharConfig {
+            index,
+            rows: rows.iter().map(|r| r.to_string()).collect(),
+        };
+        let rows = ["00000"; 8];
+
+        assert!(custom_char(7, &rows).bitmap().is_ok());
+        assert!(custom_char(8, &rows).bitmap().is_err());
+        assert!(custom_char(0, &rows[..7]).bitmap().is_err());
+        assert!(
+            custom_char(0, &["000000", "0", "0", "0", "0", "0", "0", "0"])
+                .bitmap()
+                .is_err()
+        );
+        assert!(
+            custom_char(
+                0,
+                &[
+                    "0000x",
+                    "00000",
+                    "00000",
+                    "00000",
+                    "00000",
+                    "00000",
+                    "00000",
+                    "00000"
+                ]
+            )
+            .bitmap()
+            .is_err()
+        );
+
+        let mut config = Config::default();
+        config.display.custom_chars = vec![custom_char(3, &rows), custom_char(3, &rows)];
+        assert!(config.validate().is_err());
+
+        // Nine characters never fit, eight leave no slot for the indicator
+        config.display.custom_chars = (0..9).map(|i| custom_char(i % 8, &rows)).collect();
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("defines 9 characters")
+        );
+        config.display.custom_chars = (0..8).map(|i| custom_char(i, &rows)).collect();
+        assert!(config.validate().is_err());
+        config.display.custom_chars[1].rows = [
+            "00000",
+            "10000",
+            "01000",
+            "00100",
+            "00010",
+            "00001",
+            "00000",
+            "00000",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(config.display.cgram_slots().unwrap().indicator(), 1);
+    }
+
+    #[test]
+    fn test_sensor_config_default_when_missing() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.sensor, SensorConfig::default());
+        assert_eq!(config.sensor.settings(), Bme280Settings::default());
+        assert!(config.http.listen.is_none());
+    }
+
+    #[test]
+    fn test_http_kiosk_bind() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[http]
+listen = "127.0.0.1:8080"
+kiosk_bind = "0.0.0.0:8081"
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.http.kiosk_bind.as_deref(), Some("0.0.0.0:8081"));
+        assert!(config.validate().is_ok());
+
+        config.http.kiosk_bind = Some("127.0.0.1:8080".to_string());
+        assert!(config.validate().unwrap_err().contains("kiosk_bind"));
+        // The kiosk alone is fine
+        config.http.listen = None;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_sensor_config_validation() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensor]
+oversampling_temperature = 2
+oversampling_pressure = 16
+filter = 4
+temperature_offset_c = -1.5
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sensor.settings().oversampling_pressure, 16);
+
+        let sensor = SensorConfig {
+            filter: 3,
+            humidity_offset: 50.0,
+            ..SensorConfig::default()
+        };
+        let errors = sensor.validate().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].contains("humidity_offset"));
+    }
+
+    #[test]
+    fn test_sensor_interval_config() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensor]
+interval_ms = 1000
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sensor.interval(), Duration::from_secs(1));
+        assert_eq!(config.sensor.filter, SensorConfig::default().filter);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.sensor.interval_ms, 200);
+
+        let sensor = SensorConfig {
+            interval_ms: 10,
+            ..SensorConfig::default()
+        };
+        assert!(sensor.validate().unwrap_err()[0].contains("interval_ms"));
+    }
+
+    #[test]
+    fn test_sensor_sea_level_config() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensor]
+sea_level_pa = 102000.0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sensor.sea_level_pa, 102000.0);
+        assert_eq!(SensorConfig::default().sea_level_pa, 101325.0);
+
+        let sensor = SensorConfig {
+            sea_level_pa: 1013.25,
+            ..SensorConfig::default()
+        };
+        assert!(sensor.validate().unwrap_err()[0].contains("sea_level_pa"));
+    }
+
+    #[test]
+    fn test_apply_offsets() {
+        let sensor = SensorConfig {
+            temperature_offset_c: -1.5,
+            humidity_offset: 5.0,
+            pressure_offset_pa: 120.0,
+            ..SensorConfig::default()
+        };
+        let measurement = sensor.apply_offsets(Measurement {
+            temperature_c: 25.0,
+            pressure_pa: 100000.0,
+            humidity_relative: 97.0,
+        });
+        assert_eq!(measurement.temperature_c, 23.5);
+        assert_eq!(measurement.pressure_pa, 100120.0);
+        assert_eq!(measurement.humidity_relative, 100.0);
+    }
+
+    #[test]
+    fn test_save_sensor_section() {
+        let dir = std::env::temp_dir().join(format!("wbroker-test-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wbroker-rs.toml");
+        fs::write(
+            &path,
+            "[database]\nurl = \"sqlite:./test.db\"\n\n[sensor]\nfilter = 2\n",
+        )
+        .unwrap();
+
+        let sensor = SensorConfig {
+            filter: 16,
+            temperature_offset_c: 0.5,
+            ..SensorConfig::default()
+        };
+        Config::save_sensor_section(&path, &sensor).unwrap();
+
+        let config = Config::load_from_file(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(config.database.url, "sqlite:./test.db");
+        assert_eq!(config.sensor, sensor);
+    }
+
+    #[test]
+    fn test_screensaver_config() {
+        let config = Config::default();
+        assert_eq!(config.screensaver.idle_timeout(), None);
+        assert_eq!(config.screensaver.wake_pin, None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[screensaver]
+idle_timeout_secs = 300
+wake_pin = 17
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.screensaver.idle_timeout(),
+            Some(std::time::Duration::from_secs(300))
+        );
+        assert_eq!(config.screensaver.wake_pin, Some(17));
+    }
+
+    #[test]
+    fn test_sensors_config() {
+        let config = Config::default();
+        assert_eq!(config.sensors.label, "bme280");
+        assert!(config.sensors.extra.is_empty());
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensors]
+label = "main"
+
+[[sensors.extra]]
+address = 0x77
+label = "reference"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.sensors.label, "main");
+        assert_eq!(
+            config.sensors.extra,
+            vec![ExtraSensorConfig {
+                driver: SensorType::Bme280,
+                address: Some(0x77),
+                label: "reference".to_string(),
+                profile: None,
+            }]
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_sensor_profiles() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensors]
+label = "living"
+profile = "indoor"
+
+[[sensors.extra]]
+label = "garden"
+profile = "outdoor"
+
+[[sensors.extra]]
+label = "reference"
+
+[sensors.profiles.indoor]
+temperature_c = [5, 35]
+humidity_relative = [10, 90]
+pressure_pa = [90000, 105000]
+self_heating_c = 1.5
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        let profiled: Vec<_> = config.sensors.profiled().collect();
+        assert_eq!(profiled.len(), 2);
+        assert_eq!(profiled[0].0, "living");
+        assert_eq!(profiled[0].1.temperature_c, [5.0, 35.0]);
+        // Built-in when not overridden
+        assert_eq!(profiled[1].0, "garden");
+        assert_eq!(profiled[1].1.temperature_c, [-40.0, 60.0]);
+        assert_eq!(config.sensors.self_heating_c(config.sensors.profile), 1.5);
+        assert_eq!(config.sensors.self_heating_c(None), 0.0);
+
+        config.sensors.profiles.outdoor.self_heating_c = -1.0;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("outdoor.self_heating_c")
+        );
+        config.sensors.profiles.outdoor.self_heating_c = 0.0;
+        config.sensors.profiles.custom.pressure_pa = [110000.0, 30000.0];
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("custom.pressure_pa")
+        );
+    }
+
+    #[test]
+    fn test_sensors_config_duplicate_label() {
+        let mut config = Config::default();
+        config.sensors.extra.push(ExtraSensorConfig {
+            driver: SensorType::Bme280,
+            address: None,
+            label: "bme280".to_string(),
+            profile: None,
+        });
+        assert!(config.validate().unwrap_err().contains("bme280"));
+
+        config.sensors.extra[0].label = " ".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_sensors_config_samples() {
+        let mut config = Config::default();
+        assert_eq!(config.sensors.samples, 1);
+        for samples in [0, 2] {
+            config.sensors.samples = samples;
+            assert!(config.validate().unwrap_err().contains("samples"));
+        }
+        config.sensors.samples = 3;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_sensors_config_stuck_after() {
+        let mut config = Config::default();
+        assert_eq!(config.sensors.stuck_after, 50);
+        config.sensors.stuck_after = 1;
+        assert!(config.validate().unwrap_err().contains("stuck_after"));
+        config.sensors.stuck_after = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_sensors_config_type() {
+        assert_eq!(Config::default().sensors.driver, SensorType::Bme280);
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensors]
+type = "constant"
+
+[[sensors.extra]]
+type = "constant"
+label = "reference"
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.sensors.driver, SensorType::Constant);
+        assert_eq!(config.sensors.extra[0].driver, SensorType::Constant);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sensors.without_pressure().count(), 0);
+
+        let extra: ExtraSensorConfig =
+            toml::from_str("type = \"sht31\"\nlabel = \"humidity\"").unwrap();
+        assert_eq!(extra.driver, SensorType::Sht31);
+        config.sensors.extra.push(extra);
+        let extra: ExtraSensorConfig =
+            toml::from_str("type = \"aht20\"\nlabel = \"closet\"").unwrap();
+        assert_eq!(extra.driver, SensorType::Aht20);
+        config.sensors.extra.push(extra);
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.sensors.without_pressure().collect::<Vec<_>>(),
+            vec!["humidity", "closet"]
+        );
+    }
+
+    #[test]
+    fn test_sensors_config_auto_detect() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensors]
+auto_detect = true
+
+[[sensors.extra]]
+label = "reference"
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert!(config.sensors.auto_detect);
+        assert!(config.validate().is_ok());
+
+        config.sensors.extra[0].address = Some(0x77);
+        let message = config.validate().unwrap_err();
+        assert!(message.contains("auto_detect"));
+        assert!(message.contains("\"reference\" at 0x77"));
+        config.sensors.extra[0].address = Some(0x78);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_quality_config() {
+        let config = Config::default();
+        assert_eq!(config.quality, QualityConfig::default());
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[quality]
+window = 50
+suspect_failures = 5
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.quality.window, 50);
+        assert_eq!(config.quality.suspect_failures, 5);
+        assert_eq!(config.quality.boundary_margin, 0.01);
+    }
+
+    #[test]
+    fn test_hooks_config() {
+        assert_eq!(Config::default().hooks.after_insert, None);
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[hooks]
+after_insert = "/usr/local/bin/notify {json}"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.hooks.after_insert.as_deref(),
+            Some("/usr/local/bin/notify {json}")
+        );
+        assert_eq!(config.hooks.after_insert_fields, FieldGroup::ALL);
+    }
+
+    #[test]
+    fn test_hooks_config_fields() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[hooks]
+after_insert_fields = ["derived"]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.hooks.after_insert_fields, vec![FieldGroup::Derived]);
+        assert!(config.validate().is_ok());
+
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[hooks]
+after_insert_fields = []
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("after_insert_fields")
+        );
     }
 
     #[test]
@@ -0,0 +1,373 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writes of the config file.
+//!
+//! Every change to the config file goes through `update`: the API's
+//! persisted sensor settings and `drift-report --apply`. The file is
+//! edited rather than rewritten, so only the changed values are replaced
+//! and the comments and layout around them are kept.
+//!
+//! A write never leaves the file truncated or half written. The new
+//! content goes to a temp file next to it, which is synced and renamed
+//! over the file; a crash before the rename leaves the old file and a
+//! stale temp file, which the next write replaces. The previous file is
+//! kept as one timestamped backup, `<name>.<time>.bak`, and older backups
+//! are removed. Writers, also of other processes, are excluded by a lock
+//! on `<name>.lock`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::config::Config;
+use crate::error::ConfigError;
+
+/// Change the config in a config file.
+/// # Arguments
+/// * `path` - Config file.
+/// * `change` - Change of the loaded config.
+/// # Returns
+/// * `Err(e)` if the file cannot be read or written, or the changed config
+///   is invalid; the file is unchanged then.
+pub fn update<P, F>(path: P, change: F) -> Result<(), ConfigError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut Config),
+{
+    let path = path.as_ref();
+    let write_error = |source| ConfigError::Write {
+        path: path.display().to_string(),
+        source,
+    };
+    let _lock = WriteLock::acquire(path).map_err(write_error)?;
+    let content = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let content = edit(path, &content, change)?;
+    let pending = PendingWrite::prepare(path, &content).map_err(write_error)?;
+    pending.commit().map_err(write_error)
+}
+
+/// Apply a change to the content of a config file.
+/// # Arguments
+/// * `path` - Config file, for errors.
+/// * `content` - Its content.
+/// * `change` - Change of the loaded config.
+/// # Returns
+/// * The new content.
+fn edit<F>(path: &Path, content: &str, change: F) -> Result<String, ConfigError>
+where
+    F: FnOnce(&mut Config),
+{
+    let invalid = |message: String| ConfigError::Invalid {
+        path: path.display().to_string(),
+        message,
+    };
+    let serialize = |source| ConfigError::Serialize { source };
+    let mut config: Config =
+        toml::from_str(content).map_err(|source| ConfigError::parse(path, content, source))?;
+    let before = toml::to_string(&config).map_err(serialize)?;
+    change(&mut config);
+    config.validate().map_err(invalid)?;
+    let after = toml::to_string(&config).map_err(serialize)?;
+
+    let parse = |text: &str| {
+        text.parse::<DocumentMut>()
+            .map_err(|e| invalid(e.to_string()))
+    };
+    let mut document = parse(content)?;
+    let (before, after) = (parse(&before)?, parse(&after)?);
+    apply_changes(document.as_table_mut(), before.as_table(), after.as_table());
+    Ok(document.to_string())
+}
+
+/// Copy the values which differ between two serialized configs into a
+/// document, keeping the formatting of the values it already has.
+/// # Arguments
+/// * `document` - Table of the edited document.
+/// * `before` - Same table of the config before the change.
+/// * `after` - Same table of the config after the change.
+fn apply_changes(document: &mut dyn TableLike, before: &dyn TableLike, after: &dyn TableLike) {
+    for (key, new) in after.iter() {
+        let old = before.get(key);
+        if old.is_some_and(|old| old.to_string() == new.to_string()) {
+            continue;
+        }
+        let tables = (old.and_then(Item::as_table_like), new.as_table_like());
+        if let (Some(old), Some(new)) = tables {
+            if document.get(key).is_none() {
+                document.insert(key, Item::Table(Default::default()));
+            }
+            if let Some(table) = document.get_mut(key).and_then(Item::as_table_like_mut) {
+                apply_changes(table, old, new);
+                continue;
+            }
+        }
+        let mut new = new.clone();
+        match document.get_mut(key) {
+            // Replaced in place, the comments before the key stay with it
+            Some(existing) => {
+                if let (Some(value), Item::Value(old)) = (new.as_value_mut(), &*existing) {
+                    *value.decor_mut() = old.decor().clone();
+                }
+                *existing = new;
+            }
+            None => {
+                document.insert(key, new);
+            }
+        }
+    }
+    let removed: Vec<String> = before
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !after.contains_key(key))
+        .collect();
+    for key in removed {
+        document.remove(&key);
+    }
+}
+
+/// Exclusive lock of the writers of a config file, released on drop.
+struct WriteLock {
+    _file: File,
+}
+
+impl WriteLock {
+    /// Wait for the lock of a config file.
+    /// # Arguments
+    /// * `path` - Config file.
+    fn acquire(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(path, ".lock"))?;
+        // SAFETY: flock only reads the descriptor, which the file owns
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// New content written to the temp file, not yet in place.
+/// Dropped without `commit` the temp file is removed.
+struct PendingWrite {
+    path: PathBuf,
+    temp: PathBuf,
+    committed: bool,
+}
+
+impl PendingWrite {
+    /// Write and sync the temp file of a config file.
+    /// # Arguments
+    /// * `path` - Config file.
+    /// * `content` - New content.
+    fn prepare(path: &Path, content: &str) -> io::Result<Self> {
+        let pending = Self {
+            path: path.to_path_buf(),
+            temp: temp_path(path),
+            committed: false,
+        };
+        let mut file = File::create(&pending.temp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        Ok(pending)
+    }
+
+    /// Back up the config file and move the temp file in its place.
+    fn commit(mut self) -> io::Result<()> {
+        let backup = sibling(
+            &self.path,
+            &format!(".{}.bak", Local::now().format("%Y%m%dT%H%M%S")),
+        );
+        fs::copy(&self.path, &backup)?;
+        fs::rename(&self.temp, &self.path)?;
+        self.committed = true;
+        if let Some(dir) = parent(&self.path) {
+            File::open(dir)?.sync_all()?;
+        }
+        remove_old_backups(&self.path, &backup)
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Directory of a file, `None` for a bare file name in the current one.
+fn parent(path: &Path) -> Option<&Path> {
+    path.parent().filter(|dir| !dir.as_os_str().is_empty())
+}
+
+/// Path next to a file, its name with a suffix.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Temp file of a config file, hidden in the same directory so the rename
+/// stays on one filesystem.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Remove the backups of a config file other than the latest one.
+/// # Arguments
+/// * `path` - Config file.
+/// * `keep` - Latest backup.
+fn remove_old_backups(path: &Path, keep: &Path) -> io::Result<()> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(());
+    };
+    let prefix = format!("{}.", name);
+    for entry in fs::read_dir(parent(path).unwrap_or(Path::new(".")))? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        let is_backup = file_name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".bak"))
+            .is_some_and(|time| time.len() == 15 && time.as_bytes()[8] == b'T');
+        if is_backup && entry.path().file_name() != keep.file_name() {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wbroker-rs-config-file-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn backups(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bak"))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_keeps_comments() {
+        let dir = test_dir("comments");
+        let path = dir.join("wbroker-rs.toml");
+        let original = "# Logging\n[database]\nurl = \"sqlite:./test.db\" # local\n\n\
+                        [sensor]\n# Calibrated 2025-06\ntemperature_offset_c = 0.5 # vs. kitchen\n";
+        fs::write(&path, original).unwrap();
+
+        update(&path, |config| {
+            config.sensor.temperature_offset_c = -0.25;
+            config.sensor.filter = 4;
+        })
+        .unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let first = backups(&dir);
+        let backup = fs::read_to_string(dir.join(&first[0])).unwrap();
+
+        update(&path, |config| config.sensor.filter = 8).unwrap();
+        let after_second = backups(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(content.starts_with("# Logging\n[database]\nurl = \"sqlite:./test.db\" # local\n"));
+        assert!(
+            content.contains("# Calibrated 2025-06\ntemperature_offset_c = -0.25 # vs. kitchen\n")
+        );
+        assert!(content.contains("filter = 4"));
+        assert_eq!(content.matches('=').count(), 3);
+        assert_eq!(backup, original);
+        // One backup is kept
+        assert_eq!(after_second.len(), 1);
+    }
+
+    #[test]
+    fn test_update_example_config_unchanged() {
+        let dir = test_dir("example");
+        let path = dir.join("wbroker-rs.toml");
+        let original = include_str!("../externals/wbroker-rs.toml");
+        fs::write(&path, original).unwrap();
+        update(&path, |_| {}).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, original);
+    }
+
+    #[test]
+    fn test_crash_before_rename_keeps_file() {
+        let dir = test_dir("crash");
+        let path = dir.join("wbroker-rs.toml");
+        let original = "[database]\nurl = \"sqlite:./test.db\"\n\n[sensor]\nfilter = 2\n";
+        fs::write(&path, original).unwrap();
+
+        // The process dies after the temp file is written
+        let pending = PendingWrite::prepare(&path, "[sensor]\nfilter = 16\n").unwrap();
+        std::mem::forget(pending);
+        let after_crash = fs::read_to_string(&path).unwrap();
+        let stale = fs::read_to_string(temp_path(&path)).unwrap();
+
+        // A failed write leaves nothing behind
+        let invalid = update(&path, |config| config.sensor.filter = 3);
+        let after_invalid = fs::read_to_string(&path).unwrap();
+
+        // The next write replaces the stale temp file
+        update(&path, |config| config.sensor.filter = 4).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let temp_left = temp_path(&path).exists();
+        let kept = backups(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(after_crash, original);
+        assert_eq!(stale, "[sensor]\nfilter = 16\n");
+        assert!(matches!(invalid, Err(ConfigError::Invalid { .. })));
+        assert_eq!(after_invalid, original);
+        assert_eq!(content, original.replace("filter = 2", "filter = 4"));
+        assert!(!temp_left);
+        assert_eq!(kept.len(), 1);
+    }
+}
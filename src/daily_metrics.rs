@@ -0,0 +1,535 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Degree-hours and mold risk.
+//!
+//! Heating and cooling degree-hours add up how far and how long the
+//! temperature stays below or above a base temperature during the day. The
+//! mold-risk index is the number of hours the relative humidity was above a
+//! threshold in the last 24 hours.
+//!
+//! Readings reach the accumulator task through a watch channel, so a slow
+//! database never holds up the measurement loop. A reading replaced before
+//! the task saw it is bridged by the interval to the next one. Values are
+//! taken as linear between two readings and integrated exactly, including
+//! the part of an interval on either side of the base or the threshold.
+//! Intervals longer than `MAX_GAP`, e.g. while the daemon was stopped, are
+//! not counted.
+//!
+//! Only live readings are counted. Burst capture ticks are not sent, so a
+//! capture is bridged like a missed reading, and replay and soak runs never
+//! reach the accumulator.
+//!
+//! Midnight is taken from the reading timestamps in the local timezone
+//! (`TZ`), and the day only moves forward:
+//! an interval crossing midnight is split between the two days, and a
+//! reading dated before the previous one, after the clock stepped back, is
+//! not integrated. The totals of a day are written to the `daily_metrics`
+//! table when it closes and at shutdown, and read back at start-up so a
+//! restart continues the day. The 24 hour humidity window starts empty.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use peripheral::bme280::Measurement;
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::config::DailyMetricsConfig;
+use crate::database::DailyMetricsTable;
+use crate::supervisor::Supervisor;
+use crate::trace::TraceControl;
+
+/// Longest interval between two readings which is integrated.
+pub const MAX_GAP: TimeDelta = TimeDelta::hours(1);
+
+/// Window of the mold-risk index.
+const MOLD_WINDOW: TimeDelta = TimeDelta::hours(24);
+
+/// Reading fed to the accumulator.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub at: DateTime<Local>,
+    /// Measurement with offsets applied.
+    pub measurement: Measurement,
+    /// Tick of the measurement loop which read it, for the debug trace.
+    pub tick: u64,
+}
+
+/// Totals of one day, as stored in the `daily_metrics` table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyTotals {
+    pub day: NaiveDate,
+    /// Kelvin-hours below the base temperature.
+    pub heating_degree_hours: f64,
+    /// Kelvin-hours above the base temperature.
+    pub cooling_degree_hours: f64,
+    /// Hours above the mold humidity threshold.
+    pub humid_hours: f64,
+    /// Hours with readings, to tell a complete day from a partial one.
+    pub covered_hours: f64,
+}
+
+impl DailyTotals {
+    /// Empty totals.
+    /// # Arguments
+    /// * `day` - Day of the totals.
+    pub fn new(day: NaiveDate) -> Self {
+        Self {
+            day,
+            heating_degree_hours: 0.0,
+            cooling_degree_hours: 0.0,
+            humid_hours: 0.0,
+            covered_hours: 0.0,
+        }
+    }
+}
+
+/// Current values, for the page and `/api/current`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyMetricsStatus {
+    /// Heating degree-hours of the day so far.
+    pub heating_degree_hours: f64,
+    /// Cooling degree-hours of the day so far.
+    pub cooling_degree_hours: f64,
+    /// Hours above the mold humidity threshold in the last 24 hours.
+    pub mold_risk_hours: f64,
+}
+
+/// Integrates the readings into daily totals.
+#[derive(Debug)]
+pub struct Accumulator {
+    base_temperature_c: f64,
+    mold_humidity_relative: f64,
+    today: DailyTotals,
+    last: Option<Sample>,
+    /// Humid hours of each integrated interval, by the end of the interval.
+    humid: VecDeque<(DateTime<Local>, f64)>,
+}
+
+impl Accumulator {
+    /// Create an accumulator.
+    /// # Arguments
+    /// * `config` - Base temperature and humidity threshold.
+    /// * `today` - Totals to continue, e.g. read back after a restart.
+    pub fn new(config: &DailyMetricsConfig, today: DailyTotals) -> Self {
+        Self {
+            base_temperature_c: config.base_temperature_c,
+            mold_humidity_relative: config.mold_humidity_relative,
+            today,
+            last: None,
+            humid: VecDeque::new(),
+        }
+    }
+
+    /// Totals of the current day so far.
+    pub fn today(&self) -> DailyTotals {
+        self.today
+    }
+
+    /// Current values, the mold risk as of the latest reading.
+    pub fn status(&self) -> DailyMetricsStatus {
+        let since = self.last.map(|last| last.at - MOLD_WINDOW);
+        DailyMetricsStatus {
+            heating_degree_hours: self.today.heating_degree_hours,
+            cooling_degree_hours: self.today.cooling_degree_hours,
+            mold_risk_hours: self
+                .humid
+                .iter()
+                .filter(|(end, _)| since.is_none_or(|since| *end > since))
+                .map(|(_, hours)| hours)
+                .sum(),
+        }
+    }
+
+    /// Integrate the interval since the previous reading.
+    /// # Arguments
+    /// * `sample` - Latest reading.
+    /// # Returns
+    /// * Totals of the days this reading closed, oldest first.
+    pub fn push(&mut self, sample: Sample) -> Vec<DailyTotals> {
+        let mut closed = Vec::new();
+        if let Some(last) = self.last.replace(sample) {
+            let span = sample.at - last.at;
+            if span > TimeDelta::zero() && span <= MAX_GAP {
+                self.integrate(last, sample, &mut closed);
+            }
+        }
+        self.roll_to(sample.at.date_naive(), &mut closed);
+        let since = sample.at - MOLD_WINDOW;
+        while self.humid.front().is_some_and(|(end, _)| *end <= since) {
+            self.humid.pop_front();
+        }
+        closed
+    }
+
+    /// Integrate an interval, split at every midnight it crosses.
+    fn integrate(&mut self, from: Sample, to: Sample, closed: &mut Vec<DailyTotals>) {
+        let mut start = from;
+        while start.at < to.at {
+            let end = match next_midnight(start.at) {
+                Some(midnight) if midnight < to.at => interpolate(&from, &to, midnight),
+                _ => to,
+            };
+            self.roll_to(start.at.date_naive(), closed);
+            self.add(&start, &end);
+            start = end;
+        }
+    }
+
+    /// Add a part of an interval within one day. Parts with a value which
+    /// is not a number are skipped.
+    fn add(&mut self, start: &Sample, end: &Sample) {
+        let (a, b) = (&start.measurement, &end.measurement);
+        let values = [
+            a.temperature_c,
+            b.temperature_c,
+            a.humidity_relative,
+            b.humidity_relative,
+        ];
+        if !values.iter().all(|v| v.is_finite()) {
+            return;
+        }
+        let hours = (end.at - start.at).as_seconds_f64() / 3600.0;
+        let base = self.base_temperature_c;
+        let threshold = self.mold_humidity_relative;
+        let humid = hours
+            * above(
+                a.humidity_relative - threshold,
+                b.humidity_relative - threshold,
+            )
+            .0;
+        let totals = &mut self.today;
+        totals.heating_degree_hours +=
+            hours * above(base - a.temperature_c, base - b.temperature_c).1;
+        totals.cooling_degree_hours +=
+            hours * above(a.temperature_c - base, b.temperature_c - base).1;
+        totals.humid_hours += humid;
+        totals.covered_hours += hours;
+        if humid > 0.0 {
+            self.humid.push_back((end.at, humid));
+        }
+    }
+
+    /// Close the current day if `day` is later. Earlier days are added to
+    /// the current one.
+    fn roll_to(&mut self, day: NaiveDate, closed: &mut Vec<DailyTotals>) {
+        if day > self.today.day {
+            closed.push(self.today);
+            self.today = DailyTotals::new(day);
+        }
+    }
+}
+
+/// Positive part of a value changing linearly from `a` to `b` over an
+/// interval of length 1.
+/// # Returns
+/// * `(time, area)` - Fraction of the interval the value is above 0, and
+///   the integral of the value over that time.
+fn above(a: f64, b: f64) -> (f64, f64) {
+    if a >= 0.0 && b >= 0.0 {
+        return (1.0, (a + b) / 2.0);
+    }
+    if a <= 0.0 && b <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    let time = high / (high - low);
+    (time, high * time / 2.0)
+}
+
+/// First local midnight after `at`.
+/// # Returns
+/// * `None` if the day has no midnight, in a timezone whose DST change
+///   skips it; the interval is then counted for the earlier day.
+fn next_midnight(at: DateTime<Local>) -> Option<DateTime<Local>> {
+    at.date_naive()
+        .succ_opt()?
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+}
+
+/// Reading at `at`, linear between two readings.
+fn interpolate(from: &Sample, to: &Sample, at: DateTime<Local>) -> Sample {
+    let t = (at - from.at).as_seconds_f64() / (to.at - from.at).as_seconds_f64();
+    let mix = |a: f64, b: f64| a + (b - a) * t;
+    let (a, b) = (&from.measurement, &to.measurement);
+    Sample {
+        at,
+        measurement: Measurement {
+            temperature_c: mix(a.temperature_c, b.temperature_c),
+            humidity_relative: mix(a.humidity_relative, b.humidity_relative),
+            pressure_pa: mix(a.pressure_pa, b.pressure_pa),
+        },
+        tick: to.tick,
+    }
+}
+
+/// Accumulator shared by its task, the measurement loop and the HTTP API.
+#[derive(Debug)]
+pub struct DailyMetricsStore {
+    accumulator: RwLock<Accumulator>,
+}
+
+impl DailyMetricsStore {
+    /// Create the store, continuing the totals of today if stored.
+    /// # Arguments
+    /// * `config` - Base temperature and humidity threshold.
+    /// * `table` - Table of the daily totals, if logging.
+    /// * `now` - Current time.
+    pub async fn load(
+        config: &DailyMetricsConfig,
+        table: Option<&DailyMetricsTable>,
+        now: DateTime<Local>,
+    ) -> Self {
+        let day = now.date_naive();
+        let stored = match table {
+            Some(table) => table.load(day).await.unwrap_or_else(|e| {
+                eprintln!("Failed to read the daily metrics of {}: {}", day, e);
+                None
+            }),
+            None => None,
+        };
+        Self {
+            accumulator: RwLock::new(Accumulator::new(
+                config,
+                stored.unwrap_or_else(|| DailyTotals::new(day)),
+            )),
+        }
+    }
+
+    /// Integrate a reading, see `Accumulator::push`.
+    pub fn push(&self, sample: Sample) -> Vec<DailyTotals> {
+        self.accumulator
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(sample)
+    }
+
+    /// Totals of the current day so far.
+    pub fn today(&self) -> DailyTotals {
+        self.accumulator
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .today()
+    }
+
+    /// Current values.
+    pub fn status(&self) -> DailyMetricsStatus {
+        self.accumulator
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .status()
+    }
+}
+
+/// Feed the readings of the watch channel to the store, writing the totals
+/// of every closed day. The task ends when the sender is dropped.
+/// # Arguments
+/// * `store` - Store to feed.
+/// * `samples` - Latest reading of the main sensor.
+/// * `table` - Table of the daily totals, if logging.
+/// * `trace` - Debug trace, told about every reading taken.
+/// * `supervisor` - Supervisor of the task.
+pub fn spawn(
+    store: Arc<DailyMetricsStore>,
+    mut samples: watch::Receiver<Option<Sample>>,
+    table: Option<DailyMetricsTable>,
+    trace: Arc<TraceControl>,
+    supervisor: &mut Supervisor,
+) {
+    supervisor.spawn("daily_metrics", async move {
+        while samples.changed().await.is_ok() {
+            let Some(sample) = *samples.borrow_and_update() else {
+                continue;
+            };
+            let closed = store.push(sample);
+            if trace.is_active() {
+                let days: Vec<String> = closed.iter().map(|t| t.day.to_string()).collect();
+                trace.record(sample.tick, "daily_metrics", json!({ "closed_days": days }));
+            }
+            for totals in closed {
+                let Some(table) = &table else {
+                    continue;
+                };
+                if let Err(e) = table.save(&totals).await {
+                    eprintln!("Failed to save the daily metrics of {}: {}", totals.day, e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::f64::consts::PI;
+
+    fn config() -> DailyMetricsConfig {
+        DailyMetricsConfig {
+            enabled: true,
+            base_temperature_c: 18.0,
+            mold_humidity_relative: 70.0,
+        }
+    }
+
+    fn sample(at: DateTime<Local>, temperature_c: f64, humidity_relative: f64) -> Sample {
+        Sample {
+            at,
+            measurement: Measurement {
+                temperature_c,
+                humidity_relative,
+                pressure_pa: 101_325.0,
+            },
+            tick: 0,
+        }
+    }
+
+    /// Feed one reading a minute over `[start, start + hours]`.
+    /// # Returns
+    /// * Totals of the days closed on the way.
+    fn feed(
+        accumulator: &mut Accumulator,
+        start: DateTime<Local>,
+        hours: i64,
+        profile: impl Fn(f64) -> (f64, f64),
+    ) -> Vec<DailyTotals> {
+        let mut closed = Vec::new();
+        for minute in 0..=hours * 60 {
+            let (temperature_c, humidity_relative) = profile(minute as f64 / 60.0);
+            let at = start + TimeDelta::minutes(minute);
+            closed.extend(accumulator.push(sample(at, temperature_c, humidity_relative)));
+        }
+        closed
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_constant_day() {
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 0, 0, 0).unwrap();
+        let mut accumulator = Accumulator::new(&config(), DailyTotals::new(start.date_naive()));
+
+        let closed = feed(&mut accumulator, start, 24, |_| (15.0, 75.0));
+
+        // The reading at midnight closes the day
+        assert_eq!(closed.len(), 1);
+        let day = closed[0];
+        assert_eq!(day.day, start.date_naive());
+        assert_near(day.heating_degree_hours, 3.0 * 24.0);
+        assert_near(day.cooling_degree_hours, 0.0);
+        assert_near(day.humid_hours, 24.0);
+        assert_near(day.covered_hours, 24.0);
+        assert_eq!(
+            accumulator.today(),
+            DailyTotals::new(day.day.succ_opt().unwrap())
+        );
+        assert_near(accumulator.status().mold_risk_hours, 24.0);
+    }
+
+    #[test]
+    fn test_sine_day() {
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 0, 0, 0).unwrap();
+        let mut accumulator = Accumulator::new(&config(), DailyTotals::new(start.date_naive()));
+
+        // 18 +- 5 C and 60 +- 20 %, once around the day
+        let closed = feed(&mut accumulator, start, 24, |hours| {
+            let phase = (2.0 * PI * hours / 24.0).sin();
+            (18.0 + 5.0 * phase, 60.0 + 20.0 * phase)
+        });
+
+        // Each half of the sine is 5 * 24 / pi K-hours
+        let day = closed[0];
+        assert_near(day.heating_degree_hours, 120.0 / PI);
+        assert_near(day.cooling_degree_hours, 120.0 / PI);
+        // Above 70 % while the sine is above 1/2, a third of the day
+        assert_near(day.humid_hours, 8.0);
+    }
+
+    #[test]
+    fn test_interval_split_at_midnight() {
+        let evening = Local.with_ymd_and_hms(2025, 6, 1, 23, 30, 0).unwrap();
+        let mut accumulator = Accumulator::new(&config(), DailyTotals::new(evening.date_naive()));
+        assert!(accumulator.push(sample(evening, 14.0, 50.0)).is_empty());
+
+        // One hour from 14 to 10 C, split evenly around midnight
+        let closed = accumulator.push(sample(evening + TimeDelta::hours(1), 10.0, 50.0));
+
+        assert_eq!(closed.len(), 1);
+        assert_near(closed[0].heating_degree_hours, 0.5 * (4.0 + 6.0) / 2.0);
+        assert_near(closed[0].covered_hours, 0.5);
+        let today = accumulator.today();
+        assert_eq!(today.day, closed[0].day.succ_opt().unwrap());
+        assert_near(today.heating_degree_hours, 0.5 * (6.0 + 8.0) / 2.0);
+    }
+
+    #[test]
+    fn test_gaps_and_clock_steps_are_not_counted() {
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap();
+        let mut accumulator = Accumulator::new(&config(), DailyTotals::new(start.date_naive()));
+        accumulator.push(sample(start, 8.0, 50.0));
+        // Stopped for two hours
+        accumulator.push(sample(start + TimeDelta::hours(2), 8.0, 50.0));
+        assert_eq!(accumulator.today().covered_hours, 0.0);
+
+        // A missing humidity skips the interval
+        accumulator.push(sample(start + TimeDelta::minutes(150), 8.0, f64::NAN));
+        assert_eq!(accumulator.today().covered_hours, 0.0);
+
+        // Stepped back to the previous day: the day stays
+        let stepped = start - TimeDelta::hours(13);
+        assert!(accumulator.push(sample(stepped, 8.0, 50.0)).is_empty());
+        accumulator.push(sample(stepped + TimeDelta::minutes(30), 8.0, 50.0));
+        let today = accumulator.today();
+        assert_eq!(today.day, start.date_naive());
+        assert_near(today.heating_degree_hours, 5.0);
+    }
+
+    #[test]
+    fn test_mold_risk_covers_last_24_hours() {
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 6, 0, 0).unwrap();
+        let mut accumulator = Accumulator::new(&config(), DailyTotals::new(start.date_naive()));
+
+        // Humid for the first 6 hours, then dry. The minute falling from 80
+        // to 50 % is above 70 % for a third of it.
+        let profile = |hours: f64| (20.0, if hours <= 6.0 { 80.0 } else { 50.0 });
+        let humid = 6.0 + 1.0 / 180.0;
+        let closed = feed(&mut accumulator, start, 20, profile);
+        assert_near(closed[0].humid_hours, humid);
+        assert_near(accumulator.status().mold_risk_hours, humid);
+
+        // A day later the window moved past them
+        feed(&mut accumulator, start + TimeDelta::hours(20), 11, |_| {
+            (20.0, 50.0)
+        });
+        assert_near(accumulator.status().mold_risk_hours, 0.0);
+    }
+}
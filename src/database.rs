@@ -19,53 +19,312 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use chrono::{DateTime, Local};
+use crate::actions::ActionSnapshot;
+use crate::alerts::AlertEpisode;
+use crate::config::{BackwardTimestamps, DatabaseConfig, SqliteSynchronous, TimestampSource};
+use crate::daily_metrics::DailyTotals;
+use crate::error::{DatabaseError, redact_url};
+use crate::helper::RoundingMode;
+use crate::quality::{Plausibility, Quality};
+use crate::sequence::{self, Sequence};
+use crate::trace::TraceControl;
+use chrono::{DateTime, Local, NaiveDate};
 use peripheral::bme280::Measurement;
-use sqlx::AnyPool;
-use std::sync::Once;
-use tokio::sync::mpsc;
-
-type BoxError = Box<dyn std::error::Error + Send + Sync>;
+use serde::Serialize;
+use sqlx::any::{AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, timeout, timeout_at};
+
+/// Called by the writer task for each row once it is stored.
+pub type InsertHook = Box<dyn Fn(&SensorData) + Send + Sync>;
 
 static DRIVER_INIT: Once = Once::new();
 
-fn install_driver_for_url(connection_string: &str) -> Result<(), BoxError> {
+fn install_driver_for_url(connection_string: &str) -> Result<(), DatabaseError> {
     // SQLx 0.8では、個別ドライバー指定よりinstall_default_driversが推奨されている
     // ただし、接続文字列の検証は行う
     if !connection_string.starts_with("postgresql")
         && !connection_string.starts_with("mysql")
         && !connection_string.starts_with("sqlite")
     {
-        return Err("Unsupported database URL scheme".into());
+        return Err(DatabaseError::UnsupportedUrl {
+            url: redact_url(connection_string),
+        });
     }
 
     sqlx::any::install_default_drivers();
     Ok(())
 }
 
+/// Label of the main sensor unless configured otherwise.
+pub const DEFAULT_SENSOR_LABEL: &str = "bme280";
+
+/// Mode which took a row, stored in the `source` column so test runs can be
+/// told apart from real data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Source {
+    /// Regular measurement of the daemon.
+    #[default]
+    Live,
+    /// Burst capture of the daemon.
+    Capture,
+    /// Row of an exported file, stored by `replay --store`.
+    Replay,
+    /// Simulated sensor, e.g. of `soak --simulate`.
+    Simulated,
+}
+
+impl Source {
+    pub const ALL: [Source; 4] = [
+        Source::Live,
+        Source::Capture,
+        Source::Replay,
+        Source::Simulated,
+    ];
+
+    /// Value stored in the `source` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Live => "live",
+            Source::Capture => "capture",
+            Source::Replay => "replay",
+            Source::Simulated => "simulated",
+        }
+    }
+
+    /// Look up a source by its stored value.
+    /// # Arguments
+    /// * `name` - Value of the `source` column, e.g. "live".
+    /// # Returns
+    /// * `Err(message)` listing the sources.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|source| source.as_str() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Source::as_str).collect();
+                format!("unknown source {}, expected {}", name, names.join(", "))
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct SensorData {
     pub timestamp: DateTime<Local>,
+    /// Label of the sensor the reading comes from.
+    pub sensor: String,
     pub temperature_c: f64,
     pub humidity_relative: f64,
     pub pressure_pa: f64,
     pub thi: f64,
+    /// Dew point in Celsius, stored as NULL when it is not a number.
+    pub dew_point_c: f64,
+    pub quality: Quality,
+    /// Quality score from 0 to 100, `None` unless [quality] store_score is set.
+    pub quality_score: Option<u8>,
+    /// Control and alert state at save time, stored as nullable columns.
+    pub actions: ActionSnapshot,
+    /// Burst capture the row was stored by, `None` for regular rows.
+    pub capture_id: Option<i64>,
+    /// Mode which took the row.
+    pub source: Source,
+    /// Time of the latest reading of a row stamped with the boundary of its
+    /// save window, `None` when that is the timestamp.
+    pub measured_at: Option<DateTime<Local>>,
+    /// Tick of the measurement loop which read the row, for the debug
+    /// trace. Not stored.
+    pub tick: Option<u64>,
+    /// Run and number of the row, see `sequence`. Given when queued unless
+    /// set.
+    pub sequence: Option<Sequence>,
 }
 
 impl SensorData {
-    pub fn from_measurement(measurement: Measurement, thi: f64) -> Self {
+    /// Build a row stamped with the time the measurement was taken.
+    /// The row is labeled as the default sensor, the quality flag starts as
+    /// `Quality::Good` and no actions are recorded.
+    /// # Arguments
+    /// * `measurement` - Sensor reading.
+    /// * `thi` - Temperature-humidity index.
+    /// * `measured_at` - Time the reading returned from the sensor.
+    /// # Returns
+    /// * SensorData
+    pub fn from_measurement_at(
+        measurement: Measurement,
+        thi: f64,
+        measured_at: DateTime<Local>,
+    ) -> Self {
         Self {
-            timestamp: Local::now(),
+            timestamp: measured_at,
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
             temperature_c: measurement.temperature_c,
             humidity_relative: measurement.humidity_relative,
             pressure_pa: measurement.pressure_pa,
             thi,
+            dew_point_c: measurement.dew_point_c(),
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
+        }
+    }
+
+    /// Reason the row is implausible, `None` if it may be stored.
+    fn implausibility(&self, plausibility: &Plausibility) -> Option<String> {
+        let measurement = Measurement {
+            temperature_c: self.temperature_c,
+            pressure_pa: self.pressure_pa,
+            humidity_relative: self.humidity_relative,
+        };
+        plausibility
+            .check(&self.sensor, &measurement, self.thi)
+            .err()
+    }
+
+    /// Round the stored values.
+    /// # Arguments
+    /// * `decimals` - Number of decimals kept.
+    /// * `mode` - Rounding of ties.
+    pub fn round(&mut self, decimals: u8, mode: RoundingMode) {
+        for value in [
+            &mut self.temperature_c,
+            &mut self.humidity_relative,
+            &mut self.pressure_pa,
+            &mut self.thi,
+            &mut self.dew_point_c,
+        ] {
+            *value = mode.round(*value, decimals);
         }
     }
 }
 
 pub struct Database {
-    sender: mpsc::UnboundedSender<SensorData>,
+    pool: AnyPool,
+    /// URL with the password redacted, for errors.
+    url: String,
+    db_type: DatabaseType,
+    sender: mpsc::UnboundedSender<WriterMessage>,
+    queued: Arc<AtomicUsize>,
+    timestamp_stats: Arc<TimestampStats>,
+    /// Decimals and rounding of the stored values, `None` for full precision.
+    rounding: Option<(u8, RoundingMode)>,
+    writer: JoinHandle<()>,
+}
+
+/// Rows whose timestamp went backwards, counted by the writer.
+#[derive(Debug, Default)]
+pub struct TimestampStats {
+    clamped: AtomicUsize,
+    dropped: AtomicUsize,
+    bumped: AtomicUsize,
+}
+
+/// Snapshot of `TimestampStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TimestampCounts {
+    /// Rows stored 1 ms after the previous row.
+    pub clamped: usize,
+    /// Rows not stored.
+    pub dropped: usize,
+    /// Rows stored 1 ms after the previous row or the time of insertion.
+    pub bumped: usize,
+}
+
+impl TimestampStats {
+    /// Current counts.
+    pub fn counts(&self) -> TimestampCounts {
+        TimestampCounts {
+            clamped: self.clamped.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            bumped: self.bumped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Stamps the rows in the writer and keeps the timestamps of each sensor
+/// non-decreasing, or increasing with `BackwardTimestamps::Bump`.
+struct Timestamper {
+    source: TimestampSource,
+    backward: BackwardTimestamps,
+    /// Last timestamp handed to the database, per sensor label.
+    last: HashMap<String, DateTime<Local>>,
+    stats: Arc<TimestampStats>,
+}
+
+impl Timestamper {
+    fn new(
+        source: TimestampSource,
+        backward: BackwardTimestamps,
+        stats: Arc<TimestampStats>,
+    ) -> Self {
+        Self {
+            source,
+            backward,
+            last: HashMap::new(),
+            stats,
+        }
+    }
+
+    /// Set the stored timestamp of a row.
+    /// # Arguments
+    /// * `data` - Queued row.
+    /// * `now` - Time of insertion.
+    /// # Returns
+    /// * `false` if the row must not be stored.
+    fn stamp(&mut self, data: &mut SensorData, now: DateTime<Local>) -> bool {
+        let timestamp = resolve_timestamp(data, self.source, now);
+        let last = self.last.get(&data.sensor).copied();
+        data.timestamp = match (last, self.backward) {
+            (Some(last), BackwardTimestamps::Clamp) if timestamp < last => {
+                let clamped = last + chrono::Duration::milliseconds(1);
+                self.stats.clamped.fetch_add(1, Ordering::Relaxed);
+                eprintln!(
+                    "Timestamp of {} went back from {} to {}, stored as {}",
+                    data.sensor,
+                    last.to_rfc3339(),
+                    timestamp.to_rfc3339(),
+                    clamped.to_rfc3339()
+                );
+                clamped
+            }
+            (Some(last), BackwardTimestamps::Drop) if timestamp < last => {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                eprintln!(
+                    "Timestamp of {} went back from {} to {}, row dropped",
+                    data.sensor,
+                    last.to_rfc3339(),
+                    timestamp.to_rfc3339()
+                );
+                return false;
+            }
+            // An equal timestamp is bumped too, so the order is unambiguous
+            (Some(last), BackwardTimestamps::Bump) if timestamp <= last => {
+                let bumped = last.max(now) + chrono::Duration::milliseconds(1);
+                self.stats.bumped.fetch_add(1, Ordering::Relaxed);
+                eprintln!(
+                    "Timestamp of {} is not after {}, {} stored as {}",
+                    data.sensor,
+                    last.to_rfc3339(),
+                    timestamp.to_rfc3339(),
+                    bumped.to_rfc3339()
+                );
+                bumped
+            }
+            _ => timestamp,
+        };
+        self.last.insert(data.sensor.clone(), data.timestamp);
+        true
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -75,8 +334,112 @@ enum DatabaseType {
     SQLite,
 }
 
+impl DatabaseType {
+    /// Bind parameter number `n` (1-based) in this database's syntax.
+    fn placeholder(&self, n: usize) -> String {
+        match self {
+            DatabaseType::PostgreSQL => format!("${}", n),
+            DatabaseType::MySQL | DatabaseType::SQLite => "?".to_string(),
+        }
+    }
+}
+
+/// Where the writer inserts rows.
+struct InsertTarget {
+    pool: AnyPool,
+    db_type: DatabaseType,
+    /// Limit of one insert or commit, `None` for no limit.
+    timeout: Option<Duration>,
+}
+
+/// Who the writer tells about the result of each row.
+struct RowObservers {
+    /// Hook run after each stored row.
+    on_insert: Option<InsertHook>,
+    /// Debug trace, told about every row.
+    trace: Option<Arc<TraceControl>>,
+}
+
+/// Message to the writer task.
+enum WriterMessage {
+    /// Row to insert.
+    Row(SensorData),
+    /// Commit the pending rows, then notify.
+    Flush(oneshot::Sender<()>),
+}
+
+/// When the writer commits grouped rows.
+#[derive(Debug, Clone, Copy)]
+struct GroupCommit {
+    /// Longest time a row waits before being committed.
+    interval: Duration,
+    /// Commit early once this many rows are pending.
+    batch_size: usize,
+}
+
+/// Messages gathered by the writer for one commit.
+#[derive(Default)]
+struct Batch {
+    rows: Vec<SensorData>,
+    flushes: Vec<oneshot::Sender<()>>,
+}
+
+impl Batch {
+    fn push(&mut self, message: WriterMessage) {
+        match message {
+            WriterMessage::Row(data) => self.rows.push(data),
+            WriterMessage::Flush(done) => self.flushes.push(done),
+        }
+    }
+}
+
 impl Database {
-    pub async fn new(connection_string: &str) -> Result<Self, BoxError> {
+    pub async fn new(connection_string: &str) -> Result<Self, DatabaseError> {
+        Self::from_config(&DatabaseConfig {
+            url: connection_string.to_string(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Connect to the database, create or update the table and start the
+    /// writer task.
+    /// # Arguments
+    /// * `config` - Database configuration.
+    /// # Returns
+    /// * Result<Database, DatabaseError>
+    pub async fn from_config(config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        let database = Self::connect(config).await?;
+        database.migrate().await?;
+        Ok(database)
+    }
+
+    /// Connect to the database and start the writer task.
+    /// `migrate` must be called before rows are saved.
+    /// # Arguments
+    /// * `config` - Database configuration.
+    /// # Returns
+    /// * Result<Database, DatabaseError>
+    pub async fn connect(config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        Self::connect_with_hook(config, Plausibility::new(&config.validation), None, None).await
+    }
+
+    /// Connect to the database and start the writer task, calling
+    /// `on_insert` for every row stored.
+    /// # Arguments
+    /// * `config` - Database configuration.
+    /// * `plausibility` - Plausible ranges of each sensor's rows.
+    /// * `on_insert` - Hook run by the writer after each stored row.
+    /// * `trace` - Debug trace the writer adds the result of each row to.
+    /// # Returns
+    /// * Result<Database, DatabaseError>
+    pub async fn connect_with_hook(
+        config: &DatabaseConfig,
+        plausibility: Plausibility,
+        on_insert: Option<InsertHook>,
+        trace: Option<Arc<TraceControl>>,
+    ) -> Result<Self, DatabaseError> {
+        let connection_string = config.url.as_str();
         DRIVER_INIT.call_once(|| {
             if let Err(e) = install_driver_for_url(connection_string) {
                 eprintln!("Failed to install database driver: {}", e);
@@ -91,71 +454,1273 @@ impl Database {
             DatabaseType::SQLite
         };
 
-        let pool = AnyPool::connect(connection_string).await?;
+        let pool = connect_pool(connection_string, &db_type, config.sqlite.synchronous)
+            .await
+            .map_err(|source| DatabaseError::connect(connection_string, source))?;
+
+        // Grouped commits are a SQLite option, to spare the SD card
+        let group_commit = match db_type {
+            DatabaseType::SQLite => config.sqlite.commit_interval().map(|interval| GroupCommit {
+                interval,
+                batch_size: config.sqlite.batch_size.max(1),
+            }),
+            DatabaseType::PostgreSQL | DatabaseType::MySQL => None,
+        };
 
-        let create_table_sql = if connection_string.starts_with("postgresql") {
-            r#"
+        let (sender, receiver) = mpsc::unbounded_channel::<WriterMessage>();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let timestamp_stats = Arc::new(TimestampStats::default());
+        let target = InsertTarget {
+            pool: pool.clone(),
+            db_type: db_type.clone(),
+            timeout: config.insert_timeout(),
+        };
+        let writer = tokio::spawn(run_writer(
+            target,
+            Timestamper::new(
+                config.timestamp_source,
+                config.backward_timestamps,
+                timestamp_stats.clone(),
+            ),
+            group_commit,
+            plausibility.is_enabled().then_some(plausibility),
+            receiver,
+            queued.clone(),
+            RowObservers { on_insert, trace },
+        ));
+
+        Ok(Database {
+            pool,
+            url: redact_url(connection_string),
+            db_type,
+            sender,
+            queued,
+            timestamp_stats,
+            rounding: config.storage_rounding(),
+            writer,
+        })
+    }
+
+    /// Create the `sensor_data`, `sensor_data_quarantine`, `events`,
+    /// `alert_events` and `daily_metrics` tables, or add the columns missing from a table
+    /// created by an older version.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn migrate(&self) -> Result<(), DatabaseError> {
+        self.create_tables()
+            .await
+            .map_err(|source| DatabaseError::Migrate {
+                url: self.url.clone(),
+                source,
+            })
+    }
+
+    /// Create or update the tables, see `migrate`.
+    async fn create_tables(&self) -> Result<(), sqlx::Error> {
+        // Humidity, pressure and THI are NULL for sensors which measure none
+        let create_table_sql = match self.db_type {
+            DatabaseType::PostgreSQL => {
+                r#"
             CREATE TABLE IF NOT EXISTS sensor_data (
                 id SERIAL PRIMARY KEY,
                 timestamp TIMESTAMPTZ NOT NULL,
                 temperature_c DOUBLE PRECISION NOT NULL,
-                humidity_relative DOUBLE PRECISION NOT NULL,
-                pressure_pa DOUBLE PRECISION NOT NULL,
-                thi DOUBLE PRECISION NOT NULL
+                humidity_relative DOUBLE PRECISION,
+                pressure_pa DOUBLE PRECISION,
+                thi DOUBLE PRECISION,
+                quality TEXT NOT NULL DEFAULT 'good',
+                fan_state INTEGER,
+                alert_active INTEGER,
+                sensor TEXT NOT NULL DEFAULT 'bme280',
+                capture_id BIGINT,
+                quality_score INTEGER,
+                source TEXT NOT NULL DEFAULT 'live',
+                measured_at TIMESTAMPTZ,
+                run_id TEXT,
+                seq BIGINT,
+                dew_point_c DOUBLE PRECISION
             )
             "#
-        } else if connection_string.starts_with("mysql") {
-            r#"
+            }
+            DatabaseType::MySQL => {
+                r#"
             CREATE TABLE IF NOT EXISTS sensor_data (
                 id INT AUTO_INCREMENT PRIMARY KEY,
                 timestamp DATETIME(6) NOT NULL,
                 temperature_c DOUBLE NOT NULL,
-                humidity_relative DOUBLE NOT NULL,
-                pressure_pa DOUBLE NOT NULL,
-                thi DOUBLE NOT NULL
+                humidity_relative DOUBLE,
+                pressure_pa DOUBLE,
+                thi DOUBLE,
+                quality VARCHAR(16) NOT NULL DEFAULT 'good',
+                fan_state INTEGER,
+                alert_active INTEGER,
+                sensor VARCHAR(64) NOT NULL DEFAULT 'bme280',
+                capture_id BIGINT,
+                quality_score INTEGER,
+                source VARCHAR(16) NOT NULL DEFAULT 'live',
+                measured_at DATETIME(6),
+                run_id VARCHAR(64),
+                seq BIGINT,
+                dew_point_c DOUBLE
             )
             "#
-        } else {
-            r#"
+            }
+            DatabaseType::SQLite => {
+                r#"
             CREATE TABLE IF NOT EXISTS sensor_data (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 timestamp TEXT NOT NULL,
                 temperature_c REAL NOT NULL,
-                humidity_relative REAL NOT NULL,
-                pressure_pa REAL NOT NULL,
-                thi REAL NOT NULL
+                humidity_relative REAL,
+                pressure_pa REAL,
+                thi REAL,
+                quality TEXT NOT NULL DEFAULT 'good',
+                fan_state INTEGER,
+                alert_active INTEGER,
+                sensor TEXT NOT NULL DEFAULT 'bme280',
+                capture_id BIGINT,
+                quality_score INTEGER,
+                source TEXT NOT NULL DEFAULT 'live',
+                measured_at TEXT,
+                run_id TEXT,
+                seq BIGINT,
+                dew_point_c REAL
             )
             "#
+            }
         };
 
-        sqlx::query(create_table_sql).execute(&pool).await?;
+        sqlx::query(create_table_sql).execute(&self.pool).await?;
+        add_missing_columns(&self.pool, &self.db_type).await?;
+        allow_missing_values(&self.pool, &self.db_type, create_table_sql).await?;
+
+        let create_events_sql = match self.db_type {
+            DatabaseType::PostgreSQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS events (
+                id SERIAL PRIMARY KEY,
+                timestamp TIMESTAMPTZ NOT NULL,
+                kind TEXT NOT NULL,
+                detail TEXT NOT NULL
+            )
+            "#
+            }
+            DatabaseType::MySQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS events (
+                id INT AUTO_INCREMENT PRIMARY KEY,
+                timestamp DATETIME(6) NOT NULL,
+                kind VARCHAR(64) NOT NULL,
+                detail TEXT NOT NULL
+            )
+            "#
+            }
+            DatabaseType::SQLite => {
+                r#"
+            CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                detail TEXT NOT NULL
+            )
+            "#
+            }
+        };
+        sqlx::query(create_events_sql).execute(&self.pool).await?;
 
-        let (sender, mut receiver) = mpsc::unbounded_channel::<SensorData>();
-        let pool_clone = pool.clone();
-        let db_type_clone = db_type.clone();
+        // One row per alert, written once it is cleared
+        let create_alert_events_sql = match self.db_type {
+            DatabaseType::PostgreSQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS alert_events (
+                id SERIAL PRIMARY KEY,
+                name TEXT NOT NULL,
+                field TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                started_at TIMESTAMPTZ NOT NULL,
+                ended_at TIMESTAMPTZ NOT NULL,
+                peak DOUBLE PRECISION NOT NULL
+            )
+            "#
+            }
+            DatabaseType::MySQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS alert_events (
+                id INT AUTO_INCREMENT PRIMARY KEY,
+                name VARCHAR(64) NOT NULL,
+                field VARCHAR(32) NOT NULL,
+                direction VARCHAR(8) NOT NULL,
+                started_at DATETIME(6) NOT NULL,
+                ended_at DATETIME(6) NOT NULL,
+                peak DOUBLE NOT NULL
+            )
+            "#
+            }
+            DatabaseType::SQLite => {
+                r#"
+            CREATE TABLE IF NOT EXISTS alert_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                field TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                ended_at TEXT NOT NULL,
+                peak REAL NOT NULL
+            )
+            "#
+            }
+        };
+        sqlx::query(create_alert_events_sql)
+            .execute(&self.pool)
+            .await?;
+
+        // Values are nullable, NaN is stored as NULL and named in the reason
+        let create_quarantine_sql = match self.db_type {
+            DatabaseType::PostgreSQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS sensor_data_quarantine (
+                id SERIAL PRIMARY KEY,
+                timestamp TIMESTAMPTZ NOT NULL,
+                sensor TEXT NOT NULL,
+                temperature_c DOUBLE PRECISION,
+                humidity_relative DOUBLE PRECISION,
+                pressure_pa DOUBLE PRECISION,
+                thi DOUBLE PRECISION,
+                dew_point_c DOUBLE PRECISION,
+                capture_id BIGINT,
+                reason TEXT NOT NULL
+            )
+            "#
+            }
+            DatabaseType::MySQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS sensor_data_quarantine (
+                id INT AUTO_INCREMENT PRIMARY KEY,
+                timestamp DATETIME(6) NOT NULL,
+                sensor VARCHAR(64) NOT NULL,
+                temperature_c DOUBLE,
+                humidity_relative DOUBLE,
+                pressure_pa DOUBLE,
+                thi DOUBLE,
+                dew_point_c DOUBLE,
+                capture_id BIGINT,
+                reason TEXT NOT NULL
+            )
+            "#
+            }
+            DatabaseType::SQLite => {
+                r#"
+            CREATE TABLE IF NOT EXISTS sensor_data_quarantine (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                sensor TEXT NOT NULL,
+                temperature_c REAL,
+                humidity_relative REAL,
+                pressure_pa REAL,
+                thi REAL,
+                dew_point_c REAL,
+                capture_id BIGINT,
+                reason TEXT NOT NULL
+            )
+            "#
+            }
+        };
+        sqlx::query(create_quarantine_sql)
+            .execute(&self.pool)
+            .await?;
+        ensure_column(
+            &self.pool,
+            &self.db_type,
+            "sensor_data_quarantine",
+            "dew_point_c",
+            double_type(&self.db_type),
+        )
+        .await?;
+
+        // The day is ISO 8601 text in every database
+        let create_daily_metrics_sql = match self.db_type {
+            DatabaseType::PostgreSQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS daily_metrics (
+                day TEXT PRIMARY KEY,
+                heating_degree_hours DOUBLE PRECISION NOT NULL,
+                cooling_degree_hours DOUBLE PRECISION NOT NULL,
+                humid_hours DOUBLE PRECISION NOT NULL,
+                covered_hours DOUBLE PRECISION NOT NULL
+            )
+            "#
+            }
+            DatabaseType::MySQL => {
+                r#"
+            CREATE TABLE IF NOT EXISTS daily_metrics (
+                day VARCHAR(10) PRIMARY KEY,
+                heating_degree_hours DOUBLE NOT NULL,
+                cooling_degree_hours DOUBLE NOT NULL,
+                humid_hours DOUBLE NOT NULL,
+                covered_hours DOUBLE NOT NULL
+            )
+            "#
+            }
+            DatabaseType::SQLite => {
+                r#"
+            CREATE TABLE IF NOT EXISTS daily_metrics (
+                day TEXT PRIMARY KEY,
+                heating_degree_hours REAL NOT NULL,
+                cooling_degree_hours REAL NOT NULL,
+                humid_hours REAL NOT NULL,
+                covered_hours REAL NOT NULL
+            )
+            "#
+            }
+        };
+        sqlx::query(create_daily_metrics_sql)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Handle to the `daily_metrics` table, for use from another task.
+    pub fn daily_metrics_table(&self) -> DailyMetricsTable {
+        DailyMetricsTable {
+            pool: self.pool.clone(),
+            db_type: self.db_type.clone(),
+        }
+    }
+
+    /// Store an event, bypassing the row queue.
+    /// # Arguments
+    /// * `kind` - Event kind, e.g. "capture_start".
+    /// * `at` - Time of the event.
+    /// * `detail` - Event data, stored as JSON text.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn record_event(
+        &self,
+        kind: &str,
+        at: DateTime<Local>,
+        detail: &serde_json::Value,
+    ) -> Result<(), DatabaseError> {
+        let sql = format!(
+            "INSERT INTO events (timestamp, kind, detail) VALUES ({}, {}, {})",
+            self.timestamp_placeholder(1),
+            self.placeholder(2),
+            self.placeholder(3)
+        );
+        sqlx::query(&sql)
+            .bind(at.to_rfc3339())
+            .bind(kind)
+            .bind(detail.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Store a cleared alert, bypassing the row queue.
+    /// # Arguments
+    /// * `episode` - Alert from raise to clear.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn record_alert(&self, episode: &AlertEpisode) -> Result<(), DatabaseError> {
+        let sql = format!(
+            "INSERT INTO alert_events (name, field, direction, started_at, ended_at, peak) \
+             VALUES ({}, {}, {}, {}, {}, {})",
+            self.placeholder(1),
+            self.placeholder(2),
+            self.placeholder(3),
+            self.timestamp_placeholder(4),
+            self.timestamp_placeholder(5),
+            self.placeholder(6)
+        );
+        sqlx::query(&sql)
+            .bind(&episode.name)
+            .bind(episode.field.as_str())
+            .bind(episode.direction.as_str())
+            .bind(episode.started_at.to_rfc3339())
+            .bind(episode.ended_at.to_rfc3339())
+            .bind(episode.peak)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub fn save_async(&self, mut data: SensorData) -> Result<(), DatabaseError> {
+        if data.sequence.is_none() {
+            data.sequence = Some(sequence::this_run().next());
+        }
+        if let Some((decimals, mode)) = self.rounding {
+            data.round(decimals, mode);
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(WriterMessage::Row(data)).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(DatabaseError::WriterStopped);
+        }
+        Ok(())
+    }
+
+    /// Whether the writer task stopped, after a panic, while the database
+    /// is open.
+    pub fn writer_stopped(&self) -> bool {
+        self.writer.is_finished()
+    }
+
+    /// Number of rows queued but not yet written.
+    pub fn queue_len(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Counts of rows whose timestamp went backwards.
+    pub fn timestamp_stats(&self) -> Arc<TimestampStats> {
+        self.timestamp_stats.clone()
+    }
+
+    /// Write and commit every row queued so far.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn flush(&self) -> Result<(), DatabaseError> {
+        let (done, wait) = oneshot::channel();
+        self.sender
+            .send(WriterMessage::Flush(done))
+            .map_err(|_| DatabaseError::WriterStopped)?;
+        wait.await.map_err(|_| DatabaseError::WriterStopped)
+    }
+
+    /// Commit the pending rows and stop the writer task.
+    pub async fn close(self) {
+        drop(self.sender);
+        if let Err(e) = self.writer.await {
+            eprintln!("Database writer stopped abnormally: {}", e);
+        }
+    }
+}
+
+impl Database {
+    /// Read the raw measurements of stored rows, in id order.
+    /// # Arguments
+    /// * `columns` - Other columns read along, as `f64`. Must not come
+    ///   from user input.
+    /// * `after_id` - Only rows with a larger id.
+    /// * `from` - Only rows at or after this time.
+    /// * `to` - Only rows before this time.
+    /// * `limit` - Largest number of rows returned.
+    /// # Returns
+    /// * Result<Vec<RawRow>, DatabaseError>
+    pub async fn raw_rows(
+        &self,
+        columns: &[&'static str],
+        after_id: i64,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+        limit: usize,
+    ) -> Result<Vec<RawRow>, DatabaseError> {
+        let mut select = format!(
+            "{} AS id, temperature_c, humidity_relative, pressure_pa",
+            self.id_column()
+        );
+        for column in columns {
+            select.push_str(&format!(", {}", column));
+        }
+        let rows = self
+            .rows_in_range(&select, after_id, from, to, &[], limit)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(RawRow {
+                    id: row.try_get(0)?,
+                    measurement: Measurement {
+                        temperature_c: row.try_get(1)?,
+                        humidity_relative: nullable(row, 2)?,
+                        pressure_pa: nullable(row, 3)?,
+                    },
+                    columns: (0..columns.len())
+                        .map(|i| nullable(row, 4 + i))
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Count the rows in `sensor_data_quarantine`.
+    /// # Returns
+    /// * Result<u64, DatabaseError>
+    pub async fn quarantined_rows(&self) -> Result<u64, DatabaseError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sensor_data_quarantine")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
+    /// Read stored rows with their time and sensor, in id order.
+    /// # Arguments
+    /// * `after_id` - Only rows with a larger id.
+    /// * `from` - Only rows at or after this time.
+    /// * `to` - Only rows before this time.
+    /// * `sources` - Only rows of these sources, all if empty.
+    /// * `limit` - Largest number of rows returned.
+    /// # Returns
+    /// * Result<Vec<ExportRow>, DatabaseError>
+    pub async fn export_rows(
+        &self,
+        after_id: i64,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+        sources: &[Source],
+        limit: usize,
+    ) -> Result<Vec<ExportRow>, DatabaseError> {
+        // Read as text, the drivers do not share a timestamp type
+        let timestamp = match self.db_type {
+            DatabaseType::PostgreSQL => "CAST(timestamp AS TEXT)",
+            DatabaseType::MySQL => "CAST(timestamp AS CHAR)",
+            DatabaseType::SQLite => "timestamp",
+        };
+        let select = format!(
+            "{} AS id, {} AS timestamp, sensor, temperature_c, humidity_relative, \
+             pressure_pa, thi, dew_point_c, quality, source, run_id, seq",
+            self.id_column(),
+            timestamp
+        );
+        let rows = self
+            .rows_in_range(&select, after_id, from, to, sources, limit)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(ExportRow {
+                    id: row.try_get(0)?,
+                    timestamp: row.try_get(1)?,
+                    sensor: row.try_get(2)?,
+                    measurement: Measurement {
+                        temperature_c: row.try_get(3)?,
+                        humidity_relative: nullable(row, 4)?,
+                        pressure_pa: nullable(row, 5)?,
+                    },
+                    thi: nullable(row, 6)?,
+                    dew_point_c: nullable(row, 7)?,
+                    quality: row.try_get(8)?,
+                    source: row.try_get(9)?,
+                    run_id: row.try_get(10)?,
+                    seq: row.try_get(11)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Read columns of `sensor_data` rows after an id and within a time
+    /// range, in id order.
+    /// # Arguments
+    /// * `select` - Selected columns. Must not come from user input.
+    /// * `after_id` - Only rows with a larger id.
+    /// * `from` - Only rows at or after this time.
+    /// * `to` - Only rows before this time.
+    /// * `sources` - Only rows of these sources, all if empty.
+    /// * `limit` - Largest number of rows returned.
+    /// # Returns
+    /// * Result<Vec<AnyRow>, DatabaseError>
+    async fn rows_in_range(
+        &self,
+        select: &str,
+        after_id: i64,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+        sources: &[Source],
+        limit: usize,
+    ) -> Result<Vec<AnyRow>, DatabaseError> {
+        let mut sql = format!(
+            "SELECT {} FROM sensor_data WHERE id > {}",
+            select,
+            self.placeholder(1)
+        );
+        let mut bounds = Vec::new();
+        for (bound, operator) in [(from, ">="), (to, "<")] {
+            if let Some(bound) = bound {
+                bounds.push(bound.to_rfc3339());
+                sql.push_str(&format!(
+                    " AND timestamp {} {}",
+                    operator,
+                    self.timestamp_placeholder(bounds.len() + 1)
+                ));
+            }
+        }
+        if !sources.is_empty() {
+            // The values are constants of `Source`
+            let sources: Vec<_> = sources
+                .iter()
+                .map(|source| format!("'{}'", source.as_str()))
+                .collect();
+            sql.push_str(&format!(" AND source IN ({})", sources.join(", ")));
+        }
+        sql.push_str(&format!(" ORDER BY id LIMIT {}", limit));
+
+        let mut query = sqlx::query(&sql).bind(after_id);
+        for bound in bounds {
+            query = query.bind(bound);
+        }
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    /// The id column as a 64 bit integer, it is 32 bit on PostgreSQL and
+    /// MySQL.
+    fn id_column(&self) -> &'static str {
+        match self.db_type {
+            DatabaseType::PostgreSQL => "CAST(id AS BIGINT)",
+            DatabaseType::MySQL => "CAST(id AS SIGNED)",
+            DatabaseType::SQLite => "id",
+        }
+    }
+
+    /// Overwrite a column of stored rows in one transaction.
+    /// # Arguments
+    /// * `column` - Column name. Must not come from user input.
+    /// * `values` - `(id, value)` pairs.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn update_column(
+        &self,
+        column: &'static str,
+        values: &[(i64, f64)],
+    ) -> Result<(), DatabaseError> {
+        let sql = format!(
+            "UPDATE sensor_data SET {} = {} WHERE id = {}",
+            column,
+            self.placeholder(1),
+            self.placeholder(2)
+        );
+        let mut transaction = self.pool.begin().await?;
+        for (id, value) in values {
+            sqlx::query(&sql)
+                .bind(*value)
+                .bind(*id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Bind parameter number `n` (1-based) in this database's syntax.
+    fn placeholder(&self, n: usize) -> String {
+        self.db_type.placeholder(n)
+    }
+
+    /// Bind parameter compared with the `timestamp` column, which is bound
+    /// as RFC 3339 text like on insert.
+    fn timestamp_placeholder(&self, n: usize) -> String {
+        match self.db_type {
+            DatabaseType::PostgreSQL => format!("${}::timestamptz", n),
+            DatabaseType::MySQL | DatabaseType::SQLite => "?".to_string(),
+        }
+    }
+}
+
+/// Daily totals of degree-hours and humid hours, one row per day.
+#[derive(Debug, Clone)]
+pub struct DailyMetricsTable {
+    pool: AnyPool,
+    db_type: DatabaseType,
+}
+
+impl DailyMetricsTable {
+    /// Store the totals of a day, replacing those stored before.
+    /// # Arguments
+    /// * `totals` - Totals of the day.
+    /// # Returns
+    /// * Result<(), DatabaseError>
+    pub async fn save(&self, totals: &DailyTotals) -> Result<(), DatabaseError> {
+        let columns = [
+            "heating_degree_hours",
+            "cooling_degree_hours",
+            "humid_hours",
+            "covered_hours",
+        ];
+        let update: Vec<String> = columns
+            .iter()
+            .map(|column| match self.db_type {
+                DatabaseType::MySQL => format!("{} = VALUES({})", column, column),
+                DatabaseType::PostgreSQL | DatabaseType::SQLite => {
+                    format!("{} = excluded.{}", column, column)
+                }
+            })
+            .collect();
+        let conflict = match self.db_type {
+            DatabaseType::MySQL => "ON DUPLICATE KEY UPDATE",
+            DatabaseType::PostgreSQL | DatabaseType::SQLite => "ON CONFLICT (day) DO UPDATE SET",
+        };
+        let sql = format!(
+            "INSERT INTO daily_metrics (day, {}) VALUES ({}) {} {}",
+            columns.join(", "),
+            (1..=columns.len() + 1)
+                .map(|n| self.db_type.placeholder(n))
+                .collect::<Vec<_>>()
+                .join(", "),
+            conflict,
+            update.join(", ")
+        );
+        sqlx::query(&sql)
+            .bind(totals.day.to_string())
+            .bind(totals.heating_degree_hours)
+            .bind(totals.cooling_degree_hours)
+            .bind(totals.humid_hours)
+            .bind(totals.covered_hours)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Read the totals of a day.
+    /// # Arguments
+    /// * `day` - Day to read.
+    /// # Returns
+    /// * `Ok(None)` if the day is not stored.
+    pub async fn load(&self, day: NaiveDate) -> Result<Option<DailyTotals>, DatabaseError> {
+        let sql = format!(
+            "SELECT heating_degree_hours, cooling_degree_hours, humid_hours, covered_hours \
+             FROM daily_metrics WHERE day = {}",
+            self.db_type.placeholder(1)
+        );
+        let row: Option<(f64, f64, f64, f64)> = sqlx::query_as(&sql)
+            .bind(day.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(
+            |(heating_degree_hours, cooling_degree_hours, humid_hours, covered_hours)| {
+                DailyTotals {
+                    day,
+                    heating_degree_hours,
+                    cooling_degree_hours,
+                    humid_hours,
+                    covered_hours,
+                }
+            },
+        ))
+    }
+}
+
+/// Read a nullable value of a row, NULL as NaN.
+/// # Arguments
+/// * `row` - Row read.
+/// * `index` - Column index.
+/// # Returns
+/// * Result<f64, sqlx::Error>
+fn nullable(row: &AnyRow, index: usize) -> Result<f64, sqlx::Error> {
+    Ok(row.try_get::<Option<f64>, _>(index)?.unwrap_or(f64::NAN))
+}
+
+/// Raw measurement of a stored row.
+#[derive(Debug, Clone)]
+pub struct RawRow {
+    pub id: i64,
+    pub measurement: Measurement,
+    /// Values of the other requested columns, in order.
+    pub columns: Vec<f64>,
+}
+
+/// Stored row as written by the export.
+#[derive(Debug, Clone)]
+pub struct ExportRow {
+    pub id: i64,
+    /// Time as the database returns it as text.
+    pub timestamp: String,
+    pub sensor: String,
+    pub measurement: Measurement,
+    pub thi: f64,
+    pub dew_point_c: f64,
+    pub quality: String,
+    pub source: String,
+    /// Run and number of the row, `None` for rows of older versions.
+    pub run_id: Option<String>,
+    pub seq: Option<i64>,
+}
+
+/// Writer task inserting the queued rows.
+/// Without group commit every row is committed on its own. With group
+/// commit, rows are kept in memory and written in one transaction once the
+/// interval elapses, the batch is full, a flush is requested or the queue is
+/// closed, so a crash loses at most the rows of the current window.
+/// Implausible rows are written to the quarantine table right away instead.
+/// # Arguments
+/// * `pool` - Connection pool.
+/// * `db_type` - Database type.
+/// * `timestamper` - Sets the stored timestamps.
+/// * `group_commit` - Group commit policy, `None` to commit every row.
+/// * `plausibility` - Plausible ranges, `None` to store every row.
+/// * `receiver` - Queue of writer messages.
+/// * `queued` - Number of rows queued but not yet written.
+/// * `observers` - Insert hook and debug trace, told about each row.
+async fn run_writer(
+    target: InsertTarget,
+    mut timestamper: Timestamper,
+    group_commit: Option<GroupCommit>,
+    plausibility: Option<Plausibility>,
+    mut receiver: mpsc::UnboundedReceiver<WriterMessage>,
+    queued: Arc<AtomicUsize>,
+    observers: RowObservers,
+) {
+    let RowObservers { on_insert, trace } = observers;
+    // Result of a row in the debug trace, under the tick which read it
+    let traced = |data: &SensorData, result: &str, error: Option<String>| {
+        let Some((trace, tick)) = trace.as_ref().zip(data.tick) else {
+            return;
+        };
+        if trace.is_active() {
+            let detail = serde_json::json!({
+                "sensor": data.sensor,
+                "result": result,
+                "error": error,
+            });
+            trace.record(tick, "db", detail);
+        }
+    };
+    let stored = |data: &SensorData, inserted: bool| {
+        if !inserted {
+            // Delivered before, the hook ran then
+            traced(data, "duplicate", None);
+            return;
+        }
+        traced(data, "stored", None);
+        if let Some(hook) = &on_insert {
+            hook(data);
+        }
+    };
+    while let Some(message) = receiver.recv().await {
+        let mut batch = Batch::default();
+        batch.push(message);
+        let mut open = true;
+        if let Some(policy) = &group_commit {
+            open = collect_batch(&mut receiver, &mut batch, policy).await;
+        }
+
+        let received = batch.rows.len();
+        if let Some(plausibility) = &plausibility {
+            let mut plausible = Vec::with_capacity(received);
+            for data in batch.rows.drain(..) {
+                let Some(reason) = data.implausibility(plausibility) else {
+                    plausible.push(data);
+                    continue;
+                };
+                eprintln!("Row of {} quarantined: {}", data.sensor, reason);
+                traced(&data, "quarantined", Some(reason.clone()));
+                let timestamp = resolve_timestamp(&data, timestamper.source, Local::now());
+                if let Err(e) = insert_quarantine(&target, &data, timestamp, &reason).await {
+                    eprintln!(
+                        "Failed to save quarantined row{}: {}",
+                        transient_note(&e),
+                        e
+                    );
+                }
+            }
+            batch.rows = plausible;
+        }
+        batch.rows.retain_mut(|data| {
+            let kept = timestamper.stamp(data, Local::now());
+            if !kept {
+                traced(
+                    data,
+                    "dropped",
+                    Some("timestamp went backwards".to_string()),
+                );
+            }
+            kept
+        });
+        if group_commit.is_some() {
+            match insert_batch(&target, &batch.rows).await {
+                Ok(inserted) => {
+                    for (data, inserted) in batch.rows.iter().zip(inserted) {
+                        stored(data, inserted);
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Failed to save {} sensor data rows{}: {}",
+                        batch.rows.len(),
+                        transient_note(&e),
+                        e
+                    );
+                    for data in &batch.rows {
+                        traced(data, "failed", Some(e.to_string()));
+                    }
+                }
+            }
+        } else {
+            for data in &batch.rows {
+                match insert_sensor_data(&target.pool, data, &target.db_type, target.timeout).await
+                {
+                    Ok(inserted) => stored(data, inserted),
+                    Err(e) => {
+                        eprintln!("Failed to save sensor data{}: {}", transient_note(&e), e);
+                        traced(data, "failed", Some(e.to_string()));
+                    }
+                }
+            }
+        }
+        queued.fetch_sub(received, Ordering::Relaxed);
+        for done in batch.flushes {
+            let _ = done.send(());
+        }
+
+        if !open {
+            break;
+        }
+    }
+}
+
+/// Gather messages for one group commit.
+/// Stops when the interval elapses, the batch is full or a flush is requested.
+/// # Arguments
+/// * `receiver` - Queue of writer messages.
+/// * `batch` - Batch holding the first message.
+/// * `policy` - Group commit policy.
+/// # Returns
+/// * `false` if the queue was closed.
+async fn collect_batch(
+    receiver: &mut mpsc::UnboundedReceiver<WriterMessage>,
+    batch: &mut Batch,
+    policy: &GroupCommit,
+) -> bool {
+    let deadline = Instant::now() + policy.interval;
+    while batch.flushes.is_empty() && batch.rows.len() < policy.batch_size {
+        match timeout_at(deadline, receiver.recv()).await {
+            Ok(Some(message)) => batch.push(message),
+            Ok(None) => return false,
+            Err(_) => break,
+        }
+    }
+    true
+}
+
+/// Insert rows in a single transaction.
+/// # Arguments
+/// * `target` - Database and insert timeout.
+/// * `rows` - Rows to insert.
+/// # Returns
+/// * Whether each row was inserted, `false` for one stored already.
+async fn insert_batch(
+    target: &InsertTarget,
+    rows: &[SensorData],
+) -> Result<Vec<bool>, DatabaseError> {
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut transaction = with_timeout(target.timeout, target.pool.begin()).await?;
+    let mut inserted = Vec::with_capacity(rows.len());
+    for data in rows {
+        inserted.push(
+            insert_sensor_data(&mut *transaction, data, &target.db_type, target.timeout).await?,
+        );
+    }
+    with_timeout(target.timeout, transaction.commit()).await?;
+    Ok(inserted)
+}
+
+/// Bound a database operation by the insert timeout.
+/// # Arguments
+/// * `limit` - Longest time allowed, `None` for no limit.
+/// * `operation` - Database operation.
+/// # Returns
+/// * `Err(DatabaseError::Timeout)` if the operation did not finish in time.
+async fn with_timeout<T, F>(limit: Option<Duration>, operation: F) -> Result<T, DatabaseError>
+where
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    match limit {
+        Some(limit) => match timeout(limit, operation).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(DatabaseError::Timeout(limit)),
+        },
+        None => Ok(operation.await?),
+    }
+}
+
+/// Note appended to the log of a failed write.
+fn transient_note(error: &DatabaseError) -> &'static str {
+    if error.is_transient() {
+        " (transient)"
+    } else {
+        ""
+    }
+}
+
+/// Pick the timestamp stored for a row.
+/// # Arguments
+/// * `data` - Queued row, stamped with the measurement time.
+/// * `source` - Configured timestamp source.
+/// * `now` - Time of insertion.
+/// # Returns
+/// * Timestamp to store.
+fn resolve_timestamp(
+    data: &SensorData,
+    source: TimestampSource,
+    now: DateTime<Local>,
+) -> DateTime<Local> {
+    match source {
+        TimestampSource::Measurement => data.timestamp,
+        TimestampSource::Insertion => now,
+    }
+}
+
+/// Open the connection pool.
+/// SQLite gets a single connection: writes are serialized by SQLite anyway,
+/// and every connection to `sqlite::memory:` would be a separate database.
+/// # Arguments
+/// * `connection_string` - Database URL.
+/// * `db_type` - Database type.
+/// * `synchronous` - `PRAGMA synchronous` applied to SQLite connections.
+/// # Returns
+/// * Result<AnyPool, sqlx::Error>
+async fn connect_pool(
+    connection_string: &str,
+    db_type: &DatabaseType,
+    synchronous: Option<SqliteSynchronous>,
+) -> Result<AnyPool, sqlx::Error> {
+    let options = match db_type {
+        DatabaseType::SQLite => {
+            AnyPoolOptions::new()
+                .max_connections(1)
+                .after_connect(move |connection, _| {
+                    Box::pin(async move {
+                        if let Some(synchronous) = synchronous {
+                            sqlx::query(synchronous.pragma())
+                                .execute(connection)
+                                .await?;
+                        }
+                        Ok(())
+                    })
+                })
+        }
+        DatabaseType::PostgreSQL | DatabaseType::MySQL => AnyPoolOptions::new(),
+    };
+    options.connect(connection_string).await
+}
+
+/// Bring a `sensor_data` table created by an older version up to date.
+/// # Arguments
+/// * `pool` - Connection pool.
+/// * `db_type` - Database type.
+/// # Returns
+/// * Result<(), sqlx::Error>
+async fn add_missing_columns(pool: &AnyPool, db_type: &DatabaseType) -> Result<(), sqlx::Error> {
+    ensure_column(
+        pool,
+        db_type,
+        "sensor_data",
+        "quality",
+        "TEXT NOT NULL DEFAULT 'good'",
+    )
+    .await?;
+    ensure_column(pool, db_type, "sensor_data", "fan_state", "INTEGER").await?;
+    ensure_column(pool, db_type, "sensor_data", "alert_active", "INTEGER").await?;
+    ensure_column(
+        pool,
+        db_type,
+        "sensor_data",
+        "sensor",
+        "TEXT NOT NULL DEFAULT 'bme280'",
+    )
+    .await?;
+    ensure_column(pool, db_type, "sensor_data", "capture_id", "BIGINT").await?;
+    ensure_column(pool, db_type, "sensor_data", "quality_score", "INTEGER").await?;
+    ensure_column(
+        pool,
+        db_type,
+        "sensor_data",
+        "source",
+        "TEXT NOT NULL DEFAULT 'live'",
+    )
+    .await?;
+    let timestamp = match db_type {
+        DatabaseType::PostgreSQL => "TIMESTAMPTZ",
+        DatabaseType::MySQL => "DATETIME(6)",
+        DatabaseType::SQLite => "TEXT",
+    };
+    ensure_column(pool, db_type, "sensor_data", "measured_at", timestamp).await?;
+    ensure_column(pool, db_type, "sensor_data", "run_id", "TEXT").await?;
+    ensure_column(pool, db_type, "sensor_data", "seq", "BIGINT").await?;
+    ensure_column(
+        pool,
+        db_type,
+        "sensor_data",
+        "dew_point_c",
+        double_type(db_type),
+    )
+    .await?;
+    ensure_sequence_index(pool, db_type).await?;
+    Ok(())
+}
+
+/// Columns of `sensor_data`, in any database version after
+/// `add_missing_columns`.
+const SENSOR_DATA_COLUMNS: &str = "id, timestamp, temperature_c, humidity_relative, pressure_pa, \
+     thi, quality, fan_state, alert_active, sensor, capture_id, quality_score, source, \
+     measured_at, run_id, seq, dew_point_c";
+
+/// Drop the NOT NULL of humidity, pressure and THI from a `sensor_data`
+/// table created by an older version, so rows of sensors which measure
+/// none can be stored. SQLite cannot alter a column, the table is rebuilt
+/// from `create_table_sql` instead.
+/// # Arguments
+/// * `pool` - Connection pool.
+/// * `db_type` - Database type.
+/// * `create_table_sql` - Statement creating `sensor_data`.
+/// # Returns
+/// * Result<(), sqlx::Error>
+async fn allow_missing_values(
+    pool: &AnyPool,
+    db_type: &DatabaseType,
+    create_table_sql: &str,
+) -> Result<(), sqlx::Error> {
+    match db_type {
+        DatabaseType::PostgreSQL => {
+            sqlx::query(
+                "ALTER TABLE sensor_data \
+                 ALTER COLUMN humidity_relative DROP NOT NULL, \
+                 ALTER COLUMN pressure_pa DROP NOT NULL, \
+                 ALTER COLUMN thi DROP NOT NULL",
+            )
+            .execute(pool)
+            .await?;
+        }
+        DatabaseType::MySQL => {
+            let nullable: String = sqlx::query_scalar(
+                "SELECT is_nullable FROM information_schema.columns \
+                 WHERE table_schema = DATABASE() AND table_name = 'sensor_data' \
+                 AND column_name = 'humidity_relative'",
+            )
+            .fetch_one(pool)
+            .await?;
+            if nullable != "YES" {
+                sqlx::query(
+                    "ALTER TABLE sensor_data \
+                     MODIFY humidity_relative DOUBLE NULL, \
+                     MODIFY pressure_pa DOUBLE NULL, \
+                     MODIFY thi DOUBLE NULL",
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        DatabaseType::SQLite => {
+            let not_null: i64 = sqlx::query_scalar(
+                "SELECT \"notnull\" FROM pragma_table_info('sensor_data') \
+                 WHERE name = 'humidity_relative'",
+            )
+            .fetch_one(pool)
+            .await?;
+            if not_null == 0 {
+                return Ok(());
+            }
+            let mut tx = pool.begin().await?;
+            sqlx::query("ALTER TABLE sensor_data RENAME TO sensor_data_rebuild")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(create_table_sql).execute(&mut *tx).await?;
+            let copy = format!(
+                "INSERT INTO sensor_data ({0}) SELECT {0} FROM sensor_data_rebuild",
+                SENSOR_DATA_COLUMNS
+            );
+            sqlx::query(&copy).execute(&mut *tx).await?;
+            sqlx::query("DROP TABLE sensor_data_rebuild")
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            // The index went with the old table
+            ensure_sequence_index(pool, db_type).await?;
+        }
+    }
+    Ok(())
+}
 
-        tokio::spawn(async move {
-            while let Some(data) = receiver.recv().await {
-                if let Err(e) = insert_sensor_data(&pool_clone, &data, &db_type_clone).await {
-                    eprintln!("Failed to save sensor data: {}", e);
-                }
+/// Name of the unique index on (run_id, seq).
+const SEQUENCE_INDEX: &str = "sensor_data_run_seq";
+
+/// Create the unique index skipping rows delivered twice, if it is missing.
+/// Rows without a sequence number are NULL there and never collide.
+/// # Arguments
+/// * `pool` - Connection pool.
+/// * `db_type` - Database type.
+/// # Returns
+/// * Result<(), sqlx::Error>
+async fn ensure_sequence_index(pool: &AnyPool, db_type: &DatabaseType) -> Result<(), sqlx::Error> {
+    let create = match db_type {
+        DatabaseType::PostgreSQL | DatabaseType::SQLite => format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON sensor_data (run_id, seq)",
+            SEQUENCE_INDEX
+        ),
+        // No IF NOT EXISTS for indexes
+        DatabaseType::MySQL => {
+            let probe = format!(
+                "SELECT COUNT(*) FROM information_schema.statistics \
+                 WHERE table_schema = DATABASE() AND table_name = 'sensor_data' \
+                 AND index_name = '{}'",
+                SEQUENCE_INDEX
+            );
+            let count: i64 = sqlx::query_scalar(&probe).fetch_one(pool).await?;
+            if count > 0 {
+                return Ok(());
             }
-        });
+            format!(
+                "CREATE UNIQUE INDEX {} ON sensor_data (run_id, seq)",
+                SEQUENCE_INDEX
+            )
+        }
+    };
+    sqlx::query(&create).execute(pool).await?;
+    Ok(())
+}
 
-        Ok(Database { sender })
+/// Floating point column type of a database.
+fn double_type(db_type: &DatabaseType) -> &'static str {
+    match db_type {
+        DatabaseType::PostgreSQL => "DOUBLE PRECISION",
+        DatabaseType::MySQL => "DOUBLE",
+        DatabaseType::SQLite => "REAL",
     }
+}
 
-    pub fn save_async(&self, data: SensorData) -> Result<(), BoxError> {
-        self.sender.send(data)?;
-        Ok(())
+/// Add a column to an existing table if it is missing.
+/// # Arguments
+/// * `pool` - Connection pool.
+/// * `db_type` - Database type.
+/// * `table` - Table name.
+/// * `name` - Column name.
+/// * `definition` - Column type and constraints. `TEXT` is replaced with
+///   `VARCHAR(64)` on MySQL, which does not allow defaults on `TEXT`.
+/// # Returns
+/// * Result<(), sqlx::Error>
+async fn ensure_column(
+    pool: &AnyPool,
+    db_type: &DatabaseType,
+    table: &str,
+    name: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let probe = format!("SELECT {} FROM {} LIMIT 1", name, table);
+    if sqlx::query(&probe).fetch_optional(pool).await.is_ok() {
+        return Ok(());
     }
+    let definition = match db_type {
+        DatabaseType::MySQL => definition.replace("TEXT", "VARCHAR(64)"),
+        DatabaseType::PostgreSQL | DatabaseType::SQLite => definition.to_string(),
+    };
+    let alter = format!("ALTER TABLE {} ADD COLUMN {} {}", table, name, definition);
+    sqlx::query(&alter).execute(pool).await?;
+    Ok(())
 }
 
-async fn insert_sensor_data(
-    pool: &AnyPool,
+/// Insert a row.
+/// # Arguments
+/// * `executor` - Pool or transaction.
+/// * `data` - Row to insert.
+/// * `db_type` - Database type.
+/// * `timeout` - Insert timeout, `None` for no limit.
+/// # Returns
+/// * `Ok(false)` if a row of the same run and number is stored already.
+async fn insert_sensor_data<'e, E>(
+    executor: E,
     data: &SensorData,
     db_type: &DatabaseType,
-) -> Result<(), BoxError> {
+    timeout: Option<Duration>,
+) -> Result<bool, DatabaseError>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Any>,
+{
     // データベース固有のプレースホルダーと型キャストを使用
     let sql = match db_type {
         DatabaseType::PostgreSQL => {
@@ -165,13 +1730,35 @@ async fn insert_sensor_data(
                 temperature_c,
                 humidity_relative,
                 pressure_pa,
-                thi
+                thi,
+                quality,
+                fan_state,
+                alert_active,
+                sensor,
+                capture_id,
+                quality_score,
+                source,
+                measured_at,
+                run_id,
+                seq,
+                dew_point_c
             ) VALUES (
                 $1::timestamptz,
                 $2,
                 $3,
                 $4,
-                $5
+                $5,
+                $6,
+                $7,
+                $8,
+                $9,
+                $10,
+                $11,
+                $12,
+                $13::timestamptz,
+                $14,
+                $15,
+                $16
             )"#
         }
         DatabaseType::MySQL | DatabaseType::SQLite => {
@@ -181,28 +1768,108 @@ async fn insert_sensor_data(
                 temperature_c,
                 humidity_relative,
                 pressure_pa,
-                thi
-            ) VALUES (?, ?, ?, ?, ?)
+                thi,
+                quality,
+                fan_state,
+                alert_active,
+                sensor,
+                capture_id,
+                quality_score,
+                source,
+                measured_at,
+                run_id,
+                seq,
+                dew_point_c
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#
         }
     };
 
     // すべてのDBでRFC3339形式を使用（PostgreSQLでは::timestamptzキャストで変換）
-    sqlx::query(sql)
+    let finite = |value: f64| value.is_finite().then_some(value);
+    let query = sqlx::query(sql)
         .bind(data.timestamp.to_rfc3339())
         .bind(data.temperature_c)
-        .bind(data.humidity_relative)
-        .bind(data.pressure_pa)
-        .bind(data.thi)
-        .execute(pool)
-        .await?;
+        .bind(finite(data.humidity_relative))
+        .bind(finite(data.pressure_pa))
+        .bind(finite(data.thi))
+        .bind(data.quality.as_str())
+        .bind(data.actions.fan_state.map(i32::from))
+        .bind(data.actions.alert_active.map(i64::from))
+        .bind(data.sensor.as_str())
+        .bind(data.capture_id)
+        .bind(data.quality_score.map(i32::from))
+        .bind(data.source.as_str())
+        .bind(data.measured_at.map(|at| at.to_rfc3339()))
+        .bind(
+            data.sequence
+                .as_ref()
+                .map(|sequence| sequence.run_id.to_string()),
+        )
+        .bind(data.sequence.as_ref().map(|sequence| sequence.seq as i64))
+        .bind(finite(data.dew_point_c));
+    match with_timeout(timeout, query.execute(executor)).await {
+        Ok(_) => Ok(true),
+        // Delivered before, see `sequence`
+        Err(e) if e.is_duplicate() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
 
+/// Store an implausible row in `sensor_data_quarantine`.
+/// # Arguments
+/// * `target` - Database and insert timeout.
+/// * `data` - Rejected row.
+/// * `timestamp` - Timestamp to store.
+/// * `reason` - Why the row was rejected.
+/// # Returns
+/// * Result<(), DatabaseError>
+async fn insert_quarantine(
+    target: &InsertTarget,
+    data: &SensorData,
+    timestamp: DateTime<Local>,
+    reason: &str,
+) -> Result<(), DatabaseError> {
+    let sql = match target.db_type {
+        DatabaseType::PostgreSQL => {
+            r#"
+            INSERT INTO sensor_data_quarantine (
+                timestamp, sensor, temperature_c, humidity_relative,
+                pressure_pa, thi, dew_point_c, capture_id, reason
+            ) VALUES ($1::timestamptz, $2, $3, $4, $5, $6, $7, $8, $9)"#
+        }
+        DatabaseType::MySQL | DatabaseType::SQLite => {
+            r#"
+            INSERT INTO sensor_data_quarantine (
+                timestamp, sensor, temperature_c, humidity_relative,
+                pressure_pa, thi, dew_point_c, capture_id, reason
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#
+        }
+    };
+    let finite = |value: f64| value.is_finite().then_some(value);
+    let query = sqlx::query(sql)
+        .bind(timestamp.to_rfc3339())
+        .bind(data.sensor.as_str())
+        .bind(finite(data.temperature_c))
+        .bind(finite(data.humidity_relative))
+        .bind(finite(data.pressure_pa))
+        .bind(finite(data.thi))
+        .bind(finite(data.dew_point_c))
+        .bind(data.capture_id)
+        .bind(reason);
+    with_timeout(target.timeout, query.execute(&target.pool)).await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::alerts::AlertEngine;
+    use crate::config::{
+        AlertField, AlertRuleConfig, AlertRuleKind, AlertsConfig, QualityConfig, SensorProfile,
+        SensorsConfig, SqliteConfig, ValidationConfig,
+    };
+    use crate::quality::QualityTracker;
     use chrono::{Local, TimeZone};
     use peripheral::bme280::Measurement;
     use tokio::time::{Duration, sleep};
@@ -216,12 +1883,16 @@ mod tests {
         };
         let thi = 72.5;
 
-        let sensor_data = SensorData::from_measurement(measurement, thi);
+        let sensor_data = SensorData::from_measurement_at(measurement, thi, Local::now());
 
         assert_eq!(sensor_data.temperature_c, 25.0);
         assert_eq!(sensor_data.pressure_pa, 101325.0);
         assert_eq!(sensor_data.humidity_relative, 50.0);
         assert_eq!(sensor_data.thi, 72.5);
+        assert!((sensor_data.dew_point_c - 13.85).abs() < 0.01);
+        assert_eq!(sensor_data.sensor, DEFAULT_SENSOR_LABEL);
+        assert_eq!(sensor_data.quality, Quality::Good);
+        assert_eq!(sensor_data.actions, ActionSnapshot::default());
         assert!(sensor_data.timestamp <= Local::now());
     }
 
@@ -229,10 +1900,20 @@ mod tests {
     fn test_sensor_data_debug_format() {
         let sensor_data = SensorData {
             timestamp: Local.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap(),
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
             temperature_c: 23.5,
             humidity_relative: 60.2,
             pressure_pa: 100500.0,
             thi: 75.8,
+            dew_point_c: 15.0,
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
         };
 
         let debug_string = format!("{:?}", sensor_data);
@@ -252,13 +1933,1063 @@ mod tests {
         };
 
         let before = Local::now();
-        let sensor_data = SensorData::from_measurement(measurement, 65.0);
+        let sensor_data = SensorData::from_measurement_at(measurement, 65.0, Local::now());
         let after = Local::now();
 
         assert!(sensor_data.timestamp >= before);
         assert!(sensor_data.timestamp <= after);
     }
 
+    #[test]
+    fn test_sensor_data_from_measurement_at() {
+        let measurement = Measurement {
+            temperature_c: 20.0,
+            pressure_pa: 100000.0,
+            humidity_relative: 40.0,
+        };
+        let measured_at = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap();
+
+        let sensor_data = SensorData::from_measurement_at(measurement, 65.0, measured_at);
+
+        assert_eq!(sensor_data.timestamp, measured_at);
+    }
+
+    #[test]
+    fn test_source_parse() {
+        for source in Source::ALL {
+            assert_eq!(Source::parse(source.as_str()), Ok(source));
+        }
+        assert!(Source::parse("Live").unwrap_err().contains("live, capture"));
+    }
+
+    #[test]
+    fn test_sensor_data_round() {
+        let measurement = Measurement {
+            temperature_c: 22.5,
+            pressure_pa: 100123.5,
+            humidity_relative: 41.25,
+        };
+        let row = || SensorData::from_measurement_at(measurement, 70.5, Local::now());
+
+        let mut half_up = row();
+        half_up.round(0, RoundingMode::HalfUp);
+        assert_eq!(
+            (half_up.temperature_c, half_up.pressure_pa, half_up.thi),
+            (23.0, 100124.0, 71.0)
+        );
+        let mut half_even = row();
+        half_even.round(0, RoundingMode::HalfEven);
+        assert_eq!(
+            (
+                half_even.temperature_c,
+                half_even.pressure_pa,
+                half_even.thi
+            ),
+            (22.0, 100124.0, 70.0)
+        );
+        half_even = row();
+        half_even.round(1, RoundingMode::HalfEven);
+        assert_eq!(half_even.humidity_relative, 41.2);
+    }
+
+    #[test]
+    fn test_sensor_data_quality_during_high_error_rate() {
+        let measurement = Measurement {
+            temperature_c: 23.5,
+            pressure_pa: 100500.0,
+            humidity_relative: 60.2,
+        };
+        let mut tracker = QualityTracker::new(&QualityConfig::default());
+        tracker.record(true);
+        let mut sensor_data = SensorData::from_measurement_at(measurement, 75.8, Local::now());
+        sensor_data.quality = tracker.assess(&measurement);
+        assert_eq!(sensor_data.quality, Quality::Good);
+
+        // Plausible values, but taken while most attempts are failing
+        for _ in 0..QualityConfig::default().suspect_failures {
+            tracker.record(false);
+        }
+        tracker.record(true);
+        let mut sensor_data = SensorData::from_measurement_at(measurement, 75.8, Local::now());
+        sensor_data.quality = tracker.assess(&measurement);
+        assert_eq!(sensor_data.quality, Quality::Suspect);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_database_stores_quality() {
+        sqlx::any::install_default_drivers();
+        let pool = connect_pool("sqlite::memory:", &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE sensor_data (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+             timestamp TEXT NOT NULL, temperature_c REAL NOT NULL, \
+             humidity_relative REAL NOT NULL, pressure_pa REAL NOT NULL, thi REAL NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // A table from an older version gains the columns
+        add_missing_columns(&pool, &DatabaseType::SQLite)
+            .await
+            .unwrap();
+
+        let sensor_data = SensorData {
+            timestamp: Local::now(),
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
+            temperature_c: 23.5,
+            humidity_relative: 60.2,
+            pressure_pa: 100500.0,
+            thi: 75.8,
+            dew_point_c: 15.0,
+            quality: Quality::Suspect,
+            quality_score: Some(27),
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
+        };
+        insert_sensor_data(&pool, &sensor_data, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+
+        let stored: String = sqlx::query_scalar("SELECT quality FROM sensor_data")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, "suspect");
+        let stored: String = sqlx::query_scalar("SELECT sensor FROM sensor_data")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, DEFAULT_SENSOR_LABEL);
+        let stored: i64 = sqlx::query_scalar("SELECT quality_score FROM sensor_data")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 27);
+        let stored: f64 = sqlx::query_scalar("SELECT dew_point_c FROM sensor_data")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 15.0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_database_stores_actions() {
+        sqlx::any::install_default_drivers();
+        let pool = connect_pool("sqlite::memory:", &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE sensor_data (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+             timestamp TEXT NOT NULL, temperature_c REAL NOT NULL, \
+             humidity_relative REAL NOT NULL, pressure_pa REAL NOT NULL, thi REAL NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        add_missing_columns(&pool, &DatabaseType::SQLite)
+            .await
+            .unwrap();
+
+        let mut sensor_data = SensorData::from_measurement_at(
+            Measurement {
+                temperature_c: 23.5,
+                pressure_pa: 100500.0,
+                humidity_relative: 60.2,
+            },
+            75.8,
+            Local::now(),
+        );
+        insert_sensor_data(&pool, &sensor_data, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        sensor_data.actions = ActionSnapshot {
+            fan_state: Some(true),
+            alert_active: Some(0b10),
+        };
+        insert_sensor_data(&pool, &sensor_data, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+
+        let rows: Vec<(Option<i64>, Option<i64>)> =
+            sqlx::query_as("SELECT fan_state, alert_active FROM sensor_data ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        // Disabled subsystems are stored as NULL
+        assert_eq!(rows, vec![(None, None), (Some(1), Some(2))]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_capture_rows_and_events() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let mut tagged = sample_row(1.0);
+        tagged.capture_id = Some(1_750_000_000_000);
+        database.save_async(sample_row(0.0)).unwrap();
+        database.save_async(tagged).unwrap();
+        database.flush().await.unwrap();
+        database
+            .record_event(
+                "capture_start",
+                Local::now(),
+                &serde_json::json!({"capture_id": 1_750_000_000_000_i64}),
+            )
+            .await
+            .unwrap();
+
+        let ids: Vec<Option<i64>> =
+            sqlx::query_scalar("SELECT capture_id FROM sensor_data ORDER BY id")
+                .fetch_all(&database.pool)
+                .await
+                .unwrap();
+        assert_eq!(ids, vec![None, Some(1_750_000_000_000)]);
+        let (kind, detail): (String, String) = sqlx::query_as("SELECT kind, detail FROM events")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(kind, "capture_start");
+        assert_eq!(detail, r#"{"capture_id":1750000000000}"#);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_cleared_alert_is_stored() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let mut engine = AlertEngine::from_config(&AlertsConfig {
+            rules: vec![AlertRuleConfig {
+                name: "HOT".to_string(),
+                kind: AlertRuleKind::Threshold,
+                field: AlertField::TemperatureC,
+                above: Some(30.0),
+                below: None,
+                band: None,
+                message: None,
+                always_override: false,
+            }],
+            ..AlertsConfig::default()
+        });
+        let start = std::time::Instant::now();
+        let ended_at = Local::now();
+        // One reading every 10 minutes, cleared at the 4th
+        for (i, temperature_c) in [31.0, 33.0, 32.0, 25.0].into_iter().enumerate() {
+            let measurement = Measurement {
+                temperature_c,
+                pressure_pa: 100000.0,
+                humidity_relative: 50.0,
+            };
+            let now = start + Duration::from_secs(600 * i as u64);
+            for transition in engine.update(&measurement, 70.0, now) {
+                if let Some(episode) = AlertEpisode::from_transition(&transition, ended_at) {
+                    database.record_alert(&episode).await.unwrap();
+                }
+            }
+        }
+
+        let rows: Vec<(String, String, String, String, String, f64)> = sqlx::query_as(
+            "SELECT name, field, direction, started_at, ended_at, peak FROM alert_events",
+        )
+        .fetch_all(&database.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![(
+                "HOT".to_string(),
+                "temperature_c".to_string(),
+                "above".to_string(),
+                (ended_at - chrono::Duration::minutes(30)).to_rfc3339(),
+                ended_at.to_rfc3339(),
+                33.0
+            )]
+        );
+        database.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_daily_metrics_upsert() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let table = database.daily_metrics_table();
+        let day = NaiveDate::from_ymd_opt(2025, 6, 1).unwrap();
+        assert_eq!(table.load(day).await.unwrap(), None);
+
+        let mut totals = DailyTotals {
+            heating_degree_hours: 12.5,
+            covered_hours: 6.0,
+            ..DailyTotals::new(day)
+        };
+        table.save(&totals).await.unwrap();
+        totals.heating_degree_hours = 30.0;
+        totals.covered_hours = 24.0;
+        table.save(&totals).await.unwrap();
+        assert_eq!(table.load(day).await.unwrap(), Some(totals));
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM daily_metrics")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+        database.close().await;
+    }
+
+    fn sample_row(i: f64) -> SensorData {
+        SensorData::from_measurement_at(
+            Measurement {
+                temperature_c: 20.0 + i,
+                pressure_pa: 100000.0,
+                humidity_relative: 50.0,
+            },
+            70.0,
+            Local::now(),
+        )
+    }
+
+    fn first_message(i: f64) -> Batch {
+        let mut batch = Batch::default();
+        batch.push(WriterMessage::Row(sample_row(i)));
+        batch
+    }
+
+    #[tokio::test]
+    async fn test_collect_batch_until_full() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        for i in 1..4 {
+            sender
+                .send(WriterMessage::Row(sample_row(i as f64)))
+                .unwrap();
+        }
+        let policy = GroupCommit {
+            interval: Duration::from_secs(3600),
+            batch_size: 2,
+        };
+
+        let mut batch = first_message(0.0);
+        assert!(collect_batch(&mut receiver, &mut batch, &policy).await);
+        assert_eq!(batch.rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_collect_batch_until_interval() {
+        let (_sender, mut receiver) = mpsc::unbounded_channel();
+        let policy = GroupCommit {
+            interval: Duration::from_millis(10),
+            batch_size: 100,
+        };
+
+        let mut batch = first_message(0.0);
+        assert!(collect_batch(&mut receiver, &mut batch, &policy).await);
+        assert_eq!(batch.rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_collect_batch_flush_and_close() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let policy = GroupCommit {
+            interval: Duration::from_secs(3600),
+            batch_size: 100,
+        };
+
+        let (done, _wait) = oneshot::channel();
+        sender.send(WriterMessage::Flush(done)).unwrap();
+        sender.send(WriterMessage::Row(sample_row(2.0))).unwrap();
+        let mut batch = first_message(0.0);
+        assert!(collect_batch(&mut receiver, &mut batch, &policy).await);
+        // The flush ends the batch, the next row waits for the next one
+        assert_eq!((batch.rows.len(), batch.flushes.len()), (1, 1));
+
+        drop(sender);
+        let mut batch = Batch::default();
+        assert!(!collect_batch(&mut receiver, &mut batch, &policy).await);
+        assert_eq!(batch.rows.len(), 1);
+    }
+
+    /// Path of a scratch SQLite file, removed first.
+    fn scratch_sqlite(name: &str) -> (std::path::PathBuf, String) {
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite:{}?mode=rwc", path.display());
+        (path, url)
+    }
+
+    #[tokio::test]
+    async fn test_slow_operation_times_out() {
+        let slow = async {
+            sleep(Duration::from_secs(5)).await;
+            Ok::<(), sqlx::Error>(())
+        };
+        let error = with_timeout(Some(Duration::from_millis(20)), slow)
+            .await
+            .unwrap_err();
+        assert!(error.is_transient());
+        assert!(
+            matches!(error, DatabaseError::Timeout(limit) if limit == Duration::from_millis(20))
+        );
+
+        let fast = async { Ok::<u8, sqlx::Error>(1) };
+        assert_eq!(
+            with_timeout(Some(Duration::from_secs(1)), fast)
+                .await
+                .unwrap(),
+            1
+        );
+        let failed = async { Err::<(), _>(sqlx::Error::PoolClosed) };
+        assert!(!with_timeout(None, failed).await.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_insert_into_locked_database_times_out() {
+        let (path, url) = scratch_sqlite("insert-timeout");
+        let database = Database::new(&url).await.unwrap();
+        database.close().await;
+
+        // Another connection holds the write lock, so the insert waits for
+        // SQLite's busy timeout
+        let locker = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let mut lock = locker.acquire().await.unwrap();
+        sqlx::query("BEGIN EXCLUSIVE")
+            .execute(&mut *lock)
+            .await
+            .unwrap();
+
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let error = insert_sensor_data(
+            &pool,
+            &sample_row(0.0),
+            &DatabaseType::SQLite,
+            Some(Duration::from_millis(200)),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is_transient(), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        sqlx::query("ROLLBACK").execute(&mut *lock).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    async fn count_rows(url: &str) -> i64 {
+        let pool = connect_pool(url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM sensor_data")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+        count
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_implausible_row_is_quarantined() {
+        let (path, url) = scratch_sqlite("quarantine");
+        let database = Database::new(&url).await.unwrap();
+        database.save_async(sample_row(1.0)).unwrap();
+        let mut spike = sample_row(2.0);
+        spike.temperature_c = 130.5;
+        spike.humidity_relative = f64::NAN;
+        database.save_async(spike).unwrap();
+        database.flush().await.unwrap();
+        database.close().await;
+
+        assert_eq!(count_rows(&url).await, 1);
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let (temperature_c, humidity_relative, reason): (f64, Option<f64>, String) =
+            sqlx::query_as(
+                "SELECT temperature_c, humidity_relative, reason FROM sensor_data_quarantine",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(temperature_c, 130.5);
+        assert_eq!(humidity_relative, None);
+        assert_eq!(
+            reason,
+            "temperature_c 130.5 above 85, humidity_relative NaN"
+        );
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_rows_are_checked_against_their_profile() {
+        let (path, url) = scratch_sqlite("profiles");
+        let config = DatabaseConfig {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let sensors = SensorsConfig {
+            label: "living".to_string(),
+            profile: Some(SensorProfile::Indoor),
+            extra: vec![crate::config::ExtraSensorConfig {
+                driver: crate::config::SensorType::Bme280,
+                address: None,
+                label: "garden".to_string(),
+                profile: Some(SensorProfile::Outdoor),
+            }],
+            ..Default::default()
+        };
+        let plausibility = Plausibility::from_config(&config.validation, &sensors);
+        let database = Database::connect_with_hook(&config, plausibility, None, None)
+            .await
+            .unwrap();
+        database.migrate().await.unwrap();
+        for sensor in ["living", "garden"] {
+            let mut frost = sample_row(-28.0);
+            frost.sensor = sensor.to_string();
+            database.save_async(frost).unwrap();
+        }
+        database.flush().await.unwrap();
+        database.close().await;
+
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let stored: Vec<String> = sqlx::query_scalar("SELECT sensor FROM sensor_data")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let quarantined: Vec<String> =
+            sqlx::query_scalar("SELECT sensor FROM sensor_data_quarantine")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(stored, vec!["garden"]);
+        assert_eq!(quarantined, vec!["living"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_rows_without_humidity_are_stored() {
+        let (path, url) = scratch_sqlite("bmp280");
+        let config = DatabaseConfig {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let plausibility =
+            Plausibility::new(&config.validation).without_humidity(vec!["bmp280".to_string()]);
+        let database = Database::connect_with_hook(&config, plausibility, None, None)
+            .await
+            .unwrap();
+        database.migrate().await.unwrap();
+        let mut row = sample_row(0.0);
+        row.sensor = "bmp280".to_string();
+        row.humidity_relative = f64::NAN;
+        row.thi = f64::NAN;
+        row.dew_point_c = f64::NAN;
+        database.save_async(row).unwrap();
+        database.flush().await.unwrap();
+        database.close().await;
+
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let stored: Vec<String> = sqlx::query_scalar("SELECT sensor FROM sensor_data")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let quarantined: Vec<String> =
+            sqlx::query_scalar("SELECT sensor FROM sensor_data_quarantine")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(stored, vec!["bmp280"]);
+        assert!(quarantined.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_migrate_allows_missing_values_in_older_tables() {
+        let (path, url) = scratch_sqlite("not_null");
+        sqlx::any::install_default_drivers();
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE sensor_data (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+             timestamp TEXT NOT NULL, temperature_c REAL NOT NULL, \
+             humidity_relative REAL NOT NULL, pressure_pa REAL NOT NULL, thi REAL NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO sensor_data (id, timestamp, temperature_c, humidity_relative, \
+             pressure_pa, thi) VALUES (7, '2024-01-01T00:00:00+00:00', 20.0, 50.0, 100000.0, 70.0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let config = DatabaseConfig {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let plausibility = Plausibility::new(&config.validation)
+            .without_humidity(vec![DEFAULT_SENSOR_LABEL.to_string()]);
+        let database = Database::connect_with_hook(&config, plausibility, None, None)
+            .await
+            .unwrap();
+        database.migrate().await.unwrap();
+        // Running it again leaves the rebuilt table alone
+        database.migrate().await.unwrap();
+        let mut row = sample_row(0.0);
+        row.humidity_relative = f64::NAN;
+        row.thi = f64::NAN;
+        database.save_async(row).unwrap();
+        database.flush().await.unwrap();
+        let rows = database.export_rows(0, None, None, &[], 10).await.unwrap();
+        database.close().await;
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].id, 7);
+        assert_eq!(rows[0].measurement.humidity_relative, 50.0);
+        assert_eq!(rows[1].id, 8);
+        assert!(rows[1].measurement.humidity_relative.is_nan());
+        assert!(rows[1].thi.is_nan());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_validation_disabled_stores_every_row() {
+        let (path, url) = scratch_sqlite("no-validation");
+        let config = DatabaseConfig {
+            url: url.clone(),
+            validation: ValidationConfig {
+                enabled: false,
+                ..ValidationConfig::default()
+            },
+            ..Default::default()
+        };
+        let database = Database::from_config(&config).await.unwrap();
+        let mut spike = sample_row(1.0);
+        spike.temperature_c = 130.5;
+        database.save_async(spike).unwrap();
+        database.flush().await.unwrap();
+        database.close().await;
+
+        assert_eq!(count_rows(&url).await, 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn group_commit_config(url: &str, batch_size: usize) -> DatabaseConfig {
+        DatabaseConfig {
+            url: url.to_string(),
+            sqlite: SqliteConfig {
+                commit_interval_secs: 3600,
+                batch_size,
+                synchronous: Some(SqliteSynchronous::Normal),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_group_commit_crash_loses_only_open_window() {
+        let (path, url) = scratch_sqlite("group-commit-crash");
+        let database = Database::from_config(&group_commit_config(&url, 2))
+            .await
+            .unwrap();
+
+        for i in 0..5 {
+            database.save_async(sample_row(i as f64)).unwrap();
+        }
+        // Two full batches are committed, the 5th row waits for the interval
+        while database.queue_len() > 1 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // Kill the writer without flushing
+        database.writer.abort();
+        let _ = database.writer.await;
+
+        assert_eq!(count_rows(&url).await, 4);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_redelivered_rows_are_stored_once() {
+        let (path, url) = scratch_sqlite("redelivery");
+        let config = group_commit_config(&url, 2);
+        let plausibility = Plausibility::new(&config.validation);
+        let hooked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let connect = |hooked: Arc<std::sync::Mutex<Vec<(String, u64)>>>| {
+            let hook: InsertHook = Box::new(move |data| {
+                let sequence = data.sequence.clone().unwrap();
+                hooked
+                    .lock()
+                    .unwrap()
+                    .push((sequence.run_id.to_string(), sequence.seq));
+            });
+            Database::connect_with_hook(&config, plausibility.clone(), Some(hook), None)
+        };
+        let numbered = |seq: u64| {
+            let mut row = sample_row(seq as f64);
+            row.sequence = Some(Sequence {
+                run_id: "run-a".into(),
+                seq,
+            });
+            row
+        };
+        let database = connect(hooked.clone()).await.unwrap();
+        database.migrate().await.unwrap();
+        for seq in 1..=5 {
+            database.save_async(numbered(seq)).unwrap();
+        }
+        // Two batches are committed, then the writer crashes before any
+        // flush acknowledged them
+        while database.queue_len() > 1 {
+            sleep(Duration::from_millis(10)).await;
+        }
+        database.writer.abort();
+        let _ = database.writer.await;
+        assert_eq!(count_rows(&url).await, 4);
+
+        // After the restart every unacknowledged row is delivered again,
+        // along with the rows of the new run
+        let database = connect(hooked.clone()).await.unwrap();
+        database.migrate().await.unwrap();
+        for seq in 1..=5 {
+            database.save_async(numbered(seq)).unwrap();
+        }
+        database.save_async(sample_row(6.0)).unwrap();
+        database.save_async(sample_row(7.0)).unwrap();
+        database.close().await;
+
+        let pool = connect_pool(&url, &DatabaseType::SQLite, None)
+            .await
+            .unwrap();
+        let copies: Vec<(String, i64, i64)> = sqlx::query_as(
+            "SELECT run_id, seq, COUNT(*) FROM sensor_data GROUP BY run_id, seq ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(copies.len(), 7);
+        assert!(copies.iter().all(|(_, _, count)| *count == 1));
+        let run_a: Vec<i64> = copies
+            .iter()
+            .filter(|(run_id, _, _)| run_id == "run-a")
+            .map(|(_, seq, _)| *seq)
+            .collect();
+        assert_eq!(run_a, vec![1, 2, 3, 4, 5]);
+        // The new run is numbered under its own id
+        let this_run = sequence::this_run().run_id();
+        assert_eq!(copies[5].0, this_run);
+        assert!(copies[5].1 < copies[6].1);
+        // The hook ran once per stored row
+        let hooked = hooked.lock().unwrap();
+        assert_eq!(hooked.len(), 7);
+        assert_eq!(
+            hooked
+                .iter()
+                .filter(|(run_id, _)| run_id == "run-a")
+                .count(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_group_commit_flush_and_close() {
+        let (path, url) = scratch_sqlite("group-commit-flush");
+        let database = Database::from_config(&group_commit_config(&url, 100))
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            database.save_async(sample_row(i as f64)).unwrap();
+        }
+        database.flush().await.unwrap();
+        assert_eq!(database.queue_len(), 0);
+        assert_eq!(count_rows(&url).await, 3);
+
+        database.save_async(sample_row(3.0)).unwrap();
+        database.close().await;
+        assert_eq!(count_rows(&url).await, 4);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_insert_hook_sees_stored_rows() {
+        let stored = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = stored.clone();
+        let hook: InsertHook = Box::new(move |data| seen.lock().unwrap().push(data.temperature_c));
+        let config = DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            ..Default::default()
+        };
+        let plausibility = Plausibility::new(&config.validation);
+        let database = Database::connect_with_hook(&config, plausibility, Some(hook), None)
+            .await
+            .unwrap();
+        database.migrate().await.unwrap();
+
+        database.save_async(sample_row(1.0)).unwrap();
+        database.save_async(sample_row(2.0)).unwrap();
+        database.flush().await.unwrap();
+
+        assert_eq!(*stored.lock().unwrap(), vec![21.0, 22.0]);
+        database.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_writer_traces_rows_under_their_tick() {
+        let dir = std::env::temp_dir().join(format!("wbroker-rs-db-trace-{}", std::process::id()));
+        let trace = Arc::new(TraceControl::new(&crate::config::TraceConfig {
+            dir: dir.display().to_string(),
+            ..Default::default()
+        }));
+        let request = crate::trace::TraceRequest { secs: 60 };
+        let status = trace
+            .start(request, Local::now(), std::time::Instant::now())
+            .unwrap();
+        let config = DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            ..Default::default()
+        };
+        let plausibility = Plausibility::new(&config.validation);
+        let database =
+            Database::connect_with_hook(&config, plausibility, None, Some(trace.clone()))
+                .await
+                .unwrap();
+        database.migrate().await.unwrap();
+
+        let mut row = sample_row(1.0);
+        row.tick = Some(42);
+        database.save_async(row).unwrap();
+        // Rows of other callers are not traced
+        database.save_async(sample_row(2.0)).unwrap();
+        database.flush().await.unwrap();
+        database.close().await;
+        let content = std::fs::read_to_string(&status.path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["tick"], 42);
+        assert_eq!(lines[0]["stage"], "db");
+        assert_eq!(lines[0]["detail"]["result"], "stored");
+    }
+
+    #[test]
+    fn test_resolve_timestamp_measurement_time() {
+        let measured_at = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap();
+        let inserted_at = measured_at + chrono::Duration::seconds(90);
+        let sensor_data = SensorData {
+            timestamp: measured_at,
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
+            temperature_c: 20.0,
+            humidity_relative: 40.0,
+            pressure_pa: 100000.0,
+            thi: 65.0,
+            dew_point_c: 15.0,
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
+        };
+
+        // The stamp reflects when the reading was taken, not the queue delay
+        assert_eq!(
+            resolve_timestamp(&sensor_data, TimestampSource::Measurement, inserted_at),
+            measured_at
+        );
+        assert_eq!(
+            resolve_timestamp(&sensor_data, TimestampSource::Insertion, inserted_at),
+            inserted_at
+        );
+    }
+
+    fn row_at(sensor: &str, timestamp: DateTime<Local>) -> SensorData {
+        let mut data = sample_row(0.0);
+        data.sensor = sensor.to_string();
+        data.timestamp = timestamp;
+        data
+    }
+
+    /// Measurement times with the clock stepped back 2 s after the 3rd row.
+    fn stepped_back_times() -> Vec<DateTime<Local>> {
+        let start = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 0).unwrap();
+        [0, 1000, 2000, 0, 500, 2500, 2500]
+            .iter()
+            .map(|&ms| start + chrono::Duration::milliseconds(ms))
+            .collect()
+    }
+
+    fn timestamper(backward: BackwardTimestamps) -> Timestamper {
+        Timestamper::new(
+            TimestampSource::Measurement,
+            backward,
+            Arc::new(TimestampStats::default()),
+        )
+    }
+
+    #[test]
+    fn test_timestamper_clamps_backward_step() {
+        let mut timestamper = timestamper(BackwardTimestamps::Clamp);
+        let times = stepped_back_times();
+        let stored: Vec<_> = times
+            .iter()
+            .map(|&at| {
+                let mut data = row_at("bme280", at);
+                assert!(timestamper.stamp(&mut data, Local::now()));
+                data.timestamp
+            })
+            .collect();
+
+        assert!(stored.windows(2).all(|w| w[0] <= w[1]));
+        let ms = chrono::Duration::milliseconds(1);
+        assert_eq!(stored[3], times[2] + ms);
+        assert_eq!(stored[4], times[2] + ms + ms);
+        // An equal timestamp is not a step back
+        assert_eq!(stored[5], times[5]);
+        assert_eq!(stored[6], times[6]);
+        assert_eq!(
+            timestamper.stats.counts(),
+            TimestampCounts {
+                clamped: 2,
+                ..TimestampCounts::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_timestamper_drops_backward_step() {
+        let mut timestamper = timestamper(BackwardTimestamps::Drop);
+        let times = stepped_back_times();
+        let kept: Vec<_> = times
+            .iter()
+            .filter_map(|&at| {
+                let mut data = row_at("bme280", at);
+                timestamper
+                    .stamp(&mut data, Local::now())
+                    .then_some(data.timestamp)
+            })
+            .collect();
+
+        assert_eq!(kept, vec![times[0], times[1], times[2], times[5], times[6]]);
+        assert_eq!(timestamper.stats.counts().dropped, 2);
+    }
+
+    #[test]
+    fn test_timestamper_bumps_out_of_order_rows() {
+        let mut timestamper = timestamper(BackwardTimestamps::Bump);
+        let times = stepped_back_times();
+        // Inserted right away, before the clock step
+        let now = times[2];
+        let stored: Vec<_> = times
+            .iter()
+            .map(|&at| {
+                let mut data = row_at("bme280", at);
+                assert!(timestamper.stamp(&mut data, now));
+                data.timestamp
+            })
+            .collect();
+
+        assert!(stored.windows(2).all(|w| w[0] < w[1]));
+        let ms = chrono::Duration::milliseconds(1);
+        assert_eq!(stored[..3], times[..3]);
+        assert_eq!(stored[3], times[2] + ms);
+        assert_eq!(stored[4], times[2] + ms + ms);
+        assert_eq!(stored[5], times[5]);
+        // Equal to the previous row, not only earlier
+        assert_eq!(stored[6], times[6] + ms);
+        assert_eq!(timestamper.stats.counts().bumped, 3);
+    }
+
+    #[test]
+    fn test_timestamper_bumps_past_insertion_time() {
+        let mut timestamper = timestamper(BackwardTimestamps::Bump);
+        let times = stepped_back_times();
+        let mut data = row_at("bme280", times[2]);
+        assert!(timestamper.stamp(&mut data, times[2]));
+        // A late out-of-order row, written well after the previous one
+        let now = times[2] + chrono::Duration::seconds(30);
+        let mut data = row_at("bme280", times[1]);
+        assert!(timestamper.stamp(&mut data, now));
+        assert_eq!(data.timestamp, now + chrono::Duration::milliseconds(1));
+    }
+
+    #[test]
+    fn test_timestamper_tracks_each_sensor() {
+        let mut timestamper = timestamper(BackwardTimestamps::Clamp);
+        let times = stepped_back_times();
+        let mut data = row_at("bme280", times[2]);
+        assert!(timestamper.stamp(&mut data, Local::now()));
+        // Another sensor has its own history
+        let mut data = row_at("reference", times[0]);
+        assert!(timestamper.stamp(&mut data, Local::now()));
+        assert_eq!(data.timestamp, times[0]);
+        assert_eq!(timestamper.stats.counts(), TimestampCounts::default());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_stored_timestamps_never_go_back() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        for at in stepped_back_times() {
+            database.save_async(row_at("bme280", at)).unwrap();
+            database.save_async(row_at("reference", at)).unwrap();
+        }
+        database.flush().await.unwrap();
+
+        for sensor in ["bme280", "reference"] {
+            let stored: Vec<String> = sqlx::query_scalar(
+                "SELECT timestamp FROM sensor_data WHERE sensor = ? ORDER BY id",
+            )
+            .bind(sensor)
+            .fetch_all(&database.pool)
+            .await
+            .unwrap();
+            let stored: Vec<_> = stored
+                .iter()
+                .map(|t| DateTime::parse_from_rfc3339(t).unwrap())
+                .collect();
+            assert_eq!(stored.len(), 7);
+            assert!(stored.windows(2).all(|w| w[0] <= w[1]));
+        }
+        assert_eq!(database.timestamp_stats().counts().clamped, 4);
+        assert_eq!(database.queue_len(), 0);
+        database.close().await;
+    }
+
     #[test]
     fn test_sensor_data_extreme_values() {
         let measurement = Measurement {
@@ -268,7 +2999,7 @@ mod tests {
         };
         let thi = 0.0;
 
-        let sensor_data = SensorData::from_measurement(measurement, thi);
+        let sensor_data = SensorData::from_measurement_at(measurement, thi, Local::now());
 
         assert_eq!(sensor_data.temperature_c, -40.0);
         assert_eq!(sensor_data.pressure_pa, 30000.0);
@@ -290,10 +3021,20 @@ mod tests {
 
         let sensor_data = SensorData {
             timestamp: Local::now(),
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
             temperature_c: 25.0,
             humidity_relative: 50.0,
             pressure_pa: 101325.0,
             thi: 72.5,
+            dew_point_c: 15.0,
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
         };
 
         let result = database.save_async(sensor_data);
@@ -307,10 +3048,20 @@ mod tests {
 
         let sensor_data = SensorData {
             timestamp: Local::now(),
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
             temperature_c: 23.5,
             humidity_relative: 60.2,
             pressure_pa: 100500.0,
             thi: 75.8,
+            dew_point_c: 15.0,
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
         };
 
         assert!(database.save_async(sensor_data).is_ok());
@@ -325,10 +3076,20 @@ mod tests {
         for i in 0..5 {
             let sensor_data = SensorData {
                 timestamp: Local::now(),
+                sensor: DEFAULT_SENSOR_LABEL.to_string(),
                 temperature_c: 20.0 + i as f64,
                 humidity_relative: 50.0 + i as f64,
                 pressure_pa: 100000.0 + i as f64 * 100.0,
                 thi: 70.0 + i as f64,
+                dew_point_c: 15.0,
+                quality: Quality::Good,
+                quality_score: None,
+                actions: ActionSnapshot::default(),
+                capture_id: None,
+                source: Source::Live,
+                measured_at: None,
+                tick: None,
+                sequence: None,
             };
             assert!(database.save_async(sensor_data).is_ok());
         }
@@ -351,11 +3112,6 @@ mod tests {
         assert!(!"sqlite:memory:".starts_with("mysql"));
     }
 
-    #[test]
-    fn test_box_error_type_alias() {
-        let _error: BoxError = Box::new(std::io::Error::new(std::io::ErrorKind::Other, "test"));
-    }
-
     #[test]
     fn test_rfc3339_timestamp_format() {
         let timestamp = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap();
@@ -376,10 +3132,20 @@ mod tests {
 
         let sensor_data = SensorData {
             timestamp: Local::now(),
+            sensor: DEFAULT_SENSOR_LABEL.to_string(),
             temperature_c: f64::NAN,
             humidity_relative: f64::INFINITY,
             pressure_pa: f64::NEG_INFINITY,
             thi: 75.0,
+            dew_point_c: 15.0,
+            quality: Quality::Good,
+            quality_score: None,
+            actions: ActionSnapshot::default(),
+            capture_id: None,
+            source: Source::Live,
+            measured_at: None,
+            tick: None,
+            sequence: None,
         };
 
         let result = database.save_async(sensor_data);
@@ -397,7 +3163,7 @@ mod tests {
         };
         let thi = 0.0;
 
-        let sensor_data = SensorData::from_measurement(measurement, thi);
+        let sensor_data = SensorData::from_measurement_at(measurement, thi, Local::now());
 
         assert!(sensor_data.temperature_c.is_nan());
         assert!(
@@ -420,10 +3186,20 @@ mod tests {
 
             let sensor_data = SensorData {
                 timestamp: Local::now(),
+                sensor: DEFAULT_SENSOR_LABEL.to_string(),
                 temperature_c: 25.0,
                 humidity_relative: 50.0,
                 pressure_pa: 101325.0,
                 thi: 72.5,
+                dew_point_c: 15.0,
+                quality: Quality::Good,
+                quality_score: None,
+                actions: ActionSnapshot::default(),
+                capture_id: None,
+                source: Source::Live,
+                measured_at: None,
+                tick: None,
+                sequence: None,
             };
 
             assert!(database.save_async(sensor_data).is_ok());
@@ -440,10 +3216,20 @@ mod tests {
         if let Ok(database) = Database::new(connection_string).await {
             let sensor_data = SensorData {
                 timestamp: Local::now(),
+                sensor: DEFAULT_SENSOR_LABEL.to_string(),
                 temperature_c: 23.5,
                 humidity_relative: 60.2,
                 pressure_pa: 100500.0,
                 thi: 75.8,
+                dew_point_c: 15.0,
+                quality: Quality::Good,
+                quality_score: None,
+                actions: ActionSnapshot::default(),
+                capture_id: None,
+                source: Source::Live,
+                measured_at: None,
+                tick: None,
+                sequence: None,
             };
 
             assert!(database.save_async(sensor_data).is_ok());
@@ -489,10 +3275,20 @@ mod tests {
 
             let sensor_data = SensorData {
                 timestamp: Local::now(),
+                sensor: DEFAULT_SENSOR_LABEL.to_string(),
                 temperature_c: 25.0,
                 humidity_relative: 50.0,
                 pressure_pa: 101325.0,
                 thi: 72.5,
+                dew_point_c: 15.0,
+                quality: Quality::Good,
+                quality_score: None,
+                actions: ActionSnapshot::default(),
+                capture_id: None,
+                source: Source::Live,
+                measured_at: None,
+                tick: None,
+                sequence: None,
             };
 
             assert!(database.save_async(sensor_data).is_ok());
@@ -509,10 +3305,20 @@ mod tests {
         if let Ok(database) = Database::new(connection_string).await {
             let sensor_data = SensorData {
                 timestamp: Local::now(),
+                sensor: DEFAULT_SENSOR_LABEL.to_string(),
                 temperature_c: 23.5,
                 humidity_relative: 60.2,
                 pressure_pa: 100500.0,
                 thi: 75.8,
+                dew_point_c: 15.0,
+                quality: Quality::Good,
+                quality_score: None,
+                actions: ActionSnapshot::default(),
+                capture_id: None,
+                source: Source::Live,
+                measured_at: None,
+                tick: None,
+                sequence: None,
             };
 
             assert!(database.save_async(sensor_data).is_ok());
@@ -574,10 +3380,20 @@ mod tests {
             let handle = tokio::spawn(async move {
                 let sensor_data = SensorData {
                     timestamp: Local::now(),
+                    sensor: DEFAULT_SENSOR_LABEL.to_string(),
                     temperature_c: 20.0 + i as f64,
                     humidity_relative: 50.0,
                     pressure_pa: 101325.0,
                     thi: 70.0,
+                    dew_point_c: 15.0,
+                    quality: Quality::Good,
+                    quality_score: None,
+                    actions: ActionSnapshot::default(),
+                    capture_id: None,
+                    source: Source::Live,
+                    measured_at: None,
+                    tick: None,
+                    sequence: None,
                 };
                 db_clone.save_async(sensor_data)
             });
@@ -0,0 +1,292 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Free space of the filesystem holding the SQLite file.
+//!
+//! A full SD card makes SQLite fail on every write and can leave a broken
+//! WAL behind. The free space is checked periodically: below the warning
+//! threshold an event is stored, below the floor rows are dropped and
+//! counted instead of written. Writing resumes on its own once space is
+//! freed.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::DiskConfig;
+
+const MB: u64 = 1024 * 1024;
+
+/// Free space level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskLevel {
+    #[default]
+    Ok,
+    /// Below the warning threshold, still written.
+    Low,
+    /// Below the floor, writes are paused.
+    Full,
+}
+
+/// Free space as reported by `GET /api/info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiskStatus {
+    /// Directory checked.
+    pub path: PathBuf,
+    /// Free space available to the process, `None` until checked.
+    pub free_bytes: Option<u64>,
+    pub level: DiskLevel,
+    /// Rows dropped while writes were paused.
+    pub dropped_rows: u64,
+}
+
+/// Disk status shared with the HTTP API.
+#[derive(Debug, Default)]
+pub struct DiskStats {
+    status: Mutex<DiskStatus>,
+}
+
+impl DiskStats {
+    /// Current status.
+    pub fn status(&self) -> DiskStatus {
+        self.lock().clone()
+    }
+
+    /// Count rows dropped while paused.
+    pub fn add_dropped(&self, rows: u64) {
+        self.lock().dropped_rows += rows;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DiskStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Periodic free space check, run from the measurement loop.
+#[derive(Debug)]
+pub struct DiskMonitor {
+    path: PathBuf,
+    warn_below: u64,
+    pause_below: u64,
+    interval: Duration,
+    next_check: Instant,
+    level: DiskLevel,
+    stats: Arc<DiskStats>,
+}
+
+impl DiskMonitor {
+    /// Create a monitor for the filesystem of a SQLite database.
+    /// # Arguments
+    /// * `database_url` - Database URL.
+    /// * `config` - Thresholds and check interval.
+    /// * `now` - Current time, the first check is due right away.
+    /// # Returns
+    /// * `None` for other databases and in-memory SQLite.
+    pub fn for_database(database_url: &str, config: &DiskConfig, now: Instant) -> Option<Self> {
+        let path = sqlite_directory(database_url)?;
+        let stats = DiskStats {
+            status: Mutex::new(DiskStatus {
+                path: path.clone(),
+                ..DiskStatus::default()
+            }),
+        };
+        Some(Self {
+            path,
+            warn_below: config.warn_below_mb * MB,
+            pause_below: config.pause_below_mb * MB,
+            interval: Duration::from_secs(config.check_interval_secs.max(1)),
+            next_check: now,
+            level: DiskLevel::Ok,
+            stats: Arc::new(stats),
+        })
+    }
+
+    /// Status shared with the HTTP API.
+    pub fn stats(&self) -> Arc<DiskStats> {
+        self.stats.clone()
+    }
+
+    /// Directory checked.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether writes are paused.
+    pub fn is_paused(&self) -> bool {
+        self.level == DiskLevel::Full
+    }
+
+    /// Check the free space if the interval has passed.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The new level when it changed.
+    pub fn check(&mut self, now: Instant) -> Option<DiskLevel> {
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + self.interval;
+        match free_bytes(&self.path) {
+            Ok(free) => self.apply(free),
+            Err(e) => {
+                eprintln!(
+                    "Failed to check free space of {}: {}",
+                    self.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Update the level from a free space reading.
+    /// # Arguments
+    /// * `free` - Free space in bytes.
+    /// # Returns
+    /// * The new level when it changed.
+    fn apply(&mut self, free: u64) -> Option<DiskLevel> {
+        let level = if free < self.pause_below {
+            DiskLevel::Full
+        } else if free < self.warn_below {
+            DiskLevel::Low
+        } else {
+            DiskLevel::Ok
+        };
+        {
+            let mut status = self.stats.lock();
+            status.free_bytes = Some(free);
+            status.level = level;
+        }
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some(level)
+    }
+}
+
+/// Directory holding the file of a SQLite URL.
+/// # Arguments
+/// * `url` - Database URL, e.g. "sqlite:./sensor_data.db?mode=rwc".
+/// # Returns
+/// * `None` for other databases and in-memory SQLite.
+fn sqlite_directory(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("sqlite:")?;
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let file = rest.split('?').next().unwrap_or_default();
+    if file.is_empty() || file == ":memory:" {
+        return None;
+    }
+    match Path::new(file).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Some(parent.to_path_buf()),
+        _ => Some(PathBuf::from(".")),
+    }
+}
+
+/// Free space of a filesystem available to unprivileged writers.
+/// # Arguments
+/// * `path` - Any path on the filesystem.
+/// # Returns
+/// * Free space in bytes.
+pub fn free_bytes(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is written by statvfs
+    // before it is read.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> DiskMonitor {
+        let config = DiskConfig {
+            warn_below_mb: 200,
+            pause_below_mb: 50,
+            check_interval_secs: 30,
+        };
+        DiskMonitor::for_database("sqlite:./data/sensor.db", &config, Instant::now()).unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_directory() {
+        let dir = |url| sqlite_directory(url);
+        assert_eq!(
+            dir("sqlite:./data/sensor.db"),
+            Some(PathBuf::from("./data"))
+        );
+        assert_eq!(
+            dir("sqlite:///var/lib/wbroker/sensor.db?mode=rwc"),
+            Some(PathBuf::from("/var/lib/wbroker"))
+        );
+        assert_eq!(dir("sqlite:sensor.db"), Some(PathBuf::from(".")));
+        assert_eq!(dir("sqlite::memory:"), None);
+        assert_eq!(dir("postgres://localhost/db"), None);
+    }
+
+    #[test]
+    fn test_levels_pause_and_resume() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.apply(1024 * MB), None);
+        assert_eq!(monitor.apply(100 * MB), Some(DiskLevel::Low));
+        assert!(!monitor.is_paused());
+        assert_eq!(monitor.apply(10 * MB), Some(DiskLevel::Full));
+        assert!(monitor.is_paused());
+        assert_eq!(monitor.apply(20 * MB), None);
+
+        monitor.stats().add_dropped(3);
+        // Resumes without intervention once space is freed
+        assert_eq!(monitor.apply(500 * MB), Some(DiskLevel::Ok));
+        assert!(!monitor.is_paused());
+        let status = monitor.stats().status();
+        assert_eq!(status.free_bytes, Some(500 * MB));
+        assert_eq!(status.dropped_rows, 3);
+    }
+
+    #[test]
+    fn test_check_interval() {
+        let mut monitor = monitor();
+        let now = Instant::now();
+        monitor.next_check = now + Duration::from_secs(30);
+        assert_eq!(monitor.check(now), None);
+        assert_eq!(monitor.stats().status().free_bytes, None);
+    }
+
+    #[test]
+    fn test_free_bytes() {
+        assert!(free_bytes(&std::env::temp_dir()).unwrap() > 0);
+        assert!(free_bytes(Path::new("/nonexistent/wbroker")).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Display selected by the configuration.
+
+use peripheral::bus::{I2cDevice, SharedI2c};
+use peripheral::display::{CharDisplay, DisplayGeometry};
+use peripheral::hd44780;
+use peripheral::so1602a;
+use rppal::i2c;
+
+use crate::cgram::CgramSlots;
+use crate::config::{DisplayConfig, DisplayType};
+use crate::simulate::NullDisplay;
+
+/// One of the supported display drivers.
+pub enum Display {
+    So1602a(so1602a::SO1602A<I2cDevice>),
+    Hd44780(hd44780::Hd44780<I2cDevice>),
+    /// Printed to stdout, for `--simulate`.
+    Null(NullDisplay),
+}
+
+impl Display {
+    /// Attach the display selected by the configuration to the shared bus.
+    /// # Arguments
+    /// * `config` - Display configuration.
+    /// * `bus` - Shared I2C bus.
+    /// # Returns
+    /// * Display
+    pub fn from_config(config: &DisplayConfig, bus: &SharedI2c) -> Display {
+        match config.driver {
+            DisplayType::So1602a => Display::So1602a(
+                so1602a::SO1602A::with_bus(bus.device(config.i2c_address()))
+                    .with_contrast(config.contrast, config.contrast_ramp())
+                    .with_write_delay(config.write_delay())
+                    .with_geometry(config.geometry()),
+            ),
+            DisplayType::Hd44780 => Display::Hd44780(
+                hd44780::Hd44780::with_bus(bus.device(config.i2c_address()))
+                    .with_geometry(config.geometry()),
+            ),
+        }
+    }
+
+    /// Whether the display has a contrast setting.
+    pub fn has_contrast(&self) -> bool {
+        matches!(self, Display::So1602a(_))
+    }
+
+    /// Set the contrast level. Does nothing on displays without one.
+    /// # Arguments
+    /// * `level` - Contrast level (0x00-0xFF)
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub fn set_contrast(&self, level: u8) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => d.set_contrast(level),
+            Display::Hd44780(_) | Display::Null(_) => Ok(()),
+        }
+    }
+}
+
+/// Set up a display and register the custom characters.
+/// Used at start-up and whenever the display is re-initialized, since
+/// CGRAM does not survive a controller reset.
+/// # Arguments
+/// * `display` - Display.
+/// * `custom_chars` - `(index, bitmap)` pairs.
+/// # Returns
+/// * Result<(), i2c::Error>
+pub async fn init<D: CharDisplay>(
+    display: &D,
+    custom_chars: &[(u8, [u8; 8])],
+) -> Result<(), i2c::Error> {
+    display.setup().await?;
+    for (index, data) in custom_chars {
+        display.register_char(*index, *data)?;
+    }
+    Ok(())
+}
+
+/// Result of a guarded display write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// Written, possibly after retries.
+    Written,
+    /// Written after the display was re-initialized.
+    Reinitialized,
+    /// Still failing, the write was dropped.
+    Failed,
+}
+
+/// Retries failed display writes in the loop, then re-initializes the
+/// display once, so a glitched display does not stop the logging.
+pub struct DisplayRecovery {
+    retries: u32,
+    slots: CgramSlots,
+}
+
+impl DisplayRecovery {
+    /// Create the policy.
+    /// # Arguments
+    /// * `retries` - Retries of a failed write before re-initializing.
+    /// * `slots` - Characters registered again on re-init.
+    pub fn new(retries: u32, slots: CgramSlots) -> Self {
+        Self { retries, slots }
+    }
+
+    /// CGRAM slots of the display.
+    pub fn slots(&self) -> &CgramSlots {
+        &self.slots
+    }
+
+    /// Initialize the display again and register the custom characters.
+    /// # Arguments
+    /// * `display` - Display.
+    /// # Returns
+    /// * Result<(), i2c::Error>
+    pub async fn reinit<D: CharDisplay>(&self, display: &D) -> Result<(), i2c::Error> {
+        init(display, self.slots.chars()).await
+    }
+
+    /// Run a write, retrying it and re-initializing the display on failure.
+    /// The write must redo everything it needs, since a re-init clears the
+    /// screen.
+    /// # Arguments
+    /// * `display` - Display.
+    /// * `what` - Description of the write for the log.
+    /// * `write` - Write to run.
+    /// # Returns
+    /// * What it took to complete the write.
+    pub async fn write<D, F>(&self, display: &D, what: &str, mut write: F) -> WriteOutcome
+    where
+        D: CharDisplay,
+        F: FnMut(&D) -> Result<(), i2c::Error>,
+    {
+        let mut error = match write(display) {
+            Ok(()) => return WriteOutcome::Written,
+            Err(e) => e,
+        };
+        for _ in 0..self.retries {
+            match write(display) {
+                Ok(()) => return WriteOutcome::Written,
+                Err(e) => error = e,
+            }
+        }
+        eprintln!(
+            "Display {} failed ({}), re-initializing the display",
+            what, error
+        );
+        if let Err(e) = self.reinit(display).await {
+            eprintln!("Failed to re-initialize the display: {}", e);
+            return WriteOutcome::Failed;
+        }
+        match write(display) {
+            Ok(()) => WriteOutcome::Reinitialized,
+            Err(e) => {
+                eprintln!("Display {} failed after re-init: {}", what, e);
+                WriteOutcome::Failed
+            }
+        }
+    }
+}
+
+impl CharDisplay for Display {
+    async fn setup(&self) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::setup(d).await,
+            Display::Hd44780(d) => CharDisplay::setup(d).await,
+            Display::Null(d) => CharDisplay::setup(d).await,
+        }
+    }
+
+    fn geometry(&self) -> DisplayGeometry {
+        match self {
+            Display::So1602a(d) => d.geometry(),
+            Display::Hd44780(d) => d.geometry(),
+            Display::Null(d) => d.geometry(),
+        }
+    }
+
+    fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::register_char(d, index, data),
+            Display::Hd44780(d) => CharDisplay::register_char(d, index, data),
+            Display::Null(d) => CharDisplay::register_char(d, index, data),
+        }
+    }
+
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::put_u8(d, position, data),
+            Display::Hd44780(d) => CharDisplay::put_u8(d, position, data),
+            Display::Null(d) => CharDisplay::put_u8(d, position, data),
+        }
+    }
+
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::put_str(d, line_addr, s),
+            Display::Hd44780(d) => CharDisplay::put_str(d, line_addr, s),
+            Display::Null(d) => CharDisplay::put_str(d, line_addr, s),
+        }
+    }
+
+    fn clear_home(&self) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::clear_home(d),
+            Display::Hd44780(d) => CharDisplay::clear_home(d),
+            Display::Null(d) => CharDisplay::clear_home(d),
+        }
+    }
+
+    fn display_off(&self) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::display_off(d),
+            Display::Hd44780(d) => CharDisplay::display_off(d),
+            Display::Null(d) => CharDisplay::display_off(d),
+        }
+    }
+
+    fn display_on(&self) -> Result<(), i2c::Error> {
+        match self {
+            Display::So1602a(d) => CharDisplay::display_on(d),
+            Display::Hd44780(d) => CharDisplay::display_on(d),
+            Display::Null(d) => CharDisplay::display_on(d),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peripheral::display::MockDisplay;
+    use std::cell::Cell;
+
+    /// Mock display whose writes fail a given number of times.
+    #[derive(Default)]
+    struct FlakyDisplay {
+        inner: MockDisplay,
+        failures: Cell<u32>,
+        setup_failures: Cell<u32>,
+        setups: Cell<u32>,
+        registered: Cell<u32>,
+    }
+
+    impl FlakyDisplay {
+        fn failing(failures: u32) -> Self {
+            let display = Self::default();
+            display.failures.set(failures);
+            display
+        }
+
+        fn fail(&self) -> Result<(), i2c::Error> {
+            let failures = self.failures.get();
+            if failures == 0 {
+                return Ok(());
+            }
+            self.failures.set(failures - 1);
+            Err(i2c::Error::Io(std::io::Error::other("NAK")))
+        }
+    }
+
+    impl CharDisplay for FlakyDisplay {
+        async fn setup(&self) -> Result<(), i2c::Error> {
+            self.setups.set(self.setups.get() + 1);
+            let failures = self.setup_failures.get();
+            if failures > 0 {
+                self.setup_failures.set(failures - 1);
+                return Err(i2c::Error::Io(std::io::Error::other("NAK")));
+            }
+            self.inner.setup().await
+        }
+
+        fn geometry(&self) -> DisplayGeometry {
+            self.inner.geometry()
+        }
+
+        fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+            self.registered.set(self.registered.get() + 1);
+            self.inner.register_char(index, data)
+        }
+
+        fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+            self.fail()?;
+            self.inner.put_u8(position, data)
+        }
+
+        fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+            self.fail()?;
+            self.inner.put_str(line_addr, s)
+        }
+
+        fn clear_home(&self) -> Result<(), i2c::Error> {
+            self.inner.clear_home()
+        }
+
+        fn display_off(&self) -> Result<(), i2c::Error> {
+            self.inner.display_off()
+        }
+
+        fn display_on(&self) -> Result<(), i2c::Error> {
+            self.inner.display_on()
+        }
+    }
+
+    fn recovery(retries: u32) -> DisplayRecovery {
+        let slots = CgramSlots::allocate(vec![(1, [0; 8]), (2, [0x1f; 8])]).unwrap();
+        DisplayRecovery::new(retries, slots)
+    }
+
+    fn hello(display: &FlakyDisplay) -> Result<(), i2c::Error> {
+        display.put_str(display.line_address(0), "hello")
+    }
+
+    #[tokio::test]
+    async fn test_write_succeeds_first_time() {
+        let display = FlakyDisplay::failing(0);
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Written);
+        assert_eq!(display.setups.get(), 0);
+        assert!(display.inner.grid()[0].starts_with("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_without_reinit() {
+        let display = FlakyDisplay::failing(1);
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Written);
+        assert_eq!(display.setups.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reinit_registers_custom_chars() {
+        let display = FlakyDisplay::failing(2);
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Reinitialized);
+        assert_eq!(display.setups.get(), 1);
+        // Both custom characters and the indicator glyph
+        assert_eq!(display.registered.get(), 3);
+        assert!(display.inner.grid()[0].starts_with("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_zero_retries_reinit_immediately() {
+        let display = FlakyDisplay::failing(1);
+        let outcome = recovery(0).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Reinitialized);
+        assert_eq!(display.setups.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_reinit() {
+        let display = FlakyDisplay::failing(3);
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Failed);
+        assert_eq!(display.setups.get(), 1);
+        // The next write starts over and goes through
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Written);
+    }
+
+    #[tokio::test]
+    async fn test_failed_reinit() {
+        let display = FlakyDisplay::failing(2);
+        display.setup_failures.set(1);
+        let outcome = recovery(1).write(&display, "update", hello).await;
+        assert_eq!(outcome, WriteOutcome::Failed);
+        assert_eq!(display.registered.get(), 0);
+    }
+}
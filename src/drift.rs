@@ -0,0 +1,451 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Accuracy of this unit against reference units.
+//!
+//! `drift-report` pairs the stored rows of this unit with the readings of
+//! one or more references, and suggests the [sensor] offsets which cancel
+//! the differences. A reference is a unit of [peers], polled over its
+//! `GET /api/current` for the window, or a file written by `export` on
+//! another unit, of which the window up to its latest row is used. Each
+//! reference reading is paired with the stored row closest in time.
+//!
+//! The stored rows include the offsets configured when they were taken, so
+//! the suggestion is the configured offset plus the mean difference. It is
+//! only made from enough pairs which follow the reference
+//! (`MIN_PAIRS`, `MIN_CORRELATION`). Pressure is only compared against
+//! files: peers do not report it, and may sit at another altitude anyway.
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeDelta};
+use peripheral::bme280::Measurement;
+
+use crate::config::SensorConfig;
+use crate::database::{Database, Source};
+use crate::export;
+use crate::helper::drift::{self, Drift};
+use crate::helper::units::NumberLocale;
+use crate::peers::{self, PeerReading};
+use crate::replay::{self, ReplayRow};
+use crate::sensor::EnvSensor;
+
+/// Compared fields, with their offset in [sensor].
+pub const FIELDS: [(&str, &str); 3] = [
+    ("temperature_c", "temperature_offset_c"),
+    ("humidity_relative", "humidity_offset"),
+    ("pressure_pa", "pressure_offset_pa"),
+];
+
+/// Fewest pairs an offset is suggested from.
+pub const MIN_PAIRS: usize = 10;
+
+/// Lowest correlation with the reference an offset is suggested at.
+pub const MIN_CORRELATION: f64 = 0.8;
+
+/// Reading of this unit or of a reference, with the fields it has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub at: DateTime<Local>,
+    /// Values in the order of `FIELDS`.
+    pub values: [Option<f64>; 3],
+}
+
+impl Sample {
+    /// Sample with every field.
+    pub fn from_measurement(at: DateTime<Local>, measurement: &Measurement) -> Self {
+        Self {
+            at,
+            values: [
+                Some(measurement.temperature_c),
+                Some(measurement.humidity_relative),
+                Some(measurement.pressure_pa),
+            ],
+        }
+    }
+
+    /// Sample of a peer, without pressure.
+    pub fn from_peer(at: DateTime<Local>, reading: &PeerReading) -> Self {
+        Self {
+            at,
+            values: [
+                Some(reading.temperature_c),
+                Some(reading.humidity_relative),
+                None,
+            ],
+        }
+    }
+}
+
+/// Pair every reference sample with the unit's sample closest in time.
+/// # Arguments
+/// * `unit` - Samples of this unit, in time order.
+/// * `reference` - Samples of the reference.
+/// * `max_skew` - Largest time between paired samples.
+/// # Returns
+/// * `(unit, reference)` values of each field, in the order of `FIELDS`.
+pub fn pair(unit: &[Sample], reference: &[Sample], max_skew: TimeDelta) -> [Vec<(f64, f64)>; 3] {
+    let mut pairs: [Vec<(f64, f64)>; 3] = Default::default();
+    for sample in reference {
+        let next = unit.partition_point(|own| own.at < sample.at);
+        let closest = unit[next.saturating_sub(1)..unit.len().min(next + 1)]
+            .iter()
+            .min_by_key(|own| (own.at - sample.at).abs());
+        let Some(own) = closest.filter(|own| (own.at - sample.at).abs() <= max_skew) else {
+            continue;
+        };
+        for ((pairs, own), theirs) in pairs.iter_mut().zip(own.values).zip(sample.values) {
+            if let (Some(own), Some(theirs)) = (own, theirs) {
+                pairs.push((own, theirs));
+            }
+        }
+    }
+    pairs
+}
+
+/// Offsets against one reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceDrift {
+    pub name: String,
+    /// Drift of each field, `None` without pairs.
+    pub fields: [Option<Drift>; 3],
+}
+
+/// Offsets against every reference, and over all of them together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Rows of this unit in the windows.
+    pub rows: usize,
+    pub references: Vec<ReferenceDrift>,
+    /// Drift over the pairs of every reference.
+    pub combined: [Option<Drift>; 3],
+}
+
+impl Report {
+    /// Compare this unit with the references.
+    /// # Arguments
+    /// * `unit` - Samples of this unit, in time order.
+    /// * `references` - Name and samples of each reference.
+    /// * `max_skew` - Largest time between paired samples.
+    pub fn new(unit: &[Sample], references: &[(String, Vec<Sample>)], max_skew: TimeDelta) -> Self {
+        let mut all: [Vec<(f64, f64)>; 3] = Default::default();
+        let references = references
+            .iter()
+            .map(|(name, samples)| {
+                let pairs = pair(unit, samples, max_skew);
+                for (all, pairs) in all.iter_mut().zip(&pairs) {
+                    all.extend(pairs);
+                }
+                ReferenceDrift {
+                    name: name.clone(),
+                    fields: pairs.map(|pairs| drift::drift(&pairs)),
+                }
+            })
+            .collect();
+        Self {
+            rows: unit.len(),
+            references,
+            combined: all.map(|pairs| drift::drift(&pairs)),
+        }
+    }
+
+    /// Offsets cancelling the combined drift.
+    /// # Arguments
+    /// * `current` - [sensor] settings the rows were taken with.
+    /// # Returns
+    /// * The settings with the suggested offsets, and whether each field's
+    ///   offset was changed.
+    pub fn suggested(&self, current: &SensorConfig) -> (SensorConfig, [bool; 3]) {
+        let mut sensor = current.clone();
+        let mut changed = [false; 3];
+        for (i, drift) in self.combined.into_iter().enumerate() {
+            if let Some(drift) = drift.filter(is_trusted) {
+                let offset = offset_mut(&mut sensor, i);
+                *offset = round_offset(*offset + drift.offset, i);
+                changed[i] = true;
+            }
+        }
+        (sensor, changed)
+    }
+
+    /// Write the report.
+    /// # Arguments
+    /// * `current` - [sensor] settings the rows were taken with.
+    /// * `out` - Output.
+    pub fn write<W: Write>(&self, current: &SensorConfig, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{} rows of this unit", self.rows)?;
+        for reference in &self.references {
+            writeln!(out, "Against {}:", reference.name)?;
+            for ((field, _), drift) in FIELDS.iter().zip(&reference.fields) {
+                writeln!(out, "  {:<18} {}", field, describe(drift.as_ref()))?;
+            }
+        }
+        if self.references.len() > 1 {
+            writeln!(out, "Against all references:")?;
+            for ((field, _), drift) in FIELDS.iter().zip(&self.combined) {
+                writeln!(out, "  {:<18} {}", field, describe(drift.as_ref()))?;
+            }
+        }
+        let (suggested, changed) = self.suggested(current);
+        writeln!(out, "Suggested [sensor] offsets:")?;
+        for (i, (_, name)) in FIELDS.iter().enumerate() {
+            let now = offset_of(current, i);
+            if changed[i] {
+                let offset = offset_of(&suggested, i);
+                writeln!(out, "  {} = {}  # now {}", name, offset, now)?;
+            } else {
+                writeln!(
+                    out,
+                    "  {} = {}  # kept, too few pairs or no correlation",
+                    name, now
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a drift is measured well enough to correct it.
+fn is_trusted(drift: &Drift) -> bool {
+    drift.pairs - drift.outliers >= MIN_PAIRS
+        && drift.correlation.is_some_and(|r| r >= MIN_CORRELATION)
+}
+
+/// One line of the report.
+fn describe(drift: Option<&Drift>) -> String {
+    match drift {
+        None => "no pairs".to_string(),
+        Some(drift) => format!(
+            "{:+.2} over {} pairs, {} outliers, correlation {}",
+            drift.offset,
+            drift.pairs,
+            drift.outliers,
+            drift
+                .correlation
+                .map_or("-".to_string(), |r| format!("{:.2}", r))
+        ),
+    }
+}
+
+fn offset_of(sensor: &SensorConfig, field: usize) -> f64 {
+    match field {
+        0 => sensor.temperature_offset_c,
+        1 => sensor.humidity_offset,
+        _ => sensor.pressure_offset_pa,
+    }
+}
+
+fn offset_mut(sensor: &mut SensorConfig, field: usize) -> &mut f64 {
+    match field {
+        0 => &mut sensor.temperature_offset_c,
+        1 => &mut sensor.humidity_offset,
+        _ => &mut sensor.pressure_offset_pa,
+    }
+}
+
+/// Round an offset to the resolution worth configuring: 0.01 °C, 0.1 %RH
+/// and 1 Pa.
+fn round_offset(offset: f64, field: usize) -> f64 {
+    let scale = [100.0, 10.0, 1.0][field];
+    (offset * scale).round() / scale
+}
+
+/// Read the samples of a file written by `export`, within the window up to
+/// its latest row.
+/// # Arguments
+/// * `text` - Content of the file.
+/// * `delimiter` - Field delimiter.
+/// * `locale` - Separators of the numbers.
+/// * `window` - Length of the window.
+/// # Returns
+/// * Label of the sensor and its samples, `Err(message)` if the file
+///   cannot be read.
+pub fn file_samples(
+    text: &str,
+    delimiter: char,
+    locale: &NumberLocale,
+    window: TimeDelta,
+) -> Result<(String, Vec<Sample>), String> {
+    let sensor = replay::CsvReplaySensor::parse(text, None, delimiter, locale)?;
+    let label = sensor.label().to_string();
+    let rows: Vec<ReplayRow> = sensor.into_rows();
+    let end = rows
+        .iter()
+        .map(|row| row.at)
+        .max()
+        .unwrap_or_else(Local::now);
+    let samples = rows
+        .iter()
+        .filter(|row| row.at >= end - window)
+        .map(|row| Sample::from_measurement(row.at, &row.measurement))
+        .collect();
+    Ok((label, samples))
+}
+
+/// Poll units for their current readings.
+/// # Arguments
+/// * `urls` - `GET /api/current` URL of each unit.
+/// * `window` - How long to poll.
+/// * `every` - Time between polls.
+/// * `limit` - Time a unit has to answer.
+/// # Returns
+/// * Samples of each unit, failed polls are logged and left out.
+pub async fn poll_peers(
+    urls: &[String],
+    window: Duration,
+    every: Duration,
+    limit: Duration,
+) -> Vec<Vec<Sample>> {
+    let mut samples = vec![Vec::new(); urls.len()];
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(every);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    while started.elapsed() < window {
+        ticker.tick().await;
+        for (url, samples) in urls.iter().zip(&mut samples) {
+            match peers::fetch_current(url, limit).await {
+                Ok(reading) => samples.push(Sample::from_peer(Local::now(), &reading)),
+                Err(e) => eprintln!("Failed to poll {}: {}", url, e),
+            }
+        }
+    }
+    samples
+}
+
+/// Read the stored rows of a sensor taken live in a time range.
+/// # Arguments
+/// * `database` - Database.
+/// * `sensor` - Label of the sensor.
+/// * `from` - Start of the range.
+/// * `to` - End of the range.
+/// # Returns
+/// * Samples in time order.
+pub async fn stored_samples(
+    database: &Database,
+    sensor: &str,
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let mut samples = Vec::new();
+    let mut after_id = 0;
+    loop {
+        let rows = database
+            .export_rows(after_id, Some(from), Some(to), &[Source::Live], 1000)
+            .await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after_id = last.id;
+        samples.extend(
+            rows.iter()
+                .filter(|row| row.sensor == sensor)
+                .filter_map(|row| {
+                    let at = replay::parse_timestamp(&export::normalize_timestamp(&row.timestamp))?;
+                    Some(Sample::from_measurement(at, &row.measurement))
+                }),
+        );
+    }
+    samples.sort_by_key(|sample| sample.at);
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(seconds: i64, temperature_c: f64, humidity_relative: f64) -> Sample {
+        let start = Local.with_ymd_and_hms(2025, 6, 16, 14, 0, 0).unwrap();
+        Sample::from_measurement(
+            start + TimeDelta::seconds(seconds),
+            &Measurement {
+                temperature_c,
+                humidity_relative,
+                pressure_pa: 101325.0,
+            },
+        )
+    }
+
+    #[test]
+    fn test_pair_closest_in_time() {
+        let unit = [
+            sample(0, 20.0, 50.0),
+            sample(10, 21.0, 50.0),
+            sample(20, 22.0, 50.0),
+        ];
+        let start = unit[0].at;
+        let reference = [
+            Sample::from_peer(
+                start + TimeDelta::seconds(12),
+                &PeerReading {
+                    temperature_c: 20.5,
+                    humidity_relative: 45.0,
+                },
+            ),
+            // Too far from any row
+            Sample::from_peer(
+                start + TimeDelta::seconds(90),
+                &PeerReading {
+                    temperature_c: 20.5,
+                    humidity_relative: 45.0,
+                },
+            ),
+        ];
+        let [temperature, humidity, pressure] = pair(&unit, &reference, TimeDelta::seconds(30));
+        assert_eq!(temperature, [(21.0, 20.5)]);
+        assert_eq!(humidity, [(50.0, 45.0)]);
+        // Peers do not report pressure
+        assert!(pressure.is_empty());
+    }
+
+    #[test]
+    fn test_report_suggests_trusted_offsets() {
+        // This unit reads 0.8 °C high and follows the reference, the
+        // humidity of the reference does not move
+        let unit: Vec<Sample> = (0..30)
+            .map(|i| sample(i * 60, 21.0 + i as f64 * 0.1, 50.0 + i as f64 * 0.2))
+            .collect();
+        let reference: Vec<Sample> = (0..30)
+            .map(|i| sample(i * 60 + 5, 20.2 + i as f64 * 0.1, 47.0))
+            .collect();
+        let report = Report::new(
+            &unit,
+            &[("kitchen".to_string(), reference)],
+            TimeDelta::seconds(30),
+        );
+        let current = SensorConfig {
+            temperature_offset_c: -0.5,
+            ..SensorConfig::default()
+        };
+        let (suggested, changed) = report.suggested(&current);
+        assert_eq!(changed, [true, false, false]);
+        assert_eq!(suggested.temperature_offset_c, -1.3);
+        assert_eq!(suggested.humidity_offset, 0.0);
+
+        let mut out = Vec::new();
+        report.write(&current, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("temperature_offset_c = -1.3  # now -0.5"));
+        assert!(text.contains("humidity_offset = 0  # kept"));
+    }
+}
@@ -0,0 +1,191 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Exit codes of the daemon.
+//!
+//! Systemd, provisioning scripts and the start-up watchdog tell failures
+//! apart by the code:
+//!
+//! | Code | Meaning                                                  |
+//! |------|----------------------------------------------------------|
+//! | 0    | Clean shutdown (Ctrl-C, SIGTERM, low UPS battery)        |
+//! | 1    | Any other failure, e.g. of a subcommand                  |
+//! | 2    | The config file is invalid                               |
+//! | 3    | The display failed to initialize                         |
+//! | 4    | The sensor failed to initialize                          |
+//! | 5    | The database failed to initialize, unless it is optional |
+//! | 6    | The I2C bus could not be opened                          |
+//! | 7    | Start-up did not complete within `--startup-timeout`     |
+//! | 8    | A task panicked, restart to recover                      |
+
+use std::error::Error;
+use std::fmt;
+use std::process::ExitCode;
+
+use crate::error::{ConfigError, DisplayError};
+use crate::startup::Subsystem;
+
+pub const FAILURE: u8 = 1;
+pub const CONFIG_INVALID: u8 = 2;
+pub const DISPLAY_INIT_FAILED: u8 = 3;
+pub const SENSOR_INIT_FAILED: u8 = 4;
+pub const DATABASE_INIT_FAILED: u8 = 5;
+pub const BUS_UNRECOVERABLE: u8 = 6;
+pub const STARTUP_TIMEOUT: u8 = 7;
+pub const TASK_PANICKED: u8 = 8;
+
+/// Failure which stops the daemon, classified by exit code.
+#[derive(Debug)]
+pub enum ExitError {
+    /// The config file could not be read or is invalid.
+    Config(ConfigError),
+    /// The display failed to initialize.
+    Display(DisplayError),
+    /// A critical sensor failed to initialize.
+    Sensor(String),
+    /// The database failed to connect or migrate while critical.
+    Database(String),
+    /// The I2C bus could not be opened.
+    Bus(String),
+    /// Start-up did not complete in time, stuck in the named phase.
+    StartupTimeout { secs: u64, phase: &'static str },
+    /// A task panicked, the daemon stopped to be restarted.
+    Panic { task: &'static str, message: String },
+    /// Anything else.
+    Other(Box<dyn Error>),
+}
+
+impl ExitError {
+    /// Classify the failure of a critical start-up subsystem.
+    /// # Arguments
+    /// * `subsystem` - Failed subsystem.
+    /// * `message` - Description of the failure.
+    pub fn subsystem(subsystem: Subsystem, message: String) -> Self {
+        match subsystem {
+            Subsystem::Sensor => ExitError::Sensor(message),
+            Subsystem::Database => ExitError::Database(message),
+            _ => ExitError::Other(message.into()),
+        }
+    }
+
+    /// Exit code of the failure.
+    pub fn code(&self) -> u8 {
+        match self {
+            ExitError::Config(_) => CONFIG_INVALID,
+            ExitError::Display(_) => DISPLAY_INIT_FAILED,
+            ExitError::Sensor(_) => SENSOR_INIT_FAILED,
+            ExitError::Database(_) => DATABASE_INIT_FAILED,
+            ExitError::Bus(_) => BUS_UNRECOVERABLE,
+            ExitError::StartupTimeout { .. } => STARTUP_TIMEOUT,
+            ExitError::Panic { .. } => TASK_PANICKED,
+            ExitError::Other(_) => FAILURE,
+        }
+    }
+
+    /// Exit code of the failure, for returning from `main`.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.code())
+    }
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitError::Config(e) => write!(f, "{}", e),
+            ExitError::Display(e) => write!(f, "{}", e),
+            // Already names the failed step
+            ExitError::Sensor(message) | ExitError::Database(message) => f.write_str(message),
+            ExitError::Other(e) => write!(f, "{}", e),
+            ExitError::Bus(message) => write!(f, "Failed to open the I2C bus: {}", message),
+            ExitError::StartupTimeout { secs, phase } => write!(
+                f,
+                "Start-up did not complete within {}s, stuck in {}",
+                secs, phase
+            ),
+            ExitError::Panic { task, message } => {
+                write!(f, "Task {} panicked, exiting: {}", task, message)
+            }
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for ExitError {
+    fn from(e: Box<dyn Error>) -> Self {
+        ExitError::Other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let message = || "failed".to_string();
+        let config = ExitError::Config(ConfigError::Invalid {
+            path: "wbroker-rs.toml".to_string(),
+            message: "alerts.rules name must not be empty".to_string(),
+        });
+        assert_eq!(config.code(), 2);
+        assert_eq!(
+            config.to_string(),
+            "invalid config wbroker-rs.toml: alerts.rules name must not be empty"
+        );
+        let display = ExitError::Display(DisplayError::Init {
+            address: 0x3c,
+            source: rppal::i2c::Error::Io(std::io::Error::other("no ack")),
+        });
+        assert_eq!(display.code(), 3);
+        assert!(
+            display
+                .to_string()
+                .starts_with("failed to initialize the display at 0x3c: ")
+        );
+        assert_eq!(ExitError::Sensor(message()).code(), 4);
+        assert_eq!(ExitError::Database(message()).code(), 5);
+        assert_eq!(ExitError::Bus(message()).code(), 6);
+        let timeout = ExitError::StartupTimeout {
+            secs: 30,
+            phase: "sensor_init",
+        };
+        assert_eq!(timeout.code(), 7);
+        assert_eq!(
+            timeout.to_string(),
+            "Start-up did not complete within 30s, stuck in sensor_init"
+        );
+        assert_eq!(ExitError::from(Box::<dyn Error>::from("soak")).code(), 1);
+        let panic = ExitError::Panic {
+            task: "http",
+            message: "boom".to_string(),
+        };
+        assert_eq!(panic.code(), 8);
+        assert_eq!(panic.to_string(), "Task http panicked, exiting: boom");
+    }
+
+    #[test]
+    fn test_subsystem_failures() {
+        let code = |subsystem| ExitError::subsystem(subsystem, "failed".to_string()).code();
+        assert_eq!(code(Subsystem::Sensor), SENSOR_INIT_FAILED);
+        assert_eq!(code(Subsystem::Database), DATABASE_INIT_FAILED);
+        assert_eq!(code(Subsystem::Hooks), FAILURE);
+        assert_eq!(code(Subsystem::Http), FAILURE);
+    }
+}
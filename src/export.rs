@@ -0,0 +1,393 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Export stored rows as CSV for spreadsheets.
+//!
+//! Numbers are written with the decimal separator, and optionally the digit
+//! grouping, of the configured locale. The header and the timestamps are
+//! the same in every locale, so the file can always be read back by
+//! scripts, and by `replay`. Fields holding the delimiter are quoted.
+//! Exports of overlapping ranges repeat rows, which the `run_id` and `seq`
+//! columns tell apart, see `sequence`.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::{self, Write};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+
+use crate::database::{Database, ExportRow, Source};
+use crate::helper::units::NumberLocale;
+
+/// Columns of the file, in order.
+pub const HEADER: &[&str] = &[
+    "timestamp",
+    "sensor",
+    "temperature_c",
+    "humidity_relative",
+    "pressure_pa",
+    "thi",
+    "dew_point_c",
+    "quality",
+    "source",
+    "run_id",
+    "seq",
+];
+
+/// Export options.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Only rows at or after this time.
+    pub from: Option<DateTime<Local>>,
+    /// Only rows before this time.
+    pub to: Option<DateTime<Local>>,
+    /// Only rows of these sources, all if empty.
+    pub sources: Vec<Source>,
+    /// Separators of the numbers.
+    pub locale: NumberLocale,
+    /// Separate the integer digits in thousands.
+    pub grouping: bool,
+    /// Field delimiter.
+    pub delimiter: char,
+    /// Rows per read.
+    pub batch_size: usize,
+}
+
+/// Check a field delimiter.
+/// # Arguments
+/// * `delimiter` - Field delimiter.
+/// # Returns
+/// * `Err(message)` for quotes and line breaks.
+pub fn validate_delimiter(delimiter: char) -> Result<(), String> {
+    if matches!(delimiter, '"' | '\r' | '\n') {
+        return Err(format!(
+            "the CSV delimiter must not be a quote or line break, got {:?}",
+            delimiter
+        ));
+    }
+    Ok(())
+}
+
+/// Write the rows as CSV.
+/// # Arguments
+/// * `database` - Database to read.
+/// * `options` - Export options.
+/// * `out` - Destination of the file.
+/// # Returns
+/// * Number of rows written.
+pub async fn run<W: Write>(
+    database: &Database,
+    options: &ExportOptions,
+    out: &mut W,
+) -> Result<u64, Box<dyn Error>> {
+    validate_delimiter(options.delimiter)?;
+    let header: Vec<_> = HEADER.iter().map(|column| column.to_string()).collect();
+    write_record(out, &header, options.delimiter)?;
+    let mut after_id = 0;
+    let mut written = 0;
+    loop {
+        let rows = database
+            .export_rows(
+                after_id,
+                options.from,
+                options.to,
+                &options.sources,
+                options.batch_size.max(1),
+            )
+            .await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after_id = last.id;
+        for row in &rows {
+            write_record(out, &fields(row, options), options.delimiter)?;
+        }
+        written += rows.len() as u64;
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Fields of a row, in the order of `HEADER`.
+fn fields(row: &ExportRow, options: &ExportOptions) -> Vec<String> {
+    let number = |value: f64| options.locale.format(value, options.grouping);
+    vec![
+        normalize_timestamp(&row.timestamp),
+        row.sensor.clone(),
+        number(row.measurement.temperature_c),
+        number(row.measurement.humidity_relative),
+        number(row.measurement.pressure_pa),
+        number(row.thi),
+        number(row.dew_point_c),
+        row.quality.clone(),
+        row.source.clone(),
+        row.run_id.clone().unwrap_or_default(),
+        row.seq.map(|seq| seq.to_string()).unwrap_or_default(),
+    ]
+}
+
+/// Write the timestamp the same way for every database: RFC 3339, or
+/// without offset where the database does not keep one (MySQL).
+/// # Arguments
+/// * `text` - Timestamp as the database returns it as text.
+/// # Returns
+/// * The timestamp, or `text` as is if it cannot be parsed.
+pub fn normalize_timestamp(text: &str) -> String {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return time.to_rfc3339();
+    }
+    // PostgreSQL, e.g. 2025-06-16 14:30:00.5+09
+    if let Ok(time) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f%#z") {
+        return time.to_rfc3339();
+    }
+    match NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f") {
+        Ok(time) => time.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+        Err(_) => text.to_string(),
+    }
+}
+
+/// Write one line, quoting fields as RFC 4180 does.
+fn write_record<W: Write>(out: &mut W, fields: &[String], delimiter: char) -> io::Result<()> {
+    let line: Vec<_> = fields.iter().map(|field| quote(field, delimiter)).collect();
+    writeln!(out, "{}", line.join(&delimiter.to_string()))
+}
+
+/// Quote a field holding the delimiter, a quote or a line break.
+fn quote(field: &str, delimiter: char) -> Cow<'_, str> {
+    if field.contains([delimiter, '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Split a file into records, reading the quoting of `write_record`.
+/// # Arguments
+/// * `text` - Content of the file.
+/// * `delimiter` - Field delimiter.
+/// # Returns
+/// * Fields of each non-empty line.
+pub fn parse_records(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted || field.is_empty() => quoted = !quoted,
+            '\r' | '\n' if !quoted => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                if !record.is_empty() || !field.is_empty() {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+            }
+            c if c == delimiter && !quoted => record.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if !record.is_empty() || !field.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SensorData;
+    use peripheral::bme280::Measurement;
+
+    fn options(locale: &str, delimiter: char) -> ExportOptions {
+        ExportOptions {
+            from: None,
+            to: None,
+            sources: Vec::new(),
+            locale: NumberLocale::parse(locale).unwrap(),
+            grouping: false,
+            delimiter,
+            batch_size: 2,
+        }
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            temperature_c: 21.5,
+            humidity_relative: 48.25,
+            pressure_pa: 101325.5,
+        }
+    }
+
+    #[test]
+    fn test_fields_use_locale() {
+        let row = ExportRow {
+            id: 1,
+            timestamp: "2025-06-16T14:30:00+09:00".to_string(),
+            sensor: "bme280".to_string(),
+            measurement: measurement(),
+            thi: 68.5,
+            dew_point_c: 9.75,
+            quality: "good".to_string(),
+            source: "live".to_string(),
+            run_id: Some("20250616T143000-1234".to_string()),
+            seq: Some(7),
+        };
+        let mut german = options("de", ';');
+        assert_eq!(
+            fields(&row, &german),
+            vec![
+                "2025-06-16T14:30:00+09:00",
+                "bme280",
+                "21,5",
+                "48,25",
+                "101325,5",
+                "68,5",
+                "9,75",
+                "good",
+                "live",
+                "20250616T143000-1234",
+                "7"
+            ]
+        );
+        german.grouping = true;
+        assert_eq!(fields(&row, &german)[4], "101.325,5");
+        assert_eq!(fields(&row, &options("C", ','))[2], "21.5");
+        // Rows of older versions are not numbered
+        let older = ExportRow {
+            run_id: None,
+            seq: None,
+            ..row
+        };
+        assert_eq!(fields(&older, &german)[9..], ["", ""]);
+    }
+
+    #[test]
+    fn test_write_record_quotes() {
+        let mut out = Vec::new();
+        let fields = ["21,5".to_string(), "say \"hi\"".to_string()];
+        write_record(&mut out, &fields, ',').unwrap();
+        write_record(&mut out, &fields, ';').unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"21,5\",\"say \"\"hi\"\"\"\n21,5;\"say \"\"hi\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_records_reads_quotes() {
+        let mut out = Vec::new();
+        let fields = [
+            "21,5".to_string(),
+            "say \"hi\"".to_string(),
+            "two\nlines".to_string(),
+        ];
+        write_record(&mut out, &fields, ';').unwrap();
+        write_record(&mut out, &fields, ';').unwrap();
+        let text = String::from_utf8(out).unwrap() + "\r\n";
+        assert_eq!(parse_records(&text, ';'), vec![fields.clone(), fields]);
+        assert_eq!(parse_records("a;;b", ';'), vec![vec!["a", "", "b"]]);
+    }
+
+    #[test]
+    fn test_normalize_timestamp() {
+        assert_eq!(
+            normalize_timestamp("2025-06-16T14:30:00.5+09:00"),
+            "2025-06-16T14:30:00.500+09:00"
+        );
+        assert_eq!(
+            normalize_timestamp("2025-06-16 14:30:00.5+09"),
+            "2025-06-16T14:30:00.500+09:00"
+        );
+        assert_eq!(
+            normalize_timestamp("2025-06-16 14:30:00.500000"),
+            "2025-06-16T14:30:00.500"
+        );
+        assert_eq!(normalize_timestamp("yesterday"), "yesterday");
+    }
+
+    #[test]
+    fn test_validate_delimiter() {
+        assert!(validate_delimiter(';').is_ok());
+        assert!(validate_delimiter('\t').is_ok());
+        assert!(validate_delimiter('"').is_err());
+        assert!(validate_delimiter('\n').is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_export_csv() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let start = Local::now();
+        for i in 0..4 {
+            let at = start + chrono::Duration::seconds(i);
+            let mut row = SensorData::from_measurement_at(measurement(), 68.5, at);
+            if i == 1 {
+                row.source = Source::Replay;
+            }
+            database.save_async(row).unwrap();
+        }
+        database.flush().await.unwrap();
+
+        let mut out = Vec::new();
+        let live = ExportOptions {
+            to: Some(start + chrono::Duration::seconds(3)),
+            sources: vec![Source::Live],
+            ..options("de", ';')
+        };
+        assert_eq!(run(&database, &live, &mut out).await.unwrap(), 2);
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "timestamp;sensor;temperature_c;humidity_relative;pressure_pa;thi;dew_point_c;quality;source;run_id;seq"
+        );
+        assert_eq!(lines.len(), 3);
+        let run_id = crate::sequence::this_run().run_id();
+        let dew_point = live.locale.format(measurement().dew_point_c(), false);
+        assert!(lines[1].contains(&format!(
+            ";bme280;21,5;48,25;101325,5;68,5;{};good;live;{};",
+            dew_point, run_id
+        )));
+        let seqs: Vec<u64> = lines[1..]
+            .iter()
+            .map(|line| line.rsplit(';').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(seqs[0] < seqs[1]);
+
+        // Every source by default
+        let mut out = Vec::new();
+        assert_eq!(
+            run(&database, &options("C", ','), &mut out).await.unwrap(),
+            4
+        );
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv.lines().nth(2).unwrap().contains(",good,replay,"));
+        database.close().await;
+    }
+}
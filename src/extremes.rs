@@ -0,0 +1,352 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Extremes since last viewed.
+//!
+//! The extremes page holds the highest temperature and the lowest humidity
+//! since they were last looked at. Unlike a daily min/max they only start
+//! over when viewing is confirmed, with a double press of the wake button or
+//! `POST /api/extremes/reset`. They are kept in the state file so a restart
+//! does not lose them, written at most once per `SAVE_INTERVAL` while they
+//! change to spare the SD card.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::Measurement;
+use serde_json::Value;
+
+use crate::config::ExtremesConfig;
+use crate::state;
+
+/// Shortest time between two writes of changed extremes.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Extremes since the last reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extremes {
+    /// Highest temperature, `None` until a reading.
+    pub max_temperature_c: Option<f64>,
+    /// Lowest humidity, `None` until a reading.
+    pub min_humidity_relative: Option<f64>,
+    /// Time of the last reset.
+    pub since: DateTime<Local>,
+}
+
+impl Extremes {
+    /// Empty extremes.
+    /// # Arguments
+    /// * `since` - Time of the reset.
+    pub fn new(since: DateTime<Local>) -> Self {
+        Self {
+            max_temperature_c: None,
+            min_humidity_relative: None,
+            since,
+        }
+    }
+
+    /// Take a measurement into account. Values which are not a number are
+    /// skipped.
+    /// # Arguments
+    /// * `measurement` - Measurement with offsets applied.
+    /// # Returns
+    /// * Whether an extreme changed.
+    pub fn update(&mut self, measurement: &Measurement) -> bool {
+        let max = extend(
+            &mut self.max_temperature_c,
+            measurement.temperature_c,
+            f64::max,
+        );
+        let min = extend(
+            &mut self.min_humidity_relative,
+            measurement.humidity_relative,
+            f64::min,
+        );
+        max || min
+    }
+}
+
+/// Fold a value into an extreme.
+/// # Returns
+/// * Whether the extreme changed.
+fn extend(extreme: &mut Option<f64>, value: f64, pick: fn(f64, f64) -> f64) -> bool {
+    if value.is_nan() {
+        return false;
+    }
+    let next = extreme.map_or(value, |current| pick(current, value));
+    let changed = *extreme != Some(next);
+    *extreme = Some(next);
+    changed
+}
+
+/// Extremes as shown on the extremes page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtremesStatus {
+    pub max_temperature_c: Option<f64>,
+    pub min_humidity_relative: Option<f64>,
+    /// Time since the last reset.
+    pub age: Duration,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    extremes: Extremes,
+    /// Changed since they were last written.
+    dirty: bool,
+    last_saved: Option<Instant>,
+}
+
+/// Extremes shared by the measurement loop and the HTTP API.
+#[derive(Debug)]
+pub struct ExtremesStore {
+    tracked: RwLock<Tracked>,
+    state_file: PathBuf,
+}
+
+impl ExtremesStore {
+    /// Load the extremes from the state file, or start empty. An unreadable
+    /// state file is logged and ignored.
+    /// # Arguments
+    /// * `config` - Extremes configuration.
+    /// * `now` - Current time, the reset time when starting empty.
+    pub fn load(config: &ExtremesConfig, now: DateTime<Local>) -> Self {
+        let state_file = PathBuf::from(&config.state_file);
+        let saved = read_state(&state_file).unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to read the extremes from {}: {}",
+                state_file.display(),
+                e
+            );
+            None
+        });
+        Self {
+            tracked: RwLock::new(Tracked {
+                extremes: saved.unwrap_or_else(|| Extremes::new(now)),
+                dirty: false,
+                last_saved: None,
+            }),
+            state_file,
+        }
+    }
+
+    /// Take a measurement into account, and write the extremes if they
+    /// changed and were not written within `SAVE_INTERVAL`.
+    /// # Arguments
+    /// * `measurement` - Measurement with offsets applied.
+    /// * `now` - Current time.
+    pub fn update(&self, measurement: &Measurement, now: Instant) {
+        let mut tracked = self.tracked.write().unwrap_or_else(|e| e.into_inner());
+        tracked.dirty |= tracked.extremes.update(measurement);
+        let due = tracked
+            .last_saved
+            .is_none_or(|saved| now.saturating_duration_since(saved) >= SAVE_INTERVAL);
+        if tracked.dirty && due {
+            if let Err(e) = write_state(&self.state_file, &tracked.extremes) {
+                eprintln!("Failed to save the extremes: {}", e);
+            }
+            // Retried after the interval rather than on every reading
+            tracked.dirty = false;
+            tracked.last_saved = Some(now);
+        }
+    }
+
+    /// Start the extremes over and write them at once.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The emptied extremes.
+    /// * `Err(e)` if the state file could not be written, they are reset
+    ///   anyway and a restart brings the old ones back.
+    pub fn reset(&self, now: DateTime<Local>) -> Result<Extremes, Box<dyn Error>> {
+        let mut tracked = self.tracked.write().unwrap_or_else(|e| e.into_inner());
+        tracked.extremes = Extremes::new(now);
+        tracked.dirty = false;
+        write_state(&self.state_file, &tracked.extremes)?;
+        Ok(tracked.extremes)
+    }
+
+    /// Write the extremes if they changed since they were last written,
+    /// e.g. at shutdown.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let mut tracked = self.tracked.write().unwrap_or_else(|e| e.into_inner());
+        if tracked.dirty {
+            write_state(&self.state_file, &tracked.extremes)?;
+            tracked.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Current extremes.
+    pub fn extremes(&self) -> Extremes {
+        self.tracked
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .extremes
+    }
+
+    /// Current extremes with the time since the last reset.
+    /// # Arguments
+    /// * `now` - Current time.
+    pub fn status(&self, now: DateTime<Local>) -> ExtremesStatus {
+        let extremes = self.extremes();
+        ExtremesStatus {
+            max_temperature_c: extremes.max_temperature_c,
+            min_humidity_relative: extremes.min_humidity_relative,
+            // A clock stepped back makes it look new rather than negative
+            age: (now - extremes.since).to_std().unwrap_or_default(),
+        }
+    }
+}
+
+/// Read the extremes of the state file.
+/// # Returns
+/// * `Ok(None)` if there is no state file or it holds no extremes.
+fn read_state(path: &Path) -> Result<Option<Extremes>, Box<dyn Error>> {
+    let Some(state) = state::read(path)? else {
+        return Ok(None);
+    };
+    let Some(since) = state.get("extremes_since").and_then(Value::as_str) else {
+        return Ok(None);
+    };
+    Ok(Some(Extremes {
+        max_temperature_c: state
+            .get("extremes_max_temperature_c")
+            .and_then(Value::as_f64),
+        min_humidity_relative: state
+            .get("extremes_min_humidity_relative")
+            .and_then(Value::as_f64),
+        since: DateTime::parse_from_rfc3339(since)?.with_timezone(&Local),
+    }))
+}
+
+/// Write the extremes to the state file.
+fn write_state(path: &Path, extremes: &Extremes) -> Result<(), Box<dyn Error>> {
+    state::write(
+        path,
+        [
+            (
+                "extremes_max_temperature_c",
+                extremes.max_temperature_c.into(),
+            ),
+            (
+                "extremes_min_humidity_relative",
+                extremes.min_humidity_relative.into(),
+            ),
+            ("extremes_since", extremes.since.to_rfc3339().into()),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+
+    fn config(name: &str) -> ExtremesConfig {
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        ExtremesConfig {
+            enabled: true,
+            state_file: path.display().to_string(),
+        }
+    }
+
+    fn reading(temperature_c: f64, humidity_relative: f64) -> Measurement {
+        Measurement {
+            temperature_c,
+            humidity_relative,
+            pressure_pa: 101_325.0,
+        }
+    }
+
+    #[test]
+    fn test_extremes_hold_until_reset() {
+        let config = config("extremes-reset");
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 8, 0, 0).unwrap();
+        let store = ExtremesStore::load(&config, start);
+        let now = Instant::now();
+        assert_eq!(store.extremes(), Extremes::new(start));
+
+        store.update(&reading(24.0, 55.0), now);
+        store.update(&reading(26.4, 48.0), now);
+        store.update(&reading(25.0, f64::NAN), now);
+        store.update(&reading(22.1, 52.0), now);
+        let extremes = store.extremes();
+        assert_eq!(extremes.max_temperature_c, Some(26.4));
+        assert_eq!(extremes.min_humidity_relative, Some(48.0));
+        let status = store.status(start + chrono::Duration::hours(3));
+        assert_eq!(status.age, Duration::from_secs(3 * 3600));
+
+        let viewed = start + chrono::Duration::hours(3);
+        assert_eq!(store.reset(viewed).unwrap(), Extremes::new(viewed));
+        // The next reading starts them over
+        store.update(&reading(23.0, 60.0), now);
+        let _ = fs::remove_file(&config.state_file);
+        let extremes = store.extremes();
+        assert_eq!(extremes.max_temperature_c, Some(23.0));
+        assert_eq!(extremes.min_humidity_relative, Some(60.0));
+        assert_eq!(store.status(viewed).age, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_extremes_persist_across_restart() {
+        let config = config("extremes-restart");
+        let start = Local.with_ymd_and_hms(2025, 6, 1, 8, 0, 0).unwrap();
+        let now = Instant::now();
+        let store = ExtremesStore::load(&config, start);
+        // Written at once, then at most once per interval
+        store.update(&reading(24.0, 55.0), now);
+        store.update(&reading(26.4, 48.0), now + Duration::from_secs(1));
+        let written = ExtremesStore::load(&config, start).extremes();
+        assert_eq!(written.max_temperature_c, Some(24.0));
+        store.save().unwrap();
+
+        let restarted = ExtremesStore::load(&config, start + chrono::Duration::hours(5));
+        let extremes = restarted.extremes();
+        assert_eq!(extremes.max_temperature_c, Some(26.4));
+        assert_eq!(extremes.min_humidity_relative, Some(48.0));
+        assert_eq!(extremes.since, start);
+
+        // A reset survives a restart as well
+        let viewed = start + chrono::Duration::hours(6);
+        restarted.reset(viewed).unwrap();
+        let extremes = ExtremesStore::load(&config, viewed).extremes();
+        fs::remove_file(&config.state_file).unwrap();
+        assert_eq!(extremes, Extremes::new(viewed));
+    }
+
+    #[test]
+    fn test_invalid_state_file_starts_empty() {
+        let config = config("extremes-invalid");
+        fs::write(&config.state_file, "not json").unwrap();
+        let now = Local::now();
+
+        let store = ExtremesStore::load(&config, now);
+
+        fs::remove_file(&config.state_file).unwrap();
+        assert_eq!(store.extremes(), Extremes::new(now));
+    }
+}
@@ -0,0 +1,215 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wall-clock boundaries of a fixed interval.
+//!
+//! A boundary is an instant whose local time is a whole multiple of the
+//! interval counted from the epoch, e.g. every :00 and :30 second of a 30
+//! second interval, or local midnight for a day. Across a DST transition the
+//! boundaries follow the local clock: the interval spanning the jump is
+//! shorter or longer, the first boundary after a skipped hour falls on the
+//! jump, and the boundaries of a repeated hour come twice, at distinct
+//! instants.
+
+use chrono::{DateTime, Offset, TimeDelta, TimeZone};
+
+/// First boundary strictly after a time.
+/// Assumes at most one offset change within an interval.
+/// # Arguments
+/// * `at` - Time, in the zone of the boundaries.
+/// * `interval` - Interval, whole seconds of at least 1.
+/// # Returns
+/// * `None` past the range of the calendar.
+pub fn next_boundary<Tz: TimeZone>(at: &DateTime<Tz>, interval: TimeDelta) -> Option<DateTime<Tz>> {
+    let step = interval.num_seconds().max(1);
+    let zone = at.timezone();
+    let instant = |utc: i64| zone.timestamp_opt(utc, 0).single();
+    let offset = |time: &DateTime<Tz>| i64::from(time.offset().fix().local_minus_utc());
+
+    // Next multiple on the local clock of `at`
+    let before = offset(at);
+    let wall = at.timestamp() + before;
+    let candidate = instant((wall.div_euclid(step) + 1) * step - before)?;
+    if offset(&candidate) == before {
+        return Some(candidate);
+    }
+    // The offset changes on the way: find the second it does, and the first
+    // multiple on the new local clock from there
+    let (mut lo, mut hi) = (at.timestamp(), candidate.timestamp());
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if offset(&instant(mid)?) == before {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let after = offset(&instant(hi)?);
+    let wall = hi + after;
+    instant((wall + step - 1).div_euclid(step) * step - after)
+}
+
+/// Boundaries reached by a series of readings, each reported once.
+#[derive(Debug, Clone)]
+pub struct BoundaryClock<Tz: TimeZone> {
+    interval: TimeDelta,
+    /// Boundary the readings are heading for, unset before the first one.
+    pending: Option<DateTime<Tz>>,
+}
+
+impl<Tz: TimeZone> BoundaryClock<Tz> {
+    /// Create a clock without readings.
+    /// # Arguments
+    /// * `interval` - Interval of the boundaries, whole seconds.
+    pub fn new(interval: TimeDelta) -> Self {
+        Self {
+            interval,
+            pending: None,
+        }
+    }
+
+    /// Advance to a reading.
+    /// Further boundaries skipped by a gap or a clock step forward have no
+    /// reading and are not reported. After a step back the readings head
+    /// for the same boundary again, so none is reported twice.
+    /// # Arguments
+    /// * `at` - Time of the reading.
+    /// # Returns
+    /// * The boundary the reading reached or passed, `None` if none.
+    pub fn pass(&mut self, at: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match &self.pending {
+            Some(pending) if at < pending => None,
+            Some(_) => {
+                let passed = self.pending.take();
+                self.pending = next_boundary(at, self.interval);
+                passed
+            }
+            None => {
+                self.pending = next_boundary(at, self.interval);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use chrono_tz::Europe::Berlin;
+
+    fn berlin(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<chrono_tz::Tz> {
+        Berlin
+            .with_ymd_and_hms(y, mo, d, h, mi, s)
+            .earliest()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_boundary() {
+        let interval = TimeDelta::seconds(30);
+        let at = berlin(2025, 6, 16, 14, 30, 12);
+        assert_eq!(
+            next_boundary(&at, interval),
+            Some(berlin(2025, 6, 16, 14, 30, 30))
+        );
+        // Strictly after a boundary
+        let at = berlin(2025, 6, 16, 14, 30, 30);
+        assert_eq!(
+            next_boundary(&at, interval),
+            Some(berlin(2025, 6, 16, 14, 31, 0))
+        );
+        let at = at + TimeDelta::milliseconds(1);
+        assert_eq!(
+            next_boundary(&at, interval),
+            Some(berlin(2025, 6, 16, 14, 31, 0))
+        );
+        // A day ends at local midnight, not at UTC midnight
+        let at = berlin(2025, 6, 16, 14, 30, 0);
+        assert_eq!(
+            next_boundary(&at, TimeDelta::days(1)),
+            Some(berlin(2025, 6, 17, 0, 0, 0))
+        );
+    }
+
+    /// Boundaries reported for readings every 10 s over a night.
+    fn boundaries_over(start: DateTime<chrono_tz::Tz>, hours: i64) -> Vec<DateTime<chrono_tz::Tz>> {
+        let mut clock = BoundaryClock::new(TimeDelta::minutes(30));
+        (0..hours * 360)
+            .filter_map(|i| clock.pass(&(start + TimeDelta::seconds(i * 10 + 3))))
+            .collect()
+    }
+
+    #[test]
+    fn test_boundaries_across_dst() {
+        // Spring forward: 02:00 CET is 03:00 CEST, 5 real hours
+        let boundaries = boundaries_over(berlin(2025, 3, 30, 0, 0, 0), 5);
+        let local: Vec<_> = boundaries
+            .iter()
+            .map(|b| b.format("%H:%M").to_string())
+            .collect();
+        assert_eq!(
+            local,
+            [
+                "00:30",
+                "01:00",
+                "01:30",
+                "03:00",
+                "03:30",
+                "04:00",
+                "04:30",
+                "05:00",
+                "05:30"
+            ]
+        );
+        // Fall back: 03:00 CEST is 02:00 CET, the hour comes twice
+        let boundaries = boundaries_over(berlin(2025, 10, 26, 0, 0, 0), 5);
+        assert_eq!(boundaries.len(), 9);
+        let local: Vec<_> = boundaries
+            .iter()
+            .map(|b| b.format("%H:%M %Z").to_string())
+            .collect();
+        assert_eq!(
+            local[3..7],
+            ["02:00 CEST", "02:30 CEST", "02:00 CET", "02:30 CET"]
+        );
+        // Every real 30 minutes, none missed or doubled
+        for pair in boundaries.windows(2) {
+            assert_eq!(pair[1] - pair[0], TimeDelta::minutes(30));
+        }
+    }
+
+    #[test]
+    fn test_clock_steps() {
+        let mut clock = BoundaryClock::new(TimeDelta::seconds(30));
+        let at = |s: i64| Utc.timestamp_opt(1_750_000_020 + s, 0).unwrap();
+        assert_eq!(clock.pass(&at(5)), None);
+        assert_eq!(clock.pass(&at(15)), None);
+        assert_eq!(clock.pass(&at(31)), Some(at(30)));
+        // Stepped back, 0:30 is not reported again
+        assert_eq!(clock.pass(&at(12)), None);
+        assert_eq!(clock.pass(&at(35)), None);
+        assert_eq!(clock.pass(&at(61)), Some(at(60)));
+        // Stepped forward over an hour, the reading closes the pending boundary
+        assert_eq!(clock.pass(&at(3700)), Some(at(90)));
+        assert_eq!(clock.pass(&at(3721)), Some(at(3720)));
+    }
+}
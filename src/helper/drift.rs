@@ -0,0 +1,208 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Offset of a sensor against a reference.
+//!
+//! Readings of this unit and of a reference taken at about the same time
+//! are paired, and the differences reference minus unit averaged. The
+//! robust mean leaves out differences far from their median, e.g. from a
+//! window opened next to one of the units: further than `OUTLIER_MADS`
+//! median absolute deviations (MAD), scaled to a standard deviation. The
+//! correlation tells whether the two follow the same changes at all; an
+//! offset is only a calibration error if they do.
+
+/// Differences further from the median than this many scaled MADs are
+/// left out of the mean.
+pub const OUTLIER_MADS: f64 = 3.0;
+
+/// Scale of the MAD to the standard deviation of normal data.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Offset of one field against a reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drift {
+    /// Number of paired readings.
+    pub pairs: usize,
+    /// Robust mean of reference minus unit, the correction to add.
+    pub offset: f64,
+    /// Pairs left out of the mean.
+    pub outliers: usize,
+    /// Pearson correlation of the unit and the reference, `None` without
+    /// variation.
+    pub correlation: Option<f64>,
+}
+
+/// Median of some values.
+/// # Arguments
+/// * `values` - Values, not NaN.
+/// # Returns
+/// * `None` without values.
+pub fn median(values: &[f64]) -> Option<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        n if n.is_multiple_of(2) => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+        _ => Some(sorted[middle]),
+    }
+}
+
+/// Differences of paired readings.
+/// # Arguments
+/// * `pairs` - `(unit, reference)` readings.
+/// # Returns
+/// * Reference minus unit of every pair.
+pub fn paired_differences(pairs: &[(f64, f64)]) -> Vec<f64> {
+    pairs
+        .iter()
+        .map(|(unit, reference)| reference - unit)
+        .collect()
+}
+
+/// Mean without the outliers.
+/// When more than half the values are equal the MAD is 0, and the mean is
+/// that value.
+/// # Arguments
+/// * `values` - Values, not NaN.
+/// # Returns
+/// * The mean and the number of values left out, `None` without values.
+pub fn robust_mean(values: &[f64]) -> Option<(f64, usize)> {
+    let kept: Vec<f64> = values
+        .iter()
+        .zip(inliers(values)?)
+        .filter_map(|(value, kept)| kept.then_some(*value))
+        .collect();
+    // The median itself is always within the limit
+    let mean = kept.iter().sum::<f64>() / kept.len() as f64;
+    Some((mean, values.len() - kept.len()))
+}
+
+/// Which values are within `OUTLIER_MADS` scaled MADs of their median.
+/// # Arguments
+/// * `values` - Values, not NaN.
+/// # Returns
+/// * Whether each value is kept, `None` without values.
+fn inliers(values: &[f64]) -> Option<Vec<bool>> {
+    let center = median(values)?;
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    let limit = OUTLIER_MADS * MAD_SCALE * median(&deviations)?;
+    Some(deviations.iter().map(|d| *d <= limit).collect())
+}
+
+/// Pearson correlation of paired readings.
+/// # Arguments
+/// * `pairs` - `(unit, reference)` readings.
+/// # Returns
+/// * The correlation, `None` with fewer than 2 pairs or if one side is
+///   constant.
+pub fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        sxy += (x - mean_x) * (y - mean_y);
+        sxx += (x - mean_x).powi(2);
+        syy += (y - mean_y).powi(2);
+    }
+    (sxx > 0.0 && syy > 0.0).then(|| sxy / (sxx * syy).sqrt())
+}
+
+/// Offset of one field from its paired readings.
+/// The outliers are left out of the correlation too, a single disturbed
+/// pair would otherwise hide that the two follow each other.
+/// # Arguments
+/// * `pairs` - `(unit, reference)` readings.
+/// # Returns
+/// * `None` without pairs.
+pub fn drift(pairs: &[(f64, f64)]) -> Option<Drift> {
+    let differences = paired_differences(pairs);
+    let (offset, outliers) = robust_mean(&differences)?;
+    let kept: Vec<(f64, f64)> = pairs
+        .iter()
+        .zip(inliers(&differences)?)
+        .filter_map(|(pair, kept)| kept.then_some(*pair))
+        .collect();
+    Some(Drift {
+        pairs: pairs.len(),
+        offset,
+        outliers,
+        correlation: correlation(&kept),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn test_robust_mean_ignores_outliers() {
+        let values = [0.9, 1.0, 1.1, 1.0, 0.95, 1.05, 8.0];
+        let (mean, outliers) = robust_mean(&values).unwrap();
+        assert!((mean - 1.0).abs() < 1e-9);
+        assert_eq!(outliers, 1);
+        // Mostly equal values
+        assert_eq!(robust_mean(&[0.5, 0.5, 0.5, 0.7]), Some((0.5, 1)));
+        assert_eq!(robust_mean(&[]), None);
+    }
+
+    #[test]
+    fn test_correlation() {
+        let pairs = [(20.0, 21.0), (21.0, 22.1), (22.0, 22.9), (23.0, 24.0)];
+        assert!(correlation(&pairs).unwrap() > 0.99);
+        let opposite = [(20.0, 24.0), (21.0, 23.0), (22.0, 22.0)];
+        assert!((correlation(&opposite).unwrap() + 1.0).abs() < 1e-9);
+        assert_eq!(correlation(&[(20.0, 21.0), (20.0, 22.0)]), None);
+        assert_eq!(correlation(&[(20.0, 21.0)]), None);
+    }
+
+    #[test]
+    fn test_drift_of_offset_sensor() {
+        // The unit reads 1.5 higher, one pair was disturbed
+        let mut pairs: Vec<(f64, f64)> = (0..20)
+            .map(|i| {
+                let reference = 20.0 + f64::from(i) * 0.1;
+                (
+                    reference + 1.5 + if i % 2 == 0 { 0.02 } else { -0.02 },
+                    reference,
+                )
+            })
+            .collect();
+        pairs[7].0 += 5.0;
+        let drift = drift(&pairs).unwrap();
+        assert_eq!(drift.pairs, 20);
+        assert_eq!(drift.outliers, 1);
+        assert!((drift.offset + 1.5).abs() < 0.01);
+        assert!(drift.correlation.unwrap() > 0.9);
+        assert_eq!(paired_differences(&[(21.5, 20.0)]), [-1.5]);
+    }
+}
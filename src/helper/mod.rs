@@ -0,0 +1,786 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Small helpers shared by the main loop.
+
+pub mod boundary;
+pub mod drift;
+pub mod metrics;
+pub mod rolling;
+pub mod scroll;
+pub mod units;
+
+use chrono::{DateTime, Datelike, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// Dew point in °C from the Magnus formula (b = 17.62, c = 243.12), NaN
+/// without humidity.
+pub use peripheral::bme280::dew_point_c;
+
+/// Text shown on the clock line while the system time is not set.
+pub const TIME_NOT_SET: &str = "TIME NOT SET";
+
+/// Number of characters per line of the 16x2 displays.
+pub const DISPLAY_COLUMNS: usize = 16;
+
+/// Columns of the measurement line of the 16x2 displays, the last one holds
+/// the activity indicator.
+pub const MEASUREMENT_COLUMNS: usize = DISPLAY_COLUMNS - 1;
+
+/// Largest number of decimals shown for temperature and humidity.
+pub const MAX_DECIMALS: u8 = 2;
+
+/// Widest range of the displayed THI that fits its 3 columns.
+pub const THI_DISPLAY_LIMITS: (i16, i16) = (-99, 999);
+
+/// Coefficients of a temperature-humidity index of the form
+/// `a*T + b*H*(c*T - d) + e`, with T in °C and H in %.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ThiCoefficients {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+}
+
+impl ThiCoefficients {
+    /// The common discomfort index, `0.81*T + 0.01*H*(0.99*T - 14.3) + 46.3`.
+    pub const STANDARD: ThiCoefficients = ThiCoefficients {
+        a: 0.81,
+        b: 0.01,
+        c: 0.99,
+        d: 14.3,
+        e: 46.3,
+    };
+}
+
+impl Default for ThiCoefficients {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// How displayed and stored values are rounded to their last digit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Ties round away from zero (24.25 -> 24.3).
+    #[default]
+    HalfUp,
+    /// Ties round to the even digit, banker's rounding (24.25 -> 24.2).
+    HalfEven,
+}
+
+impl RoundingMode {
+    /// Round a value to a number of decimals.
+    /// # Arguments
+    /// * `value` - Value to round.
+    /// * `decimals` - Number of decimals kept.
+    /// # Returns
+    /// * Rounded value, never negative zero.
+    pub fn round(&self, value: f64, decimals: u8) -> f64 {
+        let scale = 10f64.powi(i32::from(decimals));
+        self.round_integer(value * scale) / scale + 0.0
+    }
+
+    /// Round to an integer.
+    fn round_integer(&self, value: f64) -> f64 {
+        match self {
+            RoundingMode::HalfUp => value.round(),
+            RoundingMode::HalfEven => value.round_ties_even(),
+        }
+    }
+}
+
+/// Spacing of the measurement line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementLayout {
+    /// Values separated by spaces, THI right-aligned: "23.7C 65.2%  72".
+    #[default]
+    Normal,
+    /// No separators or padding, the units delimit the values: "23.7C65.2%72".
+    /// Keeps the THI at precisions where the normal layout drops it.
+    Compact,
+}
+
+/// Shown in place of a value which is not a number, e.g. the humidity of a
+/// BMP280 or the dew point at 0 %.
+pub const MISSING_VALUE: &str = "--";
+
+/// Precision of the displayed measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeasurementFormat {
+    /// Decimals of the temperature, 0 to `MAX_DECIMALS`.
+    pub temperature_decimals: u8,
+    /// Decimals of the humidity, 0 to `MAX_DECIMALS`.
+    pub humidity_decimals: u8,
+    /// Rounding of the last digit.
+    pub rounding: RoundingMode,
+    /// Lowest and highest displayed THI, within `THI_DISPLAY_LIMITS`.
+    pub thi_range: (i16, i16),
+    /// Spacing of the line.
+    pub layout: MeasurementLayout,
+}
+
+impl Default for MeasurementFormat {
+    fn default() -> Self {
+        Self {
+            temperature_decimals: 1,
+            humidity_decimals: 1,
+            rounding: RoundingMode::default(),
+            thi_range: THI_DISPLAY_LIMITS,
+            layout: MeasurementLayout::default(),
+        }
+    }
+}
+
+impl MeasurementFormat {
+    /// Format a value with a number of decimals.
+    /// Non-finite values are shown as `MISSING_VALUE`.
+    fn value(&self, value: f64, decimals: u8) -> String {
+        if !value.is_finite() {
+            return MISSING_VALUE.to_string();
+        }
+        let decimals = decimals.min(MAX_DECIMALS);
+        format!(
+            "{:.*}",
+            usize::from(decimals),
+            self.rounding.round(value, decimals)
+        )
+    }
+
+    /// Format a temperature with the configured decimals.
+    pub fn temperature(&self, temperature: f64) -> String {
+        self.value(temperature, self.temperature_decimals)
+    }
+
+    /// Format a humidity with the configured decimals.
+    pub fn humidity(&self, humidity: f64) -> String {
+        self.value(humidity, self.humidity_decimals)
+    }
+
+    /// Format the THI without decimals, clamped to the displayed range so
+    /// it keeps to 3 columns. Non-finite values are shown as
+    /// `MISSING_VALUE`.
+    pub fn thi(&self, thi: f64) -> String {
+        if !thi.is_finite() {
+            return MISSING_VALUE.to_string();
+        }
+        let (min, max) = self.thi_range;
+        let min = min.max(THI_DISPLAY_LIMITS.0);
+        let max = max.min(THI_DISPLAY_LIMITS.1).max(min);
+        format!("{:.0}", thi.clamp(f64::from(min), f64::from(max)))
+    }
+
+    /// Format a pressure in hPa with one decimal.
+    /// Non-finite values are shown as `MISSING_VALUE`.
+    pub fn pressure_hpa(&self, pressure_pa: f64) -> String {
+        if !pressure_pa.is_finite() {
+            return MISSING_VALUE.to_string();
+        }
+        format!("{:.1}", pressure_pa / 100.0)
+    }
+}
+
+/// Absolute humidity, the mass of water vapor in a volume of air.
+/// The saturation vapor pressure comes from the Magnus formula with the
+/// coefficients of Bolton (1980), `6.112 * exp(17.67 * T / (T + 243.5))`
+/// hPa, and the vapor is taken as an ideal gas.
+/// # Arguments
+/// * `temperature_c` - Temperature in Celsius.
+/// * `humidity_relative` - Relative humidity in %.
+/// # Returns
+/// * Absolute humidity in g/m³, 0.0 at 0 % and NaN without humidity.
+pub fn absolute_humidity(temperature_c: f64, humidity_relative: f64) -> f64 {
+    let saturation_hpa = 6.112 * (17.67 * temperature_c / (temperature_c + 243.5)).exp();
+    // 2.1674 = 100 Pa/hPa / 100 % * 1000 g/kg / 461.5 J/(kg K), the gas
+    // constant of water vapor
+    saturation_hpa * humidity_relative * 2.1674 / (273.15 + temperature_c)
+}
+
+/// Fit text to one display line.
+/// The text is cut or padded with spaces to `columns`, and characters the
+/// display cannot show are replaced with '?'.
+/// # Arguments
+/// * `text` - Text to show.
+/// * `columns` - Characters per line of the display.
+/// # Returns
+/// * Line of exactly `columns` characters.
+pub fn fit_line(text: &str, columns: usize) -> String {
+    let line: String = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .take(columns)
+        .collect();
+    format!("{: <width$}", line, width = columns)
+}
+
+/// Check whether the system time looks synchronized.
+/// A Pi without RTC starts at 1970 until NTP syncs, so any time before
+/// `min_valid_year` is treated as unsynced.
+/// # Arguments
+/// * `now` - Current time.
+/// * `min_valid_year` - Earliest plausible year.
+/// # Returns
+/// * `true` if the time is plausible.
+pub fn is_time_synced<Tz: TimeZone>(now: &DateTime<Tz>, min_valid_year: i32) -> bool {
+    now.year() >= min_valid_year
+}
+
+/// Format the measurement line shown on the 2nd line of the display.
+/// Wide values first lose the padding of the THI, then the THI itself, so
+/// the temperature and humidity always fit in `MEASUREMENT_COLUMNS`. The
+/// compact layout leaves out the spaces from the start. The THI is clamped
+/// to `format.thi_range` for the display only.
+/// # Arguments
+/// * `temperature` - Temperature in Celsius.
+/// * `humidity` - Relative humidity in %.
+/// * `thi` - Temperature-humidity index.
+/// * `format` - Precision and rounding.
+/// # Returns
+/// * Formatted line.
+pub fn format_measurement_line(
+    temperature: f64,
+    humidity: f64,
+    thi: f64,
+    format: &MeasurementFormat,
+) -> String {
+    let temperature = format.temperature(temperature);
+    let humidity = format.humidity(humidity);
+    let thi = format.thi(thi);
+    let (values, candidates) = match format.layout {
+        MeasurementLayout::Normal => {
+            let values = format!("{}C {}%", temperature, humidity);
+            let candidates = vec![
+                format!("{} {: >3}", values, thi),
+                format!("{} {}", values, thi),
+            ];
+            (values, candidates)
+        }
+        MeasurementLayout::Compact => {
+            let values = format!("{}C{}%", temperature, humidity);
+            let candidates = vec![format!("{}{}", values, thi)];
+            (values, candidates)
+        }
+    };
+    candidates
+        .into_iter()
+        .find(|line| line.chars().count() <= MEASUREMENT_COLUMNS)
+        .unwrap_or(values)
+}
+
+/// Replace `{char:N}` placeholders with the custom character code N.
+/// Placeholders with an index outside 0-7 are left as they are.
+/// # Arguments
+/// * `template` - Display text.
+/// # Returns
+/// * Text ready to send to the display.
+pub fn expand_char_placeholders(template: &str) -> String {
+    const PREFIX: &str = "{char:";
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(PREFIX) {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + PREFIX.len()..];
+        let index = after
+            .find('}')
+            .and_then(|end| after[..end].parse::<u8>().ok().map(|i| (i, end)))
+            .filter(|(i, _)| *i <= 7);
+        match index {
+            Some((i, end)) => {
+                result.push(char::from(i));
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str(PREFIX);
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Transition reported by `ClockSync::update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockTransition {
+    /// No change since the last update.
+    Unchanged,
+    /// The clock became plausible.
+    Synced,
+    /// The clock became implausible (e.g. jumped back to 1970).
+    Lost,
+}
+
+/// Tracks whether the system clock is synchronized across loop iterations.
+#[derive(Debug)]
+pub struct ClockSync {
+    min_valid_year: i32,
+    synced: Option<bool>,
+}
+
+impl ClockSync {
+    /// Create a new tracker.
+    /// # Arguments
+    /// * `min_valid_year` - Earliest plausible year.
+    pub fn new(min_valid_year: i32) -> Self {
+        Self {
+            min_valid_year,
+            synced: None,
+        }
+    }
+
+    /// Update the state with the current time.
+    /// The first update only reports `Lost` when the clock is unsynced,
+    /// so a normal start with a valid time stays silent.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The transition caused by this update.
+    pub fn update<Tz: TimeZone>(&mut self, now: &DateTime<Tz>) -> ClockTransition {
+        let synced = is_time_synced(now, self.min_valid_year);
+        let transition = match (self.synced, synced) {
+            (None, true) => ClockTransition::Unchanged,
+            (None, false) => ClockTransition::Lost,
+            (Some(false), true) => ClockTransition::Synced,
+            (Some(true), false) => ClockTransition::Lost,
+            _ => ClockTransition::Unchanged,
+        };
+        self.synced = Some(synced);
+        transition
+    }
+
+    /// Whether the last update saw a synchronized clock.
+    pub fn is_synced(&self) -> bool {
+        self.synced.unwrap_or(false)
+    }
+}
+
+/// Rounds values to a display step with hysteresis.
+/// The shown value is held until the input moves past the rounding midpoint
+/// by a margin, so noise around a boundary (22.44, 22.46, 22.44, ...) does
+/// not make the display bounce between 22.4 and 22.5.
+#[derive(Debug, Clone)]
+pub struct HysteresisRounder {
+    step: f64,
+    margin: f64,
+    rounding: RoundingMode,
+    shown: Option<f64>,
+}
+
+impl HysteresisRounder {
+    /// Create a new rounder.
+    /// # Arguments
+    /// * `step` - Display resolution (0.1 for one decimal).
+    /// * `margin` - Distance past the midpoint needed to change the shown
+    ///   value, as a fraction of `step` (0 = plain rounding).
+    pub fn new(step: f64, margin: f64) -> Self {
+        Self {
+            step,
+            margin: margin.max(0.0),
+            rounding: RoundingMode::default(),
+            shown: None,
+        }
+    }
+
+    /// Create a rounder for a number of displayed decimals.
+    /// # Arguments
+    /// * `decimals` - Displayed decimals, the step is 10^-decimals.
+    /// * `margin` - Hysteresis margin, as in `new`.
+    /// * `rounding` - Rounding of ties.
+    pub fn with_decimals(decimals: u8, margin: f64, rounding: RoundingMode) -> Self {
+        Self {
+            rounding,
+            ..Self::new(10f64.powi(-i32::from(decimals)), margin)
+        }
+    }
+
+    /// Round a new reading.
+    /// # Arguments
+    /// * `value` - Raw value.
+    /// # Returns
+    /// * Value to display, a multiple of the step. Non-finite values are
+    ///   passed through and reset the rounder.
+    pub fn update(&mut self, value: f64) -> f64 {
+        if !value.is_finite() {
+            self.shown = None;
+            return value;
+        }
+        let threshold = self.step * (0.5 + self.margin);
+        match self.shown {
+            Some(shown) if (value - shown).abs() <= threshold => shown,
+            _ => {
+                // Scale by the inverse, 1/0.1 is exact while x/0.1 is not
+                let scale = 1.0 / self.step;
+                let rounded = self.rounding.round_integer(value * scale) / scale;
+                self.shown = Some(rounded);
+                rounded
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, Utc};
+
+    #[test]
+    fn test_is_time_synced_epoch() {
+        let epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 12).unwrap();
+        assert!(!is_time_synced(&epoch, 2020));
+    }
+
+    #[test]
+    fn test_is_time_synced_boundary() {
+        let before = Utc.with_ymd_and_hms(2019, 12, 31, 23, 59, 59).unwrap();
+        let after = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        assert!(!is_time_synced(&before, 2020));
+        assert!(is_time_synced(&after, 2020));
+    }
+
+    #[test]
+    fn test_is_time_synced_now() {
+        assert!(is_time_synced(&Local::now(), 2020));
+    }
+
+    #[test]
+    fn test_format_measurement_line() {
+        let line = format_measurement_line(23.7, 65.2, 72.5, &MeasurementFormat::default());
+        assert_eq!(line, "23.7C 65.2%  72");
+    }
+
+    fn measurement_format(decimals: u8, rounding: RoundingMode) -> MeasurementFormat {
+        MeasurementFormat {
+            temperature_decimals: decimals,
+            humidity_decimals: decimals,
+            rounding,
+            ..MeasurementFormat::default()
+        }
+    }
+
+    #[test]
+    fn test_format_measurement_line_precision() {
+        let line = |decimals| {
+            format_measurement_line(
+                24.96,
+                65.234,
+                72.0,
+                &measurement_format(decimals, RoundingMode::HalfUp),
+            )
+        };
+        assert_eq!(line(0), "25C 65%  72");
+        assert_eq!(line(1), "25.0C 65.2%  72");
+        assert_eq!(line(2), "24.96C 65.23%");
+    }
+
+    #[test]
+    fn test_format_measurement_line_fits_at_every_precision() {
+        for decimals in 0..=MAX_DECIMALS {
+            for rounding in [RoundingMode::HalfUp, RoundingMode::HalfEven] {
+                let format = measurement_format(decimals, rounding);
+                for (temperature, humidity) in
+                    [(23.7, 65.2), (-12.3, 99.95), (-40.0, 100.0), (85.0, 99.95)]
+                {
+                    let line = format_measurement_line(temperature, humidity, 110.0, &format);
+                    assert!(
+                        line.chars().count() <= MEASUREMENT_COLUMNS,
+                        "{:?} too wide at {} decimals",
+                        line,
+                        decimals
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_measurement_line_humidity_boundary() {
+        // 99.95% rounds up to 100, which widens the humidity
+        let line = |decimals| {
+            format_measurement_line(
+                -12.3,
+                99.95,
+                15.0,
+                &measurement_format(decimals, RoundingMode::HalfUp),
+            )
+        };
+        assert_eq!(line(0), "-12C 100%  15");
+        assert_eq!(line(1), "-12.3C 100.0%");
+        assert_eq!(line(2), "-12.30C 99.95%");
+    }
+
+    #[test]
+    fn test_format_measurement_line_extreme_thi() {
+        let format = MeasurementFormat::default();
+        let thi_field = |thi: f64| {
+            let line = format_measurement_line(23.7, 65.2, thi, &format);
+            line["23.7C 65.2% ".len()..].to_string()
+        };
+        assert_eq!(thi_field(-1234.5), "-99");
+        assert_eq!(thi_field(-99.4), "-99");
+        assert_eq!(thi_field(-5.0), " -5");
+        assert_eq!(thi_field(999.6), "999");
+        assert_eq!(thi_field(12345.0), "999");
+        assert_eq!(thi_field(f64::INFINITY), " --");
+        assert_eq!(thi_field(f64::NEG_INFINITY), " --");
+        assert_eq!(thi_field(f64::NAN), " --");
+        for thi in [-1e9, -100.0, -99.5, 0.0, 999.5, 1000.0, 1e9] {
+            assert_eq!(thi_field(thi).chars().count(), 3, "THI {}", thi);
+        }
+    }
+
+    #[test]
+    fn test_format_measurement_line_thi_range() {
+        let format = MeasurementFormat {
+            thi_range: (0, 99),
+            ..MeasurementFormat::default()
+        };
+        assert_eq!(
+            format_measurement_line(23.7, 65.2, 120.0, &format),
+            "23.7C 65.2%  99"
+        );
+        assert_eq!(
+            format_measurement_line(23.7, 65.2, -3.0, &format),
+            "23.7C 65.2%   0"
+        );
+        // A range beyond 3 columns is narrowed to the limits
+        let format = MeasurementFormat {
+            thi_range: (-500, 5000),
+            ..MeasurementFormat::default()
+        };
+        assert_eq!(
+            format_measurement_line(23.7, 65.2, 1500.0, &format),
+            "23.7C 65.2% 999"
+        );
+    }
+
+    #[test]
+    fn test_format_measurement_line_compact() {
+        let format = |decimals| MeasurementFormat {
+            layout: MeasurementLayout::Compact,
+            ..measurement_format(decimals, RoundingMode::HalfUp)
+        };
+        let line = |decimals, thi| format_measurement_line(23.7, 65.2, thi, &format(decimals));
+        assert_eq!(line(1, 72.4), "23.7C65.2%72");
+        assert_eq!(line(1, -5.0), "23.7C65.2%-5");
+        assert_eq!(line(0, 72.4), "24C65%72");
+        // Where the normal layout has to drop the THI
+        assert_eq!(line(2, 72.4), "23.70C65.20%72");
+        assert_eq!(
+            format_measurement_line(-40.0, 100.0, -99.0, &format(2)),
+            "-40.00C100.00%"
+        );
+    }
+
+    #[test]
+    fn test_compact_layout_is_narrower() {
+        for decimals in 0..=MAX_DECIMALS {
+            let normal = measurement_format(decimals, RoundingMode::HalfUp);
+            let compact = MeasurementFormat {
+                layout: MeasurementLayout::Compact,
+                ..normal
+            };
+            for (temperature, humidity, thi) in [
+                (23.7, 65.2, 72.0),
+                (-12.3, 99.95, 15.0),
+                (-40.0, 100.0, -99.0),
+                (85.0, 0.0, 999.0),
+            ] {
+                let normal = format_measurement_line(temperature, humidity, thi, &normal);
+                let compact = format_measurement_line(temperature, humidity, thi, &compact);
+                assert!(
+                    compact.chars().count() <= MEASUREMENT_COLUMNS,
+                    "{:?}",
+                    compact
+                );
+                // Shorter for the same fields, or the THI is kept
+                let thi_kept = |line: &str| !line.ends_with('%');
+                assert!(
+                    thi_kept(&compact) && !thi_kept(&normal)
+                        || (thi_kept(&compact) == thi_kept(&normal)
+                            && compact.chars().count() < normal.chars().count()),
+                    "{:?} vs {:?}",
+                    compact,
+                    normal
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounding_mode() {
+        assert_eq!(RoundingMode::HalfUp.round(24.25, 1), 24.3);
+        assert_eq!(RoundingMode::HalfEven.round(24.25, 1), 24.2);
+        assert_eq!(RoundingMode::HalfEven.round(24.35, 1), 24.4);
+        assert_eq!(RoundingMode::HalfUp.round(2.5, 0), 3.0);
+        assert_eq!(RoundingMode::HalfEven.round(2.5, 0), 2.0);
+        assert_eq!(RoundingMode::HalfUp.round(-2.5, 0), -3.0);
+        assert_eq!(RoundingMode::HalfEven.round(-2.5, 0), -2.0);
+        assert_eq!(RoundingMode::HalfEven.round(3.5, 0), 4.0);
+        // No "-0.0" on the display
+        let line = format_measurement_line(-0.04, 50.0, 40.0, &MeasurementFormat::default());
+        assert_eq!(line, "0.0C 50.0%  40");
+    }
+
+    #[test]
+    fn test_rounding_mode_bias() {
+        // Every tie: half up drifts the sum, half even keeps it
+        let ties: Vec<f64> = (0..10).map(|i| f64::from(i) + 0.5).collect();
+        let sum = |mode: RoundingMode| ties.iter().map(|&v| mode.round(v, 0)).sum::<f64>();
+        assert_eq!(ties.iter().sum::<f64>(), 50.0);
+        assert_eq!(sum(RoundingMode::HalfUp), 55.0);
+        assert_eq!(sum(RoundingMode::HalfEven), 50.0);
+    }
+
+    #[test]
+    fn test_dew_point_c() {
+        assert!((dew_point_c(25.0, 50.0) - 13.85).abs() < 0.05);
+        assert!((dew_point_c(30.0, 70.0) - 23.93).abs() < 0.05);
+        assert!((dew_point_c(5.0, 80.0) - 1.83).abs() < 0.05);
+        assert!((dew_point_c(-5.0, 90.0) - -6.40).abs() < 0.05);
+        assert!((dew_point_c(18.0, 100.0) - 18.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_absolute_humidity() {
+        // Saturated air holds 17.3 g/m³ at 20 °C and 23.0 g/m³ at 25 °C
+        assert!((absolute_humidity(20.0, 50.0) - 8.64).abs() < 0.05);
+        assert!((absolute_humidity(22.0, 45.0) - 8.73).abs() < 0.05);
+        assert!((absolute_humidity(25.0, 60.0) - 13.82).abs() < 0.05);
+        assert!((absolute_humidity(25.0, 100.0) - 23.03).abs() < 0.05);
+        assert!((absolute_humidity(30.0, 80.0) - 24.28).abs() < 0.05);
+        assert_eq!(absolute_humidity(25.0, 0.0), 0.0);
+        // Below freezing the air holds little water, but still some
+        let frost = absolute_humidity(-10.0, 80.0);
+        assert!((frost - 1.89).abs() < 0.05, "{}", frost);
+        assert!(absolute_humidity(-20.0, 100.0) > 0.0);
+        assert!(absolute_humidity(20.0, f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_fit_line() {
+        assert_eq!(fit_line("abc", DISPLAY_COLUMNS), "abc             ");
+        assert_eq!(
+            fit_line("0123456789abcdefXYZ", DISPLAY_COLUMNS),
+            "0123456789abcdef"
+        );
+        assert_eq!(fit_line("25°C\n", DISPLAY_COLUMNS), "25?C?           ");
+        assert_eq!(fit_line("0123456789abcdefXYZ", 20), "0123456789abcdefXYZ ");
+    }
+
+    #[test]
+    fn test_expand_char_placeholders() {
+        assert_eq!(expand_char_placeholders("{char:1}"), "\u{1}");
+        assert_eq!(
+            expand_char_placeholders("A{char:0}B{char:7}"),
+            "A\u{0}B\u{7}"
+        );
+        assert_eq!(expand_char_placeholders("no placeholder"), "no placeholder");
+        assert_eq!(expand_char_placeholders("{char:8}"), "{char:8}");
+        assert_eq!(
+            expand_char_placeholders("{char:x}{char:2"),
+            "{char:x}{char:2"
+        );
+    }
+
+    #[test]
+    fn test_clock_sync_resume_on_sync() {
+        let mut clock = ClockSync::new(2020);
+        let unsynced = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 5).unwrap();
+        let synced = Utc.with_ymd_and_hms(2025, 6, 16, 14, 30, 0).unwrap();
+
+        assert_eq!(clock.update(&unsynced), ClockTransition::Lost);
+        assert!(!clock.is_synced());
+        assert_eq!(clock.update(&unsynced), ClockTransition::Unchanged);
+        assert!(!clock.is_synced());
+
+        assert_eq!(clock.update(&synced), ClockTransition::Synced);
+        assert!(clock.is_synced());
+        assert_eq!(clock.update(&synced), ClockTransition::Unchanged);
+    }
+
+    #[test]
+    fn test_clock_sync_valid_at_start() {
+        let mut clock = ClockSync::new(2020);
+        let synced = Utc.with_ymd_and_hms(2025, 6, 16, 14, 30, 0).unwrap();
+
+        assert_eq!(clock.update(&synced), ClockTransition::Unchanged);
+        assert!(clock.is_synced());
+    }
+
+    #[test]
+    fn test_hysteresis_rounder_stable_around_boundary() {
+        let mut rounder = HysteresisRounder::new(0.1, 0.3);
+        let shown: Vec<String> = [22.44, 22.46, 22.43, 22.47, 22.45, 22.46, 22.44]
+            .iter()
+            .map(|v| format!("{:.1}", rounder.update(*v)))
+            .collect();
+        assert!(shown.iter().all(|s| s == "22.4"), "{:?}", shown);
+
+        // Starting on the other side holds the upper value
+        let mut rounder = HysteresisRounder::new(0.1, 0.3);
+        for v in [22.46, 22.44, 22.43, 22.45] {
+            assert_eq!(format!("{:.1}", rounder.update(v)), "22.5");
+        }
+    }
+
+    #[test]
+    fn test_hysteresis_rounder_follows_real_changes() {
+        let mut rounder = HysteresisRounder::new(0.1, 0.3);
+        assert_eq!(format!("{:.1}", rounder.update(22.44)), "22.4");
+        assert_eq!(format!("{:.1}", rounder.update(22.49)), "22.5");
+        assert_eq!(format!("{:.1}", rounder.update(23.01)), "23.0");
+        assert_eq!(format!("{:.1}", rounder.update(-0.31)), "-0.3");
+    }
+
+    #[test]
+    fn test_hysteresis_rounder_without_margin() {
+        let mut rounder = HysteresisRounder::new(1.0, 0.0);
+        assert_eq!(rounder.update(71.4), 71.0);
+        assert_eq!(rounder.update(71.6), 72.0);
+        assert_eq!(rounder.update(71.4), 71.0);
+        assert!(rounder.update(f64::NAN).is_nan());
+        assert_eq!(rounder.update(70.6), 71.0);
+    }
+
+    #[test]
+    fn test_hysteresis_rounder_with_decimals() {
+        let mut half_up = HysteresisRounder::with_decimals(1, 0.0, RoundingMode::HalfUp);
+        let mut half_even = HysteresisRounder::with_decimals(1, 0.0, RoundingMode::HalfEven);
+        assert_eq!(half_up.update(24.25), 24.3);
+        assert_eq!(half_even.update(24.25), 24.2);
+
+        let mut rounder = HysteresisRounder::with_decimals(2, 0.3, RoundingMode::HalfUp);
+        assert_eq!(format!("{:.2}", rounder.update(24.964)), "24.96");
+        assert_eq!(format!("{:.2}", rounder.update(24.966)), "24.96");
+    }
+}
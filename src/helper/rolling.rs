@@ -0,0 +1,235 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Aggregation of samples taken at irregular times.
+//!
+//! Reads are skipped under load and come faster during a capture, so a
+//! plain mean over a window leans toward the bursts. The mean here weighs
+//! every sample by the time it stands for instead, integrating linearly
+//! between neighbouring samples (trapezoidal rule) over their actual
+//! timestamps. Minimum and maximum are those of the samples themselves.
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::Measurement;
+
+/// Time-weighted mean, minimum and maximum of one value.
+#[derive(Debug, Clone, Default)]
+pub struct TimeWeighted {
+    /// Time of the first sample.
+    first: Option<DateTime<Local>>,
+    /// Time and value of the latest sample.
+    last: Option<(DateTime<Local>, f64)>,
+    /// Integral of the value over time, in value × seconds.
+    area: f64,
+    /// Sum of the samples, for the plain mean.
+    sum: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl TimeWeighted {
+    /// Add a sample. A sample older than the latest one counts for the
+    /// minimum, maximum and plain mean, but adds no time.
+    /// # Arguments
+    /// * `at` - Time the sample was taken.
+    /// * `value` - Sample.
+    pub fn push(&mut self, at: DateTime<Local>, value: f64) {
+        match self.last {
+            Some((last_at, last_value)) => {
+                let seconds = (at - last_at).as_seconds_f64().max(0.0);
+                self.area += (last_value + value) / 2.0 * seconds;
+                self.min = self.min.min(value);
+                self.max = self.max.max(value);
+                self.last = Some((last_at.max(at), value));
+            }
+            None => {
+                self.first = Some(at);
+                self.min = value;
+                self.max = value;
+                self.last = Some((at, value));
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Time from the first to the latest sample.
+    pub fn span(&self) -> chrono::Duration {
+        match (self.first, self.last) {
+            (Some(first), Some((last, _))) => last - first,
+            _ => chrono::Duration::zero(),
+        }
+    }
+
+    /// Mean weighted by the time between the samples.
+    /// # Returns
+    /// * The plain mean while the samples span no time, `None` without
+    ///   samples.
+    pub fn mean(&self) -> Option<f64> {
+        let seconds = self.span().as_seconds_f64();
+        if seconds > 0.0 {
+            Some(self.area / seconds)
+        } else {
+            self.plain_mean()
+        }
+    }
+
+    /// Arithmetic mean of the samples, ignoring their times.
+    pub fn plain_mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// Lowest sample.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Highest sample.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+}
+
+/// Time-weighted aggregate of measurements.
+#[derive(Debug, Clone, Default)]
+pub struct MeasurementWindow {
+    pub temperature_c: TimeWeighted,
+    pub humidity_relative: TimeWeighted,
+    pub pressure_pa: TimeWeighted,
+}
+
+impl MeasurementWindow {
+    /// Add a measurement.
+    /// # Arguments
+    /// * `at` - Time the measurement was taken.
+    /// * `measurement` - Measurement.
+    pub fn push(&mut self, at: DateTime<Local>, measurement: &Measurement) {
+        self.temperature_c.push(at, measurement.temperature_c);
+        self.humidity_relative
+            .push(at, measurement.humidity_relative);
+        self.pressure_pa.push(at, measurement.pressure_pa);
+    }
+
+    /// Time from the first to the latest measurement.
+    pub fn span(&self) -> chrono::Duration {
+        self.temperature_c.span()
+    }
+
+    /// Time-weighted mean of every value.
+    /// # Returns
+    /// * `None` without measurements.
+    pub fn mean(&self) -> Option<Measurement> {
+        Some(Measurement {
+            temperature_c: self.temperature_c.mean()?,
+            humidity_relative: self.humidity_relative.mean()?,
+            pressure_pa: self.pressure_pa.mean()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 6, 16, 14, 0, 0).unwrap() + chrono::Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn test_regular_samples_match_plain_mean() {
+        let mut window = TimeWeighted::default();
+        for (i, value) in [20.0, 21.0, 22.0].into_iter().enumerate() {
+            window.push(at(i as i64 * 10), value);
+        }
+        assert_eq!(window.mean(), Some(21.0));
+        assert_eq!(window.plain_mean(), Some(21.0));
+        assert_eq!(window.span(), chrono::Duration::seconds(20));
+    }
+
+    #[test]
+    fn test_burst_does_not_bias_mean() {
+        // 20.0 for a minute, then a burst of nine 30.0 samples within 9 s
+        let mut window = TimeWeighted::default();
+        window.push(at(0), 20.0);
+        window.push(at(60), 20.0);
+        for i in 1..=9 {
+            window.push(at(60 + i), 30.0);
+        }
+        let plain = window.plain_mean().unwrap();
+        let weighted = window.mean().unwrap();
+        assert!((plain - 28.1818).abs() < 1e-3);
+        // 60 s at 20, a 1 s ramp to 30, 8 s at 30
+        assert!((weighted - (1200.0 + 25.0 + 240.0) / 69.0).abs() < 1e-9);
+        assert!(plain - weighted > 6.0);
+        assert_eq!(window.min(), Some(20.0));
+        assert_eq!(window.max(), Some(30.0));
+    }
+
+    #[test]
+    fn test_skipped_reads_are_weighted_by_gap() {
+        // A 50 s gap of skipped reads between 10 and 20
+        let mut window = TimeWeighted::default();
+        for (seconds, value) in [(0, 10.0), (5, 10.0), (10, 10.0), (55, 20.0), (60, 20.0)] {
+            window.push(at(seconds), value);
+        }
+        assert_eq!(window.plain_mean(), Some(14.0));
+        let weighted = window.mean().unwrap();
+        assert!((weighted - (50.0 + 50.0 + 675.0 + 100.0) / 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_degenerate_windows() {
+        let mut window = TimeWeighted::default();
+        assert_eq!(window.mean(), None);
+        assert_eq!(window.min(), None);
+        window.push(at(0), 21.0);
+        window.push(at(0), 23.0);
+        assert_eq!(window.mean(), Some(22.0));
+        // An earlier sample adds no time
+        window.push(at(10), 23.0);
+        window.push(at(5), 40.0);
+        assert_eq!(window.span(), chrono::Duration::seconds(10));
+        assert_eq!(window.max(), Some(40.0));
+    }
+
+    #[test]
+    fn test_measurement_window() {
+        let mut window = MeasurementWindow::default();
+        assert!(window.mean().is_none());
+        let measurement = |temperature_c| Measurement {
+            temperature_c,
+            humidity_relative: 50.0,
+            pressure_pa: 100000.0,
+        };
+        window.push(at(0), &measurement(20.0));
+        window.push(at(30), &measurement(20.0));
+        window.push(at(31), &measurement(26.0));
+        window.push(at(32), &measurement(26.0));
+        let mean = window.mean().unwrap();
+        assert!((mean.temperature_c - (600.0 + 23.0 + 26.0) / 32.0).abs() < 1e-9);
+        assert_eq!(mean.humidity_relative, 50.0);
+        assert_eq!(window.span(), chrono::Duration::seconds(32));
+    }
+}
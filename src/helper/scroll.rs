@@ -0,0 +1,103 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Scrolling of text longer than a display line.
+
+/// Blank columns between the end of a scrolling text and its next start.
+const GAP: usize = 3;
+
+/// Position of a text scrolled one column per step.
+/// The text is passed on each step, so values filled into it can change
+/// while it scrolls without starting over.
+#[derive(Debug, Clone, Default)]
+pub struct Scroller {
+    offset: usize,
+}
+
+impl Scroller {
+    /// Create a scroller at the start of the text.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start the next text from its beginning.
+    pub fn reset(&mut self) {
+        self.offset = 0;
+    }
+
+    /// Take the visible part of a text and move on by one column.
+    /// A text which fits is shown as it is and does not move.
+    /// # Arguments
+    /// * `text` - Text to scroll.
+    /// * `width` - Visible columns.
+    /// # Returns
+    /// * `width` columns of the text, wrapping around after a gap.
+    pub fn next_window(&mut self, text: &str, width: usize) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        if chars.len() <= width {
+            self.offset = 0;
+            return format!("{: <width$}", text, width = width);
+        }
+        let cycle = chars.len() + GAP;
+        let start = self.offset % cycle;
+        self.offset = (start + 1) % cycle;
+        (start..start + width)
+            .map(|i| chars.get(i % cycle).copied().unwrap_or(' '))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_text_stays() {
+        let mut scroller = Scroller::new();
+        assert_eq!(scroller.next_window("HOT", 6), "HOT   ");
+        assert_eq!(scroller.next_window("HOT", 6), "HOT   ");
+    }
+
+    #[test]
+    fn test_long_text_wraps_after_gap() {
+        let mut scroller = Scroller::new();
+        let windows: Vec<String> = (0..11)
+            .map(|_| scroller.next_window("ABCDEFG", 5))
+            .collect();
+        assert_eq!(windows[0], "ABCDE");
+        assert_eq!(windows[1], "BCDEF");
+        assert_eq!(windows[4], "EFG  ");
+        assert_eq!(windows[6], "G   A");
+        assert_eq!(windows[9], " ABCD");
+        // One full cycle is the text and the gap
+        assert_eq!(windows[10], "ABCDE");
+
+        scroller.reset();
+        assert_eq!(scroller.next_window("ABCDEFG", 5), "ABCDE");
+    }
+
+    #[test]
+    fn test_changed_text_keeps_position() {
+        let mut scroller = Scroller::new();
+        scroller.next_window("HUMIDITY 85.2%", 8);
+        assert_eq!(scroller.next_window("HUMIDITY 85.3%", 8), "UMIDITY ");
+    }
+}
@@ -0,0 +1,183 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Locale-dependent formatting of numbers, for files read by spreadsheets.
+//!
+//! Only the CSV export, and the replay reading it back, use it. The database, the JSON API and the display
+//! always use the canonical `.` decimal separator.
+
+/// Decimal and digit group separators of a locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberLocale {
+    /// Separator of the fractional digits.
+    pub decimal: char,
+    /// Separator of the thousands, if grouping is requested.
+    pub group: char,
+}
+
+impl NumberLocale {
+    /// The canonical format, also used by English.
+    pub const C: NumberLocale = NumberLocale {
+        decimal: '.',
+        group: ',',
+    };
+
+    /// Look up a locale by name, e.g. `de`, `de_DE.UTF-8` or `fr-CH`.
+    /// # Arguments
+    /// * `name` - Language, optionally with region and encoding.
+    /// # Returns
+    /// * `Err(message)` for unknown languages.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let name = name.split('.').next().unwrap_or_default().to_lowercase();
+        let mut parts = name.split(['_', '-']);
+        let language = parts.next().unwrap_or_default();
+        let region = parts.next();
+        let comma = |group| NumberLocale {
+            decimal: ',',
+            group,
+        };
+        match (language, region) {
+            ("de" | "fr" | "it", Some("ch" | "li")) => Ok(NumberLocale {
+                decimal: '.',
+                group: '\'',
+            }),
+            ("c" | "posix" | "en" | "ja" | "ko" | "zh", _) => Ok(Self::C),
+            ("de" | "da" | "es" | "id" | "it" | "nl" | "pt" | "tr", _) => Ok(comma('.')),
+            ("cs" | "fi" | "fr" | "nb" | "no" | "pl" | "ru" | "sv", _) => Ok(comma(' ')),
+            _ => Err(format!("unknown locale {}", name)),
+        }
+    }
+
+    /// Format a number with the shortest digits which read back to it.
+    /// # Arguments
+    /// * `value` - Number to format.
+    /// * `grouping` - Separate the integer digits in thousands.
+    /// # Returns
+    /// * The number, `NaN` and `inf` as is.
+    pub fn format(&self, value: f64, grouping: bool) -> String {
+        let canonical = value.to_string();
+        if !value.is_finite() {
+            return canonical;
+        }
+        let (sign, digits) = match canonical.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", canonical.as_str()),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+        let mut formatted = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if grouping && i > 0 && (integer.len() - i) % 3 == 0 {
+                formatted.push(self.group);
+            }
+            formatted.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            formatted.push(self.decimal);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    /// Read back a number written by `format`, grouped or not.
+    /// # Arguments
+    /// * `text` - Formatted number.
+    /// # Returns
+    /// * The number, `None` if `text` is not one.
+    pub fn parse_number(&self, text: &str) -> Option<f64> {
+        let canonical: String = text
+            .trim()
+            .chars()
+            .filter(|c| *c != self.group)
+            .map(|c| if c == self.decimal { '.' } else { c })
+            .collect();
+        canonical.parse().ok()
+    }
+}
+
+impl Default for NumberLocale {
+    fn default() -> Self {
+        Self::C
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!(NumberLocale::parse("C"), Ok(NumberLocale::C));
+        assert_eq!(NumberLocale::parse("en_US.UTF-8"), Ok(NumberLocale::C));
+        let german = NumberLocale::parse("de_DE.UTF-8").unwrap();
+        assert_eq!((german.decimal, german.group), (',', '.'));
+        assert_eq!(NumberLocale::parse("de"), Ok(german));
+        assert_eq!(NumberLocale::parse("fr-FR").unwrap().group, ' ');
+        let swiss = NumberLocale::parse("de-CH").unwrap();
+        assert_eq!((swiss.decimal, swiss.group), ('.', '\''));
+        assert_eq!(
+            NumberLocale::parse("xx"),
+            Err("unknown locale xx".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_canonical() {
+        let c = NumberLocale::C;
+        assert_eq!(c.format(21.5, false), "21.5");
+        assert_eq!(c.format(101325.0, false), "101325");
+        assert_eq!(c.format(101325.0, true), "101,325");
+        assert_eq!(c.format(-0.25, true), "-0.25");
+    }
+
+    #[test]
+    fn test_format_german() {
+        let german = NumberLocale::parse("de").unwrap();
+        assert_eq!(german.format(21.5, false), "21,5");
+        assert_eq!(german.format(-12.75, false), "-12,75");
+        assert_eq!(german.format(101325.25, true), "101.325,25");
+        assert_eq!(german.format(1234567.0, true), "1.234.567");
+        assert_eq!(german.format(999.5, true), "999,5");
+        assert_eq!(german.format(-1000.0, true), "-1.000");
+    }
+
+    #[test]
+    fn test_format_not_finite() {
+        let german = NumberLocale::parse("de").unwrap();
+        assert_eq!(german.format(f64::NAN, true), "NaN");
+        assert_eq!(german.format(f64::NEG_INFINITY, true), "-inf");
+    }
+
+    #[test]
+    fn test_parse_number() {
+        let german = NumberLocale::parse("de").unwrap();
+        for value in [21.5, -12.75, 101325.25, 1234567.0] {
+            assert_eq!(
+                german.parse_number(&german.format(value, true)),
+                Some(value)
+            );
+        }
+        assert_eq!(NumberLocale::C.parse_number("101325.5"), Some(101325.5));
+        assert_eq!(german.parse_number("n/a"), None);
+    }
+}
@@ -0,0 +1,453 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! External commands run on events, for custom integrations.
+//!
+//! A hook is a command template such as
+//! `/usr/local/bin/notify {sensor} {temperature_c}`. It is split on
+//! whitespace and the placeholders are substituted in each argument, then
+//! the program is run directly, without a shell, so values never need
+//! quoting. `{json}` expands to the whole reading as one JSON argument,
+//! limited to the selected field groups.
+//!
+//! Webhooks are posted as JSON over plain HTTP, and other units are read
+//! the same way.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{Map, Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+use crate::config::FieldGroup;
+use crate::database::SensorData;
+
+/// Command run on an event, at most one instance at a time.
+#[derive(Debug, Clone)]
+pub struct CommandHook {
+    name: &'static str,
+    args: Vec<String>,
+    fields: Vec<FieldGroup>,
+    running: Arc<AtomicBool>,
+}
+
+/// Clears the running flag when the command task ends, however it ends.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl CommandHook {
+    /// Parse a command template.
+    /// # Arguments
+    /// * `name` - Hook name used in the log, e.g. "after_insert".
+    /// * `template` - Command line with placeholders.
+    /// # Returns
+    /// * `Err(message)` if the template has no program.
+    pub fn new(name: &'static str, template: &str) -> Result<Self, String> {
+        let args: Vec<String> = template.split_whitespace().map(str::to_string).collect();
+        if args.is_empty() {
+            return Err(format!("hooks.{} must not be empty", name));
+        }
+        Ok(Self {
+            name,
+            args,
+            fields: FieldGroup::ALL.to_vec(),
+            running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Select the measurement fields in `{json}`. All are included by
+    /// default.
+    /// # Arguments
+    /// * `fields` - Field groups to include.
+    pub fn with_fields(mut self, fields: &[FieldGroup]) -> Self {
+        self.fields = fields.to_vec();
+        self
+    }
+
+    /// Build the `{json}` value of a reading.
+    fn to_json(&self, data: &SensorData, timestamp: &str) -> String {
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), json!(timestamp));
+        object.insert("sensor".to_string(), json!(data.sensor));
+        if self.fields.contains(&FieldGroup::Raw) {
+            object.insert("temperature_c".to_string(), json!(data.temperature_c));
+            object.insert(
+                "humidity_relative".to_string(),
+                json!(data.humidity_relative),
+            );
+            object.insert("pressure_pa".to_string(), json!(data.pressure_pa));
+        }
+        if self.fields.contains(&FieldGroup::Derived) {
+            object.insert("thi".to_string(), json!(data.thi));
+        }
+        object.insert("quality".to_string(), json!(data.quality.as_str()));
+        if let Some(sequence) = &data.sequence {
+            object.insert("run_id".to_string(), json!(sequence.run_id));
+            object.insert("seq".to_string(), json!(sequence.seq));
+        }
+        Value::Object(object).to_string()
+    }
+
+    /// Substitute the placeholders with the values of a reading.
+    /// # Arguments
+    /// * `data` - Inserted row.
+    /// # Returns
+    /// * Program and arguments.
+    pub fn render(&self, data: &SensorData) -> Vec<String> {
+        let timestamp = data.timestamp.to_rfc3339();
+        let json = self.to_json(data, &timestamp);
+        let (run_id, seq) = match &data.sequence {
+            Some(sequence) => (sequence.run_id.to_string(), sequence.seq.to_string()),
+            None => (String::new(), String::new()),
+        };
+        let values = [
+            ("{timestamp}", timestamp),
+            ("{sensor}", data.sensor.clone()),
+            ("{temperature_c}", data.temperature_c.to_string()),
+            ("{humidity_relative}", data.humidity_relative.to_string()),
+            ("{pressure_pa}", data.pressure_pa.to_string()),
+            ("{thi}", data.thi.to_string()),
+            ("{quality}", data.quality.as_str().to_string()),
+            ("{run_id}", run_id),
+            ("{seq}", seq),
+            ("{json}", json),
+        ];
+        self.args
+            .iter()
+            .map(|arg| {
+                values
+                    .iter()
+                    .fold(arg.clone(), |arg, (key, value)| arg.replace(key, value))
+            })
+            .collect()
+    }
+
+    /// Run the command in the background unless the previous run is still
+    /// going, so a slow command cannot pile up processes.
+    /// The output is logged when the command exits.
+    /// # Arguments
+    /// * `data` - Inserted row.
+    /// # Returns
+    /// * `false` if the run was skipped.
+    pub fn trigger(&self, data: &SensorData) -> bool {
+        if self.running.swap(true, Ordering::AcqRel) {
+            eprintln!("Hook {} is still running, skipped", self.name);
+            return false;
+        }
+        let guard = RunningGuard(self.running.clone());
+        let name = self.name;
+        let args = self.render(data);
+        tokio::spawn(async move {
+            let _guard = guard;
+            let output = Command::new(&args[0])
+                .args(&args[1..])
+                .kill_on_drop(true)
+                .output()
+                .await;
+            match output {
+                Ok(output) => {
+                    for line in String::from_utf8_lossy(&output.stdout).lines() {
+                        println!("Hook {}: {}", name, line);
+                    }
+                    for line in String::from_utf8_lossy(&output.stderr).lines() {
+                        eprintln!("Hook {}: {}", name, line);
+                    }
+                    if !output.status.success() {
+                        eprintln!("Hook {} failed: {}", name, output.status);
+                    }
+                }
+                Err(e) => eprintln!("Failed to run hook {}: {}", name, e),
+            }
+        });
+        true
+    }
+
+    /// Whether a run is in progress.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+}
+
+/// Post a JSON body to a webhook.
+/// # Arguments
+/// * `url` - URL of the form "http://host[:port]/path".
+/// * `body` - Body to post.
+/// # Returns
+/// * The HTTP status code of the response.
+pub async fn post_json(url: &str, body: &Value) -> Result<u16, String> {
+    let (status, _) = exchange("POST", url, Some(body)).await?;
+    Ok(status)
+}
+
+/// Get a JSON document, e.g. from another unit's HTTP API.
+/// # Arguments
+/// * `url` - URL of the form "http://host[:port]/path".
+/// # Returns
+/// * The body of a 200 response.
+pub async fn get_json(url: &str) -> Result<Value, String> {
+    match exchange("GET", url, None).await? {
+        (200, body) => {
+            serde_json::from_str(&body).map_err(|e| format!("Invalid JSON from {}: {}", url, e))
+        }
+        (status, _) => Err(format!("{} answered {}", url, status)),
+    }
+}
+
+/// Send one request over a new connection and read the whole response.
+/// # Arguments
+/// * `method` - Request method.
+/// * `url` - URL of the form "http://host[:port]/path".
+/// * `body` - JSON body, if any.
+/// # Returns
+/// * The status code and the body of the response.
+async fn exchange(method: &str, url: &str, body: Option<&Value>) -> Result<(u16, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Unsupported URL {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    let request = match body.map(|body| body.to_string()) {
+        Some(body) => format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            host,
+            body.len(),
+            body
+        ),
+        None => format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\n\
+             Connection: close\r\n\r\n",
+            method, path, host
+        ),
+    };
+
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send to {}: {}", url, e))?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .await
+        .map_err(|e| format!("Failed to read the response of {}: {}", url, e))?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("Invalid response from {}", url))?;
+    Ok((status, body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality::Quality;
+    use crate::sequence::Sequence;
+    use chrono::{Local, TimeZone};
+    use peripheral::bme280::Measurement;
+    use std::time::Duration;
+
+    fn sample_row() -> SensorData {
+        let measurement = Measurement {
+            temperature_c: 25.5,
+            humidity_relative: 60.0,
+            pressure_pa: 101325.0,
+        };
+        let at = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap();
+        let mut data = SensorData::from_measurement_at(measurement, 75.8, at);
+        data.quality = Quality::Suspect;
+        data.sequence = Some(Sequence {
+            run_id: "20250616T143000-1234".into(),
+            seq: 42,
+        });
+        data
+    }
+
+    async fn wait_idle(hook: &CommandHook) {
+        for _ in 0..100 {
+            if !hook.is_running() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("hook still running");
+    }
+
+    #[test]
+    fn test_render_substitutes_placeholders() {
+        let hook = CommandHook::new(
+            "after_insert",
+            "notify  --t={temperature_c} {sensor} {quality} {seq}",
+        )
+        .unwrap();
+        assert_eq!(
+            hook.render(&sample_row()),
+            vec!["notify", "--t=25.5", "bme280", "suspect", "42"]
+        );
+    }
+
+    #[test]
+    fn test_render_json_is_one_argument() {
+        let hook = CommandHook::new("after_insert", "notify {json}").unwrap();
+        let args = hook.render(&sample_row());
+        assert_eq!(args.len(), 2);
+        let json: serde_json::Value = serde_json::from_str(&args[1]).unwrap();
+        assert_eq!(json["sensor"], "bme280");
+        assert_eq!(json["humidity_relative"], 60.0);
+        assert_eq!(json["thi"], 75.8);
+        assert_eq!(json["run_id"], "20250616T143000-1234");
+        assert_eq!(json["seq"], 42);
+        assert_eq!(
+            json["timestamp"],
+            sample_row().timestamp.to_rfc3339().as_str()
+        );
+    }
+
+    #[test]
+    fn test_render_json_derived_only() {
+        let hook = CommandHook::new("after_insert", "notify {json}")
+            .unwrap()
+            .with_fields(&[FieldGroup::Derived]);
+        let args = hook.render(&sample_row());
+        let json: serde_json::Value = serde_json::from_str(&args[1]).unwrap();
+        assert_eq!(json["thi"], 75.8);
+        assert_eq!(json["sensor"], "bme280");
+        for raw in ["temperature_c", "humidity_relative", "pressure_pa"] {
+            assert!(json.get(raw).is_none(), "{} should be omitted", raw);
+        }
+    }
+
+    #[test]
+    fn test_render_json_raw_only() {
+        let hook = CommandHook::new("after_insert", "notify {json}")
+            .unwrap()
+            .with_fields(&[FieldGroup::Raw]);
+        let args = hook.render(&sample_row());
+        let json: serde_json::Value = serde_json::from_str(&args[1]).unwrap();
+        assert_eq!(json["temperature_c"], 25.5);
+        assert!(json.get("thi").is_none());
+    }
+
+    #[test]
+    fn test_empty_template_is_rejected() {
+        assert!(CommandHook::new("after_insert", "  ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skips_while_running() {
+        let hook = CommandHook::new("after_insert", "sleep 0.3").unwrap();
+        assert!(hook.trigger(&sample_row()));
+        assert!(!hook.trigger(&sample_row()));
+        wait_idle(&hook).await;
+        assert!(hook.trigger(&sample_row()));
+        wait_idle(&hook).await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_spawn_clears_running() {
+        let hook = CommandHook::new("after_insert", "/nonexistent/wbroker-hook").unwrap();
+        assert!(hook.trigger(&sample_row()));
+        wait_idle(&hook).await;
+        assert!(hook.trigger(&sample_row()));
+        wait_idle(&hook).await;
+    }
+
+    #[tokio::test]
+    async fn test_post_json() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alert", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            // The body ends the request
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let status = post_json(&url, &json!({"alert": "HOT"})).await.unwrap();
+        assert_eq!(status, 204);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /alert HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json"));
+        assert!(request.ends_with("\r\n\r\n{\"alert\":\"HOT\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_get_json() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/current", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in [
+                "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n{\"a\":1}",
+                "HTTP/1.1 503 Service Unavailable\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n\r\nnot json",
+            ] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 1024];
+                let n = stream.read(&mut buffer).await.unwrap();
+                assert!(buffer[..n].starts_with(b"GET /api/current HTTP/1.1\r\n"));
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        assert_eq!(get_json(&url).await.unwrap(), json!({"a": 1}));
+        assert!(get_json(&url).await.unwrap_err().contains("503"));
+        assert!(get_json(&url).await.unwrap_err().contains("Invalid JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_post_json_rejects_https() {
+        let error = post_json("https://example.com/", &json!({}))
+            .await
+            .unwrap_err();
+        assert!(error.contains("Unsupported"));
+    }
+}
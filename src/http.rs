@@ -0,0 +1,1631 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! HTTP API.
+//!
+//! * `GET /api/current` - Latest reading of the main sensor, 503 before the
+//!   first one. Polled by other units for their peers page. Carries the
+//!   degree-hours and the mold-risk index when [daily_metrics] is enabled.
+//! * `GET /api/info` - Version, start-up timing, the number of rows whose
+//!   timestamp went backwards, the free space of the SQLite filesystem and
+//!   the holder of the display lease.
+//! * `GET /api/sensor/config` - Current [sensor] settings.
+//! * `PUT /api/sensor/config[?persist=true]` - Validate and apply new
+//!   settings. The measurement loop picks them up before its next
+//!   measurement, so a measurement never runs with half-applied settings.
+//!   With `persist=true` the changed values are written to the config
+//!   file as well, see `config_file`.
+//! * `POST /api/maintenance?state=readonly|normal` - Request read-only
+//!   maintenance mode or leave it.
+//! * `GET /healthz` - Liveness and the applied maintenance state. A client
+//!   waits here until `maintenance` matches the state it requested. The
+//!   status is `degraded` while optional subsystems, listed in `degraded`,
+//!   failed at start-up.
+//! * `POST /api/capture?rate_ms=200&duration_secs=600` - Start a burst
+//!   capture. Rejected with 409 while another capture is active.
+//! * `GET /api/capture` - The active capture, `null` when idle.
+//! * `POST /api/display/lease?holder=mpd&duration_secs=60` - Take over the
+//!   display, see `lease`. Rejected with 409 while another client holds it.
+//!   The response carries the `lease_id` for the calls below.
+//! * `PUT /api/display/lease?lease_id=1&duration_secs=60` - Renew the lease
+//!   for another `duration_secs` from now. 409 once it has expired.
+//! * `DELETE /api/display/lease?lease_id=1` - Give the display back.
+//! * `GET /api/display/lease` - The held lease, `null` when the display is
+//!   free. Also reported in `GET /api/info`.
+//! * `POST /api/qnh?hpa=1018` - Set the QNH of the altimeter page. It is
+//!   kept in the state file across restarts. 409 unless the page is enabled.
+//! * `GET /api/qnh` - The QNH and when it was set.
+//! * `GET /api/extremes` - Highest temperature and lowest humidity since the
+//!   last reset. 409 unless the extremes page is enabled.
+//! * `POST /api/extremes/reset` - Start the extremes over, like a double
+//!   press of the wake button.
+//! * `POST /api/debug/trace?secs=60` - Trace every step of the
+//!   measurement loop to a file for a while, see `trace`. Rejected with 409
+//!   while another trace runs.
+//! * `GET /api/debug/trace` - The running trace, `null` when off.
+//! * `GET /api/safemode` - The safe mode the daemon runs in after repeated
+//!   crashes, `null` in normal mode. See `safe_mode`.
+//! * `POST /api/safemode/clear` - Forget the crashes and restart in normal
+//!   mode. 409 unless in safe mode.
+//! * `GET /api/openapi.json` - OpenAPI document of this API.
+//! * `GET /api/schema` - JSON Schema of the stored rows, marking the
+//!   optional fields this configuration produces.
+//!
+//! While on UPS battery every response carries `Connection: close`, so idle
+//! clients do not hold connections open.
+//!
+//! With [http] kiosk_bind a second listener serves `GET /api/current` and
+//! `GET /healthz` only, for a network which must not reach the rest. It has
+//! a router of its own, see `kiosk_router`, so a route added to `router` is
+//! never served there by accident.
+
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, header};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, watch};
+
+use crate::altimeter::{QnhSetting, QnhStore};
+use crate::capture::{CaptureControl, CaptureRequest, CaptureStatus};
+use crate::config::{self, Config, SensorConfig};
+use crate::daily_metrics::{DailyMetricsStatus, DailyMetricsStore};
+use crate::database::{TimestampCounts, TimestampStats};
+use crate::disk::{DiskStats, DiskStatus};
+use crate::error::HttpError;
+use crate::extremes::{Extremes, ExtremesStore};
+use crate::lease::{self, LeaseControl, LeaseRequest, LeaseStatus};
+use crate::maintenance::{Maintenance, MaintenanceState};
+use crate::openapi::{self, ProducedFields};
+use crate::power::PowerStats;
+use crate::safe_mode::{SafeMode, SafeModeStatus};
+use crate::startup::{StartupReport, Subsystem};
+use crate::supervisor::Supervisor;
+use crate::trace::{TraceControl, TraceError, TraceRequest, TraceStatus};
+
+/// State shared by the handlers.
+pub struct ApiState {
+    /// Sensor settings watched by the measurement loop.
+    sensor: watch::Sender<SensorConfig>,
+    /// Config file to persist to, if one was loaded.
+    config_path: Option<PathBuf>,
+    /// Serializes updates so the applied and persisted settings agree.
+    update_lock: Mutex<()>,
+    /// Start-up timing, filled in once start-up completes.
+    startup: RwLock<Option<StartupReport>>,
+    /// Optional subsystems which failed at start-up.
+    degraded: RwLock<Vec<Subsystem>>,
+    /// Latest reading of the main sensor.
+    current: RwLock<Option<Current>>,
+    /// Maintenance mode shared with the measurement loop.
+    maintenance: Arc<Maintenance>,
+    /// Burst capture slot shared with the measurement loop.
+    capture: Arc<CaptureControl>,
+    /// Display lease shared with the measurement loop.
+    display_lease: Arc<LeaseControl>,
+    /// Timestamp counters of the database writer, if logging.
+    timestamp_stats: Option<Arc<TimestampStats>>,
+    /// Free space of the SQLite filesystem, if monitored.
+    disk: Option<Arc<DiskStats>>,
+    /// UPS power state, if the power loss signal is wired.
+    power: Option<Arc<PowerStats>>,
+    /// Optional row fields reported by `GET /api/schema`.
+    produced: ProducedFields,
+    /// QNH of the altimeter page, if enabled.
+    qnh: Option<Arc<QnhStore>>,
+    /// Extremes of the extremes page, if enabled.
+    extremes: Option<Arc<ExtremesStore>>,
+    /// Degree-hours and mold risk, if enabled.
+    daily_metrics: Option<Arc<DailyMetricsStore>>,
+    /// Debug trace of the measurement loop.
+    trace: Option<Arc<TraceControl>>,
+    /// Safe mode, if the daemon started in it.
+    safe_mode: Option<Arc<SafeMode>>,
+}
+
+impl ApiState {
+    /// Create the API state.
+    /// # Arguments
+    /// * `sensor` - Sender of the sensor settings.
+    /// * `config_path` - Config file to persist to, if one was loaded.
+    /// * `maintenance` - Maintenance mode shared with the measurement loop.
+    /// * `capture` - Burst capture slot shared with the measurement loop.
+    /// * `display_lease` - Display lease shared with the measurement loop.
+    pub fn new(
+        sensor: watch::Sender<SensorConfig>,
+        config_path: Option<PathBuf>,
+        maintenance: Arc<Maintenance>,
+        capture: Arc<CaptureControl>,
+        display_lease: Arc<LeaseControl>,
+    ) -> Self {
+        Self {
+            sensor,
+            config_path,
+            update_lock: Mutex::new(()),
+            startup: RwLock::new(None),
+            degraded: RwLock::new(Vec::new()),
+            current: RwLock::new(None),
+            maintenance,
+            capture,
+            display_lease,
+            timestamp_stats: None,
+            disk: None,
+            power: None,
+            produced: ProducedFields::default(),
+            qnh: None,
+            extremes: None,
+            daily_metrics: None,
+            trace: None,
+            safe_mode: None,
+        }
+    }
+
+    /// Report the timestamp counters of the database writer.
+    /// # Arguments
+    /// * `stats` - Counters shared with the writer.
+    pub fn with_timestamp_stats(mut self, stats: Arc<TimestampStats>) -> Self {
+        self.timestamp_stats = Some(stats);
+        self
+    }
+
+    /// Report the free space of the SQLite filesystem.
+    /// # Arguments
+    /// * `stats` - Status shared with the disk monitor.
+    pub fn with_disk(mut self, stats: Arc<DiskStats>) -> Self {
+        self.disk = Some(stats);
+        self
+    }
+
+    /// Report the UPS power state and drop keep-alive while on battery.
+    /// # Arguments
+    /// * `stats` - State shared with the wind-down tracker.
+    pub fn with_power(mut self, stats: Arc<PowerStats>) -> Self {
+        self.power = Some(stats);
+        self
+    }
+
+    /// Publish the latest reading of the main sensor.
+    /// # Arguments
+    /// * `current` - Reading with offsets applied.
+    pub fn set_current(&self, current: Current) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Some(current);
+    }
+
+    /// Report the optional row fields the configuration produces.
+    /// # Arguments
+    /// * `produced` - Optional fields produced.
+    pub fn with_produced_fields(mut self, produced: ProducedFields) -> Self {
+        self.produced = produced;
+        self
+    }
+
+    /// Accept the QNH of the altimeter page.
+    /// # Arguments
+    /// * `qnh` - QNH shared with the measurement loop.
+    pub fn with_qnh(mut self, qnh: Arc<QnhStore>) -> Self {
+        self.qnh = Some(qnh);
+        self
+    }
+
+    /// Serve the extremes of the extremes page.
+    /// # Arguments
+    /// * `extremes` - Extremes shared with the measurement loop.
+    pub fn with_extremes(mut self, extremes: Arc<ExtremesStore>) -> Self {
+        self.extremes = Some(extremes);
+        self
+    }
+
+    /// Add the degree-hours and the mold risk to `GET /api/current`.
+    /// # Arguments
+    /// * `daily_metrics` - Accumulator shared with its task.
+    pub fn with_daily_metrics(mut self, daily_metrics: Arc<DailyMetricsStore>) -> Self {
+        self.daily_metrics = Some(daily_metrics);
+        self
+    }
+
+    /// Let clients start a debug trace.
+    /// # Arguments
+    /// * `trace` - Trace shared with the measurement loop.
+    pub fn with_trace(mut self, trace: Arc<TraceControl>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Report the safe mode and let clients end it.
+    /// # Arguments
+    /// * `safe_mode` - Safe mode shared with the measurement loop.
+    pub fn with_safe_mode(mut self, safe_mode: Arc<SafeMode>) -> Self {
+        self.safe_mode = Some(safe_mode);
+        self
+    }
+
+    fn is_on_battery(&self) -> bool {
+        self.power
+            .as_ref()
+            .is_some_and(|power| power.is_on_battery())
+    }
+
+    /// Publish the start-up timing.
+    /// # Arguments
+    /// * `report` - Start-up timing.
+    pub fn set_startup(&self, report: StartupReport) {
+        *self.startup.write().unwrap_or_else(|e| e.into_inner()) = Some(report);
+    }
+
+    /// Publish the optional subsystems running degraded.
+    /// # Arguments
+    /// * `degraded` - Subsystems which failed at start-up.
+    pub fn set_degraded(&self, degraded: &[Subsystem]) {
+        *self.degraded.write().unwrap_or_else(|e| e.into_inner()) = degraded.to_vec();
+    }
+}
+
+/// Body of `GET /api/current`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Current {
+    /// RFC 3339 time of the reading.
+    pub timestamp: String,
+    pub sensor: String,
+    pub temperature_c: f64,
+    pub humidity_relative: f64,
+    pub pressure_pa: f64,
+    /// Altitude from the pressure and [sensor] sea_level_pa.
+    pub altitude_m: f64,
+    /// Dew point in °C, null without humidity.
+    pub dew_point_c: f64,
+    /// Absolute humidity in g/m³, null without humidity.
+    pub absolute_humidity_g_m3: f64,
+    pub thi: f64,
+    /// Mode which took the reading, `live`, `capture` or `simulated`.
+    pub source: String,
+}
+
+/// Body of `GET /api/current`: the reading, and the degree-hours and mold
+/// risk if enabled.
+#[derive(Debug, Serialize)]
+struct CurrentBody {
+    #[serde(flatten)]
+    current: Current,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    daily_metrics: Option<DailyMetricsBody>,
+}
+
+/// Degree-hours of the day so far and the mold risk of the last 24 hours.
+#[derive(Debug, Serialize)]
+struct DailyMetricsBody {
+    heating_degree_hours: f64,
+    cooling_degree_hours: f64,
+    mold_risk_hours: f64,
+}
+
+impl From<DailyMetricsStatus> for DailyMetricsBody {
+    fn from(status: DailyMetricsStatus) -> Self {
+        Self {
+            heating_degree_hours: status.heating_degree_hours,
+            cooling_degree_hours: status.cooling_degree_hours,
+            mold_risk_hours: status.mold_risk_hours,
+        }
+    }
+}
+
+/// Body of `GET /api/info`.
+#[derive(Debug, Serialize)]
+struct Info {
+    name: &'static str,
+    version: &'static str,
+    /// `null` until start-up completes.
+    startup: Option<StartupReport>,
+    /// Rows whose timestamp went backwards, `null` without a database.
+    timestamps: Option<TimestampCounts>,
+    /// Free space, `null` unless the database is a SQLite file.
+    disk: Option<DiskStatus>,
+    /// Whether mains power is lost, `null` without a UPS signal.
+    on_battery: Option<bool>,
+    /// Lease of the display, `null` when it is free.
+    display_lease: Option<LeaseStatus>,
+}
+
+/// Query of `PUT /api/sensor/config`.
+#[derive(Debug, Default, Deserialize)]
+struct PutQuery {
+    #[serde(default)]
+    persist: bool,
+}
+
+/// Query of `POST /api/maintenance`.
+#[derive(Debug, Deserialize)]
+struct MaintenanceQuery {
+    state: MaintenanceState,
+}
+
+/// Body of `POST /api/maintenance`.
+#[derive(Debug, Serialize)]
+struct MaintenanceBody {
+    requested: MaintenanceState,
+    applied: MaintenanceState,
+}
+
+/// Body of `GET /healthz`.
+#[derive(Debug, Serialize)]
+struct Health {
+    /// "ok", or "degraded" while running without an optional subsystem.
+    status: &'static str,
+    degraded: Vec<Subsystem>,
+    /// Applied maintenance state.
+    maintenance: MaintenanceState,
+}
+
+/// Query of `POST /api/qnh`.
+#[derive(Debug, Deserialize)]
+struct QnhQuery {
+    hpa: f64,
+}
+
+/// Body of `POST /api/qnh` and `GET /api/qnh`.
+#[derive(Debug, Serialize)]
+struct QnhBody {
+    /// `null` until set.
+    qnh_hpa: Option<f64>,
+    /// RFC 3339 time it was set.
+    set_at: Option<String>,
+}
+
+impl QnhBody {
+    fn new(setting: Option<QnhSetting>) -> Self {
+        Self {
+            qnh_hpa: setting.map(|setting| setting.hpa),
+            set_at: setting.map(|setting| setting.set_at.to_rfc3339()),
+        }
+    }
+}
+
+/// Body of `GET /api/extremes` and `POST /api/extremes/reset`.
+#[derive(Debug, Serialize)]
+struct ExtremesBody {
+    /// `null` until a reading after the reset.
+    max_temperature_c: Option<f64>,
+    min_humidity_relative: Option<f64>,
+    /// RFC 3339 time of the last reset.
+    since: String,
+}
+
+impl From<Extremes> for ExtremesBody {
+    fn from(extremes: Extremes) -> Self {
+        Self {
+            max_temperature_c: extremes.max_temperature_c,
+            min_humidity_relative: extremes.min_humidity_relative,
+            since: extremes.since.to_rfc3339(),
+        }
+    }
+}
+
+/// Body of `POST /api/capture` and `GET /api/capture`.
+#[derive(Debug, Serialize)]
+struct CaptureBody {
+    /// `null` until the measurement loop starts the capture.
+    active: Option<CaptureStatus>,
+}
+
+/// Body of `POST /api/debug/trace` and `GET /api/debug/trace`.
+#[derive(Debug, Serialize)]
+struct TraceBody {
+    /// `null` when no trace runs.
+    active: Option<TraceStatus>,
+}
+
+/// Body of `GET /api/safemode` and `POST /api/safemode/clear`.
+#[derive(Debug, Serialize)]
+struct SafeModeBody {
+    /// `null` in normal mode.
+    active: Option<SafeModeStatus>,
+}
+
+/// Query of `PUT /api/display/lease`.
+#[derive(Debug, Deserialize)]
+struct RenewQuery {
+    lease_id: u64,
+    duration_secs: u64,
+}
+
+/// Query of `DELETE /api/display/lease`.
+#[derive(Debug, Deserialize)]
+struct ReleaseQuery {
+    lease_id: u64,
+}
+
+/// Body of `GET /api/display/lease`.
+#[derive(Debug, Serialize)]
+struct LeaseBody {
+    /// `null` when the display is free.
+    active: Option<LeaseStatus>,
+}
+
+/// Error body.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    errors: Vec<String>,
+}
+
+/// Build the API router.
+/// # Arguments
+/// * `state` - API state.
+/// # Returns
+/// * Router
+pub fn router(state: Arc<ApiState>) -> Router {
+    Router::new()
+        .route("/api/current", get(get_current))
+        .route("/api/info", get(get_info))
+        .route(
+            "/api/sensor/config",
+            get(get_sensor_config).put(put_sensor_config),
+        )
+        .route("/api/maintenance", post(post_maintenance))
+        .route("/api/capture", get(get_capture).post(post_capture))
+        .route(
+            "/api/display/lease",
+            get(get_lease)
+                .post(post_lease)
+                .put(put_lease)
+                .delete(delete_lease),
+        )
+        .route("/api/qnh", get(get_qnh).post(post_qnh))
+        .route("/api/extremes", get(get_extremes))
+        .route("/api/extremes/reset", post(post_extremes_reset))
+        .route("/api/debug/trace", get(get_trace).post(post_trace))
+        .route("/api/safemode", get(get_safe_mode))
+        .route("/api/safemode/clear", post(post_safe_mode_clear))
+        .route("/healthz", get(get_health))
+        .route("/api/openapi.json", get(get_openapi))
+        .route("/api/schema", get(get_schema))
+        .layer(middleware::map_response_with_state(
+            state.clone(),
+            close_on_battery,
+        ))
+        .with_state(state)
+}
+
+/// Build the router of the kiosk listener, read-only and without the
+/// control routes.
+/// # Arguments
+/// * `state` - API state, shared with the full API.
+/// # Returns
+/// * Router
+pub fn kiosk_router(state: Arc<ApiState>) -> Router {
+    Router::new()
+        .route("/api/current", get(get_current))
+        .route("/healthz", get(get_health))
+        .layer(middleware::map_response_with_state(
+            state.clone(),
+            close_on_battery,
+        ))
+        .with_state(state)
+}
+
+/// Bind the listen address and serve the API in the background.
+/// # Arguments
+/// * `listen` - Listen address, e.g. "127.0.0.1:8080".
+/// * `state` - API state.
+/// * `supervisor` - Supervisor of the server task.
+/// # Returns
+/// * `Err(e)` if the address could not be bound.
+pub async fn serve(
+    listen: &str,
+    state: Arc<ApiState>,
+    supervisor: &mut Supervisor,
+) -> Result<(), HttpError> {
+    spawn_server("http", listen, router(state), supervisor).await
+}
+
+/// Bind the kiosk address and serve the kiosk routes in the background.
+/// # Arguments
+/// * `listen` - Listen address, e.g. "0.0.0.0:8081".
+/// * `state` - API state, shared with the full API.
+/// * `supervisor` - Supervisor of the server task.
+/// # Returns
+/// * `Err(e)` if the address could not be bound.
+pub async fn serve_kiosk(
+    listen: &str,
+    state: Arc<ApiState>,
+    supervisor: &mut Supervisor,
+) -> Result<(), HttpError> {
+    spawn_server("http kiosk", listen, kiosk_router(state), supervisor).await
+}
+
+/// Bind an address and serve a router on it as a supervised task.
+/// # Arguments
+/// * `name` - Name of the task.
+/// * `listen` - Listen address.
+/// * `router` - Routes to serve.
+/// * `supervisor` - Supervisor of the server task.
+/// # Returns
+/// * `Err(e)` if the address could not be bound.
+async fn spawn_server(
+    name: &'static str,
+    listen: &str,
+    router: Router,
+    supervisor: &mut Supervisor,
+) -> Result<(), HttpError> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|source| HttpError::Bind {
+            listen: listen.to_string(),
+            source,
+        })?;
+    println!("HTTP API ({}) listening on {}", name, listen);
+    supervisor.spawn(name, async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            eprintln!("HTTP API ({}) stopped: {}", name, e);
+        }
+    });
+    Ok(())
+}
+
+/// Ask the client to close the connection while on battery.
+async fn close_on_battery(State(state): State<Arc<ApiState>>, mut response: Response) -> Response {
+    if state.is_on_battery() {
+        response.headers_mut().insert(
+            header::CONNECTION,
+            header::HeaderValue::from_static("close"),
+        );
+    }
+    response
+}
+
+async fn get_info(State(state): State<Arc<ApiState>>) -> Json<Info> {
+    let startup = state
+        .startup
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Json(Info {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        startup,
+        timestamps: state.timestamp_stats.as_ref().map(|stats| stats.counts()),
+        disk: state.disk.as_ref().map(|stats| stats.status()),
+        on_battery: state.power.as_ref().map(|power| power.is_on_battery()),
+        display_lease: state.display_lease.status(Instant::now()),
+    })
+}
+
+async fn get_current(State(state): State<Arc<ApiState>>) -> Response {
+    let current = state
+        .current
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    match current {
+        Some(current) => Json(CurrentBody {
+            current,
+            daily_metrics: state
+                .daily_metrics
+                .as_ref()
+                .map(|daily_metrics| daily_metrics.status().into()),
+        })
+        .into_response(),
+        None => error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            vec!["No reading yet".to_string()],
+        ),
+    }
+}
+
+async fn get_sensor_config(State(state): State<Arc<ApiState>>) -> Json<SensorConfig> {
+    Json(state.sensor.borrow().clone())
+}
+
+async fn put_sensor_config(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<PutQuery>,
+    Json(sensor): Json<SensorConfig>,
+) -> Response {
+    if let Err(errors) = sensor.validate() {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, errors);
+    }
+
+    let _guard = state.update_lock.lock().await;
+    if query.persist {
+        let Some(path) = &state.config_path else {
+            return error_response(
+                StatusCode::CONFLICT,
+                vec!["No config file was loaded, nothing to persist to".to_string()],
+            );
+        };
+        if let Err(e) = Config::save_sensor_section(path, &sensor) {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                vec![format!("Failed to persist sensor config: {}", e)],
+            );
+        }
+    }
+    let previous = state.sensor.send_replace(sensor.clone());
+    println!(
+        "Sensor config updated over HTTP{}: {:?} -> {:?}",
+        if query.persist { " (persisted)" } else { "" },
+        previous,
+        sensor
+    );
+
+    Json(sensor).into_response()
+}
+
+async fn post_maintenance(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<MaintenanceQuery>,
+) -> (StatusCode, Json<MaintenanceBody>) {
+    state.maintenance.request(query.state);
+    println!("Maintenance mode {:?} requested over HTTP", query.state);
+    (
+        StatusCode::ACCEPTED,
+        Json(MaintenanceBody {
+            requested: query.state,
+            applied: state.maintenance.applied(),
+        }),
+    )
+}
+
+async fn post_capture(
+    State(state): State<Arc<ApiState>>,
+    Query(request): Query<CaptureRequest>,
+) -> Response {
+    if let Err(e) = request.validate() {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, vec![e]);
+    }
+    if let Err(e) = state.capture.request(request) {
+        return error_response(StatusCode::CONFLICT, vec![e]);
+    }
+    println!(
+        "Capture at {} ms for {} s requested over HTTP",
+        request.rate_ms, request.duration_secs
+    );
+    (
+        StatusCode::ACCEPTED,
+        Json(CaptureBody {
+            active: state.capture.status(),
+        }),
+    )
+        .into_response()
+}
+
+async fn get_capture(State(state): State<Arc<ApiState>>) -> Json<CaptureBody> {
+    Json(CaptureBody {
+        active: state.capture.status(),
+    })
+}
+
+async fn post_lease(
+    State(state): State<Arc<ApiState>>,
+    Query(request): Query<LeaseRequest>,
+) -> Response {
+    if let Err(e) = request.validate() {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, vec![e]);
+    }
+    match state
+        .display_lease
+        .grant(&request, Local::now(), Instant::now())
+    {
+        Ok(lease) => {
+            println!(
+                "Display leased to {} for {} s over HTTP",
+                lease.holder, request.duration_secs
+            );
+            (StatusCode::CREATED, Json(lease)).into_response()
+        }
+        Err(e) => error_response(StatusCode::CONFLICT, vec![e]),
+    }
+}
+
+async fn put_lease(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<RenewQuery>,
+) -> Response {
+    if let Err(e) = lease::validate_duration(query.duration_secs) {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, vec![e]);
+    }
+    let renewed = state.display_lease.renew(
+        query.lease_id,
+        query.duration_secs,
+        Local::now(),
+        Instant::now(),
+    );
+    match renewed {
+        Ok(lease) => Json(lease).into_response(),
+        Err(e) => error_response(StatusCode::CONFLICT, vec![e]),
+    }
+}
+
+async fn delete_lease(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ReleaseQuery>,
+) -> Response {
+    match state.display_lease.release(query.lease_id, Instant::now()) {
+        Ok(lease) => {
+            println!("Display lease of {} released over HTTP", lease.holder);
+            Json(lease).into_response()
+        }
+        Err(e) => error_response(StatusCode::CONFLICT, vec![e]),
+    }
+}
+
+async fn get_lease(State(state): State<Arc<ApiState>>) -> Json<LeaseBody> {
+    Json(LeaseBody {
+        active: state.display_lease.status(Instant::now()),
+    })
+}
+
+async fn get_qnh(State(state): State<Arc<ApiState>>) -> Response {
+    match &state.qnh {
+        Some(qnh) => Json(QnhBody::new(qnh.setting())).into_response(),
+        None => qnh_disabled(),
+    }
+}
+
+async fn post_qnh(State(state): State<Arc<ApiState>>, Query(query): Query<QnhQuery>) -> Response {
+    let Some(qnh) = &state.qnh else {
+        return qnh_disabled();
+    };
+    if let Err(e) = config::validate_qnh(query.hpa) {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, vec![e]);
+    }
+    match qnh.set(query.hpa, Local::now()) {
+        Ok(setting) => {
+            println!("QNH set to {} hPa over HTTP", setting.hpa);
+            Json(QnhBody::new(Some(setting))).into_response()
+        }
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            vec![format!("Failed to save the QNH: {}", e)],
+        ),
+    }
+}
+
+async fn post_trace(
+    State(state): State<Arc<ApiState>>,
+    Query(request): Query<TraceRequest>,
+) -> Response {
+    let Some(trace) = &state.trace else {
+        return trace_disabled();
+    };
+    match trace.start(request, Local::now(), Instant::now()) {
+        Ok(status) => {
+            println!(
+                "Trace for {} s into {} requested over HTTP",
+                status.secs, status.path
+            );
+            (
+                StatusCode::ACCEPTED,
+                Json(TraceBody {
+                    active: Some(status),
+                }),
+            )
+                .into_response()
+        }
+        Err(e @ TraceError::OutOfRange { .. }) => {
+            error_response(StatusCode::UNPROCESSABLE_ENTITY, vec![e.to_string()])
+        }
+        Err(e @ TraceError::Running(_)) => {
+            error_response(StatusCode::CONFLICT, vec![e.to_string()])
+        }
+        Err(e @ TraceError::Create { .. }) => {
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, vec![e.to_string()])
+        }
+    }
+}
+
+async fn get_trace(State(state): State<Arc<ApiState>>) -> Response {
+    match &state.trace {
+        Some(trace) => Json(TraceBody {
+            active: trace.status(),
+        })
+        .into_response(),
+        None => trace_disabled(),
+    }
+}
+
+fn trace_disabled() -> Response {
+    error_response(
+        StatusCode::CONFLICT,
+        vec!["The debug trace is not available".to_string()],
+    )
+}
+
+async fn get_safe_mode(State(state): State<Arc<ApiState>>) -> Response {
+    Json(SafeModeBody {
+        active: state.safe_mode.as_ref().map(|safe_mode| safe_mode.status()),
+    })
+    .into_response()
+}
+
+async fn post_safe_mode_clear(State(state): State<Arc<ApiState>>) -> Response {
+    let Some(safe_mode) = &state.safe_mode else {
+        return error_response(StatusCode::CONFLICT, vec!["Not in safe mode".to_string()]);
+    };
+    safe_mode.request_clear();
+    println!("Safe mode clear requested over HTTP");
+    (
+        StatusCode::ACCEPTED,
+        Json(SafeModeBody {
+            active: Some(safe_mode.status()),
+        }),
+    )
+        .into_response()
+}
+
+fn qnh_disabled() -> Response {
+    error_response(
+        StatusCode::CONFLICT,
+        vec!["The altimeter page is not enabled".to_string()],
+    )
+}
+
+async fn get_extremes(State(state): State<Arc<ApiState>>) -> Response {
+    match &state.extremes {
+        Some(extremes) => Json(ExtremesBody::from(extremes.extremes())).into_response(),
+        None => extremes_disabled(),
+    }
+}
+
+async fn post_extremes_reset(State(state): State<Arc<ApiState>>) -> Response {
+    let Some(extremes) = &state.extremes else {
+        return extremes_disabled();
+    };
+    match extremes.reset(Local::now()) {
+        Ok(extremes) => {
+            println!("Extremes reset over HTTP");
+            Json(ExtremesBody::from(extremes)).into_response()
+        }
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            vec![format!("Failed to save the extremes reset: {}", e)],
+        ),
+    }
+}
+
+fn extremes_disabled() -> Response {
+    error_response(
+        StatusCode::CONFLICT,
+        vec!["The extremes page is not enabled".to_string()],
+    )
+}
+
+async fn get_health(State(state): State<Arc<ApiState>>) -> Json<Health> {
+    let degraded = state
+        .degraded
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Json(Health {
+        status: if degraded.is_empty() {
+            "ok"
+        } else {
+            "degraded"
+        },
+        degraded,
+        maintenance: state.maintenance.applied(),
+    })
+}
+
+async fn get_openapi() -> Json<serde_json::Value> {
+    Json(openapi::document())
+}
+
+async fn get_schema(State(state): State<Arc<ApiState>>) -> Json<serde_json::Value> {
+    Json(openapi::sensor_data_schema(&state.produced))
+}
+
+fn error_response(status: StatusCode, errors: Vec<String>) -> Response {
+    (status, Json(ErrorBody { errors })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn state() -> (Arc<ApiState>, watch::Receiver<SensorConfig>) {
+        let (sender, receiver) = watch::channel(SensorConfig::default());
+        let maintenance = Arc::new(Maintenance::new());
+        let capture = Arc::new(CaptureControl::default());
+        let display_lease = Arc::new(LeaseControl::default());
+        (
+            Arc::new(ApiState::new(
+                sender,
+                None,
+                maintenance,
+                capture,
+                display_lease,
+            )),
+            receiver,
+        )
+    }
+
+    fn put(uri: &str, body: &str) -> Request<Body> {
+        Request::put(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_info() {
+        let (state, _receiver) = state();
+        let get_info = || Request::get("/api/info").body(Body::empty()).unwrap();
+
+        let json = body_json(router(state.clone()).oneshot(get_info()).await.unwrap()).await;
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["startup"].is_null());
+        assert!(json["timestamps"].is_null());
+        assert!(json["disk"].is_null());
+        assert!(json["on_battery"].is_null());
+
+        let mut report = StartupReport {
+            phases: vec![crate::startup::PhaseTiming {
+                name: "sensor_init",
+                ms: 42,
+            }],
+            total_ms: 50,
+            first_sample_ms: None,
+        };
+        state.set_startup(report.clone());
+        report.first_sample_ms = Some(250);
+        state.set_startup(report);
+
+        let json = body_json(router(state.clone()).oneshot(get_info()).await.unwrap()).await;
+        assert_eq!(json["startup"]["phases"][0]["name"], "sensor_init");
+        assert_eq!(json["startup"]["phases"][0]["ms"], 42);
+        assert_eq!(json["startup"]["total_ms"], 50);
+        assert_eq!(json["startup"]["first_sample_ms"], 250);
+    }
+
+    #[tokio::test]
+    async fn test_get_info_timestamps() {
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let maintenance = Arc::new(Maintenance::new());
+        let state = ApiState::new(sender, None, maintenance, Arc::default(), Arc::default())
+            .with_timestamp_stats(Arc::new(TimestampStats::default()))
+            .with_disk(Arc::new(DiskStats::default()));
+        let response = router(Arc::new(state))
+            .oneshot(Request::get("/api/info").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let json = body_json(response).await;
+        assert_eq!(json["timestamps"]["clamped"], 0);
+        assert_eq!(json["timestamps"]["dropped"], 0);
+        assert_eq!(json["disk"]["level"], "ok");
+        assert_eq!(json["disk"]["dropped_rows"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed_on_battery() {
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let maintenance = Arc::new(Maintenance::new());
+        let mut wind_down = crate::power::WindDown::new(None);
+        let state = Arc::new(
+            ApiState::new(sender, None, maintenance, Arc::default(), Arc::default())
+                .with_power(wind_down.stats()),
+        );
+        let get_info = || Request::get("/api/info").body(Body::empty()).unwrap();
+
+        let response = router(state.clone()).oneshot(get_info()).await.unwrap();
+        assert!(response.headers().get(header::CONNECTION).is_none());
+        assert_eq!(body_json(response).await["on_battery"], false);
+
+        wind_down.update(true, false, std::time::Instant::now());
+        let response = router(state.clone()).oneshot(get_info()).await.unwrap();
+        assert_eq!(response.headers()[header::CONNECTION], "close");
+        assert_eq!(body_json(response).await["on_battery"], true);
+    }
+
+    #[tokio::test]
+    async fn test_documented_routes_are_served() {
+        let (state, _receiver) = state();
+        let document = openapi::document();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                let request = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(path)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = router(state.clone()).oneshot(request).await.unwrap();
+                assert!(
+                    ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED]
+                        .contains(&response.status()),
+                    "{} {} answered {}",
+                    method,
+                    path,
+                    response.status()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_schema() {
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let maintenance = Arc::new(Maintenance::new());
+        let produced = ProducedFields {
+            alert_active: true,
+            ..ProducedFields::default()
+        };
+        let state = ApiState::new(sender, None, maintenance, Arc::default(), Arc::default())
+            .with_produced_fields(produced);
+        let response = router(Arc::new(state))
+            .oneshot(Request::get("/api/schema").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let json = body_json(response).await;
+        assert_eq!(json["properties"]["alert_active"]["x-produced"], true);
+        assert_eq!(json["properties"]["capture_id"]["x-produced"], false);
+    }
+
+    #[tokio::test]
+    async fn test_get_current() {
+        let (state, _receiver) = state();
+        let get_current = || Request::get("/api/current").body(Body::empty()).unwrap();
+
+        let response = router(state.clone()).oneshot(get_current()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state.set_current(Current {
+            timestamp: "2025-06-16T14:30:45+09:00".to_string(),
+            sensor: "bme280".to_string(),
+            temperature_c: 24.1,
+            humidity_relative: 55.0,
+            pressure_pa: 101325.0,
+            altitude_m: 0.0,
+            dew_point_c: 14.5,
+            absolute_humidity_g_m3: 12.0,
+            thi: 72.0,
+            source: "live".to_string(),
+        });
+        let response = router(state.clone()).oneshot(get_current()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = body_json(response).await;
+        // What a peer accepts
+        assert!(json.get("daily_metrics").is_none());
+        let reading = crate::peers::PeerReading::from_json(json).unwrap();
+        assert_eq!(reading.temperature_c, 24.1);
+    }
+
+    #[tokio::test]
+    async fn test_kiosk_router_serves_only_public_routes() {
+        let (state, _receiver) = state();
+        state.set_current(Current {
+            timestamp: "2025-06-16T14:30:45+09:00".to_string(),
+            sensor: "bme280".to_string(),
+            temperature_c: 24.1,
+            humidity_relative: 55.0,
+            pressure_pa: 101325.0,
+            altitude_m: 0.0,
+            dew_point_c: 14.5,
+            absolute_humidity_g_m3: 12.0,
+            thi: 72.0,
+            source: "live".to_string(),
+        });
+        // Every route of the full API, as documented
+        let document = crate::openapi::document();
+        let mut served = Vec::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                let request = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(path)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = kiosk_router(state.clone()).oneshot(request).await.unwrap();
+                match response.status() {
+                    StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => {}
+                    _ => served.push(format!("{} {}", method, path)),
+                }
+            }
+        }
+        served.sort();
+        assert_eq!(served, vec!["get /api/current", "get /healthz"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_sensor_config() {
+        let (state, _receiver) = state();
+        let response = router(state.clone())
+            .oneshot(
+                Request::get("/api/sensor/config")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = body_json(response).await;
+        assert_eq!(json["oversampling_temperature"], 1);
+        assert_eq!(json["filter"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_put_sensor_config_applies() {
+        let (state, mut receiver) = state();
+        let response = router(state.clone())
+            .oneshot(put(
+                "/api/sensor/config",
+                r#"{"filter": 16, "temperature_offset_c": -0.8}"#,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(receiver.has_changed().unwrap());
+        let sensor = receiver.borrow_and_update().clone();
+        assert_eq!(sensor.filter, 16);
+        assert_eq!(sensor.temperature_offset_c, -0.8);
+    }
+
+    #[tokio::test]
+    async fn test_put_invalid_sensor_config() {
+        let (state, receiver) = state();
+        let response = router(state.clone())
+            .oneshot(put(
+                "/api/sensor/config",
+                r#"{"oversampling_temperature": 0, "filter": 3}"#,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let json = body_json(response).await;
+        assert_eq!(json["errors"].as_array().unwrap().len(), 2);
+        assert!(!receiver.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_persist_without_config_file() {
+        let (state, receiver) = state();
+        let response = router(state.clone())
+            .oneshot(put("/api/sensor/config?persist=true", r#"{"filter": 2}"#))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(!receiver.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_request_and_health() {
+        let (state, _receiver) = state();
+        let health = || Request::get("/healthz").body(Body::empty()).unwrap();
+        let json = body_json(router(state.clone()).oneshot(health()).await.unwrap()).await;
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["maintenance"], "normal");
+
+        let response = router(state.clone())
+            .oneshot(
+                Request::post("/api/maintenance?state=readonly")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let json = body_json(response).await;
+        assert_eq!(json["requested"], "readonly");
+        assert_eq!(json["applied"], "normal");
+        assert_eq!(state.maintenance.requested(), MaintenanceState::Readonly);
+
+        // Reported once the measurement loop has applied it
+        state.maintenance.set_applied(MaintenanceState::Readonly);
+        let json = body_json(router(state.clone()).oneshot(health()).await.unwrap()).await;
+        assert_eq!(json["maintenance"], "readonly");
+    }
+
+    #[tokio::test]
+    async fn test_post_qnh() {
+        let (state, _receiver) = state();
+        let post = |uri: &str| Request::post(uri).body(Body::empty()).unwrap();
+        let response = router(state)
+            .oneshot(post("/api/qnh?hpa=1018"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-http-qnh-{}.json", std::process::id()));
+        let altimeter = crate::config::AltimeterConfig {
+            enabled: true,
+            state_file: path.display().to_string(),
+            ..Default::default()
+        };
+        let qnh = Arc::new(QnhStore::load(&altimeter, Local::now()));
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let state = Arc::new(
+            ApiState::new(
+                sender,
+                None,
+                Arc::new(Maintenance::new()),
+                Arc::new(CaptureControl::default()),
+                Arc::default(),
+            )
+            .with_qnh(qnh.clone()),
+        );
+        let get = || Request::get("/api/qnh").body(Body::empty()).unwrap();
+        let json = body_json(router(state.clone()).oneshot(get()).await.unwrap()).await;
+        assert_eq!(json["qnh_hpa"], serde_json::Value::Null);
+
+        let response = router(state.clone())
+            .oneshot(post("/api/qnh?hpa=10180"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = router(state.clone())
+            .oneshot(post("/api/qnh?hpa=1018.5"))
+            .await
+            .unwrap();
+        let saved = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["qnh_hpa"], 1018.5);
+        assert!(saved.unwrap().contains("1018.5"));
+        assert_eq!(qnh.setting().unwrap().hpa, 1018.5);
+        let json = body_json(router(state).oneshot(get()).await.unwrap()).await;
+        assert_eq!(json["qnh_hpa"], 1018.5);
+    }
+
+    #[tokio::test]
+    async fn test_extremes_reset() {
+        let (state, _receiver) = state();
+        let reset = || {
+            Request::post("/api/extremes/reset")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router(state).oneshot(reset()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let path = std::env::temp_dir().join(format!(
+            "wbroker-rs-http-extremes-{}.json",
+            std::process::id()
+        ));
+        let config = crate::config::ExtremesConfig {
+            enabled: true,
+            state_file: path.display().to_string(),
+        };
+        let extremes = Arc::new(ExtremesStore::load(&config, Local::now()));
+        extremes.update(
+            &peripheral::bme280::Measurement {
+                temperature_c: 26.4,
+                humidity_relative: 41.0,
+                pressure_pa: 101_325.0,
+            },
+            std::time::Instant::now(),
+        );
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let state = Arc::new(
+            ApiState::new(
+                sender,
+                None,
+                Arc::new(Maintenance::new()),
+                Arc::new(CaptureControl::default()),
+                Arc::default(),
+            )
+            .with_extremes(extremes.clone()),
+        );
+        let get = || Request::get("/api/extremes").body(Body::empty()).unwrap();
+        let json = body_json(router(state.clone()).oneshot(get()).await.unwrap()).await;
+        assert_eq!(json["max_temperature_c"], 26.4);
+        assert_eq!(json["min_humidity_relative"], 41.0);
+
+        let response = router(state.clone()).oneshot(reset()).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(response).await["max_temperature_c"],
+            serde_json::Value::Null
+        );
+        assert_eq!(extremes.extremes().min_humidity_relative, None);
+    }
+
+    #[tokio::test]
+    async fn test_health_degraded() {
+        let (state, _receiver) = state();
+        state.set_degraded(&[Subsystem::Database, Subsystem::Button]);
+
+        let request = Request::get("/healthz").body(Body::empty()).unwrap();
+        let json = body_json(router(state).oneshot(request).await.unwrap()).await;
+
+        assert_eq!(json["status"], "degraded");
+        assert_eq!(json["degraded"], serde_json::json!(["database", "button"]));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_invalid_state() {
+        let (state, _receiver) = state();
+        let response = router(state.clone())
+            .oneshot(
+                Request::post("/api/maintenance?state=offline")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.maintenance.requested(), MaintenanceState::Normal);
+    }
+
+    #[tokio::test]
+    async fn test_capture_one_at_a_time() {
+        let (state, _receiver) = state();
+        let post = |uri: &str| Request::post(uri).body(Body::empty()).unwrap();
+
+        let response = router(state.clone())
+            .oneshot(post("/api/capture?rate_ms=100&duration_secs=600"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(body_json(response).await["active"].is_null());
+
+        let response = router(state.clone())
+            .oneshot(post("/api/capture?rate_ms=100&duration_secs=60"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // Reported once the measurement loop has started it
+        let capture = state
+            .capture
+            .start(chrono::Local::now(), std::time::Instant::now())
+            .unwrap();
+        let get = Request::get("/api/capture").body(Body::empty()).unwrap();
+        let json = body_json(router(state.clone()).oneshot(get).await.unwrap()).await;
+        assert_eq!(json["active"]["capture_id"], capture.status.capture_id);
+        assert_eq!(json["active"]["duration_secs"], 600);
+    }
+
+    #[tokio::test]
+    async fn test_capture_invalid_request() {
+        let (state, _receiver) = state();
+        let response = router(state.clone())
+            .oneshot(
+                Request::post("/api/capture?rate_ms=5&duration_secs=600")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            state
+                .capture
+                .request(CaptureRequest {
+                    rate_ms: 100,
+                    duration_secs: 1
+                })
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debug_trace() {
+        let (state, _receiver) = state();
+        let post = |uri: &str| Request::post(uri).body(Body::empty()).unwrap();
+        let response = router(state)
+            .oneshot(post("/api/debug/trace"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let dir =
+            std::env::temp_dir().join(format!("wbroker-rs-http-trace-{}", std::process::id()));
+        let trace = Arc::new(TraceControl::new(&config::TraceConfig {
+            dir: dir.display().to_string(),
+            ..config::TraceConfig::default()
+        }));
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let state = Arc::new(
+            ApiState::new(
+                sender,
+                None,
+                Arc::new(Maintenance::new()),
+                Arc::new(CaptureControl::default()),
+                Arc::default(),
+            )
+            .with_trace(trace.clone()),
+        );
+        let response = router(state.clone())
+            .oneshot(post("/api/debug/trace?secs=3601"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = router(state.clone())
+            .oneshot(post("/api/debug/trace?secs=30"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let json = body_json(response).await;
+        assert_eq!(json["active"]["secs"], 30);
+        assert!(trace.is_active());
+        let response = router(state.clone())
+            .oneshot(post("/api/debug/trace"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let get = Request::get("/api/debug/trace")
+            .body(Body::empty())
+            .unwrap();
+        let active = body_json(router(state).oneshot(get).await.unwrap()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(active["active"]["path"], json["active"]["path"]);
+    }
+
+    #[tokio::test]
+    async fn test_safe_mode_clear() {
+        let (state, _receiver) = state();
+        let post = || {
+            Request::post("/api/safemode/clear")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let get = || Request::get("/api/safemode").body(Body::empty()).unwrap();
+        let json = body_json(router(state.clone()).oneshot(get()).await.unwrap()).await;
+        assert!(json["active"].is_null());
+        let response = router(state).oneshot(post()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-http-crashes-{}", std::process::id()));
+        let crash = serde_json::json!({ "at": Local::now().to_rfc3339(), "code": 5 });
+        let crashes = serde_json::json!({ "crashes": [crash, crash, crash, crash] });
+        std::fs::write(&path, crashes.to_string()).unwrap();
+        let config = config::SafeModeConfig {
+            crash_file: path.display().to_string(),
+            ..config::SafeModeConfig::default()
+        };
+        let safe_mode = Arc::new(SafeMode::check(&config, Local::now()).unwrap());
+        let (sender, _receiver) = watch::channel(SensorConfig::default());
+        let state = Arc::new(
+            ApiState::new(
+                sender,
+                None,
+                Arc::new(Maintenance::new()),
+                Arc::new(CaptureControl::default()),
+                Arc::default(),
+            )
+            .with_safe_mode(safe_mode.clone()),
+        );
+        let json = body_json(router(state.clone()).oneshot(get()).await.unwrap()).await;
+        assert_eq!(json["active"]["code"], 5);
+        assert_eq!(json["active"]["crashes"], 4);
+        assert_eq!(safe_mode.exit_reason(false, Local::now()), None);
+        let response = router(state).oneshot(post()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            safe_mode.exit_reason(false, Local::now()),
+            Some(crate::safe_mode::ExitReason::Cleared)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_display_lease_lifecycle() {
+        let (state, _receiver) = state();
+        let send = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router(state.clone())
+            .oneshot(send(
+                "POST",
+                "/api/display/lease?holder=mpd&duration_secs=60",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let lease_id = body_json(response).await["lease_id"].as_u64().unwrap();
+        let response = router(state.clone())
+            .oneshot(send(
+                "POST",
+                "/api/display/lease?holder=other&duration_secs=60",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let info = send("GET", "/api/info");
+        let json = body_json(router(state.clone()).oneshot(info).await.unwrap()).await;
+        assert_eq!(json["display_lease"]["holder"], "mpd");
+
+        let uri = format!("/api/display/lease?lease_id={}&duration_secs=30", lease_id);
+        let response = router(state.clone())
+            .oneshot(send("PUT", &uri))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let uri = format!("/api/display/lease?lease_id={}", lease_id);
+        let response = router(state.clone())
+            .oneshot(send("DELETE", &uri))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router(state.clone())
+            .oneshot(send("DELETE", &uri))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let get = send("GET", "/api/display/lease");
+        let json = body_json(router(state.clone()).oneshot(get).await.unwrap()).await;
+        assert!(json["active"].is_null());
+        let kinds: Vec<&str> = state
+            .display_lease
+            .take_events(Instant::now())
+            .iter()
+            .map(lease::LeaseEvent::kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "display_lease_start",
+                "display_lease_renew",
+                "display_lease_end"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_display_lease_invalid_request() {
+        let (state, _receiver) = state();
+        for (method, uri) in [
+            ("POST", "/api/display/lease?holder=mpd&duration_secs=0"),
+            ("POST", "/api/display/lease?holder=%20&duration_secs=60"),
+            ("PUT", "/api/display/lease?lease_id=1&duration_secs=3600"),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = router(state.clone()).oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "{}",
+                uri
+            );
+        }
+        assert!(!state.display_lease.is_held(Instant::now()));
+    }
+}
@@ -0,0 +1,413 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Display lease for an external client.
+//!
+//! Another program sharing the display, e.g. a music player showing the
+//! current track, takes it over with `POST /api/display/lease` for a
+//! bounded time. The measurement loop keeps measuring and logging but
+//! draws nothing until the lease is released or expires, then redraws the
+//! display from scratch. The holder renews the lease before it expires to
+//! keep it. An active alert with `always_override` is drawn over the lease.
+//!
+//! The grant, renewals and end of each lease are queued here and stored in
+//! the `events` table by the measurement loop.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Longest lease, renewals included one at a time.
+pub const MAX_LEASE_SECS: u64 = 600;
+
+/// Longest name of a lease holder.
+pub const MAX_HOLDER_LEN: usize = 32;
+
+/// Requested lease.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LeaseRequest {
+    /// Name of the client, shown in `/api/info` and the events.
+    pub holder: String,
+    /// Length of the lease in seconds.
+    pub duration_secs: u64,
+}
+
+impl LeaseRequest {
+    /// Check the request.
+    /// # Returns
+    /// * `Err(message)` if the holder or duration is out of range.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.holder.trim().is_empty() || self.holder.chars().count() > MAX_HOLDER_LEN {
+            return Err(format!(
+                "holder must be 1-{} characters, got {:?}",
+                MAX_HOLDER_LEN, self.holder
+            ));
+        }
+        validate_duration(self.duration_secs)
+    }
+}
+
+/// Check the length of a lease or renewal.
+/// # Arguments
+/// * `duration_secs` - Requested length in seconds.
+/// # Returns
+/// * `Err(message)` if out of range.
+pub fn validate_duration(duration_secs: u64) -> Result<(), String> {
+    if !(1..=MAX_LEASE_SECS).contains(&duration_secs) {
+        return Err(format!(
+            "duration_secs must be 1-{}, got {}",
+            MAX_LEASE_SECS, duration_secs
+        ));
+    }
+    Ok(())
+}
+
+/// State of a held lease.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeaseStatus {
+    /// Id the holder renews and releases the lease with.
+    pub lease_id: u64,
+    pub holder: String,
+    pub granted_at: String,
+    /// RFC 3339 time the lease expires unless renewed.
+    pub expires_at: String,
+    /// Whether an `always_override` alert is drawn over the lease.
+    pub overridden: bool,
+}
+
+/// Why a lease ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    Released,
+    Expired,
+}
+
+/// Change of the lease, stored in the `events` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseEvent {
+    Granted(LeaseStatus),
+    Renewed(LeaseStatus),
+    Ended {
+        status: LeaseStatus,
+        reason: EndReason,
+    },
+}
+
+impl LeaseEvent {
+    /// Kind of the event row.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LeaseEvent::Granted(_) => "display_lease_start",
+            LeaseEvent::Renewed(_) => "display_lease_renew",
+            LeaseEvent::Ended { .. } => "display_lease_end",
+        }
+    }
+
+    /// Event data, the lease and why it ended.
+    pub fn detail(&self) -> serde_json::Value {
+        match self {
+            LeaseEvent::Granted(status) | LeaseEvent::Renewed(status) => json!(status),
+            LeaseEvent::Ended { status, reason } => {
+                let mut detail = json!(status);
+                detail["reason"] = json!(reason);
+                detail
+            }
+        }
+    }
+}
+
+/// Held lease.
+#[derive(Debug)]
+struct Lease {
+    status: LeaseStatus,
+    until: Instant,
+}
+
+/// Lease slot shared by the HTTP API and the measurement loop.
+#[derive(Debug, Default)]
+pub struct LeaseControl {
+    slot: Mutex<Slot>,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    lease: Option<Lease>,
+    overridden: bool,
+    /// Id of the last lease granted.
+    last_id: u64,
+    /// Events not stored yet.
+    events: Vec<LeaseEvent>,
+}
+
+impl Slot {
+    /// End the lease if it has run out.
+    fn expire(&mut self, instant: Instant) {
+        if let Some(lease) = self.lease.take_if(|lease| instant >= lease.until) {
+            self.events.push(LeaseEvent::Ended {
+                status: lease.status,
+                reason: EndReason::Expired,
+            });
+        }
+    }
+}
+
+impl LeaseControl {
+    /// Grant a lease unless another one is held.
+    /// # Arguments
+    /// * `request` - Validated request.
+    /// * `now` - Current time.
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * The granted lease.
+    /// * `Err(message)` naming the holder of the current lease.
+    pub fn grant(
+        &self,
+        request: &LeaseRequest,
+        now: DateTime<Local>,
+        instant: Instant,
+    ) -> Result<LeaseStatus, String> {
+        let mut slot = self.lock();
+        slot.expire(instant);
+        if let Some(lease) = &slot.lease {
+            return Err(format!(
+                "The display is leased to {} until {}",
+                lease.status.holder, lease.status.expires_at
+            ));
+        }
+        slot.last_id += 1;
+        let duration = Duration::from_secs(request.duration_secs);
+        let status = LeaseStatus {
+            lease_id: slot.last_id,
+            holder: request.holder.clone(),
+            granted_at: now.to_rfc3339(),
+            expires_at: expires_at(now, duration),
+            overridden: slot.overridden,
+        };
+        slot.lease = Some(Lease {
+            status: status.clone(),
+            until: instant + duration,
+        });
+        slot.events.push(LeaseEvent::Granted(status.clone()));
+        Ok(status)
+    }
+
+    /// Extend a lease to a new duration from now.
+    /// # Arguments
+    /// * `lease_id` - Id of the lease.
+    /// * `duration_secs` - Validated duration.
+    /// * `now` - Current time.
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * The renewed lease.
+    /// * `Err(message)` if the lease is not held, e.g. after it expired.
+    pub fn renew(
+        &self,
+        lease_id: u64,
+        duration_secs: u64,
+        now: DateTime<Local>,
+        instant: Instant,
+    ) -> Result<LeaseStatus, String> {
+        let mut slot = self.lock();
+        slot.expire(instant);
+        let lease = slot
+            .lease
+            .as_mut()
+            .filter(|lease| lease.status.lease_id == lease_id)
+            .ok_or_else(|| not_held(lease_id))?;
+        let duration = Duration::from_secs(duration_secs);
+        lease.until = instant + duration;
+        lease.status.expires_at = expires_at(now, duration);
+        let status = lease.status.clone();
+        slot.events.push(LeaseEvent::Renewed(status.clone()));
+        Ok(status)
+    }
+
+    /// Give the display back.
+    /// # Arguments
+    /// * `lease_id` - Id of the lease.
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * The released lease.
+    /// * `Err(message)` if the lease is not held.
+    pub fn release(&self, lease_id: u64, instant: Instant) -> Result<LeaseStatus, String> {
+        let mut slot = self.lock();
+        slot.expire(instant);
+        let status = slot
+            .lease
+            .take_if(|lease| lease.status.lease_id == lease_id)
+            .ok_or_else(|| not_held(lease_id))?
+            .status;
+        slot.events.push(LeaseEvent::Ended {
+            status: status.clone(),
+            reason: EndReason::Released,
+        });
+        Ok(status)
+    }
+
+    /// Held lease, `None` when the display is free.
+    /// # Arguments
+    /// * `instant` - Current monotonic time.
+    pub fn status(&self, instant: Instant) -> Option<LeaseStatus> {
+        let mut slot = self.lock();
+        slot.expire(instant);
+        slot.lease.as_ref().map(|lease| lease.status.clone())
+    }
+
+    /// Whether a lease is held.
+    /// # Arguments
+    /// * `instant` - Current monotonic time.
+    pub fn is_held(&self, instant: Instant) -> bool {
+        self.status(instant).is_some()
+    }
+
+    /// Publish whether an alert is drawn over the lease.
+    /// # Arguments
+    /// * `overridden` - Whether an `always_override` alert is active.
+    pub fn set_overridden(&self, overridden: bool) {
+        let mut slot = self.lock();
+        slot.overridden = overridden;
+        if let Some(lease) = &mut slot.lease {
+            lease.status.overridden = overridden;
+        }
+    }
+
+    /// Take the events to store, ending the lease first if it ran out.
+    /// # Arguments
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * Events in the order they happened.
+    pub fn take_events(&self, instant: Instant) -> Vec<LeaseEvent> {
+        let mut slot = self.lock();
+        slot.expire(instant);
+        std::mem::take(&mut slot.events)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Slot> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn not_held(lease_id: u64) -> String {
+    format!("Lease {} is not held", lease_id)
+}
+
+/// RFC 3339 time a lease of the duration expires.
+fn expires_at(now: DateTime<Local>, duration: Duration) -> String {
+    let duration = chrono::Duration::from_std(duration).unwrap_or_default();
+    (now + duration).to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(holder: &str) -> LeaseRequest {
+        LeaseRequest {
+            holder: holder.to_string(),
+            duration_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_validate_request() {
+        assert!(request("mpd").validate().is_ok());
+        assert!(request(" ").validate().is_err());
+        assert!(request(&"x".repeat(MAX_HOLDER_LEN + 1)).validate().is_err());
+        for duration_secs in [0, MAX_LEASE_SECS + 1] {
+            let request = LeaseRequest {
+                duration_secs,
+                ..request("mpd")
+            };
+            assert!(request.validate().is_err(), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn test_one_lease_at_a_time() {
+        let control = LeaseControl::default();
+        let now = Local::now();
+        let instant = Instant::now();
+
+        let lease = control.grant(&request("mpd"), now, instant).unwrap();
+        assert_eq!(lease.lease_id, 1);
+        let error = control.grant(&request("other"), now, instant).unwrap_err();
+        assert!(error.contains("leased to mpd"), "{}", error);
+        // Only the holder's id releases it
+        assert!(control.release(2, instant).is_err());
+        control.release(1, instant).unwrap();
+        assert!(!control.is_held(instant));
+
+        let lease = control.grant(&request("other"), now, instant).unwrap();
+        assert_eq!(lease.lease_id, 2);
+        assert!(control.release(1, instant).is_err());
+    }
+
+    #[test]
+    fn test_lease_expires_unless_renewed() {
+        let control = LeaseControl::default();
+        let now = Local::now();
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        control.grant(&request("mpd"), now, start).unwrap();
+
+        let renewed = control.renew(1, 60, now, start + 50 * second).unwrap();
+        assert!(renewed.expires_at > renewed.granted_at);
+        assert!(control.is_held(start + 109 * second));
+        assert!(!control.is_held(start + 110 * second));
+        assert!(control.renew(1, 60, now, start + 110 * second).is_err());
+
+        let events = control.take_events(start + 110 * second);
+        let kinds: Vec<&str> = events.iter().map(LeaseEvent::kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                "display_lease_start",
+                "display_lease_renew",
+                "display_lease_end"
+            ]
+        );
+        assert_eq!(events[2].detail()["reason"], "expired");
+        assert_eq!(events[2].detail()["holder"], "mpd");
+        assert!(control.take_events(start + 110 * second).is_empty());
+    }
+
+    #[test]
+    fn test_overridden_is_reported() {
+        let control = LeaseControl::default();
+        let instant = Instant::now();
+        control
+            .grant(&request("mpd"), Local::now(), instant)
+            .unwrap();
+        control.set_overridden(true);
+        assert!(control.status(instant).unwrap().overridden);
+        control.set_overridden(false);
+        assert!(!control.status(instant).unwrap().overridden);
+        control.release(1, instant).unwrap();
+        assert_eq!(
+            control.take_events(instant)[1].detail()["reason"],
+            "released"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Auto-dimming of the display by ambient light.
+//!
+//! With a [light_sensor] section, a BH1750 is read every cycle and the
+//! illuminance mapped to a display contrast, on a log scale between
+//! `dark_lux` and `bright_lux` since the eye perceives brightness that way.
+//! The contrast is only written once it moved by `CONTRAST_STEP`, so sensor
+//! noise does not rewrite it every cycle.
+
+use crate::config::LightSensorConfig;
+
+/// Smallest contrast change written to the display.
+pub const CONTRAST_STEP: u8 = 4;
+
+/// Map an illuminance to a display contrast.
+/// # Arguments
+/// * `lux` - Illuminance.
+/// * `config` - Lux and contrast ranges.
+/// # Returns
+/// * `min_contrast` up to `dark_lux`, `max_contrast` from `bright_lux` on,
+///   logarithmic in between.
+pub fn lux_to_contrast(lux: f64, config: &LightSensorConfig) -> u8 {
+    let (dark, bright) = (config.dark_lux.ln(), config.bright_lux.ln());
+    let position = if lux > config.dark_lux {
+        ((lux.ln() - dark) / (bright - dark)).min(1.0)
+    } else {
+        0.0
+    };
+    let range = f64::from(config.max_contrast - config.min_contrast);
+    config.min_contrast + (range * position).round() as u8
+}
+
+/// Contrast to write to the display for each light reading.
+#[derive(Debug)]
+pub struct AutoDim {
+    config: LightSensorConfig,
+    applied: Option<u8>,
+    failing: bool,
+}
+
+impl AutoDim {
+    /// Create the auto-dimming state. The first reading always sets the
+    /// contrast.
+    /// # Arguments
+    /// * `config` - Light sensor configuration.
+    pub fn new(config: &LightSensorConfig) -> Self {
+        Self {
+            config: config.clone(),
+            applied: None,
+            failing: false,
+        }
+    }
+
+    /// Take a light reading. A failed reading keeps the contrast and is
+    /// logged when the sensor starts and stops failing.
+    /// # Arguments
+    /// * `lux` - Illuminance, or why it could not be read.
+    /// # Returns
+    /// * The contrast to write, `None` to keep the current one.
+    pub fn update(&mut self, lux: Result<f64, String>) -> Option<u8> {
+        let lux = match lux {
+            Ok(lux) if !lux.is_nan() => lux,
+            Ok(_) => return None,
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Failed to read the light sensor: {}", e);
+                    self.failing = true;
+                }
+                return None;
+            }
+        };
+        if self.failing {
+            println!("Light sensor readings resumed.");
+            self.failing = false;
+        }
+        let contrast = lux_to_contrast(lux, &self.config);
+        match self.applied {
+            Some(applied) if applied.abs_diff(contrast) < CONTRAST_STEP => None,
+            _ => {
+                self.applied = Some(contrast);
+                Some(contrast)
+            }
+        }
+    }
+
+    /// The contrast was set by something else, rewrite it on the next reading.
+    pub fn forget(&mut self) {
+        self.applied = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lux_to_contrast() {
+        let config = LightSensorConfig::default();
+        assert_eq!(lux_to_contrast(0.0, &config), config.min_contrast);
+        assert_eq!(lux_to_contrast(1.0, &config), config.min_contrast);
+        assert_eq!(lux_to_contrast(1000.0, &config), config.max_contrast);
+        assert_eq!(lux_to_contrast(54612.5, &config), config.max_contrast);
+        // Halfway on the log scale, between 1 and 1000 lx
+        let halfway = lux_to_contrast(1000f64.sqrt(), &config);
+        assert_eq!(halfway, 0x10 + (f64::from(0xFF - 0x10) / 2.0).round() as u8);
+        let mut previous = 0;
+        for lux in [2.0, 10.0, 50.0, 200.0, 800.0] {
+            let contrast = lux_to_contrast(lux, &config);
+            assert!(contrast > previous, "{} lx", lux);
+            previous = contrast;
+        }
+
+        let fixed = LightSensorConfig {
+            min_contrast: 0x80,
+            max_contrast: 0x80,
+            ..config
+        };
+        assert_eq!(lux_to_contrast(100.0, &fixed), 0x80);
+    }
+
+    #[test]
+    fn test_auto_dim_writes_on_change() {
+        let mut auto_dim = AutoDim::new(&LightSensorConfig::default());
+
+        let first = auto_dim.update(Ok(100.0)).unwrap();
+        // Noise around the same level
+        assert_eq!(auto_dim.update(Ok(101.0)), None);
+        assert_eq!(auto_dim.update(Err("no ack".to_string())), None);
+        assert!(auto_dim.update(Ok(10.0)).unwrap() < first);
+
+        auto_dim.forget();
+        assert!(auto_dim.update(Ok(10.0)).is_some());
+        assert_eq!(auto_dim.update(Ok(f64::NAN)), None);
+    }
+}
@@ -20,18 +20,80 @@
 // SOFTWARE.
 
 use std::error::Error;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Instant;
 
 use chrono::prelude::*;
-use clap::Parser;
-use tokio::time::{Duration, interval};
+use clap::{Parser, Subcommand};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::time::Duration;
 
-use peripheral::bme280;
-use peripheral::so1602a;
+use peripheral::aht20;
+use peripheral::bh1750;
+use peripheral::bme280::{self, OversamplingConfig};
+use peripheral::bus::{I2cDevice, SharedI2c};
+use peripheral::display::CharDisplay;
+use peripheral::sensor::{ConstantSensor, Sensor};
+use peripheral::sht31;
 
+mod actions;
+mod alerts;
+mod altimeter;
+mod capture;
+mod cgram;
 mod config;
+mod config_file;
+mod daily_metrics;
 mod database;
+mod disk;
+mod display;
+mod drift;
+mod error;
+mod exit;
+mod export;
+mod extremes;
+mod helper;
+mod hooks;
+mod http;
+mod lease;
+mod light;
+mod maintenance;
+mod openapi;
+mod page;
+mod peers;
+mod power;
+mod precision;
+mod quality;
+mod recompute;
+mod replay;
+mod safe_mode;
+mod scheduler;
+mod screen;
+mod screensaver;
+mod sensor;
+mod sequence;
+mod simulate;
+mod soak;
+mod startup;
+mod state;
+mod supervisor;
+mod timezone;
+mod trace;
+use actions::SharedActions;
 use config::Config;
-use database::{Database, SensorData};
+use config::SensorType;
+use database::{Database, InsertHook, Source};
+use error::{ConfigError, DisplayError, SensorError, WbrokerError};
+use exit::ExitError;
+use helper::{ClockSync, ClockTransition, ThiCoefficients};
+use hooks::CommandHook;
+use maintenance::{Maintenance, MaintenanceState, ReadonlyPeriod};
+use power::{PowerPins, PowerTransition, WindDown};
+use screensaver::{DoublePress, LongPress, Screensaver, ScreensaverTransition, WakeButton};
+use sensor::{DeviceSensor, EnvSensor, SensorSet};
+use startup::Subsystem;
 
 #[derive(Parser)]
 #[command(name = "wbroker-rs")]
@@ -40,103 +102,1838 @@ struct Args {
     #[arg(short, long, env = "WBROKER_CONFIG", default_value = "config.toml")]
     #[arg(help = "Path to configuration file")]
     config_filepath: String,
+
+    #[arg(long, value_name = "SECS")]
+    #[arg(help = "Exit with code 7 if start-up, up to the first sample, takes longer")]
+    startup_timeout: Option<u64>,
+
+    #[arg(long)]
+    #[arg(help = "Run without the I2C bus: simulated BME280s, display printed to stdout")]
+    simulate: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the pipeline on an accelerated virtual clock and check for leaks
+    Soak {
+        #[arg(long, default_value_t = 24.0, help = "Virtual duration in hours")]
+        hours: f64,
+        #[arg(long, help = "Use simulated hardware (required)")]
+        simulate: bool,
+        #[arg(
+            long,
+            default_value_t = 60.0,
+            help = "Virtual clock acceleration factor"
+        )]
+        speed: f64,
+        #[arg(long, default_value_t = 100, help = "Number of samples over the run")]
+        samples: u64,
+        #[arg(
+            long,
+            default_value_t = 4096,
+            help = "Maximum allowed RSS growth in kB"
+        )]
+        max_rss_growth_kb: u64,
+        #[arg(long, default_value_t = 4, help = "Maximum allowed open fd growth")]
+        max_fd_growth: u64,
+        #[arg(
+            long,
+            default_value_t = 100,
+            help = "Maximum queue depth at the end of the run"
+        )]
+        max_queue_depth: u64,
+        #[arg(long, value_name = "URL")]
+        #[arg(help = "Database to write the rows to (default: a temporary SQLite file)")]
+        database_url: Option<String>,
+    },
+    /// Recompute derived columns of stored rows from the raw measurements
+    Recompute {
+        #[arg(long = "column", required = true, value_name = "NAME")]
+        #[arg(help = "Derived column to recompute, repeatable (thi, dew_point_c)")]
+        columns: Vec<String>,
+        #[arg(long, value_name = "RFC3339", help = "Only rows at or after this time")]
+        from: Option<String>,
+        #[arg(long, value_name = "RFC3339", help = "Only rows before this time")]
+        to: Option<String>,
+        #[arg(long, default_value_t = 500, help = "Rows per transaction")]
+        batch_size: usize,
+        #[arg(long, help = "Count the changes without writing them")]
+        dry_run: bool,
+        #[arg(long, value_name = "PATH")]
+        #[arg(help = "File keeping the last processed id, to resume an interrupted run")]
+        resume_file: Option<std::path::PathBuf>,
+    },
+    /// Write stored rows as CSV, numbers formatted for a spreadsheet locale
+    Export {
+        #[arg(long, value_name = "RFC3339", help = "Only rows at or after this time")]
+        from: Option<String>,
+        #[arg(long, value_name = "RFC3339", help = "Only rows before this time")]
+        to: Option<String>,
+        #[arg(long, value_name = "PATH", help = "Output file instead of stdout")]
+        output: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "NAME")]
+        #[arg(help = "Locale of the decimal separator, e.g. de (default: [export] locale)")]
+        locale: Option<String>,
+        #[arg(long, value_name = "CHAR")]
+        #[arg(help = "Field delimiter, e.g. ; (default: [export] delimiter)")]
+        delimiter: Option<char>,
+        #[arg(long, help = "Separate the integer digits in thousands")]
+        grouping: bool,
+        #[arg(long = "source", value_name = "NAME")]
+        #[arg(help = "Only rows of this source: live, capture, replay or simulated (repeatable)")]
+        sources: Vec<String>,
+    },
+    /// Feed an exported CSV through the display and the alerts on a virtual clock
+    Replay {
+        #[arg(long, value_name = "PATH", help = "CSV file written by export")]
+        input: std::path::PathBuf,
+        #[arg(long, default_value = "60x", value_parser = replay::parse_speed)]
+        #[arg(help = "Virtual clock acceleration factor, e.g. 60x")]
+        speed: f64,
+        #[arg(long, value_name = "SECS")]
+        #[arg(help = "Pace gaps between rows longer than this as if this long")]
+        compress_gaps: Option<u64>,
+        #[arg(long, value_name = "LABEL")]
+        #[arg(help = "Sensor to replay (default: the one of the first row)")]
+        sensor: Option<String>,
+        #[arg(long, value_name = "NAME")]
+        #[arg(help = "Locale of the decimal separator, e.g. de (default: [export] locale)")]
+        locale: Option<String>,
+        #[arg(long, value_name = "CHAR")]
+        #[arg(help = "Field delimiter, e.g. ; (default: [export] delimiter)")]
+        delimiter: Option<char>,
+        #[arg(long, help = "Store the rows, and the alerts with [alerts] history on")]
+        store: bool,
+    },
+    /// Compare the stored readings with reference units and suggest sensor offsets
+    DriftReport {
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Unit of [peers] to poll (repeatable)"
+        )]
+        peer: Vec<String>,
+        #[arg(long, value_name = "PATH")]
+        #[arg(help = "CSV file written by export on a reference unit (repeatable)")]
+        reference: Vec<std::path::PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 60,
+            help = "Length of the compared window in minutes"
+        )]
+        window_mins: u64,
+        #[arg(long, value_name = "LABEL")]
+        #[arg(help = "Sensor of this unit to compare (default: [sensors] label)")]
+        sensor: Option<String>,
+        #[arg(long, default_value_t = 60)]
+        #[arg(help = "Largest time in seconds between paired readings")]
+        max_skew_secs: u64,
+        #[arg(long, value_name = "NAME")]
+        #[arg(help = "Locale of the decimal separator, e.g. de (default: [export] locale)")]
+        locale: Option<String>,
+        #[arg(long, value_name = "CHAR")]
+        #[arg(help = "Field delimiter, e.g. ; (default: [export] delimiter)")]
+        delimiter: Option<char>,
+        #[arg(long, help = "Write the suggested offsets to the [sensor] section")]
+        apply: bool,
+    },
+    /// Ask the running daemon to sample faster for a while, over its HTTP API
+    Capture {
+        #[arg(long, default_value_t = capture::NORMAL_RATE_MS)]
+        #[arg(help = "Measurement period in milliseconds during the capture")]
+        rate_ms: u64,
+        #[arg(long, help = "Length of the capture in seconds")]
+        duration_secs: u64,
+    },
+    /// Print a JSON Schema of the config file, for editor completion
+    #[cfg(feature = "config-schema")]
+    ConfigSchema,
 }
 
 /// Entry point of the program.
 /// This program reads temperature and humidity data from a BME280 sensor
-/// and displays it on a SO1602A LCD. It also shows a custom character
-/// (backslash dot) on the LCD.
+/// and displays it on a SO1602A OLED or a HD44780 LCD. It also shows the custom
+/// characters from the configuration (backslash dot by default) on the LCD.
 /// The program runs indefinitely, updating the display every 200 milliseconds.
 /// # Returns
-/// * `Ok(())` if the program runs successfully.
-/// * `Err(e)` if there is an error during execution.
+/// * Exit code, see the `exit` module.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            // Panics were counted by the panic hook already
+            if !matches!(e, ExitError::Panic { .. }) {
+                safe_mode::record_crash(e.code());
+            }
+            e.exit_code()
+        }
+    }
+}
+
+/// Run a subcommand, or the daemon until it is stopped.
+/// # Returns
+/// * `Ok(())` on a clean shutdown.
+/// * `Err(e)` classifying the failure.
+async fn run() -> Result<(), ExitError> {
+    let mut boot = Boot::new(Instant::now());
+    supervisor::install_panic_hook();
     let args = Args::parse();
-    let (config, config_loaded) = Config::load_or_default_with_status(&args.config_filepath);
+    // Disarmed by the first sample
+    boot.watchdog = args.startup_timeout.map(|secs| {
+        startup::StartupWatchdog::spawn(Duration::from_secs(secs), boot.timer.current_phase())
+    });
+    let (config, config_loaded) =
+        Config::load_or_default_with_status(&args.config_filepath).map_err(ExitError::Config)?;
+    // Before any timestamp is taken, subcommands included. The zone was
+    // checked when loading.
+    let forced_zone = timezone::setup(config.clock.forced_timezone().ok().flatten());
 
-    let so1602a = so1602a::SO1602A::new(so1602a::SO1602A_ADDR)?;
-    let bme280 = bme280::Bme280::new(bme280::BME280_ADDR)?;
+    if let Some(command) = args.command {
+        return run_command(command, &config, config_loaded, &args.config_filepath)
+            .await
+            .map_err(ExitError::from);
+    }
+    if let Some(zone) = forced_zone {
+        println!("Time zone: {}", zone);
+    }
+    // Too many crashes lately, only the clock and the thermometer are run
+    safe_mode::arm(&config.safe_mode);
+    let safe_mode = safe_mode::SafeMode::check(&config.safe_mode, Local::now()).map(Arc::new);
+    if let Some(safe_mode) = &safe_mode {
+        let status = safe_mode.status();
+        eprintln!(
+            "Safe mode after {} crashes, the last with exit code {}: running without database, hooks, webhooks and peers until {}.",
+            status.crashes, status.code, status.until
+        );
+    }
+    let harness = args.simulate.then(|| {
+        println!("Simulating the hardware, the display is printed to stdout");
+        simulate::Harness::new(config.display.geometry())
+    });
+    daemon(
+        &config,
+        config_loaded,
+        &args.config_filepath,
+        boot,
+        safe_mode,
+        harness,
+    )
+    .await
+}
 
-    let database = if config_loaded {
-        Some(
-            Database::new(&config.database.url)
-                .await
-                .map_err(|e| format!("Failed to initialize database: {}", e))?,
-        )
+/// Start-up of the daemon, timed from the start of the process.
+struct Boot {
+    /// Start of the process.
+    at: Instant,
+    /// Duration of each start-up phase.
+    timer: startup::StartupTimer,
+    /// Exits if the first sample is late, `None` without a timeout.
+    watchdog: Option<startup::StartupWatchdog>,
+}
+
+impl Boot {
+    /// Start timing, in the config loading phase.
+    /// # Arguments
+    /// * `at` - Start of the process.
+    fn new(at: Instant) -> Self {
+        Self {
+            at,
+            timer: startup::StartupTimer::start("config_load", at),
+            watchdog: None,
+        }
+    }
+}
+
+/// Set up the devices and the tasks, and measure until stopped.
+/// # Arguments
+/// * `config` - Loaded config, or the defaults.
+/// * `config_loaded` - Whether a config file was loaded.
+/// * `config_filepath` - Path of the config file, for errors and the HTTP API.
+/// * `boot` - Start-up timing and watchdog.
+/// * `safe_mode` - Safe mode, after too many crashes lately.
+/// * `harness` - Simulated hardware, `None` to use the I2C bus.
+/// # Returns
+/// * `Ok(())` on a clean shutdown.
+/// * `Err(e)` classifying the failure.
+async fn daemon(
+    config: &Config,
+    config_loaded: bool,
+    config_filepath: &str,
+    boot: Boot,
+    safe_mode: Option<Arc<safe_mode::SafeMode>>,
+    harness: Option<simulate::Harness>,
+) -> Result<(), ExitError> {
+    let Boot {
+        at: boot,
+        mut timer,
+        mut watchdog,
+    } = boot;
+    // Both devices share one bus so their transfers are not interleaved
+    timer.begin("display_init", Instant::now());
+    // The display comes first so it can show why the other devices failed
+    let (bus, display, harness_sensor, wall_clock, mut on_tick) = match harness {
+        Some(harness) => (
+            None,
+            display::Display::Null(harness.display),
+            harness.sensor,
+            harness.clock,
+            harness.on_tick,
+        ),
+        None => {
+            let bus = SharedI2c::open().map_err(|e| ExitError::Bus(e.to_string()))?;
+            let display = display::Display::from_config(&config.display, &bus);
+            (Some(bus), display, None, simulate::WallClock::System, None)
+        }
+    };
+    let simulated = bus.is_none();
+    if let (Some(bus), Some(_)) = (&bus, config.hardware.i2c_speed_hz) {
+        match bus.clock_speed() {
+            Ok(hz) => {
+                if let Some(warning) = config.hardware.speed_mismatch(hz) {
+                    eprintln!("Warning: {}", warning);
+                }
+            }
+            Err(e) => eprintln!("Warning: could not read the I2C clock speed: {}", e),
+        }
+    }
+    let slots = config.display.cgram_slots().map_err(|message| {
+        ExitError::Config(ConfigError::Invalid {
+            path: config_filepath.to_string(),
+            message,
+        })
+    })?;
+    display::init(&display, slots.chars())
+        .await
+        .map_err(|source| {
+            ExitError::Display(DisplayError::Init {
+                address: config.display.i2c_address(),
+                source,
+            })
+        })?;
+    let recovery = display::DisplayRecovery::new(config.display.write_retries, slots);
+
+    // Failures of the subsystems below stop the program or are carried on
+    // without, as configured in [subsystems]
+    let mut policy = startup::FailurePolicy::new(&config.subsystems);
+    timer.begin("sensor_init", Instant::now());
+    let main_address = match (&bus, config.sensors.driver) {
+        (Some(bus), SensorType::Bme280) if config.sensors.auto_detect => {
+            policy.check(&display, Subsystem::Sensor, bme280_detect(bus))?
+        }
+        _ => Some(sensor_addresses(config.sensors.driver)[0]),
+    };
+    let oversampling = config.sensor.settings().oversampling();
+    // Sensors found without a humidity channel, such as a BMP280, whose
+    // rows are checked without humidity and THI
+    let mut without_humidity = Vec::new();
+    let main_sensor = match (harness_sensor, main_address) {
+        (Some(sensor), _) => Some(sensor),
+        (None, Some(address)) => policy
+            .check(
+                &display,
+                Subsystem::Sensor,
+                sensor_init(bus.as_ref(), config.sensors.driver, address, oversampling),
+            )?
+            .map(|device| -> Box<dyn EnvSensor> {
+                if !device.measures_humidity() {
+                    without_humidity.push(config.sensors.label.clone());
+                }
+                Box::new(DeviceSensor::new(&config.sensors.label, device))
+            }),
+        (None, None) => None,
+    };
+    // Extra sensors default to the second address of their type, or the
+    // first if a main sensor of the same type uses the second
+    let extra_address = |driver: SensorType| {
+        let [first, second] = sensor_addresses(driver);
+        if driver == config.sensors.driver && main_address == Some(second) {
+            first
+        } else {
+            second
+        }
+    };
+    let samples = config.sensors.samples;
+    let mut sensor_list: Vec<Box<dyn EnvSensor>> = Vec::new();
+    // Without the main sensor only the clock and the fault are shown, the
+    // others are only worth logging alongside it
+    if let Some(main_sensor) = main_sensor {
+        let stuck_after = config.sensors.stuck_after;
+        sensor_list.push(sensor::compensated(
+            sensor::sampled(sensor::guarded(main_sensor, stuck_after), samples),
+            config.sensors.self_heating_c(config.sensors.profile),
+        ));
+        for extra in &config.sensors.extra {
+            let device = policy.check(
+                &display,
+                Subsystem::Sensor,
+                sensor_init(
+                    bus.as_ref(),
+                    extra.driver,
+                    extra.address.unwrap_or_else(|| extra_address(extra.driver)),
+                    oversampling,
+                ),
+            )?;
+            if let Some(device) = device {
+                if !device.measures_humidity() {
+                    without_humidity.push(extra.label.clone());
+                }
+                sensor_list.push(sensor::compensated(
+                    sensor::sampled(
+                        sensor::guarded(
+                            Box::new(DeviceSensor::new(&extra.label, device)),
+                            stuck_after,
+                        ),
+                        samples,
+                    ),
+                    config.sensors.self_heating_c(extra.profile),
+                ));
+            }
+        }
+    }
+    let mut sensors = SensorSet::new(sensor_list, &config.quality);
+    policy.check(
+        &display,
+        Subsystem::Sensor,
+        sensors.configure(config.sensor.settings()),
+    )?;
+
+    // Debug trace of every tick, requested over HTTP or by signal
+    let trace = Arc::new(trace::TraceControl::new(&config.trace));
+    let database = if safe_mode.is_some() {
+        None
+    } else if config_loaded {
+        timer.begin("db_connect", Instant::now());
+        let after_insert = match &config.hooks.after_insert {
+            Some(template) => policy.check(
+                &display,
+                Subsystem::Hooks,
+                CommandHook::new("after_insert", template)
+                    .map(|hook| hook.with_fields(&config.hooks.after_insert_fields))
+                    .map_err(|message| ConfigError::Hook {
+                        name: "after_insert",
+                        message,
+                    }),
+            )?,
+            None => None,
+        };
+        let on_insert = after_insert.map(|hook| -> InsertHook {
+            Box::new(move |data| {
+                hook.trigger(data);
+            })
+        });
+        let database = policy.check(
+            &display,
+            Subsystem::Database,
+            Database::connect_with_hook(
+                &config.database,
+                quality::Plausibility::from_config(&config.database.validation, &config.sensors)
+                    .without_humidity(without_humidity),
+                on_insert,
+                Some(trace.clone()),
+            )
+            .await,
+        )?;
+        timer.begin("db_migrations", Instant::now());
+        match database {
+            Some(database) => {
+                let migrated =
+                    policy.check(&display, Subsystem::Database, database.migrate().await)?;
+                migrated.map(|_| database)
+            }
+            None => None,
+        }
     } else {
         println!("No config file found. Running without database logging.");
         None
     };
-    let indicator: [u8; 4] = [0x01, b'|', b'/', b'-'];
-    let mut counter: usize = 0;
-
-    // Custom characters data
-    let char_data: [(u8, [u8; 8]); 1] = [(
-        // Backslash dot data
-        0x01,
-        [
-            0b00000,
-            0b10000,
-            0b01000,
-            0b00100,
-            0b00010,
-            0b00001,
-            0b00000,
-            0b00000,
-        ],
-    )];
-
-    so1602a.setup().await?;
-    for (index, data) in char_data {
-        so1602a.register_char(index, data)?;
-    }
-
-    let mut interval = interval(Duration::from_millis(200));
-
-    loop {
-        interval.tick().await;
-
-        let now = Local::now();
-        let measurement = bme280.make_measurement().await?;
-        let thi = calc_thi(measurement.temperature_c, measurement.humidity_relative);
-
-        so1602a.put_str(
-            so1602a::SO1602A_1ST_LINE,
-            &format!("{}", now.format("%Y/%m/%d %H:%M")),
-        )?;
-        so1602a.put_str(
-            so1602a::SO1602A_2ND_LINE,
-            &format!(
-                "{: >2.1}C {: >3.1}% {: >3.0}",
-                measurement.temperature_c, measurement.humidity_relative, thi,
-            ),
-        )?;
+    record_safe_mode_episode(&database, &config.safe_mode).await;
+    if database.is_some() {
+        // Consumers drop duplicate rows by run id and number
+        println!("Row run id: {}", sequence::this_run().run_id());
+    }
+    // Sensor settings can be re-tuned over HTTP while running
+    let (sensor_tx, mut sensor_rx) = watch::channel(config.sensor.clone());
+    // Read-only maintenance mode, requested over HTTP or by signal
+    let maintenance = Arc::new(Maintenance::new());
+    // Long-running tasks, a panic in one of them stops the daemon
+    let mut supervisor = supervisor::Supervisor::default();
+    spawn_maintenance_signal(maintenance.clone(), &mut supervisor);
+    spawn_trace_signal(trace.clone(), &mut supervisor);
+    // Burst capture, requested over HTTP
+    let capture_control = Arc::new(capture::CaptureControl::default());
+    // Display lease, granted to other programs over HTTP
+    let display_lease = Arc::new(lease::LeaseControl::default());
+    // Writes pause while the SQLite filesystem is nearly full
+    let mut disk_monitor = database.as_ref().and_then(|_| {
+        disk::DiskMonitor::for_database(&config.database.url, &config.disk, Instant::now())
+    });
+    // QNH of the altimeter page, set in the config or over HTTP
+    let qnh = config
+        .altimeter
+        .enabled
+        .then(|| Arc::new(altimeter::QnhStore::load(&config.altimeter, Local::now())));
+    // Extremes since last viewed, reset by a double press or over HTTP
+    let extremes = config.extremes.enabled.then(|| {
+        Arc::new(extremes::ExtremesStore::load(
+            &config.extremes,
+            Local::now(),
+        ))
+    });
+    // Degree-hours and mold risk, integrated by their own task
+    let (sample_tx, sample_rx) = watch::channel(None);
+    let daily_metrics_table = database.as_ref().map(Database::daily_metrics_table);
+    let daily_metrics = if config.daily_metrics.enabled {
+        let store = Arc::new(
+            daily_metrics::DailyMetricsStore::load(
+                &config.daily_metrics,
+                daily_metrics_table.as_ref(),
+                Local::now(),
+            )
+            .await,
+        );
+        daily_metrics::spawn(
+            store.clone(),
+            sample_rx,
+            daily_metrics_table.clone(),
+            trace.clone(),
+            &mut supervisor,
+        );
+        Some(store)
+    } else {
+        None
+    };
+    // Winds down on the UPS power loss signal
+    let power_pins = policy
+        .check(
+            &display,
+            Subsystem::Power,
+            PowerPins::from_config(&config.power).map_err(|source| WbrokerError::Gpio {
+                function: "power",
+                source,
+            }),
+        )?
+        .flatten();
+    let mut wind_down = WindDown::new(config.power.save_interval());
+    let mut save_windows = config.database.save_window().map(|length| {
+        if config.database.align_to_interval {
+            sensor::SaveWindows::aligned(length)
+        } else {
+            sensor::SaveWindows::new(length)
+        }
+    });
+    // Sets the display contrast from the ambient light, not simulated
+    let mut light_sensor = match (&config.light_sensor, &bus) {
+        (Some(light), Some(bus)) => policy
+            .check(
+                &display,
+                Subsystem::LightSensor,
+                bh1750::Bh1750::with_bus(bus.device(light.address)).map_err(|source| {
+                    SensorError::Init {
+                        driver: "BH1750",
+                        address: light.address,
+                        source,
+                    }
+                }),
+            )?
+            .map(|sensor| (sensor, light::AutoDim::new(light))),
+        _ => None,
+    };
+    if light_sensor.is_some() && !display.has_contrast() {
+        eprintln!("Warning: the display has no contrast setting, the light sensor is not used.");
+        light_sensor = None;
+    }
+    let api = if config.http.listen.is_some() || config.http.kiosk_bind.is_some() {
+        timer.begin("http_bind", Instant::now());
+        let config_path = config_loaded.then(|| config_filepath.into());
+        let mut api = http::ApiState::new(
+            sensor_tx,
+            config_path,
+            maintenance.clone(),
+            capture_control.clone(),
+            display_lease.clone(),
+        );
+        if let Some(database) = &database {
+            api = api.with_timestamp_stats(database.timestamp_stats());
+        }
+        if let Some(monitor) = &disk_monitor {
+            api = api.with_disk(monitor.stats());
+        }
+        if power_pins.is_some() {
+            api = api.with_power(wind_down.stats());
+        }
+        if let Some(qnh) = &qnh {
+            api = api.with_qnh(qnh.clone());
+        }
+        if let Some(extremes) = &extremes {
+            api = api.with_extremes(extremes.clone());
+        }
+        if let Some(daily_metrics) = &daily_metrics {
+            api = api.with_daily_metrics(daily_metrics.clone());
+        }
+        api = api.with_trace(trace.clone());
+        if let Some(safe_mode) = &safe_mode {
+            api = api.with_safe_mode(safe_mode.clone());
+        }
+        api = api.with_produced_fields(openapi::ProducedFields::from_config(config));
+        let api = Arc::new(api);
+        let mut serving = false;
+        if let Some(listen) = &config.http.listen {
+            serving |= policy
+                .check(
+                    &display,
+                    Subsystem::Http,
+                    http::serve(listen, api.clone(), &mut supervisor).await,
+                )?
+                .is_some();
+        }
+        if let Some(kiosk_bind) = &config.http.kiosk_bind {
+            serving |= policy
+                .check(
+                    &display,
+                    Subsystem::Http,
+                    http::serve_kiosk(kiosk_bind, api.clone(), &mut supervisor).await,
+                )?
+                .is_some();
+        }
+        serving.then_some(api)
+    } else {
+        None
+    };
 
-        so1602a.put_u8(so1602a::SO1602A_2ND_LINE + 15, indicator[counter])?;
+    timer.begin("button_init", Instant::now());
+    let wake_button = match config.screensaver.wake_pin {
+        Some(pin) => policy.check(
+            &display,
+            Subsystem::Button,
+            WakeButton::new(pin).map_err(|source| WbrokerError::Gpio {
+                function: "wake button",
+                source,
+            }),
+        )?,
+        None => None,
+    };
+    let mut screensaver = Screensaver::new(config.screensaver.idle_timeout(), Instant::now());
+    let mut double_press = DoublePress::default();
+    let mut long_press = LongPress::default();
+    // Measurements are drawn through the screen from here on
+    let mut screen = screen::Screen::new(display, recovery, &config.display);
+    // Displays without a contrast setting are turned off instead of dimmed
+    let dim_on_battery =
+        config.power.display == config::WindDownDisplay::Dim && screen.display().has_contrast();
+
+    // Measured slowly in safe mode
+    let mut rate = match safe_mode {
+        Some(_) => Duration::from_millis(config.safe_mode.interval_ms),
+        None => config.sensor.interval(),
+    };
+    let mut ticks = scheduler::TickScheduler::starting(
+        tokio::time::Instant::now(),
+        rate,
+        config.clock.missed_ticks,
+        config.clock.first_tick,
+    );
+    if config.database.align_to_interval {
+        ticks.align(tokio::time::Instant::now(), wall_clock.now());
+    }
+    let mut clock = ClockSync::new(config.clock.min_valid_year);
+    // Fan control and alerts publish their outputs here for the stored rows
+    let actions = SharedActions::new();
+    // Kept in the log so stored THI values can be interpreted later
+    let comfort = config.comfort.coefficients();
+    println!("Comfort formula: {}", config.comfort.summary());
+    if config.display.precision_check {
+        let format = config.display.measurement_format();
+        for warning in precision::check(&config.sensor.settings(), &format) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    let mut maintenance_rx = maintenance.subscribe();
+    let mut readonly = ReadonlyPeriod::default();
+    let mut active_capture: Option<capture::Capture> = None;
+    let mut alerts = alerts::AlertEngine::from_config(&config.alerts);
+    let webhook_url = config
+        .alerts
+        .escalation
+        .webhook_url
+        .as_deref()
+        .filter(|_| safe_mode.is_none());
+    // Other units' readings, paged in between the main page
+    let peer_units = match safe_mode {
+        Some(_) => &[][..],
+        None => &config.peers.units[..],
+    };
+    let peer_cache =
+        (!peer_units.is_empty()).then(|| peers::spawn_poller(&config.peers, &mut supervisor));
+    let mut pager = peers::PeerPager::new(
+        Duration::from_secs(config.peers.page_secs),
+        peer_units.len(),
+        Instant::now(),
+    )
+    .with_peers_per_page(usize::from(config.display.geometry().rows));
+    if qnh.is_some() {
+        pager = pager.with_altimeter();
+    }
+    if daily_metrics.is_some() {
+        pager = pager.with_degree_hours();
+    }
+    if extremes.is_some() {
+        pager = pager.with_extremes();
+    }
+
+    let mut startup_report = timer.finish(Instant::now());
+    println!("Startup timing: {}", startup_report.summary());
+    if let Some(api) = &api {
+        api.set_startup(startup_report.clone());
+        api.set_degraded(policy.degraded());
+    }
+    let sensor_failed = policy.is_degraded(Subsystem::Sensor) && sensors.is_empty();
+
+    // Stop cleanly on Ctrl-C, SIGTERM or a low UPS battery, so grouped rows
+    // get committed
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut failure = None;
+    // Correlates the entries of the debug trace
+    let mut tick: u64 = 0;
+    // A panic of the loop itself is torn down like one of a task
+    let main_loop = supervisor::catch_panic(async {
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = &mut shutdown => break,
+                failed = supervisor.failure() => {
+                    failure = Some(failed);
+                    break;
+                }
+            }
+            tick += 1;
+            // The soak test samples the process here, and ends the run
+            if on_tick
+                .as_mut()
+                .is_some_and(|on_tick| on_tick(tick, database.as_ref()).is_break())
+            {
+                break;
+            }
+            // Rows would only pile up in the queue
+            if database.as_ref().is_some_and(|d| d.writer_stopped()) {
+                failure = Some(supervisor::TaskFailure {
+                    task: "database writer",
+                    message: "stopped while the database is open".to_string(),
+                });
+                break;
+            }
 
-        if let Some(ref database) = database {
-            let sensor_data = SensorData::from_measurement(measurement, thi);
-            if let Err(e) = database.save_async(sensor_data) {
-                eprintln!("Failed to queue sensor data for saving: {}", e);
+            let now = wall_clock.now();
+            match clock.update(&now) {
+                ClockTransition::Lost => {
+                    println!("System time is not set ({}). Waiting for time sync.", now)
+                }
+                ClockTransition::Synced => println!("System time synchronized ({}).", now),
+                ClockTransition::Unchanged => {}
+            }
+            if let Some(safe_mode) = &safe_mode {
+                let pressed = wake_button.as_ref().is_some_and(|b| b.is_pressed());
+                let long = long_press.update(pressed, Instant::now());
+                if let Some(reason) = safe_mode.exit_reason(long, now) {
+                    println!("Leaving safe mode ({}), restarting.", reason.as_str());
+                    if let Err(e) = safe_mode.leave(reason, now) {
+                        eprintln!("Failed to clear the crashes: {}", e);
+                    }
+                    break;
+                }
+            }
+            // Apply new settings between measurements only
+            if sensor_rx.has_changed().unwrap_or(false) {
+                let sensor = sensor_rx.borrow_and_update().clone();
+                if let Err(e) = sensors.configure(sensor.settings()) {
+                    eprintln!("Failed to apply sensor settings: {}", e);
+                }
+                if safe_mode.is_none() && sensor.interval() != rate {
+                    rate = sensor.interval();
+                    // A running capture keeps its own period until it ends
+                    if active_capture.is_none() {
+                        ticks.restart(tokio::time::Instant::now(), rate);
+                        if config.database.align_to_interval {
+                            ticks.align(tokio::time::Instant::now(), wall_clock.now());
+                        }
+                    }
+                }
+            }
+            if maintenance_rx.has_changed().unwrap_or(false) {
+                let state = *maintenance_rx.borrow_and_update();
+                if state == MaintenanceState::Readonly && !readonly.is_active() {
+                    // Everything queued so far is on disk before the mode is reported
+                    let flushed = match &database {
+                        Some(database) => database.flush().await,
+                        None => Ok(()),
+                    };
+                    if let Err(e) = flushed {
+                        eprintln!("Failed to flush the database: {}", e);
+                    }
+                    println!("Entered read-only maintenance mode.");
+                }
+                if let Some(gap) = readonly.apply(state, wall_clock.now()) {
+                    println!("Left read-only maintenance mode. {}", gap.summary());
+                }
+                maintenance.set_applied(state);
+            }
+            let was_paused = disk_monitor.as_ref().is_some_and(|m| m.is_paused());
+            let disk_level = disk_monitor
+                .as_mut()
+                .and_then(|monitor| monitor.check(Instant::now()));
+            if let (Some(monitor), Some(level)) = (&disk_monitor, disk_level) {
+                report_disk_level(&database, monitor, level, was_paused).await;
+            }
+            if let Some(pins) = &power_pins {
+                let (mains_lost, battery_low) = pins.read();
+                match wind_down.update(mains_lost, battery_low, Instant::now()) {
+                    PowerTransition::MainsLost => {
+                        eprintln!("Mains power lost, winding down.");
+                        record_power_event(&database, "power_loss", serde_json::json!({})).await;
+                        if dim_on_battery {
+                            screen.write("dim", |d| d.set_contrast(0)).await;
+                        } else if !screensaver.is_blanked() {
+                            screen.write("off", |d| d.display_off()).await;
+                        }
+                    }
+                    PowerTransition::MainsRestored { outage } => {
+                        println!("Mains power restored after {} s.", outage.as_secs());
+                        let detail = serde_json::json!({ "outage_secs": outage.as_secs() });
+                        record_power_event(&database, "power_restored", detail).await;
+                        if let Some((_, auto_dim)) = &mut light_sensor {
+                            auto_dim.forget();
+                        }
+                        if dim_on_battery {
+                            let contrast = config.display.contrast;
+                            screen.write("contrast", |d| d.set_contrast(contrast)).await;
+                        } else if !screensaver.is_blanked() {
+                            screen.write("on", |d| d.display_on()).await;
+                        }
+                    }
+                    PowerTransition::BatteryLow => {
+                        eprintln!("UPS battery low, shutting down.");
+                        record_power_event(&database, "battery_low", serde_json::json!({})).await;
+                        break;
+                    }
+                    PowerTransition::Unchanged => {}
+                }
+            }
+            // Turned off on battery, unless dimmed instead
+            let power_blanked = wind_down.is_on_battery() && !dim_on_battery;
+            // The wind-down contrast is kept while on battery
+            let contrast = light_sensor
+                .as_mut()
+                .filter(|_| !wind_down.is_on_battery() && !screen.is_paused())
+                .and_then(|(sensor, auto_dim)| {
+                    auto_dim.update(sensor.read_lux().map_err(|e| e.to_string()))
+                });
+            if let Some(contrast) = contrast {
+                screen.write("contrast", |d| d.set_contrast(contrast)).await;
+            }
+            if active_capture
+                .as_ref()
+                .is_some_and(|capture| capture.is_over(Instant::now()))
+            {
+                if let Some(capture) = active_capture.take() {
+                    record_capture_event(&database, "capture_end", &capture).await;
+                    println!("Capture ended: {}", capture.event_detail());
+                }
+                capture_control.finish();
+                ticks.restart(tokio::time::Instant::now(), rate);
+                if config.database.align_to_interval {
+                    ticks.align(tokio::time::Instant::now(), wall_clock.now());
+                }
+            }
+            if active_capture.is_none() {
+                active_capture = capture_control.start(now, Instant::now());
+                if let Some(capture) = &active_capture {
+                    record_capture_event(&database, "capture_start", capture).await;
+                    println!("Capture started: {}", capture.event_detail());
+                    ticks.restart(tokio::time::Instant::now(), capture.rate());
+                }
+            }
+            for event in display_lease.take_events(Instant::now()) {
+                println!("Display lease event {}: {}", event.kind(), event.detail());
+                record_lease_event(&database, &event).await;
+            }
+            for event in trace.take_events(Instant::now()) {
+                println!("Trace event {}: {}", event.kind(), event.detail());
+                record_trace_event(&database, &event).await;
+            }
+            if sensor_failed {
+                // Nothing more to wait for at start-up
+                if let Some(watchdog) = watchdog.take() {
+                    watchdog.disarm();
+                }
+                let visible = !screensaver.is_blanked() && !power_blanked;
+                if screen.set_paused(display_lease.is_held(Instant::now())) && !visible {
+                    screen.write("off", |d| d.display_off()).await;
+                }
+                screen
+                    .show_fault(now, clock.is_synced(), Subsystem::Sensor.name(), visible)
+                    .await;
+                continue;
+            }
+            let readings = sensors.measure_all().await;
+            if trace.is_active() {
+                let detail: Vec<_> = readings
+                    .iter()
+                    .map(|reading| {
+                        reading.as_ref().map(|reading| {
+                            serde_json::json!({
+                                "sensor": reading.label,
+                                "temperature_c": reading.measurement.temperature_c,
+                                "humidity_relative": reading.measurement.humidity_relative,
+                                "pressure_pa": reading.measurement.pressure_pa,
+                                "quality": reading.quality.as_str(),
+                            })
+                        })
+                    })
+                    .collect();
+                trace.record(tick, "raw", serde_json::json!({ "readings": detail }));
+            }
+            // The tick is skipped without the main sensor, the others are only
+            // worth logging alongside it
+            let Some(mut main_reading) = readings[0].clone() else {
+                continue;
+            };
+            if startup_report.first_sample_ms.is_none() {
+                startup_report.first_sample_ms = Some(boot.elapsed().as_millis() as u64);
+                println!("Startup timing: {}", startup_report.summary());
+                if let Some(api) = &api {
+                    api.set_startup(startup_report.clone());
+                }
+                if let Some(watchdog) = watchdog.take() {
+                    watchdog.disarm();
+                }
+            }
+            main_reading.measurement = sensor_rx.borrow().apply_offsets(main_reading.measurement);
+            let measurement = main_reading.measurement;
+            // Captured right after the reading, before any queueing delay
+            let measured_at = wall_clock.now();
+            let thi = calc_thi(
+                measurement.temperature_c,
+                measurement.humidity_relative,
+                &comfort,
+            );
+            if let Some(extremes) = &extremes {
+                extremes.update(&measurement, Instant::now());
+            }
+            let source = if active_capture.is_some() {
+                Source::Capture
+            } else if simulated {
+                Source::Simulated
+            } else {
+                Source::Live
+            };
+            if trace.is_active() {
+                let detail = serde_json::json!({
+                    "temperature_c": measurement.temperature_c,
+                    "humidity_relative": measurement.humidity_relative,
+                    "pressure_pa": measurement.pressure_pa,
+                    "thi": thi,
+                    "source": source.as_str(),
+                });
+                trace.record(tick, "offsets", detail);
+            }
+            // Capture ticks stay out of the daily totals, the interval is bridged
+            if source != Source::Capture {
+                sample_tx.send_replace(Some(daily_metrics::Sample {
+                    at: measured_at,
+                    measurement,
+                    tick,
+                }));
+            }
+            if let Some(api) = &api {
+                api.set_current(http::Current {
+                    timestamp: measured_at.to_rfc3339(),
+                    sensor: main_reading.label.clone(),
+                    temperature_c: measurement.temperature_c,
+                    humidity_relative: measurement.humidity_relative,
+                    pressure_pa: measurement.pressure_pa,
+                    altitude_m: measurement.altitude_m(sensor_rx.borrow().sea_level_pa),
+                    dew_point_c: measurement.dew_point_c(),
+                    absolute_humidity_g_m3: helper::absolute_humidity(
+                        measurement.temperature_c,
+                        measurement.humidity_relative,
+                    ),
+                    thi,
+                    source: source.as_str().to_string(),
+                });
+            }
+            let mut raised = false;
+            for transition in alerts.update(&measurement, thi, Instant::now()) {
+                raised |= matches!(
+                    transition,
+                    alerts::AlertTransition::Escalated {
+                        stage: alerts::Stage::Display,
+                        ..
+                    }
+                );
+                if trace.is_active() {
+                    let detail = serde_json::json!({ "transition": format!("{:?}", transition) });
+                    trace.record(tick, "alert", detail);
+                }
+                alerts::notify(&transition, webhook_url);
+                if config.alerts.history {
+                    record_alert_episode(&database, &transition, measured_at).await;
+                }
+            }
+            if alerts.is_enabled() {
+                actions.set_alert_active(Some(alerts.active_mask()));
+            }
+
+            // An always_override alert is drawn over the lease
+            let overridden = alerts.overrides_lease();
+            display_lease.set_overridden(overridden);
+            let resumed = screen.set_paused(display_lease.is_held(Instant::now()) && !overridden);
+
+            // A new alert wakes the display like a button press
+            let pressed = wake_button.as_ref().is_some_and(|b| b.is_pressed());
+            let activity = raised || pressed;
+            // A double press confirms the extremes were seen
+            if let (Some(extremes), true) =
+                (&extremes, double_press.update(pressed, Instant::now()))
+            {
+                match extremes.reset(now) {
+                    Ok(_) => println!("Extremes reset by the button"),
+                    Err(e) => eprintln!("Failed to save the extremes reset: {}", e),
+                }
+            }
+            match screensaver.update(Instant::now(), activity) {
+                _ if power_blanked => {}
+                ScreensaverTransition::Blank => {
+                    screen.write("off", |d| d.display_off()).await;
+                }
+                ScreensaverTransition::Wake => {
+                    screen.write("on", |d| d.display_on()).await;
+                }
+                ScreensaverTransition::Unchanged => {}
+            }
+            let visible = !screensaver.is_blanked() && !power_blanked;
+            if resumed && !visible {
+                // Otherwise left on as the lease holder had it
+                screen.write("off", |d| d.display_off()).await;
+            }
+
+            let peer_summaries = peer_cache
+                .as_ref()
+                .map(|cache| cache.summaries(Instant::now()))
+                .unwrap_or_default();
+            let alert_message = alerts.display_message();
+            let frame = screen::Frame {
+                now,
+                clock_synced: clock.is_synced(),
+                measurement,
+                thi,
+                readonly: readonly.is_active(),
+                safe_mode: safe_mode.as_ref().map(|safe_mode| safe_mode.code()),
+                alert: alerts.display_text(),
+                alert_message: alert_message.as_deref(),
+                // An active alert keeps the main page up
+                page: match alerts.display_text() {
+                    Some(_) => page::Page::Main,
+                    None => pager.page(Instant::now()),
+                },
+                peers: &peer_summaries,
+                qnh: qnh.as_ref().and_then(|qnh| qnh.status(now)),
+                extremes: extremes.as_ref().map(|extremes| extremes.status(now)),
+                daily_metrics: daily_metrics.as_ref().map(|metrics| metrics.status()),
+            };
+            let outcome = screen.show(&frame, visible).await;
+            if trace.is_active() {
+                let detail = serde_json::json!({
+                    "page": format!("{:?}", frame.page),
+                    "visible": visible,
+                    "paused": screen.is_paused(),
+                    "outcome": outcome.map(|outcome| format!("{:?}", outcome)),
+                    "lines": outcome.and(screen.drawn()),
+                });
+                trace.record(tick, "frame", detail);
+            }
+
+            let skip_db =
+                readonly.is_active() || (config.clock.skip_db_when_unsynced && !clock.is_synced());
+            let disk_full = disk_monitor.as_ref().is_some_and(|m| m.is_paused());
+            if trace.is_active() {
+                let detail = serde_json::json!({
+                    "readonly": readonly.is_active(),
+                    "clock_synced": clock.is_synced(),
+                    "skip_db": skip_db,
+                    "disk_full": disk_full,
+                    "logging": database.is_some(),
+                });
+                trace.record(tick, "filter", detail);
+            }
+            if let (Some(monitor), false, true) = (&disk_monitor, skip_db, disk_full) {
+                monitor
+                    .stats()
+                    .add_dropped(readings.iter().flatten().count() as u64);
+            } else if let (Some(database), false) = (&database, skip_db) {
+                // Capture ticks are skipped instead of queued while the writer lags
+                let admitted = match active_capture.as_mut() {
+                    Some(capture) => capture.admit(database.queue_len()).map(Some),
+                    None => Some(None),
+                };
+                let others = readings[1..].iter().flatten();
+                let rows: Vec<_> = std::iter::once(&main_reading)
+                    .chain(others)
+                    .filter_map(|reading| match save_windows.as_mut() {
+                        // Capture ticks are stored as read
+                        Some(windows) if active_capture.is_none() => {
+                            windows.push(reading, measured_at)
+                        }
+                        _ => Some((reading.clone(), measured_at)),
+                    })
+                    .collect();
+                let capture_admitted = admitted.is_some();
+                // Rows are thinned out on battery
+                let admitted =
+                    admitted.filter(|_| !rows.is_empty() && wind_down.admit_save(Instant::now()));
+                if trace.is_active() {
+                    let held: Vec<_> = rows.iter().map(|(reading, _)| &reading.label).collect();
+                    let detail = serde_json::json!({
+                        "window_rows": held,
+                        "capture_admitted": capture_admitted,
+                        "saved": admitted.is_some(),
+                    });
+                    trace.record(tick, "filter", detail);
+                }
+                if let Some(capture_id) = admitted {
+                    for (reading, row_at) in &rows {
+                        let mut sensor_data = reading.to_sensor_data(*row_at, &comfort);
+                        sensor_data.measured_at = (*row_at != measured_at).then_some(measured_at);
+                        sensor_data.actions = actions.snapshot();
+                        sensor_data.capture_id = capture_id;
+                        sensor_data.source = source;
+                        sensor_data.tick = Some(tick);
+                        let queued = database.save_async(sensor_data);
+                        if trace.is_active() {
+                            let detail = serde_json::json!({
+                                "sensor": reading.label,
+                                "timestamp": row_at.to_rfc3339(),
+                                "error": queued.as_ref().err().map(|e| e.to_string()),
+                                "queue_len": database.queue_len(),
+                            });
+                            trace.record(tick, "queue", detail);
+                        }
+                        if let Err(e) = queued {
+                            eprintln!("Failed to queue sensor data for saving: {}", e);
+                        }
+                    }
+                }
+                if let Some(capture) = &active_capture {
+                    capture_control.publish(capture);
+                }
             }
         }
+    });
+    if let Err(message) = main_loop.await {
+        failure = Some(supervisor::TaskFailure {
+            task: "main loop",
+            message,
+        });
+    }
 
-        counter = (counter + 1) & 0x03;
+    println!("Shutting down.");
+    if failure.is_some() {
+        // Best effort, the display may be what failed
+        let lines = page::render_text(&["PANIC -", "restarting"], config.display.geometry());
+        let shown = screen.write("panic", |d| {
+            d.display_on()?;
+            page::draw(d, &lines)
+        });
+        let _ = tokio::time::timeout(supervisor::TEARDOWN_TIMEOUT, shown).await;
+    }
+    if let Some(Err(e)) = extremes.as_ref().map(|extremes| extremes.save()) {
+        eprintln!("Failed to save the extremes: {}", e);
     }
+    // The day so far, continued after a restart
+    if let (Some(metrics), Some(table)) = (&daily_metrics, &daily_metrics_table) {
+        let today = metrics.today();
+        if let Err(e) = table.save(&today).await {
+            eprintln!("Failed to save the daily metrics of {}: {}", today.day, e);
+        }
+    }
+    // Stopped before the first sample, which is still a clean shutdown
+    if let Some(watchdog) = watchdog.take() {
+        watchdog.disarm();
+    }
+    let Some(failure) = failure else {
+        if let Some(database) = database {
+            database.close().await;
+        }
+        return Ok(());
+    };
+    if let Some(database) = database {
+        let closed = tokio::time::timeout(supervisor::TEARDOWN_TIMEOUT, database.close()).await;
+        if closed.is_err() {
+            eprintln!(
+                "Queued rows were not written within {:?}",
+                supervisor::TEARDOWN_TIMEOUT
+            );
+        }
+    }
+    supervisor.shutdown(supervisor::TEARDOWN_TIMEOUT).await;
+    Err(ExitError::Panic {
+        task: failure.task,
+        message: failure.message,
+    })
+}
 
-    #[allow(unreachable_code)]
-    Ok(())
+/// Parse an optional RFC 3339 time argument.
+/// # Arguments
+/// * `time` - Argument value.
+/// # Returns
+/// * The time in the local time zone.
+fn parse_time(time: Option<String>) -> Result<Option<DateTime<Local>>, Box<dyn Error>> {
+    time.map(|time| {
+        DateTime::parse_from_rfc3339(&time)
+            .map(|time| time.with_timezone(&Local))
+            .map_err(|e| format!("Invalid time {}: {}", time, e).into())
+    })
+    .transpose()
+}
+
+/// Run a subcommand.
+/// # Arguments
+/// * `command` - Subcommand from the command line.
+/// * `config` - Loaded config, or the defaults.
+/// * `config_loaded` - Whether a config file was loaded.
+/// * `config_filepath` - Path of the config file, for errors.
+/// # Returns
+/// * Result<(), Box<dyn Error>>
+async fn run_command(
+    command: Command,
+    config: &Config,
+    config_loaded: bool,
+    config_filepath: &str,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Soak {
+            hours,
+            simulate,
+            speed,
+            samples,
+            max_rss_growth_kb,
+            max_fd_growth,
+            max_queue_depth,
+            database_url,
+        } => {
+            if !simulate {
+                return Err("soak currently requires --simulate".into());
+            }
+            let options = soak::SoakOptions {
+                hours,
+                interval_ms: 200,
+                speed,
+                samples,
+                max_rss_growth_kb,
+                max_fd_growth,
+                max_queue_depth,
+                database_url,
+                chaos: config.chaos.clone(),
+            };
+            let report = soak::run(&options).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.passed {
+                return Err("soak test failed".into());
+            }
+            Ok(())
+        }
+        Command::Recompute {
+            columns,
+            from,
+            to,
+            batch_size,
+            dry_run,
+            resume_file,
+        } => {
+            if !config_loaded {
+                return Err(
+                    format!("recompute requires a config file ({})", config_filepath).into(),
+                );
+            }
+            let options = recompute::RecomputeOptions {
+                columns: columns
+                    .iter()
+                    .map(|name| recompute::DerivedColumn::parse(name))
+                    .collect::<Result<_, _>>()?,
+                from: parse_time(from)?,
+                to: parse_time(to)?,
+                batch_size,
+                dry_run,
+                resume_file,
+            };
+            let database = Database::from_config(&config.database)
+                .await
+                .map_err(|e| format!("Failed to open the database: {}", e))?;
+            let result = recompute::run(&database, &options, &config.comfort.coefficients()).await;
+            database.close().await;
+            println!("{}", serde_json::to_string_pretty(&result?)?);
+            Ok(())
+        }
+        Command::Export {
+            from,
+            to,
+            output,
+            locale,
+            delimiter,
+            grouping,
+            sources,
+        } => {
+            if !config_loaded {
+                return Err(format!("export requires a config file ({})", config_filepath).into());
+            }
+            let options = export::ExportOptions {
+                from: parse_time(from)?,
+                to: parse_time(to)?,
+                sources: sources
+                    .iter()
+                    .map(|name| Source::parse(name))
+                    .collect::<Result<_, _>>()?,
+                locale: helper::units::NumberLocale::parse(
+                    locale.as_deref().unwrap_or(&config.export.locale),
+                )?,
+                grouping: grouping || config.export.grouping,
+                delimiter: delimiter.unwrap_or(config.export.delimiter),
+                batch_size: 1000,
+            };
+            export::validate_delimiter(options.delimiter)?;
+            let mut out: Box<dyn std::io::Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let database = Database::from_config(&config.database)
+                .await
+                .map_err(|e| format!("Failed to open the database: {}", e))?;
+            let result = export::run(&database, &options, &mut out).await;
+            database.close().await;
+            let written = result?;
+            if output.is_some() {
+                println!("Exported {} rows", written);
+            }
+            Ok(())
+        }
+        Command::Replay {
+            input,
+            speed,
+            compress_gaps,
+            sensor,
+            locale,
+            delimiter,
+            store,
+        } => {
+            if store && !config_loaded {
+                return Err(format!(
+                    "replay --store requires a config file ({})",
+                    config_filepath
+                )
+                .into());
+            }
+            let locale = helper::units::NumberLocale::parse(
+                locale.as_deref().unwrap_or(&config.export.locale),
+            )?;
+            let delimiter = delimiter.unwrap_or(config.export.delimiter);
+            export::validate_delimiter(delimiter)?;
+            let text = std::fs::read_to_string(&input)
+                .map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+            let mut sensor =
+                replay::CsvReplaySensor::parse(&text, sensor.as_deref(), delimiter, &locale)?;
+            let options = replay::ReplayOptions {
+                speed,
+                max_gap: compress_gaps.map(Duration::from_secs),
+            };
+            let database = if store {
+                let database = Database::from_config(&config.database)
+                    .await
+                    .map_err(|e| format!("Failed to open the database: {}", e))?;
+                Some(database)
+            } else {
+                None
+            };
+            // Not locked, the alerts are logged in between
+            let mut out = std::io::stdout();
+            let result =
+                replay::run(&mut sensor, config, &options, database.as_ref(), &mut out).await;
+            if let Some(database) = database {
+                database.close().await;
+            }
+            let report = result?;
+            println!(
+                "Replayed {} rows, {} alert transitions",
+                report.rows,
+                report.alerts.len()
+            );
+            Ok(())
+        }
+        Command::DriftReport {
+            peer,
+            reference,
+            window_mins,
+            sensor,
+            max_skew_secs,
+            locale,
+            delimiter,
+            apply,
+        } => {
+            if !config_loaded {
+                return Err(
+                    format!("drift-report requires a config file ({})", config_filepath).into(),
+                );
+            }
+            let window = Duration::from_secs(window_mins * 60);
+            let max_skew = chrono::TimeDelta::seconds(max_skew_secs as i64);
+            let label = sensor.unwrap_or_else(|| config.sensors.label.clone());
+            let locale = helper::units::NumberLocale::parse(
+                locale.as_deref().unwrap_or(&config.export.locale),
+            )?;
+            let delimiter = delimiter.unwrap_or(config.export.delimiter);
+            export::validate_delimiter(delimiter)?;
+
+            // Every unit of [peers] without a chosen reference
+            let units: Vec<_> = if peer.is_empty() && reference.is_empty() {
+                config.peers.units.iter().collect()
+            } else {
+                peer.iter()
+                    .map(|name| {
+                        config
+                            .peers
+                            .units
+                            .iter()
+                            .find(|unit| &unit.name == name)
+                            .ok_or_else(|| format!("No unit {} in [peers]", name))
+                    })
+                    .collect::<Result<_, _>>()?
+            };
+            if units.is_empty() && reference.is_empty() {
+                return Err("drift-report needs --peer, --reference or units in [peers]".into());
+            }
+
+            let window_delta = chrono::TimeDelta::from_std(window)?;
+            let mut references = Vec::new();
+            let mut ranges = Vec::new();
+            for path in &reference {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let (name, samples) = drift::file_samples(&text, delimiter, &locale, window_delta)?;
+                if let (Some(first), Some(last)) = (samples.first(), samples.last()) {
+                    ranges.push((first.at, last.at));
+                }
+                references.push((format!("{} ({})", name, path.display()), samples));
+            }
+            if !units.is_empty() {
+                eprintln!("Polling {} peers for {} minutes", units.len(), window_mins);
+                let started = Local::now();
+                let urls: Vec<String> = units
+                    .iter()
+                    .map(|unit| peers::current_url(&unit.url))
+                    .collect();
+                let polled = drift::poll_peers(
+                    &urls,
+                    window,
+                    Duration::from_secs(config.peers.poll_interval_secs),
+                    config.peers.timeout(),
+                )
+                .await;
+                ranges.push((started, Local::now()));
+                for (unit, samples) in units.iter().zip(polled) {
+                    references.push((unit.name.clone(), samples));
+                }
+            }
+
+            let database = Database::from_config(&config.database)
+                .await
+                .map_err(|e| format!("Failed to open the database: {}", e))?;
+            let mut unit = Vec::new();
+            let mut result = Ok(());
+            for (from, to) in ranges {
+                match drift::stored_samples(&database, &label, from - max_skew, to + max_skew).await
+                {
+                    Ok(samples) => unit.extend(samples),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            database.close().await;
+            result?;
+            unit.sort_by_key(|sample| sample.at);
+            unit.dedup();
+
+            let report = drift::Report::new(&unit, &references, max_skew);
+            report.write(&config.sensor, &mut std::io::stdout().lock())?;
+            if apply {
+                let (suggested, changed) = report.suggested(&config.sensor);
+                if !changed.contains(&true) {
+                    println!("Nothing to apply");
+                    return Ok(());
+                }
+                suggested.validate().map_err(|errors| errors.join(", "))?;
+                Config::save_sensor_section(config_filepath, &suggested)?;
+                println!("Wrote the offsets to {}", config_filepath);
+            }
+            Ok(())
+        }
+        Command::Capture {
+            rate_ms,
+            duration_secs,
+        } => {
+            let Some(listen) = &config.http.listen else {
+                return Err("capture requires [http] listen in the config file".into());
+            };
+            let request = capture::CaptureRequest {
+                rate_ms,
+                duration_secs,
+            };
+            request.validate()?;
+            println!("{}", capture::request_over_http(listen, request).await?);
+            Ok(())
+        }
+        #[cfg(feature = "config-schema")]
+        Command::ConfigSchema => {
+            println!("{}", serde_json::to_string_pretty(&Config::json_schema())?);
+            Ok(())
+        }
+    }
+}
+
+/// Wait for Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Log a change of the free space level and store it in the events table.
+/// Nothing is stored while the disk is full.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `monitor` - Disk monitor.
+/// * `level` - New level.
+/// * `was_paused` - Whether writes were paused before.
+async fn report_disk_level(
+    database: &Option<Database>,
+    monitor: &disk::DiskMonitor,
+    level: disk::DiskLevel,
+    was_paused: bool,
+) {
+    let status = monitor.stats().status();
+    let free_mb = status.free_bytes.unwrap_or_default() / (1024 * 1024);
+    let path = monitor.path().display();
+    let kind = match level {
+        disk::DiskLevel::Full => {
+            eprintln!(
+                "Only {} MB free on {}, database writes paused.",
+                free_mb, path
+            );
+            return;
+        }
+        disk::DiskLevel::Low => {
+            eprintln!("Warning: only {} MB free on {}.", free_mb, path);
+            "disk_low"
+        }
+        disk::DiskLevel::Ok => {
+            println!("{} MB free on {}.", free_mb, path);
+            "disk_ok"
+        }
+    };
+    if was_paused {
+        println!(
+            "Database writes resumed, {} rows were dropped so far.",
+            status.dropped_rows
+        );
+    }
+    if let Some(database) = database {
+        let detail = serde_json::json!(status);
+        if let Err(e) = database.record_event(kind, Local::now(), &detail).await {
+            eprintln!("Failed to record {}: {}", kind, e);
+        }
+    }
+}
+
+/// Store the start or end of a capture in the events table.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `kind` - "capture_start" or "capture_end".
+/// * `capture` - The capture.
+async fn record_capture_event(database: &Option<Database>, kind: &str, capture: &capture::Capture) {
+    if let Some(database) = database {
+        let detail = capture.event_detail();
+        if let Err(e) = database.record_event(kind, Local::now(), &detail).await {
+            eprintln!("Failed to record {}: {}", kind, e);
+        }
+    }
+}
+
+/// Store a cleared alert in the alert_events table.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `transition` - Transition from the alert engine, other than a clear
+///   is ignored.
+/// * `measured_at` - Time of the measurement that cleared the alert.
+async fn record_alert_episode(
+    database: &Option<Database>,
+    transition: &alerts::AlertTransition,
+    measured_at: DateTime<Local>,
+) {
+    let Some(database) = database else {
+        return;
+    };
+    let Some(episode) = alerts::AlertEpisode::from_transition(transition, measured_at) else {
+        return;
+    };
+    if let Err(e) = database.record_alert(&episode).await {
+        eprintln!("Failed to record alert {}: {}", episode.name, e);
+    }
+}
+
+/// Store a change of the display lease in the events table.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `event` - Grant, renewal or end of a lease.
+async fn record_lease_event(database: &Option<Database>, event: &lease::LeaseEvent) {
+    let Some(database) = database else {
+        return;
+    };
+    let kind = event.kind();
+    if let Err(e) = database
+        .record_event(kind, Local::now(), &event.detail())
+        .await
+    {
+        eprintln!("Failed to record {}: {}", kind, e);
+    }
+}
+
+/// Store the start or end of a debug trace in the events table.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `event` - Start or end of a trace, with its file.
+async fn record_trace_event(database: &Option<Database>, event: &trace::TraceEvent) {
+    let Some(database) = database else {
+        return;
+    };
+    let kind = event.kind();
+    if let Err(e) = database
+        .record_event(kind, Local::now(), &event.detail())
+        .await
+    {
+        eprintln!("Failed to record {}: {}", kind, e);
+    }
+}
+
+/// Store a change of the UPS power state in the events table.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `kind` - "power_loss", "power_restored" or "battery_low".
+/// * `detail` - Details of the event.
+async fn record_power_event(database: &Option<Database>, kind: &str, detail: serde_json::Value) {
+    let Some(database) = database else {
+        return;
+    };
+    if let Err(e) = database.record_event(kind, Local::now(), &detail).await {
+        eprintln!("Failed to record {}: {}", kind, e);
+    }
+}
+
+/// Store the last safe mode episode in the events table, once a database
+/// is reachable.
+/// # Arguments
+/// * `database` - Database, if logging.
+/// * `config` - [safe_mode] settings.
+async fn record_safe_mode_episode(database: &Option<Database>, config: &config::SafeModeConfig) {
+    let Some(database) = database else {
+        return;
+    };
+    let detail = match safe_mode::take_episode(config) {
+        Ok(Some(detail)) => detail,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to read the safe mode episode: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = database
+        .record_event("safe_mode", Local::now(), &detail)
+        .await
+    {
+        eprintln!("Failed to record safe_mode: {}", e);
+    }
+}
+
+/// Set up a sensor of the configured type.
+/// # Arguments
+/// * `bus` - Shared I2C bus, `None` to simulate the sensors on the bus.
+/// * `driver` - Type of the sensor.
+/// * `address` - I2C address, unused by sensors off the bus.
+/// * `oversampling` - Oversampling rates of a BME280, from [sensor].
+/// # Returns
+/// * The sensor, or the error of its driver.
+fn sensor_init(
+    bus: Option<&SharedI2c>,
+    driver: SensorType,
+    address: u16,
+    oversampling: OversamplingConfig,
+) -> Result<Box<dyn Sensor>, SensorError> {
+    match (driver, bus) {
+        (SensorType::Bme280, Some(bus)) => Ok(Box::new(bme280_init(bus, address, oversampling)?)),
+        (SensorType::Sht31, Some(bus)) => Ok(Box::new(
+            sht31::Sht31::with_bus(bus.device(address)).map_err(|source| SensorError::Init {
+                driver: "SHT31",
+                address,
+                source,
+            })?,
+        )),
+        (SensorType::Aht20, Some(bus)) => Ok(Box::new(
+            aht20::Aht20::with_bus(bus.device(address)).map_err(|source| SensorError::Init {
+                driver: "AHT20",
+                address,
+                source,
+            })?,
+        )),
+        (SensorType::Bme280 | SensorType::Sht31 | SensorType::Aht20, None) => {
+            Ok(Box::new(simulate::SimulatedSensor::new()))
+        }
+        (SensorType::Constant, _) => Ok(Box::new(ConstantSensor::default())),
+    }
+}
+
+/// I2C addresses of a sensor type, the default one first.
+fn sensor_addresses(driver: SensorType) -> [u16; 2] {
+    match driver {
+        SensorType::Sht31 => [sht31::SHT31_ADDR, sht31::SHT31_ADDR2],
+        // The address is fixed
+        SensorType::Aht20 => [aht20::AHT20_ADDR, aht20::AHT20_ADDR],
+        SensorType::Bme280 | SensorType::Constant => [bme280::BME280_ADDR, bme280::BME280_ADDR2],
+    }
+}
+
+/// Set up a BME280 on the shared bus.
+/// # Arguments
+/// * `bus` - Shared I2C bus.
+/// * `address` - I2C address of the sensor.
+/// * `oversampling` - Oversampling rates to measure with.
+/// # Returns
+/// * `Err(SensorError::Init)` naming the address if it does not respond,
+///   `Err(SensorError::WrongChipId)` if another device does.
+fn bme280_init(
+    bus: &SharedI2c,
+    address: u16,
+    oversampling: OversamplingConfig,
+) -> Result<bme280::Bme280<I2cDevice>, SensorError> {
+    let device = bme280::Bme280::with_bus_and_oversampling(bus.device(address), oversampling)
+        .map_err(|e| match e {
+            bme280::Bme280Error::I2c(source) => SensorError::Init {
+                driver: "BME280",
+                address,
+                source,
+            },
+            bme280::Bme280Error::WrongChipId { found, .. } => SensorError::WrongChipId {
+                driver: "BME280",
+                address,
+                id: found,
+            },
+            e => SensorError::Bme280(e),
+        })?;
+    if !device.chip().has_humidity() {
+        eprintln!(
+            "Warning: found a BMP280 at {:#04x}, its humidity is not measured.",
+            address
+        );
+    }
+    Ok(device)
+}
+
+/// Find the BME280 at its first or second address by the chip ID.
+/// # Arguments
+/// * `bus` - Shared I2C bus.
+/// # Returns
+/// * Address of the sensor, or `Err(SensorError::Detect)` listing the
+///   tried addresses.
+fn bme280_detect(bus: &SharedI2c) -> Result<u16, SensorError> {
+    let addresses = [bme280::BME280_ADDR, bme280::BME280_ADDR2];
+    let (address, _) = bme280::detect(&addresses, |address| bus.device(address))?;
+    println!("Found the BME280 at {:#04x}", address);
+    Ok(address)
+}
+
+/// Toggle read-only maintenance mode on every SIGRTMIN+1.
+/// # Arguments
+/// * `maintenance` - Maintenance mode to toggle.
+/// * `supervisor` - Supervisor of the listening task.
+fn spawn_maintenance_signal(
+    maintenance: Arc<Maintenance>,
+    supervisor: &mut supervisor::Supervisor,
+) {
+    let mut toggle = match signal(SignalKind::from_raw(libc::SIGRTMIN() + 1)) {
+        Ok(toggle) => toggle,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGRTMIN+1: {}", e);
+            return;
+        }
+    };
+    supervisor.spawn("maintenance signal", async move {
+        while toggle.recv().await.is_some() {
+            let state = maintenance.toggle();
+            println!("Maintenance mode {:?} requested by signal", state);
+        }
+    });
+}
+
+/// Start a debug trace of `trace::DEFAULT_SECS` on every SIGRTMIN+2.
+/// # Arguments
+/// * `trace` - Trace to start.
+/// * `supervisor` - Supervisor of the listening task.
+fn spawn_trace_signal(trace: Arc<trace::TraceControl>, supervisor: &mut supervisor::Supervisor) {
+    let mut start = match signal(SignalKind::from_raw(libc::SIGRTMIN() + 2)) {
+        Ok(start) => start,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGRTMIN+2: {}", e);
+            return;
+        }
+    };
+    supervisor.spawn("trace signal", async move {
+        while start.recv().await.is_some() {
+            let request = trace::TraceRequest {
+                secs: trace::DEFAULT_SECS,
+            };
+            match trace.start(request, Local::now(), Instant::now()) {
+                Ok(status) => println!(
+                    "Trace for {} s into {} requested by signal",
+                    status.secs, status.path
+                ),
+                Err(e) => eprintln!("Failed to start a trace: {}", e),
+            }
+        }
+    });
 }
 
 /// Calculate the temperature-humidity index.
 /// # Arguments
 /// * `temperature` - Temperature in Celsius.
 /// * `humidity` - Relative humidity in %.
+/// * `coefficients` - Coefficients of the formula.
 /// # Returns
 /// * Temperature-humidity index.
-fn calc_thi(temperature: f64, humidity: f64) -> f64 {
-    0.81 * temperature + 0.01 * humidity * (0.99 * temperature - 14.3) + 46.3
+fn calc_thi(temperature: f64, humidity: f64, coefficients: &ThiCoefficients) -> f64 {
+    let ThiCoefficients { a, b, c, d, e } = *coefficients;
+    peripheral::math::thi(temperature, humidity, [a, b, c, d, e])
 }
 
 #[cfg(test)]
@@ -147,7 +1944,7 @@ mod tests {
     fn test_calc_thi_normal_conditions() {
         let temperature = 25.0;
         let humidity = 50.0;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         let expected = 0.81 * 25.0 + 0.01 * 50.0 * (0.99 * 25.0 - 14.3) + 46.3;
         assert_eq!(thi, expected);
@@ -158,7 +1955,7 @@ mod tests {
     fn test_calc_thi_hot_humid() {
         let temperature = 35.0;
         let humidity = 80.0;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         assert!(thi > 30.0);
         assert!(thi < 120.0);
@@ -168,7 +1965,7 @@ mod tests {
     fn test_calc_thi_cold_dry() {
         let temperature = 5.0;
         let humidity = 20.0;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         assert!(thi < 60.0);
         assert!(thi > 0.0);
@@ -176,7 +1973,7 @@ mod tests {
 
     #[test]
     fn test_calc_thi_zero_values() {
-        let thi = calc_thi(0.0, 0.0);
+        let thi = calc_thi(0.0, 0.0, &ThiCoefficients::STANDARD);
         assert_eq!(thi, 46.3);
     }
 
@@ -190,7 +1987,7 @@ mod tests {
         let component3 = 46.3;
 
         let expected = component1 + component2 + component3;
-        let actual = calc_thi(temperature, humidity);
+        let actual = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         assert_eq!(actual, expected);
     }
@@ -199,7 +1996,7 @@ mod tests {
     fn test_calc_thi_negative_temperature() {
         let temperature = -10.0;
         let humidity = 30.0;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         assert!(thi < 50.0);
     }
@@ -208,9 +2005,9 @@ mod tests {
     fn test_calc_thi_high_humidity() {
         let temperature = 25.0;
         let humidity = 100.0;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
-        let thi_low_humidity = calc_thi(temperature, 0.0);
+        let thi_low_humidity = calc_thi(temperature, 0.0, &ThiCoefficients::STANDARD);
         assert!(thi > thi_low_humidity);
     }
 
@@ -218,12 +2015,26 @@ mod tests {
     fn test_calc_thi_precision() {
         let temperature = 22.5;
         let humidity = 55.5;
-        let thi = calc_thi(temperature, humidity);
+        let thi = calc_thi(temperature, humidity, &ThiCoefficients::STANDARD);
 
         let rounded_thi = (thi * 10.0).round() / 10.0;
         assert!((thi - rounded_thi).abs() < 0.1);
     }
 
+    #[test]
+    fn test_calc_thi_custom_coefficients() {
+        let coefficients = ThiCoefficients {
+            a: 0.8,
+            b: 0.02,
+            c: 1.0,
+            d: 15.0,
+            e: 45.0,
+        };
+        let thi = calc_thi(25.0, 50.0, &coefficients);
+        assert!((thi - (0.8 * 25.0 + 0.02 * 50.0 * (25.0 - 15.0) + 45.0)).abs() < 1e-9);
+        assert_ne!(thi, calc_thi(25.0, 50.0, &ThiCoefficients::STANDARD));
+    }
+
     #[test]
     fn test_char_data_format() {
         let char_data: [(u8, [u8; 8]); 1] = [(
@@ -280,4 +2091,84 @@ mod tests {
         assert!(line2_format.contains("65.2"));
         assert!(line2_format.contains("72"));
     }
+
+    /// Measures the simulated curves a few times, then panics.
+    struct PanickingSensor {
+        measurements: u32,
+        panic_after: u32,
+    }
+
+    impl EnvSensor for PanickingSensor {
+        fn label(&self) -> &str {
+            "panicking"
+        }
+
+        fn measure(&mut self) -> sensor::MeasureFuture<'_> {
+            self.measurements += 1;
+            assert!(self.measurements <= self.panic_after, "sensor driver bug");
+            let measurement = simulate::SimulatedSensor::new().measurement_at(&Local::now());
+            Box::pin(async move { Ok(measurement) })
+        }
+
+        fn can_stick(&self) -> bool {
+            false
+        }
+    }
+
+    fn panicking_harness(config: &Config, panic_after: u32) -> simulate::Harness {
+        simulate::Harness {
+            sensor: Some(Box::new(PanickingSensor {
+                measurements: 0,
+                panic_after,
+            })),
+            display: simulate::NullDisplay::quiet(config.display.geometry()),
+            clock: simulate::WallClock::System,
+            on_tick: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panic_in_the_measurement_is_shown() {
+        let config = Config::default();
+        let harness = panicking_harness(&config, 0);
+        let screen = harness.display.screen();
+        let boot = Boot::new(Instant::now());
+        let result = daemon(&config, false, "config.toml", boot, None, Some(harness)).await;
+        let Err(ExitError::Panic { task, message }) = result else {
+            panic!("expected a panic, got {:?}", result);
+        };
+        assert_eq!(task, "main loop");
+        assert_eq!(message, "sensor driver bug");
+        assert!(screen.is_on());
+        assert_eq!(screen.grid()[0].trim_end(), "PANIC -");
+        assert_eq!(screen.grid()[1].trim_end(), "restarting");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_panic_in_the_measurement_flushes_the_rows() {
+        let path = std::env::temp_dir().join(format!(
+            "wbroker-rs-main-loop-panic-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut config = Config::default();
+        config.database.url = format!("sqlite:{}?mode=rwc", path.display());
+        let harness = panicking_harness(&config, 3);
+        let screen = harness.display.screen();
+        let boot = Boot::new(Instant::now());
+        let result = daemon(&config, true, "config.toml", boot, None, Some(harness)).await;
+        assert!(
+            matches!(result, Err(ExitError::Panic { .. })),
+            "{:?}",
+            result
+        );
+        assert_eq!(screen.grid()[0].trim_end(), "PANIC -");
+
+        let database = Database::new(&config.database.url).await.unwrap();
+        let rows = database.raw_rows(&[], 0, None, None, 10).await.unwrap();
+        assert_eq!(rows.len(), 3);
+        database.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
 }
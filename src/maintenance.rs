@@ -0,0 +1,220 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Read-only maintenance mode, for imaging the SD card of a running unit.
+//!
+//! The mode is requested over HTTP or with SIGRTMIN+1 and applied by the
+//! measurement loop between measurements: the queued rows are flushed and
+//! nothing is stored until the mode is left again, while the display keeps
+//! showing live values. The applied mode is published separately, so a
+//! client can wait until the flush has completed before it starts copying.
+//! The mode is runtime state only and is never read from the config file.
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Maintenance state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceState {
+    /// Measurements are stored as usual.
+    #[default]
+    Normal,
+    /// Nothing is written, only the display is updated.
+    Readonly,
+}
+
+impl MaintenanceState {
+    /// The other state, used by the signal toggle.
+    pub fn toggled(self) -> Self {
+        match self {
+            MaintenanceState::Normal => MaintenanceState::Readonly,
+            MaintenanceState::Readonly => MaintenanceState::Normal,
+        }
+    }
+}
+
+/// Requested and applied maintenance state, shared by the measurement loop,
+/// the HTTP API and the signal handler.
+#[derive(Debug)]
+pub struct Maintenance {
+    requested: watch::Sender<MaintenanceState>,
+    applied: watch::Sender<MaintenanceState>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    /// Create the shared state, starting in `Normal`.
+    pub fn new() -> Self {
+        Self {
+            requested: watch::Sender::new(MaintenanceState::Normal),
+            applied: watch::Sender::new(MaintenanceState::Normal),
+        }
+    }
+
+    /// Request a state. The measurement loop applies it before its next
+    /// measurement.
+    /// # Arguments
+    /// * `state` - Requested state.
+    pub fn request(&self, state: MaintenanceState) {
+        self.requested.send_replace(state);
+    }
+
+    /// Request the opposite of the current request.
+    /// # Returns
+    /// * Requested state.
+    pub fn toggle(&self) -> MaintenanceState {
+        let mut state = MaintenanceState::Normal;
+        self.requested.send_modify(|requested| {
+            *requested = requested.toggled();
+            state = *requested;
+        });
+        state
+    }
+
+    /// Latest requested state.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn requested(&self) -> MaintenanceState {
+        *self.requested.borrow()
+    }
+
+    /// Receiver the measurement loop watches for requests.
+    pub fn subscribe(&self) -> watch::Receiver<MaintenanceState> {
+        self.requested.subscribe()
+    }
+
+    /// Publish the state the measurement loop has applied.
+    /// # Arguments
+    /// * `state` - Applied state.
+    pub fn set_applied(&self, state: MaintenanceState) {
+        self.applied.send_replace(state);
+    }
+
+    /// State the measurement loop has applied.
+    pub fn applied(&self) -> MaintenanceState {
+        *self.applied.borrow()
+    }
+}
+
+/// Period spent in read-only mode, reported as a data gap when it ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataGap {
+    /// When storing stopped.
+    pub from: DateTime<Local>,
+    /// When storing resumed.
+    pub to: DateTime<Local>,
+}
+
+impl DataGap {
+    /// One-line description for the log.
+    pub fn summary(&self) -> String {
+        format!(
+            "Data gap {} - {} ({}s, maintenance)",
+            self.from.to_rfc3339(),
+            self.to.to_rfc3339(),
+            (self.to - self.from).num_seconds()
+        )
+    }
+}
+
+/// Tracks the read-only period on the measurement loop side.
+#[derive(Debug, Default)]
+pub struct ReadonlyPeriod {
+    since: Option<DateTime<Local>>,
+}
+
+impl ReadonlyPeriod {
+    /// Whether storing is paused.
+    pub fn is_active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Apply a state.
+    /// # Arguments
+    /// * `state` - State to apply.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The gap that ended, when leaving read-only mode.
+    pub fn apply(&mut self, state: MaintenanceState, now: DateTime<Local>) -> Option<DataGap> {
+        match state {
+            MaintenanceState::Readonly => {
+                self.since.get_or_insert(now);
+                None
+            }
+            MaintenanceState::Normal => self.since.take().map(|from| DataGap { from, to: now }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_request_and_apply_are_separate() {
+        let maintenance = Maintenance::new();
+        let mut receiver = maintenance.subscribe();
+        maintenance.request(MaintenanceState::Readonly);
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(*receiver.borrow_and_update(), MaintenanceState::Readonly);
+        assert_eq!(maintenance.requested(), MaintenanceState::Readonly);
+        assert_eq!(maintenance.applied(), MaintenanceState::Normal);
+        maintenance.set_applied(MaintenanceState::Readonly);
+        assert_eq!(maintenance.applied(), MaintenanceState::Readonly);
+    }
+
+    #[test]
+    fn test_toggle() {
+        let maintenance = Maintenance::new();
+        assert_eq!(maintenance.toggle(), MaintenanceState::Readonly);
+        assert_eq!(maintenance.toggle(), MaintenanceState::Normal);
+        assert_eq!(maintenance.requested(), MaintenanceState::Normal);
+    }
+
+    #[test]
+    fn test_readonly_period_reports_gap() {
+        let start = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 0).unwrap();
+        let later = Local.with_ymd_and_hms(2025, 6, 16, 14, 45, 0).unwrap();
+        let mut period = ReadonlyPeriod::default();
+        assert_eq!(period.apply(MaintenanceState::Normal, start), None);
+        assert_eq!(period.apply(MaintenanceState::Readonly, start), None);
+        // A repeated request keeps the original start
+        assert_eq!(period.apply(MaintenanceState::Readonly, later), None);
+        assert!(period.is_active());
+        let gap = period.apply(MaintenanceState::Normal, later).unwrap();
+        assert_eq!(
+            gap,
+            DataGap {
+                from: start,
+                to: later
+            }
+        );
+        assert!(gap.summary().contains("900s"));
+        assert!(!period.is_active());
+    }
+}
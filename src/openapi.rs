@@ -0,0 +1,473 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Machine-readable description of the HTTP API.
+//!
+//! The OpenAPI document is maintained by hand next to the router. A test
+//! reads the routes registered in `http::router` and fails when one of them
+//! is missing here, so a new route cannot ship undocumented.
+
+use serde_json::{Value, json};
+
+use crate::config::Config;
+
+/// Optional `SensorData` fields filled in by this build and configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProducedFields {
+    /// Fan relay state. No fan control exists in this build.
+    pub fan_state: bool,
+    /// Active alert bitmask, with alert rules configured.
+    pub alert_active: bool,
+    /// Burst capture id, with the HTTP API enabled.
+    pub capture_id: bool,
+    /// Quality score, with [quality] store_score.
+    pub quality_score: bool,
+    /// Time of the latest reading, with [database] align_to_interval.
+    pub measured_at: bool,
+}
+
+impl ProducedFields {
+    /// Optional fields produced with a configuration.
+    /// # Arguments
+    /// * `config` - Configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            fan_state: false,
+            alert_active: !config.alerts.rules.is_empty(),
+            capture_id: config.http.listen.is_some(),
+            quality_score: config.quality.store_score,
+            measured_at: config.database.align_to_interval,
+        }
+    }
+}
+
+/// JSON Schema of a stored row.
+/// Optional columns are nullable and carry `x-produced`, telling whether
+/// this build and configuration ever fill them in.
+/// # Arguments
+/// * `produced` - Optional fields produced.
+/// # Returns
+/// * JSON Schema (draft 2020-12)
+pub fn sensor_data_schema(produced: &ProducedFields) -> Value {
+    let mut schema = sensor_data_properties(produced);
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema
+}
+
+fn sensor_data_properties(produced: &ProducedFields) -> Value {
+    json!({
+        "title": "SensorData",
+        "description": "Row of the sensor_data table.",
+        "type": "object",
+        "properties": {
+            "timestamp": { "type": "string", "format": "date-time" },
+            "sensor": { "type": "string", "description": "Label of the sensor." },
+            "temperature_c": { "type": "number" },
+            "humidity_relative": {
+                "type": ["number", "null"],
+                "minimum": 0,
+                "maximum": 100,
+                "description": "Null for sensors which measure no humidity.",
+            },
+            "pressure_pa": {
+                "type": ["number", "null"],
+                "description": "Null for sensors which measure no pressure.",
+            },
+            "thi": {
+                "type": ["number", "null"],
+                "description": "Temperature-humidity index, null without humidity.",
+            },
+            "dew_point_c": {
+                "type": ["number", "null"],
+                "description": "Dew point, null without humidity and for rows of older versions.",
+            },
+            "quality": { "enum": ["good", "suspect"] },
+            "source": {
+                "enum": ["live", "capture", "replay", "simulated"],
+                "description": "Mode which took the row.",
+            },
+            "fan_state": {
+                "type": ["boolean", "null"],
+                "x-produced": produced.fan_state,
+            },
+            "alert_active": {
+                "type": ["integer", "null"],
+                "minimum": 0,
+                "description": "Bit N is set while alert rule N is active.",
+                "x-produced": produced.alert_active,
+            },
+            "capture_id": {
+                "type": ["integer", "null"],
+                "description": "Burst capture the row was stored by, null for regular rows.",
+                "x-produced": produced.capture_id,
+            },
+            "quality_score": {
+                "type": ["integer", "null"],
+                "minimum": 0,
+                "maximum": 100,
+                "description": "Margin to the sensor's range limits, recent error rate and steadiness combined.",
+                "x-produced": produced.quality_score,
+            },
+            "measured_at": {
+                "type": ["string", "null"],
+                "format": "date-time",
+                "description": "Time of the latest reading when the timestamp is the boundary of the save window.",
+                "x-produced": produced.measured_at,
+            },
+            "run_id": {
+                "type": ["string", "null"],
+                "description": "Run of the program which stored the row, null for rows of older versions.",
+            },
+            "seq": {
+                "type": ["integer", "null"],
+                "minimum": 1,
+                "description": "Number of the row within its run, stored once per run_id.",
+            },
+        },
+        "required": [
+            "timestamp",
+            "sensor",
+            "temperature_c",
+            "humidity_relative",
+            "pressure_pa",
+            "thi",
+            "quality",
+            "source",
+        ],
+    })
+}
+
+/// OpenAPI document of the HTTP API.
+/// # Returns
+/// * OpenAPI 3.1 document
+pub fn document() -> Value {
+    let errors = json!({
+        "description": "Rejected",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Errors" } } },
+    });
+    let object = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        })
+    };
+    let sensor_config = json!({
+        "description": "Sensor settings",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/SensorConfig" } } },
+    });
+    let query = |name: &str, schema: Value, required: bool| json!({ "name": name, "in": "query", "required": required, "schema": schema });
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/api/current": {
+                "get": {
+                    "summary": "Latest reading of the main sensor",
+                    "responses": {
+                        "200": {
+                            "description": "Reading with offsets applied",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Current" } } },
+                        },
+                        "503": errors,
+                    },
+                },
+            },
+            "/api/info": {
+                "get": {
+                    "summary": "Version, start-up timing, timestamp counters, disk and UPS power state, display lease",
+                    "responses": { "200": object("Info") },
+                },
+            },
+            "/api/sensor/config": {
+                "get": {
+                    "summary": "Current [sensor] settings",
+                    "responses": { "200": sensor_config },
+                },
+                "put": {
+                    "summary": "Apply new [sensor] settings",
+                    "parameters": [query("persist", json!({ "type": "boolean" }), false)],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/SensorConfig" } } },
+                    },
+                    "responses": {
+                        "200": sensor_config,
+                        "409": errors,
+                        "422": errors,
+                        "500": errors,
+                    },
+                },
+            },
+            "/api/maintenance": {
+                "post": {
+                    "summary": "Request read-only maintenance mode or leave it",
+                    "parameters": [query("state", json!({ "enum": ["normal", "readonly"] }), true)],
+                    "responses": { "202": object("Requested and applied state") },
+                },
+            },
+            "/api/capture": {
+                "get": {
+                    "summary": "The active burst capture",
+                    "responses": { "200": object("Capture, active is null when idle") },
+                },
+                "post": {
+                    "summary": "Start a burst capture",
+                    "parameters": [
+                        query("rate_ms", json!({ "type": "integer", "minimum": 50, "maximum": 200 }), false),
+                        query("duration_secs", json!({ "type": "integer", "minimum": 1 }), true),
+                    ],
+                    "responses": { "202": object("Capture requested"), "409": errors, "422": errors },
+                },
+            },
+            "/api/display/lease": {
+                "get": {
+                    "summary": "The held display lease",
+                    "responses": { "200": object("Lease, active is null when the display is free") },
+                },
+                "post": {
+                    "summary": "Take over the display, the loop stops drawing until the lease ends",
+                    "parameters": [
+                        query("holder", json!({ "type": "string", "minLength": 1, "maxLength": 32 }), true),
+                        query("duration_secs", json!({ "type": "integer", "minimum": 1, "maximum": 600 }), true),
+                    ],
+                    "responses": { "201": object("Granted lease with its lease_id"), "409": errors, "422": errors },
+                },
+                "put": {
+                    "summary": "Renew the lease for duration_secs from now",
+                    "parameters": [
+                        query("lease_id", json!({ "type": "integer" }), true),
+                        query("duration_secs", json!({ "type": "integer", "minimum": 1, "maximum": 600 }), true),
+                    ],
+                    "responses": { "200": object("Renewed lease"), "409": errors, "422": errors },
+                },
+                "delete": {
+                    "summary": "Give the display back",
+                    "parameters": [query("lease_id", json!({ "type": "integer" }), true)],
+                    "responses": { "200": object("Released lease"), "409": errors },
+                },
+            },
+            "/api/qnh": {
+                "get": {
+                    "summary": "QNH of the altimeter page",
+                    "responses": { "200": object("Qnh, null fields until set"), "409": errors },
+                },
+                "post": {
+                    "summary": "Set the QNH of the altimeter page, kept across restarts",
+                    "parameters": [
+                        query("hpa", json!({ "type": "number", "minimum": 850, "maximum": 1100 }), true),
+                    ],
+                    "responses": { "200": object("Qnh"), "409": errors, "422": errors, "500": errors },
+                },
+            },
+            "/api/extremes": {
+                "get": {
+                    "summary": "Highest temperature and lowest humidity since the last reset",
+                    "responses": { "200": object("Extremes, null values until a reading"), "409": errors },
+                },
+            },
+            "/api/extremes/reset": {
+                "post": {
+                    "summary": "Start the extremes over, kept across restarts",
+                    "responses": { "200": object("Extremes"), "409": errors, "500": errors },
+                },
+            },
+            "/api/debug/trace": {
+                "get": {
+                    "summary": "The running debug trace",
+                    "responses": { "200": object("Trace, active is null when off"), "409": errors },
+                },
+                "post": {
+                    "summary": "Trace every step of the measurement loop to a file for a while",
+                    "parameters": [
+                        query("secs", json!({ "type": "integer", "minimum": 1, "default": 60 }), false),
+                    ],
+                    "responses": { "202": object("Started trace with its file"), "409": errors, "422": errors, "500": errors },
+                },
+            },
+            "/api/safemode": {
+                "get": {
+                    "summary": "The safe mode the daemon runs in after repeated crashes",
+                    "responses": { "200": object("Safe mode, active is null in normal mode") },
+                },
+            },
+            "/api/safemode/clear": {
+                "post": {
+                    "summary": "Forget the crashes and restart in normal mode",
+                    "responses": { "202": object("Safe mode being left"), "409": errors },
+                },
+            },
+            "/healthz": {
+                "get": {
+                    "summary": "Liveness, optional subsystems running degraded and the applied maintenance state",
+                    "responses": { "200": object("Health") },
+                },
+            },
+            "/api/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "responses": { "200": object("OpenAPI document") },
+                },
+            },
+            "/api/schema": {
+                "get": {
+                    "summary": "JSON Schema of the stored rows, with the optional fields this configuration produces",
+                    "responses": { "200": object("JSON Schema of SensorData") },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "SensorConfig": {
+                    "type": "object",
+                    "properties": {
+                        "oversampling_temperature": { "enum": [1, 2, 4, 8, 16] },
+                        "oversampling_pressure": { "enum": [0, 1, 2, 4, 8, 16] },
+                        "oversampling_humidity": { "enum": [0, 1, 2, 4, 8, 16] },
+                        "filter": { "enum": [0, 2, 4, 8, 16] },
+                        "temperature_offset_c": { "type": "number" },
+                        "humidity_offset": { "type": "number" },
+                        "pressure_offset_pa": { "type": "number" },
+                        "sea_level_pa": { "type": "number", "minimum": 85000, "maximum": 110000 },
+                        "interval_ms": { "type": "integer", "minimum": 50, "maximum": 60000 },
+                    },
+                },
+                "SensorData": sensor_data_properties(&ProducedFields {
+                    fan_state: true,
+                    alert_active: true,
+                    capture_id: true,
+                    quality_score: true,
+                    measured_at: true,
+                }),
+                "Current": {
+                    "type": "object",
+                    "properties": {
+                        "timestamp": { "type": "string", "format": "date-time" },
+                        "sensor": { "type": "string" },
+                        "temperature_c": { "type": "number" },
+                        "humidity_relative": { "type": "number", "minimum": 0, "maximum": 100 },
+                        "pressure_pa": { "type": "number" },
+                        "altitude_m": { "type": "number", "description": "Altitude from the pressure and [sensor] sea_level_pa." },
+                        "dew_point_c": { "type": ["number", "null"], "description": "Dew point, null without humidity." },
+                        "absolute_humidity_g_m3": { "type": ["number", "null"], "description": "Absolute humidity in g/m³, null without humidity." },
+                        "thi": { "type": "number" },
+                        "source": { "enum": ["live", "capture", "simulated"] },
+                        "daily_metrics": { "$ref": "#/components/schemas/DailyMetrics" },
+                    },
+                    "required": ["timestamp", "sensor", "temperature_c", "humidity_relative", "pressure_pa", "altitude_m", "dew_point_c", "absolute_humidity_g_m3", "thi", "source"],
+                },
+                "DailyMetrics": {
+                    "type": "object",
+                    "description": "Degree-hours and mold risk, only with [daily_metrics] enabled.",
+                    "properties": {
+                        "heating_degree_hours": { "type": "number", "minimum": 0 },
+                        "cooling_degree_hours": { "type": "number", "minimum": 0 },
+                        "mold_risk_hours": { "type": "number", "minimum": 0, "maximum": 24 },
+                    },
+                    "required": ["heating_degree_hours", "cooling_degree_hours", "mold_risk_hours"],
+                },
+                "Errors": {
+                    "type": "object",
+                    "properties": {
+                        "errors": { "type": "array", "items": { "type": "string" } },
+                    },
+                    "required": ["errors"],
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(path, method)` of every route registered in `http::router`, read
+    /// from its source.
+    fn registered_routes() -> Vec<(String, String)> {
+        let source = include_str!("http.rs");
+        let start = source.find("pub fn router(").unwrap();
+        let end = start + source[start..].find(".with_state(").unwrap();
+        let mut routes = Vec::new();
+        for call in source[start..end].split(".route(").skip(1) {
+            let path = call.split('"').nth(1).unwrap().to_string();
+            for method in ["get", "put", "post", "delete", "patch"] {
+                let pattern = format!("{}(", method);
+                let called = call.match_indices(&pattern).any(|(i, _)| {
+                    let before = call.as_bytes()[..i].last().copied().unwrap_or(b' ');
+                    !before.is_ascii_alphanumeric() && before != b'_'
+                });
+                if called {
+                    routes.push((path.clone(), method.to_string()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let routes = registered_routes();
+        assert!(routes.contains(&("/api/capture".to_string(), "post".to_string())));
+
+        let document = document();
+        for (path, method) in &routes {
+            assert!(
+                document["paths"][path][method].is_object(),
+                "{} {} is not in the OpenAPI document",
+                method.to_uppercase(),
+                path
+            );
+        }
+        let documented: usize = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|item| item.as_object().unwrap().len())
+            .sum();
+        assert_eq!(documented, routes.len(), "documented routes: {:?}", routes);
+    }
+
+    #[test]
+    fn test_sensor_data_schema_produced_fields() {
+        let mut config = Config::default();
+        let schema = sensor_data_schema(&ProducedFields::from_config(&config));
+        assert_eq!(schema["title"], "SensorData");
+        assert_eq!(schema["properties"]["alert_active"]["x-produced"], false);
+        assert_eq!(schema["properties"]["capture_id"]["x-produced"], false);
+        assert_eq!(schema["properties"]["fan_state"]["x-produced"], false);
+        assert_eq!(schema["properties"]["quality_score"]["x-produced"], false);
+
+        config.http.listen = Some("127.0.0.1:8080".to_string());
+        config.quality.store_score = true;
+        let schema = sensor_data_schema(&ProducedFields::from_config(&config));
+        assert_eq!(schema["properties"]["capture_id"]["x-produced"], true);
+        assert_eq!(schema["properties"]["quality_score"]["x-produced"], true);
+        assert!(
+            schema["required"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("quality"))
+        );
+    }
+}
@@ -0,0 +1,898 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Display pages.
+//!
+//! Every page is rendered as plain text lines first, so the layout can be
+//! checked without hardware. Pages adapt to the `DisplayGeometry` of the
+//! display: lines are as wide as the display, and 4 lines show the whole
+//! measurement on the main page and more units per peers page. The
+//! snapshot tests render each page in `Page::ALL` with fixed fixtures on the
+//! 16x2 and 20x4 geometries and compare the grid with the golden files in
+//! `src/page/snapshots/<cols>x<rows>`. Run the tests with
+//! `UPDATE_SNAPSHOTS=1` to write missing or changed golden files, then
+//! review them.
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::Measurement;
+use peripheral::display::{CharDisplay, DisplayGeometry};
+use rppal::i2c;
+
+use crate::altimeter::QnhStatus;
+use crate::daily_metrics::DailyMetricsStatus;
+use crate::extremes::ExtremesStatus;
+use crate::helper::{self, metrics};
+use crate::peers::PeerSummary;
+
+/// Shown in read-only maintenance mode.
+const READONLY_ICON: &str = "RO";
+
+/// Shown before the name of an active alert.
+const ALERT_MARK: &str = "! ";
+
+/// Shown with the exit code of the last crash in safe mode.
+const SAFE_MODE_TEXT: &str = "SAFE MODE";
+
+/// Time shown beside `SAFE_MODE_TEXT` where it fits.
+const SAFE_MODE_CLOCK_FORMAT: &str = "%H:%M";
+
+/// Format of the clock line.
+const CLOCK_FORMAT: &str = "%Y/%m/%d %H:%M";
+
+/// Format of the clock line without the year, leaving room for `RO`.
+const SHORT_CLOCK_FORMAT: &str = "%m/%d %H:%M";
+
+/// Columns of a peers line after the name: " 24.1C  55%".
+const PEER_VALUE_COLUMNS: usize = 11;
+
+/// Page shown on the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// Clock and temperature, humidity and THI.
+    Main,
+    /// Temperature and humidity of other units, one per display line.
+    Peers(usize),
+    /// Indicated altitude at the QNH setting.
+    Altimeter,
+    /// Degree-hours of the day and the mold-risk index.
+    DegreeHours,
+    /// Highest temperature and lowest humidity since last viewed.
+    Extremes,
+}
+
+// Only the snapshot tests enumerate the pages so far
+#[cfg_attr(not(test), allow(dead_code))]
+impl Page {
+    /// All pages. A page missing here has no snapshot test.
+    pub const ALL: &'static [Page] = &[
+        Page::Main,
+        Page::Peers(0),
+        Page::Altimeter,
+        Page::DegreeHours,
+        Page::Extremes,
+    ];
+
+    /// Name of the page, used for the snapshot files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Page::Main => "main",
+            Page::Peers(_) => "peers",
+            Page::Altimeter => "altimeter",
+            Page::DegreeHours => "degree_hours",
+            Page::Extremes => "extremes",
+        }
+    }
+}
+
+/// Data rendered on the pages.
+#[derive(Debug, Clone)]
+pub struct PageContext<'a> {
+    /// Size of the display.
+    pub geometry: DisplayGeometry,
+    /// Current time.
+    pub now: DateTime<Local>,
+    /// Whether the clock is synchronized.
+    pub clock_synced: bool,
+    /// Latest measurement.
+    pub measurement: Measurement,
+    /// Temperature-humidity index of the measurement.
+    pub thi: f64,
+    /// Precision of the temperature and humidity.
+    pub format: helper::MeasurementFormat,
+    /// Whether read-only maintenance mode is active.
+    pub readonly: bool,
+    /// Exit code of the last crash while in safe mode, shown instead of
+    /// the clock.
+    pub safe_mode: Option<u8>,
+    /// Name of the active alert, shown instead of the clock.
+    pub alert: Option<&'a str>,
+    /// Visible part of the scrolling alert message, shown instead of the
+    /// measurement on 2 lines and below it on 4.
+    pub alert_message: Option<&'a str>,
+    /// Current frame of the activity indicator.
+    pub indicator: &'a str,
+    /// Other units, for the peers pages.
+    pub peers: &'a [PeerSummary],
+    /// QNH setting, for the altimeter page.
+    pub qnh: Option<QnhStatus>,
+    /// Extremes since last viewed, for the extremes page.
+    pub extremes: Option<ExtremesStatus>,
+    /// Degree-hours and mold risk, for the degree-hours page.
+    pub daily_metrics: Option<DailyMetricsStatus>,
+}
+
+impl PageContext<'_> {
+    /// Characters per line of the display.
+    fn columns(&self) -> usize {
+        usize::from(self.geometry.cols)
+    }
+
+    /// Fit text to one line of the display.
+    fn fit(&self, text: &str) -> String {
+        helper::fit_line(text, self.columns())
+    }
+}
+
+/// Render a page.
+/// # Arguments
+/// * `page` - Page to render.
+/// * `context` - Data to render.
+/// # Returns
+/// * One line per display row, each as wide as the display.
+pub fn render(page: Page, context: &PageContext) -> Vec<String> {
+    let lines = match page {
+        Page::Main => render_main(context),
+        Page::Peers(page) => render_peers(context.peers, page, context.geometry),
+        Page::Altimeter => render_altimeter(context),
+        Page::DegreeHours => render_degree_hours(context.daily_metrics, context.columns()),
+        Page::Extremes => render_extremes(context.extremes, context.columns()),
+    };
+    fill_rows(lines, context.geometry)
+}
+
+/// Draw rendered lines on the display.
+/// # Arguments
+/// * `display` - Display.
+/// * `lines` - Lines from `render`.
+/// # Returns
+/// * Result<(), i2c::Error>
+pub fn draw<D: CharDisplay>(display: &D, lines: &[String]) -> Result<(), i2c::Error> {
+    for (row, line) in lines.iter().enumerate() {
+        display.put_str(display.line_address(row as u8), line)?;
+    }
+    Ok(())
+}
+
+/// Render lines on every row of the display, blank below the text.
+/// # Arguments
+/// * `text` - Text of the first lines.
+/// * `geometry` - Size of the display.
+/// # Returns
+/// * One line per display row, each as wide as the display.
+pub fn render_text(text: &[&str], geometry: DisplayGeometry) -> Vec<String> {
+    let columns = usize::from(geometry.cols);
+    let lines = text
+        .iter()
+        .map(|line| helper::fit_line(line, columns))
+        .collect();
+    fill_rows(lines, geometry)
+}
+
+/// Add blank lines up to the rows of the display, dropping lines past them.
+fn fill_rows(mut lines: Vec<String>, geometry: DisplayGeometry) -> Vec<String> {
+    let blank = helper::fit_line("", usize::from(geometry.cols));
+    lines.resize(usize::from(geometry.rows), blank);
+    lines
+}
+
+/// Render the fault screen shown while running without the sensor: the
+/// clock, and the failed subsystem with the activity indicator.
+/// # Arguments
+/// * `now` - Current time.
+/// * `clock_synced` - Whether the clock is synchronized.
+/// * `subsystem` - Name of the failed subsystem, e.g. "sensor".
+/// * `indicator` - Current frame of the activity indicator.
+/// * `geometry` - Size of the display.
+/// # Returns
+/// * One line per display row, each as wide as the display.
+pub fn render_fault(
+    now: DateTime<Local>,
+    clock_synced: bool,
+    subsystem: &str,
+    indicator: &str,
+    geometry: DisplayGeometry,
+) -> Vec<String> {
+    let columns = usize::from(geometry.cols);
+    let clock_line = if clock_synced {
+        helper::fit_line(&now.format(CLOCK_FORMAT).to_string(), columns)
+    } else {
+        helper::fit_line(helper::TIME_NOT_SET, columns)
+    };
+    let fault_line = with_indicator(
+        &format!("NO {}", subsystem.to_uppercase()),
+        indicator,
+        columns,
+    );
+    fill_rows(vec![clock_line, fault_line], geometry)
+}
+
+/// Fit text to a line whose last column holds the activity indicator.
+fn with_indicator(text: &str, indicator: &str, columns: usize) -> String {
+    let mut line: String = helper::fit_line(text, columns)
+        .chars()
+        .take(columns - 1)
+        .collect();
+    line.push_str(indicator);
+    line
+}
+
+/// Put `left` at the start of a line and `right` at its end.
+fn spread(left: &str, right: &str, columns: usize) -> String {
+    let width = columns.saturating_sub(left.chars().count());
+    helper::fit_line(&format!("{}{:>width$}", left, right), columns)
+}
+
+/// Render the 1st line of the main page: the clock, or the name of an
+/// active alert. Safe mode shows `SAFE MODE E<code>` instead of the clock,
+/// with the time where it fits. In read-only mode `RO` takes the end of
+/// the line, and the year is dropped when there is no room for both.
+fn render_clock_line(context: &PageContext) -> String {
+    let columns = context.columns();
+    let clock = context.now.format(CLOCK_FORMAT).to_string();
+    let clock_format = if context.readonly && clock.len() + 1 + READONLY_ICON.len() > columns {
+        SHORT_CLOCK_FORMAT
+    } else {
+        CLOCK_FORMAT
+    };
+    let mut clock_line = if let Some(alert) = context.alert {
+        context.fit(&format!("{}{}", ALERT_MARK, alert))
+    } else if let Some(code) = context.safe_mode {
+        let text = format!("{} E{}", SAFE_MODE_TEXT, code);
+        let time = context.now.format(SAFE_MODE_CLOCK_FORMAT).to_string();
+        if context.clock_synced && text.len() + 1 + time.len() <= columns {
+            spread(&text, &time, columns)
+        } else {
+            context.fit(&text)
+        }
+    } else if context.clock_synced {
+        context.fit(&context.now.format(clock_format).to_string())
+    } else {
+        context.fit(helper::TIME_NOT_SET)
+    };
+    if context.readonly {
+        clock_line = clock_line
+            .chars()
+            .take(columns - READONLY_ICON.len())
+            .collect();
+        clock_line.push_str(READONLY_ICON);
+    }
+    clock_line
+}
+
+/// Render the main page.
+/// On 2 lines the activity indicator takes the last column of the 2nd
+/// line. An active alert replaces the clock on the 1st line, and its
+/// message the measurement on the 2nd. Displays with 4 lines show the
+/// whole measurement, see `render_main_4_lines`.
+fn render_main(context: &PageContext) -> Vec<String> {
+    let clock_line = render_clock_line(context);
+    if context.geometry.rows >= 4 {
+        return render_main_4_lines(context, clock_line);
+    }
+    let measurement_line = match context.alert_message {
+        Some(message) => message.to_string(),
+        None => helper::format_measurement_line(
+            context.measurement.temperature_c,
+            context.measurement.humidity_relative,
+            context.thi,
+            &context.format,
+        ),
+    };
+    let measurement_line = with_indicator(&measurement_line, context.indicator, context.columns());
+    vec![clock_line, measurement_line]
+}
+
+/// Render the main page on 4 lines: the clock, the temperature and
+/// humidity ("T 23.7C     H 65.2%"), the station pressure and THI
+/// ("P 1008.2hPa   THI 72"), then the dew point ("DP 16.7C") or the alert
+/// message with the activity indicator in the last column. Only the dew
+/// point gives way to an alert.
+fn render_main_4_lines(context: &PageContext, clock_line: String) -> Vec<String> {
+    let columns = context.columns();
+    let format = &context.format;
+    let measurement = &context.measurement;
+    let climate_line = spread(
+        &format!("T {}C", format.temperature(measurement.temperature_c)),
+        &format!("H {}%", format.humidity(measurement.humidity_relative)),
+        columns,
+    );
+    let pressure_line = spread(
+        &format!("P {}hPa", format.pressure_hpa(measurement.pressure_pa)),
+        &format!("THI {}", format.thi(context.thi)),
+        columns,
+    );
+    let dew_point = helper::dew_point_c(measurement.temperature_c, measurement.humidity_relative);
+    let dew_point_line = format!("DP {}C", format.temperature(dew_point));
+    let message_line = with_indicator(
+        context.alert_message.unwrap_or(&dew_point_line),
+        context.indicator,
+        columns,
+    );
+    vec![clock_line, climate_line, pressure_line, message_line]
+}
+
+/// Render the altimeter page: the QNH and the altitude it indicates at the
+/// station pressure ("QNH1018 ALT 447m"), then when the QNH was set. A QNH
+/// older than the staleness limit is marked `STALE!`. The station pressure
+/// follows on displays with more lines.
+fn render_altimeter(context: &PageContext) -> Vec<String> {
+    let station_hpa = context.measurement.pressure_pa / 100.0;
+    let station_line = context.fit(&format!(
+        "STN {}hPa",
+        context.format.pressure_hpa(context.measurement.pressure_pa)
+    ));
+    match context.qnh {
+        Some(qnh) => {
+            let altitude = metrics::indicated_altitude_m(station_hpa, qnh.hpa);
+            let state = if qnh.stale { "STALE!" } else { "SET" };
+            vec![
+                context.fit(&format!(
+                    "QNH{:>4.0} ALT{:>5}",
+                    qnh.hpa,
+                    format!("{:.0}m", altitude)
+                )),
+                context.fit(&format!("{} {} AGO", state, format_age(qnh.age))),
+                station_line,
+            ]
+        }
+        None => vec![context.fit("QNH NOT SET"), station_line],
+    }
+}
+
+/// Render the extremes page: the highest temperature and the lowest
+/// humidity ("HI 26.4C LO 41%"), then how long ago they were reset
+/// ("SINCE 3h"). An extreme without a reading yet shows "--".
+fn render_extremes(extremes: Option<ExtremesStatus>, columns: usize) -> Vec<String> {
+    let Some(extremes) = extremes else {
+        return vec![helper::fit_line("NO EXTREMES", columns)];
+    };
+    let temperature = extremes
+        .max_temperature_c
+        .map_or_else(|| format!("{:>5}", "--"), |t| format!("{:>5.1}", t));
+    let humidity = extremes
+        .min_humidity_relative
+        .map_or_else(|| format!("{:>3}", "--"), |h| format!("{:>3.0}", h));
+    vec![
+        helper::fit_line(&format!("HI{}C LO{}%", temperature, humidity), columns),
+        helper::fit_line(&format!("SINCE {}", format_age(extremes.age)), columns),
+    ]
+}
+
+/// Render the degree-hours page: the heating and cooling degree-hours of
+/// the day ("DH H 12.3 C  0.0"), then the hours above the mold humidity
+/// threshold in the last 24 hours ("MOLD  6.5h/24h").
+fn render_degree_hours(daily_metrics: Option<DailyMetricsStatus>, columns: usize) -> Vec<String> {
+    let Some(metrics) = daily_metrics else {
+        return vec![helper::fit_line("NO DEGREE HOURS", columns)];
+    };
+    vec![
+        helper::fit_line(
+            &format!(
+                "DH H{:>5.1} C{:>5.1}",
+                metrics.heating_degree_hours, metrics.cooling_degree_hours
+            ),
+            columns,
+        ),
+        helper::fit_line(
+            &format!("MOLD{:>5.1}h/24h", metrics.mold_risk_hours),
+            columns,
+        ),
+    ]
+}
+
+/// Render a peers page, one unit per line: "Liv   24.1C  55%". Wider
+/// displays have room for longer names.
+/// A unit without a current reading shows "--" and the age of its last
+/// reading.
+fn render_peers(peers: &[PeerSummary], page: usize, geometry: DisplayGeometry) -> Vec<String> {
+    let per_page = usize::from(geometry.rows);
+    let columns = usize::from(geometry.cols);
+    let name_width = columns.saturating_sub(PEER_VALUE_COLUMNS);
+    peers
+        .iter()
+        .skip(page * per_page)
+        .take(per_page)
+        .map(|unit| {
+            let line = match unit {
+                PeerSummary {
+                    name,
+                    reading: Some(reading),
+                    ..
+                } => format!(
+                    "{:<name_width$}{:>5.1}C{:>4.0}%",
+                    name, reading.temperature_c, reading.humidity_relative
+                ),
+                PeerSummary { name, age, .. } => format!(
+                    "{:<name_width$}{:>6}{:>5}",
+                    name,
+                    "--",
+                    age.map(format_age).unwrap_or_default()
+                ),
+            };
+            helper::fit_line(&line, columns)
+        })
+        .collect()
+}
+
+/// Age in its largest unit: "45s", "12m", "3h", "2d".
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..172_800 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use peripheral::display::MockDisplay;
+    use peripheral::so1602a::{SO1602A_GEOMETRY, SO2004A_GEOMETRY};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Fixture rendered on every page.
+    struct Fixture {
+        name: &'static str,
+        clock_synced: bool,
+        readonly: bool,
+        alert: Option<&'static str>,
+        /// Visible part of the alert message.
+        alert_message: Option<&'static str>,
+        measurement: Measurement,
+        /// The second peer stopped answering 12 minutes ago.
+        peer_failed: bool,
+        /// Hours since the QNH of 1018 hPa was set, `None` if not set.
+        qnh_age_hours: Option<u64>,
+        /// Exit code of the last crash in safe mode.
+        safe_mode: Option<u8>,
+    }
+
+    fn fixtures() -> Vec<Fixture> {
+        let normal = Measurement {
+            temperature_c: 23.7,
+            pressure_pa: 100_820.0,
+            humidity_relative: 65.2,
+        };
+        vec![
+            Fixture {
+                name: "normal",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "negative_temperature",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: Measurement {
+                    temperature_c: -12.3,
+                    ..normal
+                },
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "full_humidity",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: Measurement {
+                    humidity_relative: 100.0,
+                    ..normal
+                },
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "high_pressure",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: Measurement {
+                    pressure_pa: 103_550.0,
+                    ..normal
+                },
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "missing_humidity",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: Measurement {
+                    humidity_relative: f64::NAN,
+                    ..normal
+                },
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "dry",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: Measurement {
+                    humidity_relative: 0.0,
+                    ..normal
+                },
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "time_not_set",
+                clock_synced: false,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "readonly",
+                clock_synced: true,
+                readonly: true,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "alert",
+                clock_synced: true,
+                readonly: false,
+                alert: Some("HOT"),
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "alert_message",
+                clock_synced: true,
+                readonly: false,
+                alert: Some("WET"),
+                alert_message: Some("HIGH HUMIDITY 8"),
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "peer_failed",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: true,
+                qnh_age_hours: Some(3),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "qnh_stale",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(14),
+                safe_mode: None,
+            },
+            Fixture {
+                name: "qnh_not_set",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: None,
+                safe_mode: None,
+            },
+            Fixture {
+                name: "safe_mode",
+                clock_synced: true,
+                readonly: false,
+                alert: None,
+                alert_message: None,
+                measurement: normal,
+                peer_failed: false,
+                qnh_age_hours: Some(3),
+                safe_mode: Some(8),
+            },
+        ]
+    }
+
+    /// Other units of the fixture.
+    fn peers(fixture: &Fixture) -> Vec<PeerSummary> {
+        let unit = |name: &str, temperature_c, humidity_relative| PeerSummary {
+            name: name.to_string(),
+            reading: Some(crate::peers::PeerReading {
+                temperature_c,
+                humidity_relative,
+            }),
+            age: Some(std::time::Duration::from_secs(20)),
+        };
+        let mut bed = unit("Bed", 22.8, 60.0);
+        if fixture.peer_failed {
+            bed.reading = None;
+            bed.age = Some(std::time::Duration::from_secs(12 * 60));
+        }
+        vec![unit("Liv", 24.1, 55.0), bed, unit("Kit", -3.5, 100.0)]
+    }
+
+    /// Extremes of the fixture, reset 3 hours ago.
+    fn extremes(fixture: &Fixture) -> Option<ExtremesStatus> {
+        let humidity = fixture.measurement.humidity_relative;
+        Some(ExtremesStatus {
+            max_temperature_c: Some(fixture.measurement.temperature_c + 2.3),
+            min_humidity_relative: (!humidity.is_nan()).then_some(humidity - 20.0),
+            age: std::time::Duration::from_secs(3 * 3600),
+        })
+    }
+
+    /// Degree-hours of the fixture, colder days have more heating.
+    fn daily_metrics(fixture: &Fixture) -> Option<DailyMetricsStatus> {
+        let temperature = fixture.measurement.temperature_c;
+        let humidity = fixture.measurement.humidity_relative;
+        Some(DailyMetricsStatus {
+            heating_degree_hours: (18.0 - temperature).max(0.0) * 14.5,
+            cooling_degree_hours: (temperature - 18.0).max(0.0) * 14.5,
+            mold_risk_hours: if humidity > 70.0 { 14.5 } else { 2.5 },
+        })
+    }
+
+    /// QNH of the fixture.
+    fn qnh(fixture: &Fixture) -> Option<QnhStatus> {
+        fixture.qnh_age_hours.map(|hours| QnhStatus {
+            hpa: 1018.0,
+            age: std::time::Duration::from_secs(hours * 3600),
+            stale: hours > 12,
+        })
+    }
+
+    /// Geometries of the snapshot tests.
+    const GEOMETRIES: [DisplayGeometry; 2] = [SO1602A_GEOMETRY, SO2004A_GEOMETRY];
+
+    fn snapshot_path(page: Page, fixture: &str, geometry: DisplayGeometry) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/page/snapshots")
+            .join(format!("{}x{}", geometry.cols, geometry.rows))
+            .join(format!("{}__{}.txt", page.name(), fixture))
+    }
+
+    /// Render a page through a mock display of the geometry.
+    fn render_grid(page: Page, fixture: &Fixture, geometry: DisplayGeometry) -> String {
+        let context = PageContext {
+            geometry,
+            now: Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap(),
+            clock_synced: fixture.clock_synced,
+            measurement: fixture.measurement,
+            thi: crate::calc_thi(
+                fixture.measurement.temperature_c,
+                fixture.measurement.humidity_relative,
+                &helper::ThiCoefficients::STANDARD,
+            ),
+            indicator: "\u{1}",
+            format: helper::MeasurementFormat::default(),
+            readonly: fixture.readonly,
+            safe_mode: fixture.safe_mode,
+            alert: fixture.alert,
+            alert_message: fixture.alert_message,
+            peers: &peers(fixture),
+            qnh: qnh(fixture),
+            extremes: extremes(fixture),
+            daily_metrics: daily_metrics(fixture),
+        };
+        let display = MockDisplay::with_geometry(geometry);
+        draw(&display, &render(page, &context)).unwrap();
+        display
+            .grid()
+            .iter()
+            .map(|line| format!("|{}|\n", line))
+            .collect()
+    }
+
+    #[test]
+    fn test_page_snapshots() {
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        let mut mismatches = Vec::new();
+        for geometry in GEOMETRIES {
+            for &page in Page::ALL {
+                for fixture in fixtures() {
+                    let path = snapshot_path(page, fixture.name, geometry);
+                    let actual = render_grid(page, &fixture, geometry);
+                    let expected = fs::read_to_string(&path).unwrap_or_default();
+                    if actual == expected {
+                        continue;
+                    }
+                    if update {
+                        fs::create_dir_all(path.parent().unwrap()).unwrap();
+                        fs::write(&path, &actual).unwrap();
+                    } else {
+                        mismatches.push(format!(
+                            "{}:\n--- expected\n{}--- actual\n{}",
+                            path.display(),
+                            expected,
+                            actual
+                        ));
+                    }
+                }
+            }
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_every_page_has_snapshots() {
+        for geometry in GEOMETRIES {
+            for &page in Page::ALL {
+                for fixture in fixtures() {
+                    let path = snapshot_path(page, fixture.name, geometry);
+                    assert!(path.exists(), "missing snapshot {}", path.display());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_peers_pages() {
+        let fixture = &fixtures()[0];
+        let peers = peers(fixture);
+        let last = render_peers(&peers, 1, SO1602A_GEOMETRY);
+        assert_eq!(last, vec!["Kit   -3.5C 100%".to_string()]);
+        // All units on one page of 4 lines, with longer names
+        let all = render_peers(&peers, 0, SO2004A_GEOMETRY);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2], "Kit       -3.5C 100%");
+        assert!(render_peers(&peers, 1, SO2004A_GEOMETRY).is_empty());
+        assert_eq!(format_age(std::time::Duration::from_secs(45)), "45s");
+        assert_eq!(format_age(std::time::Duration::from_secs(3 * 3600)), "3h");
+        assert_eq!(format_age(std::time::Duration::from_secs(3 * 86_400)), "3d");
+    }
+
+    #[test]
+    fn test_render_extremes() {
+        let empty = ExtremesStatus {
+            max_temperature_c: None,
+            min_humidity_relative: None,
+            age: std::time::Duration::from_secs(30),
+        };
+        let lines = render_extremes(Some(empty), helper::DISPLAY_COLUMNS);
+        assert_eq!(lines[0], "HI   --C LO --% ");
+        assert_eq!(lines[1], "SINCE 30s       ");
+        let lines = render_extremes(
+            Some(ExtremesStatus {
+                max_temperature_c: Some(-12.3),
+                min_humidity_relative: Some(100.0),
+                ..empty
+            }),
+            helper::DISPLAY_COLUMNS,
+        );
+        assert_eq!(lines[0], "HI-12.3C LO100% ");
+    }
+
+    #[test]
+    fn test_render_degree_hours() {
+        let lines = render_degree_hours(
+            Some(DailyMetricsStatus {
+                heating_degree_hours: 123.4,
+                cooling_degree_hours: 0.0,
+                mold_risk_hours: 24.0,
+            }),
+            helper::DISPLAY_COLUMNS,
+        );
+        assert_eq!(lines[0], "DH H123.4 C  0.0");
+        assert_eq!(lines[1], "MOLD 24.0h/24h  ");
+        assert_eq!(
+            render_degree_hours(None, helper::DISPLAY_COLUMNS)[0],
+            "NO DEGREE HOURS "
+        );
+    }
+
+    #[test]
+    fn test_render_fault() {
+        let now = Local.with_ymd_and_hms(2025, 6, 1, 12, 34, 0).unwrap();
+
+        let lines = render_fault(now, true, "sensor", "|", SO1602A_GEOMETRY);
+
+        assert_eq!(lines[0], "2025/06/01 12:34");
+        assert_eq!(lines[1], "NO SENSOR      |");
+        let lines = render_fault(now, false, "sensor", "|", SO1602A_GEOMETRY);
+        assert_eq!(
+            lines[0],
+            helper::fit_line(helper::TIME_NOT_SET, helper::DISPLAY_COLUMNS)
+        );
+        let lines = render_fault(now, true, "sensor", "|", SO2004A_GEOMETRY);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "NO SENSOR          |");
+        assert_eq!(lines[3], " ".repeat(20));
+    }
+
+    #[test]
+    fn test_render_lines_fit_display() {
+        for geometry in GEOMETRIES {
+            for &page in Page::ALL {
+                for fixture in fixtures() {
+                    let context = PageContext {
+                        geometry,
+                        now: Local::now(),
+                        clock_synced: fixture.clock_synced,
+                        measurement: fixture.measurement,
+                        thi: 70.0,
+                        indicator: "|",
+                        format: helper::MeasurementFormat::default(),
+                        readonly: fixture.readonly,
+                        safe_mode: fixture.safe_mode,
+                        alert: fixture.alert,
+                        alert_message: fixture.alert_message,
+                        peers: &peers(&fixture),
+                        qnh: qnh(&fixture),
+                        extremes: extremes(&fixture),
+                        daily_metrics: daily_metrics(&fixture),
+                    };
+                    let lines = render(page, &context);
+                    assert_eq!(lines.len(), usize::from(geometry.rows));
+                    for line in lines {
+                        assert_eq!(line.chars().count(), usize::from(geometry.cols));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Readings of other units, shown on the peers page.
+//!
+//! A background task polls `GET /api/current` of every unit in [peers] and
+//! keeps the last valid answer. A unit which does not answer in time, or
+//! answers something other than a current reading, keeps its last reading
+//! with its age, and the page shows "--" for it until it answers again. The
+//! display alternates between the main page and as many peers pages as
+//! needed, two units per page.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::{MissedTickBehavior, interval, timeout};
+
+use crate::config::PeersConfig;
+use crate::hooks;
+use crate::page::Page;
+use crate::supervisor::Supervisor;
+
+/// Units shown on one peers page of a 2 line display, one per line.
+pub const PEERS_PER_PAGE: usize = 2;
+
+/// Reading of another unit, as answered by its `GET /api/current`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct PeerReading {
+    pub temperature_c: f64,
+    pub humidity_relative: f64,
+}
+
+impl PeerReading {
+    /// Validate an answer of `GET /api/current`.
+    /// # Arguments
+    /// * `body` - JSON body.
+    /// # Returns
+    /// * `Err(message)` if a field is missing, not a number or out of range.
+    pub fn from_json(body: Value) -> Result<Self, String> {
+        let reading: PeerReading =
+            serde_json::from_value(body).map_err(|e| format!("not a current reading: {}", e))?;
+        if !reading.temperature_c.is_finite() {
+            return Err(format!("invalid temperature_c {}", reading.temperature_c));
+        }
+        if !(0.0..=100.0).contains(&reading.humidity_relative) {
+            return Err(format!(
+                "humidity_relative {} outside 0-100",
+                reading.humidity_relative
+            ));
+        }
+        Ok(reading)
+    }
+}
+
+/// A unit as shown on the peers page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSummary {
+    pub name: String,
+    /// Latest reading, `None` while the unit fails or its reading is stale.
+    pub reading: Option<PeerReading>,
+    /// Time since the last valid reading, `None` if there never was one.
+    pub age: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct PeerState {
+    name: String,
+    last: Option<(PeerReading, Instant)>,
+    failing: bool,
+}
+
+/// Last readings of the units, shared with the poll task.
+#[derive(Debug)]
+pub struct PeerCache {
+    peers: Mutex<Vec<PeerState>>,
+    stale_after: Duration,
+}
+
+impl PeerCache {
+    /// Create an empty cache.
+    /// # Arguments
+    /// * `names` - Names of the units, in display order.
+    /// * `stale_after` - Age after which a reading is no longer shown.
+    pub fn new(names: Vec<String>, stale_after: Duration) -> Self {
+        let peers = names
+            .into_iter()
+            .map(|name| PeerState {
+                name,
+                last: None,
+                failing: false,
+            })
+            .collect();
+        Self {
+            peers: Mutex::new(peers),
+            stale_after,
+        }
+    }
+
+    /// Store the result of a poll. A failure is logged when it starts, not
+    /// on every poll.
+    /// # Arguments
+    /// * `index` - Index of the unit.
+    /// * `result` - Reading or the reason the poll failed.
+    /// * `now` - Time of the poll.
+    pub fn record(&self, index: usize, result: Result<PeerReading, String>, now: Instant) {
+        let mut peers = self.lock();
+        let Some(peer) = peers.get_mut(index) else {
+            return;
+        };
+        match result {
+            Ok(reading) => {
+                if peer.failing {
+                    println!("Peer {} is back.", peer.name);
+                }
+                peer.last = Some((reading, now));
+                peer.failing = false;
+            }
+            Err(e) => {
+                if !peer.failing {
+                    eprintln!("Peer {} failed: {}", peer.name, e);
+                }
+                peer.failing = true;
+            }
+        }
+    }
+
+    /// The units as shown on the peers page.
+    /// # Arguments
+    /// * `now` - Current time.
+    pub fn summaries(&self, now: Instant) -> Vec<PeerSummary> {
+        self.lock()
+            .iter()
+            .map(|peer| {
+                let age = peer.last.map(|(_, at)| now.saturating_duration_since(at));
+                let fresh = !peer.failing && age.is_some_and(|age| age < self.stale_after);
+                PeerSummary {
+                    name: peer.name.clone(),
+                    reading: peer.last.filter(|_| fresh).map(|(reading, _)| reading),
+                    age,
+                }
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PeerState>> {
+        self.peers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// URL of the current reading of a unit.
+/// # Arguments
+/// * `base` - Base URL of the unit, e.g. "http://kitchen.local:8080".
+pub fn current_url(base: &str) -> String {
+    format!("{}/api/current", base.trim_end_matches('/'))
+}
+
+/// Ask a unit for its current reading.
+/// # Arguments
+/// * `url` - URL of its `GET /api/current`.
+/// * `limit` - Time the unit has to answer.
+/// # Returns
+/// * `Err(message)` if it does not answer in time or answers something else.
+pub async fn fetch_current(url: &str, limit: Duration) -> Result<PeerReading, String> {
+    match timeout(limit, hooks::get_json(url)).await {
+        Ok(body) => body.and_then(PeerReading::from_json),
+        Err(_) => Err(format!("no answer within {} s", limit.as_secs())),
+    }
+}
+
+/// Poll every unit in the background.
+/// Units are polled one after another, each bounded by the timeout.
+/// # Arguments
+/// * `config` - Peers configuration with at least one unit.
+/// * `supervisor` - Supervisor of the polling task.
+/// # Returns
+/// * Cache filled in by the task.
+pub fn spawn_poller(config: &PeersConfig, supervisor: &mut Supervisor) -> Arc<PeerCache> {
+    let poll_interval = Duration::from_secs(config.poll_interval_secs);
+    // A reading survives two missed polls
+    let cache = Arc::new(PeerCache::new(
+        config.units.iter().map(|unit| unit.name.clone()).collect(),
+        poll_interval * 3,
+    ));
+    let urls: Vec<String> = config
+        .units
+        .iter()
+        .map(|unit| current_url(&unit.url))
+        .collect();
+    let limit = config.timeout();
+    let task_cache = cache.clone();
+    supervisor.spawn("peers", async move {
+        let mut ticker = interval(poll_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            for (index, url) in urls.iter().enumerate() {
+                let result = fetch_current(url, limit).await;
+                task_cache.record(index, result, Instant::now());
+            }
+        }
+    });
+    cache
+}
+
+/// Alternates the main page with the peers pages, and the altimeter and
+/// extremes pages when enabled.
+#[derive(Debug)]
+pub struct PeerPager {
+    page_time: Duration,
+    peers: usize,
+    per_page: usize,
+    altimeter: bool,
+    degree_hours: bool,
+    extremes: bool,
+    start: Instant,
+}
+
+impl PeerPager {
+    /// Create a pager starting on the main page.
+    /// # Arguments
+    /// * `page_time` - Time each page is shown.
+    /// * `peers` - Number of units.
+    /// * `now` - Current time.
+    pub fn new(page_time: Duration, peers: usize, now: Instant) -> Self {
+        Self {
+            page_time: page_time.max(Duration::from_secs(1)),
+            peers,
+            per_page: PEERS_PER_PAGE,
+            altimeter: false,
+            degree_hours: false,
+            extremes: false,
+            start: now,
+        }
+    }
+
+    /// Show as many units per peers page as the display has lines.
+    /// # Arguments
+    /// * `per_page` - Units per page, `PEERS_PER_PAGE` unless set.
+    pub fn with_peers_per_page(mut self, per_page: usize) -> Self {
+        self.per_page = per_page.max(1);
+        self
+    }
+
+    /// Show the altimeter page after the peers pages.
+    pub fn with_altimeter(mut self) -> Self {
+        self.altimeter = true;
+        self
+    }
+
+    /// Show the degree-hours page after the altimeter page.
+    pub fn with_degree_hours(mut self) -> Self {
+        self.degree_hours = true;
+        self
+    }
+
+    /// Show the extremes page last.
+    pub fn with_extremes(mut self) -> Self {
+        self.extremes = true;
+        self
+    }
+
+    /// Page to show.
+    /// # Arguments
+    /// * `now` - Current time.
+    pub fn page(&self, now: Instant) -> Page {
+        let slot =
+            now.saturating_duration_since(self.start).as_millis() / self.page_time.as_millis();
+        let extra: Vec<Page> = [
+            (self.altimeter, Page::Altimeter),
+            (self.degree_hours, Page::DegreeHours),
+            (self.extremes, Page::Extremes),
+        ]
+        .into_iter()
+        .filter_map(|(shown, page)| shown.then_some(page))
+        .collect();
+        let pages = self.peers.div_ceil(self.per_page);
+        let cycle = pages + 1 + extra.len();
+        match (slot % cycle as u128) as usize {
+            0 => Page::Main,
+            page if page <= pages => Page::Peers(page - 1),
+            page => extra[page - pages - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reading_validation() {
+        let reading = PeerReading::from_json(
+            json!({"temperature_c": 24.1, "humidity_relative": 55.0, "thi": 72.0}),
+        )
+        .unwrap();
+        assert_eq!(reading.temperature_c, 24.1);
+
+        assert!(PeerReading::from_json(json!({"temperature_c": 24.1})).is_err());
+        assert!(
+            PeerReading::from_json(json!({"temperature_c": "hot", "humidity_relative": 55.0}))
+                .is_err()
+        );
+        assert!(
+            PeerReading::from_json(json!({"temperature_c": 24.1, "humidity_relative": 120.0}))
+                .unwrap_err()
+                .contains("outside")
+        );
+        assert!(PeerReading::from_json(json!({"errors": ["no reading yet"]})).is_err());
+    }
+
+    #[test]
+    fn test_failed_peer_keeps_age() {
+        let start = Instant::now();
+        let cache = PeerCache::new(vec!["Liv".into(), "Bed".into()], Duration::from_secs(90));
+        let reading = PeerReading {
+            temperature_c: 24.1,
+            humidity_relative: 55.0,
+        };
+        cache.record(0, Ok(reading), start);
+        cache.record(1, Err("timeout".into()), start);
+
+        let summaries = cache.summaries(start + Duration::from_secs(10));
+        assert_eq!(summaries[0].reading, Some(reading));
+        assert_eq!(summaries[0].age, Some(Duration::from_secs(10)));
+        assert_eq!(summaries[1].reading, None);
+        assert_eq!(summaries[1].age, None);
+
+        // A failure hides the reading, the age keeps counting
+        cache.record(0, Err("timeout".into()), start + Duration::from_secs(30));
+        let summaries = cache.summaries(start + Duration::from_secs(60));
+        assert_eq!(summaries[0].reading, None);
+        assert_eq!(summaries[0].age, Some(Duration::from_secs(60)));
+
+        cache.record(0, Ok(reading), start + Duration::from_secs(60));
+        assert!(
+            cache.summaries(start + Duration::from_secs(60))[0]
+                .reading
+                .is_some()
+        );
+        // Too old without a poll
+        assert!(
+            cache.summaries(start + Duration::from_secs(150))[0]
+                .reading
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_pager_cycles_through_peers() {
+        let start = Instant::now();
+        let pager = PeerPager::new(Duration::from_secs(5), 5, start);
+        let at = |secs| pager.page(start + Duration::from_secs(secs));
+        assert_eq!(at(0), Page::Main);
+        assert_eq!(at(5), Page::Peers(0));
+        assert_eq!(at(10), Page::Peers(1));
+        assert_eq!(at(19), Page::Peers(2));
+        assert_eq!(at(20), Page::Main);
+
+        let single = PeerPager::new(Duration::from_secs(5), 1, start);
+        assert_eq!(single.page(start + Duration::from_secs(5)), Page::Peers(0));
+        assert_eq!(single.page(start + Duration::from_secs(10)), Page::Main);
+
+        let altimeter = PeerPager::new(Duration::from_secs(5), 1, start).with_altimeter();
+        let at = |secs| altimeter.page(start + Duration::from_secs(secs));
+        assert_eq!(at(5), Page::Peers(0));
+        assert_eq!(at(10), Page::Altimeter);
+        assert_eq!(at(15), Page::Main);
+        let alone = PeerPager::new(Duration::from_secs(5), 0, start).with_altimeter();
+        assert_eq!(alone.page(start + Duration::from_secs(5)), Page::Altimeter);
+
+        let extremes = PeerPager::new(Duration::from_secs(5), 1, start)
+            .with_altimeter()
+            .with_extremes();
+        let at = |secs| extremes.page(start + Duration::from_secs(secs));
+        assert_eq!(at(10), Page::Altimeter);
+        assert_eq!(at(15), Page::Extremes);
+        assert_eq!(at(20), Page::Main);
+        let alone = PeerPager::new(Duration::from_secs(5), 0, start).with_extremes();
+        assert_eq!(alone.page(start + Duration::from_secs(5)), Page::Extremes);
+
+        let degree_hours = PeerPager::new(Duration::from_secs(5), 0, start)
+            .with_degree_hours()
+            .with_extremes();
+        let at = |secs| degree_hours.page(start + Duration::from_secs(secs));
+        assert_eq!(at(5), Page::DegreeHours);
+        assert_eq!(at(10), Page::Extremes);
+        assert_eq!(at(15), Page::Main);
+
+        // 5 units fit on 2 pages of a 4 line display
+        let four_lines = PeerPager::new(Duration::from_secs(5), 5, start).with_peers_per_page(4);
+        let at = |secs| four_lines.page(start + Duration::from_secs(secs));
+        assert_eq!(at(10), Page::Peers(1));
+        assert_eq!(at(15), Page::Main);
+    }
+}
@@ -0,0 +1,274 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wind-down on the power loss signals of a UPS hat.
+//!
+//! The UPS pulls one GPIO low while mains power is lost and, optionally,
+//! another one when its battery runs low. On battery the display is turned
+//! off or dimmed, fewer rows are stored and HTTP connections are not kept
+//! alive. A low battery stops the program the same way as SIGTERM, so
+//! pending rows are committed and the filesystem stays clean.
+//!
+//! The state machine is kept apart from the pins so it can be tested without
+//! hardware.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rppal::gpio;
+
+use crate::config::PowerConfig;
+
+/// Transition reported by `WindDown::update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerTransition {
+    /// No change since the last update.
+    Unchanged,
+    /// Mains power was lost, wind down.
+    MainsLost,
+    /// Mains power is back, resume normal operation.
+    MainsRestored {
+        /// Time spent on battery.
+        outage: Duration,
+    },
+    /// The UPS battery runs low, shut down.
+    BatteryLow,
+}
+
+/// Power state shared with the HTTP API.
+#[derive(Debug, Default)]
+pub struct PowerStats {
+    on_battery: AtomicBool,
+}
+
+impl PowerStats {
+    /// Whether mains power is lost.
+    pub fn is_on_battery(&self) -> bool {
+        self.on_battery.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracker of the UPS signals, run from the measurement loop.
+#[derive(Debug)]
+pub struct WindDown {
+    save_interval: Option<Duration>,
+    on_battery_since: Option<Instant>,
+    battery_low: bool,
+    last_saved: Option<Instant>,
+    stats: Arc<PowerStats>,
+}
+
+impl WindDown {
+    /// Create a tracker, starting on mains power.
+    /// # Arguments
+    /// * `save_interval` - Interval between stored rows on battery, `None` to keep every row.
+    pub fn new(save_interval: Option<Duration>) -> Self {
+        Self {
+            save_interval,
+            on_battery_since: None,
+            battery_low: false,
+            last_saved: None,
+            stats: Arc::new(PowerStats::default()),
+        }
+    }
+
+    /// Power state shared with the HTTP API.
+    pub fn stats(&self) -> Arc<PowerStats> {
+        self.stats.clone()
+    }
+
+    /// Whether mains power is lost.
+    pub fn is_on_battery(&self) -> bool {
+        self.on_battery_since.is_some()
+    }
+
+    /// Update the state from the signals.
+    /// A mains change is reported before a low battery raised at the same
+    /// time, which is then reported by the next update.
+    /// # Arguments
+    /// * `mains_lost` - Whether the mains loss signal is active.
+    /// * `battery_low` - Whether the battery low signal is active.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The transition caused by this update.
+    pub fn update(&mut self, mains_lost: bool, battery_low: bool, now: Instant) -> PowerTransition {
+        match (mains_lost, self.on_battery_since) {
+            (true, None) => {
+                self.on_battery_since = Some(now);
+                self.last_saved = None;
+                self.stats.on_battery.store(true, Ordering::Relaxed);
+                return PowerTransition::MainsLost;
+            }
+            (false, Some(since)) => {
+                self.on_battery_since = None;
+                self.stats.on_battery.store(false, Ordering::Relaxed);
+                return PowerTransition::MainsRestored {
+                    outage: now.saturating_duration_since(since),
+                };
+            }
+            _ => {}
+        }
+        let raised = battery_low && !self.battery_low;
+        self.battery_low = battery_low;
+        if raised {
+            PowerTransition::BatteryLow
+        } else {
+            PowerTransition::Unchanged
+        }
+    }
+
+    /// Decide whether a row is stored at this tick.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * `true` on mains power, or once per save interval on battery.
+    pub fn admit_save(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.save_interval.filter(|_| self.is_on_battery()) else {
+            return true;
+        };
+        let due = self
+            .last_saved
+            .is_none_or(|last| now.saturating_duration_since(last) >= interval);
+        if due {
+            self.last_saved = Some(now);
+        }
+        due
+    }
+}
+
+/// GPIO inputs pulled low by the UPS.
+pub struct PowerPins {
+    mains_loss: Option<gpio::InputPin>,
+    battery_low: Option<gpio::InputPin>,
+}
+
+impl PowerPins {
+    /// Configure the configured pins as inputs with pull-up.
+    /// # Arguments
+    /// * `config` - Power configuration.
+    /// # Returns
+    /// * `Ok(None)` if no pin is configured.
+    pub fn from_config(config: &PowerConfig) -> Result<Option<Self>, gpio::Error> {
+        if config.mains_loss_pin.is_none() && config.battery_low_pin.is_none() {
+            return Ok(None);
+        }
+        let gpio = gpio::Gpio::new()?;
+        let input = |pin: Option<u8>| -> Result<Option<gpio::InputPin>, gpio::Error> {
+            pin.map(|pin| Ok(gpio.get(pin)?.into_input_pullup()))
+                .transpose()
+        };
+        Ok(Some(Self {
+            mains_loss: input(config.mains_loss_pin)?,
+            battery_low: input(config.battery_low_pin)?,
+        }))
+    }
+
+    /// Read the signals.
+    /// # Returns
+    /// * `(mains_lost, battery_low)`
+    pub fn read(&self) -> (bool, bool) {
+        let active = |pin: &Option<gpio::InputPin>| pin.as_ref().is_some_and(|pin| pin.is_low());
+        (active(&self.mains_loss), active(&self.battery_low))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mains_loss_and_restore() {
+        let start = Instant::now();
+        let mut wind_down = WindDown::new(None);
+        let stats = wind_down.stats();
+
+        assert_eq!(
+            wind_down.update(false, false, start),
+            PowerTransition::Unchanged
+        );
+        assert_eq!(
+            wind_down.update(true, false, start),
+            PowerTransition::MainsLost
+        );
+        assert!(wind_down.is_on_battery());
+        assert!(stats.is_on_battery());
+        assert_eq!(
+            wind_down.update(true, false, start + Duration::from_secs(1)),
+            PowerTransition::Unchanged
+        );
+        assert_eq!(
+            wind_down.update(false, false, start + Duration::from_secs(90)),
+            PowerTransition::MainsRestored {
+                outage: Duration::from_secs(90)
+            }
+        );
+        assert!(!stats.is_on_battery());
+    }
+
+    #[test]
+    fn test_battery_low_reported_once() {
+        let start = Instant::now();
+        let mut wind_down = WindDown::new(None);
+
+        // Raised together with the mains loss, reported on the next update
+        assert_eq!(
+            wind_down.update(true, true, start),
+            PowerTransition::MainsLost
+        );
+        assert_eq!(
+            wind_down.update(true, true, start),
+            PowerTransition::BatteryLow
+        );
+        assert_eq!(
+            wind_down.update(true, true, start),
+            PowerTransition::Unchanged
+        );
+        wind_down.update(true, false, start);
+        assert_eq!(
+            wind_down.update(true, true, start),
+            PowerTransition::BatteryLow
+        );
+    }
+
+    #[test]
+    fn test_save_interval_stretched_on_battery() {
+        let start = Instant::now();
+        let mut wind_down = WindDown::new(Some(Duration::from_secs(60)));
+        assert!(wind_down.admit_save(start));
+        assert!(wind_down.admit_save(start));
+
+        wind_down.update(true, false, start);
+        assert!(wind_down.admit_save(start));
+        assert!(!wind_down.admit_save(start + Duration::from_secs(59)));
+        assert!(wind_down.admit_save(start + Duration::from_secs(60)));
+        assert!(!wind_down.admit_save(start + Duration::from_secs(61)));
+
+        wind_down.update(false, false, start + Duration::from_secs(62));
+        assert!(wind_down.admit_save(start + Duration::from_secs(62)));
+        assert!(wind_down.admit_save(start + Duration::from_secs(62)));
+
+        let mut unlimited = WindDown::new(None);
+        unlimited.update(true, false, start);
+        assert!(unlimited.admit_save(start));
+        assert!(unlimited.admit_save(start));
+    }
+}
@@ -0,0 +1,174 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Check of the displayed precision against the sensor noise.
+//!
+//! The BME280 noise depends on the oversampling and the IIR filter. A digit
+//! finer than the noise only shows the noise, so start-up warns when the
+//! configured decimals go below the meaningful resolution of the settings.
+//!
+//! The model starts from the approximate RMS noise of a single sample and
+//! divides it by √N for oversampling N (averaged samples) and by
+//! √(2c - 1) for filter coefficient c (first order IIR). A digit is
+//! meaningful when its step is at least twice the RMS noise, so it does not
+//! flip between neighbouring values on ordinary readings.
+
+use peripheral::bme280::Bme280Settings;
+
+use crate::helper::MeasurementFormat;
+
+/// Approximate RMS noise of the temperature with oversampling x1 and the
+/// filter off, in °C.
+pub const TEMPERATURE_NOISE_C: f64 = 0.01;
+/// Approximate RMS noise of the humidity with oversampling x1, in %RH.
+/// The IIR filter does not apply to the humidity.
+pub const HUMIDITY_NOISE_RH: f64 = 0.02;
+
+/// Noise reduction of the IIR filter.
+/// # Arguments
+/// * `filter` - Filter coefficient, 0 for off.
+/// # Returns
+/// * Factor applied to the RMS noise.
+fn filter_factor(filter: u8) -> f64 {
+    if filter < 2 {
+        1.0
+    } else {
+        1.0 / (2.0 * f64::from(filter) - 1.0).sqrt()
+    }
+}
+
+/// Smallest meaningful step of a value.
+/// # Arguments
+/// * `noise` - RMS noise with oversampling x1 and no filter.
+/// * `oversampling` - Oversampling, 0 if the value is skipped.
+/// * `filter` - Noise factor of the filter.
+/// # Returns
+/// * Resolution, `None` if the value is not measured.
+fn resolution(noise: f64, oversampling: u8, filter: f64) -> Option<f64> {
+    (oversampling > 0).then(|| 2.0 * noise / f64::from(oversampling).sqrt() * filter)
+}
+
+/// Warnings for displayed digits finer than the sensor resolves.
+/// # Arguments
+/// * `settings` - BME280 settings.
+/// * `format` - Precision of the displayed measurement.
+/// # Returns
+/// * One message per value shown with too many decimals, empty if none.
+pub fn check(settings: &Bme280Settings, format: &MeasurementFormat) -> Vec<String> {
+    let values = [
+        (
+            "temperature",
+            "C",
+            format.temperature_decimals,
+            resolution(
+                TEMPERATURE_NOISE_C,
+                settings.oversampling_temperature,
+                filter_factor(settings.filter),
+            ),
+            settings.oversampling_temperature,
+        ),
+        (
+            "humidity",
+            "%",
+            format.humidity_decimals,
+            resolution(HUMIDITY_NOISE_RH, settings.oversampling_humidity, 1.0),
+            settings.oversampling_humidity,
+        ),
+    ];
+    let mut warnings = Vec::new();
+    for (name, unit, decimals, resolution, oversampling) in values {
+        let Some(resolution) = resolution else {
+            warnings.push(format!(
+                "{} oversampling is 0, the displayed {} is not measured",
+                name, name
+            ));
+            continue;
+        };
+        let step = 10f64.powi(-i32::from(decimals));
+        if step < resolution {
+            warnings.push(format!(
+                "{} is shown with {} decimals, but oversampling x{} resolves about {:.3}{}; \
+                 the last digit shows noise",
+                name, decimals, oversampling, resolution, unit
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(temperature: u8, humidity: u8, filter: u8) -> Bme280Settings {
+        Bme280Settings {
+            oversampling_temperature: temperature,
+            oversampling_pressure: 1,
+            oversampling_humidity: humidity,
+            filter,
+        }
+    }
+
+    fn format(temperature_decimals: u8, humidity_decimals: u8) -> MeasurementFormat {
+        MeasurementFormat {
+            temperature_decimals,
+            humidity_decimals,
+            ..MeasurementFormat::default()
+        }
+    }
+
+    #[test]
+    fn test_default_precision_is_within_budget() {
+        assert!(check(&Bme280Settings::default(), &format(1, 1)).is_empty());
+        assert!(check(&settings(1, 1, 0), &format(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_two_decimals_need_oversampling() {
+        let warnings = check(&settings(1, 1, 0), &format(2, 2));
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].starts_with("temperature is shown with 2 decimals"));
+        assert!(warnings[0].contains("0.020C"));
+        assert!(warnings[1].starts_with("humidity"));
+
+        // x4 halves the temperature noise, the humidity needs x16
+        let warnings = check(&settings(4, 4, 0), &format(2, 2));
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with("humidity"));
+        assert!(check(&settings(4, 16, 0), &format(2, 2)).is_empty());
+    }
+
+    #[test]
+    fn test_filter_reduces_temperature_noise_only() {
+        // √(2*4 - 1) brings x1 below 0.01C
+        assert!(check(&settings(1, 16, 4), &format(2, 2)).is_empty());
+        assert_eq!(check(&settings(1, 1, 16), &format(1, 2)).len(), 1);
+    }
+
+    #[test]
+    fn test_skipped_humidity() {
+        let warnings = check(&settings(1, 0, 0), &format(1, 0));
+        assert_eq!(
+            warnings,
+            vec!["humidity oversampling is 0, the displayed humidity is not measured"]
+        );
+    }
+}
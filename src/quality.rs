@@ -0,0 +1,634 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Data quality flag stored with every row.
+//!
+//! A marginal sensor produces plausible-but-wrong readings in between failed
+//! ones. Readings taken while the recent failure count is high, or which sit
+//! at the edge of the sensor's range, are flagged as suspect. Readings
+//! outside the configured plausible ranges are quarantined instead, each
+//! sensor checked against the ranges of its profile.
+//!
+//! For dashboards the same signals are combined into a quality score of
+//! 0-100, see `quality_score`. Its points are weighted as
+//!
+//! * 40 for the margin to the edge of the sensor's range: full points at
+//!   5% of the range or more from the nearer limit, none at the limit or
+//!   for a value which is not a number.
+//! * 40 for the recent error rate: the share of successful attempts in the
+//!   window.
+//! * 20 for steadiness: none once the standard deviation of the readings in
+//!   the window reaches 0.5 °C, 3 %RH or 50 Pa, whichever is reached first.
+//!   A change of the air counts as well as sensor noise.
+
+use std::collections::{HashMap, VecDeque};
+
+use peripheral::bme280::Measurement;
+
+use crate::config::{QualityConfig, SensorsConfig, ValidationConfig};
+
+/// Quality flag of a stored row.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// Nothing indicates a problem.
+    #[default]
+    Good,
+    /// Taken during a high failure rate or at the edge of the sensor's range.
+    Suspect,
+}
+
+impl Quality {
+    /// Value stored in the `quality` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Suspect => "suspect",
+        }
+    }
+}
+
+/// Points of the quality score for the margin to the range limits.
+const SCORE_MARGIN: f64 = 40.0;
+/// Points of the quality score for the recent error rate.
+const SCORE_ERRORS: f64 = 40.0;
+/// Points of the quality score for steady readings.
+const SCORE_STEADY: f64 = 20.0;
+/// Fraction of a range from its limit which earns the full margin points.
+const FULL_MARGIN: f64 = 0.05;
+/// Standard deviation of temperature (°C), humidity (%) and pressure (Pa)
+/// at which no steadiness points are left.
+const NOISE_LIMITS: [f64; 3] = [0.5, 3.0, 50.0];
+
+/// Tracks recent measurement failures and derives the quality flag.
+#[derive(Debug)]
+pub struct QualityTracker {
+    window: usize,
+    suspect_failures: usize,
+    boundary_margin: f64,
+    outcomes: VecDeque<bool>,
+    readings: VecDeque<Measurement>,
+}
+
+impl QualityTracker {
+    /// Create a new tracker.
+    /// # Arguments
+    /// * `config` - Quality configuration.
+    pub fn new(config: &QualityConfig) -> Self {
+        Self {
+            window: config.window.max(1),
+            suspect_failures: config.suspect_failures,
+            boundary_margin: config.boundary_margin,
+            outcomes: VecDeque::with_capacity(config.window.max(1)),
+            readings: VecDeque::with_capacity(config.window.max(1)),
+        }
+    }
+
+    /// Record the outcome of a measurement attempt.
+    /// # Arguments
+    /// * `success` - Whether the measurement succeeded.
+    pub fn record(&mut self, success: bool) {
+        if self.outcomes.len() == self.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+    }
+
+    /// Record a successful reading, kept for the quality score.
+    /// # Arguments
+    /// * `measurement` - Raw reading of the sensor.
+    pub fn record_reading(&mut self, measurement: Measurement) {
+        if self.readings.len() == self.window {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(measurement);
+    }
+
+    /// Number of failures among the recent attempts.
+    pub fn recent_failures(&self) -> usize {
+        self.outcomes.iter().filter(|success| !**success).count()
+    }
+
+    /// Derive the quality flag of a measurement.
+    /// # Arguments
+    /// * `measurement` - Measurement to assess.
+    /// # Returns
+    /// * `Quality::Suspect` if the recent failures reach the threshold or
+    ///   the measurement is near the limits of the sensor's range.
+    pub fn assess(&self, measurement: &Measurement) -> Quality {
+        if self.suspect_failures > 0 && self.recent_failures() >= self.suspect_failures {
+            return Quality::Suspect;
+        }
+        if near_boundary(measurement, self.boundary_margin) {
+            return Quality::Suspect;
+        }
+        Quality::Good
+    }
+}
+
+/// Quality score of a reading, 0 (untrustworthy) to 100.
+/// The weighting is described in the module documentation.
+/// # Arguments
+/// * `measurement` - Reading to score.
+/// * `history` - Recent attempts and readings of the same sensor.
+/// # Returns
+/// * Score from 0 to 100.
+pub fn quality_score(measurement: &Measurement, history: &QualityTracker) -> u8 {
+    let margin = range_margin(measurement);
+    let margin_points = SCORE_MARGIN * (margin / FULL_MARGIN).clamp(0.0, 1.0);
+
+    let attempts = history.outcomes.len();
+    let error_points = if attempts == 0 {
+        SCORE_ERRORS
+    } else {
+        SCORE_ERRORS * (attempts - history.recent_failures()) as f64 / attempts as f64
+    };
+
+    let noise = [
+        std_dev(history.readings.iter().map(|m| m.temperature_c)),
+        std_dev(history.readings.iter().map(|m| m.humidity_relative)),
+        std_dev(history.readings.iter().map(|m| m.pressure_pa)),
+    ]
+    .iter()
+    .zip(NOISE_LIMITS)
+    .map(|(std_dev, limit)| std_dev / limit)
+    .fold(0.0, f64::max);
+    let steady_points = SCORE_STEADY * (1.0 - noise.min(1.0));
+
+    (margin_points + error_points + steady_points).round() as u8
+}
+
+/// Smallest distance of a value to the limits of the BME280 operating
+/// range, as a fraction of the range.
+/// # Arguments
+/// * `measurement` - Measurement to check.
+/// # Returns
+/// * Fraction from 0 to 0.5, 0 if any value is not a number.
+fn range_margin(measurement: &Measurement) -> f64 {
+    let margin = |value: f64, min: f64, max: f64| {
+        if value.is_finite() {
+            ((value - min).min(max - value) / (max - min)).max(0.0)
+        } else {
+            0.0
+        }
+    };
+    margin(measurement.temperature_c, -40.0, 85.0)
+        .min(margin(measurement.humidity_relative, 0.0, 100.0))
+        .min(margin(measurement.pressure_pa, 30000.0, 110000.0))
+}
+
+/// Population standard deviation of the finite values.
+/// # Returns
+/// * 0 for fewer than two values.
+fn std_dev(values: impl Iterator<Item = f64>) -> f64 {
+    let values: Vec<f64> = values.filter(|v| v.is_finite()).collect();
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    variance.sqrt()
+}
+
+/// Check whether a measurement is at the edge of the BME280 operating range.
+/// Humidity is clamped to 0-100% by the driver, so both ends count as edges.
+/// # Arguments
+/// * `measurement` - Measurement to check.
+/// * `margin` - Fraction of each range treated as the edge.
+/// # Returns
+/// * `true` if any value is within the margin of its limit, or not a number.
+fn near_boundary(measurement: &Measurement, margin: f64) -> bool {
+    let near = |value: f64, min: f64, max: f64| {
+        let edge = (max - min) * margin;
+        !value.is_finite() || value <= min + edge || value >= max - edge
+    };
+    near(measurement.temperature_c, -40.0, 85.0)
+        || near(measurement.humidity_relative, 0.0, 100.0)
+        || near(measurement.pressure_pa, 30000.0, 110000.0)
+}
+
+/// Check a reading against the plausible ranges, optionally without its
+/// pressure or without its humidity and the THI derived from it.
+/// # Arguments
+/// * `measurement` - Measurement to check.
+/// * `thi` - Temperature-humidity index, which must be a number.
+/// * `config` - Plausible ranges.
+/// * `pressure` - Whether the pressure is checked.
+/// * `humidity` - Whether the humidity and the THI are checked.
+/// # Returns
+/// * `Err(reason)` listing every implausible value, e.g.
+///   "temperature_c 130.2 above 85, thi NaN".
+fn check_values(
+    measurement: &Measurement,
+    thi: f64,
+    config: &ValidationConfig,
+    pressure: bool,
+    humidity: bool,
+) -> Result<(), String> {
+    let mut reasons = Vec::new();
+    let values = [
+        (
+            "temperature_c",
+            measurement.temperature_c,
+            config.temperature_c,
+            true,
+        ),
+        (
+            "humidity_relative",
+            measurement.humidity_relative,
+            config.humidity_relative,
+            humidity,
+        ),
+        (
+            "pressure_pa",
+            measurement.pressure_pa,
+            config.pressure_pa,
+            pressure,
+        ),
+    ];
+    for (name, value, [min, max], checked) in values {
+        if !checked {
+            continue;
+        }
+        if value.is_nan() {
+            reasons.push(format!("{} NaN", name));
+        } else if value < min {
+            reasons.push(format!("{} {} below {}", name, value, min));
+        } else if value > max {
+            reasons.push(format!("{} {} above {}", name, value, max));
+        }
+    }
+    if humidity && !thi.is_finite() {
+        reasons.push(format!("thi {}", thi));
+    }
+    if reasons.is_empty() {
+        Ok(())
+    } else {
+        Err(reasons.join(", "))
+    }
+}
+
+/// Plausible ranges of the rows of each sensor.
+#[derive(Debug, Clone)]
+pub struct Plausibility {
+    /// Ranges of the sensors without a profile, and whether rows are
+    /// checked at all.
+    default: ValidationConfig,
+    /// Ranges of the sensors with a profile, by label.
+    profiles: HashMap<String, ValidationConfig>,
+    /// Labels of the sensors which measure no pressure.
+    without_pressure: Vec<String>,
+    /// Labels of the sensors which measure no humidity.
+    without_humidity: Vec<String>,
+}
+
+impl Plausibility {
+    /// The same ranges for every sensor.
+    /// # Arguments
+    /// * `validation` - [database.validation].
+    pub fn new(validation: &ValidationConfig) -> Self {
+        Self {
+            default: validation.clone(),
+            profiles: HashMap::new(),
+            without_pressure: Vec::new(),
+            without_humidity: Vec::new(),
+        }
+    }
+
+    /// Ranges of every configured sensor, from its profile if it has one.
+    /// # Arguments
+    /// * `validation` - [database.validation], for the sensors without a
+    ///   profile.
+    /// * `sensors` - [sensors].
+    pub fn from_config(validation: &ValidationConfig, sensors: &SensorsConfig) -> Self {
+        Self {
+            profiles: sensors
+                .profiled()
+                .map(|(label, profile)| (label.to_string(), profile.validation()))
+                .collect(),
+            without_pressure: sensors.without_pressure().map(str::to_string).collect(),
+            ..Self::new(validation)
+        }
+    }
+
+    /// Leave out the humidity and the THI of sensors found without a
+    /// humidity channel, such as a BMP280 in place of a BME280.
+    /// # Arguments
+    /// * `labels` - Labels of the sensors.
+    pub fn without_humidity(mut self, labels: Vec<String>) -> Self {
+        self.without_humidity = labels;
+        self
+    }
+
+    /// Whether rows are checked.
+    pub fn is_enabled(&self) -> bool {
+        self.default.enabled
+    }
+
+    /// Check a row of a sensor. The NaN pressure or humidity of a sensor
+    /// which measures none is not checked, nor is the THI without humidity.
+    /// # Arguments
+    /// * `sensor` - Label of the sensor.
+    /// * `measurement` - Measurement to check.
+    /// * `thi` - Temperature-humidity index, which must be a number.
+    /// # Returns
+    /// * `Err(reason)` as of `check_values`.
+    pub fn check(&self, sensor: &str, measurement: &Measurement, thi: f64) -> Result<(), String> {
+        let ranges = self.profiles.get(sensor).unwrap_or(&self.default);
+        let pressure = !self.without_pressure.iter().any(|label| label == sensor);
+        let humidity = !self.without_humidity.iter().any(|label| label == sensor);
+        check_values(measurement, thi, ranges, pressure, humidity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> QualityConfig {
+        QualityConfig {
+            window: 10,
+            suspect_failures: 3,
+            boundary_margin: 0.01,
+            store_score: false,
+        }
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            temperature_c: 23.5,
+            pressure_pa: 100500.0,
+            humidity_relative: 55.0,
+        }
+    }
+
+    #[test]
+    fn test_default_is_good() {
+        let tracker = QualityTracker::new(&config());
+        assert_eq!(tracker.assess(&measurement()), Quality::Good);
+        assert_eq!(Quality::default(), Quality::Good);
+    }
+
+    #[test]
+    fn test_high_error_rate_is_suspect() {
+        let mut tracker = QualityTracker::new(&config());
+        for success in [true, false, true, false, true, false, true] {
+            tracker.record(success);
+        }
+        assert_eq!(tracker.recent_failures(), 3);
+        assert_eq!(tracker.assess(&measurement()), Quality::Suspect);
+    }
+
+    #[test]
+    fn test_failures_age_out_of_window() {
+        let mut tracker = QualityTracker::new(&config());
+        for _ in 0..3 {
+            tracker.record(false);
+        }
+        assert_eq!(tracker.assess(&measurement()), Quality::Suspect);
+        for _ in 0..8 {
+            tracker.record(true);
+        }
+        assert_eq!(tracker.recent_failures(), 2);
+        assert_eq!(tracker.assess(&measurement()), Quality::Good);
+    }
+
+    #[test]
+    fn test_boundary_values_are_suspect() {
+        let tracker = QualityTracker::new(&config());
+        let suspect = [
+            Measurement {
+                humidity_relative: 100.0,
+                ..measurement()
+            },
+            Measurement {
+                humidity_relative: 0.0,
+                ..measurement()
+            },
+            Measurement {
+                temperature_c: 84.5,
+                ..measurement()
+            },
+            Measurement {
+                pressure_pa: 30000.0,
+                ..measurement()
+            },
+            Measurement {
+                humidity_relative: f64::NAN,
+                ..measurement()
+            },
+        ];
+        for m in suspect {
+            assert_eq!(tracker.assess(&m), Quality::Suspect, "{:?}", m);
+        }
+    }
+
+    #[test]
+    fn test_stable_reading_scores_high() {
+        let mut tracker = QualityTracker::new(&config());
+        assert_eq!(quality_score(&measurement(), &tracker), 100);
+        for i in 0..10 {
+            tracker.record(true);
+            tracker.record_reading(Measurement {
+                temperature_c: 23.5 + 0.01 * (i % 2) as f64,
+                ..measurement()
+            });
+        }
+        assert!(quality_score(&measurement(), &tracker) >= 99);
+    }
+
+    #[test]
+    fn test_boundary_reading_during_errors_scores_low() {
+        let mut tracker = QualityTracker::new(&config());
+        for success in [true, false, true, false, false, true] {
+            tracker.record(success);
+        }
+        for humidity_relative in [94.0, 99.5, 99.5] {
+            tracker.record_reading(Measurement {
+                humidity_relative,
+                ..measurement()
+            });
+        }
+        let boundary = Measurement {
+            humidity_relative: 99.5,
+            ..measurement()
+        };
+        // 4 for the margin, 20 for 3 failures in 6 attempts and 3 for a
+        // deviation of 2.6 %RH
+        let score = quality_score(&boundary, &tracker);
+        assert_eq!(score, 27);
+        assert!(score < quality_score(&measurement(), &tracker));
+
+        let missing = Measurement {
+            humidity_relative: f64::NAN,
+            ..measurement()
+        };
+        assert!(quality_score(&missing, &tracker) < score);
+    }
+
+    #[test]
+    fn test_score_weights() {
+        let mut tracker = QualityTracker::new(&config());
+        // 2.5% of the range from 0 %RH earns half the margin points
+        let dry = Measurement {
+            humidity_relative: 2.5,
+            ..measurement()
+        };
+        assert_eq!(quality_score(&dry, &tracker), 80);
+        for success in [true, false, true, true] {
+            tracker.record(success);
+        }
+        assert_eq!(quality_score(&measurement(), &tracker), 90);
+        // A standard deviation of 0.25 °C costs half the steadiness points
+        for temperature_c in [23.25, 23.75] {
+            tracker.record_reading(Measurement {
+                temperature_c,
+                ..measurement()
+            });
+        }
+        assert_eq!(quality_score(&measurement(), &tracker), 80);
+    }
+
+    #[test]
+    fn test_as_str() {
+        assert_eq!(Quality::Good.as_str(), "good");
+        assert_eq!(Quality::Suspect.as_str(), "suspect");
+    }
+
+    #[test]
+    fn test_check_values() {
+        let config = ValidationConfig::default();
+        assert_eq!(
+            check_values(&measurement(), 70.0, &config, true, true),
+            Ok(())
+        );
+        // The range limits themselves are plausible
+        let limits = Measurement {
+            temperature_c: 85.0,
+            pressure_pa: 30000.0,
+            humidity_relative: 0.0,
+        };
+        assert_eq!(check_values(&limits, 70.0, &config, true, true), Ok(()));
+
+        let implausible = Measurement {
+            temperature_c: 130.5,
+            pressure_pa: 12000.0,
+            humidity_relative: f64::NAN,
+        };
+        assert_eq!(
+            check_values(&implausible, f64::NAN, &config, true, true),
+            Err("temperature_c 130.5 above 85, humidity_relative NaN, \
+                 pressure_pa 12000 below 30000, thi NaN"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn test_profiles_select_ranges() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[sensors]
+label = "living"
+profile = "indoor"
+
+[[sensors.extra]]
+label = "garden"
+profile = "outdoor"
+
+[[sensors.extra]]
+label = "reference"
+"#;
+        let config: crate::config::Config = toml::from_str(toml_str).unwrap();
+        let plausibility = Plausibility::from_config(&config.database.validation, &config.sensors);
+        let frost = Measurement {
+            temperature_c: -8.5,
+            ..measurement()
+        };
+
+        assert_eq!(plausibility.check("garden", &frost, 30.0), Ok(()));
+        assert_eq!(
+            plausibility.check("living", &frost, 30.0),
+            Err("temperature_c -8.5 below 0".to_string())
+        );
+        // Without a profile, [database.validation] applies
+        assert_eq!(plausibility.check("reference", &frost, 30.0), Ok(()));
+        let heat = Measurement {
+            temperature_c: 70.0,
+            ..measurement()
+        };
+        assert!(plausibility.check("garden", &heat, 80.0).is_err());
+        assert_eq!(plausibility.check("reference", &heat, 80.0), Ok(()));
+    }
+
+    #[test]
+    fn test_sensor_without_pressure() {
+        let toml_str = r#"
+[database]
+url = "sqlite:./test.db"
+
+[[sensors.extra]]
+type = "sht31"
+label = "humidity"
+"#;
+        let config: crate::config::Config = toml::from_str(toml_str).unwrap();
+        let plausibility = Plausibility::from_config(&config.database.validation, &config.sensors);
+        let no_pressure = Measurement {
+            pressure_pa: f64::NAN,
+            ..measurement()
+        };
+
+        assert_eq!(plausibility.check("humidity", &no_pressure, 70.0), Ok(()));
+        assert_eq!(
+            plausibility.check("bme280", &no_pressure, 70.0),
+            Err("pressure_pa NaN".to_string())
+        );
+        let wet = Measurement {
+            humidity_relative: 101.0,
+            ..no_pressure
+        };
+        assert_eq!(
+            plausibility.check("humidity", &wet, 70.0),
+            Err("humidity_relative 101 above 100".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sensor_without_humidity() {
+        let plausibility = Plausibility::new(&ValidationConfig::default())
+            .without_humidity(vec!["bmp280".to_string()]);
+        let no_humidity = Measurement {
+            humidity_relative: f64::NAN,
+            ..measurement()
+        };
+
+        assert_eq!(plausibility.check("bmp280", &no_humidity, f64::NAN), Ok(()));
+        assert_eq!(
+            plausibility.check("bme280", &no_humidity, f64::NAN),
+            Err("humidity_relative NaN, thi NaN".to_string())
+        );
+        let cold = Measurement {
+            temperature_c: -100.0,
+            ..no_humidity
+        };
+        assert!(plausibility.check("bmp280", &cold, f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Recompute derived columns of stored rows from their raw measurements.
+//!
+//! Rows are read in id order and updated in batches, one transaction per
+//! batch. With a resume file the last processed id is kept after every
+//! batch, so an interrupted run continues where it stopped. Raw measurement
+//! columns are never written.
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::Measurement;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::helper::ThiCoefficients;
+
+/// Columns holding sensor readings, which are never rewritten.
+pub const RAW_COLUMNS: &[&str] = &["temperature_c", "humidity_relative", "pressure_pa"];
+
+/// Largest difference still treated as unchanged.
+const EPSILON: f64 = 1e-9;
+
+/// Column computed from the raw measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivedColumn {
+    /// Comfort index of the configured formula.
+    Thi,
+    /// Dew point of the temperature and humidity.
+    DewPoint,
+}
+
+impl DerivedColumn {
+    /// Every derived column.
+    pub const ALL: &'static [DerivedColumn] = &[DerivedColumn::Thi, DerivedColumn::DewPoint];
+
+    /// Column name in `sensor_data`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DerivedColumn::Thi => "thi",
+            DerivedColumn::DewPoint => "dew_point_c",
+        }
+    }
+
+    /// Look up a column by name.
+    /// # Arguments
+    /// * `name` - Column name.
+    /// # Returns
+    /// * `Err(message)` for raw measurement columns and unknown names.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        if RAW_COLUMNS.contains(&name) {
+            return Err(format!(
+                "{} is a raw measurement column and is never recomputed",
+                name
+            ));
+        }
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "unknown derived column {} (expected one of: {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// Compute the value from a raw measurement.
+    /// # Arguments
+    /// * `measurement` - Stored raw measurement.
+    /// * `comfort` - Coefficients of the comfort index.
+    /// # Returns
+    /// * Value of the column.
+    pub fn compute(&self, measurement: &Measurement, comfort: &ThiCoefficients) -> f64 {
+        match self {
+            DerivedColumn::Thi => crate::calc_thi(
+                measurement.temperature_c,
+                measurement.humidity_relative,
+                comfort,
+            ),
+            DerivedColumn::DewPoint => {
+                crate::helper::dew_point_c(measurement.temperature_c, measurement.humidity_relative)
+            }
+        }
+    }
+}
+
+/// Recompute options.
+#[derive(Debug, Clone)]
+pub struct RecomputeOptions {
+    /// Columns to recompute.
+    pub columns: Vec<DerivedColumn>,
+    /// Only rows at or after this time.
+    pub from: Option<DateTime<Local>>,
+    /// Only rows before this time.
+    pub to: Option<DateTime<Local>>,
+    /// Rows per read and per transaction.
+    pub batch_size: usize,
+    /// Count the changes without writing them.
+    pub dry_run: bool,
+    /// File keeping the last processed id.
+    pub resume_file: Option<PathBuf>,
+}
+
+/// Result of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct RecomputeReport {
+    /// Rows read.
+    pub scanned: u64,
+    /// Values which differed from the stored ones, per column.
+    pub changed: Vec<(String, u64)>,
+    /// Id of the last row read, 0 if none.
+    pub last_id: i64,
+    pub dry_run: bool,
+}
+
+/// Recompute the derived columns.
+/// # Arguments
+/// * `database` - Database to update.
+/// * `options` - Recompute options.
+/// * `comfort` - Coefficients of the comfort index.
+/// # Returns
+/// * Result<RecomputeReport, Box<dyn Error>>
+pub async fn run(
+    database: &Database,
+    options: &RecomputeOptions,
+    comfort: &ThiCoefficients,
+) -> Result<RecomputeReport, Box<dyn Error>> {
+    if options.columns.is_empty() {
+        return Err("no column to recompute".into());
+    }
+    let mut after_id = match &options.resume_file {
+        Some(path) => read_resume_id(path)?,
+        None => 0,
+    };
+    if after_id > 0 {
+        println!("Resuming after id {}", after_id);
+    }
+    let mut report = RecomputeReport {
+        changed: options
+            .columns
+            .iter()
+            .map(|column| (column.name().to_string(), 0))
+            .collect(),
+        last_id: after_id,
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    let names: Vec<&'static str> = options.columns.iter().map(|c| c.name()).collect();
+    loop {
+        let rows = database
+            .raw_rows(
+                &names,
+                after_id,
+                options.from,
+                options.to,
+                options.batch_size.max(1),
+            )
+            .await
+            .map_err(|e| format!("Failed to read rows: {}", e))?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for (index, column) in options.columns.iter().enumerate() {
+            let values: Vec<(i64, f64)> = rows
+                .iter()
+                .map(|row| (row.id, column.compute(&row.measurement, comfort)))
+                .zip(&rows)
+                .filter(|((_, value), row)| !same_value(*value, row.columns[index]))
+                .map(|(value, _)| value)
+                .collect();
+            report.changed[index].1 += values.len() as u64;
+            if !options.dry_run && !values.is_empty() {
+                database
+                    .update_column(column.name(), &values)
+                    .await
+                    .map_err(|e| format!("Failed to update {}: {}", column.name(), e))?;
+            }
+        }
+        report.scanned += rows.len() as u64;
+        report.last_id = after_id;
+        if let (Some(path), false) = (&options.resume_file, options.dry_run) {
+            fs::write(path, after_id.to_string())
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        println!(
+            "{} {} rows, last id {}",
+            if options.dry_run {
+                "Checked"
+            } else {
+                "Updated"
+            },
+            report.scanned,
+            after_id
+        );
+    }
+    Ok(report)
+}
+
+/// Whether a recomputed value matches the stored one. NaN matches NaN.
+fn same_value(value: f64, stored: f64) -> bool {
+    (value.is_nan() && stored.is_nan()) || (value - stored).abs() <= EPSILON
+}
+
+/// Read the last processed id of an earlier run.
+/// # Arguments
+/// * `path` - Resume file.
+/// # Returns
+/// * 0 if the file does not exist yet.
+fn read_resume_id(path: &PathBuf) -> Result<i64, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid resume file {}: {}", path.display(), e).into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SensorData;
+
+    fn row(temperature_c: f64, thi: f64, at: DateTime<Local>) -> SensorData {
+        let measurement = Measurement {
+            temperature_c,
+            humidity_relative: 50.0,
+            pressure_pa: 101325.0,
+        };
+        SensorData::from_measurement_at(measurement, thi, at)
+    }
+
+    fn options(dry_run: bool) -> RecomputeOptions {
+        RecomputeOptions {
+            columns: vec![DerivedColumn::Thi],
+            from: None,
+            to: None,
+            batch_size: 2,
+            dry_run,
+            resume_file: None,
+        }
+    }
+
+    async fn stored_thi(database: &Database) -> Vec<f64> {
+        database
+            .raw_rows(&["thi"], 0, None, None, 100)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.columns[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!(DerivedColumn::parse("thi"), Ok(DerivedColumn::Thi));
+        assert_eq!(
+            DerivedColumn::parse("dew_point_c"),
+            Ok(DerivedColumn::DewPoint)
+        );
+        for raw in RAW_COLUMNS {
+            assert!(DerivedColumn::parse(raw).unwrap_err().contains("raw"));
+        }
+        assert!(DerivedColumn::parse("id").unwrap_err().contains("unknown"));
+    }
+
+    #[test]
+    fn test_compute_uses_coefficients() {
+        let measurement = Measurement {
+            temperature_c: 25.0,
+            humidity_relative: 50.0,
+            pressure_pa: 101325.0,
+        };
+        let custom = ThiCoefficients {
+            e: 40.0,
+            ..ThiCoefficients::STANDARD
+        };
+        let standard = DerivedColumn::Thi.compute(&measurement, &ThiCoefficients::STANDARD);
+        let shifted = DerivedColumn::Thi.compute(&measurement, &custom);
+        assert!((standard - shifted - 6.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_same_value() {
+        assert!(same_value(70.0, 70.0));
+        assert!(same_value(f64::NAN, f64::NAN));
+        assert!(!same_value(70.0, 70.1));
+        assert!(!same_value(70.0, f64::NAN));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_recompute_thi() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let at = Local::now();
+        let standard = ThiCoefficients::STANDARD;
+        let correct = crate::calc_thi(20.0, 50.0, &standard);
+        database.save_async(row(20.0, 0.0, at)).unwrap();
+        database.save_async(row(20.0, correct, at)).unwrap();
+        database.save_async(row(20.0, 1.0, at)).unwrap();
+        database.flush().await.unwrap();
+
+        let report = run(&database, &options(true), &standard).await.unwrap();
+        assert_eq!(report.scanned, 3);
+        assert_eq!(report.changed, vec![("thi".to_string(), 2)]);
+        assert_eq!(stored_thi(&database).await, vec![0.0, correct, 1.0]);
+
+        let report = run(&database, &options(false), &standard).await.unwrap();
+        assert_eq!(report.changed, vec![("thi".to_string(), 2)]);
+        assert_eq!(report.last_id, 3);
+        assert_eq!(stored_thi(&database).await, vec![correct; 3]);
+        database.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_recompute_dew_point() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let mut stale = row(20.0, 0.0, Local::now());
+        stale.dew_point_c = f64::NAN;
+        database.save_async(stale).unwrap();
+        database.flush().await.unwrap();
+
+        let options = RecomputeOptions {
+            columns: vec![DerivedColumn::DewPoint],
+            ..options(false)
+        };
+        let report = run(&database, &options, &ThiCoefficients::STANDARD)
+            .await
+            .unwrap();
+        assert_eq!(report.changed, vec![("dew_point_c".to_string(), 1)]);
+        let stored = database
+            .raw_rows(&["dew_point_c"], 0, None, None, 100)
+            .await
+            .unwrap();
+        assert_eq!(stored[0].columns[0], crate::helper::dew_point_c(20.0, 50.0));
+        database.close().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_recompute_range_and_resume() {
+        let database = Database::new("sqlite::memory:").await.unwrap();
+        let start = Local::now();
+        for i in 0..5 {
+            database
+                .save_async(row(20.0, 0.0, start + chrono::Duration::seconds(i)))
+                .unwrap();
+        }
+        database.flush().await.unwrap();
+        let resume =
+            std::env::temp_dir().join(format!("wbroker-rs-recompute-{}.id", std::process::id()));
+        fs::write(&resume, "1").unwrap();
+
+        let options = RecomputeOptions {
+            to: Some(start + chrono::Duration::seconds(4)),
+            resume_file: Some(resume.clone()),
+            ..options(false)
+        };
+        let report = run(&database, &options, &ThiCoefficients::STANDARD)
+            .await
+            .unwrap();
+        // Row 1 was done by the earlier run, row 5 is out of range
+        assert_eq!(report.scanned, 3);
+        assert_eq!(fs::read_to_string(&resume).unwrap(), "4");
+        let thi = stored_thi(&database).await;
+        assert_eq!(thi[0], 0.0);
+        assert_ne!(thi[1], 0.0);
+        assert_ne!(thi[3], 0.0);
+        assert_eq!(thi[4], 0.0);
+
+        let _ = fs::remove_file(&resume);
+        database.close().await;
+    }
+}
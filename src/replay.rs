@@ -0,0 +1,465 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Replay of an exported CSV through the display and the alert engine.
+//!
+//! The rows of one sensor are read back from a file written by `export` and
+//! measured in order by `CsvReplaySensor`. Their timestamps drive a virtual
+//! clock, paced `speed` times faster than real time. Long gaps can be
+//! shortened so quiet periods pass quickly, the alert engine still sees
+//! them as they are. Each row is drawn on a fake display printed to the
+//! terminal. Alert transitions are logged but never posted to the webhook,
+//! and nothing is stored unless a database is given.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use peripheral::bme280::Measurement;
+use peripheral::display::MockDisplay;
+
+use crate::alerts::{self, AlertEngine, AlertEpisode, AlertTransition};
+use crate::cgram::CgramSlots;
+use crate::config::Config;
+use crate::database::{Database, SensorData, Source};
+use crate::display::DisplayRecovery;
+use crate::error::SensorError;
+use crate::export;
+use crate::helper::units::NumberLocale;
+use crate::page::Page;
+use crate::screen::{Frame, Screen};
+use crate::sensor::{EnvSensor, MeasureFuture};
+
+/// Columns read from the file, the others are ignored.
+const COLUMNS: [&str; 5] = [
+    "timestamp",
+    "sensor",
+    "temperature_c",
+    "humidity_relative",
+    "pressure_pa",
+];
+
+/// Row of the file.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayRow {
+    pub at: DateTime<Local>,
+    pub measurement: Measurement,
+}
+
+/// Sensor measuring the rows of an exported file, in order.
+#[derive(Debug)]
+pub struct CsvReplaySensor {
+    label: String,
+    rows: VecDeque<ReplayRow>,
+}
+
+impl CsvReplaySensor {
+    /// Read the rows of one sensor from an exported file.
+    /// # Arguments
+    /// * `text` - Content of the file, with the header of `export`.
+    /// * `sensor` - Label of the sensor to replay, the one of the first row
+    ///   if not specified.
+    /// * `delimiter` - Field delimiter.
+    /// * `locale` - Separators of the numbers.
+    /// # Returns
+    /// * `Err(message)` naming the line which cannot be read.
+    pub fn parse(
+        text: &str,
+        sensor: Option<&str>,
+        delimiter: char,
+        locale: &NumberLocale,
+    ) -> Result<Self, String> {
+        let mut records = export::parse_records(text, delimiter).into_iter();
+        let header = records.next().ok_or("the file is empty")?;
+        let columns = COLUMNS
+            .iter()
+            .map(|name| {
+                header
+                    .iter()
+                    .position(|column| column == name)
+                    .ok_or_else(|| format!("the file has no {} column", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut label = sensor.map(str::to_string);
+        let mut rows = VecDeque::new();
+        for (i, record) in records.enumerate() {
+            // Line 1 is the header
+            let line = i + 2;
+            let field = |n: usize| record.get(columns[n]).map_or("", String::as_str);
+            if *label.get_or_insert_with(|| field(1).to_string()) != field(1) {
+                continue;
+            }
+            let number = |n: usize| {
+                locale.parse_number(field(n)).ok_or_else(|| {
+                    format!(
+                        "line {}: {} is not a number: {:?}",
+                        line,
+                        COLUMNS[n],
+                        field(n)
+                    )
+                })
+            };
+            let at = parse_timestamp(field(0))
+                .ok_or_else(|| format!("line {}: invalid timestamp {:?}", line, field(0)))?;
+            rows.push_back(ReplayRow {
+                at,
+                measurement: Measurement {
+                    temperature_c: number(2)?,
+                    humidity_relative: number(3)?,
+                    pressure_pa: number(4)?,
+                },
+            });
+        }
+        let label = label.unwrap_or_default();
+        if rows.is_empty() {
+            return Err(format!("the file has no rows of sensor {:?}", label));
+        }
+        Ok(Self { label, rows })
+    }
+
+    /// Time of the next row, `None` once every row was measured.
+    pub fn next_at(&self) -> Option<DateTime<Local>> {
+        self.rows.front().map(|row| row.at)
+    }
+
+    /// Rows not measured yet, in order.
+    pub fn into_rows(self) -> Vec<ReplayRow> {
+        self.rows.into()
+    }
+}
+
+impl EnvSensor for CsvReplaySensor {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        let result = self
+            .rows
+            .pop_front()
+            .map(|row| row.measurement)
+            .ok_or(SensorError::ReplayEnded);
+        Box::pin(async move { result })
+    }
+}
+
+/// Read a timestamp written by `export`, RFC 3339 or without offset.
+/// # Arguments
+/// * `text` - Timestamp of a row.
+/// # Returns
+/// * The time, `None` if it cannot be read. Times without offset are local.
+pub fn parse_timestamp(text: &str) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Local));
+    }
+    let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    Local.from_local_datetime(&time).earliest()
+}
+
+/// Parse a virtual clock acceleration factor.
+/// # Arguments
+/// * `text` - Factor, e.g. "60x" or "60".
+/// # Returns
+/// * `Err(message)` unless it is a number above 0.
+pub fn parse_speed(text: &str) -> Result<f64, String> {
+    let speed: f64 = text
+        .strip_suffix('x')
+        .unwrap_or(text)
+        .parse()
+        .map_err(|_| format!("invalid speed {:?}, e.g. 60x", text))?;
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(format!("speed must be above 0, got {}", text));
+    }
+    Ok(speed)
+}
+
+/// Replay options.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// Virtual clock acceleration factor.
+    pub speed: f64,
+    /// Gaps between rows longer than this are paced as if this long, every
+    /// gap is waited out if not specified.
+    pub max_gap: Option<Duration>,
+}
+
+/// Alert transition of a replayed row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayAlert {
+    /// Time of the row.
+    pub at: DateTime<Local>,
+    pub transition: AlertTransition,
+}
+
+/// Result of a replay.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Rows replayed.
+    pub rows: u64,
+    pub alerts: Vec<ReplayAlert>,
+}
+
+/// Feed the rows through the display and the alert engine.
+/// # Arguments
+/// * `sensor` - Rows to replay.
+/// * `config` - Configuration of the display, the alerts and the THI.
+/// * `options` - Replay options.
+/// * `database` - Database the rows, and the alerts with `history` on, are
+///   stored in. Nothing is stored if not specified.
+/// * `out` - Destination of the drawn display, one line per row.
+/// # Returns
+/// * The alert transitions of the rows.
+pub async fn run<W: Write>(
+    sensor: &mut CsvReplaySensor,
+    config: &Config,
+    options: &ReplayOptions,
+    database: Option<&Database>,
+    out: &mut W,
+) -> Result<ReplayReport, Box<dyn Error>> {
+    let comfort = config.comfort.coefficients();
+    let mut alerts = AlertEngine::from_config(&config.alerts);
+    let recovery = DisplayRecovery::new(0, CgramSlots::default());
+    let display = MockDisplay::with_geometry(config.display.geometry());
+    let mut screen = Screen::new(display, recovery, &config.display);
+    let mut report = ReplayReport::default();
+    // Virtual clock of the alert engine, following the file
+    let start = Instant::now();
+    let mut first = None;
+    let mut previous = None;
+    while let Some(at) = sensor.next_at() {
+        let first = *first.get_or_insert(at);
+        if let Some(previous) = previous.replace(at) {
+            let gap = (at - previous).to_std().unwrap_or_default();
+            let gap = options.max_gap.map_or(gap, |max| gap.min(max));
+            tokio::time::sleep(gap.div_f64(options.speed)).await;
+        }
+        let measurement = sensor.measure().await?;
+        let thi = crate::calc_thi(
+            measurement.temperature_c,
+            measurement.humidity_relative,
+            &comfort,
+        );
+        let now = start + (at - first).to_std().unwrap_or_default();
+        for transition in alerts.update(&measurement, thi, now) {
+            alerts::notify(&transition, None);
+            let episode = AlertEpisode::from_transition(&transition, at);
+            if let (Some(database), Some(episode), true) =
+                (database, episode, config.alerts.history)
+            {
+                database.record_alert(&episode).await?;
+            }
+            report.alerts.push(ReplayAlert { at, transition });
+        }
+
+        let alert_message = alerts.display_message();
+        let frame = Frame {
+            now: at,
+            clock_synced: true,
+            measurement,
+            thi,
+            readonly: false,
+            safe_mode: None,
+            alert: alerts.display_text(),
+            alert_message: alert_message.as_deref(),
+            page: Page::Main,
+            peers: &[],
+            qnh: None,
+            extremes: None,
+            daily_metrics: None,
+        };
+        screen.show(&frame, true).await;
+        let grid = screen.display().grid();
+        writeln!(out, "{}  {}  {}", at.to_rfc3339(), grid[0], grid[1])?;
+
+        if let Some(database) = database {
+            let mut row = SensorData::from_measurement_at(measurement, thi, at);
+            row.sensor = sensor.label().to_string();
+            row.source = Source::Replay;
+            database.save_async(row)?;
+        }
+        report.rows += 1;
+    }
+    if let Some(database) = database {
+        database.flush().await?;
+    }
+    out.flush()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::{Direction, Stage};
+    use crate::config::{AlertField, AlertRuleConfig, AlertRuleKind};
+
+    /// HOT from 09:01 to 09:04 peaking at 32.5 C, an hour without rows, and
+    /// HOT again from 10:05 until the end. The rows of "outdoor" are skipped.
+    const EXPORT: &str = "\
+timestamp;sensor;temperature_c;humidity_relative;pressure_pa;thi;quality
+2025-06-16T09:00:00+09:00;bme280;25,0;50;101325;72,6;good
+2025-06-16T09:00:00+09:00;outdoor;35,0;50;101325;85,2;good
+2025-06-16T09:01:00+09:00;bme280;31,0;50;101325;80,1;good
+2025-06-16T09:02:00+09:00;bme280;32,5;50;101325;81,8;good
+2025-06-16T09:03:00+09:00;bme280;31,5;50;101325;80,6;good
+2025-06-16T09:04:00+09:00;bme280;29,0;50;101325;77,9;good
+2025-06-16T10:05:00+09:00;bme280;30,5;50;101325;79,5;good
+2025-06-16T10:15:00+09:00;bme280;30,5;50;101325;79,5;good
+";
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.alerts.rules.push(AlertRuleConfig {
+            name: "HOT".to_string(),
+            kind: AlertRuleKind::Threshold,
+            field: AlertField::TemperatureC,
+            above: Some(30.0),
+            below: None,
+            band: None,
+            message: None,
+            always_override: false,
+        });
+        config
+    }
+
+    fn sensor() -> CsvReplaySensor {
+        let german = NumberLocale::parse("de").unwrap();
+        CsvReplaySensor::parse(EXPORT, None, ';', &german).unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Local> {
+        parse_timestamp(&format!("2025-06-16T{}+09:00", time)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_parse_rows_of_one_sensor() {
+        let mut sensor = sensor();
+        assert_eq!(sensor.label(), "bme280");
+        assert_eq!(sensor.rows.len(), 7);
+        assert_eq!(sensor.rows[2].measurement.temperature_c, 32.5);
+
+        let german = NumberLocale::parse("de").unwrap();
+        let outdoor = CsvReplaySensor::parse(EXPORT, Some("outdoor"), ';', &german).unwrap();
+        assert_eq!(outdoor.rows.len(), 1);
+        let broken = EXPORT.replace("32,5", "hot");
+        assert_eq!(
+            CsvReplaySensor::parse(&broken, None, ';', &german).unwrap_err(),
+            "line 5: temperature_c is not a number: \"hot\""
+        );
+        assert!(
+            CsvReplaySensor::parse("timestamp;sensor\n", None, ';', &german)
+                .unwrap_err()
+                .contains("temperature_c")
+        );
+
+        // Measured in order, then exhausted
+        let first = sensor.measure().await.unwrap();
+        assert_eq!(first.temperature_c, 25.0);
+        assert_eq!(sensor.next_at(), Some(at("09:01:00")));
+        sensor.rows.clear();
+        assert!(matches!(
+            sensor.measure().await,
+            Err(SensorError::ReplayEnded)
+        ));
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("60x"), Ok(60.0));
+        assert_eq!(parse_speed("0.5"), Ok(0.5));
+        assert!(parse_speed("fast").is_err());
+        assert!(parse_speed("0x").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_alert_events() {
+        let mut out = Vec::new();
+        let options = ReplayOptions {
+            speed: 60.0,
+            max_gap: Some(Duration::from_secs(60)),
+        };
+        let started = tokio::time::Instant::now();
+        let report = run(&mut sensor(), &config(), &options, None, &mut out)
+            .await
+            .unwrap();
+
+        assert_eq!(report.rows, 7);
+        let alerts: Vec<_> = report
+            .alerts
+            .iter()
+            .map(|alert| (alert.at, alert.transition.clone()))
+            .collect();
+        assert_eq!(
+            alerts,
+            vec![
+                (
+                    at("09:01:00"),
+                    AlertTransition::Escalated {
+                        name: "HOT".to_string(),
+                        stage: Stage::Display,
+                        value: 31.0,
+                        elapsed: Duration::ZERO,
+                    }
+                ),
+                (
+                    at("09:04:00"),
+                    AlertTransition::Cleared {
+                        name: "HOT".to_string(),
+                        field: AlertField::TemperatureC,
+                        direction: Direction::Above,
+                        peak: 32.5,
+                        duration: Duration::from_secs(180),
+                    }
+                ),
+                (
+                    at("10:05:00"),
+                    AlertTransition::Escalated {
+                        name: "HOT".to_string(),
+                        stage: Stage::Display,
+                        value: 30.5,
+                        elapsed: Duration::ZERO,
+                    }
+                ),
+                // The log stage follows the file, not the shortened gap
+                (
+                    at("10:15:00"),
+                    AlertTransition::Escalated {
+                        name: "HOT".to_string(),
+                        stage: Stage::Log,
+                        value: 30.5,
+                        elapsed: Duration::from_secs(600),
+                    }
+                ),
+            ]
+        );
+        // 4 one-minute gaps, and the 61 and 10 minute ones shortened to one
+        assert_eq!(started.elapsed(), Duration::from_secs(6));
+
+        let lines = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = lines.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[1].starts_with(&at("09:01:00").to_rfc3339()));
+        assert!(lines[1].contains("HOT"));
+        assert!(!lines[4].contains("HOT"));
+    }
+}
@@ -0,0 +1,398 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Safe mode after repeated crashes.
+//!
+//! Every unclean exit of the daemon is appended to [safe_mode] crash_file
+//! with its exit code, by the panic hook or on the way out of `main`. When
+//! more than max_crashes of them fall within window_mins at start-up, the
+//! daemon starts in safe mode: without the database, hooks, webhooks and
+//! peers, measuring every interval_ms and showing `SAFE MODE E<code>` on
+//! the clock line. An optional feature which keeps crashing cannot
+//! boot-loop the clock and thermometer this way.
+//!
+//! Safe mode ends on `POST /api/safemode/clear`, a long press of the wake
+//! button, or once the last crash has left the window. The crashes are
+//! forgotten and the daemon stops cleanly for systemd to start it again in
+//! normal mode. The episode is kept in the crash file until the next start
+//! with a database stores it in the `events` table.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::config::SafeModeConfig;
+use crate::state;
+
+/// Most crashes kept in the crash file, the oldest are dropped.
+const MAX_KEPT: usize = 32;
+
+/// Crash file of the daemon, set once it starts.
+static CRASH_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Record the crashes of this process from now on. Left unset for the
+/// subcommands, whose failures are not crashes of the daemon.
+/// # Arguments
+/// * `config` - [safe_mode] settings.
+pub fn arm(config: &SafeModeConfig) {
+    if config.enabled {
+        let _ = CRASH_FILE.set(PathBuf::from(&config.crash_file));
+    }
+}
+
+/// Record an unclean exit in the crash file, if armed. Best effort, as it
+/// runs from the panic hook and on the way out.
+/// # Arguments
+/// * `code` - Exit code, see the `exit` module.
+pub fn record_crash(code: u8) {
+    let Some(path) = CRASH_FILE.get() else {
+        return;
+    };
+    let mut crashes = read_crashes(path).unwrap_or_default();
+    crashes.push(Crash {
+        at: Local::now(),
+        code,
+    });
+    let first = crashes.len().saturating_sub(MAX_KEPT);
+    if let Err(e) = write_crashes(path, &crashes[first..]) {
+        eprintln!("Failed to record the crash in {}: {}", path.display(), e);
+    }
+}
+
+/// Unclean exit of the daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crash {
+    pub at: DateTime<Local>,
+    /// Exit code, see the `exit` module.
+    pub code: u8,
+}
+
+/// Crashes within a window.
+/// # Arguments
+/// * `crashes` - Crashes, oldest first.
+/// * `window` - Length of the window.
+/// * `now` - End of the window.
+/// # Returns
+/// * The crashes after `now - window`, oldest first.
+pub fn recent(crashes: &[Crash], window: Duration, now: DateTime<Local>) -> Vec<Crash> {
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+    crashes
+        .iter()
+        .filter(|crash| now.signed_duration_since(crash.at) < window)
+        .copied()
+        .collect()
+}
+
+/// Why safe mode ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Requested over HTTP.
+    Cleared,
+    /// Long press of the wake button.
+    LongPress,
+    /// The last crash left the window.
+    Expired,
+}
+
+impl ExitReason {
+    /// Name stored with the episode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::Cleared => "cleared",
+            ExitReason::LongPress => "long_press",
+            ExitReason::Expired => "expired",
+        }
+    }
+}
+
+/// Safe mode as reported by `GET /api/safemode`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SafeModeStatus {
+    /// Exit code of the last crash.
+    pub code: u8,
+    /// Crashes within the window.
+    pub crashes: usize,
+    pub since: String,
+    /// When safe mode ends by itself, unless the daemon crashes again.
+    pub until: String,
+}
+
+/// Safe mode the daemon runs in, shared with the HTTP API.
+#[derive(Debug)]
+pub struct SafeMode {
+    crash_file: PathBuf,
+    status: SafeModeStatus,
+    until: DateTime<Local>,
+    clear_requested: AtomicBool,
+}
+
+impl SafeMode {
+    /// Decide at start-up whether to run in safe mode. The episode is
+    /// written to the crash file, to be stored once a database is
+    /// reachable.
+    /// # Arguments
+    /// * `config` - [safe_mode] settings.
+    /// * `now` - Start-up time.
+    /// # Returns
+    /// * `Some(safe_mode)` after more than `max_crashes` crashes within the
+    ///   window.
+    pub fn check(config: &SafeModeConfig, now: DateTime<Local>) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let crash_file = PathBuf::from(&config.crash_file);
+        let crashes = read_crashes(&crash_file).unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to read the crashes from {}: {}",
+                crash_file.display(),
+                e
+            );
+            Vec::new()
+        });
+        let recent = recent(&crashes, config.window(), now);
+        let last = *recent.last()?;
+        if recent.len() <= config.max_crashes as usize {
+            return None;
+        }
+        let window = chrono::Duration::from_std(config.window()).unwrap_or(chrono::Duration::MAX);
+        let until = last.at + window;
+        let status = SafeModeStatus {
+            code: last.code,
+            crashes: recent.len(),
+            since: now.to_rfc3339_opts(SecondsFormat::Secs, false),
+            until: until.to_rfc3339_opts(SecondsFormat::Secs, false),
+        };
+        if let Err(e) = state::write(&crash_file, [("safe_mode", json!(status))]) {
+            eprintln!(
+                "Failed to record safe mode in {}: {}",
+                crash_file.display(),
+                e
+            );
+        }
+        Some(Self {
+            crash_file,
+            status,
+            until,
+            clear_requested: AtomicBool::new(false),
+        })
+    }
+
+    /// Exit code of the last crash, shown on the display.
+    pub fn code(&self) -> u8 {
+        self.status.code
+    }
+
+    /// Crashes and times of the safe mode.
+    pub fn status(&self) -> SafeModeStatus {
+        self.status.clone()
+    }
+
+    /// Ask the measurement loop to end safe mode.
+    pub fn request_clear(&self) {
+        self.clear_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether safe mode should end now.
+    /// # Arguments
+    /// * `long_press` - Whether the wake button was just held down long.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * Why safe mode ends, `None` to carry on.
+    pub fn exit_reason(&self, long_press: bool, now: DateTime<Local>) -> Option<ExitReason> {
+        if self.clear_requested.load(Ordering::Relaxed) {
+            Some(ExitReason::Cleared)
+        } else if long_press {
+            Some(ExitReason::LongPress)
+        } else if now >= self.until {
+            Some(ExitReason::Expired)
+        } else {
+            None
+        }
+    }
+
+    /// End safe mode: forget the crashes so the next start is a normal
+    /// one, and note how the episode ended.
+    /// # Arguments
+    /// * `reason` - Why safe mode ends.
+    /// * `now` - Current time.
+    pub fn leave(&self, reason: ExitReason, now: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+        let mut episode = json!(self.status);
+        episode["ended_at"] = json!(now.to_rfc3339_opts(SecondsFormat::Secs, false));
+        episode["reason"] = json!(reason.as_str());
+        state::write(
+            &self.crash_file,
+            [("crashes", json!([])), ("safe_mode", episode)],
+        )
+    }
+}
+
+/// Take the last safe mode episode out of the crash file, for storing it
+/// as an event once a database is reachable.
+/// # Arguments
+/// * `config` - [safe_mode] settings.
+/// # Returns
+/// * `Ok(Some(detail))` once per episode.
+pub fn take_episode(config: &SafeModeConfig) -> Result<Option<Value>, Box<dyn Error>> {
+    let path = Path::new(&config.crash_file);
+    let Some(mut state) = state::read(path)? else {
+        return Ok(None);
+    };
+    let episode = state.remove("safe_mode").filter(Value::is_object);
+    if episode.is_some() {
+        state::write(path, [("safe_mode", Value::Null)])?;
+    }
+    Ok(episode)
+}
+
+/// Read the crashes of a crash file, oldest first.
+fn read_crashes(path: &Path) -> Result<Vec<Crash>, Box<dyn Error>> {
+    let Some(state) = state::read(path)? else {
+        return Ok(Vec::new());
+    };
+    let Some(crashes) = state.get("crashes").and_then(Value::as_array) else {
+        return Ok(Vec::new());
+    };
+    Ok(crashes
+        .iter()
+        .filter_map(|crash| {
+            let at = DateTime::parse_from_rfc3339(crash.get("at")?.as_str()?).ok()?;
+            let code = u8::try_from(crash.get("code")?.as_u64()?).ok()?;
+            Some(Crash {
+                at: at.with_timezone(&Local),
+                code,
+            })
+        })
+        .collect())
+}
+
+fn write_crashes(path: &Path, crashes: &[Crash]) -> Result<(), Box<dyn Error>> {
+    let crashes: Vec<_> = crashes
+        .iter()
+        .map(|crash| json!({ "at": crash.at.to_rfc3339(), "code": crash.code }))
+        .collect();
+    state::write(path, [("crashes", json!(crashes))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+
+    fn config(name: &str) -> SafeModeConfig {
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        SafeModeConfig {
+            crash_file: path.to_string_lossy().into_owned(),
+            ..SafeModeConfig::default()
+        }
+    }
+
+    fn at(minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 6, 16, 14, minute, 0).unwrap()
+    }
+
+    fn crash(minute: u32, code: u8) -> Crash {
+        Crash {
+            at: at(minute),
+            code,
+        }
+    }
+
+    #[test]
+    fn test_recent() {
+        let crashes = [crash(0, 8), crash(5, 5), crash(12, 8)];
+
+        assert_eq!(
+            recent(&crashes, Duration::from_secs(600), at(14)),
+            vec![crash(5, 5), crash(12, 8)]
+        );
+        assert!(recent(&crashes, Duration::from_secs(60), at(14)).is_empty());
+    }
+
+    #[test]
+    fn test_safe_mode_after_too_many_crashes() {
+        let config = config("safe-mode");
+        let path = Path::new(&config.crash_file);
+        assert!(SafeMode::check(&config, at(0)).is_none());
+
+        // An old crash is not counted
+        write_crashes(
+            path,
+            &[crash(0, 8), crash(20, 8), crash(21, 5), crash(22, 8)],
+        )
+        .unwrap();
+        assert!(SafeMode::check(&config, at(23)).is_none());
+        write_crashes(
+            path,
+            &[crash(20, 8), crash(21, 5), crash(22, 8), crash(23, 5)],
+        )
+        .unwrap();
+        let safe_mode = SafeMode::check(&config, at(24)).unwrap();
+        assert_eq!(safe_mode.code(), 5);
+        assert_eq!(safe_mode.status().crashes, 4);
+        assert_eq!(safe_mode.exit_reason(false, at(32)), None);
+        // Ten minutes after the last crash
+        assert_eq!(
+            safe_mode.exit_reason(false, at(33)),
+            Some(ExitReason::Expired)
+        );
+        assert_eq!(
+            safe_mode.exit_reason(true, at(25)),
+            Some(ExitReason::LongPress)
+        );
+        safe_mode.request_clear();
+        assert_eq!(
+            safe_mode.exit_reason(true, at(25)),
+            Some(ExitReason::Cleared)
+        );
+
+        // The next start is a normal one, and stores the episode once
+        safe_mode.leave(ExitReason::Cleared, at(26)).unwrap();
+        assert!(SafeMode::check(&config, at(27)).is_none());
+        let episode = take_episode(&config).unwrap().unwrap();
+        assert_eq!(episode["code"], 5);
+        assert_eq!(episode["crashes"], 4);
+        assert_eq!(episode["reason"], "cleared");
+        assert!(take_episode(&config).unwrap().is_none());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let config = SafeModeConfig {
+            enabled: false,
+            ..config("safe-mode-disabled")
+        };
+        let path = Path::new(&config.crash_file);
+        write_crashes(path, &[crash(0, 8), crash(1, 8), crash(2, 8), crash(3, 8)]).unwrap();
+
+        assert!(SafeMode::check(&config, at(4)).is_none());
+        let _ = fs::remove_file(path);
+    }
+}
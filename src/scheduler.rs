@@ -0,0 +1,289 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Measurement tick schedule without drift.
+//!
+//! Tick n is due at the start time plus n intervals, computed from the start
+//! instead of from the previous tick, so a late tick does not delay the ones
+//! after it. Ticks whose time passed while a cycle overran are skipped or
+//! caught up as configured.
+//!
+//! Unlike `tokio::time::interval`, whose first tick completes right away, the
+//! first tick is due one interval after the start by default. The first
+//! frame is then drawn well after the display setup instead of right behind
+//! its 20 ms wait, which some displays are not ready for yet.
+//!
+//! With `[database] align_to_interval` the schedule is moved onto the
+//! wall-clock multiples of the interval, so the readings closing the save
+//! windows are taken right at their boundaries.
+
+use chrono::{DateTime, Local};
+use tokio::time::{Duration, Instant, sleep_until};
+
+use crate::config::{FirstTick, MissedTicks};
+
+/// Schedule of the measurement ticks.
+#[derive(Debug)]
+pub struct TickScheduler {
+    start: Instant,
+    period: Duration,
+    missed: MissedTicks,
+    /// Index of the next tick.
+    next: u64,
+}
+
+/// Next tick of the schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    /// Time the tick is due, in the past when it runs late.
+    pub due: Instant,
+    /// Ticks skipped because their time passed.
+    pub skipped: u64,
+}
+
+impl TickScheduler {
+    /// Create a schedule whose first tick is due at `start`.
+    /// # Arguments
+    /// * `start` - Time of the first tick.
+    /// * `period` - Interval between ticks, at least 1 ms.
+    /// * `missed` - Handling of missed ticks.
+    pub fn new(start: Instant, period: Duration, missed: MissedTicks) -> Self {
+        Self {
+            start,
+            period: period.max(Duration::from_millis(1)),
+            missed,
+            next: 0,
+        }
+    }
+
+    /// Create a schedule starting now.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// * `period` - Interval between ticks.
+    /// * `missed` - Handling of missed ticks.
+    /// * `first` - When the first tick is due.
+    pub fn starting(now: Instant, period: Duration, missed: MissedTicks, first: FirstTick) -> Self {
+        let start = match first {
+            FirstTick::Immediate => now,
+            FirstTick::AfterInterval => now + period,
+        };
+        Self::new(start, period, missed)
+    }
+
+    /// Start over with another interval, the first tick due at `start`.
+    /// # Arguments
+    /// * `start` - Time of the first tick.
+    /// * `period` - Interval between ticks.
+    pub fn restart(&mut self, start: Instant, period: Duration) {
+        *self = Self::new(start, period, self.missed);
+    }
+
+    /// Move the ticks onto the wall-clock multiples of the interval, by less
+    /// than one interval. The wall clock is only read here: a later step or
+    /// drift of it does not move the ticks.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// * `wall` - Wall-clock time at `now`.
+    pub fn align(&mut self, now: Instant, wall: DateTime<Local>) {
+        let period = self.period.as_nanos() as i128;
+        let ahead = chrono::Duration::from_std(self.start.saturating_duration_since(now))
+            .unwrap_or_default();
+        let start = (wall.naive_local() + ahead).and_utc();
+        let phase = i128::from(start.timestamp_nanos_opt().unwrap_or(0)).rem_euclid(period);
+        let delay = (period - phase) % period;
+        self.start += Duration::from_nanos(delay as u64);
+    }
+
+    /// Time tick `n` is due.
+    fn due(&self, n: u64) -> Instant {
+        let offset = self.period.as_nanos().saturating_mul(u128::from(n));
+        self.start + Duration::from_nanos(u64::try_from(offset).unwrap_or(u64::MAX))
+    }
+
+    /// Take the next tick.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * The tick. With `MissedTicks::Skip` a tick more than one interval
+    ///   late is replaced by the latest one due, which runs right away.
+    pub fn next_tick(&mut self, now: Instant) -> Tick {
+        let mut n = self.next;
+        let mut skipped = 0;
+        if self.missed == MissedTicks::Skip && now >= self.due(n) + self.period {
+            let elapsed = now.saturating_duration_since(self.start).as_nanos();
+            let latest = u64::try_from(elapsed / self.period.as_nanos()).unwrap_or(u64::MAX);
+            skipped = latest - n;
+            n = latest;
+        }
+        self.next = n + 1;
+        Tick {
+            due: self.due(n),
+            skipped,
+        }
+    }
+
+    /// Wait for the next tick, logging skipped ticks.
+    pub async fn tick(&mut self) {
+        let tick = self.next_tick(Instant::now());
+        if tick.skipped > 0 {
+            eprintln!(
+                "Measurement overran, skipped {} tick(s) of {:?}",
+                tick.skipped, self.period
+            );
+        }
+        sleep_until(tick.due).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(200);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_on_time_ticks_stay_on_grid() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(start, PERIOD, MissedTicks::Skip);
+        let first = scheduler.next_tick(start);
+        assert_eq!(
+            first,
+            Tick {
+                due: start,
+                skipped: 0
+            }
+        );
+        // Each cycle wakes a little late, the schedule does not drift
+        for n in 1..=100u64 {
+            let woke = start + PERIOD * (n as u32 - 1) + ms(7);
+            let tick = scheduler.next_tick(woke);
+            assert_eq!(tick.due, start + PERIOD * n as u32);
+            assert_eq!(tick.skipped, 0);
+        }
+    }
+
+    #[test]
+    fn test_align_to_wall_clock() {
+        use chrono::TimeZone;
+        let now = Instant::now();
+        let wall = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 0).unwrap() + ms(130);
+        let mut scheduler =
+            TickScheduler::starting(now, PERIOD, MissedTicks::Skip, FirstTick::AfterInterval);
+        // The first tick at 14:30:00.330 moves to 14:30:00.400
+        scheduler.align(now, wall);
+        assert_eq!(scheduler.next_tick(now).due, now + ms(270));
+        assert_eq!(scheduler.next_tick(now).due, now + ms(470));
+
+        let mut scheduler = TickScheduler::new(now, PERIOD, MissedTicks::Skip);
+        scheduler.align(now, wall - ms(130));
+        assert_eq!(scheduler.next_tick(now).due, now);
+    }
+
+    #[test]
+    fn test_short_overrun_keeps_every_tick() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(start, PERIOD, MissedTicks::Skip);
+        scheduler.next_tick(start);
+        // Tick 1 is due at 200 ms, the cycle ended at 350 ms
+        let tick = scheduler.next_tick(start + ms(350));
+        assert_eq!(
+            tick,
+            Tick {
+                due: start + ms(200),
+                skipped: 0
+            }
+        );
+        assert_eq!(scheduler.next_tick(start + ms(360)).due, start + ms(400));
+    }
+
+    #[test]
+    fn test_overrun_skips_missed_ticks() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(start, PERIOD, MissedTicks::Skip);
+        scheduler.next_tick(start);
+        // The cycle took 1.05 s, ticks 1 to 4 were missed
+        let tick = scheduler.next_tick(start + ms(1050));
+        assert_eq!(
+            tick,
+            Tick {
+                due: start + ms(1000),
+                skipped: 4
+            }
+        );
+        assert_eq!(
+            scheduler.next_tick(start + ms(1060)),
+            Tick {
+                due: start + ms(1200),
+                skipped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_overrun_catches_up() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(start, PERIOD, MissedTicks::CatchUp);
+        scheduler.next_tick(start);
+        let late = start + ms(1050);
+        let dues: Vec<_> = (0..6).map(|_| scheduler.next_tick(late)).collect();
+        for (i, tick) in dues.iter().enumerate() {
+            assert_eq!(tick.due, start + PERIOD * (i as u32 + 1));
+            assert_eq!(tick.skipped, 0);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_tick_after_one_interval() {
+        let started = Instant::now();
+        let mut scheduler =
+            TickScheduler::starting(started, PERIOD, MissedTicks::Skip, FirstTick::AfterInterval);
+        scheduler.tick().await;
+        assert_eq!(Instant::now() - started, PERIOD);
+        scheduler.tick().await;
+        assert_eq!(Instant::now() - started, PERIOD * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_immediate_first_tick() {
+        let started = Instant::now();
+        let mut scheduler =
+            TickScheduler::starting(started, PERIOD, MissedTicks::Skip, FirstTick::Immediate);
+        scheduler.tick().await;
+        assert_eq!(Instant::now(), started);
+        scheduler.tick().await;
+        assert_eq!(Instant::now() - started, PERIOD);
+    }
+
+    #[test]
+    fn test_restart_with_new_period() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(start, PERIOD, MissedTicks::Skip);
+        scheduler.next_tick(start);
+        let restart = start + ms(500);
+        scheduler.restart(restart, ms(50));
+        assert_eq!(scheduler.next_tick(restart).due, restart);
+        assert_eq!(scheduler.next_tick(restart).due, restart + ms(50));
+    }
+}
@@ -0,0 +1,495 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Display side of the measurement loop.
+//!
+//! The loop hands every measurement to a `Screen`, which rounds it with
+//! hysteresis, renders the main page and draws it. The screen owns its
+//! display and only relies on `CharDisplay`, so any display implementing
+//! the trait can be injected in place of the configured SO1602A or HD44780
+//! while the formatting stays the same. The pages are laid out for the
+//! geometry the display reports.
+//!
+//! While another program holds the display lease the screen is paused and
+//! writes nothing. The first draw after the pause initializes the display
+//! again, since its content and custom characters are unknown by then.
+
+use chrono::prelude::*;
+use peripheral::bme280::Measurement;
+use peripheral::display::{CharDisplay, DisplayGeometry};
+use rppal::i2c;
+
+use crate::altimeter::QnhStatus;
+use crate::config::DisplayConfig;
+use crate::daily_metrics::DailyMetricsStatus;
+use crate::display::{DisplayRecovery, WriteOutcome};
+use crate::extremes::ExtremesStatus;
+use crate::helper::scroll::Scroller;
+use crate::helper::{HysteresisRounder, MeasurementFormat};
+use crate::page::{self, Page};
+use crate::peers::PeerSummary;
+
+/// Frames of the activity indicator after the backslash glyph, whose slot
+/// is allocated with the custom characters.
+const INDICATOR: [char; 3] = ['|', '/', '-'];
+
+/// Data of one measurement cycle, before rounding.
+#[derive(Debug, Clone)]
+pub struct Frame<'a> {
+    /// Current time.
+    pub now: DateTime<Local>,
+    /// Whether the clock is synchronized.
+    pub clock_synced: bool,
+    /// Measurement with offsets applied.
+    pub measurement: Measurement,
+    /// Temperature-humidity index of the measurement.
+    pub thi: f64,
+    /// Whether read-only maintenance mode is active.
+    pub readonly: bool,
+    /// Exit code of the last crash while in safe mode.
+    pub safe_mode: Option<u8>,
+    /// Name of the active alert.
+    pub alert: Option<&'a str>,
+    /// Message of the active alert, scrolled if it does not fit.
+    pub alert_message: Option<&'a str>,
+    /// Page to draw.
+    pub page: Page,
+    /// Other units, for the peers pages.
+    pub peers: &'a [PeerSummary],
+    /// QNH setting, for the altimeter page.
+    pub qnh: Option<QnhStatus>,
+    /// Extremes since last viewed, for the extremes page.
+    pub extremes: Option<ExtremesStatus>,
+    /// Degree-hours and mold risk, for the degree-hours page.
+    pub daily_metrics: Option<DailyMetricsStatus>,
+}
+
+/// Display fed by the measurement loop.
+pub struct Screen<D> {
+    display: D,
+    geometry: DisplayGeometry,
+    recovery: DisplayRecovery,
+    format: MeasurementFormat,
+    temperature_rounder: HysteresisRounder,
+    humidity_rounder: HysteresisRounder,
+    thi_rounder: HysteresisRounder,
+    indicator: Vec<String>,
+    counter: usize,
+    scroller: Scroller,
+    /// Whether the display is handed to someone else.
+    paused: bool,
+    /// Whether to initialize the display before the next draw.
+    redraw: bool,
+    /// Lines of the latest frame drawn, for the debug trace.
+    drawn: Option<Vec<String>>,
+}
+
+impl<D: CharDisplay> Screen<D> {
+    /// Create a screen on an initialized display.
+    /// # Arguments
+    /// * `display` - Display to draw on.
+    /// * `recovery` - Retry and re-init policy of the display writes.
+    /// * `config` - Display configuration, for the precision and rounding.
+    pub fn new(display: D, recovery: DisplayRecovery, config: &DisplayConfig) -> Self {
+        let format = config.measurement_format();
+        let margin = config.rounding_hysteresis;
+        let indicator = std::iter::once(char::from(recovery.slots().indicator()))
+            .chain(INDICATOR)
+            .map(String::from)
+            .collect();
+        let geometry = display.geometry();
+        Self {
+            display,
+            geometry,
+            recovery,
+            format,
+            temperature_rounder: HysteresisRounder::with_decimals(
+                format.temperature_decimals,
+                margin,
+                format.rounding,
+            ),
+            humidity_rounder: HysteresisRounder::with_decimals(
+                format.humidity_decimals,
+                margin,
+                format.rounding,
+            ),
+            thi_rounder: HysteresisRounder::new(1.0, margin),
+            indicator,
+            counter: 0,
+            scroller: Scroller::new(),
+            paused: false,
+            redraw: false,
+            drawn: None,
+        }
+    }
+
+    /// Hand the display to someone else or take it back.
+    /// # Arguments
+    /// * `paused` - Whether to stop writing to the display.
+    /// # Returns
+    /// * `true` when the display was taken back.
+    pub fn set_paused(&mut self, paused: bool) -> bool {
+        let resumed = self.paused && !paused;
+        self.redraw |= resumed;
+        self.paused = paused;
+        resumed
+    }
+
+    /// Whether the display is handed to someone else.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The display drawn on.
+    pub fn display(&self) -> &D {
+        &self.display
+    }
+
+    /// Lines of the latest frame drawn by `show`, `None` before the first.
+    pub fn drawn(&self) -> Option<&[String]> {
+        self.drawn.as_deref()
+    }
+
+    /// Run a write on the display, retrying it and re-initializing the
+    /// display on failure.
+    /// # Arguments
+    /// * `what` - Description of the write for the log.
+    /// * `write` - Write to run.
+    /// # Returns
+    /// * What it took to complete the write, `None` while paused.
+    pub async fn write<F>(&self, what: &str, write: F) -> Option<WriteOutcome>
+    where
+        F: FnMut(&D) -> Result<(), i2c::Error>,
+    {
+        if self.paused {
+            return None;
+        }
+        Some(self.recovery.write(&self.display, what, write).await)
+    }
+
+    /// Initialize the display after it was taken back.
+    async fn reinit(&self) {
+        if let Err(e) = self.recovery.reinit(&self.display).await {
+            eprintln!("Failed to re-initialize the display: {}", e);
+        }
+    }
+
+    /// Show a measurement cycle.
+    /// The rounding, the activity indicator and the alert message advance
+    /// even when nothing is drawn, so the display resumes where it would
+    /// have been. The message scrolls by one column per cycle and starts
+    /// over with the next alert.
+    /// # Arguments
+    /// * `frame` - Data of the cycle.
+    /// * `visible` - Whether to draw, `false` while the display is off.
+    /// # Returns
+    /// * The outcome of the write, `None` if nothing was drawn or the screen
+    ///   is paused.
+    pub async fn show(&mut self, frame: &Frame<'_>, visible: bool) -> Option<WriteOutcome> {
+        // Only the displayed values are held, stored rows keep the raw ones
+        let shown = Measurement {
+            temperature_c: self
+                .temperature_rounder
+                .update(frame.measurement.temperature_c),
+            humidity_relative: self
+                .humidity_rounder
+                .update(frame.measurement.humidity_relative),
+            ..frame.measurement
+        };
+        let shown_thi = self.thi_rounder.update(frame.thi);
+        let indicator = &self.indicator[self.counter];
+        self.counter = (self.counter + 1) % self.indicator.len();
+        let message = match frame.alert_message {
+            Some(message) => Some(
+                self.scroller
+                    .next_window(message, usize::from(self.geometry.cols) - 1),
+            ),
+            None => {
+                self.scroller.reset();
+                None
+            }
+        };
+        if !visible || self.paused {
+            return None;
+        }
+        if std::mem::take(&mut self.redraw) {
+            self.reinit().await;
+        }
+        let context = page::PageContext {
+            geometry: self.geometry,
+            now: frame.now,
+            clock_synced: frame.clock_synced,
+            measurement: shown,
+            thi: shown_thi,
+            format: self.format,
+            readonly: frame.readonly,
+            safe_mode: frame.safe_mode,
+            alert: frame.alert,
+            alert_message: message.as_deref(),
+            indicator,
+            peers: frame.peers,
+            qnh: frame.qnh,
+            extremes: frame.extremes,
+            daily_metrics: frame.daily_metrics,
+        };
+        let lines = page::render(frame.page, &context);
+        let outcome = self
+            .recovery
+            .write(&self.display, "update", |d| page::draw(d, &lines))
+            .await;
+        self.drawn = Some(lines);
+        Some(outcome)
+    }
+
+    /// Show the clock and a failed subsystem in place of the measurement,
+    /// while running without it.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// * `clock_synced` - Whether the clock is synchronized.
+    /// * `subsystem` - Name of the failed subsystem.
+    /// * `visible` - Whether to draw, `false` while the display is off.
+    /// # Returns
+    /// * The outcome of the write, `None` if nothing was drawn or the screen
+    ///   is paused.
+    pub async fn show_fault(
+        &mut self,
+        now: DateTime<Local>,
+        clock_synced: bool,
+        subsystem: &str,
+        visible: bool,
+    ) -> Option<WriteOutcome> {
+        let indicator = &self.indicator[self.counter];
+        self.counter = (self.counter + 1) % self.indicator.len();
+        if !visible || self.paused {
+            return None;
+        }
+        if std::mem::take(&mut self.redraw) {
+            self.reinit().await;
+        }
+        let lines = page::render_fault(now, clock_synced, subsystem, indicator, self.geometry);
+        Some(
+            self.recovery
+                .write(&self.display, "update", |d| page::draw(d, &lines))
+                .await,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgram::CgramSlots;
+    use peripheral::display::MockDisplay;
+    use std::cell::{Cell, RefCell};
+
+    /// Mock display recording every line written to it and counting
+    /// initializations.
+    #[derive(Default)]
+    struct RecordingDisplay {
+        inner: MockDisplay,
+        lines: RefCell<Vec<String>>,
+        setups: Cell<usize>,
+    }
+
+    impl CharDisplay for RecordingDisplay {
+        async fn setup(&self) -> Result<(), i2c::Error> {
+            self.setups.set(self.setups.get() + 1);
+            self.inner.setup().await
+        }
+
+        fn geometry(&self) -> DisplayGeometry {
+            self.inner.geometry()
+        }
+
+        fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+            self.inner.register_char(index, data)
+        }
+
+        fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+            self.inner.put_u8(position, data)
+        }
+
+        fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+            self.lines.borrow_mut().push(s.to_string());
+            self.inner.put_str(line_addr, s)
+        }
+
+        fn clear_home(&self) -> Result<(), i2c::Error> {
+            self.inner.clear_home()
+        }
+
+        fn display_off(&self) -> Result<(), i2c::Error> {
+            self.inner.display_off()
+        }
+
+        fn display_on(&self) -> Result<(), i2c::Error> {
+            self.inner.display_on()
+        }
+    }
+
+    fn frame(temperature_c: f64) -> Frame<'static> {
+        Frame {
+            now: Local.with_ymd_and_hms(2025, 6, 1, 12, 34, 0).unwrap(),
+            clock_synced: true,
+            measurement: Measurement {
+                temperature_c,
+                humidity_relative: 65.2,
+                pressure_pa: 101325.0,
+            },
+            thi: 72.0,
+            readonly: false,
+            safe_mode: None,
+            alert: None,
+            alert_message: None,
+            page: Page::Main,
+            peers: &[],
+            qnh: None,
+            extremes: None,
+            daily_metrics: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_display_receives_each_cycle() {
+        let recovery = DisplayRecovery::new(0, CgramSlots::default());
+        let mut screen = Screen::new(
+            RecordingDisplay::default(),
+            recovery,
+            &DisplayConfig::default(),
+        );
+
+        for (cycle, temperature_c) in [23.7, 24.1, 24.6].into_iter().enumerate() {
+            assert_eq!(
+                screen.show(&frame(temperature_c), true).await,
+                Some(WriteOutcome::Written)
+            );
+            let lines = screen.display().lines.borrow();
+            assert_eq!(lines.len(), 2 * (cycle + 1));
+            assert_eq!(lines[2 * cycle], "2025/06/01 12:34");
+            assert!(lines[2 * cycle + 1].starts_with(&format!("{:.1}C", temperature_c)));
+        }
+        let grid = screen.display().inner.grid();
+        assert!(grid[1].ends_with('/'), "{:?}", grid);
+    }
+
+    #[tokio::test]
+    async fn test_hidden_cycle_advances_without_drawing() {
+        let recovery = DisplayRecovery::new(0, CgramSlots::default());
+        let mut screen = Screen::new(
+            RecordingDisplay::default(),
+            recovery,
+            &DisplayConfig::default(),
+        );
+
+        assert_eq!(screen.show(&frame(23.7), false).await, None);
+        assert!(screen.display().lines.borrow().is_empty());
+        screen.show(&frame(23.7), true).await;
+        let grid = screen.display().inner.grid();
+        assert!(grid[1].ends_with('|'), "{:?}", grid);
+    }
+
+    #[tokio::test]
+    async fn test_alert_message_scrolls_until_cleared() {
+        let recovery = DisplayRecovery::new(0, CgramSlots::default());
+        let mut screen = Screen::new(
+            RecordingDisplay::default(),
+            recovery,
+            &DisplayConfig::default(),
+        );
+        let alert = Frame {
+            alert: Some("WET"),
+            alert_message: Some("HIGH HUMIDITY 85% - CHECK VENTILATION"),
+            ..frame(23.7)
+        };
+
+        screen.show(&alert, true).await;
+        let grid = screen.display().inner.grid();
+        assert_eq!(grid[0], "! WET           ");
+        assert!(grid[1].starts_with("HIGH HUMIDITY 8"), "{:?}", grid);
+        screen.show(&alert, false).await;
+        screen.show(&alert, true).await;
+        assert!(screen.display().inner.grid()[1].starts_with("GH HUMIDITY 85%"));
+
+        screen.show(&frame(23.7), true).await;
+        assert!(screen.display().inner.grid()[1].starts_with("23.7C"));
+        // The next alert starts from the beginning
+        screen.show(&alert, true).await;
+        assert!(screen.display().inner.grid()[1].starts_with("HIGH HUMIDITY 8"));
+    }
+
+    #[tokio::test]
+    async fn test_four_lines_keep_the_measurement_during_alerts() {
+        let recovery = DisplayRecovery::new(0, CgramSlots::default());
+        let display = RecordingDisplay {
+            inner: MockDisplay::with_geometry(peripheral::so1602a::SO2004A_GEOMETRY),
+            ..Default::default()
+        };
+        let mut screen = Screen::new(display, recovery, &DisplayConfig::default());
+        let alert = Frame {
+            alert: Some("WET"),
+            alert_message: Some("HIGH HUMIDITY 85% - CHECK VENTILATION"),
+            ..frame(23.7)
+        };
+
+        screen.show(&alert, true).await;
+        let grid = screen.display().inner.grid();
+        assert_eq!(grid[0], format!("{:<20}", "! WET"));
+        assert_eq!(grid[1], "T 23.7C      H 65.2%");
+        assert_eq!(grid[2], "P 1013.2hPa   THI 72");
+        // The message scrolls through the 19 columns before the indicator
+        assert_eq!(grid[3], "HIGH HUMIDITY 85% -₁");
+        assert_eq!(screen.drawn().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_paused_screen_redraws_on_resume() {
+        let recovery = DisplayRecovery::new(0, CgramSlots::default());
+        let mut screen = Screen::new(
+            RecordingDisplay::default(),
+            recovery,
+            &DisplayConfig::default(),
+        );
+        screen.show(&frame(23.7), true).await;
+
+        assert!(!screen.set_paused(true));
+        assert_eq!(screen.show(&frame(24.1), true).await, None);
+        assert_eq!(screen.write("off", |d| d.display_off()).await, None);
+        assert_eq!(screen.display().lines.borrow().len(), 2);
+        // The lease holder draws its own content meanwhile
+        let display = &screen.display().inner;
+        display.clear_home().unwrap();
+        display
+            .put_str(display.line_address(0), "Now playing")
+            .unwrap();
+
+        assert!(screen.set_paused(false));
+        assert_eq!(
+            screen.show(&frame(24.6), true).await,
+            Some(WriteOutcome::Written)
+        );
+        let grid = screen.display().inner.grid();
+        assert_eq!(grid[0], "2025/06/01 12:34");
+        assert!(grid[1].starts_with("24.6C"), "{:?}", grid);
+        assert_eq!(screen.display().setups.get(), 1);
+        screen.show(&frame(24.6), true).await;
+        assert_eq!(screen.display().setups.get(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Screensaver blanking the display after a period of inactivity.
+//!
+//! The idle/wake decision and the double and long press detection are
+//! kept apart from the GPIO button so they can be tested without hardware.
+
+use std::time::{Duration, Instant};
+
+use rppal::gpio;
+
+/// Transition reported by `Screensaver::update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreensaverTransition {
+    /// No change since the last update.
+    Unchanged,
+    /// The idle timeout expired, the display should be turned off.
+    Blank,
+    /// Activity while blanked, the display should be turned back on.
+    Wake,
+}
+
+/// Idle tracker deciding when to blank and wake the display.
+#[derive(Debug)]
+pub struct Screensaver {
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+    blanked: bool,
+}
+
+impl Screensaver {
+    /// Create a new screensaver.
+    /// # Arguments
+    /// * `idle_timeout` - Idle period before blanking, `None` to never blank.
+    /// * `now` - Current time, counted as activity.
+    pub fn new(idle_timeout: Option<Duration>, now: Instant) -> Self {
+        Self {
+            idle_timeout,
+            last_activity: now,
+            blanked: false,
+        }
+    }
+
+    /// Update the state.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// * `activity` - Whether there was activity (button press, alert) since the last update.
+    /// # Returns
+    /// * The transition caused by this update.
+    pub fn update(&mut self, now: Instant, activity: bool) -> ScreensaverTransition {
+        if activity {
+            self.last_activity = now;
+            if self.blanked {
+                self.blanked = false;
+                return ScreensaverTransition::Wake;
+            }
+            return ScreensaverTransition::Unchanged;
+        }
+        let idle = now.saturating_duration_since(self.last_activity);
+        match self.idle_timeout {
+            Some(timeout) if !self.blanked && idle >= timeout => {
+                self.blanked = true;
+                ScreensaverTransition::Blank
+            }
+            _ => ScreensaverTransition::Unchanged,
+        }
+    }
+
+    /// Whether the display is blanked.
+    pub fn is_blanked(&self) -> bool {
+        self.blanked
+    }
+}
+
+/// Longest time between the two presses of a double press.
+pub const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(800);
+
+/// Detects double presses of the button, polled once per measurement cycle.
+#[derive(Debug, Default)]
+pub struct DoublePress {
+    was_pressed: bool,
+    /// First press of a possible double press.
+    first: Option<Instant>,
+}
+
+impl DoublePress {
+    /// Update with the button state.
+    /// # Arguments
+    /// * `pressed` - Whether the button is held down.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * `true` on the second press within `DOUBLE_PRESS_WINDOW` of the first.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> bool {
+        let press = pressed && !self.was_pressed;
+        self.was_pressed = pressed;
+        if !press {
+            return false;
+        }
+        match self.first.take() {
+            Some(first) if now.saturating_duration_since(first) <= DOUBLE_PRESS_WINDOW => true,
+            _ => {
+                self.first = Some(now);
+                false
+            }
+        }
+    }
+}
+
+/// Shortest hold of the button which counts as a long press.
+pub const LONG_PRESS: Duration = Duration::from_secs(3);
+
+/// Detects long presses of the button, polled once per measurement cycle.
+#[derive(Debug, Default)]
+pub struct LongPress {
+    /// Start of the current press.
+    since: Option<Instant>,
+    reported: bool,
+}
+
+impl LongPress {
+    /// Update with the button state.
+    /// # Arguments
+    /// * `pressed` - Whether the button is held down.
+    /// * `now` - Current time.
+    /// # Returns
+    /// * `true` once per press, when it has been held for `LONG_PRESS`.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> bool {
+        if !pressed {
+            self.since = None;
+            self.reported = false;
+            return false;
+        }
+        let since = *self.since.get_or_insert(now);
+        if self.reported || now.saturating_duration_since(since) < LONG_PRESS {
+            return false;
+        }
+        self.reported = true;
+        true
+    }
+}
+
+/// Push button waking the display, wired between a GPIO pin and GND.
+pub struct WakeButton {
+    pin: gpio::InputPin,
+}
+
+impl WakeButton {
+    /// Configure the pin as an input with pull-up.
+    /// # Arguments
+    /// * `pin` - BCM GPIO number.
+    /// # Returns
+    /// * Result<WakeButton, gpio::Error>
+    pub fn new(pin: u8) -> Result<Self, gpio::Error> {
+        let pin = gpio::Gpio::new()?.get(pin)?.into_input_pullup();
+        Ok(Self { pin })
+    }
+
+    /// Whether the button is held down.
+    pub fn is_pressed(&self) -> bool {
+        self.pin.is_low()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_after_idle_timeout() {
+        let start = Instant::now();
+        let mut screensaver = Screensaver::new(Some(Duration::from_secs(60)), start);
+
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(59), false),
+            ScreensaverTransition::Unchanged
+        );
+        assert!(!screensaver.is_blanked());
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(60), false),
+            ScreensaverTransition::Blank
+        );
+        assert!(screensaver.is_blanked());
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(61), false),
+            ScreensaverTransition::Unchanged
+        );
+    }
+
+    #[test]
+    fn test_wake_on_activity() {
+        let start = Instant::now();
+        let mut screensaver = Screensaver::new(Some(Duration::from_secs(60)), start);
+        screensaver.update(start + Duration::from_secs(60), false);
+
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(90), true),
+            ScreensaverTransition::Wake
+        );
+        assert!(!screensaver.is_blanked());
+        // The idle period restarts at the wake-up
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(149), false),
+            ScreensaverTransition::Unchanged
+        );
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(150), false),
+            ScreensaverTransition::Blank
+        );
+    }
+
+    #[test]
+    fn test_activity_postpones_blanking() {
+        let start = Instant::now();
+        let mut screensaver = Screensaver::new(Some(Duration::from_secs(60)), start);
+
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(50), true),
+            ScreensaverTransition::Unchanged
+        );
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(100), false),
+            ScreensaverTransition::Unchanged
+        );
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(110), false),
+            ScreensaverTransition::Blank
+        );
+    }
+
+    #[test]
+    fn test_double_press() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut button = DoublePress::default();
+
+        // Held down over several polls counts once
+        assert!(!button.update(true, at(0)));
+        assert!(!button.update(true, at(200)));
+        assert!(!button.update(false, at(400)));
+        assert!(button.update(true, at(600)));
+        assert!(!button.update(false, at(800)));
+        // A third press starts a new double press
+        assert!(!button.update(true, at(1000)));
+        assert!(!button.update(false, at(1200)));
+        // Too slow
+        assert!(!button.update(true, at(2000)));
+        assert!(!button.update(false, at(2200)));
+        assert!(button.update(true, at(2400)));
+    }
+
+    #[test]
+    fn test_long_press() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut button = LongPress::default();
+
+        assert!(!button.update(true, at(0)));
+        assert!(!button.update(true, at(2000)));
+        assert!(button.update(true, at(3000)));
+        // Reported once while held
+        assert!(!button.update(true, at(5000)));
+        assert!(!button.update(false, at(5200)));
+        // Released in between
+        assert!(!button.update(true, at(6000)));
+        assert!(!button.update(false, at(8000)));
+        assert!(!button.update(true, at(9000)));
+    }
+
+    #[test]
+    fn test_disabled_never_blanks() {
+        let start = Instant::now();
+        let mut screensaver = Screensaver::new(None, start);
+
+        assert_eq!(
+            screensaver.update(start + Duration::from_secs(86400), false),
+            ScreensaverTransition::Unchanged
+        );
+        assert!(!screensaver.is_blanked());
+    }
+}
@@ -0,0 +1,936 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sensor layer.
+//!
+//! Every sensor implements `EnvSensor` and carries a label, stored with its
+//! rows so readings of several sensors can be compared in one table. The
+//! first sensor is the main one: it drives the display and gets the
+//! [sensor] offsets, the others are only logged.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use peripheral::bme280::{Bme280Settings, Measurement};
+use peripheral::sensor::Sensor;
+
+use crate::config::QualityConfig;
+use crate::database::SensorData;
+use crate::error::SensorError;
+use crate::helper::ThiCoefficients;
+use crate::helper::boundary::BoundaryClock;
+use crate::helper::rolling::MeasurementWindow;
+use crate::quality::{Quality, QualityTracker, quality_score};
+
+/// Future returned by `EnvSensor::measure`.
+pub type MeasureFuture<'a> = Pin<Box<dyn Future<Output = Result<Measurement, SensorError>> + 'a>>;
+
+/// Future returned by `EnvSensor::reset`.
+pub type ResetFuture<'a> = Pin<Box<dyn Future<Output = Result<(), SensorError>> + 'a>>;
+
+/// Environment sensor.
+pub trait EnvSensor {
+    /// Label stored in the `sensor` column of the rows of this sensor.
+    fn label(&self) -> &str;
+
+    /// Take a measurement.
+    fn measure(&mut self) -> MeasureFuture<'_>;
+
+    /// Apply the [sensor] tuning. Sensors without such settings ignore it.
+    /// # Arguments
+    /// * `settings` - Oversampling and filter settings.
+    /// # Returns
+    /// * Result<(), SensorError>
+    fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        let _ = settings;
+        Ok(())
+    }
+
+    /// Reset the sensor hardware. Sensors without a reset ignore it.
+    fn reset(&mut self) -> ResetFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Whether the sensor can get stuck on one reading, see `guarded`.
+    fn can_stick(&self) -> bool {
+        true
+    }
+}
+
+/// Device of the peripheral crate with a label, chosen by the `type` of
+/// the sensor in the configuration.
+pub struct DeviceSensor {
+    label: String,
+    device: Box<dyn Sensor>,
+}
+
+impl DeviceSensor {
+    /// Create a new labeled device.
+    /// # Arguments
+    /// * `label` - Label of the rows.
+    /// * `device` - Sensor driver.
+    pub fn new(label: &str, device: Box<dyn Sensor>) -> Self {
+        Self {
+            label: label.to_string(),
+            device,
+        }
+    }
+}
+
+impl EnvSensor for DeviceSensor {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        Box::pin(async move { Ok(self.device.measure().await?) })
+    }
+
+    fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        Ok(self.device.configure(settings)?)
+    }
+
+    fn reset(&mut self) -> ResetFuture<'_> {
+        Box::pin(async move { Ok(self.device.reset().await?) })
+    }
+
+    fn can_stick(&self) -> bool {
+        self.device.can_stick()
+    }
+}
+
+/// Detects a sensor which stopped measuring: it keeps returning the same
+/// reading, or the temperature is not a number. Humidity and pressure may
+/// be NaN, a BMP280 has no humidity and an SHT31 or AHT20 has no pressure.
+#[derive(Debug, Default)]
+pub struct StuckDetector {
+    threshold: usize,
+    last: Option<Measurement>,
+    repeats: usize,
+}
+
+impl StuckDetector {
+    /// Create a new detector.
+    /// # Arguments
+    /// * `threshold` - Identical readings in a row taken as stuck.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            ..Self::default()
+        }
+    }
+
+    /// Check a reading.
+    /// # Arguments
+    /// * `measurement` - Reading of the sensor.
+    /// # Returns
+    /// * Whether the sensor is stuck. The count starts over afterwards.
+    pub fn update(&mut self, measurement: &Measurement) -> bool {
+        if measurement.temperature_c.is_nan() {
+            self.clear();
+            return true;
+        }
+        // Compared bit for bit, so a NaN repeats as well
+        let bits = |m: &Measurement| {
+            [
+                m.temperature_c.to_bits(),
+                m.pressure_pa.to_bits(),
+                m.humidity_relative.to_bits(),
+            ]
+        };
+        let repeated = self
+            .last
+            .is_some_and(|last| bits(&last) == bits(measurement));
+        self.repeats = if repeated { self.repeats + 1 } else { 1 };
+        self.last = Some(*measurement);
+        if self.repeats >= self.threshold {
+            self.clear();
+            return true;
+        }
+        false
+    }
+
+    /// Forget the readings so far.
+    pub fn clear(&mut self) {
+        self.last = None;
+        self.repeats = 0;
+    }
+}
+
+/// Sensor reset when it is stuck, and read again right away.
+pub struct StuckGuard {
+    inner: Box<dyn EnvSensor>,
+    detector: StuckDetector,
+}
+
+impl EnvSensor for StuckGuard {
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    /// Take a measurement. A stuck reading is not returned: the sensor is
+    /// reset and measured once more.
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        Box::pin(async move {
+            let measurement = self.inner.measure().await?;
+            if !self.detector.update(&measurement) {
+                return Ok(measurement);
+            }
+            eprintln!(
+                "Sensor {} looks stuck at {:?}, resetting it.",
+                self.inner.label(),
+                measurement
+            );
+            self.inner.reset().await?;
+            let measurement = self.inner.measure().await?;
+            self.detector.update(&measurement);
+            Ok(measurement)
+        })
+    }
+
+    fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        self.inner.configure(settings)
+    }
+
+    fn reset(&mut self) -> ResetFuture<'_> {
+        self.detector.clear();
+        self.inner.reset()
+    }
+}
+
+/// Wrap a sensor for the stuck detection.
+/// # Arguments
+/// * `sensor` - Sensor.
+/// * `stuck_after` - Identical readings in a row taken as stuck, 0 = off.
+/// # Returns
+/// * The sensor itself if it cannot stick, or a `StuckGuard` of it.
+pub fn guarded(sensor: Box<dyn EnvSensor>, stuck_after: usize) -> Box<dyn EnvSensor> {
+    if stuck_after == 0 || !sensor.can_stick() {
+        sensor
+    } else {
+        Box::new(StuckGuard {
+            inner: sensor,
+            detector: StuckDetector::new(stuck_after),
+        })
+    }
+}
+
+/// Smallest number of sub-samples for a trimmed mean, which drops the
+/// lowest and highest of each value.
+pub const MIN_TRIMMED_SAMPLES: usize = 3;
+
+/// Sensor averaging several rapid readings, without their outliers.
+/// Each value is averaged over the sub-samples minus its own minimum and
+/// maximum, so a single spike does not reach the stored row.
+pub struct TrimmedMean {
+    inner: Box<dyn EnvSensor>,
+    samples: usize,
+}
+
+impl TrimmedMean {
+    /// Wrap a sensor.
+    /// # Arguments
+    /// * `inner` - Sensor to sample.
+    /// * `samples` - Readings per measurement, at least `MIN_TRIMMED_SAMPLES`.
+    pub fn new(inner: Box<dyn EnvSensor>, samples: usize) -> Self {
+        Self {
+            inner,
+            samples: samples.max(MIN_TRIMMED_SAMPLES),
+        }
+    }
+}
+
+impl EnvSensor for TrimmedMean {
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    /// Take the sub-samples. Failed readings are skipped as long as
+    /// `MIN_TRIMMED_SAMPLES` of them succeed.
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        Box::pin(async move {
+            let mut readings = Vec::with_capacity(self.samples);
+            let mut last_error = None;
+            for _ in 0..self.samples {
+                match self.inner.measure().await {
+                    Ok(measurement) => readings.push(measurement),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            match (trimmed_mean(&readings), last_error) {
+                (Some(measurement), _) => Ok(measurement),
+                (None, Some(e)) => Err(e),
+                (None, None) => Err(SensorError::TooFewReadings),
+            }
+        })
+    }
+
+    fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        self.inner.configure(settings)
+    }
+
+    fn reset(&mut self) -> ResetFuture<'_> {
+        self.inner.reset()
+    }
+}
+
+/// Wrap a sensor for the configured number of sub-samples.
+/// # Arguments
+/// * `sensor` - Sensor.
+/// * `samples` - Readings per measurement, 1 for a single reading.
+/// # Returns
+/// * The sensor itself, or a `TrimmedMean` of it.
+pub fn sampled(sensor: Box<dyn EnvSensor>, samples: usize) -> Box<dyn EnvSensor> {
+    if samples >= MIN_TRIMMED_SAMPLES {
+        Box::new(TrimmedMean::new(sensor, samples))
+    } else {
+        sensor
+    }
+}
+
+/// Sensor corrected for its self-heating, as set in its profile.
+pub struct SelfHeating {
+    inner: Box<dyn EnvSensor>,
+    self_heating_c: f64,
+}
+
+impl EnvSensor for SelfHeating {
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        Box::pin(async move {
+            let measurement = self.inner.measure().await?;
+            Ok(Measurement {
+                temperature_c: measurement.temperature_c - self.self_heating_c,
+                ..measurement
+            })
+        })
+    }
+
+    fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        self.inner.configure(settings)
+    }
+
+    fn reset(&mut self) -> ResetFuture<'_> {
+        self.inner.reset()
+    }
+}
+
+/// Wrap a sensor for its self-heating compensation.
+/// # Arguments
+/// * `sensor` - Sensor.
+/// * `self_heating_c` - Degrees subtracted from the temperature.
+/// # Returns
+/// * The sensor itself without compensation, or a `SelfHeating` of it.
+pub fn compensated(sensor: Box<dyn EnvSensor>, self_heating_c: f64) -> Box<dyn EnvSensor> {
+    if self_heating_c == 0.0 {
+        sensor
+    } else {
+        Box::new(SelfHeating {
+            inner: sensor,
+            self_heating_c,
+        })
+    }
+}
+
+/// Average each value of the readings without its minimum and maximum.
+/// # Arguments
+/// * `readings` - Sub-samples.
+/// # Returns
+/// * `None` if there are fewer than `MIN_TRIMMED_SAMPLES` readings.
+pub fn trimmed_mean(readings: &[Measurement]) -> Option<Measurement> {
+    if readings.len() < MIN_TRIMMED_SAMPLES {
+        return None;
+    }
+    let trimmed = |value: fn(&Measurement) -> f64| {
+        let mut values: Vec<f64> = readings.iter().map(value).collect();
+        values.sort_by(f64::total_cmp);
+        let kept = &values[1..values.len() - 1];
+        kept.iter().sum::<f64>() / kept.len() as f64
+    };
+    Some(Measurement {
+        temperature_c: trimmed(|m| m.temperature_c),
+        pressure_pa: trimmed(|m| m.pressure_pa),
+        humidity_relative: trimmed(|m| m.humidity_relative),
+    })
+}
+
+/// Successful reading of one sensor.
+#[derive(Debug, Clone)]
+pub struct Reading {
+    pub label: String,
+    pub measurement: Measurement,
+    /// Quality derived from the sensor's own failure history.
+    pub quality: Quality,
+    /// Quality score, when it is stored.
+    pub quality_score: Option<u8>,
+}
+
+impl Reading {
+    /// Build the row stored for this reading.
+    /// # Arguments
+    /// * `measured_at` - Time the reading was taken.
+    /// * `comfort` - Coefficients of the stored THI.
+    /// # Returns
+    /// * SensorData
+    pub fn to_sensor_data(
+        &self,
+        measured_at: DateTime<Local>,
+        comfort: &ThiCoefficients,
+    ) -> SensorData {
+        let thi = crate::calc_thi(
+            self.measurement.temperature_c,
+            self.measurement.humidity_relative,
+            comfort,
+        );
+        let mut data = SensorData::from_measurement_at(self.measurement, thi, measured_at);
+        data.sensor = self.label.clone();
+        data.quality = self.quality;
+        data.quality_score = self.quality_score;
+        data
+    }
+}
+
+/// Sensors measured together, each with its own failure history.
+pub struct SensorSet {
+    sensors: Vec<Box<dyn EnvSensor>>,
+    quality: Vec<QualityTracker>,
+    store_score: bool,
+}
+
+impl SensorSet {
+    /// Create a new set.
+    /// # Arguments
+    /// * `sensors` - Sensors, the main one first.
+    /// * `quality_config` - Quality configuration.
+    pub fn new(sensors: Vec<Box<dyn EnvSensor>>, quality_config: &QualityConfig) -> Self {
+        let quality = sensors
+            .iter()
+            .map(|_| QualityTracker::new(quality_config))
+            .collect();
+        Self {
+            sensors,
+            quality,
+            store_score: quality_config.store_score,
+        }
+    }
+
+    /// Whether the set has no sensor, when the main one failed at start-up.
+    pub fn is_empty(&self) -> bool {
+        self.sensors.is_empty()
+    }
+
+    /// Apply the [sensor] tuning to every sensor.
+    /// # Arguments
+    /// * `settings` - Oversampling and filter settings.
+    /// # Returns
+    /// * `Err(e)` naming the first sensor which rejected the settings.
+    pub fn configure(&mut self, settings: Bme280Settings) -> Result<(), SensorError> {
+        for sensor in &mut self.sensors {
+            sensor
+                .configure(settings)
+                .map_err(|e| SensorError::Configure {
+                    label: sensor.label().to_string(),
+                    source: Box::new(e),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Measure every sensor once.
+    /// Failures are logged and counted against the sensor's quality.
+    /// # Returns
+    /// * One entry per sensor, in order, `None` where the measurement failed.
+    pub async fn measure_all(&mut self) -> Vec<Option<Reading>> {
+        let mut readings = Vec::with_capacity(self.sensors.len());
+        for (sensor, quality) in self.sensors.iter_mut().zip(&mut self.quality) {
+            match sensor.measure().await {
+                Ok(measurement) => {
+                    quality.record(true);
+                    quality.record_reading(measurement);
+                    readings.push(Some(Reading {
+                        label: sensor.label().to_string(),
+                        measurement,
+                        // Judged on the raw reading, against the sensor's own range
+                        quality: quality.assess(&measurement),
+                        quality_score: self
+                            .store_score
+                            .then(|| quality_score(&measurement, quality)),
+                    }));
+                }
+                Err(e) => {
+                    quality.record(false);
+                    eprintln!("Failed to read the sensor {}: {}", sensor.label(), e);
+                    readings.push(None);
+                }
+            }
+        }
+        readings
+    }
+}
+
+/// Readings of every sensor averaged into one row per save window.
+pub struct SaveWindows {
+    length: chrono::Duration,
+    windows: HashMap<String, MeasurementWindow>,
+    /// Wall-clock boundaries closing the windows of each sensor, if aligned.
+    boundaries: Option<HashMap<String, BoundaryClock<Local>>>,
+}
+
+impl SaveWindows {
+    /// Create the windows.
+    /// # Arguments
+    /// * `length` - Time covered by one stored row.
+    pub fn new(length: Duration) -> Self {
+        Self {
+            length: chrono::Duration::from_std(length).unwrap_or(chrono::Duration::MAX),
+            windows: HashMap::new(),
+            boundaries: None,
+        }
+    }
+
+    /// Create windows closing on the wall-clock multiples of their length
+    /// instead of a length after they started. The first window only covers
+    /// the time up to the first boundary.
+    /// # Arguments
+    /// * `length` - Time covered by one stored row, whole seconds.
+    pub fn aligned(length: Duration) -> Self {
+        Self {
+            boundaries: Some(HashMap::new()),
+            ..Self::new(length)
+        }
+    }
+
+    /// Add a reading to the window of its sensor.
+    /// A closing window starts the next one from the same reading, so no
+    /// time between two samples is left out.
+    /// # Arguments
+    /// * `reading` - Reading of a sensor.
+    /// * `measured_at` - Time the reading was taken.
+    /// # Returns
+    /// * The time-weighted mean of the window once it covers the length,
+    ///   with the quality of the latest reading, and the time of the row:
+    ///   the boundary which closed an aligned window, else `measured_at`.
+    pub fn push(
+        &mut self,
+        reading: &Reading,
+        measured_at: DateTime<Local>,
+    ) -> Option<(Reading, DateTime<Local>)> {
+        let window = self.windows.entry(reading.label.clone()).or_default();
+        window.push(measured_at, &reading.measurement);
+        let row_at = match self.boundaries.as_mut() {
+            Some(boundaries) => boundaries
+                .entry(reading.label.clone())
+                .or_insert_with(|| BoundaryClock::new(self.length))
+                .pass(&measured_at)?,
+            None if window.span() < self.length => return None,
+            None => measured_at,
+        };
+        let mean = window.mean()?;
+        *window = MeasurementWindow::default();
+        window.push(measured_at, &reading.measurement);
+        let row = Reading {
+            measurement: mean,
+            ..reading.clone()
+        };
+        Some((row, row_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use peripheral::bme280::Bme280;
+    use peripheral::bus::MockI2cBus;
+    use peripheral::sensor::ConstantSensor;
+    use rppal::i2c;
+    use std::io;
+
+    /// Sensor returning a fixed temperature, or failing.
+    struct MockSensor {
+        label: &'static str,
+        temperature_c: Option<f64>,
+    }
+
+    impl EnvSensor for MockSensor {
+        fn label(&self) -> &str {
+            self.label
+        }
+
+        fn measure(&mut self) -> MeasureFuture<'_> {
+            let result = self
+                .temperature_c
+                .map(|temperature_c| Measurement {
+                    temperature_c,
+                    pressure_pa: 100500.0,
+                    humidity_relative: 50.0,
+                })
+                .ok_or_else(no_response);
+            Box::pin(async move { result })
+        }
+    }
+
+    fn no_response() -> SensorError {
+        SensorError::Bus(i2c::Error::Io(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no response",
+        )))
+    }
+
+    fn mock(label: &'static str, temperature_c: Option<f64>) -> Box<dyn EnvSensor> {
+        Box::new(MockSensor {
+            label,
+            temperature_c,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_two_sensors_give_labeled_rows() {
+        let mut sensors = SensorSet::new(
+            vec![mock("bme280", Some(23.5)), mock("reference", Some(23.1))],
+            &QualityConfig::default(),
+        );
+        let measured_at = Local::now();
+
+        let rows: Vec<SensorData> = sensors
+            .measure_all()
+            .await
+            .iter()
+            .flatten()
+            .map(|reading| reading.to_sensor_data(measured_at, &ThiCoefficients::STANDARD))
+            .collect();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].sensor, "bme280");
+        assert_eq!(rows[0].temperature_c, 23.5);
+        assert_eq!(rows[1].sensor, "reference");
+        assert_eq!(rows[1].temperature_c, 23.1);
+        assert_eq!(
+            rows[1].thi,
+            crate::calc_thi(23.1, 50.0, &ThiCoefficients::STANDARD)
+        );
+        assert!(rows.iter().all(|row| row.timestamp == measured_at));
+    }
+
+    #[tokio::test]
+    async fn test_failing_sensor_does_not_hide_others() {
+        let mut sensors = SensorSet::new(
+            vec![mock("bme280", Some(23.5)), mock("reference", None)],
+            &QualityConfig::default(),
+        );
+
+        let readings = sensors.measure_all().await;
+
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].as_ref().unwrap().label, "bme280");
+        assert!(readings[1].is_none());
+    }
+
+    #[tokio::test]
+    async fn test_self_heating_compensation() {
+        let mut sensor = compensated(mock("garden", Some(23.5)), 1.5);
+        let measurement = sensor.measure().await.unwrap();
+        assert_eq!(sensor.label(), "garden");
+        assert_eq!(measurement.temperature_c, 22.0);
+        assert_eq!(measurement.humidity_relative, 50.0);
+
+        let mut sensor = compensated(mock("living", Some(23.5)), 0.0);
+        assert_eq!(sensor.measure().await.unwrap().temperature_c, 23.5);
+    }
+
+    /// Sensor returning queued readings, `None` for a failure.
+    struct SequenceSensor {
+        readings: Vec<Option<f64>>,
+    }
+
+    impl EnvSensor for SequenceSensor {
+        fn label(&self) -> &str {
+            "sequence"
+        }
+
+        fn measure(&mut self) -> MeasureFuture<'_> {
+            let result = self
+                .readings
+                .remove(0)
+                .map(|temperature_c| Measurement {
+                    temperature_c,
+                    pressure_pa: 100500.0,
+                    humidity_relative: 50.0,
+                })
+                .ok_or_else(no_response);
+            Box::pin(async move { result })
+        }
+    }
+
+    fn measurement(temperature_c: f64, humidity_relative: f64) -> Measurement {
+        Measurement {
+            temperature_c,
+            pressure_pa: 100500.0,
+            humidity_relative,
+        }
+    }
+
+    #[test]
+    fn test_stuck_detector() {
+        let mut detector = StuckDetector::new(3);
+        assert!(!detector.update(&measurement(21.0, 50.0)));
+        assert!(!detector.update(&measurement(21.0, 50.0)));
+        assert!(!detector.update(&measurement(21.1, 50.0)));
+        assert!(!detector.update(&measurement(21.1, 50.0)));
+        assert!(detector.update(&measurement(21.1, 50.0)));
+        // Counted again from the next reading
+        assert!(!detector.update(&measurement(21.1, 50.0)));
+
+        let mut detector = StuckDetector::new(3);
+        assert!(detector.update(&measurement(f64::NAN, 50.0)));
+        // No humidity on a BMP280
+        for temperature_c in [21.0, 21.1, 21.2, 21.3] {
+            assert!(!detector.update(&measurement(temperature_c, f64::NAN)));
+        }
+        assert!(!detector.update(&measurement(21.3, f64::NAN)));
+        assert!(detector.update(&measurement(21.3, f64::NAN)));
+
+        // No pressure on an SHT31
+        let mut detector = StuckDetector::new(3);
+        for temperature_c in [21.0, 21.1, 21.2] {
+            let sht31 = Measurement {
+                pressure_pa: f64::NAN,
+                ..measurement(temperature_c, 50.0)
+            };
+            assert!(!detector.update(&sht31));
+        }
+    }
+
+    /// Sensor returning queued readings and counting its resets.
+    struct StuckSensor {
+        readings: Vec<Measurement>,
+        resets: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl EnvSensor for StuckSensor {
+        fn label(&self) -> &str {
+            "stuck"
+        }
+
+        fn measure(&mut self) -> MeasureFuture<'_> {
+            let result = Ok(self.readings.remove(0));
+            Box::pin(async move { result })
+        }
+
+        fn reset(&mut self) -> ResetFuture<'_> {
+            self.resets.set(self.resets.get() + 1);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stuck_sensor_is_reset_and_read_again() {
+        let resets = std::rc::Rc::new(std::cell::Cell::new(0));
+        let stuck = measurement(21.0, 50.0);
+        let mut sensor = guarded(
+            Box::new(StuckSensor {
+                readings: vec![stuck, stuck, stuck, measurement(21.2, 48.0)],
+                resets: resets.clone(),
+            }),
+            3,
+        );
+        for _ in 0..2 {
+            assert_eq!(sensor.measure().await.unwrap().temperature_c, 21.0);
+        }
+        assert_eq!(resets.get(), 0);
+        // The third identical reading is replaced by one after the reset
+        assert_eq!(sensor.measure().await.unwrap().temperature_c, 21.2);
+        assert_eq!(resets.get(), 1);
+
+        let sensor = guarded(mock("living", Some(21.0)), 0);
+        assert_eq!(sensor.label(), "living");
+    }
+
+    /// Constant sensor counting its resets.
+    struct CountedConstant {
+        inner: ConstantSensor,
+        resets: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Sensor for CountedConstant {
+        fn measure(&self) -> peripheral::sensor::MeasureFuture<'_> {
+            self.inner.measure()
+        }
+
+        fn reset(&self) -> peripheral::sensor::ResetFuture<'_> {
+            self.resets.set(self.resets.get() + 1);
+            self.inner.reset()
+        }
+
+        fn can_stick(&self) -> bool {
+            self.inner.can_stick()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_constant_sensor_is_never_reset() {
+        let resets = std::rc::Rc::new(std::cell::Cell::new(0));
+        let device = CountedConstant {
+            inner: ConstantSensor::default(),
+            resets: resets.clone(),
+        };
+        let mut sensor = guarded(Box::new(DeviceSensor::new("constant", Box::new(device))), 3);
+        for _ in 0..10 {
+            assert_eq!(sensor.measure().await.unwrap().temperature_c, 20.0);
+        }
+        assert_eq!(resets.get(), 0);
+    }
+
+    #[test]
+    fn test_save_windows_average_each_sensor() {
+        let mut windows = SaveWindows::new(Duration::from_secs(60));
+        let start = Local::now();
+        let reading = |label: &str, temperature_c| Reading {
+            label: label.to_string(),
+            measurement: measurement(temperature_c, 50.0),
+            quality: Quality::Good,
+            quality_score: None,
+        };
+        let push = |windows: &mut SaveWindows, label, temperature_c, seconds| {
+            let at = start + chrono::Duration::seconds(seconds);
+            windows.push(&reading(label, temperature_c), at)
+        };
+        assert!(push(&mut windows, "main", 20.0, 0).is_none());
+        assert!(push(&mut windows, "outdoor", 10.0, 0).is_none());
+        // A burst at the end of the window weighs by its time only
+        assert!(push(&mut windows, "main", 20.0, 50).is_none());
+        for seconds in 51..60 {
+            assert!(push(&mut windows, "main", 30.0, seconds).is_none());
+        }
+        let (row, row_at) = push(&mut windows, "main", 30.0, 60).unwrap();
+        assert_eq!(row.label, "main");
+        assert_eq!(row_at, start + chrono::Duration::seconds(60));
+        assert!((row.measurement.temperature_c - (1000.0 + 25.0 + 270.0) / 60.0).abs() < 1e-9);
+        assert_eq!(row.measurement.humidity_relative, 50.0);
+        let (row, _) = push(&mut windows, "outdoor", 12.0, 60).unwrap();
+        assert_eq!(row.measurement.temperature_c, 11.0);
+
+        // The next window starts from the closing reading
+        assert!(push(&mut windows, "main", 20.0, 90).is_none());
+        let (row, _) = push(&mut windows, "main", 20.0, 120).unwrap();
+        assert!((row.measurement.temperature_c - (750.0 + 600.0) / 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aligned_save_windows_close_on_boundaries() {
+        let mut windows = SaveWindows::aligned(Duration::from_secs(30));
+        let start = Local.with_ymd_and_hms(2025, 6, 16, 14, 0, 7).unwrap();
+        let reading = |temperature_c| Reading {
+            label: "main".to_string(),
+            measurement: measurement(temperature_c, 50.0),
+            quality: Quality::Good,
+            quality_score: None,
+        };
+        let mut rows = Vec::new();
+        for seconds in (0..=120).step_by(10) {
+            let at = start + chrono::Duration::seconds(seconds);
+            rows.extend(windows.push(&reading(20.0), at));
+        }
+        let times: Vec<_> = rows
+            .iter()
+            .map(|(_, at)| at.format("%M:%S").to_string())
+            .collect();
+        // The first window only runs from 00:07 to the boundary
+        assert_eq!(times, ["00:30", "01:00", "01:30", "02:00"]);
+        assert!(
+            rows.iter()
+                .all(|(row, _)| row.measurement.temperature_c == 20.0)
+        );
+    }
+
+    #[test]
+    fn test_trimmed_mean_excludes_outlier() {
+        let readings = [
+            measurement(22.0, 50.0),
+            measurement(22.2, 51.0),
+            measurement(85.0, 49.5),
+            measurement(22.1, 50.5),
+            measurement(22.3, 0.0),
+        ];
+        let mean = trimmed_mean(&readings).unwrap();
+        assert!((mean.temperature_c - 22.2).abs() < 1e-9);
+        // Trimmed on its own, the humidity spike is dropped too
+        assert!((mean.humidity_relative - 50.0).abs() < 1e-9);
+        assert_eq!(mean.pressure_pa, 100500.0);
+    }
+
+    #[test]
+    fn test_trimmed_mean_needs_three_readings() {
+        assert!(trimmed_mean(&[]).is_none());
+        assert!(trimmed_mean(&[measurement(22.0, 50.0), measurement(22.2, 50.0)]).is_none());
+        let mean = trimmed_mean(&[
+            measurement(22.0, 50.0),
+            measurement(-40.0, 50.0),
+            measurement(22.4, 50.0),
+        ])
+        .unwrap();
+        assert_eq!(mean.temperature_c, 22.0);
+    }
+
+    #[tokio::test]
+    async fn test_trimmed_mean_sensor() {
+        let inner = SequenceSensor {
+            readings: vec![Some(23.0), Some(23.1), None, Some(150.0), Some(23.1)],
+        };
+        let mut sensor = sampled(Box::new(inner), 5);
+        assert_eq!(sensor.label(), "sequence");
+        let measurement = sensor.measure().await.unwrap();
+        assert!((measurement.temperature_c - 23.1).abs() < 1e-9);
+
+        // Too many failures leave nothing to trim
+        let inner = SequenceSensor {
+            readings: vec![Some(23.0), None, None],
+        };
+        let mut sensor = sampled(Box::new(inner), 3);
+        assert!(sensor.measure().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bme280_sensor() {
+        let bus = MockI2cBus::new();
+        // Same calibration and raw data as the driver's own mock test
+        bus.seed(0xD0, &[peripheral::bme280::BME280_CHIP_ID]);
+        bus.seed(0x88, &[0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC]);
+        bus.seed(0xF7, &[0x50, 0x00, 0x00, 0x7E, 0xED, 0x00, 0x80, 0x00]);
+        let mut sensor = DeviceSensor::new("bme280", Box::new(Bme280::with_bus(bus).unwrap()));
+
+        assert_eq!(sensor.label(), "bme280");
+        let measurement = sensor.measure().await.unwrap();
+        assert!((measurement.temperature_c - 25.08).abs() < 0.01);
+
+        let mut sensor = DeviceSensor::new("constant", Box::new(ConstantSensor::default()));
+        sensor.configure(Bme280Settings::default()).unwrap();
+        let measurement = sensor.measure().await.unwrap();
+        assert_eq!(measurement.temperature_c, 20.0);
+        assert_eq!(measurement.humidity_relative, 50.0);
+    }
+}
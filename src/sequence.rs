@@ -0,0 +1,118 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sequence numbers of the stored rows, for consumers to drop duplicates.
+//!
+//! Every row queued for the database is numbered in the order it was
+//! queued, from 1 in each run of the program. The run is named by a run id
+//! made of its start time and process id, so a consumer seeing a new run
+//! id knows the numbers started over. Rows which were not stored, e.g.
+//! quarantined, dropped or failed, leave gaps.
+//!
+//! Delivery of the rows to each sink:
+//!
+//! * Database: exactly once per (run_id, seq). A unique index skips a row
+//!   delivered again, e.g. one re-sent after its commit was not
+//!   acknowledged. Rows of older versions have neither and are never
+//!   skipped.
+//! * `after_insert` hook: at most once. It runs after the commit, not for a
+//!   skipped duplicate, and is skipped itself while its previous run is
+//!   still going. `{run_id}` and `{seq}` are in `{json}` and may be used as
+//!   placeholders.
+//! * CSV export: at least once, as exports of overlapping ranges repeat
+//!   rows. The `run_id` and `seq` columns tell the copies apart.
+//! * `GET /api/current`: the latest reading, which is not numbered. A
+//!   poller sees it again until the next one.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use chrono::{DateTime, Local};
+
+/// Numbering of this run, see `this_run`.
+static THIS_RUN: OnceLock<RowSequence> = OnceLock::new();
+
+/// Numbering of the rows of this run of the program, started on first use.
+pub fn this_run() -> &'static RowSequence {
+    THIS_RUN.get_or_init(|| RowSequence::start(Local::now(), std::process::id()))
+}
+
+/// Position of a row among the rows of its run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    pub run_id: Arc<str>,
+    /// From 1.
+    pub seq: u64,
+}
+
+/// Numbers the rows of one run.
+#[derive(Debug)]
+pub struct RowSequence {
+    run_id: Arc<str>,
+    last: AtomicU64,
+}
+
+impl RowSequence {
+    /// Start a run.
+    /// # Arguments
+    /// * `now` - Start time of the run.
+    /// * `pid` - Process id of the run.
+    pub fn start(now: DateTime<Local>, pid: u32) -> Self {
+        Self {
+            run_id: format!("{}-{}", now.format("%Y%m%dT%H%M%S"), pid).into(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Id of the run, e.g. "20250616T143045-1234".
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Number the next row.
+    pub fn next(&self) -> Sequence {
+        Sequence {
+            run_id: self.run_id.clone(),
+            seq: self.last.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_rows_are_numbered_per_run() {
+        let start = Local.with_ymd_and_hms(2025, 6, 16, 14, 30, 45).unwrap();
+        let run = RowSequence::start(start, 1234);
+        assert_eq!(run.run_id(), "20250616T143045-1234");
+        assert_eq!(run.next().seq, 1);
+        assert_eq!(run.next().seq, 2);
+
+        // A restart begins at 1 under a new run id
+        let restarted = RowSequence::start(start + chrono::Duration::seconds(5), 1240);
+        let first = restarted.next();
+        assert_eq!(first.seq, 1);
+        assert_ne!(&*first.run_id, run.run_id());
+    }
+}
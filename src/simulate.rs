@@ -0,0 +1,529 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Simulated hardware for running without a Raspberry Pi.
+//!
+//! `wbroker-rs --simulate` measures with `SimulatedSensor` and draws on a
+//! `NullDisplay`, which prints the lines to stdout, so the rest of the
+//! pipeline runs unchanged on a machine without an I2C bus. `Harness` hands
+//! them to the daemon, and lets the soak test and other tests measure with
+//! a sensor of their own, on a virtual `WallClock`.
+//!
+//! `ChaosSensor` adds faults to the simulated sensor, periodic ones from the
+//! undocumented [chaos] section for `soak --simulate` and scripted ones for
+//! tests. The tests below drive each recovery path with it and with the
+//! fault injection of the peripheral fakes.
+
+use std::f64::consts::PI;
+use std::io;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, TimeZone, Timelike};
+use peripheral::bme280::Measurement;
+use peripheral::chaos::FaultInjector;
+use peripheral::display::{CharDisplay, DisplayGeometry, MockDisplay};
+use peripheral::sensor::{self as device, Sensor};
+use rppal::i2c;
+
+use crate::config::ChaosConfig;
+use crate::database::Database;
+use crate::error::SensorError;
+use crate::sensor::{EnvSensor, MeasureFuture};
+
+/// Temperature read by an out-of-range measurement of `ChaosSensor`, above
+/// the 85 °C limit of the BME280.
+pub const OUT_OF_RANGE_C: f64 = 150.0;
+
+/// Simulated sensor producing plausible diurnal curves.
+/// The output only depends on the given time, so runs are reproducible.
+#[derive(Debug, Default)]
+pub struct SimulatedSensor;
+
+impl SimulatedSensor {
+    /// Create a new simulated sensor.
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Make a measurement for the given time.
+    /// Temperature peaks at 15:00, humidity moves the opposite way.
+    /// # Arguments
+    /// * `now` - Time of the measurement.
+    /// # Returns
+    /// * Measurement
+    pub fn measurement_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Measurement {
+        let seconds = now.num_seconds_from_midnight() as f64;
+        let phase = 2.0 * PI * (seconds / 86400.0 - 9.0 / 24.0);
+        let slow = 2.0 * PI * (now.timestamp() as f64 / (3.0 * 86400.0));
+        Measurement {
+            temperature_c: 22.0 + 4.0 * phase.sin(),
+            pressure_pa: 101325.0 + 300.0 * slow.sin(),
+            humidity_relative: 55.0 - 10.0 * phase.sin(),
+        }
+    }
+}
+
+impl Sensor for SimulatedSensor {
+    fn measure(&self) -> device::MeasureFuture<'_> {
+        let measurement = self.measurement_at(&Local::now());
+        Box::pin(async move { Ok(measurement) })
+    }
+}
+
+/// Display printing its lines to stdout instead of writing to the bus.
+/// The DDRAM of the configured geometry is emulated, and a line is printed
+/// whenever its content changes.
+pub struct NullDisplay {
+    screen: Arc<MockDisplay>,
+    shown: Mutex<Vec<String>>,
+    printed: bool,
+}
+
+impl NullDisplay {
+    /// Create a new blank display.
+    /// # Arguments
+    /// * `geometry` - Size of the emulated display.
+    pub fn new(geometry: DisplayGeometry) -> Self {
+        let screen = Arc::new(MockDisplay::with_geometry(geometry));
+        let shown = Mutex::new(screen.grid());
+        Self {
+            screen,
+            shown,
+            printed: true,
+        }
+    }
+
+    /// Create a new blank display which prints nothing.
+    /// # Arguments
+    /// * `geometry` - Size of the emulated display.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn quiet(geometry: DisplayGeometry) -> Self {
+        Self {
+            printed: false,
+            ..Self::new(geometry)
+        }
+    }
+
+    /// Displayed characters, one string per line.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn grid(&self) -> Vec<String> {
+        self.screen.grid()
+    }
+
+    /// Emulated display, to read it after the display was handed over.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn screen(&self) -> Arc<MockDisplay> {
+        Arc::clone(&self.screen)
+    }
+
+    /// Print the lines which changed since the last call.
+    fn print_changes(&self) {
+        if !self.printed {
+            return;
+        }
+        let grid = self.screen.grid();
+        let mut shown = self.shown.lock().unwrap_or_else(|e| e.into_inner());
+        for (row, line) in grid.iter().enumerate() {
+            if shown.get(row) != Some(line) {
+                println!("display {}: |{}|", row + 1, line);
+            }
+        }
+        *shown = grid;
+    }
+}
+
+impl CharDisplay for NullDisplay {
+    async fn setup(&self) -> Result<(), i2c::Error> {
+        self.screen.setup().await
+    }
+
+    fn geometry(&self) -> DisplayGeometry {
+        self.screen.geometry()
+    }
+
+    fn register_char(&self, index: u8, data: [u8; 8]) -> Result<(), i2c::Error> {
+        self.screen.register_char(index, data)
+    }
+
+    fn put_u8(&self, position: u8, data: u8) -> Result<(), i2c::Error> {
+        self.screen.put_u8(position, data)?;
+        self.print_changes();
+        Ok(())
+    }
+
+    fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+        self.screen.put_str(line_addr, s)?;
+        self.print_changes();
+        Ok(())
+    }
+
+    fn clear_home(&self) -> Result<(), i2c::Error> {
+        self.screen.clear_home()?;
+        self.print_changes();
+        Ok(())
+    }
+
+    fn display_off(&self) -> Result<(), i2c::Error> {
+        if self.printed {
+            println!("display off");
+        }
+        self.screen.display_off()
+    }
+
+    fn display_on(&self) -> Result<(), i2c::Error> {
+        if self.printed {
+            println!("display on");
+        }
+        self.screen.display_on()
+    }
+}
+
+/// Time of the measurements, the system time or a virtual one.
+#[derive(Debug, Clone, Default)]
+pub enum WallClock {
+    /// Local system time.
+    #[default]
+    System,
+    /// Virtual time, only moved by `advance`.
+    Virtual(Arc<Mutex<DateTime<Local>>>),
+}
+
+impl WallClock {
+    /// Create a virtual clock.
+    /// # Arguments
+    /// * `start` - Initial time.
+    pub fn starting_at(start: DateTime<Local>) -> Self {
+        WallClock::Virtual(Arc::new(Mutex::new(start)))
+    }
+
+    /// Current time.
+    pub fn now(&self) -> DateTime<Local> {
+        match self {
+            WallClock::System => Local::now(),
+            WallClock::Virtual(now) => *now.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// Move a virtual clock forward. The system time is left alone.
+    /// # Arguments
+    /// * `step` - Time to add.
+    pub fn advance(&self, step: ChronoDuration) {
+        if let WallClock::Virtual(now) = self {
+            *now.lock().unwrap_or_else(|e| e.into_inner()) += step;
+        }
+    }
+}
+
+/// Called by the main loop at the start of each tick with the tick number
+/// and the database, the loop stops on `ControlFlow::Break`.
+pub type TickHook = Box<dyn FnMut(u64, Option<&Database>) -> ControlFlow<()>>;
+
+/// Simulated hardware the daemon runs on instead of the I2C bus.
+pub struct Harness {
+    /// Main sensor, `None` for a `SimulatedSensor` of the configured type.
+    pub sensor: Option<Box<dyn EnvSensor>>,
+    /// Display drawn on.
+    pub display: NullDisplay,
+    /// Time of the measurements and the rows.
+    pub clock: WallClock,
+    /// Called at the start of each tick, `None` to run until stopped.
+    pub on_tick: Option<TickHook>,
+}
+
+impl Harness {
+    /// Simulate the configured sensors on the system time, and print the
+    /// display to stdout.
+    /// # Arguments
+    /// * `geometry` - Size of the display.
+    pub fn new(geometry: DisplayGeometry) -> Self {
+        Self {
+            sensor: None,
+            display: NullDisplay::new(geometry),
+            clock: WallClock::System,
+            on_tick: None,
+        }
+    }
+}
+
+/// Simulated sensor with injected faults.
+pub struct ChaosSensor {
+    sensor: SimulatedSensor,
+    config: ChaosConfig,
+    faults: Arc<FaultInjector>,
+    out_of_range_next: u32,
+    measurements: u64,
+    clock: WallClock,
+}
+
+impl ChaosSensor {
+    /// Create a sensor with the periodic faults of [chaos].
+    /// # Arguments
+    /// * `config` - Periodic faults, all off by default.
+    pub fn new(config: &ChaosConfig) -> Self {
+        let faults = Arc::new(FaultInjector::new());
+        faults.set_latency(Duration::from_millis(config.latency_ms));
+        Self {
+            sensor: SimulatedSensor::new(),
+            config: config.clone(),
+            faults,
+            out_of_range_next: 0,
+            measurements: 0,
+            clock: WallClock::System,
+        }
+    }
+
+    /// Measure at the time of the given clock instead of the system time.
+    /// # Arguments
+    /// * `clock` - Clock of the measurements.
+    pub fn with_clock(mut self, clock: WallClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Scripted faults of the measurements, shared with the caller.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn faults(&self) -> Arc<FaultInjector> {
+        Arc::clone(&self.faults)
+    }
+
+    /// Read out of range in the next measurements.
+    /// # Arguments
+    /// * `count` - Number of measurements
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn out_of_range_next(&mut self, count: u32) {
+        self.out_of_range_next = count;
+    }
+
+    /// Make a measurement for the given time, unless a fault is due.
+    /// # Arguments
+    /// * `now` - Time of the measurement.
+    /// # Returns
+    /// * The measurement, `OUT_OF_RANGE_C` warm when out of range.
+    pub fn measurement_at<Tz: TimeZone>(
+        &mut self,
+        now: &DateTime<Tz>,
+    ) -> Result<Measurement, SensorError> {
+        self.measurements += 1;
+        let due = |every: u64| every > 0 && self.measurements.is_multiple_of(every);
+        self.faults.check()?;
+        if due(self.config.fail_every) {
+            return Err(SensorError::Bus(i2c::Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "injected fault",
+            ))));
+        }
+        let mut measurement = self.sensor.measurement_at(now);
+        if self.out_of_range_next > 0 || due(self.config.out_of_range_every) {
+            self.out_of_range_next = self.out_of_range_next.saturating_sub(1);
+            measurement.temperature_c = OUT_OF_RANGE_C;
+        }
+        Ok(measurement)
+    }
+}
+
+impl EnvSensor for ChaosSensor {
+    fn label(&self) -> &str {
+        "simulated"
+    }
+
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        let now = self.clock.now();
+        let result = self.measurement_at(&now);
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgram::CgramSlots;
+    use crate::config::{QualityConfig, ValidationConfig};
+    use crate::display::{DisplayRecovery, WriteOutcome};
+    use crate::quality::{Plausibility, Quality};
+    use crate::sensor::{DeviceSensor, SensorSet};
+    use chrono::Utc;
+    use peripheral::bme280::{BME280_CHIP_ID, Bme280};
+    use peripheral::bus::MockI2cBus;
+    use peripheral::display::{CharDisplay, MockDisplay};
+
+    fn quality() -> QualityConfig {
+        QualityConfig {
+            window: 5,
+            suspect_failures: 2,
+            boundary_margin: 0.01,
+            store_score: false,
+        }
+    }
+
+    #[test]
+    fn test_simulated_measurement_ranges() {
+        let sensor = SimulatedSensor::new();
+        let start = Utc.with_ymd_and_hms(2025, 6, 16, 0, 0, 0).unwrap();
+        for hour in 0..48 {
+            let m = sensor.measurement_at(&(start + chrono::Duration::hours(hour)));
+            assert!(m.temperature_c >= 18.0 && m.temperature_c <= 26.0);
+            assert!(m.humidity_relative >= 45.0 && m.humidity_relative <= 65.0);
+            assert!(m.pressure_pa >= 101025.0 && m.pressure_pa <= 101625.0);
+        }
+    }
+
+    #[test]
+    fn test_simulated_measurement_diurnal_peak() {
+        let sensor = SimulatedSensor::new();
+        let afternoon = Utc.with_ymd_and_hms(2025, 6, 16, 15, 0, 0).unwrap();
+        let night = Utc.with_ymd_and_hms(2025, 6, 16, 3, 0, 0).unwrap();
+        let warm = sensor.measurement_at(&afternoon);
+        let cold = sensor.measurement_at(&night);
+        assert!(warm.temperature_c > cold.temperature_c);
+        assert!(warm.humidity_relative < cold.humidity_relative);
+    }
+
+    #[tokio::test]
+    async fn test_chaos_sensor_failures_recover() {
+        let sensor = ChaosSensor::new(&ChaosConfig::default());
+        let faults = sensor.faults();
+        let mut sensors = SensorSet::new(vec![Box::new(sensor)], &quality());
+
+        faults.fail_next(2, io::ErrorKind::TimedOut);
+        assert!(sensors.measure_all().await[0].is_none());
+        assert!(sensors.measure_all().await[0].is_none());
+        // Kept, but flagged until the failures leave the window
+        let reading = sensors.measure_all().await.remove(0).unwrap();
+        assert_eq!(reading.quality, Quality::Suspect);
+        for _ in 0..3 {
+            sensors.measure_all().await;
+        }
+        let reading = sensors.measure_all().await.remove(0).unwrap();
+        assert_eq!(reading.quality, Quality::Good);
+        assert_eq!(faults.injected(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_chaos_out_of_range_is_quarantined() {
+        let mut sensor = ChaosSensor::new(&ChaosConfig::default());
+        let plausibility = Plausibility::new(&ValidationConfig::default());
+        sensor.out_of_range_next(1);
+
+        let measurement = sensor.measure().await.unwrap();
+        assert_eq!(
+            plausibility.check("simulated", &measurement, 70.0),
+            Err("temperature_c 150 above 85".to_string())
+        );
+        let measurement = sensor.measure().await.unwrap();
+        assert_eq!(plausibility.check("simulated", &measurement, 70.0), Ok(()));
+    }
+
+    #[test]
+    fn test_chaos_periodic_faults() {
+        let config = ChaosConfig {
+            fail_every: 3,
+            out_of_range_every: 2,
+            latency_ms: 0,
+        };
+        let mut sensor = ChaosSensor::new(&config);
+        let now = Utc.with_ymd_and_hms(2025, 6, 16, 12, 0, 0).unwrap();
+        let outcomes: Vec<Option<f64>> = (0..6)
+            .map(|_| sensor.measurement_at(&now).ok().map(|m| m.temperature_c))
+            .collect();
+        assert!(outcomes[0].unwrap() < OUT_OF_RANGE_C);
+        assert_eq!(outcomes[1], Some(OUT_OF_RANGE_C));
+        assert_eq!(outcomes[2], None);
+        assert_eq!(outcomes[3], Some(OUT_OF_RANGE_C));
+        assert!(outcomes[4].unwrap() < OUT_OF_RANGE_C);
+        assert_eq!(outcomes[5], None);
+    }
+
+    #[tokio::test]
+    async fn test_chaos_display_is_reinitialized() {
+        let display = MockDisplay::new();
+        let faults = display.faults();
+        let recovery = DisplayRecovery::new(1, CgramSlots::default());
+        let hello = |d: &MockDisplay| d.put_str(d.line_address(0), "hello");
+
+        faults.fail_next(2, io::ErrorKind::Other);
+        assert_eq!(
+            recovery.write(&display, "update", hello).await,
+            WriteOutcome::Reinitialized
+        );
+        // Locked up, the write, its retry and the re-init fail
+        faults.lock_up();
+        assert_eq!(
+            recovery.write(&display, "update", hello).await,
+            WriteOutcome::Failed
+        );
+        faults.release();
+        assert_eq!(
+            recovery.write(&display, "update", hello).await,
+            WriteOutcome::Written
+        );
+        assert!(display.grid()[0].starts_with("hello"));
+        assert_eq!(faults.injected(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_chaos_bus_lock_up_and_latency() {
+        let bus = MockI2cBus::new();
+        bus.seed(0xD0, &[BME280_CHIP_ID]);
+        let faults = bus.faults();
+        let device = Bme280::with_bus(bus).unwrap();
+        let mut sensors = SensorSet::new(
+            vec![Box::new(DeviceSensor::new("bme280", Box::new(device)))],
+            &quality(),
+        );
+
+        faults.lock_up();
+        assert!(sensors.measure_all().await[0].is_none());
+        assert!(sensors.measure_all().await[0].is_none());
+        faults.release();
+        assert!(sensors.measure_all().await[0].is_some());
+        assert_eq!(faults.injected(), 2);
+
+        // Every transfer of the measurement is delayed
+        faults.set_latency(Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        assert!(sensors.measure_all().await[0].is_some());
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_simulated_sensor_and_null_display_in_a_set() {
+        let mut sensors = SensorSet::new(
+            vec![Box::new(DeviceSensor::new(
+                "bme280",
+                Box::new(SimulatedSensor::new()),
+            ))],
+            &quality(),
+        );
+        let reading = sensors.measure_all().await[0].clone().unwrap();
+        assert!((18.0..=26.0).contains(&reading.measurement.temperature_c));
+
+        let display = NullDisplay::new(peripheral::hd44780::HD44780_16X2_GEOMETRY);
+        display.setup().await.unwrap();
+        display
+            .put_str(display.line_address(1), "23.7C 65.2%")
+            .unwrap();
+        assert_eq!(display.grid()[1], "23.7C 65.2%     ");
+        display.clear_home().unwrap();
+        assert_eq!(display.grid()[1], " ".repeat(16));
+    }
+}
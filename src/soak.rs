@@ -0,0 +1,454 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Long-running soak test of the simulated pipeline.
+//!
+//! The daemon's main loop runs with the default configuration on simulated
+//! hardware and a virtual clock which advances `speed` times faster than
+//! real time. Rows go to a temporary SQLite file unless a database is given.
+//! Process RSS, open file descriptors and the database queue depth are
+//! sampled periodically and checked for unbounded growth.
+//! Faults from the [chaos] section are injected into the simulated sensor
+//! and counted in the report.
+
+use std::error::Error;
+use std::fs;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{Duration as ChronoDuration, Local};
+use serde::Serialize;
+
+use crate::config::{ChaosConfig, Config};
+use crate::database::Database;
+use crate::sensor::{EnvSensor, MeasureFuture};
+use crate::simulate::{ChaosSensor, Harness, NullDisplay, WallClock};
+
+/// Soak test options.
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    /// Virtual duration in hours.
+    pub hours: f64,
+    /// Virtual measurement interval in milliseconds.
+    pub interval_ms: u64,
+    /// Virtual clock acceleration factor.
+    pub speed: f64,
+    /// Number of samples taken over the whole run.
+    pub samples: u64,
+    /// Maximum allowed RSS growth in kB.
+    pub max_rss_growth_kb: u64,
+    /// Maximum allowed growth of open file descriptors.
+    pub max_fd_growth: u64,
+    /// Maximum allowed queue depth at the end of the run.
+    pub max_queue_depth: u64,
+    /// Database URL. A temporary SQLite file is used if not specified.
+    pub database_url: Option<String>,
+    /// Faults injected into the simulated sensor.
+    pub chaos: ChaosConfig,
+}
+
+/// One sample of the process counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct SoakSample {
+    pub virtual_secs: u64,
+    pub rss_kb: Option<u64>,
+    pub open_fds: Option<u64>,
+    pub queue_depth: u64,
+}
+
+/// Injected faults seen by the pipeline.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FaultCounts {
+    /// Measurements which failed.
+    pub sensor_failures: u64,
+    /// Rows which failed the plausibility check and were quarantined.
+    pub quarantined: u64,
+}
+
+/// Sensor counting the failed measurements of the one it wraps.
+struct CountedSensor {
+    inner: ChaosSensor,
+    failures: Arc<AtomicU64>,
+}
+
+impl EnvSensor for CountedSensor {
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    fn measure(&mut self) -> MeasureFuture<'_> {
+        let failures = Arc::clone(&self.failures);
+        let measured = self.inner.measure();
+        Box::pin(async move {
+            let result = measured.await;
+            if result.is_err() {
+                failures.fetch_add(1, Ordering::Relaxed);
+            }
+            result
+        })
+    }
+}
+
+/// Machine-readable soak test report.
+#[derive(Debug, Serialize)]
+pub struct SoakReport {
+    pub hours: f64,
+    pub ticks: u64,
+    pub samples: Vec<SoakSample>,
+    pub rss_growth_kb: Option<i64>,
+    pub fd_growth: Option<i64>,
+    pub final_queue_depth: u64,
+    pub faults: FaultCounts,
+    pub failures: Vec<String>,
+    pub passed: bool,
+}
+
+/// Run the soak test.
+/// # Arguments
+/// * `options` - Soak test options.
+/// # Returns
+/// * `Ok(SoakReport)` when the run completed, whether or not it passed.
+/// * `Err(e)` if the pipeline could not be set up or failed.
+pub async fn run(options: &SoakOptions) -> Result<SoakReport, Box<dyn Error>> {
+    let scratch = std::env::temp_dir().join(format!("wbroker-soak-{}", std::process::id()));
+    let temp_db = scratch.with_extension("db");
+    let database_url = match options.database_url {
+        Some(ref url) => url.clone(),
+        None => format!("sqlite:{}?mode=rwc", temp_db.display()),
+    };
+    let mut config = Config::default();
+    config.database.url = database_url;
+    // Ticks at the real pace, the virtual clock moves by the full interval
+    config.sensor.interval_ms = ((options.interval_ms as f64 / options.speed) as u64).max(1);
+    // The safe mode episode of the installed daemon is left alone
+    config.safe_mode.crash_file = scratch.with_extension("crashes").display().to_string();
+    // An explicit database may hold rows quarantined before
+    let quarantined_before = quarantined_rows(&config.database.url).await?;
+
+    let clock = WallClock::starting_at(Local::now());
+    let failures = Arc::new(AtomicU64::new(0));
+    let sensor = CountedSensor {
+        inner: ChaosSensor::new(&options.chaos).with_clock(clock.clone()),
+        failures: Arc::clone(&failures),
+    };
+    let ticks = (options.hours * 3600.0 * 1000.0 / options.interval_ms as f64) as u64;
+    let sample_every = (ticks / options.samples.max(1)).max(1);
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let on_tick = {
+        let clock = clock.clone();
+        let samples = Arc::clone(&samples);
+        let step = ChronoDuration::milliseconds(options.interval_ms as i64);
+        let interval_ms = options.interval_ms;
+        move |tick: u64, database: Option<&Database>| {
+            // Ticks done before this one
+            let done = tick - 1;
+            if done > 0 {
+                clock.advance(step);
+            }
+            let queue_depth = database.map_or(0, Database::queue_len) as u64;
+            if done.is_multiple_of(sample_every) || done == ticks {
+                let mut samples = samples.lock().unwrap_or_else(|e| e.into_inner());
+                samples.push(take_sample(done * interval_ms / 1000, queue_depth));
+            }
+            if done == ticks {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    };
+    let harness = Harness {
+        sensor: Some(Box::new(sensor)),
+        display: NullDisplay::quiet(config.display.geometry()),
+        clock,
+        on_tick: Some(Box::new(on_tick)),
+    };
+    let boot = crate::Boot::new(Instant::now());
+    let ran = crate::daemon(&config, true, "", boot, None, Some(harness)).await;
+
+    let quarantined = quarantined_rows(&config.database.url).await;
+    let _ = fs::remove_file(&temp_db);
+    ran.map_err(|e| format!("The pipeline stopped: {}", e))?;
+    let faults = FaultCounts {
+        sensor_failures: failures.load(Ordering::Relaxed),
+        quarantined: quarantined? - quarantined_before,
+    };
+    let samples = std::mem::take(&mut *samples.lock().unwrap_or_else(|e| e.into_inner()));
+    let final_queue_depth = samples.last().map_or(0, |sample| sample.queue_depth);
+    Ok(evaluate(options, ticks, samples, final_queue_depth, faults))
+}
+
+/// Count the quarantined rows of a database.
+/// # Arguments
+/// * `url` - Database URL.
+/// # Returns
+/// * Result<u64, Box<dyn Error>>
+async fn quarantined_rows(url: &str) -> Result<u64, Box<dyn Error>> {
+    let database = Database::new(url)
+        .await
+        .map_err(|e| format!("Failed to initialize database: {}", e))?;
+    let count = database.quarantined_rows().await;
+    database.close().await;
+    Ok(count?)
+}
+
+/// Build the report and decide whether the run passed.
+/// The second sample is used as the baseline so start-up allocations
+/// (connection pool, caches) are not counted as growth.
+/// # Arguments
+/// * `options` - Soak test options.
+/// * `ticks` - Number of ticks run.
+/// * `samples` - Samples taken during the run.
+/// * `final_queue_depth` - Queue depth at the end of the run.
+/// * `faults` - Injected faults seen by the pipeline.
+/// # Returns
+/// * SoakReport
+fn evaluate(
+    options: &SoakOptions,
+    ticks: u64,
+    samples: Vec<SoakSample>,
+    final_queue_depth: u64,
+    faults: FaultCounts,
+) -> SoakReport {
+    let baseline = samples.get(1).or(samples.first());
+    let last = samples.last();
+    let growth = |f: fn(&SoakSample) -> Option<u64>| match (baseline.and_then(f), last.and_then(f))
+    {
+        (Some(b), Some(l)) => Some(l as i64 - b as i64),
+        _ => None,
+    };
+    let rss_growth_kb = growth(|s| s.rss_kb);
+    let fd_growth = growth(|s| s.open_fds);
+
+    let mut failures = Vec::new();
+    if let Some(g) = rss_growth_kb.filter(|&g| g > options.max_rss_growth_kb as i64) {
+        failures.push(format!(
+            "RSS grew by {} kB (limit {} kB)",
+            g, options.max_rss_growth_kb
+        ));
+    }
+    if let Some(g) = fd_growth.filter(|&g| g > options.max_fd_growth as i64) {
+        failures.push(format!(
+            "Open file descriptors grew by {} (limit {})",
+            g, options.max_fd_growth
+        ));
+    }
+    if final_queue_depth > options.max_queue_depth {
+        failures.push(format!(
+            "Database queue depth is {} at the end of the run (limit {})",
+            final_queue_depth, options.max_queue_depth
+        ));
+    }
+    let depths: Vec<u64> = samples.iter().map(|s| s.queue_depth).collect();
+    if is_growing(&depths) {
+        failures.push("Database queue depth grows without bound".to_string());
+    }
+
+    SoakReport {
+        hours: options.hours,
+        ticks,
+        samples,
+        rss_growth_kb,
+        fd_growth,
+        final_queue_depth,
+        faults,
+        passed: failures.is_empty(),
+        failures,
+    }
+}
+
+/// Check whether a counter grew in each of the last samples.
+/// # Arguments
+/// * `values` - Sampled values, oldest first.
+/// # Returns
+/// * `true` if the last 4 samples are strictly increasing.
+fn is_growing(values: &[u64]) -> bool {
+    const WINDOW: usize = 4;
+    if values.len() < WINDOW {
+        return false;
+    }
+    values[values.len() - WINDOW..]
+        .windows(2)
+        .all(|w| w[1] > w[0])
+}
+
+/// Take a sample of the process counters.
+/// # Arguments
+/// * `virtual_secs` - Elapsed virtual time.
+/// * `queue_depth` - Rows queued for the database.
+/// # Returns
+/// * SoakSample
+fn take_sample(virtual_secs: u64, queue_depth: u64) -> SoakSample {
+    let rss_kb = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_vm_rss(&status));
+    let open_fds = fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count() as u64);
+    SoakSample {
+        virtual_secs,
+        rss_kb,
+        open_fds,
+        queue_depth,
+    }
+}
+
+/// Parse the VmRSS value out of /proc/self/status.
+/// # Arguments
+/// * `status` - Content of /proc/self/status.
+/// # Returns
+/// * RSS in kB, if present.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> SoakOptions {
+        SoakOptions {
+            hours: 24.0,
+            interval_ms: 200,
+            speed: 60.0,
+            samples: 100,
+            max_rss_growth_kb: 1024,
+            max_fd_growth: 4,
+            max_queue_depth: 10,
+            database_url: None,
+            chaos: ChaosConfig::default(),
+        }
+    }
+
+    fn sample(rss_kb: u64, open_fds: u64, queue_depth: u64) -> SoakSample {
+        SoakSample {
+            virtual_secs: 0,
+            rss_kb: Some(rss_kb),
+            open_fds: Some(open_fds),
+            queue_depth,
+        }
+    }
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\twbroker-rs\nVmPeak:\t  20000 kB\nVmRSS:\t    4321 kB\nThreads:\t4\n";
+        assert_eq!(parse_vm_rss(status), Some(4321));
+        assert_eq!(parse_vm_rss("Name:\twbroker-rs\n"), None);
+    }
+
+    #[test]
+    fn test_is_growing() {
+        assert!(is_growing(&[0, 1, 2, 3, 4]));
+        assert!(!is_growing(&[0, 5, 2, 3, 4]));
+        assert!(!is_growing(&[3, 3, 3, 3]));
+        assert!(!is_growing(&[1, 2]));
+    }
+
+    #[test]
+    fn test_evaluate_stable_run_passes() {
+        let samples = vec![
+            sample(3000, 10, 0),
+            sample(4000, 12, 1),
+            sample(4100, 12, 0),
+            sample(4050, 12, 0),
+        ];
+        let report = evaluate(&options(), 1000, samples, 0, FaultCounts::default());
+        assert!(report.passed);
+        assert_eq!(report.rss_growth_kb, Some(50));
+        assert_eq!(report.fd_growth, Some(0));
+    }
+
+    #[test]
+    fn test_evaluate_memory_growth_fails() {
+        let samples = vec![
+            sample(3000, 10, 0),
+            sample(4000, 10, 0),
+            sample(9000, 10, 0),
+        ];
+        let report = evaluate(&options(), 1000, samples, 0, FaultCounts::default());
+        assert!(!report.passed);
+        assert!(report.failures[0].contains("RSS grew by 5000 kB"));
+    }
+
+    #[test]
+    fn test_evaluate_queue_growth_fails() {
+        let samples = vec![
+            sample(4000, 10, 0),
+            sample(4000, 10, 5),
+            sample(4000, 10, 9),
+            sample(4000, 10, 20),
+            sample(4000, 10, 50),
+        ];
+        let report = evaluate(&options(), 1000, samples, 50, FaultCounts::default());
+        assert!(!report.passed);
+        assert_eq!(report.failures.len(), 2);
+    }
+
+    #[test]
+    fn test_report_is_json() {
+        let report = evaluate(
+            &options(),
+            10,
+            vec![sample(1, 1, 0)],
+            0,
+            FaultCounts::default(),
+        );
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains("\"passed\":true"));
+        assert!(json.contains("\"samples\""));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires sqlx any drivers"]
+    async fn test_soak_counts_injected_faults() {
+        let options = SoakOptions {
+            hours: 0.01,
+            speed: 1_000_000.0,
+            samples: 4,
+            // The writer may lag behind ticks of 1 ms until the run ends
+            max_queue_depth: 180,
+            chaos: ChaosConfig {
+                fail_every: 10,
+                out_of_range_every: 15,
+                latency_ms: 0,
+            },
+            ..options()
+        };
+        let report = run(&options).await.unwrap();
+        assert_eq!(report.ticks, 180);
+        assert_eq!(report.samples.last().unwrap().virtual_secs, 36);
+        // Every 30th measurement fails before it could be out of range
+        assert_eq!(
+            report.faults,
+            FaultCounts {
+                sensor_failures: 18,
+                quarantined: 6,
+            }
+        );
+        assert!(report.passed, "{:?}", report.failures);
+    }
+}
@@ -0,0 +1,589 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Start-up error reporting and timing.
+//!
+//! The display is initialized before anything else, so when the sensor or
+//! the database fails to initialize the error is shown on the device too,
+//! not only on stderr which nobody sees on a headless Pi.
+//!
+//! Every subsystem after the display goes through `FailurePolicy`, which
+//! stops the program when a critical subsystem fails and carries on without
+//! an optional one, as configured in [subsystems].
+//!
+//! Each start-up phase is timed, and an optional watchdog aborts the process
+//! when start-up hangs, e.g. on a locked I2C bus, so systemd can flag the
+//! unit instead of waiting forever.
+
+use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use peripheral::display::CharDisplay;
+use rppal::i2c;
+use serde::Serialize;
+
+use crate::config::{Criticality, SubsystemsConfig};
+use crate::error::WbrokerError;
+use crate::exit::ExitError;
+use crate::helper;
+use crate::safe_mode;
+
+/// Duration of one start-up phase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    pub ms: u64,
+}
+
+/// Start-up timing summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StartupReport {
+    /// Phases in the order they ran.
+    pub phases: Vec<PhaseTiming>,
+    /// Time from process start to the end of the last phase.
+    pub total_ms: u64,
+    /// Time from process start to the first successful measurement.
+    pub first_sample_ms: Option<u64>,
+}
+
+impl StartupReport {
+    /// One-line `name=ms` summary for the log.
+    pub fn summary(&self) -> String {
+        let mut fields: Vec<String> = self
+            .phases
+            .iter()
+            .map(|phase| format!("{}={}ms", phase.name, phase.ms))
+            .collect();
+        fields.push(format!("total={}ms", self.total_ms));
+        if let Some(ms) = self.first_sample_ms {
+            fields.push(format!("first_sample={}ms", ms));
+        }
+        fields.join(" ")
+    }
+}
+
+/// Times the start-up phases.
+/// The running phase is shared, so the watchdog can tell where start-up hung.
+#[derive(Debug)]
+pub struct StartupTimer {
+    started: Instant,
+    phase_started: Instant,
+    phases: Vec<PhaseTiming>,
+    current: Arc<Mutex<&'static str>>,
+}
+
+impl StartupTimer {
+    /// Start timing with the first phase.
+    /// # Arguments
+    /// * `phase` - Name of the first phase.
+    /// * `now` - Process start.
+    pub fn start(phase: &'static str, now: Instant) -> Self {
+        Self {
+            started: now,
+            phase_started: now,
+            phases: Vec::new(),
+            current: Arc::new(Mutex::new(phase)),
+        }
+    }
+
+    /// End the running phase and begin the next one.
+    /// # Arguments
+    /// * `phase` - Name of the next phase.
+    /// * `now` - Current time.
+    pub fn begin(&mut self, phase: &'static str, now: Instant) {
+        self.end_phase(now);
+        *self.lock() = phase;
+    }
+
+    /// End the running phase and build the report.
+    /// # Arguments
+    /// * `now` - Current time.
+    /// # Returns
+    /// * Timing of every phase.
+    pub fn finish(mut self, now: Instant) -> StartupReport {
+        self.end_phase(now);
+        StartupReport {
+            phases: self.phases,
+            total_ms: millis(now - self.started),
+            first_sample_ms: None,
+        }
+    }
+
+    /// Name of the running phase, updated as start-up progresses.
+    pub fn current_phase(&self) -> Arc<Mutex<&'static str>> {
+        self.current.clone()
+    }
+
+    fn end_phase(&mut self, now: Instant) {
+        let name = *self.lock();
+        self.phases.push(PhaseTiming {
+            name,
+            ms: millis(now - self.phase_started),
+        });
+        self.phase_started = now;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, &'static str> {
+        self.current.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Aborts the process with `exit::STARTUP_TIMEOUT` unless disarmed in time.
+/// It runs on its own thread, so it fires even while a blocking I2C
+/// transfer holds up the async runtime.
+#[derive(Debug)]
+pub struct StartupWatchdog {
+    done: mpsc::Sender<()>,
+}
+
+impl StartupWatchdog {
+    /// Arm the watchdog.
+    /// # Arguments
+    /// * `timeout` - Time allowed for start-up.
+    /// * `phase` - Running phase, from `StartupTimer::current_phase`.
+    pub fn spawn(timeout: Duration, phase: Arc<Mutex<&'static str>>) -> Self {
+        let (done, wait) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            // Dropping the watchdog disconnects the channel and ends the thread
+            if let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                let error = ExitError::StartupTimeout {
+                    secs: timeout.as_secs(),
+                    phase: *phase.lock().unwrap_or_else(|e| e.into_inner()),
+                };
+                eprintln!("{}", error);
+                safe_mode::record_crash(error.code());
+                std::process::exit(error.code().into());
+            }
+        });
+        Self { done }
+    }
+
+    /// Start-up completed, stop the watchdog.
+    pub fn disarm(self) {
+        let _ = self.done.send(());
+    }
+}
+
+/// Whole milliseconds of a duration.
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+/// Show a start-up error on the display.
+/// # Arguments
+/// * `display` - Initialized display.
+/// * `step` - Name of the failed step, e.g. "sensor".
+/// * `message` - Error message.
+/// # Returns
+/// * Result<(), i2c::Error>
+pub fn show_error<D: CharDisplay>(
+    display: &D,
+    step: &str,
+    message: &str,
+) -> Result<(), i2c::Error> {
+    let columns = usize::from(display.geometry().cols);
+    display.put_str(
+        display.line_address(0),
+        &helper::fit_line(&format!("{} ERROR", step.to_uppercase()), columns),
+    )?;
+    display.put_str(display.line_address(1), &helper::fit_line(message, columns))?;
+    Ok(())
+}
+
+/// Pass through a successful start-up step, or report its error.
+/// The error is printed to stderr and its root cause shown on the display,
+/// which has no room for the context. A failure to show it is only
+/// printed, so the original error is what gets returned.
+/// # Arguments
+/// * `display` - Initialized display.
+/// * `step` - Name of the step, e.g. "sensor".
+/// * `result` - Result of the step.
+/// # Returns
+/// * `Ok(value)` if the step succeeded.
+/// * `Err(e)` describing the failed step.
+pub fn check_step<D, T, E>(display: &D, step: &str, result: Result<T, E>) -> Result<T, WbrokerError>
+where
+    D: CharDisplay,
+    E: Into<WbrokerError>,
+{
+    result.map_err(|e| {
+        let error = e.into();
+        eprintln!("Error: {}", error);
+        report_on_display(display, step, &error);
+        error
+    })
+}
+
+/// Show the root cause of a start-up error on the display.
+/// # Arguments
+/// * `display` - Initialized display.
+/// * `step` - Name of the failed step.
+/// * `error` - Error of the step.
+fn report_on_display<D: CharDisplay>(display: &D, step: &str, error: &WbrokerError) {
+    let mut cause: &dyn Error = error;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    if let Err(display_error) = show_error(display, step, &cause.to_string()) {
+        eprintln!("Failed to show the error on the display: {}", display_error);
+    }
+}
+
+/// Subsystem set up at start-up, after the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    Sensor,
+    Database,
+    Hooks,
+    Http,
+    Button,
+    Power,
+    LightSensor,
+}
+
+impl Subsystem {
+    /// Name in the log, on the display and in [subsystems].
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Sensor => "sensor",
+            Subsystem::Database => "database",
+            Subsystem::Hooks => "hooks",
+            Subsystem::Http => "http",
+            Subsystem::Button => "button",
+            Subsystem::Power => "power",
+            Subsystem::LightSensor => "light_sensor",
+        }
+    }
+}
+
+/// Decides, per subsystem, whether a start-up failure is fatal.
+#[derive(Debug)]
+pub struct FailurePolicy {
+    config: SubsystemsConfig,
+    degraded: Vec<Subsystem>,
+}
+
+impl FailurePolicy {
+    /// Create a policy.
+    /// # Arguments
+    /// * `config` - Criticality of each subsystem.
+    pub fn new(config: &SubsystemsConfig) -> Self {
+        Self {
+            config: *config,
+            degraded: Vec::new(),
+        }
+    }
+
+    /// Configured criticality of a subsystem.
+    pub fn criticality(&self, subsystem: Subsystem) -> Criticality {
+        match subsystem {
+            Subsystem::Sensor => self.config.sensor,
+            Subsystem::Database => self.config.database,
+            Subsystem::Hooks => self.config.hooks,
+            Subsystem::Http => self.config.http,
+            Subsystem::Button => self.config.button,
+            Subsystem::Power => self.config.power,
+            Subsystem::LightSensor => self.config.light_sensor,
+        }
+    }
+
+    /// Pass through a successful start-up step, or handle its failure.
+    /// The error is printed and shown on the display either way. A critical
+    /// subsystem returns it, an optional one is marked degraded.
+    /// # Arguments
+    /// * `display` - Initialized display.
+    /// * `subsystem` - Subsystem of the step.
+    /// * `result` - Result of the step.
+    /// # Returns
+    /// * `Ok(Some(value))` if the step succeeded.
+    /// * `Ok(None)` if an optional subsystem failed.
+    /// * `Err(e)` classifying the failure if a critical subsystem failed.
+    pub fn check<D, T, E>(
+        &mut self,
+        display: &D,
+        subsystem: Subsystem,
+        result: Result<T, E>,
+    ) -> Result<Option<T>, ExitError>
+    where
+        D: CharDisplay,
+        E: Into<WbrokerError>,
+    {
+        match (result, self.criticality(subsystem)) {
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(e), Criticality::Critical) => check_step(display, subsystem.name(), Err(e))
+                .map_err(|e| ExitError::subsystem(subsystem, e.to_string())),
+            (Err(e), Criticality::Optional) => {
+                let error = e.into();
+                eprintln!("Warning: {}. Continuing without it.", error);
+                report_on_display(display, subsystem.name(), &error);
+                if !self.degraded.contains(&subsystem) {
+                    self.degraded.push(subsystem);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether an optional subsystem failed.
+    pub fn is_degraded(&self, subsystem: Subsystem) -> bool {
+        self.degraded.contains(&subsystem)
+    }
+
+    /// Optional subsystems which failed, in the order they failed.
+    pub fn degraded(&self) -> &[Subsystem] {
+        &self.degraded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{DatabaseError, SensorError};
+    use peripheral::display::DisplayGeometry;
+    use std::io;
+    use std::sync::Mutex;
+
+    /// Display recording the printed lines.
+    #[derive(Default)]
+    struct MockDisplay {
+        lines: Mutex<Vec<(u8, String)>>,
+    }
+
+    impl CharDisplay for MockDisplay {
+        async fn setup(&self) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+
+        fn geometry(&self) -> DisplayGeometry {
+            peripheral::hd44780::HD44780_16X2_GEOMETRY
+        }
+
+        fn register_char(&self, _index: u8, _data: [u8; 8]) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+
+        fn put_u8(&self, _position: u8, _data: u8) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+
+        fn put_str(&self, line_addr: u8, s: &str) -> Result<(), i2c::Error> {
+            self.lines.lock().unwrap().push((line_addr, s.to_string()));
+            Ok(())
+        }
+
+        fn clear_home(&self) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+
+        fn display_off(&self) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+
+        fn display_on(&self) -> Result<(), i2c::Error> {
+            Ok(())
+        }
+    }
+
+    fn sensor_error(message: &str) -> SensorError {
+        SensorError::Init {
+            driver: "BME280",
+            address: 0x76,
+            source: i2c::Error::Io(io::Error::other(message)),
+        }
+    }
+
+    fn database_error() -> DatabaseError {
+        DatabaseError::connect(
+            "postgresql://user:secret@db:5432/env",
+            sqlx::Error::Io(io::Error::other("no such host")),
+        )
+    }
+
+    #[test]
+    fn test_sensor_failure_is_rendered() {
+        let display = MockDisplay::default();
+
+        let result = check_step(&display, "sensor", Err::<(), _>(sensor_error("no ack")));
+
+        let error = result.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to initialize BME280 at 0x76: ")
+        );
+        let lines = display.lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], (0x80, "SENSOR ERROR    ".to_string()));
+        // The root cause, without the context
+        let cause = i2c::Error::Io(io::Error::other("no ack")).to_string();
+        assert_eq!(lines[1], (0xC0, helper::fit_line(&cause, 16)));
+    }
+
+    #[test]
+    fn test_success_renders_nothing() {
+        let display = MockDisplay::default();
+
+        let value = check_step(&display, "database", Ok::<u8, DatabaseError>(42)).unwrap();
+
+        assert_eq!(value, 42);
+        assert!(display.lines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_optional_failure_degrades() {
+        let display = MockDisplay::default();
+        let config = SubsystemsConfig {
+            database: Criticality::Optional,
+            ..SubsystemsConfig::default()
+        };
+        let mut policy = FailurePolicy::new(&config);
+
+        let database = policy
+            .check(
+                &display,
+                Subsystem::Database,
+                Err::<u8, _>(database_error()),
+            )
+            .unwrap();
+
+        assert_eq!(database, None);
+        assert!(policy.is_degraded(Subsystem::Database));
+        assert_eq!(policy.degraded(), &[Subsystem::Database]);
+        assert_eq!(display.lines.lock().unwrap()[0].1, "DATABASE ERROR  ");
+        let sensor = policy.check(&display, Subsystem::Sensor, Ok::<u8, SensorError>(1));
+        assert_eq!(sensor.unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_critical_failure_exits() {
+        let display = MockDisplay::default();
+        let config = SubsystemsConfig {
+            database: Criticality::Optional,
+            ..SubsystemsConfig::default()
+        };
+        let mut policy = FailurePolicy::new(&config);
+
+        let error = policy
+            .check(
+                &display,
+                Subsystem::Sensor,
+                Err::<u8, _>(sensor_error("no ack")),
+            )
+            .unwrap_err();
+
+        assert_eq!(error.code(), crate::exit::SENSOR_INIT_FAILED);
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to initialize BME280 at 0x76: ")
+        );
+        assert!(policy.degraded().is_empty());
+        assert_eq!(display.lines.lock().unwrap()[0].1, "SENSOR ERROR    ");
+
+        // Critical by default
+        let mut policy = FailurePolicy::new(&SubsystemsConfig::default());
+        assert!(
+            policy
+                .check(
+                    &display,
+                    Subsystem::Database,
+                    Err::<u8, _>(database_error())
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_show_error_truncates_message() {
+        let display = MockDisplay::default();
+        show_error(
+            &display,
+            "database",
+            "error returned from database: no such host",
+        )
+        .unwrap();
+
+        let lines = display.lines.lock().unwrap();
+        assert_eq!(lines[0].1, "DATABASE ERROR  ");
+        assert_eq!(lines[1].1, "error returned f");
+    }
+
+    #[test]
+    fn test_startup_timer_phases() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        let mut timer = StartupTimer::start("config_load", start);
+        let phase = timer.current_phase();
+        timer.begin("display_init", at(5));
+        assert_eq!(*phase.lock().unwrap(), "display_init");
+        timer.begin("sensor_init", at(125));
+        let report = timer.finish(at(140));
+
+        assert_eq!(
+            report.phases,
+            vec![
+                PhaseTiming {
+                    name: "config_load",
+                    ms: 5
+                },
+                PhaseTiming {
+                    name: "display_init",
+                    ms: 120
+                },
+                PhaseTiming {
+                    name: "sensor_init",
+                    ms: 15
+                },
+            ]
+        );
+        assert_eq!(report.total_ms, 140);
+        assert_eq!(
+            report.summary(),
+            "config_load=5ms display_init=120ms sensor_init=15ms total=140ms"
+        );
+    }
+
+    #[test]
+    fn test_startup_report_first_sample() {
+        let report = StartupReport {
+            phases: vec![],
+            total_ms: 10,
+            first_sample_ms: Some(230),
+        };
+        assert_eq!(report.summary(), "total=10ms first_sample=230ms");
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["first_sample_ms"], 230);
+    }
+
+    #[test]
+    fn test_startup_watchdog_disarmed_in_time() {
+        let timer = StartupTimer::start("config_load", Instant::now());
+        let watchdog = StartupWatchdog::spawn(Duration::from_millis(50), timer.current_phase());
+        watchdog.disarm();
+        // Would exit the test process if the watchdog still fired
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,91 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! State file: a small JSON object of runtime state which survives
+//! restarts, e.g. the QNH set over HTTP. Features share one file, each
+//! under its own keys, so writing one keeps the entries of the others.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+/// Read the entries of a state file.
+/// # Arguments
+/// * `path` - State file.
+/// # Returns
+/// * `Ok(None)` if there is no state file.
+/// * `Err(e)` if it cannot be read or is not a JSON object.
+pub fn read(path: &Path) -> Result<Option<Map<String, Value>>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+/// Write entries to a state file, keeping its other entries. An unreadable
+/// file is replaced.
+/// # Arguments
+/// * `path` - State file.
+/// * `entries` - Keys and values to set.
+pub fn write<I>(path: &Path, entries: I) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = (&'static str, Value)>,
+{
+    let mut state: Map<String, Value> = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => Map::new(),
+    };
+    for (key, value) in entries {
+        state.insert(key.to_string(), value);
+    }
+    fs::write(path, serde_json::to_string_pretty(&state)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_write_keeps_other_entries() {
+        let path =
+            std::env::temp_dir().join(format!("wbroker-rs-state-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(read(&path).unwrap().is_none());
+
+        write(&path, [("a", json!(1)), ("b", json!("x"))]).unwrap();
+        write(&path, [("b", json!("y"))]).unwrap();
+        let state = read(&path).unwrap().unwrap();
+        fs::write(&path, "not json").unwrap();
+        let invalid = read(&path);
+        write(&path, [("c", json!(null))]).unwrap();
+        let replaced = read(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(state["a"], 1);
+        assert_eq!(state["b"], "y");
+        assert!(invalid.is_err());
+        assert_eq!(replaced.len(), 1);
+    }
+}
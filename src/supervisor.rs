@@ -0,0 +1,257 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Supervision of the background tasks.
+//!
+//! A panicking task would otherwise leave the daemon half-running, e.g.
+//! still measuring while nothing is stored. The panic hook logs every
+//! panic with a backtrace, and the long-running tasks are spawned in a
+//! `Supervisor` so the main loop notices when one of them panics, shows it
+//! on the display, flushes the database and exits with
+//! `exit::TASK_PANICKED` for systemd to restart the daemon. The main loop
+//! itself runs in `catch_panic`, and is torn down the same way.
+//!
+//! This holds for every profile, the release one included, as long as
+//! panics unwind: with `panic = "abort"` the process would die in the hook
+//! before any of it. The build refuses that setting.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::{Future, poll_fn};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::task::Poll;
+
+use tokio::task::{Id, JoinSet};
+use tokio::time::{Duration, timeout};
+
+use crate::exit;
+use crate::safe_mode;
+
+// A panicking task has to unwind into a `JoinError` to be torn down
+#[cfg(panic = "abort")]
+compile_error!("wbroker-rs needs panic = \"unwind\", see src/supervisor.rs");
+
+/// Longest time each teardown step may take after a panic.
+pub const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+thread_local! {
+    /// Whether `catch_panic` is polling on this thread.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Log every panic with a backtrace and count it towards safe mode. A
+/// panic of the main thread outside `catch_panic`, e.g. during start-up,
+/// exits right away with `exit::TASK_PANICKED`.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        let name = thread.name().unwrap_or("unnamed");
+        eprintln!(
+            "Panic in thread {}: {}\n{}",
+            name,
+            info,
+            Backtrace::force_capture()
+        );
+        safe_mode::record_crash(exit::TASK_PANICKED);
+        if name == "main" && !CATCHING.get() {
+            std::process::exit(i32::from(exit::TASK_PANICKED));
+        }
+    }));
+}
+
+/// Run a future which is not a task of its own, catching its panics.
+/// # Arguments
+/// * `future` - Future to run, the main loop.
+/// # Returns
+/// * `Ok(output)` of the future.
+/// * `Err(message)` of the panic, once the future is dropped.
+pub async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = pin!(future);
+    poll_fn(|cx| {
+        let outer = CATCHING.replace(true);
+        let polled = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx)));
+        CATCHING.set(outer);
+        match polled {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(panic_message(payload.as_ref()))),
+        }
+    })
+    .await
+}
+
+/// Supervised task which panicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskFailure {
+    /// Name of the task.
+    pub task: &'static str,
+    /// Panic message.
+    pub message: String,
+}
+
+/// Long-running background tasks, watched for panics.
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: JoinSet<()>,
+    names: HashMap<Id, &'static str>,
+}
+
+impl Supervisor {
+    /// Spawn a task under supervision.
+    /// # Arguments
+    /// * `name` - Name of the task, for the log.
+    /// * `task` - Task to run.
+    pub fn spawn<F>(&mut self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = self.tasks.spawn(task);
+        self.names.insert(handle.id(), name);
+    }
+
+    /// Wait until a task panics. Tasks which return are only logged.
+    /// Cancel safe, for use in `select!`.
+    /// # Returns
+    /// * The failed task, never while no task panics.
+    pub async fn failure(&mut self) -> TaskFailure {
+        loop {
+            match self.tasks.join_next_with_id().await {
+                Some(Ok((id, ()))) => {
+                    println!("Task {} finished", self.name(id));
+                }
+                Some(Err(e)) if e.is_panic() => {
+                    let task = self.name(e.id());
+                    return TaskFailure {
+                        task,
+                        message: panic_message(e.into_panic().as_ref()),
+                    };
+                }
+                Some(Err(_)) => {}
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Abort every task and wait for them to stop.
+    /// # Arguments
+    /// * `limit` - Longest time to wait.
+    pub async fn shutdown(mut self, limit: Duration) {
+        self.tasks.abort_all();
+        let stopped = timeout(limit, async {
+            while self.tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if stopped.is_err() {
+            eprintln!("Tasks did not stop within {:?}", limit);
+        }
+    }
+
+    fn name(&self, id: Id) -> &'static str {
+        self.names.get(&id).copied().unwrap_or("unknown")
+    }
+}
+
+/// Message of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Sets the flag when the task owning it is dropped.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_tears_down_the_others() {
+        let mut supervisor = Supervisor::default();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(dropped.clone());
+        supervisor.spawn("poller", async move {
+            let _flag = flag;
+            std::future::pending::<()>().await;
+        });
+        supervisor.spawn("finished", async {});
+        supervisor.spawn("writer", async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            panic!("writer failed: {}", 42);
+        });
+
+        let failure = timeout(Duration::from_secs(5), supervisor.failure())
+            .await
+            .unwrap();
+        assert_eq!(
+            failure,
+            TaskFailure {
+                task: "writer",
+                message: "writer failed: 42".to_string(),
+            }
+        );
+        assert!(!dropped.load(Ordering::SeqCst));
+        supervisor.shutdown(TEARDOWN_TIMEOUT).await;
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_no_failure_without_panic() {
+        let mut supervisor = Supervisor::default();
+        supervisor.spawn("finished", async {});
+        let waited = timeout(Duration::from_millis(50), supervisor.failure()).await;
+        assert!(waited.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_catch_panic() {
+        assert_eq!(catch_panic(async { 42 }).await, Ok(42));
+        let caught = catch_panic(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            panic!("loop failed: {}", 42);
+        })
+        .await;
+        assert_eq!(caught, Err::<(), _>("loop failed: 42".to_string()));
+        assert!(!CATCHING.get());
+    }
+
+    #[test]
+    fn test_panic_message() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&"owned".to_string()), "owned");
+        assert_eq!(panic_message(&42), "non-string panic payload");
+    }
+}
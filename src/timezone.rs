@@ -0,0 +1,253 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Time zone of the timestamps.
+//!
+//! Every timestamp is taken with `chrono::Local`, which follows `TZ` or
+//! /etc/localtime. Minimal images without tzdata have neither, and `Local`
+//! silently falls back to UTC. `[clock] timezone` forces a zone from the
+//! database built into the program instead: its rules for the current year
+//! are set as a POSIX `TZ` string, which needs no zoneinfo files, so the
+//! stored and the displayed times both follow it.
+
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Days, Offset, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz, TzOffset};
+
+/// Directory of the zoneinfo files named by `TZ`.
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Make `chrono::Local` follow the forced zone, or warn when it falls back
+/// to UTC. Call before other threads use the environment.
+/// # Arguments
+/// * `forced` - Zone of `[clock] timezone`, `None` to use the system one.
+/// # Returns
+/// * The zone and the rule set in `TZ`, if forced.
+pub fn setup(forced: Option<Tz>) -> Option<String> {
+    match forced {
+        Some(tz) => {
+            let rule = posix_rule(tz, Utc::now().year());
+            // SAFETY: called at start-up, no other thread reads the
+            // environment until the measurement loop starts
+            unsafe { std::env::set_var("TZ", &rule) };
+            return Some(format!("{} ({})", tz.name(), rule));
+        }
+        None if !is_configured(std::env::var("TZ").ok().as_deref(), Path::exists) => eprintln!(
+            "Warning: no time zone is configured (TZ or /etc/localtime), timestamps are in UTC. \
+             Set [clock] timezone, e.g. \"Asia/Tokyo\""
+        ),
+        None => {}
+    }
+    None
+}
+
+/// Whether `chrono::Local` finds a configured time zone.
+/// # Arguments
+/// * `tz` - Value of the `TZ` environment variable.
+/// * `exists` - Whether a file exists, `Path::exists` outside of tests.
+/// # Returns
+/// * `true` if `TZ` names a zoneinfo file or holds a POSIX rule, or
+///   /etc/localtime exists without `TZ`.
+pub fn is_configured(tz: Option<&str>, exists: impl Fn(&Path) -> bool) -> bool {
+    let Some(tz) = tz.filter(|tz| !tz.is_empty()) else {
+        return exists(Path::new("/etc/localtime"));
+    };
+    let name = tz.strip_prefix(':').unwrap_or(tz);
+    // A POSIX rule holds its offset, e.g. JST-9
+    name == "UTC"
+        || name.contains(|c: char| c.is_ascii_digit())
+        || exists(Path::new(name))
+        || exists(&Path::new(ZONEINFO_DIR).join(name))
+}
+
+/// POSIX `TZ` rule of a zone for a year, e.g.
+/// `<CET>-1<CEST>-2,M3.5.0/2,M10.5.0/3`. A zone which does not switch to
+/// daylight saving time and back once that year gets the offset it ends
+/// the year with.
+/// # Arguments
+/// * `tz` - Zone.
+/// * `year` - Year whose transitions are written as the yearly rule.
+/// # Returns
+/// * The rule, read by `chrono::Local` from `TZ`.
+pub fn posix_rule(tz: Tz, year: i32) -> String {
+    let new_year = |year| {
+        Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0)
+            .single()
+            .expect("January 1st exists")
+    };
+    let start = new_year(year);
+    let steps = (new_year(year + 1) - start).num_minutes() / STEP_MINUTES;
+    let at = |step: i64| start + TimeDelta::minutes(step * STEP_MINUTES);
+
+    let mut transitions = Vec::new();
+    let mut previous = tz.offset_from_utc_datetime(&start.naive_utc());
+    for step in 1..steps {
+        let offset = tz.offset_from_utc_datetime(&at(step).naive_utc());
+        if offset != previous {
+            transitions.push((step, previous, offset));
+            previous = offset;
+        }
+    }
+    let [(first, before, after), (second, _, _)] = transitions[..] else {
+        return zone_rule(&previous);
+    };
+    // The southern hemisphere starts the year in daylight saving time
+    let (standard, daylight, start_step, end_step) = if is_daylight(&after) {
+        (before, after, first, second)
+    } else {
+        (after, before, second, first)
+    };
+    // Each transition is written in the local time it happens in
+    format!(
+        "{}{},{},{}",
+        zone_rule(&standard),
+        zone_rule(&daylight),
+        date_rule(at(start_step), &standard),
+        date_rule(at(end_step), &daylight),
+    )
+}
+
+/// Transitions are on a quarter hour in every zone.
+const STEP_MINUTES: i64 = 15;
+
+/// Whether an offset is daylight saving time.
+fn is_daylight(offset: &TzOffset) -> bool {
+    !offset.dst_offset().is_zero()
+}
+
+/// Name and offset of a zone in a POSIX rule, e.g. `<JST>-9`.
+fn zone_rule(offset: &TzOffset) -> String {
+    let seconds = offset.fix().local_minus_utc();
+    let name = match offset.abbreviation() {
+        Some(name) => name.to_string(),
+        None => offset.fix().to_string(),
+    };
+    // POSIX offsets are west of Greenwich
+    let sign = if seconds > 0 { "-" } else { "" };
+    format!("<{}>{}{}", name, sign, clock_time(seconds.abs()))
+}
+
+/// Date and local time of a transition, e.g. `M3.5.0/2` for 2:00 on the
+/// last Sunday of March.
+/// # Arguments
+/// * `at` - Time of the transition.
+/// * `before` - Offset in effect until the transition.
+fn date_rule(at: DateTime<Utc>, before: &TzOffset) -> String {
+    let local = at.naive_utc() + TimeDelta::seconds(before.fix().local_minus_utc().into());
+    let date = local.date();
+    // Week 5 is the last one of the month
+    let week = if (date + Days::new(7)).month() != date.month() {
+        5
+    } else {
+        (date.day() - 1) / 7 + 1
+    };
+    format!(
+        "M{}.{}.{}/{}",
+        date.month(),
+        week,
+        date.weekday().num_days_from_sunday(),
+        clock_time(local.num_seconds_from_midnight() as i32)
+    )
+}
+
+/// Hours with the minutes and seconds only when not zero, e.g. `5:30`.
+fn clock_time(seconds: i32) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (minutes, seconds) {
+        (0, 0) => hours.to_string(),
+        (_, 0) => format!("{}:{:02}", hours, minutes),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use std::process::Command;
+
+    /// Zone checked by `test_forced_zone_is_local_time` in a child process,
+    /// as `TZ` is shared by the whole process.
+    const CHILD_ZONE: &str = "WBROKER_TEST_ZONE";
+
+    #[test]
+    fn test_posix_rule() {
+        assert_eq!(posix_rule(chrono_tz::Asia::Tokyo, 2025), "<JST>-9");
+        assert_eq!(posix_rule(chrono_tz::Asia::Kolkata, 2025), "<IST>-5:30");
+        assert_eq!(posix_rule(chrono_tz::UTC, 2025), "<UTC>0");
+        assert_eq!(
+            posix_rule(chrono_tz::Europe::Berlin, 2025),
+            "<CET>-1<CEST>-2,M3.5.0/2,M10.5.0/3"
+        );
+        assert_eq!(
+            posix_rule(chrono_tz::America::New_York, 2025),
+            "<EST>5<EDT>4,M3.2.0/2,M11.1.0/2"
+        );
+        assert_eq!(
+            posix_rule(chrono_tz::Australia::Sydney, 2025),
+            "<AEST>-10<AEDT>-11,M10.1.0/2,M4.1.0/3"
+        );
+    }
+
+    #[test]
+    fn test_is_configured() {
+        let tzdata =
+            |path: &Path| path.starts_with(ZONEINFO_DIR) || path == Path::new("/etc/localtime");
+        let bare = |_: &Path| false;
+        assert!(is_configured(None, tzdata));
+        assert!(!is_configured(None, bare));
+        assert!(!is_configured(Some(""), bare));
+        assert!(is_configured(Some("Asia/Tokyo"), tzdata));
+        assert!(!is_configured(Some("Asia/Tokyo"), bare));
+        assert!(is_configured(Some("JST-9"), bare));
+        assert!(is_configured(Some("UTC"), bare));
+    }
+
+    #[test]
+    fn test_forced_zone_is_local_time() {
+        let Ok(name) = std::env::var(CHILD_ZONE) else {
+            for tz in [chrono_tz::Europe::Berlin, chrono_tz::Australia::Sydney] {
+                let status = Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "timezone::tests::test_forced_zone_is_local_time"])
+                    .env("TZ", posix_rule(tz, 2025))
+                    .env(CHILD_ZONE, tz.name())
+                    .status()
+                    .unwrap();
+                assert!(status.success(), "{} is not applied", tz.name());
+            }
+            return;
+        };
+        let tz: Tz = name.parse().unwrap();
+        // Stored and displayed times, every hour of the year
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        for hour in 0..365 * 24 {
+            let at = start + TimeDelta::hours(hour);
+            let local = at.with_timezone(&Local);
+            let zoned = at.with_timezone(&tz);
+            assert_eq!(local.to_rfc3339(), zoned.to_rfc3339());
+            assert_eq!(
+                local.format("%Y/%m/%d %H:%M").to_string(),
+                zoned.format("%Y/%m/%d %H:%M").to_string()
+            );
+        }
+    }
+}
@@ -0,0 +1,421 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Debug trace of the measurement loop.
+//!
+//! `POST /api/debug/trace?secs=60`, or SIGRTMIN+2 for `DEFAULT_SECS`,
+//! turns on a verbose trace for a bounded time: every raw reading, filter
+//! decision, rendered frame, queue operation and database result is
+//! appended as a JSON line to `trace-<time>.jsonl` in [trace] dir. Each
+//! line carries the tick of the measurement loop it belongs to, also when
+//! it is written by another task such as the database writer, so the lines
+//! of one tick can be put together:
+//!
+//! ```text
+//! {"at":"2025-06-16T14:00:00.201+09:00","tick":42,"stage":"raw","detail":{..}}
+//! ```
+//!
+//! The file is bounded: once it holds half of [trace] max_bytes it is
+//! moved to `<name>.1`, replacing the previous one, and a new file begins.
+//! The trace turns itself off after its time; its start and end are stored
+//! in the `events` table with the path of the file.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use thiserror::Error;
+
+use crate::config::TraceConfig;
+
+/// Length of a trace requested without one, e.g. by the signal.
+pub const DEFAULT_SECS: u64 = 60;
+
+/// Query of `POST /api/debug/trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct TraceRequest {
+    /// Length of the trace in seconds.
+    #[serde(default = "default_secs")]
+    pub secs: u64,
+}
+
+fn default_secs() -> u64 {
+    DEFAULT_SECS
+}
+
+/// Why a trace could not be started.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TraceError {
+    #[error("secs must be 1-{max}, got {secs}")]
+    OutOfRange { secs: u64, max: u64 },
+    #[error("a trace is running into {0}")]
+    Running(String),
+    #[error("failed to create {path}: {message}")]
+    Create { path: String, message: String },
+}
+
+/// State of a running or finished trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceStatus {
+    /// File the entries are written to.
+    pub path: String,
+    pub started_at: String,
+    pub secs: u64,
+    /// Entries written.
+    pub entries: u64,
+    /// Times the file was moved aside, each dropping older entries.
+    pub rotations: u64,
+}
+
+/// Start or end of a trace, stored in the `events` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    Started(TraceStatus),
+    Ended {
+        status: TraceStatus,
+        /// Why the trace ended early, `None` when it ran its time.
+        error: Option<String>,
+    },
+}
+
+impl TraceEvent {
+    /// Kind of the event row.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TraceEvent::Started(_) => "trace_start",
+            TraceEvent::Ended { .. } => "trace_end",
+        }
+    }
+
+    /// Event data, the trace and why it ended early.
+    pub fn detail(&self) -> Value {
+        match self {
+            TraceEvent::Started(status) => json!(status),
+            TraceEvent::Ended { status, error } => {
+                let mut detail = json!(status);
+                if let Some(error) = error {
+                    detail["error"] = json!(error);
+                }
+                detail
+            }
+        }
+    }
+}
+
+/// Running trace.
+#[derive(Debug)]
+struct Session {
+    status: TraceStatus,
+    file: File,
+    /// Bytes in the current file.
+    written: u64,
+    until: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    session: Option<Session>,
+    events: Vec<TraceEvent>,
+}
+
+impl Slot {
+    /// End the session, recording why.
+    fn end(&mut self, error: Option<String>) {
+        if let Some(session) = self.session.take() {
+            self.events.push(TraceEvent::Ended {
+                status: session.status,
+                error,
+            });
+        }
+    }
+}
+
+/// Trace shared by the HTTP API, the signal, the measurement loop and the
+/// tasks it feeds.
+#[derive(Debug)]
+pub struct TraceControl {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_secs: u64,
+    /// Checked before anything is built for an entry.
+    active: AtomicBool,
+    slot: Mutex<Slot>,
+}
+
+impl TraceControl {
+    /// Create the control, with no trace running.
+    /// # Arguments
+    /// * `config` - Trace configuration.
+    pub fn new(config: &TraceConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+            max_bytes: config.max_bytes,
+            max_secs: config.max_secs,
+            active: AtomicBool::new(false),
+            slot: Mutex::new(Slot::default()),
+        }
+    }
+
+    /// Start a trace.
+    /// # Arguments
+    /// * `request` - Requested trace.
+    /// * `now` - Current time, names the file.
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * The new trace.
+    /// * `Err(e)` if the length is out of range, a trace is running or the
+    ///   file cannot be created.
+    pub fn start(
+        &self,
+        request: TraceRequest,
+        now: DateTime<Local>,
+        instant: Instant,
+    ) -> Result<TraceStatus, TraceError> {
+        if !(1..=self.max_secs).contains(&request.secs) {
+            return Err(TraceError::OutOfRange {
+                secs: request.secs,
+                max: self.max_secs,
+            });
+        }
+        let mut slot = self.lock();
+        self.expire_locked(&mut slot, instant);
+        if let Some(session) = &slot.session {
+            return Err(TraceError::Running(session.status.path.clone()));
+        }
+        let path = self
+            .dir
+            .join(format!("trace-{}.jsonl", now.format("%Y%m%dT%H%M%S")));
+        let file = fs::create_dir_all(&self.dir)
+            .and_then(|_| File::create(&path))
+            .map_err(|e| TraceError::Create {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            })?;
+        let status = TraceStatus {
+            path: path.display().to_string(),
+            started_at: now.to_rfc3339(),
+            secs: request.secs,
+            entries: 0,
+            rotations: 0,
+        };
+        slot.events.push(TraceEvent::Started(status.clone()));
+        slot.session = Some(Session {
+            status: status.clone(),
+            file,
+            written: 0,
+            until: instant + Duration::from_secs(request.secs),
+        });
+        self.active.store(true, Ordering::Relaxed);
+        Ok(status)
+    }
+
+    /// Whether a trace is running. Callers check this before building the
+    /// detail of an entry.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// The running trace, `None` when off.
+    pub fn status(&self) -> Option<TraceStatus> {
+        let mut slot = self.lock();
+        self.expire_locked(&mut slot, Instant::now());
+        slot.session.as_ref().map(|session| session.status.clone())
+    }
+
+    /// Append an entry, if a trace is running.
+    /// # Arguments
+    /// * `tick` - Tick of the measurement loop the entry belongs to.
+    /// * `stage` - Step of the loop, e.g. "raw" or "db".
+    /// * `detail` - Data of the step.
+    pub fn record(&self, tick: u64, stage: &str, detail: Value) {
+        if !self.is_active() {
+            return;
+        }
+        let mut slot = self.lock();
+        self.expire_locked(&mut slot, Instant::now());
+        let Some(session) = slot.session.as_mut() else {
+            return;
+        };
+        let entry = json!({
+            "at": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            "tick": tick,
+            "stage": stage,
+            "detail": detail,
+        });
+        let line = format!("{}\n", entry);
+        if let Err(e) = self.append(session, line.as_bytes()) {
+            eprintln!("Failed to write the trace {}: {}", session.status.path, e);
+            slot.end(Some(e.to_string()));
+            self.active.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Take the events to store, ending the trace first if it ran its time.
+    /// # Arguments
+    /// * `instant` - Current monotonic time.
+    /// # Returns
+    /// * Events in the order they happened.
+    pub fn take_events(&self, instant: Instant) -> Vec<TraceEvent> {
+        let mut slot = self.lock();
+        self.expire_locked(&mut slot, instant);
+        std::mem::take(&mut slot.events)
+    }
+
+    fn expire_locked(&self, slot: &mut Slot, instant: Instant) {
+        if slot
+            .session
+            .as_ref()
+            .is_some_and(|session| instant >= session.until)
+        {
+            slot.end(None);
+            self.active.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Write a line, moving the file aside first once it is full.
+    fn append(&self, session: &mut Session, line: &[u8]) -> io::Result<()> {
+        let len = line.len() as u64;
+        if session.written > 0 && session.written + len > self.max_bytes / 2 {
+            let path = Path::new(&session.status.path);
+            fs::rename(path, rotated_path(path))?;
+            session.file = File::create(path)?;
+            session.written = 0;
+            session.status.rotations += 1;
+        }
+        session.file.write_all(line)?;
+        session.written += len;
+        session.status.entries += 1;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Slot> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Path the full trace file is moved to.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MIN_TRACE_BYTES;
+    use chrono::TimeZone;
+
+    fn control(name: &str, max_bytes: u64) -> (TraceControl, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("wbroker-rs-trace-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = TraceConfig {
+            dir: dir.display().to_string(),
+            max_bytes,
+            max_secs: 600,
+        };
+        (TraceControl::new(&config), dir)
+    }
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 6, 16, 14, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_trace_runs_its_time() {
+        let (trace, dir) = control("time", 1 << 20);
+        let start = Instant::now();
+        trace.record(1, "raw", json!({}));
+        assert!(!trace.is_active());
+
+        let status = trace
+            .start(TraceRequest { secs: 60 }, now(), start)
+            .unwrap();
+        assert!(status.path.ends_with("trace-20250616T140000.jsonl"));
+        assert!(matches!(
+            trace.start(TraceRequest { secs: 60 }, now(), start),
+            Err(TraceError::Running(_))
+        ));
+        trace.record(7, "raw", json!({ "temperature_c": 23.5 }));
+        trace.record(7, "db", json!({ "result": "stored" }));
+
+        let events = trace.take_events(start + Duration::from_secs(30));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind(), "trace_start");
+        let events = trace.take_events(start + Duration::from_secs(60));
+        assert!(!trace.is_active());
+        let content = fs::read_to_string(&status.path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind(), "trace_end");
+        assert_eq!(events[0].detail()["entries"], 2);
+        assert_eq!(events[0].detail()["path"], status.path.as_str());
+        let lines: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["tick"], 7);
+        assert_eq!(lines[0]["stage"], "raw");
+        assert_eq!(lines[0]["detail"]["temperature_c"], 23.5);
+        assert_eq!(lines[1]["stage"], "db");
+    }
+
+    #[test]
+    fn test_trace_file_is_bounded() {
+        let (trace, dir) = control("bounded", MIN_TRACE_BYTES);
+        let status = trace
+            .start(TraceRequest { secs: 60 }, now(), Instant::now())
+            .unwrap();
+        let padding = "x".repeat(1000);
+        for tick in 0..200 {
+            trace.record(tick, "frame", json!({ "padding": padding }));
+        }
+        let path = Path::new(&status.path);
+        let size =
+            fs::metadata(path).unwrap().len() + fs::metadata(rotated_path(path)).unwrap().len();
+        let last = fs::read_to_string(path).unwrap();
+        let status = trace.status().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(size <= MIN_TRACE_BYTES);
+        assert!(status.rotations >= 2);
+        assert_eq!(status.entries, 200);
+        assert!(last.lines().last().unwrap().contains("\"tick\":199"));
+    }
+
+    #[test]
+    fn test_trace_request_out_of_range() {
+        let (trace, _) = control("range", 1 << 20);
+        let start = |secs| trace.start(TraceRequest { secs }, now(), Instant::now());
+        assert!(start(0).is_err());
+        assert!(start(601).is_err());
+        assert!(trace.take_events(Instant::now()).is_empty());
+    }
+}
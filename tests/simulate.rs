@@ -0,0 +1,105 @@
+// MIT License
+// Copyright (c) 2025 Yukke.org
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Runs the daemon with `--simulate` for a few ticks: the simulated sensor
+//! is measured, the display is printed to stdout and the rows are stored.
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const BINARY: &str = env!("CARGO_BIN_EXE_wbroker-rs");
+
+/// Lines of the display drawn before the daemon is stopped.
+const DRAWN_LINES: usize = 5;
+
+/// Write a config logging to a SQLite file in `dir`, measuring every 50 ms.
+fn write_config(dir: &Path) -> std::path::PathBuf {
+    let config = dir.join("config.toml");
+    let database = dir.join("sensor.db");
+    let toml = format!(
+        "[database]\nurl = \"sqlite:{}?mode=rwc\"\n\n[sensor]\ninterval_ms = 50\n",
+        database.display()
+    );
+    std::fs::write(&config, toml).unwrap();
+    config
+}
+
+#[test]
+fn test_simulate_runs_the_loop() {
+    let dir = std::env::temp_dir().join(format!("wbroker-simulate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = write_config(&dir);
+
+    let mut child = Command::new(BINARY)
+        .arg("--simulate")
+        .arg("-c")
+        .arg(&config)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Wait for the first sample and a few redraws of the measurement line
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut started = false;
+    let mut drawn = 0;
+    while !(started && drawn >= DRAWN_LINES) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let Ok(line) = receiver.recv_timeout(timeout) else {
+            let _ = child.kill();
+            panic!("started: {}, lines drawn: {}", started, drawn);
+        };
+        started |= line.starts_with("Startup timing");
+        if line.starts_with("display 2:") {
+            drawn += 1;
+        }
+    }
+    // SAFETY: the pid is that of the child, which has not been waited for
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let status = child.wait().unwrap();
+    assert!(status.success(), "{}", status);
+
+    let export = Command::new(BINARY)
+        .arg("-c")
+        .arg(&config)
+        .args(["export", "--source", "simulated"])
+        .output()
+        .unwrap();
+    assert!(export.status.success());
+    let csv = String::from_utf8(export.stdout).unwrap();
+    // The header and at least one row
+    assert!(csv.lines().count() >= 2, "{}", csv);
+    std::fs::remove_dir_all(&dir).unwrap();
+}